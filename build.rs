@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto");
+
+    // Only the gRPC service needs generated code, so protoc is only required with that feature
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+        std::env::set_var("PROTOC", protoc);
+        tonic_prost_build::compile_protos("proto/speedscore.proto").expect("failed to compile proto/speedscore.proto");
+        #[cfg(feature = "flight")]
+        tonic_prost_build::compile_protos("proto/flight.proto").expect("failed to compile proto/flight.proto");
+    }
+}
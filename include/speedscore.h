@@ -0,0 +1,58 @@
+/*
+ * C interface to SpeedScore. Build the shared library with
+ *
+ *     cargo build --release --features ffi
+ *
+ * and link against target/release/libspeedscore.so (.dylib on macOS, .dll on Windows).
+ *
+ * Failing calls return NULL or -1; speedscore_last_error() then describes the
+ * failure. Error messages are per-thread. An internal error (a panic) inside the
+ * library is reported the same way, and never unwinds into the caller.
+ */
+#ifndef SPEEDSCORE_H
+#define SPEEDSCORE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SpeedscoreScorer SpeedscoreScorer;
+typedef struct SpeedscoreResults SpeedscoreResults;
+
+enum {
+    SPEEDSCORE_MODEL_ADDITIVE = 0,
+    SPEEDSCORE_MODEL_DOMINANT = 1,
+    SPEEDSCORE_MODEL_RECESSIVE = 2
+};
+
+/* Last error on this thread, or NULL. Valid until the next failing call. */
+const char *speedscore_last_error(void);
+
+/* Scorer lifecycle and configuration */
+SpeedscoreScorer *speedscore_scorer_new(const char *scoring_path);
+int speedscore_scorer_set_dosage(SpeedscoreScorer *scorer, int dosage);
+int speedscore_scorer_set_model(SpeedscoreScorer *scorer, int model);
+int speedscore_scorer_set_threads(SpeedscoreScorer *scorer, size_t threads);
+void speedscore_scorer_free(SpeedscoreScorer *scorer);
+
+/* Scoring; the scorer may be reused for several files */
+SpeedscoreResults *speedscore_score_file(const SpeedscoreScorer *scorer, const char *vcf_path);
+/* Several VCFs with the same samples, read one after another as one */
+SpeedscoreResults *speedscore_score_files(const SpeedscoreScorer *scorer, const char *const *vcf_paths, size_t count);
+
+/* Results, in VCF header order. Strings are owned by the results handle. */
+size_t speedscore_results_len(const SpeedscoreResults *results);
+const char *speedscore_results_sample_name(const SpeedscoreResults *results, size_t index);
+double speedscore_results_score(const SpeedscoreResults *results, size_t index);
+uint64_t speedscore_results_total_variants(const SpeedscoreResults *results, size_t index);
+uint64_t speedscore_results_matched_variants(const SpeedscoreResults *results, size_t index);
+void speedscore_results_free(SpeedscoreResults *results);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SPEEDSCORE_H */
@@ -0,0 +1,61 @@
+//! R bindings, built by `R CMD INSTALL r` through extendr (see `r/src/Makevars`). Results
+//! are returned as data.frames. Counts are doubles, as R's integers stop at 2^31.
+
+use clap::ValueEnum;
+use extendr_api::prelude::*;
+use speedscore::common::{load_scoring_file, Model};
+use speedscore::error::NonFinite;
+use speedscore::scorer::Scorer;
+
+/// A SpeedScore error as an R error, whose message is the error's.
+fn r_error(e: speedscore::error::Error) -> Error {
+    Error::Other(e.to_string())
+}
+
+/// Score every sample of `vcf` against `scoring`, as a data.frame with columns `sample`,
+/// `score`, `total_variants` and `matched_variants`. `score()` in `R/speedscore.R` gives
+/// the arguments their defaults.
+#[extendr]
+fn score_vcf(vcf: &str, scoring: &str, dosage: bool, model: &str, threads: Nullable<i32>) -> Result<Robj> {
+    let model = Model::from_str(model, true).map_err(Error::Other)?;
+    let mut scorer = Scorer::from_scoring_file(scoring).map_err(r_error)?.dosage(dosage).model(model);
+    if let NotNull(threads) = threads {
+        let threads = usize::try_from(threads).ok().filter(|&n| n > 0).ok_or_else(|| Error::Other("threads must be at least 1".to_string()))?;
+        scorer = scorer.threads(threads);
+    }
+    let results = scorer.run(vcf).map_err(r_error)?;
+
+    let samples = &results.samples;
+    call!(
+        "data.frame",
+        sample = samples.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+        score = samples.iter().map(|s| s.score).collect::<Vec<_>>(),
+        total_variants = samples.iter().map(|s| s.total_variants as f64).collect::<Vec<_>>(),
+        matched_variants = samples.iter().map(|s| s.matched_variants as f64).collect::<Vec<_>>(),
+        stringsAsFactors = false
+    )
+}
+
+/// Load a scoring file as a data.frame with columns `chr`, `pos`, `effect_allele` and
+/// `effect_weight`, sorted by chromosome and position.
+#[extendr]
+fn load_scoring_file_df(path: &str) -> Result<Robj> {
+    let (weights, _) = load_scoring_file(path, NonFinite::default(), None).map_err(r_error)?;
+    let mut rows: Vec<_> = weights.into_iter().collect();
+    rows.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    call!(
+        "data.frame",
+        chr = rows.iter().map(|((chr, _), _)| chr.as_str()).collect::<Vec<_>>(),
+        pos = rows.iter().map(|((_, pos), _)| *pos as f64).collect::<Vec<_>>(),
+        effect_allele = rows.iter().map(|(_, w)| w.effect_allele.as_str()).collect::<Vec<_>>(),
+        effect_weight = rows.iter().map(|(_, w)| w.weight as f64).collect::<Vec<_>>(),
+        stringsAsFactors = false
+    )
+}
+
+extendr_module! {
+    mod speedscore;
+    fn score_vcf;
+    fn load_scoring_file_df;
+}
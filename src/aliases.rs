@@ -0,0 +1,94 @@
+//! Chromosome aliases, so that a VCF naming chromosomes by reference accession matches a
+//! scoring file that names them `1` or `chr1`.
+//!
+//! VCFs aligned to an NCBI or GenBank assembly name chromosomes by accession:
+//! `NC_000001.11` or `CM000663.2` for chromosome 1 of GRCh38. The accessions of the
+//! GRCh37 and GRCh38 chromosomes are built in, and `--chr-aliases` adds more from a file.
+//! Every chromosome name read from the VCF or the scoring file goes through [`canonical`]
+//! before it is matched.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::sync::OnceLock;
+use crate::error::{Error, IoContext, Result};
+use crate::source;
+
+/// The chromosomes of GRCh37 and GRCh38 by their GRCh37 RefSeq, GRCh38 RefSeq, GRCh37
+/// GenBank and GRCh38 GenBank accessions.
+const BUILT_IN: &[(&str, [&str; 4])] = &[
+    ("1", ["NC_000001.10", "NC_000001.11", "CM000663.1", "CM000663.2"]),
+    ("2", ["NC_000002.11", "NC_000002.12", "CM000664.1", "CM000664.2"]),
+    ("3", ["NC_000003.11", "NC_000003.12", "CM000665.1", "CM000665.2"]),
+    ("4", ["NC_000004.11", "NC_000004.12", "CM000666.1", "CM000666.2"]),
+    ("5", ["NC_000005.9", "NC_000005.10", "CM000667.1", "CM000667.2"]),
+    ("6", ["NC_000006.11", "NC_000006.12", "CM000668.1", "CM000668.2"]),
+    ("7", ["NC_000007.13", "NC_000007.14", "CM000669.1", "CM000669.2"]),
+    ("8", ["NC_000008.10", "NC_000008.11", "CM000670.1", "CM000670.2"]),
+    ("9", ["NC_000009.11", "NC_000009.12", "CM000671.1", "CM000671.2"]),
+    ("10", ["NC_000010.10", "NC_000010.11", "CM000672.1", "CM000672.2"]),
+    ("11", ["NC_000011.9", "NC_000011.10", "CM000673.1", "CM000673.2"]),
+    ("12", ["NC_000012.11", "NC_000012.12", "CM000674.1", "CM000674.2"]),
+    ("13", ["NC_000013.10", "NC_000013.11", "CM000675.1", "CM000675.2"]),
+    ("14", ["NC_000014.8", "NC_000014.9", "CM000676.1", "CM000676.2"]),
+    ("15", ["NC_000015.9", "NC_000015.10", "CM000677.1", "CM000677.2"]),
+    ("16", ["NC_000016.9", "NC_000016.10", "CM000678.1", "CM000678.2"]),
+    ("17", ["NC_000017.10", "NC_000017.11", "CM000679.1", "CM000679.2"]),
+    ("18", ["NC_000018.9", "NC_000018.10", "CM000680.1", "CM000680.2"]),
+    ("19", ["NC_000019.9", "NC_000019.10", "CM000681.1", "CM000681.2"]),
+    ("20", ["NC_000020.10", "NC_000020.11", "CM000682.1", "CM000682.2"]),
+    ("21", ["NC_000021.8", "NC_000021.9", "CM000683.1", "CM000683.2"]),
+    ("22", ["NC_000022.10", "NC_000022.11", "CM000684.1", "CM000684.2"]),
+    ("X", ["NC_000023.10", "NC_000023.11", "CM000685.1", "CM000685.2"]),
+    ("Y", ["NC_000024.9", "NC_000024.10", "CM000686.1", "CM000686.2"]),
+    // Both builds use the revised Cambridge Reference Sequence
+    ("MT", ["NC_012920.1", "NC_012920.1", "J01415.2", "J01415.2"]),
+];
+
+/// Chromosome names by alias.
+pub type Aliases = HashMap<String, String>;
+
+static ALIASES: OnceLock<Aliases> = OnceLock::new();
+
+/// The built-in aliases.
+pub fn built_in() -> Aliases {
+    BUILT_IN
+        .iter()
+        .flat_map(|(chr, accessions)| accessions.iter().map(move |accession| (accession.to_string(), chr.to_string())))
+        .collect()
+}
+
+/// The built-in aliases and those in the file at `path`, plain or gzipped: one alias per
+/// line, then the chromosome it names, separated by whitespace. Further columns, blank
+/// lines and lines starting with `#` are ignored. An alias in the file replaces a
+/// built-in one.
+pub fn load(path: &str) -> Result<Aliases> {
+    let reader = source::decompressed(File::open(path).with_path(path)?).with_path(path)?;
+    let mut aliases = built_in();
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.map_err(|e| Error::read(path, line_number, e))?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(alias), Some(chr)) = (fields.next(), fields.next()) else {
+            return Err(Error::parse(path, line_number, "Expected an alias and a chromosome", &line));
+        };
+        aliases.insert(alias.to_string(), chr.trim_start_matches("chr").to_string());
+    }
+    Ok(aliases)
+}
+
+/// Fix the aliases for the rest of the process. Has no effect once [`canonical`] has run.
+pub fn configure(aliases: Aliases) {
+    let _ = ALIASES.set(aliases);
+}
+
+/// The name `chr` is matched by: what it is an alias of, or else itself without "chr".
+pub fn canonical(chr: &str) -> &str {
+    match ALIASES.get_or_init(built_in).get(chr) {
+        Some(canonical) => canonical,
+        None => chr.trim_start_matches("chr"),
+    }
+}
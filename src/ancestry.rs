@@ -0,0 +1,199 @@
+//! Projection onto reference ancestry principal components, and PGS adjustment for them.
+//!
+//! A loadings file is tab-separated like a scoring file, with `chr_name`, `chr_position`,
+//! `effect_allele` and `allelefrequency_effect` columns followed by one column per PC,
+//! each named `PC<n>`. A sample's coordinate on a PC is the sum over called variants of
+//! `loading * (g - 2p)`, where `g` is its effect-allele count and `p` the reference
+//! frequency; missing genotypes contribute nothing, i.e. they are mean-imputed.
+//!
+//! Calibration turns a raw score into a z-score relative to samples of similar ancestry,
+//! as eMERGE and GenoPred do: the score's mean is modelled as linear in the PCs and its
+//! variance as log-linear, `z = (score - mean(PCs)) / sqrt(exp(log_variance(PCs)))`.
+//! A calibration file is tab-separated with a `term`, `mean` and `log_variance` header and
+//! one row per term: `intercept` plus any of the loadings' PCs. PCs without a row have
+//! coefficient 0. The coefficients must have been fitted on PCs projected with the same
+//! loadings file.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use crate::common::parse_position;
+use crate::error::{Error, IoContext, Result};
+use crate::multi_sample::SampleData;
+use crate::stats;
+
+/// One variant's PC loadings.
+#[derive(Clone, Debug)]
+pub struct Loading {
+    pub effect_allele: String,
+    /// Effect-allele frequency in the reference the PCs were computed on
+    pub frequency: f32,
+    /// One loading per PC, in [`Loadings::names`] order
+    pub weights: Vec<f32>,
+}
+
+/// Reference SNP loadings, keyed like [`crate::EffectWeights`] by (chr without "chr", pos).
+#[derive(Clone, Debug)]
+pub struct Loadings {
+    /// PC column names, e.g. `PC1`..`PC10`
+    pub names: Vec<String>,
+    pub variants: HashMap<(String, u64), Loading>,
+    /// Coefficients for calibrated z-scores, if given
+    pub calibration: Option<Calibration>,
+}
+
+/// Coefficients of the ancestry mean and log-variance models, intercept first and then
+/// one per PC in [`Loadings::names`] order.
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    pub mean: Vec<f64>,
+    pub log_variance: Vec<f64>,
+}
+
+/// Read a loadings file; see the module documentation for the layout.
+pub fn load_loadings(path: &str) -> Result<Loadings> {
+    let file = File::open(path).with_path(path)?;
+    let mut lines = BufReader::new(file).lines().enumerate();
+
+    let header = loop {
+        match lines.next() {
+            None => return Err(Error::invalid(path, "loadings file has no header")),
+            Some((index, line)) => {
+                let line = line.map_err(|e| Error::read(path, index + 1, e))?;
+                if !line.starts_with('#') {
+                    break line;
+                }
+            }
+        }
+    };
+    let headers: Vec<&str> = header.split('\t').collect();
+    let column = |name: &str| {
+        headers.iter().position(|h| *h == name).ok_or_else(|| Error::invalid(path, format!("Missing '{}' column", name)))
+    };
+    let chr_index = column("chr_name")?;
+    let pos_index = column("chr_position")?;
+    let allele_index = column("effect_allele")?;
+    let frequency_index = column("allelefrequency_effect")?;
+    let pc_indices: Vec<usize> = (0..headers.len()).filter(|&i| headers[i].starts_with("PC")).collect();
+    if pc_indices.is_empty() {
+        return Err(Error::invalid(path, "no PC columns (PC1, PC2, ...) in the loadings file"));
+    }
+
+    let mut variants = HashMap::new();
+    for (index, line) in lines {
+        let line_number = index + 1;
+        let line = line.map_err(|e| Error::read(path, line_number, e))?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != headers.len() {
+            return Err(Error::parse(
+                path,
+                line_number,
+                format!("Expected {} columns (from header), found {}", headers.len(), parts.len()),
+                &line,
+            ));
+        }
+        let pos = parse_position(parts[pos_index]).ok_or_else(|| Error::parse(path, line_number, "Invalid numeric position", &line))?;
+        let frequency = parts[frequency_index]
+            .parse::<f32>()
+            .ok()
+            .filter(|f| (0.0..=1.0).contains(f))
+            .ok_or_else(|| Error::parse(path, line_number, "Invalid allele frequency", &line))?;
+        let weights = pc_indices
+            .iter()
+            .map(|&i| parts[i].parse::<f32>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| Error::parse(path, line_number, "Invalid numeric loading", &line))?;
+        let chr = parts[chr_index].trim_start_matches("chr").to_string();
+        variants.insert((chr, pos), Loading { effect_allele: parts[allele_index].to_string(), frequency, weights });
+    }
+
+    println!("PC loadings loaded: {} variants, {} PCs", variants.len(), pc_indices.len());
+    Ok(Loadings { names: pc_indices.iter().map(|&i| headers[i].to_string()).collect(), variants, calibration: None })
+}
+
+/// Read calibration coefficients for the PCs named `pc_names`; see the module documentation.
+pub fn load_calibration(path: &str, pc_names: &[String]) -> Result<Calibration> {
+    let file = File::open(path).with_path(path)?;
+    let mut calibration = Calibration { mean: vec![0.0; pc_names.len() + 1], log_variance: vec![0.0; pc_names.len() + 1] };
+    let mut has_intercept = false;
+    let mut header = None;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.map_err(|e| Error::read(path, line_number, e))?;
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        let Some((term_index, mean_index, variance_index)) = header else {
+            let column = |name: &str| {
+                parts.iter().position(|h| *h == name).ok_or_else(|| Error::invalid(path, format!("Missing '{}' column", name)))
+            };
+            header = Some((column("term")?, column("mean")?, column("log_variance")?));
+            continue;
+        };
+
+        let term = parts.get(term_index).copied().unwrap_or_default();
+        let slot = match term {
+            "intercept" => {
+                has_intercept = true;
+                0
+            }
+            _ => 1 + pc_names.iter().position(|name| name == term).ok_or_else(|| {
+                Error::parse(path, line_number, "Term is neither 'intercept' nor a PC of the loadings file", &line)
+            })?,
+        };
+        let coefficient = |i: usize| {
+            parts
+                .get(i)
+                .and_then(|value| value.parse::<f64>().ok())
+                .ok_or_else(|| Error::parse(path, line_number, "Invalid numeric coefficient", &line))
+        };
+        calibration.mean[slot] = coefficient(mean_index)?;
+        calibration.log_variance[slot] = coefficient(variance_index)?;
+    }
+
+    if !has_intercept {
+        return Err(Error::invalid(path, "calibration has no 'intercept' row"));
+    }
+    Ok(calibration)
+}
+
+impl Calibration {
+    /// `score` as a z-score against samples with the same PC coordinates.
+    pub fn z_score(&self, score: f64, pcs: &[f64]) -> f64 {
+        let linear = |coefficients: &[f64]| coefficients[0] + coefficients[1..].iter().zip(pcs).map(|(c, pc)| c * pc).sum::<f64>();
+        (score - linear(&self.mean)) / linear(&self.log_variance).exp().sqrt()
+    }
+}
+
+impl Loading {
+    /// Add one called genotype with `count` effect alleles to a sample's PC coordinates.
+    pub fn project(&self, count: f64, pcs: &mut [f64]) {
+        let centered = count - 2.0 * self.frequency as f64;
+        for (pc, &weight) in pcs.iter_mut().zip(&self.weights) {
+            *pc += weight as f64 * centered;
+        }
+    }
+}
+
+/// Residuals of the samples' scores after an ordinary least-squares regression on their
+/// PC coordinates (with an intercept), fitted across the samples themselves. `None` when
+/// there are no PCs, no more samples than coefficients, or the PCs are collinear.
+pub fn adjusted_scores(samples: &[SampleData]) -> Option<Vec<f64>> {
+    if samples.first()?.pcs.is_empty() {
+        return None;
+    }
+    let x: Vec<Vec<f64>> = samples.iter().map(|sample| std::iter::once(1.0).chain(sample.pcs.iter().copied()).collect()).collect();
+    let y: Vec<f64> = samples.iter().map(|sample| sample.score).collect();
+    let fit = stats::least_squares(&x, &y)?;
+    Some(
+        x.iter()
+            .zip(&y)
+            .map(|(row, y)| y - row.iter().zip(&fit.coefficients).map(|(x, b)| x * b).sum::<f64>())
+            .collect(),
+    )
+}
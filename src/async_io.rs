@@ -0,0 +1,216 @@
+//! Async entry points, enabled with the `tokio` feature. Scoring is CPU-bound and
+//! stays synchronous; these functions run it on tokio's blocking pool so that
+//! embedding services don't stall their executor threads.
+//!
+//! [`open`] reads the inputs a run can name as an [`AsyncRead`], for [`run_reader`] or
+//! for the service's own use: standard input and local files through tokio, a bucket
+//! object from the pipe of the provider's tool (see [`crate::stream`]), and a URL or
+//! htsget dataset as it downloads. The HTTP client is a blocking one, so a download runs
+//! on the blocking pool and hands its bytes over a bounded channel, which the reader
+//! awaits. Bucket objects need a runtime with I/O enabled, as for any child process.
+
+use std::future::Future;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::process::{Child, ChildStdout};
+use tokio::sync::mpsc;
+use tokio_util::io::SyncIoBridge;
+use crate::error::{Error, IoContext, Result};
+use crate::htsget;
+use crate::input::InputConfig;
+use crate::remote;
+use crate::scorer::{ScoreResults, Scorer};
+use crate::source;
+use crate::stdin;
+use crate::stream;
+use crate::upload::Provider;
+
+/// Bytes a download hands over at a time
+const CHUNK: usize = 64 * 1024;
+
+/// Chunks a download may get ahead of the reader
+const CHUNKS_AHEAD: usize = 16;
+
+/// Async counterpart of [`Scorer::run`].
+pub async fn run_async(scorer: Arc<Scorer>, input: String) -> Result<ScoreResults> {
+    let name = input.clone();
+    spawn_blocking(&name, move || scorer.run(&input)).await
+}
+
+/// Score a VCF streamed from an async reader such as a pipe, socket or HTTP body, or one
+/// from [`open`]. Gzip/BGZF input is recognised by its magic bytes; `name` only appears
+/// in error messages.
+pub async fn run_reader<R>(scorer: Arc<Scorer>, name: String, reader: R) -> Result<ScoreResults>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let bridge = SyncIoBridge::new(reader);
+    spawn_blocking(&name.clone(), move || {
+        let reader = source::decompressed(bridge).with_path(&name)?;
+        let mut source = scorer.vcf_source(&name, reader)?;
+        scorer.run_source(&mut source)
+    })
+    .await
+}
+
+/// Open `path` for async reading, as it is stored: `-` for standard input, a local file,
+/// an `s3://` or `gs://` object, the htsget endpoint of `config`, or an `http://` or
+/// `https://` URL, which is read as it downloads whether or not `config` streams URLs.
+/// Compressed inputs are returned compressed.
+pub async fn open(path: &str, config: &InputConfig) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
+    if stdin::is_stdin(path) {
+        return Ok(Box::new(tokio::io::stdin()));
+    }
+    if let Some(provider) = Provider::of(path) {
+        return Ok(Box::new(Download::start(provider, path)?));
+    }
+    let url = path.to_string();
+    if htsget::is_endpoint(path, config) {
+        return Ok(Box::new(Chunks::start(path, move || htsget::stream(&url)).await?));
+    }
+    if remote::is_url(path) {
+        return Ok(Box::new(Chunks::start(path, move || remote::stream(&url)).await?));
+    }
+    Ok(Box::new(tokio::fs::File::open(path).await.with_path(path)?))
+}
+
+/// A bucket object being written to the standard output of the provider's tool.
+struct Download {
+    child: Child,
+    stdout: ChildStdout,
+    program: &'static str,
+}
+
+impl Download {
+    fn start(provider: Provider, url: &str) -> Result<Self> {
+        let (program, command) = provider.read_command(url);
+        let spawned = tokio::process::Command::from(command).stdin(Stdio::null()).stdout(Stdio::piped()).kill_on_drop(true).spawn();
+        let mut child = spawned.map_err(|e| stream::tool_error(program, e)).with_path(url)?;
+        let stdout = child.stdout.take().expect("the tool's output is piped");
+        Ok(Download { child, stdout, program })
+    }
+}
+
+impl AsyncRead for Download {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let download = self.get_mut();
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut download.stdout).poll_read(cx, buf))?;
+        // The object ends where the tool does, which has to have succeeded
+        if buf.filled().len() == filled && buf.remaining() > 0 {
+            let status = ready!(Box::pin(download.child.wait()).as_mut().poll(cx))?;
+            if !status.success() {
+                return Poll::Ready(Err(io::Error::other(format!("`{}` failed ({})", download.program, status))));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A blocking reader read on the blocking pool, its bytes awaited chunk by chunk.
+struct Chunks {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl Chunks {
+    /// Open the reader with `open` and start reading it. A reader dropped before the end
+    /// stops the download at the next chunk.
+    async fn start<F>(path: &str, open: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Box<dyn Read + Send>> + Send + 'static,
+    {
+        let mut reader = spawn_blocking(path, open).await?;
+        let (tx, chunks) = mpsc::channel(CHUNKS_AHEAD);
+        tokio::task::spawn_blocking(move || loop {
+            let mut chunk = vec![0; CHUNK];
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => return,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                    return;
+                }
+            };
+            chunk.truncate(read);
+            if tx.blocking_send(Ok(chunk)).is_err() {
+                return;
+            }
+        });
+        Ok(Chunks { chunks, current: Vec::new(), pos: 0 })
+    }
+}
+
+impl AsyncRead for Chunks {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let chunks = self.get_mut();
+        while chunks.pos == chunks.current.len() {
+            match ready!(chunks.chunks.poll_recv(cx)) {
+                Some(chunk) => {
+                    chunks.current = chunk?;
+                    chunks.pos = 0;
+                }
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let count = buf.remaining().min(chunks.current.len() - chunks.pos);
+        buf.put_slice(&chunks.current[chunks.pos..chunks.pos + count]);
+        chunks.pos += count;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Run `op` on the blocking pool. A panic in it carries on here; a task cancelled because
+/// the runtime is shutting down is an error about `name`.
+async fn spawn_blocking<T: Send + 'static>(name: &str, op: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    match tokio::task::spawn_blocking(op).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(Error::invalid(name, "cancelled, as the async runtime is shutting down")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Waker;
+
+    const VCF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/bcf/plain.vcf");
+
+    #[test]
+    fn open_reads_a_file_scored_as_by_run() {
+        let scoring = std::env::temp_dir().join(format!("speedscore-async-{}.txt", std::process::id()));
+        std::fs::write(&scoring, "chr_name\tchr_position\teffect_allele\teffect_weight\n1\t10\tG\t1.0\n2\t5\tC\t0.5\n").unwrap();
+        let scorer = Arc::new(Scorer::from_scoring_file(scoring.to_str().unwrap()).unwrap());
+        std::fs::remove_file(&scoring).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let results = runtime.block_on(async {
+            let reader = open(VCF, &InputConfig::default()).await.unwrap();
+            run_reader(scorer.clone(), VCF.to_string(), reader).await.unwrap()
+        });
+        let scores = |results: &ScoreResults| results.samples.iter().map(|sample| (sample.name.clone(), sample.score)).collect::<Vec<_>>();
+        assert_eq!(scores(&results), scores(&scorer.run(VCF).unwrap()));
+    }
+
+    #[test]
+    fn a_task_cancelled_at_shutdown_is_an_error() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let handle = runtime.handle().clone();
+        runtime.shutdown_background();
+        // A runtime shut down cancels what is spawned on it
+        let _entered = handle.enter();
+        let mut cancelled = Box::pin(spawn_blocking("cancelled", || Ok(())));
+        match cancelled.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(outcome) => assert!(outcome.unwrap_err().to_string().contains("cancelled, as the async runtime is shutting down")),
+            Poll::Pending => panic!("a task spawned on a runtime shut down is not cancelled"),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! `--vcf-dir`: scoring every VCF in a directory, or every file a glob pattern such as
+//! `data/*.vcf.gz` matches, against the same scoring file, which is loaded once. The scores
+//! of all of them go in one table, with a row for each VCF and sample.
+//!
+//! Each VCF is scored on its own, as `--vcf` would score it, with the options that change
+//! how genotypes are read and scored; the analyses that add columns or change the weights
+//! are refused. A VCF that cannot be scored is reported and left out of the table, and the
+//! run fails once the others have been written.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use regex::Regex;
+use crate::common::{create_parent_dirs, Args, EffectWeights, Mode};
+use crate::error::{Error, IoContext, Result};
+use crate::input::InputConfig;
+use crate::scorer::Scorer;
+use crate::sink::{csv_field, OutputFormat};
+
+/// File name endings of the VCFs picked up from a directory.
+pub const EXTENSIONS: [&str; 5] = [".vcf", ".vcf.gz", ".vcf.bgz", ".vcf.zst", ".bcf"];
+
+/// Refuse the options `--vcf-dir` does not apply to each VCF.
+pub fn check(args: &Args) -> Result<()> {
+    let refused = [
+        ("--output-format", args.output_format != OutputFormat::Csv),
+        ("--mode", args.mode != Mode::Auto),
+        ("--plink-compat", args.plink_compat),
+        ("--tui", args.tui),
+        ("--lims-mapping", args.lims_mapping.is_some()),
+        ("--pca-loadings", args.pca_loadings.is_some()),
+        ("--clump-reference", args.clump_reference.is_some()),
+        ("--frequency-scaling", args.frequency_scaling.is_some()),
+        ("--max-abs-weight", args.max_abs_weight.is_some()),
+        ("--winsorize-quantile", args.winsorize_quantile.is_some()),
+        ("--liftover", args.liftover.is_some()),
+        ("--chromosomes", !args.chromosomes.is_empty()),
+        ("--exclude-regions", !args.exclude_regions.is_empty()),
+        ("--region-cap", args.region_cap.is_some()),
+        ("--pt-thresholds", !args.pt_thresholds.is_empty()),
+        ("--permutations", args.permutations.is_some()),
+        ("--set-column", args.set_column.is_some()),
+        ("--set-bed", args.set_bed.is_some()),
+        ("--sex", args.sex.is_some()),
+        ("--environment", args.environment.is_some()),
+        ("--parent-of-origin", args.parent_of_origin.is_some()),
+        ("--pedigree", args.pedigree.is_some()),
+        ("--variant-variance", args.variant_variance.is_some()),
+        ("--reference", args.reference.is_some()),
+        ("--t-scores", args.t_scores),
+        ("--percentiles", args.percentiles),
+        ("--risk-cutoffs", !args.risk_cutoffs.is_empty()),
+        ("--phenotype", args.phenotype.is_some()),
+        ("--sample-fraction", args.sample_fraction.is_some()),
+        ("--sample-range", args.sample_range.is_some()),
+        ("--include", args.include.is_some()),
+        ("--exclude", args.exclude.is_some()),
+        ("--strict-vcf", args.strict_vcf),
+        ("--rejected", args.rejected.is_some()),
+        ("--checksums", args.checksums),
+        ("--expect-vcf-checksum", args.expect_vcf_checksum.is_some()),
+        ("--expect-scoring-checksum", args.expect_scoring_checksum.is_some()),
+        ("--provenance", args.provenance.is_some()),
+        ("--pgs-catalog", args.pgs_catalog),
+        ("--checkpoint", args.checkpoint.is_some()),
+        ("--strict", args.strict),
+    ];
+    if args.scorings.len() > 1 {
+        return Err(Error::invalid("--scoring", "--vcf-dir scores one scoring file; give a single --scoring with it"));
+    }
+    match refused.iter().find(|(_, given)| *given) {
+        Some((flag, _)) => Err(Error::invalid(flag, "is not available with --vcf-dir, which writes only each VCF's scores; score the VCFs one at a time with --vcf instead")),
+        None => Ok(()),
+    }
+}
+
+/// The files `pattern` names, sorted: the VCFs and BCFs directly inside it if it is a
+/// directory, else the files whose names match it, where `*` stands for any characters and
+/// `?` for any one. Hidden files and tabix or CSI indexes are skipped.
+pub fn expand(pattern: &str) -> Result<Vec<String>> {
+    let path = Path::new(pattern);
+    // A directory is read for its VCFs, a pattern for the names matching it
+    let (dir, glob) = if path.is_dir() {
+        (path, None)
+    } else {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if dir.to_string_lossy().contains(['*', '?']) {
+            return Err(Error::invalid("--vcf-dir", format!("{}: only the file name can have wildcards", pattern)));
+        }
+        let regex = name.chars().map(|c| match c {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            c => regex::escape(&c.to_string()),
+        });
+        (dir, Some(Regex::new(&format!("^{}$", regex.collect::<String>())).expect("an escaped pattern is a valid regex")))
+    };
+    let matches = |name: &str| match &glob {
+        Some(glob) => glob.is_match(name) && !name.ends_with(".tbi") && !name.ends_with(".csi"),
+        None => EXTENSIONS.iter().any(|ext| name.ends_with(ext)),
+    };
+    let dir_name = dir.to_string_lossy();
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).with_path(&dir_name)? {
+        let entry = entry.with_path(&dir_name)?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || !matches(&name) {
+            continue;
+        }
+        if entry.metadata().with_path(&name)?.is_file() {
+            found.push(match dir == Path::new(".") && !pattern.starts_with("./") {
+                true => name,
+                false => dir.join(name).to_string_lossy().into_owned(),
+            });
+        }
+    }
+    if found.is_empty() {
+        let message = if path.is_dir() { "the directory has no VCF or BCF" } else { "no file matches this pattern" };
+        return Err(Error::invalid(pattern, message));
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Score each of `vcfs` with `weights` as `args` say, and write every sample's scores to
+/// `output`. Returns the error of the first VCF that could not be scored, if any.
+pub fn run(vcfs: &[String], weights: EffectWeights, args: &Args, output: &str) -> Result<()> {
+    let mut scorer = Scorer::new(weights)
+        .dosage(args.dosage)
+        .model(args.model)
+        .rescale_by_coverage(args.rescale_by_coverage)
+        .non_finite(args.non_finite)
+        .on_error(args.on_error, args.max_errors)
+        .unsorted(args.unsorted)
+        .input_config(InputConfig::from_args(args));
+    if let Some(centering) = args.center {
+        scorer = scorer.center(centering);
+    }
+    if let Some(field) = args.info_field {
+        scorer = scorer.info_field(field);
+    }
+
+    create_parent_dirs(output)?;
+    let mut out = BufWriter::new(File::create(output).with_path(output)?);
+    write!(out, "VCF_File,Sample_Name,Polygenic_Score,Calculation_Time_Seconds,Total_Variants,Matched_Variants").with_path(output)?;
+    if args.info_field.is_some() {
+        write!(out, ",Effective_Variants").with_path(output)?;
+    }
+    if args.rescale_by_coverage {
+        write!(out, ",Weight_Coverage,Rescaled_Score").with_path(output)?;
+    }
+    writeln!(out).with_path(output)?;
+
+    let mut failures = Vec::new();
+    let mut samples = 0;
+    for (i, vcf) in vcfs.iter().enumerate() {
+        println!("Scoring {} ({} of {})", vcf, i + 1, vcfs.len());
+        let start = Instant::now();
+        let results = match scorer.run(vcf) {
+            Ok(results) => results,
+            Err(e) => {
+                eprintln!("Warning: {} was not scored: {}", vcf, e);
+                failures.push(e);
+                continue;
+            }
+        };
+        let seconds = if args.reproducible { 0.0 } else { start.elapsed().as_secs_f64() };
+        for sample in &results.samples {
+            write!(
+                out,
+                "{},{},{:.6},{:.6},{},{}",
+                csv_field(vcf),
+                csv_field(&sample.name),
+                sample.score,
+                seconds,
+                sample.total_variants,
+                sample.matched_variants
+            )
+            .with_path(output)?;
+            // Left blank where the VCF gave no value, as for a raw genotype export's INFO
+            if args.info_field.is_some() {
+                write!(out, ",{}", sample.effective_variants.map_or_else(String::new, |e| format!("{:.4}", e))).with_path(output)?;
+            }
+            if args.rescale_by_coverage {
+                let coverage = sample.weight_coverage.map_or_else(String::new, |c| format!("{:.6}", c));
+                let rescaled = sample.rescaled_score.map_or_else(String::new, |r| format!("{:.6}", r));
+                write!(out, ",{},{}", coverage, rescaled).with_path(output)?;
+            }
+            writeln!(out).with_path(output)?;
+        }
+        samples += results.samples.len();
+    }
+    out.flush().with_path(output)?;
+    println!("Results written to: {}", output);
+    println!("Scored {} samples in {} of {} VCFs", samples, vcfs.len() - failures.len(), vcfs.len());
+    match failures.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
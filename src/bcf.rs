@@ -0,0 +1,544 @@
+//! Reading BCF, the binary form of VCF that `bcftools` writes by default.
+//!
+//! A BCF is BGZF-compressed like a bgzipped VCF (or not compressed, from `bcftools -Ou`),
+//! so [`crate::bgzf::open`] inflates it as usual and hands the inflated bytes here when
+//! they start with the BCF magic. The header is VCF text already. Each record is decoded
+//! into a line of VCF text, as [`crate::hts`] does with htslib's records, so everything
+//! after reading works as it does for a VCF.
+//!
+//! Records refer to contigs, FILTER, INFO and FORMAT keys by their index in the header's
+//! dictionaries, which are built as htslib builds them: `PASS` first, then each ID in the
+//! order the header declares it, unless its line gives an `IDX`. A record that cannot be
+//! decoded stops the run, as BCF does not say where the next record starts.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+
+/// The magic every BCF starts with, before its minor version.
+const MAGIC: &[u8; 4] = b"BCF\x02";
+
+/// Value types of the typed values records are made of.
+const MISSING: u8 = 0;
+const INT8: u8 = 1;
+const INT16: u8 = 2;
+const INT32: u8 = 3;
+const FLOAT: u8 = 5;
+const CHAR: u8 = 7;
+
+/// Float bit patterns for a missing value and for the end of a shorter vector.
+const FLOAT_MISSING: u32 = 0x7F80_0001;
+const FLOAT_END: u32 = 0x7F80_0002;
+
+/// Whether the (inflated) stream holds a BCF rather than VCF text.
+pub fn is_bcf(reader: &mut impl BufRead) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(MAGIC))
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("not a valid BCF: {}", message.into()))
+}
+
+/// A BCF as VCF text: the header, then one line per record.
+pub struct Reader<R> {
+    inner: R,
+    contigs: Vec<String>,
+    /// FILTER, INFO and FORMAT IDs by their dictionary index
+    strings: Vec<String>,
+    samples: usize,
+    /// The undecoded shared and per-sample parts of the record being read
+    shared: Vec<u8>,
+    indiv: Vec<u8>,
+    /// The text of the header or the last record decoded, and how much of it has been read
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Read the BCF header from `inner`, which must start with it.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; 5];
+        inner.read_exact(&mut magic).map_err(|_| invalid("it ends in its magic"))?;
+        if magic[..4] != *MAGIC || !(1..=2).contains(&magic[4]) {
+            return Err(invalid(format!("unsupported version {}.{}", magic[3], magic[4])));
+        }
+        let mut length = [0u8; 4];
+        inner.read_exact(&mut length).map_err(|_| invalid("it ends in its header"))?;
+        let mut text = vec![0u8; u32::from_le_bytes(length) as usize];
+        inner.read_exact(&mut text).map_err(|_| invalid("it ends in its header"))?;
+        while text.last() == Some(&0) {
+            text.pop();
+        }
+        if !text.ends_with(b"\n") {
+            text.push(b'\n');
+        }
+        let header = std::str::from_utf8(&text).map_err(|_| invalid("its header is not UTF-8"))?;
+        let (contigs, strings) = dictionaries(header)?;
+        let samples = header
+            .lines()
+            .find(|line| line.starts_with("#CHROM"))
+            .ok_or_else(|| invalid("its header has no #CHROM line"))?
+            .split('\t')
+            .count()
+            .saturating_sub(9);
+        Ok(Reader { inner, contigs, strings, samples, shared: Vec::new(), indiv: Vec::new(), pending: text, pos: 0 })
+    }
+
+    /// Decode the next record into `pending`; `false` at the end of the file.
+    fn next_record(&mut self) -> io::Result<bool> {
+        let mut lengths = [0u8; 8];
+        match self.inner.fill_buf()? {
+            [] => return Ok(false),
+            _ => self.inner.read_exact(&mut lengths).map_err(|_| invalid("it ends in a record"))?,
+        }
+        let shared_length = u32::from_le_bytes(lengths[..4].try_into().expect("four bytes")) as usize;
+        let indiv_length = u32::from_le_bytes(lengths[4..].try_into().expect("four bytes")) as usize;
+        self.shared.resize(shared_length, 0);
+        self.indiv.resize(indiv_length, 0);
+        self.inner.read_exact(&mut self.shared).map_err(|_| invalid("it ends in a record"))?;
+        self.inner.read_exact(&mut self.indiv).map_err(|_| invalid("it ends in a record"))?;
+        self.pending.clear();
+        self.pos = 0;
+        let record = Record { contigs: &self.contigs, strings: &self.strings, samples: self.samples };
+        record.decode(&self.shared, &self.indiv, &mut self.pending)?;
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = buf.len().min(available.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.pending.len() && !self.next_record()? {
+            return Ok(&[]);
+        }
+        Ok(&self.pending[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.pending.len());
+    }
+}
+
+/// The contig and string dictionaries of a BCF's header.
+fn dictionaries(header: &str) -> io::Result<(Vec<String>, Vec<String>)> {
+    let mut contigs = Dictionary::default();
+    let mut strings = Dictionary::default();
+    strings.add("PASS", None);
+    for line in header.lines() {
+        let Some((kind, fields)) = line.strip_prefix("##").and_then(|line| line.split_once("=<")) else {
+            continue;
+        };
+        let dictionary = match kind {
+            "contig" => &mut contigs,
+            "FILTER" | "INFO" | "FORMAT" => &mut strings,
+            _ => continue,
+        };
+        let fields = structured_fields(fields.strip_suffix('>').unwrap_or(fields));
+        let id = fields.get("ID").ok_or_else(|| invalid(format!("header line without an ID: {}", line)))?;
+        let idx = fields.get("IDX").map(|idx| idx.parse().map_err(|_| invalid(format!("header line with a bad IDX: {}", line)))).transpose()?;
+        dictionary.add(id, idx);
+    }
+    Ok((contigs.names, strings.names))
+}
+
+/// IDs by their index, with the index of each ID already in it.
+#[derive(Default)]
+struct Dictionary {
+    names: Vec<String>,
+    indexes: HashMap<String, usize>,
+}
+
+impl Dictionary {
+    fn add(&mut self, id: &str, idx: Option<usize>) {
+        if self.indexes.contains_key(id) {
+            return;
+        }
+        let index = idx.unwrap_or(self.names.len());
+        if self.names.len() <= index {
+            self.names.resize(index + 1, String::new());
+        }
+        self.names[index] = id.to_string();
+        self.indexes.insert(id.to_string(), index);
+    }
+}
+
+/// The `key=value` fields of a structured header line, between its `<` and `>`; values
+/// may be quoted, and hold commas when they are.
+fn structured_fields(text: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut rest = text;
+    while let Some((key, after)) = rest.split_once('=') {
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut escaped = false;
+                let end = quoted
+                    .char_indices()
+                    .find(|&(_, c)| {
+                        let closes = c == '"' && !escaped;
+                        escaped = c == '\\' && !escaped;
+                        closes
+                    })
+                    .map_or(quoted.len(), |(end, _)| end);
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => after.split_once(',').map_or((after, ""), |(value, next)| (value, next)),
+        };
+        fields.insert(key.trim(), value);
+        rest = next.strip_prefix(',').unwrap_or(next);
+    }
+    fields
+}
+
+/// What is needed to turn one record into VCF text.
+struct Record<'a> {
+    contigs: &'a [String],
+    strings: &'a [String],
+    samples: usize,
+}
+
+/// Typed values read one after another from a record.
+struct Values<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Values<'a> {
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.bytes.get(self.at..self.at + length).ok_or_else(|| invalid("a record ends early"))?;
+        self.at += length;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("four bytes")))
+    }
+
+    /// The type and count of the next typed value.
+    fn descriptor(&mut self) -> io::Result<(u8, usize)> {
+        let byte = self.take(1)?[0];
+        let (kind, count) = (byte & 0x0f, (byte >> 4) as usize);
+        if count < 15 {
+            return Ok((kind, count));
+        }
+        // A count of 15 or more follows as a typed integer
+        let count = self.int()?.and_then(|count| usize::try_from(count).ok()).ok_or_else(|| invalid("a bad value count"))?;
+        Ok((kind, count))
+    }
+
+    /// A single typed integer, such as a dictionary index; `None` if missing.
+    fn int(&mut self) -> io::Result<Option<i32>> {
+        let (kind, count) = self.descriptor()?;
+        if count != 1 || !matches!(kind, INT8 | INT16 | INT32) {
+            return Err(invalid("a key or count that is not a single integer"));
+        }
+        let bytes = self.take(width(kind)?)?;
+        Ok(int_value(kind, bytes).flatten())
+    }
+
+    /// The `count` raw values of type `kind`.
+    fn array(&mut self, kind: u8, count: usize) -> io::Result<&'a [u8]> {
+        self.take(width(kind)? * count)
+    }
+}
+
+/// Bytes per value of type `kind`.
+fn width(kind: u8) -> io::Result<usize> {
+    match kind {
+        MISSING | INT8 | CHAR => Ok(1),
+        INT16 => Ok(2),
+        INT32 | FLOAT => Ok(4),
+        _ => Err(invalid(format!("unknown value type {}", kind))),
+    }
+}
+
+/// An integer value: `None` at the end of a shorter vector, `Some(None)` if missing.
+fn int_value(kind: u8, bytes: &[u8]) -> Option<Option<i32>> {
+    let (value, missing) = match kind {
+        INT8 => (bytes[0] as i8 as i32, i8::MIN as i32),
+        INT16 => (i16::from_le_bytes([bytes[0], bytes[1]]) as i32, i16::MIN as i32),
+        _ => (i32::from_le_bytes(bytes.try_into().expect("four bytes")), i32::MIN),
+    };
+    match value {
+        _ if value == missing + 1 => None,
+        _ if value == missing => Some(None),
+        _ => Some(Some(value)),
+    }
+}
+
+/// A float value: `None` at the end of a shorter vector, `Some(None)` if missing.
+fn float_value(bytes: &[u8]) -> Option<Option<f32>> {
+    match u32::from_le_bytes(bytes.try_into().expect("four bytes")) {
+        FLOAT_END => None,
+        FLOAT_MISSING => Some(None),
+        bits => Some(Some(f32::from_bits(bits))),
+    }
+}
+
+impl Record<'_> {
+    /// Write the record with shared part `shared` and per-sample part `indiv` to `out` as
+    /// a line of VCF text.
+    fn decode(&self, shared: &[u8], indiv: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let mut values = Values { bytes: shared, at: 0 };
+        let contig = values.u32()? as usize;
+        let pos = values.u32()? as i32;
+        values.take(4)?;
+        let qual = float_value(values.take(4)?).flatten();
+        let counts = values.u32()?;
+        let (info_count, allele_count) = ((counts & 0xffff) as usize, (counts >> 16) as usize);
+        let counts = values.u32()?;
+        let (sample_count, format_count) = ((counts & 0x00ff_ffff) as usize, (counts >> 24) as usize);
+        if sample_count != self.samples && format_count > 0 {
+            return Err(invalid(format!("a record with {} samples where the header has {}", sample_count, self.samples)));
+        }
+
+        let name = self.contigs.get(contig).filter(|name| !name.is_empty()).ok_or_else(|| invalid(format!("contig {} is not in the header", contig)))?;
+        out.extend_from_slice(name.as_bytes());
+        out.push(b'\t');
+        out.extend_from_slice(itoa::Buffer::new().format(i64::from(pos) + 1).as_bytes());
+        out.push(b'\t');
+        let (kind, count) = values.descriptor()?;
+        write_string(out, values.array(kind, count)?);
+        for allele in 0..allele_count {
+            out.push(if allele < 2 { b'\t' } else { b',' });
+            let (kind, count) = values.descriptor()?;
+            write_string(out, values.array(kind, count)?);
+        }
+        if allele_count < 2 {
+            out.extend_from_slice(if allele_count == 0 { b"\t.\t." } else { b"\t." });
+        }
+        out.push(b'\t');
+        match qual {
+            Some(qual) => out.extend_from_slice(ryu::Buffer::new().format(qual).as_bytes()),
+            None => out.push(b'.'),
+        }
+
+        out.push(b'\t');
+        let (kind, count) = values.descriptor()?;
+        let filters = values.array(kind, count)?;
+        if count == 0 {
+            out.push(b'.');
+        }
+        for (i, filter) in filters.chunks(width(kind)?).enumerate() {
+            if i > 0 {
+                out.push(b';');
+            }
+            let filter = int_value(kind, filter).flatten().ok_or_else(|| invalid("a missing FILTER"))?;
+            out.extend_from_slice(self.string(filter)?.as_bytes());
+        }
+
+        out.push(b'\t');
+        if info_count == 0 {
+            out.push(b'.');
+        }
+        for i in 0..info_count {
+            if i > 0 {
+                out.push(b';');
+            }
+            let key = values.int()?.ok_or_else(|| invalid("a missing INFO key"))?;
+            out.extend_from_slice(self.string(key)?.as_bytes());
+            let (kind, count) = values.descriptor()?;
+            let value = values.array(kind, count)?;
+            // Flags have no value
+            if kind != MISSING && count > 0 {
+                out.push(b'=');
+                write_values(out, kind, value, false)?;
+            }
+        }
+
+        if format_count == 0 {
+            out.push(b'\n');
+            return Ok(());
+        }
+        // Each FORMAT field holds `count` values of every sample in turn
+        let mut values = Values { bytes: indiv, at: 0 };
+        let mut fields = Vec::with_capacity(format_count);
+        out.push(b'\t');
+        for i in 0..format_count {
+            if i > 0 {
+                out.push(b':');
+            }
+            let key = self.string(values.int()?.ok_or_else(|| invalid("a missing FORMAT key"))?)?;
+            out.extend_from_slice(key.as_bytes());
+            let (kind, count) = values.descriptor()?;
+            let bytes = values.array(kind, count * sample_count)?;
+            fields.push((key == "GT", kind, bytes, count * width(kind)?));
+        }
+        for sample in 0..sample_count {
+            out.push(b'\t');
+            for (i, &(genotype, kind, bytes, stride)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(b':');
+                }
+                write_values(out, kind, &bytes[sample * stride..(sample + 1) * stride], genotype)?;
+            }
+        }
+        out.push(b'\n');
+        Ok(())
+    }
+
+    /// The FILTER, INFO or FORMAT ID with dictionary index `index`.
+    fn string(&self, index: i32) -> io::Result<&str> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.strings.get(index))
+            .filter(|name| !name.is_empty())
+            .map(String::as_str)
+            .ok_or_else(|| invalid(format!("key {} is not in the header", index)))
+    }
+}
+
+/// A character value, which ends at its first NUL; `.` if empty.
+fn write_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    match end {
+        0 => out.push(b'.'),
+        _ => out.extend_from_slice(&bytes[..end]),
+    }
+}
+
+/// The values in `bytes` of type `kind`, comma-separated, or as a genotype.
+fn write_values(out: &mut Vec<u8>, kind: u8, bytes: &[u8], genotype: bool) -> io::Result<()> {
+    let start = out.len();
+    let mut floats = ryu::Buffer::new();
+    let mut ints = itoa::Buffer::new();
+    match kind {
+        CHAR => write_string(out, bytes),
+        FLOAT => {
+            for (i, value) in bytes.chunks_exact(4).map_while(float_value).enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                match value {
+                    Some(value) => out.extend_from_slice(floats.format(value).as_bytes()),
+                    None => out.push(b'.'),
+                }
+            }
+        }
+        MISSING => {}
+        _ => {
+            for (i, value) in bytes.chunks_exact(width(kind)?).map_while(|bytes| int_value(kind, bytes)).enumerate() {
+                if genotype {
+                    write_allele(out, i, value);
+                    continue;
+                }
+                if i > 0 {
+                    out.push(b',');
+                }
+                match value {
+                    Some(value) => out.extend_from_slice(ints.format(value).as_bytes()),
+                    None => out.push(b'.'),
+                }
+            }
+        }
+    }
+    if out.len() == start {
+        out.push(b'.');
+    }
+    Ok(())
+}
+
+/// The `i`th allele of a genotype, encoded as `(allele + 1) << 1 | phased`, with the
+/// separator before it.
+fn write_allele(out: &mut Vec<u8>, i: usize, value: Option<i32>) {
+    let value = value.unwrap_or(0);
+    if i > 0 {
+        out.push(if value & 1 == 1 { b'|' } else { b'/' });
+    }
+    match (value >> 1) - 1 {
+        allele if allele < 0 => out.push(b'.'),
+        allele if allele < 10 => out.push(b'0' + allele as u8),
+        allele => out.extend_from_slice(itoa::Buffer::new().format(allele).as_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! The fixtures in `tests/data/bcf` were written by htslib from the `.vcf` next to each.
+    //! htslib reads the `.bcf`s back as exactly those records.
+
+    use flate2::read::MultiGzDecoder;
+    use super::*;
+
+    fn decode(bcf: &[u8]) -> String {
+        let mut reader = io::BufReader::new(MultiGzDecoder::new(bcf));
+        assert!(is_bcf(&mut reader).unwrap());
+        let mut text = String::new();
+        Reader::new(reader).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    fn records(text: &str) -> Vec<&str> {
+        text.lines().filter(|line| !line.starts_with("##")).collect()
+    }
+
+    #[test]
+    fn header_idx_orders_the_dictionaries() {
+        // Contigs, FILTERs, INFO and FORMAT keys all given out of order, with gaps
+        let text = decode(include_bytes!("../tests/data/bcf/idx.bcf"));
+        assert_eq!(text, include_str!("../tests/data/bcf/idx.vcf"));
+    }
+
+    #[test]
+    fn records_read_as_htslib_writes_them() {
+        let text = decode(include_bytes!("../tests/data/bcf/plain.bcf"));
+        assert_eq!(records(&text), records(include_str!("../tests/data/bcf/plain.vcf")));
+    }
+
+    #[test]
+    fn opened_files_read_as_vcf_text() {
+        let mut text = String::new();
+        crate::bgzf::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/bcf/plain.bcf"), &Default::default()).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(records(&text), records(include_str!("../tests/data/bcf/plain.vcf")));
+    }
+
+    #[test]
+    fn ids_without_idx_follow_pass_in_declaration_order() {
+        let header = "##fileformat=VCFv4.2\n\
+            ##contig=<ID=2>\n\
+            ##contig=<ID=1,IDX=3>\n\
+            ##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth, in reads\">\n\
+            ##FILTER=<ID=LowQual,Description=\"Low\">\n\
+            ##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n\
+            ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n";
+        let (contigs, strings) = dictionaries(header).unwrap();
+        assert_eq!(contigs, ["2", "", "", "1"]);
+        // An ID declared for INFO and FORMAT alike has one index
+        assert_eq!(strings, ["PASS", "DP", "LowQual", "GT"]);
+    }
+
+    #[test]
+    fn vector_ends_shorten_values() {
+        let mut out = Vec::new();
+        // A haploid genotype padded to diploid, then a missing allele
+        write_values(&mut out, INT8, &[4, i8::MIN as u8 + 1], true).unwrap();
+        out.push(b' ');
+        write_values(&mut out, INT8, &[0, 5], true).unwrap();
+        out.push(b' ');
+        let floats = [0.5f32.to_bits(), FLOAT_MISSING, FLOAT_END].map(u32::to_le_bytes).concat();
+        write_values(&mut out, FLOAT, &floats, false).unwrap();
+        out.push(b' ');
+        let ints = [300i16, i16::MIN, i16::MIN + 1].map(i16::to_le_bytes).concat();
+        write_values(&mut out, INT16, &ints, false).unwrap();
+        out.push(b' ');
+        // A vector that ends before its first value is missing
+        write_values(&mut out, FLOAT, &FLOAT_END.to_le_bytes(), false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1 .|1 0.5,. 300,. .");
+    }
+
+    #[test]
+    fn alleles_carry_their_phase_and_index() {
+        let mut out = Vec::new();
+        for (i, value) in [(0, Some(2)), (1, Some(5)), (2, Some(24)), (3, Some(0)), (4, None)] {
+            write_allele(&mut out, i, value);
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), "0|1/11/./.");
+    }
+}
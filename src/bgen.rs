@@ -0,0 +1,411 @@
+//! Reading BGEN files (v1.1, v1.2 and v1.3), as UK Biobank ships its imputed genotypes.
+//!
+//! [`crate::bgzf::open`] hands a BGEN here, and it is read as the VCF of its dosages, as
+//! [`crate::plink`] reads PLINK filesets: each variant's genotype probabilities become the
+//! expected count of its second allele, a `DS` field rounded to 4 decimals, with the first
+//! allele as REF. Records have no `GT`, so they are scored from `DS` with or without
+//! `--dosage`. Genotype blocks may be uncompressed, zlib- or zstd-compressed, phased or
+//! not, of any ploidy and of any bit depth; variants with more than two alleles are not
+//! supported.
+//!
+//! Samples are named by the `.sample` file given with `--sample`, or else by the IDs the
+//! BGEN holds, or else by `PREFIX.sample` next to `PREFIX.bgen`. Chromosomes are written
+//! without leading zeros, as UK Biobank's `01` to `22`, and PLINK's numeric codes for the
+//! sex chromosomes and mitochondria are written as X, Y and MT.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek};
+use std::path::Path;
+use flate2::read::ZlibDecoder;
+use crate::error::{Error, IoContext, Result};
+use crate::plink;
+use crate::resources;
+
+/// The magic number, 16 bytes in: after the offset of the first variant, the header's
+/// length, and the numbers of variants and samples.
+pub const MAGIC: [u8; 4] = *b"bgen";
+
+/// Whether the file at `path`, starting with `header`, is a BGEN: by its magic number, or
+/// its name for the older files without one.
+pub fn is_bgen(header: &[u8], path: &str) -> bool {
+    header.get(16..20) == Some(&MAGIC[..]) || path.ends_with(".bgen")
+}
+
+/// How genotype blocks are compressed.
+#[derive(Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Zlib,
+    Zstd,
+}
+
+/// A BGEN file as VCF text: the header, then one line per variant.
+pub struct Reader {
+    file: BufReader<File>,
+    variants: u32,
+    variant: u32,
+    samples: usize,
+    /// 1 for v1.1 files, 2 for v1.2 and v1.3
+    layout: u32,
+    compression: Compression,
+    /// The genotype block being read, compressed and not
+    block: Vec<u8>,
+    probabilities: Vec<u8>,
+    /// The text of the header or the last variant, and how much of it has been read
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+/// Open the BGEN at `path` and read its header and sample IDs.
+pub fn open(path: &str, sample_file: Option<&str>) -> Result<Reader> {
+    let mut file = BufReader::with_capacity(resources::current().read_buffer, File::open(path).with_path(path)?);
+    let short = |_| Error::invalid(path, "not a BGEN: its header ends early");
+    let offset = read_u32(&mut file).map_err(short)?;
+    let header_length = read_u32(&mut file).map_err(short)?;
+    let variants = read_u32(&mut file).map_err(short)?;
+    let sample_count = read_u32(&mut file).map_err(short)? as usize;
+    if header_length < 20 || offset < header_length {
+        return Err(Error::invalid(path, "not a BGEN: its header's length is out of range"));
+    }
+    // The magic number and free data, then the flags
+    io::copy(&mut file.by_ref().take(header_length as u64 - 16), &mut io::sink()).with_path(path)?;
+    let flags = read_u32(&mut file).map_err(short)?;
+    let compression = match flags & 3 {
+        0 => Compression::None,
+        1 => Compression::Zlib,
+        2 => Compression::Zstd,
+        _ => return Err(Error::invalid(path, "unsupported BGEN compression")),
+    };
+    let layout = (flags >> 2) & 0x0f;
+    if !(1..=2).contains(&layout) || (layout == 1 && compression == Compression::Zstd) {
+        return Err(Error::invalid(path, format!("unsupported BGEN layout {}", layout)));
+    }
+
+    let embedded = if flags >> 31 == 1 { Some(read_sample_ids(path, &mut file)?) } else { None };
+    let default = format!("{}.sample", path.strip_suffix(".bgen").unwrap_or(path));
+    let (samples, source) = match (sample_file, embedded) {
+        (Some(sample_file), _) => (read_sample_file(sample_file)?, sample_file),
+        (None, Some(ids)) => (ids, path),
+        (None, None) if Path::new(&default).exists() => (read_sample_file(&default)?, default.as_str()),
+        (None, None) => return Err(Error::invalid(path, "the BGEN has no sample IDs; give its .sample file with --sample")),
+    };
+    if samples.len() != sample_count {
+        return Err(Error::invalid(path, format!("the BGEN has {} samples, and {} names {}", sample_count, source, samples.len())));
+    }
+    // Variants start `offset` bytes after the offset itself
+    let position = file.stream_position().with_path(path)?;
+    let first_variant = 4 + offset as u64;
+    if position > first_variant {
+        return Err(Error::invalid(path, "not a BGEN: its first variant overlaps its header"));
+    }
+    io::copy(&mut file.by_ref().take(first_variant - position), &mut io::sink()).with_path(path)?;
+
+    let mut text = format!("##fileformat=VCFv4.2\n##source=SpeedScore, from the BGEN file {}\n", path);
+    text.push_str("##FORMAT=<ID=DS,Number=A,Type=Float,Description=\"Expected count of the second allele\">\n");
+    text.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT");
+    for sample in &samples {
+        text.push('\t');
+        text.push_str(sample);
+    }
+    text.push('\n');
+    Ok(Reader {
+        file,
+        variants,
+        variant: 0,
+        samples: sample_count,
+        layout,
+        compression,
+        block: Vec::new(),
+        probabilities: Vec::new(),
+        pending: text.into_bytes(),
+        pos: 0,
+    })
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// A string of `length` bytes.
+fn read_string(reader: &mut impl Read, length: usize) -> io::Result<String> {
+    let mut bytes = vec![0u8; length];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "a string is not valid UTF-8"))
+}
+
+/// The sample IDs the BGEN holds after its header.
+fn read_sample_ids(path: &str, reader: &mut impl Read) -> Result<Vec<String>> {
+    let short = |_| Error::invalid(path, "not a BGEN: its sample IDs end early");
+    let _length = read_u32(reader).map_err(short)?;
+    let count = read_u32(reader).map_err(short)?;
+    (0..count)
+        .map(|_| {
+            let length = read_u16(reader)? as usize;
+            read_string(reader, length)
+        })
+        .collect::<io::Result<_>>()
+        .map_err(short)
+}
+
+/// The sample IDs of the `.sample` file at `path`: its `ID_2` column, or else its first.
+/// The first line names the columns and the second gives their types.
+pub(crate) fn read_sample_file(path: &str) -> Result<Vec<String>> {
+    let reader = BufReader::new(File::open(path).with_path(path)?);
+    let mut samples = Vec::new();
+    let mut column = None;
+    let mut types_read = false;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::read(path, index + 1, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(column) = column else {
+            column = Some(line.split_whitespace().position(|name| name == "ID_2").unwrap_or(0));
+            continue;
+        };
+        if !types_read {
+            types_read = true;
+            continue;
+        }
+        let id = line.split_whitespace().nth(column).ok_or_else(|| Error::parse(path, index + 1, "Expected a sample ID", &line))?;
+        samples.push(id.to_string());
+    }
+    Ok(samples)
+}
+
+/// A BGEN chromosome as a VCF one: without leading zeros, and with PLINK's codes named.
+pub(crate) fn chromosome(chr: &str) -> &str {
+    let trimmed = chr.trim_start_matches('0');
+    if !trimmed.is_empty() && chr.bytes().all(|b| b.is_ascii_digit()) {
+        plink::chromosome(trimmed)
+    } else {
+        chr
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Reader {
+    /// Write the next variant to `pending`; `false` after the last one.
+    fn next_variant(&mut self) -> io::Result<bool> {
+        if self.variant == self.variants {
+            return Ok(false);
+        }
+        self.variant += 1;
+        let variant = self.variant;
+        self.read_variant().map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid(format!("the BGEN ends in variant {}", variant)),
+            _ => invalid(format!("variant {}: {}", variant, e)),
+        })?;
+        Ok(true)
+    }
+
+    fn read_variant(&mut self) -> io::Result<()> {
+        let file = &mut self.file;
+        if self.layout == 1 && read_u32(file)? as usize != self.samples {
+            return Err(invalid("its sample count does not match the header's".to_string()));
+        }
+        let mut fields = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let length = read_u16(file)? as usize;
+            fields.push(read_string(file, length)?);
+        }
+        let [id, rsid, chr] = <[String; 3]>::try_from(fields).expect("three fields");
+        let position = read_u32(file)?;
+        let allele_count = if self.layout == 1 { 2 } else { read_u16(file)? };
+        if allele_count != 2 {
+            return Err(invalid(format!("it has {} alleles, and only biallelic variants are supported", allele_count)));
+        }
+        let mut alleles = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let length = read_u32(file)? as usize;
+            alleles.push(read_string(file, length)?);
+        }
+
+        self.pending.clear();
+        self.pos = 0;
+        let id = [rsid.as_str(), id.as_str()].into_iter().find(|id| !id.is_empty() && *id != ".").unwrap_or(".");
+        let mut numbers = itoa::Buffer::new();
+        for field in [chromosome(&chr), numbers.format(position), id, allele(&alleles[0]), allele(&alleles[1]), ".", ".", ".", "DS"] {
+            self.pending.extend_from_slice(field.as_bytes());
+            self.pending.push(b'\t');
+        }
+        self.read_probabilities()?;
+        if self.layout == 1 {
+            self.write_layout1()?;
+        } else {
+            self.write_layout2()?;
+        }
+        *self.pending.last_mut().expect("a FORMAT column") = b'\n';
+        Ok(())
+    }
+
+    /// Read the variant's genotype block into `probabilities`, decompressed.
+    fn read_probabilities(&mut self) -> io::Result<()> {
+        let file = &mut self.file;
+        let (stored, uncompressed) = match (self.layout, self.compression) {
+            (1, Compression::None) => (6 * self.samples, None),
+            (1, _) => (read_u32(file)? as usize, Some(6 * self.samples)),
+            (_, Compression::None) => (read_u32(file)? as usize, None),
+            _ => {
+                let length = read_u32(file)? as usize;
+                let uncompressed = read_u32(file)? as usize;
+                (length.checked_sub(4).ok_or_else(|| invalid("its genotype block's length is out of range".to_string()))?, Some(uncompressed))
+            }
+        };
+        let Some(uncompressed) = uncompressed else {
+            self.probabilities.resize(stored, 0);
+            return file.read_exact(&mut self.probabilities);
+        };
+        self.block.resize(stored, 0);
+        file.read_exact(&mut self.block)?;
+        self.probabilities.clear();
+        if self.compression == Compression::Zstd {
+            self.probabilities = zstd::bulk::decompress(&self.block, uncompressed)
+                .map_err(|e| invalid(format!("its genotype block does not decompress: {}", e)))?;
+        } else {
+            self.probabilities.reserve(uncompressed);
+            ZlibDecoder::new(&self.block[..])
+                .read_to_end(&mut self.probabilities)
+                .map_err(|e| invalid(format!("its genotype block does not inflate: {}", e)))?;
+        }
+        if self.probabilities.len() != uncompressed {
+            return Err(invalid("its genotype block's length does not match its header".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Write the dosages of a v1.1 block: three probabilities per sample, in 1/32768ths,
+    /// all 0 for a missing genotype. They are scaled to add up to 1.
+    fn write_layout1(&mut self) -> io::Result<()> {
+        if self.probabilities.len() != 6 * self.samples {
+            return Err(invalid("its genotype block's length does not match its sample count".to_string()));
+        }
+        for sample in self.probabilities.chunks_exact(6) {
+            let [hom_ref, het, hom_alt] = [0, 2, 4].map(|at| u16::from_le_bytes([sample[at], sample[at + 1]]) as u64);
+            let total = hom_ref + het + hom_alt;
+            match total {
+                0 => self.pending.extend_from_slice(b".\t"),
+                _ => write_dosage(&mut self.pending, het + 2 * hom_alt, total),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the dosages of a v1.2 or v1.3 block: each sample's ploidy, then its
+    /// probabilities packed at a given bit depth. Unphased, they are of carrying 0, 1, ...
+    /// copies of the second allele, the last left out as it makes the rest add up to 1;
+    /// phased, of each haplotype carrying the first allele.
+    fn write_layout2(&mut self) -> io::Result<()> {
+        let data = &self.probabilities;
+        let samples = self.samples;
+        let short = || invalid("its genotype block ends early".to_string());
+        if data.len() < 10 + samples || u32::from_le_bytes(data[..4].try_into().expect("four bytes")) as usize != samples {
+            return Err(invalid("its genotype block's sample count does not match the header's".to_string()));
+        }
+        if u16::from_le_bytes([data[4], data[5]]) != 2 {
+            return Err(invalid("its genotype block's allele count does not match the variant's".to_string()));
+        }
+        let ploidies = &data[8..8 + samples];
+        let phased = data[8 + samples] == 1;
+        let depth = data[9 + samples] as u32;
+        if !(1..=32).contains(&depth) {
+            return Err(invalid(format!("its probabilities' bit depth of {} is out of range", depth)));
+        }
+        let packed = &data[10 + samples..];
+        let max = (1u64 << depth) - 1;
+        let mut bit = 0usize;
+        for &ploidy in ploidies {
+            let copies = (ploidy & 0x3f) as u64;
+            // Dosage = ploidy - sum of (copies of the first allele) x probability
+            let mut first_allele = 0;
+            for value in 0..copies {
+                let probability = if depth == 8 {
+                    *packed.get(bit / 8).ok_or_else(short)? as u64
+                } else {
+                    let at = bit / 8;
+                    let bytes = packed.get(at..(bit + depth as usize).div_ceil(8)).ok_or_else(short)?;
+                    let word = bytes.iter().rev().fold(0u64, |word, &b| word << 8 | b as u64);
+                    (word >> (bit % 8)) & max
+                };
+                bit += depth as usize;
+                first_allele += if phased { 1 } else { copies - value } * probability;
+            }
+            if ploidy & 0x80 != 0 {
+                self.pending.extend_from_slice(b".\t");
+            } else {
+                write_dosage(&mut self.pending, (copies * max).saturating_sub(first_allele), max);
+            }
+        }
+        if (bit as u64).div_ceil(8) != packed.len() as u64 {
+            return Err(invalid("its genotype block's length does not match its probabilities".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Write `numerator / denominator` to 4 decimals, and a tab.
+fn write_dosage(text: &mut Vec<u8>, numerator: u64, denominator: u64) {
+    write_scaled_dosage(text, (numerator * 10000 + denominator / 2) / denominator);
+}
+
+/// Write a dosage given in ten-thousandths, without trailing zeros, and a tab.
+pub(crate) fn write_scaled_dosage(text: &mut Vec<u8>, scaled: u64) {
+    let mut numbers = itoa::Buffer::new();
+    text.extend_from_slice(numbers.format(scaled / 10000).as_bytes());
+    let mut fraction = scaled % 10000;
+    if fraction > 0 {
+        let mut digits = 4;
+        while fraction.is_multiple_of(10) {
+            fraction /= 10;
+            digits -= 1;
+        }
+        text.push(b'.');
+        let formatted = numbers.format(fraction);
+        text.extend(std::iter::repeat_n(b'0', digits - formatted.len()));
+        text.extend_from_slice(formatted.as_bytes());
+    }
+    text.push(b'\t');
+}
+
+/// A BGEN allele as a VCF one: an empty one is missing.
+fn allele(allele: &str) -> &str {
+    if allele.is_empty() {
+        "."
+    } else {
+        allele
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = buf.len().min(available.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for Reader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.pending.len() && !self.next_variant()? {
+            return Ok(&[]);
+        }
+        Ok(&self.pending[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.pending.len());
+    }
+}
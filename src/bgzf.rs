@@ -0,0 +1,519 @@
+//! Reading VCFs, plain, (b)gzipped or zstd-compressed, told apart by their first bytes
+//! rather than their extension. BGZF files, as written by `bgzip`, are a series of
+//! independent gzip blocks of at most 64 KiB, so they are inflated in parallel on
+//! `io_threads` threads ahead of the scorer; plain gzip falls back to a single streaming
+//! decoder, and zstd is decompressed by libzstd as it is read. Standard input and
+//! streamed inputs are decompressed on one thread, however they are compressed.
+//!
+//! A damaged file fails with the byte it is damaged at rather than scoring what could be
+//! read: a stream that ends mid-block is truncated, a block whose CRC does not match is
+//! corrupt, and a BGZF file without the empty block `bgzip` ends every file with was cut
+//! short between blocks.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use rayon::prelude::*;
+use crate::bcf;
+use crate::bgen;
+use crate::error::{IoContext, Result};
+use crate::input::InputConfig;
+use crate::oxford;
+use crate::pgen;
+use crate::plink;
+use crate::resources;
+use crate::stdin;
+use crate::stream;
+
+/// The empty block that ends a BGZF file.
+const EOF_MARKER: [u8; 28] = [
+    0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0x1b, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// The first two bytes of every gzip member, BGZF blocks included.
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The first four bytes of every Zstandard frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Skippable Zstandard frames have one of 16 magic numbers, these with the low four bits unset.
+const ZSTD_SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+
+/// Base-2 log of the largest window a zstd frame may ask for: 2 GiB, as `zstd --long=31`
+/// writes. libzstd refuses windows over 128 MiB unless told otherwise.
+const ZSTD_WINDOW_LOG_MAX: u32 = 31;
+
+/// Open a plain, gzipped, bgzipped or zstd-compressed VCF for reading, sized by [`resources::current`]. A
+/// BCF reads as the VCF text it holds, a PLINK `.bed` or `.pgen` as the VCF its fileset
+/// stands for, and a BGEN or Oxford `.gen` as the VCF of its dosages; see [`crate::bcf`],
+/// [`crate::plink`], [`crate::pgen`], [`crate::bgen`] and [`crate::oxford`]. A `path` of
+/// `-` reads standard input, which can be opened once (see [`crate::input`]), and a bucket
+/// object or URL that [`crate::stream`] streams with `config` is read as it downloads: a
+/// VCF, BCF or `.gen`, plain or compressed. With `config.htslib`, a VCF or BCF is read
+/// through htslib, once PLINK, BGEN and `.gen` files are told apart.
+pub fn open(path: &str, config: &InputConfig) -> Result<Box<dyn BufRead + Send>> {
+    if stdin::is_stdin(path) {
+        return open_stream(path, Box::new(io::stdin()), config);
+    }
+    if stream::is_streamed(path, config) {
+        return open_stream(path, stream::open(path, config)?, config);
+    }
+    let tuning = resources::current();
+    let mut file = File::open(path).with_path(path)?;
+    let mut header = Vec::with_capacity(20);
+    file.by_ref().take(20).read_to_end(&mut header).with_path(path)?;
+    file.seek(SeekFrom::Start(0)).with_path(path)?;
+    if pgen::is_pgen_header(&header, path) {
+        return Ok(Box::new(pgen::open(path)?));
+    }
+    if header.starts_with(&plink::BED_MAGIC[..2]) {
+        return Ok(Box::new(plink::open(path)?));
+    }
+    if bgen::is_bgen(&header, path) {
+        return Ok(Box::new(bgen::open(path, config.sample_file.as_deref())?));
+    }
+    #[cfg(feature = "htslib")]
+    if config.htslib && !oxford::is_gen(path) {
+        return crate::hts::open(path);
+    }
+    let bgzf = is_bgzf(&header);
+    if bgzf {
+        check_eof_marker(path, &mut file)?;
+        file.seek(SeekFrom::Start(0)).with_path(path)?;
+    }
+
+    let mut reader: Box<dyn BufRead + Send> = if is_zstd(&header) {
+        Box::new(unzstd(BufReader::with_capacity(tuning.read_buffer, file)).with_path(path)?)
+    } else if !header.starts_with(&GZIP_MAGIC) {
+        Box::new(BufReader::with_capacity(tuning.read_buffer, file))
+    } else if !bgzf || tuning.io_threads == 0 {
+        Box::new(BufReader::with_capacity(tuning.read_buffer, Gunzip::new(file)))
+    } else {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(tuning.io_threads).build()?;
+        // Half the read-ahead is being inflated while the other half waits to be read
+        let batch = (tuning.readahead_blocks / 2).max(1);
+        let (tx, rx) = mpsc::sync_channel(batch);
+        thread::spawn(move || pump(file, &pool, batch, &tx));
+        Box::new(ParallelReader { blocks: rx, current: Vec::new(), pos: 0 })
+    };
+    // A `.gen` is text compressed like a VCF, and told apart by its name
+    if oxford::is_gen(path) {
+        return Ok(Box::new(oxford::open(path, reader, config)?));
+    }
+    // A BCF is compressed like a VCF, and told apart once inflated
+    if bcf::is_bcf(&mut reader).with_path(path)? {
+        return Ok(Box::new(bcf::Reader::new(reader).with_path(path)?));
+    }
+    Ok(reader)
+}
+
+/// Input that can only be read from its start, decompressed on one thread as it is read.
+pub(crate) fn open_stream(path: &str, input: Box<dyn Read + Send>, config: &InputConfig) -> Result<Box<dyn BufRead + Send>> {
+    let tuning = resources::current();
+    let mut input = BufReader::with_capacity(tuning.read_buffer, input);
+    let header = input.fill_buf().with_path(path)?;
+    if header.starts_with(&plink::BED_MAGIC[..2]) || bgen::is_bgen(header, path) {
+        let message = "PLINK and BGEN files are read by seeking around them, so they have to be local files";
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message)).with_path(path);
+    }
+    let mut reader: Box<dyn BufRead + Send> = if header.starts_with(&GZIP_MAGIC) {
+        Box::new(BufReader::with_capacity(tuning.read_buffer, Gunzip::new(input)))
+    } else if is_zstd(header) {
+        Box::new(unzstd(input).with_path(path)?)
+    } else {
+        Box::new(input)
+    };
+    if oxford::is_gen(path) {
+        return Ok(Box::new(oxford::open(path, reader, config)?));
+    }
+    if bcf::is_bcf(&mut reader).with_path(path)? {
+        return Ok(Box::new(bcf::Reader::new(reader).with_path(path)?));
+    }
+    Ok(reader)
+}
+
+/// Whether `header` starts a Zstandard stream: with a frame, or with a skippable frame,
+/// as `pzstd` starts its files.
+pub(crate) fn is_zstd(header: &[u8]) -> bool {
+    match header.get(..4) {
+        Some(magic) => magic == ZSTD_MAGIC || u32::from_le_bytes(magic.try_into().expect("four bytes")) & !0x0f == ZSTD_SKIPPABLE_MAGIC,
+        None => false,
+    }
+}
+
+/// Decompresses the Zstandard frames read from `input`, one after another, skipping
+/// skippable frames and checking content checksums.
+pub(crate) fn unzstd<R: BufRead>(input: R) -> io::Result<BufReader<zstd::stream::read::Decoder<'static, R>>> {
+    let mut decoder = zstd::stream::read::Decoder::with_buffer(input)?;
+    decoder.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
+    Ok(BufReader::with_capacity(resources::current().read_buffer, decoder))
+}
+
+/// A gzip member carrying the `BC` extra subfield that marks a BGZF block.
+fn is_bgzf(header: &[u8]) -> bool {
+    header.len() >= 16 && header[..4] == [0x1f, 0x8b, 8, 4] && header[12..14] == *b"BC" && header[14..16] == [2, 0]
+}
+
+/// Fail unless the BGZF file ends with [`EOF_MARKER`]; without it, the file was cut short
+/// and would otherwise read as a valid, shorter one.
+fn check_eof_marker(path: &str, file: &mut File) -> Result<()> {
+    let length = file.seek(SeekFrom::End(0)).with_path(path)?;
+    let mut tail = [0u8; EOF_MARKER.len()];
+    if length < tail.len() as u64 || file.seek(SeekFrom::End(-(tail.len() as i64))).and_then(|_| file.read_exact(&mut tail)).is_err() || tail != EOF_MARKER {
+        let message = format!("file is truncated at byte {}: it lacks the end-of-file block bgzip ends every file with", length);
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, message)).with_path(path);
+    }
+    Ok(())
+}
+
+/// Read raw blocks a batch at a time, inflate each batch on `pool`, and pass the
+/// results on in file order. Stops at the end of the file, on the first error, or
+/// when the reader is dropped.
+fn pump(mut file: File, pool: &rayon::ThreadPool, batch: usize, tx: &SyncSender<io::Result<Vec<u8>>>) {
+    // Where the next block starts
+    let mut offset = 0;
+    loop {
+        let mut blocks = Vec::with_capacity(batch);
+        while blocks.len() < batch {
+            match next_block(&mut file, offset) {
+                Ok(Some(block)) => {
+                    let start = offset;
+                    offset += block.len() as u64;
+                    blocks.push((start, block));
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            }
+        }
+        if blocks.is_empty() {
+            return;
+        }
+        let inflated: Vec<io::Result<Vec<u8>>> = pool.install(|| blocks.par_iter().map(|(start, block)| inflate(block, *start)).collect());
+        for data in inflated {
+            let failed = data.is_err();
+            if tx.send(data).is_err() || failed {
+                return;
+            }
+        }
+    }
+}
+
+/// The next whole compressed block, starting at byte `offset`, or `None` at end of file.
+pub(crate) fn next_block(file: &mut File, offset: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut block = vec![0u8; 12];
+    let mut filled = 0;
+    while filled < block.len() {
+        match file.read(&mut block[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(truncated(offset + filled as u64)),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if block[..4] != [0x1f, 0x8b, 8, 4] {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("file is corrupt at byte {}: not a BGZF block", offset)));
+    }
+    let extra_len = u16::from_le_bytes([block[10], block[11]]) as usize;
+    block.resize(12 + extra_len, 0);
+    read_rest(file, &mut block[12..])?;
+
+    // BSIZE (total block size minus one) lives in the `BC` subfield
+    let mut extra = &block[12..];
+    let mut block_size = None;
+    while extra.len() >= 4 {
+        let field_len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if extra[..2] == *b"BC" && field_len == 2 && extra.len() >= 6 {
+            block_size = Some(u16::from_le_bytes([extra[4], extra[5]]) as usize + 1);
+        }
+        extra = &extra[(4 + field_len).min(extra.len())..];
+    }
+    let block_size = block_size
+        .filter(|&size| size > block.len())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("file is corrupt at byte {}: BGZF block without a valid BSIZE", offset)))?;
+
+    let header_len = block.len();
+    block.resize(block_size, 0);
+    read_rest(file, &mut block[header_len..])?;
+    Ok(Some(block))
+}
+
+fn read_rest(file: &mut File, buf: &mut [u8]) -> io::Result<()> {
+    match file.read_exact(buf) {
+        // A failed read_exact leaves the file at its end
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(truncated(file.stream_position()?)),
+        result => result,
+    }
+}
+
+fn truncated(at: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, format!("file is truncated at byte {}", at))
+}
+
+/// Inflate the block that starts at byte `offset`, checking its CRC.
+pub(crate) fn inflate(block: &[u8], offset: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(64 * 1024);
+    GzDecoder::new(block)
+        .read_to_end(&mut data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("file is corrupt at byte {}: {}", offset, e)))?;
+    Ok(data)
+}
+
+/// How far into a compressed stream a [`Gunzip`] has read.
+#[derive(Default)]
+struct Position {
+    bytes: AtomicU64,
+    /// Whether the end of the stream was reached
+    ended: AtomicBool,
+}
+
+/// Counts the compressed bytes read through it.
+struct Counted<R> {
+    inner: R,
+    position: Arc<Position>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position.bytes.fetch_add(read as u64, Ordering::Relaxed);
+        if read == 0 && !buf.is_empty() {
+            self.position.ended.store(true, Ordering::Relaxed);
+        }
+        Ok(read)
+    }
+}
+
+/// A streaming decoder for (multi-member) gzip whose errors say where the stream is
+/// damaged: one that ends mid-member is truncated at its last byte, and anything else
+/// the decoder rejects, such as a CRC mismatch, is corrupt around the byte reached.
+pub struct Gunzip<R: Read> {
+    decoder: MultiGzDecoder<Counted<R>>,
+    position: Arc<Position>,
+}
+
+impl<R: Read> Gunzip<R> {
+    pub fn new(reader: R) -> Self {
+        let position = Arc::new(Position::default());
+        Gunzip { decoder: MultiGzDecoder::new(Counted { inner: reader, position: Arc::clone(&position) }), position }
+    }
+}
+
+impl<R: Read> Read for Gunzip<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf).map_err(|e| {
+            let bytes = self.position.bytes.load(Ordering::Relaxed);
+            if e.kind() == io::ErrorKind::Interrupted {
+                e
+            } else if self.position.ended.load(Ordering::Relaxed) {
+                truncated(bytes)
+            } else {
+                io::Error::new(io::ErrorKind::InvalidData, format!("file is corrupt around byte {}: {}", bytes, e))
+            }
+        })
+    }
+}
+
+/// Decompressed blocks handed over from the pump thread, read in order.
+struct ParallelReader {
+    blocks: Receiver<io::Result<Vec<u8>>>,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ParallelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for ParallelReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Loops past empty blocks, such as the end-of-file marker
+        while self.pos >= self.current.len() {
+            match self.blocks.recv() {
+                Ok(block) => {
+                    self.current = block?;
+                    self.pos = 0;
+                }
+                // The pump is done
+                Err(_) => return Ok(&[]),
+            }
+        }
+        Ok(&self.current[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos += amount;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::*;
+
+    const TEXT: &[u8] = b"##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\n1\t100\t.\tA\tG\t.\t.\t.\tGT\t0/1\n";
+
+    fn unzstd_all(data: &[u8]) -> io::Result<Vec<u8>> {
+        assert!(is_zstd(data));
+        let mut output = Vec::new();
+        unzstd(data)?.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn frames_and_skippable_frames_are_read_one_after_another() {
+        // A skippable frame first, as pzstd writes, then two frames at different levels
+        let mut data = vec![0x50, 0x2a, 0x4d, 0x18, 4, 0, 0, 0, 1, 2, 3, 4];
+        data.extend(zstd::encode_all(TEXT, 1).unwrap());
+        data.extend(zstd::encode_all(TEXT, 19).unwrap());
+        assert_eq!(unzstd_all(&data).unwrap(), [TEXT, TEXT].concat());
+    }
+
+    #[test]
+    fn long_windows_are_read() {
+        // Past the 128 MiB libzstd reads by default, as `zstd --long=28` asks for
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 3).unwrap();
+        encoder.window_log(28).unwrap();
+        encoder.long_distance_matching(true).unwrap();
+        encoder.write_all(TEXT).unwrap();
+        let data = encoder.finish().unwrap();
+        assert_eq!(unzstd_all(&data).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn truncated_and_damaged_frames_fail() {
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 3).unwrap();
+        encoder.include_checksum(true).unwrap();
+        encoder.write_all(TEXT).unwrap();
+        let data = encoder.finish().unwrap();
+        assert!(unzstd_all(&data[..data.len() - 6]).is_err());
+        let mut damaged = data.clone();
+        *damaged.last_mut().unwrap() ^= 0xff;
+        assert!(unzstd_all(&damaged).is_err());
+    }
+
+    /// `data` as one BGZF block, as `bgzip` writes it.
+    fn bgzf_block(data: &[u8]) -> Vec<u8> {
+        let mut deflater = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        deflater.write_all(data).unwrap();
+        let compressed = deflater.finish().unwrap();
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let mut block = vec![0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0];
+        block.extend(((compressed.len() + 25) as u16).to_le_bytes());
+        block.extend(compressed);
+        block.extend(crc.sum().to_le_bytes());
+        block.extend((data.len() as u32).to_le_bytes());
+        block
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Flip a byte of the CRC32 (`from_end` 8) or ISIZE (`from_end` 4) a gzip member ends with.
+    fn damage_trailer(member: &[u8], from_end: usize) -> Vec<u8> {
+        let mut damaged = member.to_vec();
+        let at = damaged.len() - from_end;
+        damaged[at] ^= 0xff;
+        damaged
+    }
+
+    fn gunzip_all(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        Gunzip::new(data).read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    /// Write `data` to a file of its own and read it through [`open`].
+    fn open_all(name: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let path = std::env::temp_dir().join(format!("speedscore-{}-{}.vcf.gz", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let path = path.to_str().unwrap();
+        let mut output = Vec::new();
+        let read = open(path, &InputConfig::default()).and_then(|mut reader| reader.read_to_end(&mut output).with_path(path));
+        std::fs::remove_file(path).unwrap();
+        read.map(|_| output)
+    }
+
+    #[test]
+    fn bgzf_blocks_are_read_up_to_the_eof_marker() {
+        let data = [bgzf_block(TEXT), bgzf_block(TEXT), EOF_MARKER.to_vec()].concat();
+        assert_eq!(open_all("bgzf", &data).unwrap(), [TEXT, TEXT].concat());
+    }
+
+    #[test]
+    fn gzip_truncated_mid_member_fails_at_its_end() {
+        let member = gzip(TEXT);
+        let cut = &member[..member.len() - 12];
+        assert_eq!(gunzip_all(cut).unwrap_err().to_string(), format!("file is truncated at byte {}", cut.len()));
+    }
+
+    #[test]
+    fn bgzf_truncated_mid_block_fails_at_its_end() {
+        let block = bgzf_block(TEXT);
+        let cut = &block[..block.len() - 12];
+        let path = std::env::temp_dir().join(format!("speedscore-cut-block-{}.vcf.gz", std::process::id()));
+        std::fs::write(&path, cut).unwrap();
+        let read = next_block(&mut File::open(&path).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap_err().to_string(), format!("file is truncated at byte {}", cut.len()));
+        // Read whole, the file has lost its end-of-file block too
+        let message = open_all("cut-file", cut).unwrap_err().to_string();
+        assert!(message.ends_with(&format!("file is truncated at byte {}: it lacks the end-of-file block bgzip ends every file with", cut.len())), "{}", message);
+    }
+
+    #[test]
+    fn bgzf_without_eof_marker_fails() {
+        let data = [bgzf_block(TEXT), bgzf_block(TEXT)].concat();
+        let message = open_all("no-eof", &data).unwrap_err().to_string();
+        assert!(message.ends_with(&format!("file is truncated at byte {}: it lacks the end-of-file block bgzip ends every file with", data.len())), "{}", message);
+    }
+
+    // flate2 checks CRC32 and ISIZE together, so either mismatch is the same error
+    const MISMATCH: &str = "corrupt gzip stream does not have a matching checksum";
+
+    #[test]
+    fn gzip_crc_and_isize_mismatches_fail() {
+        let member = gzip(TEXT);
+        for from_end in [8, 4] {
+            let message = gunzip_all(&damage_trailer(&member, from_end)).unwrap_err().to_string();
+            assert_eq!(message, format!("file is corrupt around byte {}: {}", member.len(), MISMATCH));
+        }
+    }
+
+    #[test]
+    fn bgzf_crc_and_isize_mismatches_fail() {
+        let first = bgzf_block(TEXT);
+        for from_end in [8, 4] {
+            let damaged = damage_trailer(&bgzf_block(TEXT), from_end);
+            assert_eq!(inflate(&damaged, 0).unwrap_err().to_string(), format!("file is corrupt at byte 0: {}", MISMATCH));
+            // The second block is the damaged one
+            let data = [first.clone(), damaged, EOF_MARKER.to_vec()].concat();
+            let message = open_all("damaged", &data).unwrap_err().to_string();
+            assert!(message.ends_with(&format!("file is corrupt at byte {}: {}", first.len(), MISMATCH)), "{}", message);
+        }
+    }
+
+    #[test]
+    fn zstd_is_told_from_gzip_and_text() {
+        assert!(!is_zstd(&GZIP_MAGIC));
+        assert!(!is_zstd(TEXT));
+        assert!(!is_zstd(&ZSTD_MAGIC[..3]));
+    }
+}
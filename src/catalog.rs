@@ -0,0 +1,240 @@
+//! PGS Catalog metadata for the score being applied (`--pgs-catalog`).
+//!
+//! PGS Catalog scoring files name their score in a `#pgs_id=` header line. With
+//! `--pgs-catalog`, that ID is looked up with the Catalog's REST API, and the score's name,
+//! trait, publication, genome build and variant count are printed and recorded in the JSON
+//! and single-sample results and the `--provenance` file, so that whoever reads them knows
+//! exactly which published score was applied. The lookup needs `--features pgs-catalog`.
+//!
+//! `--pgs-id` looks a score up by its ID instead, and scores with its scoring file as the
+//! Catalog harmonized it to the VCF's build, downloaded into the cache (see
+//! [`crate::remote`]), which also needs `--features remote`.
+
+use std::fmt;
+use std::io::BufRead;
+use serde::{Deserialize, Serialize};
+use crate::common::open_scoring_file;
+use crate::error::{Error, Result};
+#[cfg(feature = "pgs-catalog")]
+use std::collections::HashMap;
+#[cfg(feature = "pgs-catalog")]
+use crate::error::IoContext;
+#[cfg(feature = "pgs-catalog")]
+use crate::genome::GenomeBuild;
+
+/// The Catalog's REST endpoint for one score, to which its ID is appended.
+#[cfg(feature = "pgs-catalog")]
+const SCORE_API: &str = "https://www.pgscatalog.org/rest/score/";
+
+/// How the PGS Catalog describes a score.
+#[derive(Clone, Debug, Serialize)]
+pub struct ScoreMetadata {
+    pub pgs_id: String,
+    pub name: Option<String>,
+    /// The trait as the publication reports it
+    pub trait_reported: Option<String>,
+    /// The trait's ontology terms (EFO, MONDO, HP, ...)
+    pub trait_ontology: Vec<OntologyTerm>,
+    pub publication: Option<Publication>,
+    /// The build the published variant positions are on, as the Catalog gives it (`NR` when
+    /// not reported)
+    pub genome_build: Option<String>,
+    /// Variants in the published score
+    pub variants: Option<u64>,
+    pub scoring_file_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OntologyTerm {
+    pub id: String,
+    pub label: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Publication {
+    /// The Catalog's publication ID, e.g. `PGP000001`
+    pub pgp_id: String,
+    /// First author, journal and year, e.g. `Mavaddat N et al. J Natl Cancer Inst (2015)`
+    pub citation: String,
+    pub title: Option<String>,
+    pub doi: Option<String>,
+    pub pmid: Option<String>,
+}
+
+/// A score as the REST API returns it; fields not used are left out.
+#[cfg(feature = "pgs-catalog")]
+#[derive(Deserialize)]
+struct ApiScore {
+    id: Option<String>,
+    name: Option<String>,
+    trait_reported: Option<String>,
+    #[serde(default)]
+    trait_efo: Vec<OntologyTerm>,
+    publication: Option<ApiPublication>,
+    variants_genomebuild: Option<String>,
+    variants_number: Option<u64>,
+    ftp_scoring_file: Option<String>,
+    /// The harmonized scoring files by build, e.g. `GRCh38`
+    #[serde(default)]
+    ftp_harmonized_scoring_files: HashMap<String, ApiHarmonized>,
+}
+
+#[cfg(feature = "pgs-catalog")]
+#[derive(Deserialize)]
+struct ApiHarmonized {
+    /// The file with the positions lifted to the build (`hmPOS`)
+    positions: Option<String>,
+}
+
+#[cfg(feature = "pgs-catalog")]
+#[derive(Deserialize)]
+struct ApiPublication {
+    id: String,
+    title: Option<String>,
+    doi: Option<String>,
+    /// A number, though older entries may give it as a string
+    #[serde(rename = "PMID")]
+    pmid: Option<serde_json::Value>,
+    journal: Option<String>,
+    firstauthor: Option<String>,
+    date_publication: Option<String>,
+}
+
+/// The ID `#pgs_id=` declares in the scoring file's header, if any.
+pub fn pgs_id(scoring_path: &str) -> Result<Option<String>> {
+    let reader = open_scoring_file(scoring_path)?;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::read(scoring_path, index + 1, e))?;
+        let Some(comment) = line.trim_start_matches('\u{feff}').strip_prefix('#') else {
+            break;
+        };
+        if let Some(("pgs_id", id)) = comment.split_once('=') {
+            let id = id.trim();
+            if !id.is_empty() {
+                return Ok(Some(id.to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Look up the score the scoring file at `scoring_path` declares in the PGS Catalog.
+#[cfg(feature = "pgs-catalog")]
+pub fn lookup(scoring_path: &str) -> Result<ScoreMetadata> {
+    let id = pgs_id(scoring_path)?
+        .ok_or_else(|| Error::invalid(scoring_path, "no #pgs_id= header line naming the score to look up in the PGS Catalog"))?;
+    fetch(&id, scoring_path).map(ScoreMetadata::from_api)
+}
+
+/// Look up the score `id` in the PGS Catalog, with the URL of its scoring file harmonized
+/// to `build`.
+#[cfg(feature = "pgs-catalog")]
+pub fn scoring_file(id: &str, build: GenomeBuild) -> Result<(ScoreMetadata, String)> {
+    let mut score = fetch(id, "--pgs-id")?;
+    let url = score.ftp_harmonized_scoring_files.remove(&build.to_string()).and_then(|files| files.positions).ok_or_else(|| {
+        Error::invalid("--pgs-id", format!("{} has no scoring file harmonized to {} in the PGS Catalog", id, build))
+    })?;
+    // Older entries give the EBI FTP server's URLs, which it also serves over HTTPS
+    let url = match url.strip_prefix("ftp://") {
+        Some(rest) => format!("https://{}", rest),
+        None => url,
+    };
+    Ok((ScoreMetadata::from_api(score), url))
+}
+
+/// The Catalog's entry for the score `id`; errors name `context`.
+#[cfg(feature = "pgs-catalog")]
+fn fetch(id: &str, context: &str) -> Result<ApiScore> {
+    let url = format!("{}{}", SCORE_API, id);
+    let response = match ureq::get(&url).timeout(std::time::Duration::from_secs(60)).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => return Err(Error::invalid(context, format!("{} is not in the PGS Catalog", id))),
+        // ureq names the URL in its errors
+        Err(e) => return Err(std::io::Error::other(e)).with_path(context),
+    };
+    let score: ApiScore =
+        serde_json::from_reader(response.into_reader()).map_err(|e| Error::invalid(&url, format!("not a PGS Catalog score: {}", e)))?;
+    // The API answers an unknown ID with an empty object
+    if score.id.is_none() {
+        return Err(Error::invalid(context, format!("{} is not in the PGS Catalog", id)));
+    }
+    Ok(score)
+}
+
+impl ScoreMetadata {
+    #[cfg(feature = "pgs-catalog")]
+    fn from_api(score: ApiScore) -> Self {
+        let publication = score.publication.map(|p| {
+            let year = p.date_publication.as_deref().and_then(|date| date.get(..4));
+            let citation = [
+                p.firstauthor.map(|author| format!("{} et al.", author)),
+                p.journal,
+                year.map(|year| format!("({})", year)),
+            ];
+            let pmid = p.pmid.and_then(|pmid| match pmid {
+                serde_json::Value::Number(n) => Some(n.to_string()),
+                serde_json::Value::String(s) if !s.is_empty() => Some(s),
+                _ => None,
+            });
+            Publication {
+                pgp_id: p.id,
+                citation: citation.into_iter().flatten().collect::<Vec<_>>().join(" "),
+                title: p.title,
+                doi: p.doi,
+                pmid,
+            }
+        });
+        ScoreMetadata {
+            pgs_id: score.id.unwrap_or_default(),
+            name: score.name,
+            trait_reported: score.trait_reported,
+            trait_ontology: score.trait_efo,
+            publication,
+            genome_build: score.variants_genomebuild,
+            variants: score.variants_number,
+            scoring_file_url: score.ftp_scoring_file,
+        }
+    }
+
+    /// Columns for single-sample results, tab-free.
+    pub fn columns(&self) -> Vec<(&'static str, String)> {
+        let text = |value: Option<&str>| value.unwrap_or("").replace(['\t', '\n', '\r'], " ");
+        vec![
+            ("PGS_ID", self.pgs_id.clone()),
+            ("PGS_Trait", text(self.trait_reported.as_deref())),
+            ("PGS_Publication", text(self.publication.as_ref().map(|p| p.citation.as_str()))),
+            ("PGS_Genome_Build", text(self.genome_build.as_deref())),
+            ("PGS_Variants", self.variants.map_or_else(String::new, |n| n.to_string())),
+        ]
+    }
+}
+
+impl fmt::Display for ScoreMetadata {
+    /// A summary for the console, over several lines.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PGS Catalog: {}", self.pgs_id)?;
+        if let Some(name) = &self.name {
+            write!(f, " ({})", name)?;
+        }
+        if let Some(trait_reported) = &self.trait_reported {
+            write!(f, "\n  Trait: {}", trait_reported)?;
+            let terms: Vec<&str> = self.trait_ontology.iter().map(|term| term.id.as_str()).collect();
+            if !terms.is_empty() {
+                write!(f, " [{}]", terms.join(", "))?;
+            }
+        }
+        if let Some(publication) = &self.publication {
+            write!(f, "\n  Publication: {} {}", publication.pgp_id, publication.citation)?;
+            if let Some(doi) = &publication.doi {
+                write!(f, ", doi:{}", doi)?;
+            }
+        }
+        if let Some(build) = &self.genome_build {
+            write!(f, "\n  Genome build: {}", build)?;
+        }
+        if let Some(variants) = self.variants {
+            write!(f, "\n  Variants: {}", variants)?;
+        }
+        Ok(())
+    }
+}
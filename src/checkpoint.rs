@@ -0,0 +1,470 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use crate::checksum;
+use crate::common::{EffectWeights, ScoreOptions, SiteCounts};
+use crate::error::{Error, ErrorPolicy, IoContext, PolicyState, Result};
+use crate::multi_sample::{Analyses, SampleData};
+use crate::order::SortPosition;
+
+const MAGIC: &str = "#SpeedScore checkpoint v15";
+/// What every checkpoint's first line starts with, whichever version wrote it
+const MAGIC_PREFIX: &str = "#SpeedScore checkpoint ";
+/// Columns of a sample row before its PC coordinates and further scores
+const COLUMNS: usize = 11;
+
+/// Where to checkpoint a run and how often (in VCF lines).
+#[derive(Clone, Debug)]
+pub struct CheckpointConfig {
+    pub path: String,
+    pub interval: usize,
+}
+
+/// Snapshot of a multi-sample run: how far into the VCF we got, plus every
+/// sample's accumulators at that point.
+pub struct Checkpoint {
+    /// The VCF, or the VCFs read as one, in order
+    pub vcf_paths: Vec<String>,
+    pub scoring_variants: usize,
+    pub fingerprint: Fingerprint,
+    pub lines_processed: usize,
+    pub vcf_chr_format: bool,
+    pub sites: SiteCounts,
+    /// Malformed and rejected records up to `lines_processed`
+    pub policy: PolicyState,
+    pub sort: SortPosition,
+    pub samples: Vec<(String, SampleData)>,
+}
+
+/// What a run's scores depend on besides the VCF and its samples, so that a checkpoint
+/// taken with other weights or options is not resumed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fingerprint {
+    /// SHA-256 of the weights scored, those of further scoring files included, after every
+    /// option that changes them
+    pub weights: String,
+    /// The options that turn genotypes into scores (`--dosage`, `--model`, `--include` and
+    /// the others), as JSON
+    pub options: String,
+    /// The `--pt-thresholds`, and a SHA-256 of what each other analysis scored in the same
+    /// pass reads (`--permutations` with `--seed`, `--pca-loadings`, sets, `--environment`,
+    /// `--pedigree`), as JSON
+    pub analyses: String,
+}
+
+impl Fingerprint {
+    pub fn of(effect_weights: &EffectWeights, analyses: &Analyses, options: &ScoreOptions, policy: &ErrorPolicy) -> Self {
+        let mut sha256 = Sha256::new();
+        let mut keys: Vec<_> = effect_weights.keys().collect();
+        keys.sort();
+        for key in keys {
+            // `{:?}` on f32 prints the shortest representation that round-trips exactly
+            sha256.update(format!("{}\t{}\t{:?}\n", key.0, key.1, effect_weights[key]));
+        }
+        if let Some(panel) = analyses.panel {
+            let mut keys: Vec<_> = panel.positions().collect();
+            keys.sort();
+            for key in keys {
+                sha256.update(format!("{}\t{}\t{:?}\n", key.0, key.1, panel.weights(key)));
+            }
+        }
+        let mut options = serde_json::to_value(options).expect("options serialization cannot fail");
+        options["filter"] = policy.record_filter().map(|filter| filter.to_string()).into();
+        Fingerprint { weights: checksum::hex(&sha256.finalize()), options: options.to_string(), analyses: analyses_json(effect_weights, analyses).to_string() }
+    }
+}
+
+/// [`Fingerprint::analyses`]: each analysis's digest, or null where it is not run.
+fn analyses_json(effect_weights: &EffectWeights, analyses: &Analyses) -> Value {
+    let mut keys: Vec<_> = effect_weights.keys().collect();
+    keys.sort();
+    let permutations = analyses.permutations.map(|permutations| {
+        digest(keys.iter().map(|key| format!("{}\t{}\t{:?}\n", key.0, key.1, permutations.weights(key))))
+    });
+    let loadings = analyses.loadings.map(|loadings| {
+        let mut variants: Vec<_> = loadings.variants.iter().collect();
+        variants.sort_by(|a, b| a.0.cmp(b.0));
+        let rows = variants.into_iter().map(|(key, loading)| format!("{}\t{}\t{}\t{:?}\t{:?}\n", key.0, key.1, loading.effect_allele, loading.frequency, loading.weights));
+        digest([format!("{:?}\t{:?}\n", loadings.names, loadings.calibration)].into_iter().chain(rows))
+    });
+    let sets = analyses.partitions.map(|partitions| {
+        let rows = keys.iter().map(|key| format!("{}\t{}\t{:?}\n", key.0, key.1, partitions.sets(key)));
+        digest([format!("{:?}\n", partitions.names)].into_iter().chain(rows))
+    });
+    let environments = analyses.environments.map(|environments| {
+        let mut rows: Vec<_> = environments.iter().map(|(sample, value)| format!("{}\t{:?}\n", sample, value)).collect();
+        rows.sort();
+        digest(rows)
+    });
+    let pedigree = analyses.pedigree.map(|pedigree| {
+        let mut rows: Vec<_> = pedigree.iter().map(|(child, (father, mother))| format!("{}\t{}\t{}\n", child, father, mother)).collect();
+        rows.sort();
+        digest(rows)
+    });
+    json!({
+        "pt_thresholds": analyses.thresholds,
+        "permutations": permutations,
+        "pca_loadings": loadings,
+        "sets": sets,
+        "environment": environments,
+        "pedigree": pedigree,
+    })
+}
+
+fn digest(rows: impl IntoIterator<Item = String>) -> String {
+    let mut sha256 = Sha256::new();
+    for row in rows {
+        sha256.update(row);
+    }
+    checksum::hex(&sha256.finalize())
+}
+
+impl Checkpoint {
+    /// Write the checkpoint atomically: a temp file is written and fsynced first,
+    /// then renamed over `path`, so a kill mid-write never leaves a torn file.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let tmp_path = format!("{}.tmp", path);
+        self.write_to(&tmp_path).with_path(&tmp_path)?;
+        fs::rename(&tmp_path, path).with_path(path)
+    }
+
+    fn write_to(&self, tmp_path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(tmp_path)?);
+        writeln!(out, "{}", MAGIC)?;
+        writeln!(out, "vcf\t{}", self.vcf_paths.join("\t"))?;
+        writeln!(out, "scoring_variants\t{}", self.scoring_variants)?;
+        writeln!(out, "weights\t{}", self.fingerprint.weights)?;
+        writeln!(out, "options\t{}", self.fingerprint.options)?;
+        writeln!(out, "analyses\t{}", self.fingerprint.analyses)?;
+        writeln!(out, "lines_processed\t{}", self.lines_processed)?;
+        writeln!(out, "vcf_chr_format\t{}", self.vcf_chr_format)?;
+        writeln!(out, "position_hits\t{}", self.sites.position_hits)?;
+        writeln!(out, "allele_hits\t{}", self.sites.allele_hits)?;
+        // JSON escapes the tabs and line breaks of rejected records
+        writeln!(out, "policy\t{}", serde_json::to_string(&self.policy)?)?;
+        writeln!(out, "sort\t{}", serde_json::to_string(&self.sort)?)?;
+        let first = self.samples.first().map(|(_, data)| data);
+        writeln!(out, "pcs\t{}", first.map_or(0, |data| data.pcs.len()))?;
+        writeln!(out, "thresholds\t{}", first.map_or(0, |data| data.threshold_scores.len()))?;
+        writeln!(out, "permutations\t{}", first.map_or(0, |data| data.null_deviations.len()))?;
+        writeln!(out, "sets\t{}", first.map_or(0, |data| data.set_scores.len()))?;
+        writeln!(out, "scores\t{}", first.map_or(0, |data| data.panel_scores.len()))?;
+        writeln!(out, "samples\t{}", self.samples.len())?;
+        for (name, data) in &self.samples {
+            // `{}` on f64 prints the shortest representation that round-trips exactly
+            write!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                name,
+                data.score,
+                data.total_variants,
+                data.matched_variants,
+                data.dosage_sum,
+                data.variance.map_or("NA".to_string(), |v| v.to_string()),
+                data.effective_variants.map_or("NA".to_string(), |v| v.to_string()),
+                data.weight_coverage.map_or("NA".to_string(), |v| v.to_string()),
+                data.interaction_sum.map_or("NA".to_string(), |v| v.to_string()),
+                data.maternal_score.map_or("NA".to_string(), |v| v.to_string()),
+                data.paternal_score.map_or("NA".to_string(), |v| v.to_string())
+            )?;
+            let extras = [&data.pcs, &data.threshold_scores, &data.null_deviations, &data.set_scores, &data.panel_scores];
+            for value in extras.into_iter().flatten() {
+                write!(out, "\t{}", value)?;
+            }
+            for matched in &data.panel_matched {
+                write!(out, "\t{}", matched)?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()?;
+        out.get_ref().sync_all()
+    }
+
+    /// Load a checkpoint if one exists at `path`. Returns `Ok(None)` when there is nothing to resume.
+    pub fn load(path: &str) -> Result<Option<Self>> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(path).with_path(path)?);
+        let mut lines = reader.lines();
+
+        match lines.next().transpose().with_path(path)?.as_deref() {
+            Some(MAGIC) => {}
+            Some(line) if line.starts_with(MAGIC_PREFIX) => {
+                return Err(invalid(path, "written by another version of SpeedScore; delete it to start over"));
+            }
+            _ => return Err(invalid(path, "not a SpeedScore checkpoint file")),
+        }
+
+        let vcf_paths = header_value(&mut lines, path, "vcf")?.split('\t').map(str::to_string).collect();
+        let scoring_variants = parse_field(&header_value(&mut lines, path, "scoring_variants")?, path)?;
+        let fingerprint = Fingerprint {
+            weights: header_value(&mut lines, path, "weights")?,
+            options: header_value(&mut lines, path, "options")?,
+            analyses: header_value(&mut lines, path, "analyses")?,
+        };
+        let lines_processed = parse_field(&header_value(&mut lines, path, "lines_processed")?, path)?;
+        let vcf_chr_format = parse_field(&header_value(&mut lines, path, "vcf_chr_format")?, path)?;
+        let sites = SiteCounts {
+            position_hits: parse_field(&header_value(&mut lines, path, "position_hits")?, path)?,
+            allele_hits: parse_field(&header_value(&mut lines, path, "allele_hits")?, path)?,
+        };
+        let policy = json_field(&header_value(&mut lines, path, "policy")?, path)?;
+        let sort = json_field(&header_value(&mut lines, path, "sort")?, path)?;
+        let pcs: usize = parse_field(&header_value(&mut lines, path, "pcs")?, path)?;
+        let thresholds: usize = parse_field(&header_value(&mut lines, path, "thresholds")?, path)?;
+        let permutations: usize = parse_field(&header_value(&mut lines, path, "permutations")?, path)?;
+        let sets: usize = parse_field(&header_value(&mut lines, path, "sets")?, path)?;
+        let scores: usize = parse_field(&header_value(&mut lines, path, "scores")?, path)?;
+        let sample_count: usize = parse_field(&header_value(&mut lines, path, "samples")?, path)?;
+
+        let mut samples = Vec::with_capacity(sample_count);
+        // Rows end with the sample's PC coordinates, thresholded scores, null deviations, set
+        // scores, and scores and matched counts over further scoring files, if any
+        for line in lines {
+            let line = line.with_path(path)?;
+            let parts: Vec<&str> = line.split('\t').collect();
+            if parts.len() != COLUMNS + pcs + thresholds + permutations + sets + 2 * scores {
+                return Err(invalid(path, "malformed sample row"));
+            }
+            let mut values = parts[COLUMNS..].iter().map(|value| parse_field::<f64>(value, path));
+            let data = SampleData {
+                score: parse_field(parts[1], path)?,
+                total_variants: parse_field(parts[2], path)?,
+                matched_variants: parse_field(parts[3], path)?,
+                dosage_sum: parse_field(parts[4], path)?,
+                variance: optional_field(parts[5], path)?,
+                effective_variants: optional_field(parts[6], path)?,
+                weight_coverage: optional_field(parts[7], path)?,
+                interaction_sum: optional_field(parts[8], path)?,
+                maternal_score: optional_field(parts[9], path)?,
+                paternal_score: optional_field(parts[10], path)?,
+                pcs: values.by_ref().take(pcs).collect::<Result<_>>()?,
+                threshold_scores: values.by_ref().take(thresholds).collect::<Result<_>>()?,
+                null_deviations: values.by_ref().take(permutations).collect::<Result<_>>()?,
+                set_scores: values.by_ref().take(sets).collect::<Result<_>>()?,
+                panel_scores: values.take(scores).collect::<Result<_>>()?,
+                panel_matched: parts[parts.len() - scores..].iter().map(|value| parse_field(value, path)).collect::<Result<_>>()?,
+            };
+            samples.push((parts[0].to_string(), data));
+        }
+
+        if samples.len() != sample_count {
+            return Err(invalid(path, "sample rows do not match the recorded sample count (truncated file?)"));
+        }
+
+        Ok(Some(Checkpoint { vcf_paths, scoring_variants, fingerprint, lines_processed, vcf_chr_format, sites, policy, sort, samples }))
+    }
+
+    /// Make sure this checkpoint was taken from the same inputs we are about to process.
+    /// `fresh` is a new sample's accumulators, which saved ones must match in shape.
+    pub fn validate(&self, path: &str, vcf_paths: &[String], scoring_variants: usize, fingerprint: &Fingerprint, sample_names: &[String], fresh: &SampleData) -> Result<()> {
+        if self.vcf_paths != vcf_paths {
+            return Err(mismatch(path, format!("checkpoint is for VCF '{}', not '{}'", self.vcf_paths.join("', '"), vcf_paths.join("', '"))));
+        }
+        if self.scoring_variants != scoring_variants {
+            return Err(mismatch(path, format!(
+                "checkpoint was taken with {} scoring variants, current scoring file has {}",
+                self.scoring_variants, scoring_variants
+            )));
+        }
+        if self.fingerprint.weights != fingerprint.weights {
+            return Err(mismatch(path, "checkpoint was taken with other scoring weights (a changed scoring file, or an option that changes its weights)".to_string()));
+        }
+        if self.fingerprint.options != fingerprint.options {
+            let differing = differing(&self.fingerprint.options, &fingerprint.options);
+            return Err(mismatch(path, format!("checkpoint was taken with other scoring options: {}", differing)));
+        }
+        if self.fingerprint.analyses != fingerprint.analyses {
+            let differing = differing(&self.fingerprint.analyses, &fingerprint.analyses);
+            return Err(mismatch(path, format!("checkpoint was taken with other analyses or their inputs: {}", differing)));
+        }
+        if self.samples.len() != sample_names.len()
+            || self.samples.iter().zip(sample_names).any(|(s, name)| &s.0 != name)
+        {
+            return Err(mismatch(path, "checkpoint sample names do not match the VCF header".to_string()));
+        }
+        if let Some((_, data)) = self.samples.iter().find(|(_, data)| data.pcs.len() != fresh.pcs.len()) {
+            return Err(mismatch(path, format!(
+                "checkpoint has {} PC coordinates per sample, this run projects onto {}",
+                data.pcs.len(),
+                fresh.pcs.len()
+            )));
+        }
+        if let Some((_, data)) = self.samples.iter().find(|(_, data)| data.threshold_scores.len() != fresh.threshold_scores.len()) {
+            return Err(mismatch(path, format!(
+                "checkpoint has {} thresholded scores per sample, this run has {} --pt-thresholds",
+                data.threshold_scores.len(),
+                fresh.threshold_scores.len()
+            )));
+        }
+        if let Some((_, data)) = self.samples.iter().find(|(_, data)| data.null_deviations.len() != fresh.null_deviations.len()) {
+            return Err(mismatch(path, format!(
+                "checkpoint has {} permutations per sample, this run has {}",
+                data.null_deviations.len(),
+                fresh.null_deviations.len()
+            )));
+        }
+        if let Some((_, data)) = self.samples.iter().find(|(_, data)| data.set_scores.len() != fresh.set_scores.len()) {
+            return Err(mismatch(path, format!(
+                "checkpoint has {} set scores per sample, this run partitions into {} sets",
+                data.set_scores.len(),
+                fresh.set_scores.len()
+            )));
+        }
+        if let Some((_, data)) = self.samples.iter().find(|(_, data)| data.panel_scores.len() != fresh.panel_scores.len()) {
+            return Err(mismatch(path, format!(
+                "checkpoint has {} scores over further scoring files per sample, this run has {}",
+                data.panel_scores.len(),
+                fresh.panel_scores.len()
+            )));
+        }
+        if self.samples.iter().any(|(_, data)| data.variance.is_some() != fresh.variance.is_some()) {
+            let (has, lacks) = if fresh.variance.is_some() { ("lacks", "has") } else { ("has", "lacks") };
+            return Err(mismatch(path, format!(
+                "checkpoint {} score standard errors, but this scoring file {} effect_weight_se",
+                has, lacks
+            )));
+        }
+        if self.samples.iter().any(|(_, data)| data.effective_variants.is_some() != fresh.effective_variants.is_some()) {
+            let (has, lacks) = if fresh.effective_variants.is_some() { ("lacks", "uses") } else { ("has", "does not use") };
+            return Err(mismatch(path, format!("checkpoint {} effective variant counts, but this run {} --info-field", has, lacks)));
+        }
+        if self.samples.iter().any(|(_, data)| data.weight_coverage.is_some() != fresh.weight_coverage.is_some()) {
+            let (has, lacks) = if fresh.weight_coverage.is_some() { ("lacks", "uses") } else { ("has", "does not use") };
+            return Err(mismatch(path, format!("checkpoint {} weight coverage, but this run {} --rescale-by-coverage", has, lacks)));
+        }
+        if self.samples.iter().any(|(_, data)| data.interaction_sum.is_some() != fresh.interaction_sum.is_some()) {
+            let (has, lacks) = if fresh.interaction_sum.is_some() { ("lacks", "uses") } else { ("has", "does not use") };
+            return Err(mismatch(path, format!("checkpoint {} interaction sums, but this run {} --environment", has, lacks)));
+        }
+        if self.samples.iter().any(|(_, data)| data.maternal_score.is_some() != fresh.maternal_score.is_some()) {
+            let (has, lacks) = if fresh.maternal_score.is_some() { ("lacks", "uses") } else { ("has", "does not use") };
+            return Err(mismatch(path, format!("checkpoint {} parent-of-origin scores, but this run {} --parent-of-origin", has, lacks)));
+        }
+        Ok(())
+    }
+}
+
+pub fn remove(path: &str) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::io(path, e)),
+        _ => Ok(()),
+    }
+}
+
+/// The keys of two JSON objects whose values differ, with both values.
+fn differing(saved: &str, current: &str) -> String {
+    let saved: Map<String, Value> = serde_json::from_str(saved).unwrap_or_default();
+    let current: Map<String, Value> = serde_json::from_str(current).unwrap_or_default();
+    let keys: BTreeSet<&String> = saved.keys().chain(current.keys()).collect();
+    keys.into_iter()
+        .filter(|key| saved.get(*key) != current.get(*key))
+        .map(|key| format!("{} {} (now {})", key, saved.get(key).unwrap_or(&Value::Null), current.get(key).unwrap_or(&Value::Null)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn header_value(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    path: &str,
+    key: &str,
+) -> Result<String> {
+    let line = lines.next().transpose().with_path(path)?.ok_or_else(|| invalid(path, "unexpected end of file"))?;
+    match line.split_once('\t') {
+        Some((k, v)) if k == key => Ok(v.to_string()),
+        _ => Err(invalid(path, &format!("expected '{}' entry", key))),
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(value: &str, path: &str) -> Result<T> {
+    value.parse().map_err(|_| invalid(path, &format!("invalid value '{}'", value)))
+}
+
+fn json_field<T: serde::de::DeserializeOwned>(value: &str, path: &str) -> Result<T> {
+    serde_json::from_str(value).map_err(|e| invalid(path, &format!("invalid value '{}': {}", value, e)))
+}
+
+/// A value written as `NA` when absent.
+fn optional_field<T: std::str::FromStr>(value: &str, path: &str) -> Result<Option<T>> {
+    match value {
+        "NA" => Ok(None),
+        value => parse_field(value, path).map(Some),
+    }
+}
+
+fn invalid(path: &str, msg: &str) -> Error {
+    Error::invalid(path, format!("invalid checkpoint: {}", msg))
+}
+
+fn mismatch(path: &str, msg: String) -> Error {
+    Error::invalid(path, format!("cannot resume: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rejected::{Input, Reason, Rejection};
+
+    #[test]
+    fn save_and_load_keep_the_error_policy_and_sort_check() {
+        let path = std::env::temp_dir().join(format!("speedscore-checkpoint-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let record = "1\tabc\tv9\tA\tG\n".to_string();
+        let checkpoint = Checkpoint {
+            vcf_paths: vec!["cohort.vcf".to_string()],
+            scoring_variants: 1,
+            fingerprint: Fingerprint { weights: "w".to_string(), options: "{}".to_string(), analyses: "{}".to_string() },
+            lines_processed: 20,
+            vcf_chr_format: false,
+            sites: SiteCounts::default(),
+            policy: PolicyState {
+                count: 2,
+                non_finite: 1,
+                filtered: 3,
+                skipped: Vec::new(),
+                rejected: vec![Rejection { input: Input::Vcf, line: 12, reason: Reason::Malformed, detail: "Invalid numeric position".to_string(), record: record.clone() }],
+            },
+            sort: SortPosition { chr: "2".to_string(), pos: 500, finished: vec!["1".to_string()], warned: false },
+            samples: vec![("S1".to_string(), SampleData { score: 0.5, ..Default::default() })],
+        };
+        checkpoint.save(path).unwrap();
+        let loaded = Checkpoint::load(path).unwrap().unwrap();
+        remove(path).unwrap();
+        assert_eq!((loaded.policy.count, loaded.policy.non_finite, loaded.policy.filtered), (2, 1, 3));
+        assert_eq!(loaded.policy.rejected[0].record, record);
+        assert_eq!(loaded.policy.rejected[0].reason, Reason::Malformed);
+        assert_eq!(loaded.sort, checkpoint.sort);
+        assert_eq!(loaded.samples[0].1.score, 0.5);
+    }
+
+    #[test]
+    fn resumes_only_the_same_vcfs() {
+        let path = std::env::temp_dir().join(format!("speedscore-checkpoint-vcfs-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let vcfs = vec!["chr1,2.vcf.gz".to_string(), "chr3.vcf.gz".to_string()];
+        let fingerprint = Fingerprint { weights: "w".to_string(), options: "{}".to_string(), analyses: "{}".to_string() };
+        let checkpoint = Checkpoint {
+            vcf_paths: vcfs.clone(),
+            scoring_variants: 1,
+            fingerprint: fingerprint.clone(),
+            lines_processed: 20,
+            vcf_chr_format: false,
+            sites: SiteCounts::default(),
+            policy: PolicyState::default(),
+            sort: SortPosition::default(),
+            samples: vec![("S1".to_string(), SampleData::default())],
+        };
+        checkpoint.save(path).unwrap();
+        let loaded = Checkpoint::load(path).unwrap().unwrap();
+        remove(path).unwrap();
+        let samples = ["S1".to_string()];
+        assert_eq!(loaded.vcf_paths, vcfs);
+        loaded.validate(path, &vcfs, 1, &fingerprint, &samples, &SampleData::default()).unwrap();
+        let message = loaded.validate(path, &vcfs[..1], 1, &fingerprint, &samples, &SampleData::default()).unwrap_err().to_string();
+        assert!(message.contains("cannot resume: checkpoint is for VCF 'chr1,2.vcf.gz', 'chr3.vcf.gz', not 'chr1,2.vcf.gz'"), "{}", message);
+    }
+}
@@ -0,0 +1,64 @@
+//! SHA-256 and MD5 checksums of the input files, to tie results to the exact files they
+//! came from, and to check inputs against the checksums a data provider published.
+
+use std::fs::File;
+use std::io::Read;
+use md5::Md5;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::error::{Error, IoContext, Result};
+
+/// Bytes read at a time while hashing.
+const CHUNK: usize = 1 << 20;
+
+/// Checksums of one file, as lowercase hex.
+#[derive(Clone, Debug, Serialize)]
+pub struct Digests {
+    pub sha256: String,
+    pub md5: String,
+}
+
+/// Checksums of a run's inputs.
+#[derive(Clone, Debug, Serialize)]
+pub struct InputChecksums {
+    pub vcf: Digests,
+    pub scoring: Digests,
+}
+
+impl Digests {
+    /// Hash the file at `path`, both ways in one read.
+    pub fn of(path: &str) -> Result<Self> {
+        let mut file = File::open(path).with_path(path)?;
+        let mut sha256 = Sha256::new();
+        let mut md5 = Md5::new();
+        let mut buffer = vec![0; CHUNK];
+        loop {
+            let read = file.read(&mut buffer).with_path(path)?;
+            if read == 0 {
+                break;
+            }
+            sha256.update(&buffer[..read]);
+            md5.update(&buffer[..read]);
+        }
+        Ok(Digests { sha256: hex(&sha256.finalize()), md5: hex(&md5.finalize()) })
+    }
+
+    /// Check the file at `path` against `expected`, a SHA-256 (64 hex digits) or an MD5
+    /// (32), in either case.
+    pub fn verify(&self, path: &str, expected: &str) -> Result<()> {
+        let expected = expected.trim().to_ascii_lowercase();
+        let (name, actual) = match expected.len() {
+            64 => ("SHA-256", &self.sha256),
+            32 => ("MD5", &self.md5),
+            _ => return Err(Error::invalid(path, format!("expected checksum '{}' is neither a SHA-256 nor an MD5", expected))),
+        };
+        if *actual != expected {
+            return Err(Error::invalid(path, format!("{} is {}, expected {}", name, actual, expected)));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
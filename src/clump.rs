@@ -0,0 +1,268 @@
+//! LD clumping of the scoring file against a reference panel, for going from GWAS summary
+//! statistics to a P+T score in one run.
+//!
+//! Clumping follows plink's `--clump`: variants are visited from the smallest p-value up,
+//! and each one not yet clumped becomes an index variant, clumping away every other
+//! unclumped variant within the window whose genotype r² with it in the reference panel
+//! exceeds the threshold. Only index variants are kept. Variants missing from the panel
+//! cannot be clumped and are dropped, as plink does.
+//!
+//! The panel is a VCF, or a PLINK 1 binary fileset given as `prefix` or `prefix.bed`.
+//! r² is the squared Pearson correlation of hard-call allele counts over the panel samples
+//! called at both variants.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use rayon::prelude::*;
+use crate::common::{create_parent_dirs, open_scoring_file, parse_allele_count, parse_position, position_columns, trim_line, EffectWeights};
+use crate::bgzf;
+use crate::error::{Error, IoContext, Result};
+use crate::input::InputConfig;
+
+/// Stored in place of an allele count for a missing or multi-allelic call.
+const MISSING: u8 = 3;
+
+/// Clumping parameters; the defaults are PRSice's, which suit polygenic scores better
+/// than plink's own.
+#[derive(Clone, Copy, Debug)]
+pub struct ClumpOptions {
+    /// Variants further apart than this (in kb) are never clumped together
+    pub window_kb: u32,
+    /// Variants in LD with an index variant above this r² are clumped away
+    pub r2: f64,
+    /// Only variants at or below this p-value can be index variants
+    pub p: f64,
+}
+
+impl Default for ClumpOptions {
+    fn default() -> Self {
+        ClumpOptions { window_kb: 250, r2: 0.1, p: 1.0 }
+    }
+}
+
+/// What clumping did to the scoring file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClumpSummary {
+    pub input: usize,
+    /// Dropped for having no (biallelic, matching) genotypes in the reference panel
+    pub not_in_reference: usize,
+    /// Dropped for a p-value above [`ClumpOptions::p`] without being clumped
+    pub above_p: usize,
+    /// Clumped away by a more significant variant
+    pub clumped: usize,
+    /// Index variants, which stay in the scoring file
+    pub kept: usize,
+}
+
+/// Clump `effect_weights` in place against the panel at `reference`. Every variant needs
+/// a `p_value`.
+pub fn clump(effect_weights: &mut EffectWeights, reference: &str, options: &ClumpOptions, scoring_path: &str) -> Result<ClumpSummary> {
+    if !(options.r2 > 0.0 && options.r2 <= 1.0) {
+        return Err(Error::invalid("--clump-r2", format!("{} is not in (0, 1]", options.r2)));
+    }
+    let missing = effect_weights.values().filter(|w| w.p_value.is_none()).count();
+    if missing > 0 {
+        return Err(Error::invalid(
+            scoring_path,
+            format!("clumping needs a p_value for every variant; {} of {} have none", missing, effect_weights.len()),
+        ));
+    }
+
+    let genotypes = match plink_prefix(reference) {
+        Some(prefix) => read_plink(&prefix, effect_weights)?,
+        None => read_vcf(reference, effect_weights)?,
+    };
+    let mut summary = ClumpSummary { input: effect_weights.len(), ..Default::default() };
+    summary.not_in_reference = effect_weights.len() - genotypes.len();
+
+    // Per chromosome, the panel variants in position order
+    let mut chromosomes: HashMap<&str, Vec<u64>> = HashMap::new();
+    for (chr, pos) in genotypes.keys() {
+        chromosomes.entry(chr).or_default().push(*pos);
+    }
+    chromosomes.values_mut().for_each(|positions| positions.sort_unstable());
+
+    let p_value = |key: &(String, u64)| effect_weights[key].p_value.unwrap_or(1.0);
+    let mut order: Vec<&(String, u64)> = genotypes.keys().collect();
+    order.sort_by(|a, b| p_value(a).total_cmp(&p_value(b)).then_with(|| a.cmp(b)));
+
+    let window = u64::from(options.window_kb) * 1000;
+    // Index variants and the variants clumped with them
+    let mut done: HashSet<&(String, u64)> = HashSet::new();
+    let mut kept = HashSet::new();
+    for index in order {
+        if done.contains(index) {
+            continue;
+        }
+        if p_value(index) > options.p {
+            summary.above_p += 1;
+            continue;
+        }
+        done.insert(index);
+        kept.insert(index.clone());
+        let positions = &chromosomes[index.0.as_str()];
+        let start = positions.partition_point(|&pos| pos < index.1.saturating_sub(window));
+        let candidates: Vec<(&(String, u64), &Vec<u8>)> = positions[start..]
+            .iter()
+            .take_while(|&&pos| pos <= index.1.saturating_add(window))
+            .map(|&pos| genotypes.get_key_value(&(index.0.clone(), pos)).expect("positions come from the panel"))
+            .filter(|(key, _)| !done.contains(key))
+            .collect();
+        let index_genotypes = &genotypes[index];
+        let linked: Vec<&(String, u64)> = candidates
+            .into_par_iter()
+            .filter(|(_, other)| r_squared(index_genotypes, other) > options.r2)
+            .map(|(key, _)| key)
+            .collect();
+        summary.clumped += linked.len();
+        done.extend(linked);
+    }
+
+    summary.kept = kept.len();
+    effect_weights.retain(|key, _| kept.contains(key));
+    Ok(summary)
+}
+
+/// Copy the scoring file at `scoring_path` to `output`, keeping its comments, header and
+/// the rows still in `effect_weights`, all columns intact.
+pub fn write_clumped(scoring_path: &str, effect_weights: &EffectWeights, output: &str) -> Result<()> {
+    let mut text = String::new();
+    open_scoring_file(scoring_path)?.read_to_string(&mut text).with_path(scoring_path)?;
+    let mut kept = String::new();
+    // Positions of the chromosome and position columns, once the header is seen
+    let mut columns: Option<(usize, usize)> = None;
+    for line in text.lines().map(trim_line) {
+        let parts: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let keep = match columns {
+            _ if line.starts_with('#') => true,
+            _ if line.trim().is_empty() => false,
+            None => {
+                let (chr, pos, _) = position_columns(&parts).unwrap_or((0, 0, false));
+                columns = Some((chr, pos));
+                true
+            }
+            // Rows were validated when the scoring file was loaded
+            Some((chr, pos)) => {
+                let key = (parts[chr].trim_start_matches("chr").to_string(), parse_position(parts[pos]).unwrap_or(0));
+                effect_weights.contains_key(&key)
+            }
+        };
+        if keep {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    create_parent_dirs(output)?;
+    fs::write(output, kept).with_path(output)
+}
+
+/// Squared correlation of two variants' allele counts over the samples called at both.
+fn r_squared(a: &[u8], b: &[u8]) -> f64 {
+    let (mut n, mut sx, mut sy, mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+    for (&x, &y) in a.iter().zip(b) {
+        if x == MISSING || y == MISSING {
+            continue;
+        }
+        let (x, y) = (x as f64, y as f64);
+        n += 1.0;
+        sx += x;
+        sy += y;
+        sxx += x * x;
+        syy += y * y;
+        sxy += x * y;
+    }
+    let covariance = sxy - sx * sy / n;
+    let (vx, vy) = (sxx - sx * sx / n, syy - sy * sy / n);
+    if n < 2.0 || vx <= 0.0 || vy <= 0.0 {
+        return 0.0;
+    }
+    covariance * covariance / (vx * vy)
+}
+
+/// The fileset prefix if `reference` names a PLINK 1 binary fileset.
+fn plink_prefix(reference: &str) -> Option<String> {
+    if let Some(prefix) = reference.strip_suffix(".bed") {
+        return Some(prefix.to_string());
+    }
+    Path::new(&format!("{}.bed", reference)).exists().then(|| reference.to_string())
+}
+
+/// Whether a panel variant with alleles `a1`/`a2` at `key` is one of the scoring variants.
+fn wanted(effect_weights: &EffectWeights, key: &(String, u64), a1: &str, a2: &str) -> bool {
+    effect_weights.get(key).is_some_and(|w| w.effect_allele == a1 || w.effect_allele == a2)
+}
+
+/// Alt-allele counts of the panel VCF's samples at the scoring variants.
+fn read_vcf(path: &str, effect_weights: &EffectWeights) -> Result<HashMap<(String, u64), Vec<u8>>> {
+    let mut genotypes = HashMap::new();
+    for (index, line) in bgzf::open(path, &InputConfig::default())?.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.map_err(|e| Error::read(path, line_number, e))?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() < 10 {
+            return Err(Error::parse(path, line_number, "Too few columns (no genotypes)", &line));
+        }
+        let pos = parts[1].parse::<u64>().map_err(|_| Error::parse(path, line_number, "Invalid numeric position", &line))?;
+        let key = (parts[0].trim_start_matches("chr").to_string(), pos);
+        if !wanted(effect_weights, &key, parts[3], parts[4]) {
+            continue;
+        }
+        let counts = parts[9..]
+            .iter()
+            .map(|field| {
+                let gt = field.split(':').next().unwrap_or_default();
+                parse_allele_count(gt, true).unwrap_or(MISSING)
+            })
+            .collect();
+        genotypes.insert(key, counts);
+    }
+    Ok(genotypes)
+}
+
+/// A1-allele counts of a PLINK 1 fileset's samples at the scoring variants.
+fn read_plink(prefix: &str, effect_weights: &EffectWeights) -> Result<HashMap<(String, u64), Vec<u8>>> {
+    let fam_path = format!("{}.fam", prefix);
+    let samples = BufReader::new(File::open(&fam_path).with_path(&fam_path)?).lines().count();
+
+    let bed_path = format!("{}.bed", prefix);
+    let mut bed = BufReader::new(File::open(&bed_path).with_path(&bed_path)?);
+    let mut magic = [0u8; 3];
+    bed.read_exact(&mut magic).with_path(&bed_path)?;
+    if magic != [0x6c, 0x1b, 0x01] {
+        return Err(Error::invalid(&bed_path, "not a variant-major PLINK 1 .bed file"));
+    }
+
+    let bim_path = format!("{}.bim", prefix);
+    let bim = fs::read_to_string(&bim_path).with_path(&bim_path)?;
+    let mut record = vec![0u8; samples.div_ceil(4)];
+    let mut genotypes = HashMap::new();
+    for (index, line) in bim.lines().enumerate() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 6 {
+            return Err(Error::parse(&bim_path, index + 1, "Expected 6 columns", line));
+        }
+        let pos = parts[3].parse::<u64>().map_err(|_| Error::parse(&bim_path, index + 1, "Invalid numeric position", line))?;
+        bed.read_exact(&mut record)
+            .map_err(|_| Error::invalid(&bed_path, "shorter than the .bim and .fam files imply"))?;
+        let key = (parts[0].trim_start_matches("chr").to_string(), pos);
+        if !wanted(effect_weights, &key, parts[4], parts[5]) {
+            continue;
+        }
+        // Two bits per sample, lowest first: 00 hom A1, 01 missing, 10 het, 11 hom A2
+        let counts = (0..samples)
+            .map(|i| match (record[i / 4] >> (2 * (i % 4))) & 0b11 {
+                0b00 => 2,
+                0b10 => 1,
+                0b11 => 0,
+                _ => MISSING,
+            })
+            .collect();
+        genotypes.insert(key, counts);
+    }
+    Ok(genotypes)
+}
@@ -1,128 +1,1652 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Read};
+use std::ops::Range;
+use crate::aliases;
+use crate::bgzf;
+use crate::consumer;
+use crate::error::{Error, ErrorFormat, IoContext, NonFinite, OnError, Result, SkippedRecords};
+use crate::export::ExportFormat;
+use crate::genome::{BuildMismatch, GenomeBuild};
+use crate::input::InputConfig;
+use crate::multi_sample;
+use crate::order::Unsorted;
+use crate::plan::ManifestFormat;
+use crate::origin::HaplotypeOrder;
+use crate::rejected::{Input, Reason, Rejections};
+use crate::resources;
+use crate::scale::ScaleSource;
+use crate::sink::OutputFormat;
+use crate::source;
+use crate::spans::Stage;
+use crate::stream;
 use std::time::Duration;
-use clap::Parser;
-use flate2::read::GzDecoder;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
 
+// Top-level command line: either a subcommand, or the scoring flags in `Args`.
+// (A doc comment here would replace the `about` text in --help.)
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-pub struct Args {
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// How failures are reported on stderr; `json` prints one object with the failure kind and exit code
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+
+    /// Send tracing spans of the run to this OpenTelemetry collector over OTLP/HTTP, e.g.
+    /// http://localhost:4318 (requires --features otel)
+    #[arg(long, global = true, value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Print the JSON Schema of the JSON results and provenance files (of the current
+    /// version, or VERSION) and exit
+    #[arg(long, value_name = "VERSION", num_args = 0..=1, default_missing_value = "1", exclusive = true)]
+    pub print_schema: Option<u32>,
+
+    #[command(flatten)]
+    pub score: Option<Args>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a JSON-over-HTTP scoring service with scoring files kept in memory
+    Serve(ServeArgs),
+    /// Run a gRPC scoring service with streaming genotype submission
+    Grpc(GrpcArgs),
+    /// Watch an intake directory and score each VCF that lands in it
+    Watch(WatchArgs),
+    /// Score jobs taken from a Redis queue, publishing an event as each finishes
+    Queue(QueueArgs),
+    /// Split a run into jobs for a batch scheduler, writing a manifest of shards
+    Plan(PlanArgs),
+    /// Generate a synthetic VCF, a matching scoring file and the expected scores
+    Simulate(SimulateArgs),
+    /// Test the association of computed scores with a phenotype, adjusting for covariates
+    Evaluate(EvaluateArgs),
+    /// Compare computed scores with another tool's, such as a plink2 .sscore, sample by sample
+    Concordance(ConcordanceArgs),
+    /// Score a reference cohort and write its score distribution for --reference
+    BuildReference(BuildReferenceArgs),
+    /// Write scores, with each scoring variant's inclusion and harmonization, as an
+    /// sgkit-style Zarr or Arrow dataset
+    Export(ExportArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub bind: String,
+
+    /// Scoring file to register at startup, as ID=PATH (repeatable)
+    #[arg(long, value_name = "ID=PATH")]
+    pub scoring: Vec<String>,
+
+    /// Number of requests handled concurrently
+    #[arg(long, default_value_t = 4)]
+    pub workers: usize,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GrpcArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    pub bind: String,
+
+    /// Scoring file to load at startup, as ID=PATH (repeatable)
+    #[arg(long, value_name = "ID=PATH", required = true)]
+    pub scoring: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WatchArgs {
+    /// Directory where new VCFs are dropped
+    #[arg(long)]
+    pub intake: String,
+
+    /// Scoring file to apply to every VCF (repeatable)
+    #[arg(short, long, required = true)]
+    pub scoring: Vec<String>,
+
+    /// Directory for results and provenance files
+    #[arg(long)]
+    pub output_dir: String,
+
+    /// Where scored VCFs are moved (default: INTAKE/processed)
+    #[arg(long)]
+    pub processed_dir: Option<String>,
+
+    /// Where VCFs that failed to score are moved, with an .error file (default: INTAKE/failed)
+    #[arg(long)]
+    pub failed_dir: Option<String>,
+
+    /// Seconds between scans of the intake directory
+    #[arg(long, default_value_t = 10)]
+    pub poll_interval: u64,
+
+    /// Process the VCFs currently in the intake directory and exit
+    #[arg(long)]
+    pub once: bool,
+
+    /// Score from the FORMAT/DS dosage field instead of GT hard calls
+    #[arg(long)]
+    pub dosage: bool,
+
+    /// Genetic model applied to effect-allele counts
+    #[arg(long, value_enum, default_value_t = Model::Additive)]
+    pub model: Model,
+
+    /// Format of the per-sample results files
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub output_format: OutputFormat,
+
+    /// Record SHA-256 and MD5 checksums of each VCF and scoring file in the provenance files
+    #[arg(long)]
+    pub checksums: bool,
+
+    /// Address to serve Prometheus metrics on, at /metrics (requires --features server)
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_bind: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct QueueArgs {
+    /// Redis server to take jobs from
+    #[arg(long, value_name = "URL", default_value = "redis://127.0.0.1:6379")]
+    pub redis: String,
+
+    /// Redis list jobs are pushed onto, as JSON
+    #[arg(long, value_name = "KEY", default_value = "speedscore:jobs")]
+    pub jobs: String,
+
+    /// Redis list a completion event is pushed onto for each job, as JSON
+    #[arg(long, value_name = "KEY", default_value = "speedscore:events")]
+    pub events: String,
+
+    /// Scoring file jobs can ask for, as ID=PATH (repeatable)
+    #[arg(long, value_name = "ID=PATH", required = true)]
+    pub scoring: Vec<String>,
+
+    /// Directory for results and provenance files
+    #[arg(long)]
+    pub output_dir: String,
+
+    /// Number of jobs run concurrently
+    #[arg(long, default_value_t = 2)]
+    pub workers: usize,
+
+    /// Format of the per-sample results files
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub output_format: OutputFormat,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PlanArgs {
+    /// VCF the jobs score
+    #[arg(short = 'v', long)]
+    pub vcf: String,
+
+    /// Scoring file the jobs score against
+    #[arg(short = 's', long)]
+    pub scoring: String,
+
+    /// Samples per job; by default each job scores every sample
+    #[arg(long)]
+    pub samples_per_job: Option<usize>,
+
+    /// Also split by chromosome: one job per scoring-file chromosome (and sample block)
+    #[arg(long)]
+    pub by_chromosome: bool,
+
+    /// CPUs to request for each job
+    #[arg(long, default_value_t = 4)]
+    pub cpus_per_job: usize,
+
+    /// Directory the jobs write their results to
+    #[arg(long, default_value = ".")]
+    pub output_dir: String,
+
+    /// Where to write the manifest
+    #[arg(long)]
+    pub output: String,
+
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Json)]
+    pub manifest_format: ManifestFormat,
+
+    /// Score from the FORMAT/DS dosage field instead of GT hard calls
+    #[arg(long)]
+    pub dosage: bool,
+
+    /// Genetic model applied to effect-allele counts
+    #[arg(long, value_enum, default_value_t = Model::Additive)]
+    pub model: Model,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SimulateArgs {
+    /// Output prefix: writes PREFIX.vcf.gz, PREFIX.scoring.txt and PREFIX.expected.tsv
+    #[arg(long)]
+    pub out: String,
+
+    #[arg(long, default_value_t = 100)]
+    pub samples: usize,
+
+    #[arg(long, default_value_t = 10_000)]
+    pub variants: usize,
+
+    /// Variants are spread evenly over chromosomes 1..=N
+    #[arg(long, default_value_t = 22)]
+    pub chromosomes: usize,
+
+    /// Fraction of VCF variants that appear in the scoring file
+    #[arg(long, default_value_t = 0.5)]
+    pub scored_fraction: f64,
+
+    /// Fraction of genotypes written as missing
+    #[arg(long, default_value_t = 0.0)]
+    pub missing_rate: f64,
+
+    /// Also write a FORMAT/DS dosage field
+    #[arg(long)]
+    pub dosage: bool,
+
+    /// Write chromosomes as "chr1" instead of "1"
+    #[arg(long)]
+    pub chr_prefix: bool,
+
+    /// Random seed; the same seed gives the same files
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BuildReferenceArgs {
+    /// Multi-sample VCF of the reference cohort, e.g. 1000 Genomes
     #[arg(short, long)]
     pub vcf: String,
 
     #[arg(short, long)]
     pub scoring: String,
 
+    /// Sample table with an IID, #IID or Sample_Name column and a population column, for
+    /// per-population distributions besides the overall one
     #[arg(long)]
+    pub populations: Option<String>,
+
+    /// Population column of --populations
+    #[arg(long, default_value = "POP", requires = "populations")]
+    pub population_column: String,
+
+    /// Where to write the distribution file
+    #[arg(short, long)]
     pub output: String,
 
+    /// Score from the FORMAT/DS dosage field instead of GT hard calls
+    #[arg(long)]
+    pub dosage: bool,
+
+    /// Genetic model applied to effect-allele counts; runs using the file must match it
+    #[arg(long, value_enum, default_value_t = Model::Additive)]
+    pub model: Model,
+
+    /// Score centering; runs using the file must match it
+    #[arg(long, value_enum)]
+    pub center: Option<Centering>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    #[arg(short, long)]
+    pub vcf: String,
+
+    #[arg(short, long)]
+    pub scoring: String,
+
+    /// Dataset to write: a Zarr store, or with --format arrow a directory of Arrow IPC files
+    #[arg(short, long)]
+    pub output: String,
+
+    #[arg(long, value_enum, default_value_t = ExportFormat::Zarr)]
+    pub format: ExportFormat,
+
+    /// Score from the FORMAT/DS dosage field instead of GT hard calls
+    #[arg(long)]
+    pub dosage: bool,
+
+    /// Genetic model applied to effect-allele counts
+    #[arg(long, value_enum, default_value_t = Model::Additive)]
+    pub model: Model,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct EvaluateArgs {
+    /// Per-sample results of a scoring run (CSV output, or a plink2 .sscore file)
+    #[arg(long)]
+    pub scores: String,
+
+    /// Column of --scores to evaluate, e.g. Adjusted_Polygenic_Score, Calibrated_Z or SCORE1_AVG
+    #[arg(long, default_value = "Polygenic_Score")]
+    pub score_column: String,
+
+    /// Tab-, space- or comma-separated table with a header row, a sample ID column
+    /// (IID, #IID or Sample_Name), the phenotype and any covariates
+    #[arg(long)]
+    pub phenotype: String,
+
+    /// Phenotype column of --phenotype
+    #[arg(long)]
+    pub phenotype_column: String,
+
+    /// Covariate columns of --phenotype to adjust for, comma-separated
+    #[arg(long, value_delimiter = ',')]
+    pub covariates: Vec<String>,
+
+    /// Whether the phenotype is binary (logistic regression) or quantitative (linear)
+    #[arg(long = "trait", value_enum, default_value_t = TraitType::Auto)]
+    pub trait_type: TraitType,
+
+    /// Population prevalence of a binary trait, to report R² on the liability scale
+    #[arg(long)]
+    pub prevalence: Option<f64>,
+
+    /// Also write the report as JSON to this file
+    #[arg(long)]
+    pub output: Option<String>,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct ConcordanceArgs {
+    /// Per-sample results of a scoring run (CSV output, or a plink2 .sscore file)
+    #[arg(long)]
+    pub scores: String,
+
+    /// Column of --scores to compare
+    #[arg(long, default_value = "Polygenic_Score")]
+    pub score_column: String,
+
+    /// The other tool's per-sample scores: a table with a header row and a sample ID column
+    /// (IID, #IID or Sample_Name), such as a plink2 .sscore file
+    #[arg(long)]
+    pub other: String,
+
+    /// Column of --other to compare; by default SCORE1_SUM, or else SCORE1_AVG times ALLELE_CT
+    #[arg(long)]
+    pub other_column: Option<String>,
+
+    /// Largest difference between a sample's two scores that is not discordant, relative to
+    /// the larger score when that is beyond 1
+    #[arg(long, default_value_t = 1e-4)]
+    pub tolerance: f64,
+
+    /// Exit with code 3 if any sample is discordant
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Also write the report, with every discordant sample, as JSON to this file
+    #[arg(long)]
+    pub output: Option<String>,
+}
+
+/// Phenotype scale for `speedscore evaluate`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraitType {
+    /// Binary if every value is 0/1, or 1/2 (plink's control/case coding); otherwise quantitative
+    Auto,
+    Quantitative,
+    Binary,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct Args {
+    /// The VCF to score; several, comma-separated or with --vcf repeated, are scored as one
+    #[arg(short = 'v', long = "vcf", value_name = "VCF", value_delimiter = ',', required_unless_present_any = ["bfile", "pfile", "htsget", "vcf_dir"], conflicts_with_all = ["bfile", "pfile", "htsget", "vcf_dir"])]
+    pub vcfs: Vec<String>,
+
+    /// The path the VCF or the fileset given is read by, or the VCFs' paths joined by commas to name them in messages; set before scoring
+    #[arg(skip)]
+    pub vcf: String,
+
+    /// Score the PLINK fileset PREFIX.bed, PREFIX.bim and PREFIX.fam instead of a VCF
+    #[arg(long, value_name = "PREFIX", conflicts_with = "pfile")]
+    pub bfile: Option<String>,
+
+    /// Score the PLINK 2 fileset PREFIX.pgen, PREFIX.pvar and PREFIX.psam instead of a VCF
+    #[arg(long, value_name = "PREFIX", conflicts_with = "htsget")]
+    pub pfile: Option<String>,
+
+    /// Score the variants the htsget server endpoint URL (.../variants/<id>) serves, asking
+    /// only for the regions around the scoring variants. Requires --features remote
+    #[arg(long, value_name = "URL", conflicts_with = "bfile")]
+    pub htsget: Option<String>,
+
+    /// Score every VCF in this directory, or every file this glob pattern matches (e.g.
+    /// 'data/*.vcf.gz'), each on its own, into one table of all their samples (needs --output)
+    #[arg(long, value_name = "DIR|GLOB", conflicts_with_all = ["bfile", "pfile", "htsget"])]
+    pub vcf_dir: Option<String>,
+
+    /// The .sample file naming the samples of a BGEN or .gen given as the VCF
+    #[arg(long, value_name = "FILE")]
+    pub sample: Option<String>,
+
+    /// The chromosome of every variant of a .gen given as the VCF, for files whose first column is not one
+    #[arg(long, value_name = "CHR")]
+    pub gen_chr: Option<String>,
+
+    /// The scoring file; several, comma-separated or with --scoring repeated, are scored in
+    /// the same pass over the VCF, each into a score column of its own (implies --mode multi)
+    #[arg(short = 's', long = "scoring", value_name = "SCORING", value_delimiter = ',', required_unless_present = "pgs_ids")]
+    pub scorings: Vec<String>,
+
+    /// Score with the PGS Catalog's scoring file for this score (e.g. PGS000027), harmonized to
+    /// the VCF's build or --genome-build and downloaded into the cache; several are scored as
+    /// --scoring would score them. Implies --pgs-catalog, and requires building with
+    /// `--features pgs-catalog,remote`
+    #[arg(long = "pgs-id", value_name = "ID", value_delimiter = ',', conflicts_with_all = ["scorings", "vcf_dir"])]
+    pub pgs_ids: Vec<String>,
+
+    /// The first scoring file given, which every analysis is of; set before scoring
+    #[arg(skip)]
+    pub scoring: String,
+
+    /// Output file (default: derived from the VCF name, next to the VCF)
+    #[arg(long)]
+    pub output: Option<String>,
+
+    /// Format of the per-sample results file (multi-sample only)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub output_format: OutputFormat,
+
+    /// JSON mapping of the results onto a flat table for import into a LIMS or a REDCap
+    /// instrument: its columns, how sample IDs are rewritten and fixed fields
+    #[arg(long, value_name = "MAPPING", requires = "lims_output")]
+    pub lims_mapping: Option<String>,
+
+    /// Where to write the --lims-mapping table
+    #[arg(long, requires = "lims_mapping")]
+    pub lims_output: Option<String>,
+
     #[arg(long)]
     pub info: bool,
+
+    /// Show a live dashboard of throughput, per-chromosome progress and match rate
+    /// instead of the spinner (multi-sample only; requires --features tui)
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Mean-center scores by subtracting each variant's expected contribution under the
+    /// given effect-allele frequencies, as plink's score centering does
+    #[arg(long, value_enum)]
+    pub center: Option<Centering>,
+
+    /// Reproduce plink2 --score defaults for sample-by-sample comparison: missing genotypes
+    /// are mean-imputed and results are written as a plink2 .sscore file (implies --mode multi)
+    #[arg(long)]
+    pub plink_compat: bool,
+
+    /// Reference SNP loadings (e.g. 1000 Genomes PCs) to project samples onto in the same
+    /// pass; results gain PC coordinates and a PC-adjusted score (implies --mode multi)
+    #[arg(long)]
+    pub pca_loadings: Option<String>,
+
+    /// Ancestry calibration coefficients (mean and log-variance models on the PCs, as in
+    /// eMERGE/GenoPred) for per-sample calibrated z-scores; requires --pca-loadings
+    #[arg(long, requires = "pca_loadings")]
+    pub calibration: Option<String>,
+
+    /// Reference panel (VCF, or PLINK .bed/.bim/.fam prefix) to LD-clump the scoring file
+    /// against before scoring; needs a `p_value` column
+    #[arg(long)]
+    pub clump_reference: Option<String>,
+
+    /// Clumping window: variants further apart than this many kb are not clumped together
+    #[arg(long, default_value_t = 250, requires = "clump_reference")]
+    pub clump_kb: u32,
+
+    /// Clumping r² threshold: variants in stronger LD with a more significant variant are dropped
+    #[arg(long, default_value_t = 0.1, requires = "clump_reference")]
+    pub clump_r2: f64,
+
+    /// Largest p-value an index variant may have; variants above it that are not clumped are dropped
+    #[arg(long, default_value_t = 1.0, requires = "clump_reference")]
+    pub clump_p: f64,
+
+    /// Also save the clumped scoring file here
+    #[arg(long, requires = "clump_reference")]
+    pub clumped_scoring: Option<String>,
+
+    /// Reweight every variant by [2p(1-p)]^ALPHA, with p its scoring-file
+    /// `allelefrequency_effect`: 0.5 turns per-allele weights into per-standardized-genotype
+    /// weights, -0.5 the reverse
+    #[arg(long, allow_negative_numbers = true)]
+    pub frequency_scaling: Option<f64>,
+
+    /// Cap every |effect_weight| at this value, keeping its sign
+    #[arg(long, conflicts_with = "winsorize_quantile")]
+    pub max_abs_weight: Option<f64>,
+
+    /// Winsorize the effect weights: cap every |effect_weight| at this quantile (0 to 1,
+    /// e.g. 0.99) of the scoring file's |effect_weight|
+    #[arg(long)]
+    pub winsorize_quantile: Option<f64>,
+
+    /// UCSC chain file (plain or gzipped, e.g. hg19ToHg38.over.chain.gz) to lift the scoring
+    /// file's positions to the VCF's build with
+    #[arg(long, conflicts_with_all = ["set_column", "clumped_scoring"])]
+    pub liftover: Option<String>,
+
+    /// Write the scoring variants --liftover could not lift here, with the reason
+    #[arg(long, requires = "liftover")]
+    pub unlifted: Option<String>,
+
+    /// File of chromosome aliases to add to the built-in RefSeq and GenBank accessions: an
+    /// alias and the chromosome it names per line, whitespace-separated
+    #[arg(long)]
+    pub chr_aliases: Option<String>,
+
+    /// Score only the scoring variants on these chromosomes, comma-separated, as one job of a
+    /// `plan`; a sample's scores over disjoint lists add up to its whole score
+    #[arg(long, value_delimiter = ',')]
+    pub chromosomes: Vec<String>,
+
+    /// Drop the scoring variants in these regions, comma-separated: the presets `mhc` and
+    /// `long-range-ld` (which need --genome-build), or BED files
+    #[arg(long, value_delimiter = ',')]
+    pub exclude_regions: Vec<String>,
+
+    /// Scale down the weights in these regions (presets or BED files, like --exclude-regions)
+    /// so that together they hold at most --region-cap of the summed |effect_weight|
+    #[arg(long, value_delimiter = ',', requires = "region_cap")]
+    pub cap_regions: Vec<String>,
+
+    /// Largest share (0 to 1) of the summed |effect_weight| the --cap-regions may hold
+    #[arg(long, requires = "cap_regions")]
+    pub region_cap: Option<f64>,
+
+    /// Genome build of the scoring file's positions, for the region presets and the build
+    /// check; defaults to the build its header declares
+    #[arg(long, value_enum)]
+    pub genome_build: Option<GenomeBuild>,
+
+    /// What to do when the scoring file and the VCF are on different genome builds
+    #[arg(long, value_enum, default_value_t = BuildMismatch::Error)]
+    pub build_mismatch: BuildMismatch,
+
+    /// P-value thresholds for pruning-and-thresholding, comma-separated (e.g. 5e-8,1e-4,0.01,1):
+    /// one extra score per threshold over the variants whose scoring-file `p_value` is at or
+    /// below it, all in the same pass (implies --mode multi)
+    #[arg(long, value_delimiter = ',')]
+    pub pt_thresholds: Vec<f64>,
+
+    /// Also score every sample under N random shuffles of the effect weights across the
+    /// scoring variants, and report its percentile and z-score against that null (implies --mode multi)
+    #[arg(long, value_name = "N")]
+    pub permutations: Option<usize>,
+
+    /// Scoring-file column naming each variant's sets (separated by ',' or ';'); every
+    /// sample also gets a partial score per set (implies --mode multi)
+    #[arg(long, conflicts_with = "set_bed")]
+    pub set_column: Option<String>,
+
+    /// BED file of named intervals (chrom, start, end, name) defining the sets for partial
+    /// scores, e.g. genes; a set may span several intervals (implies --mode multi)
+    #[arg(long)]
+    pub set_bed: Option<String>,
+
+    /// Sample table with an IID, #IID or Sample_Name column and a sex column (e.g. a plink2
+    /// .psam), to also report every score as a z-score within its sex (implies --mode multi)
+    #[arg(long)]
+    pub sex: Option<String>,
+
+    /// Sex column of --sex, coded 1/M/male and 2/F/female
+    #[arg(long, default_value = "SEX", requires = "sex")]
+    pub sex_column: String,
+
+    /// Sample table with an IID, #IID or Sample_Name column and an exposure column, to add
+    /// the scoring file's `interaction_weight` gene-environment terms (implies --mode multi)
+    #[arg(long, requires = "environment_column")]
+    pub environment: Option<String>,
+
+    /// Exposure column of --environment
+    #[arg(long, requires = "environment")]
+    pub environment_column: Option<String>,
+
+    /// Also report maternal and paternal scores from the scoring file's `maternal_weight` and
+    /// `paternal_weight` columns, reading phased GT calls in this haplotype order (implies --mode multi)
+    #[arg(long, value_enum)]
+    pub parent_of_origin: Option<HaplotypeOrder>,
+
+    /// Pedigree (plink .fam, or a table with IID, PAT and MAT columns) for mid-parent scores,
+    /// offspring deviations and the pTDT over the VCF's trios (implies --mode multi)
+    #[arg(long)]
+    pub pedigree: Option<String>,
+
+    /// Write each matched variant's contribution to the cohort's score variance to this
+    /// file, ranked (implies --mode multi)
+    #[arg(long, conflicts_with = "checkpoint")]
+    pub variant_variance: Option<String>,
+
+    /// Reference score distribution from `speedscore build-reference`, to also report every
+    /// score as a percentile and z-score within the reference cohort
+    #[arg(long)]
+    pub reference: Option<String>,
+
+    /// Population of --reference to compare against (default: the whole reference cohort)
+    #[arg(long, requires = "reference")]
+    pub reference_population: Option<String>,
+
+    /// Also report every score as a T-score (50 + 10z) of its standardized score
+    #[arg(long)]
+    pub t_scores: bool,
+
+    /// Also report the percentile (0-100) of every score's standardized score
+    #[arg(long)]
+    pub percentiles: bool,
+
+    /// Percentile cutoffs between risk categories, comma-separated, e.g. 20,80,95; adds
+    /// each sample's category
+    #[arg(long, value_delimiter = ',')]
+    pub risk_cutoffs: Vec<f64>,
+
+    /// Names of the risk categories, lowest first, one more than the cutoffs (default:
+    /// the percentile ranges, e.g. <20,20-80,80-95,>=95)
+    #[arg(long, value_delimiter = ',', requires = "risk_cutoffs")]
+    pub risk_labels: Vec<String>,
+
+    /// Standardized score that --t-scores, --percentiles and --risk-cutoffs are computed from
+    #[arg(long, value_enum, default_value_t = ScaleSource::Auto)]
+    pub scale_from: ScaleSource,
+
+    /// Phenotype table (as for `speedscore evaluate`) to pick the best --pt-thresholds score against
+    #[arg(long, requires_all = ["pt_thresholds", "phenotype_column"])]
+    pub phenotype: Option<String>,
+
+    /// Phenotype column of --phenotype
+    #[arg(long, requires = "phenotype")]
+    pub phenotype_column: Option<String>,
+
+    /// Covariate columns of --phenotype to adjust for when comparing thresholds, comma-separated
+    #[arg(long, value_delimiter = ',', requires = "phenotype")]
+    pub covariates: Vec<String>,
+
+    /// Score only this random fraction of the samples, e.g. 0.01 for a quick sanity check (multi-sample only)
+    #[arg(long)]
+    pub sample_fraction: Option<f64>,
+
+    /// Score only the samples at these 1-based header positions, inclusive, e.g. 1-50000, as
+    /// one job of a `plan` (multi-sample only)
+    #[arg(long, value_name = "FIRST-LAST", value_parser = multi_sample::parse_sample_range, conflicts_with = "sample_fraction")]
+    pub sample_range: Option<Range<usize>>,
+
+    /// Seed for --sample-fraction and --permutations; the same seed always picks the same
+    /// samples and shuffles
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+
+    /// Force the single- or multi-sample code path instead of detecting it from the VCF header
+    #[arg(long, value_enum, default_value_t = Mode::Auto)]
+    pub mode: Mode,
+
+    /// Score from the FORMAT/DS dosage field instead of GT hard calls
+    #[arg(long)]
+    pub dosage: bool,
+
+    /// Genetic model applied to effect-allele counts
+    #[arg(long, value_enum, default_value_t = Model::Additive)]
+    pub model: Model,
+
+    /// Also report every score divided by the share of the scoring file's total |effect_weight|
+    /// at the variants the sample has a called genotype at, to compare samples with different coverage
+    #[arg(long)]
+    pub rescale_by_coverage: bool,
+
+    /// INFO field holding each variant's imputation quality; adds each sample's effective
+    /// variant count, the quality summed over its called scoring variants
+    #[arg(long, value_enum)]
+    pub info_field: Option<InfoField>,
+
+    /// Score only the VCF records matching this bcftools-style expression, e.g.
+    /// 'INFO/R2>0.8 && FILTER="PASS"'
+    #[arg(short = 'i', long, value_name = "EXPR", conflicts_with = "exclude")]
+    pub include: Option<String>,
+
+    /// Leave out the VCF records matching this bcftools-style expression, e.g. 'QUAL<30'
+    #[arg(short = 'e', long, value_name = "EXPR")]
+    pub exclude: Option<String>,
+
+    /// How to handle malformed VCF records
+    #[arg(long, value_enum, default_value_t = OnError::Skip)]
+    pub on_error: OnError,
+
+    /// Abort once more than this many malformed records have been seen, regardless of --on-error
+    #[arg(long)]
+    pub max_errors: Option<usize>,
+
+    /// Parse every VCF record in full and check it against the spec and the header, as
+    /// noodles-vcf does, before scoring it; slower. Records that fail are handled as
+    /// --on-error says. Requires building with `--features strict-vcf`
+    #[arg(long)]
+    pub strict_vcf: bool,
+
+    /// Read the VCF or BCF through htslib instead of the built-in reader. A record htslib
+    /// cannot parse stops the run. Requires building with `--features htslib`
+    #[arg(long)]
+    pub htslib: bool,
+
+    /// Scan the whole VCF even when it has a tabix or CSI index
+    #[arg(long)]
+    pub no_index: bool,
+
+    /// What to do when the VCF's records are not sorted by chromosome and position
+    #[arg(long, value_enum, default_value_t = Unsorted::Error)]
+    pub unsorted: Unsorted,
+
+    /// What to do with NaN or infinite weights in the scoring file and dosages in the VCF
+    #[arg(long, value_enum, default_value_t = NonFinite::Reject)]
+    pub non_finite: NonFinite,
+
+    /// Write 0 as the calculation time in the results, so that reruns on the same inputs
+    /// give byte-identical files
+    #[arg(long)]
+    pub reproducible: bool,
+
+    /// Write every VCF record and scoring row left out of the score here, one per line with
+    /// a reason code
+    #[arg(long)]
+    pub rejected: Option<String>,
+
+    /// Compute SHA-256 and MD5 checksums of the VCF and scoring file, printed and recorded
+    /// in the --provenance file
+    #[arg(long)]
+    pub checksums: bool,
+
+    /// SHA-256 or MD5 checksum the VCF must have; the run stops before scoring if it differs
+    /// (implies --checksums)
+    #[arg(long)]
+    pub expect_vcf_checksum: Option<String>,
+
+    /// SHA-256 or MD5 checksum the scoring file must have (implies --checksums)
+    #[arg(long)]
+    pub expect_scoring_checksum: Option<String>,
+
+    /// Directory where inputs given as http(s):// URLs are downloaded and kept for later
+    /// runs (default: $SPEEDSCORE_CACHE_DIR, else ~/.cache/speedscore; URL inputs require
+    /// --features remote)
+    #[arg(long)]
+    pub cache_dir: Option<String>,
+
+    /// Read a --vcf or --scoring given as an http(s):// URL as it downloads; without this flag
+    /// such a URL is downloaded into the cache first. s3:// and gs:// inputs are always read
+    /// as they download, with `aws` or `gcloud`, which must be on the PATH
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Write a JSON record of the inputs, settings and summary to this file, as `watch` does
+    #[arg(long)]
+    pub provenance: Option<String>,
+
+    /// Version of the JSON results and provenance schema the outputs must follow; the run
+    /// fails if this build does not write it
+    #[arg(long, value_name = "VERSION", default_value_t = crate::schema::SCHEMA_VERSION)]
+    pub schema_version: u32,
+
+    /// Look up the score the scoring file's `#pgs_id=` names in the PGS Catalog, and record
+    /// its trait, publication, genome build and variant count with the results. Requires
+    /// building with `--features pgs-catalog`
+    #[arg(long)]
+    pub pgs_catalog: bool,
+
+    /// Periodically save progress to this file and resume from it if it exists (multi-sample only)
+    #[arg(long)]
+    pub checkpoint: Option<String>,
+
+    /// Number of VCF lines between checkpoint saves
+    #[arg(long, default_value_t = 1_000_000)]
+    pub checkpoint_interval: usize,
+
+    /// Memory budget such as 8G; buffers and batches are sized to fit it.
+    /// Defaults to the cgroup or SLURM allocation, if there is one
+    #[arg(long, value_parser = resources::parse_size)]
+    pub memory_limit: Option<u64>,
+
+    /// CPUs to use in all, the --io-threads included.
+    /// Defaults to SLURM_CPUS_PER_TASK, or every core
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// Threads reserved for decompressing BGZF input; the remaining CPUs score (default: 1)
+    #[arg(long)]
+    pub io_threads: Option<usize>,
+
+    /// VCFs given with --vcf to open and decompress at once, the next ones ahead of the
+    /// scan of the current one (default: 1)
+    #[arg(long, default_value_t = 1)]
+    pub vcf_jobs: usize,
+
+    /// Exit non-zero if the QC thresholds below are not met
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Minimum fraction of scoring variants found in the VCF with a matching allele (with --strict);
+    /// below it, the likely cause is reported
+    #[arg(long, default_value_t = 0.5)]
+    pub min_match_rate: f64,
+
+    /// Minimum fraction of position matches whose effect allele is REF or ALT (with --strict)
+    #[arg(long, default_value_t = 0.9)]
+    pub min_allele_concordance: f64,
+}
+
+/// Genetic model applied to the effect-allele count before it is multiplied by the weight.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Model {
+    /// Score is linear in the number of effect alleles (0, 1, 2)
+    #[default]
+    Additive,
+    /// One or more effect alleles count as 1
+    Dominant,
+    /// Only two effect alleles count as 1
+    Recessive,
+}
+
+impl Model {
+    /// Recode an effect-allele count (or fractional dosage) in [0, 2] under this model.
+    /// For dosages this is the usual hard-threshold approximation, not an expectation
+    /// over genotype probabilities.
+    pub fn apply(self, count: f64) -> f64 {
+        match self {
+            Model::Additive => count,
+            Model::Dominant => count.min(1.0),
+            Model::Recessive => (count - 1.0).max(0.0),
+        }
+    }
+
+    /// Mean of [`Model::apply`] over genotypes in Hardy-Weinberg equilibrium with
+    /// effect-allele frequency `p`: what centering subtracts per scored variant.
+    pub fn expected(self, p: f64) -> f64 {
+        match self {
+            Model::Additive => 2.0 * p,
+            Model::Dominant => 1.0 - (1.0 - p) * (1.0 - p),
+            Model::Recessive => p * p,
+        }
+    }
+}
+
+/// Dominance coding of an effect-allele count in [0, 2]: 1 for a heterozygote, 0 for
+/// either homozygote. Dosages are interpolated linearly between those points.
+pub fn heterozygosity(count: f64) -> f64 {
+    1.0 - (count - 1.0).abs()
+}
+
+/// INFO field with a variant's imputation quality, for `--info-field`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InfoField {
+    /// Minimac's estimated R² (`R2`), as written by the Michigan and TOPMed imputation servers
+    R2,
+    /// Beagle's dosage R² (`DR2`)
+    Dr2,
+    /// The IMPUTE info score (`INFO`)
+    Info,
+}
+
+impl InfoField {
+    /// The INFO key, e.g. `DR2`.
+    pub fn key(self) -> &'static str {
+        match self {
+            InfoField::R2 => "R2",
+            InfoField::Dr2 => "DR2",
+            InfoField::Info => "INFO",
+        }
+    }
+}
+
+/// Where the effect-allele frequencies for `--center` come from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Centering {
+    /// The scoring file's `allelefrequency_effect` column
+    ScoringFile,
+    /// Called genotypes of all samples in the VCF, per variant
+    Cohort,
+}
+
+/// Options controlling how a sample's genotype field turns into an effect-allele value.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ScoreOptions {
+    /// Read the FORMAT/DS dosage instead of the GT hard call
+    pub dosage: bool,
+    pub model: Model,
+    /// Subtract each variant's expected contribution, as plink's score centering does
+    pub center: Option<Centering>,
+    /// Score missing genotypes as the cohort's expected value instead of skipping them,
+    /// as plink2 --score does by default
+    pub mean_impute: bool,
+    /// Weight effective variant counts by this INFO field's imputation quality
+    pub info_field: Option<InfoField>,
+    /// Track the share of the total |weight| each sample's called genotypes cover, and
+    /// rescale scores by it
+    pub rescale_by_coverage: bool,
+    /// How a NaN or infinite DS is read
+    pub non_finite: NonFinite,
+}
+
+impl ScoreOptions {
+    /// Index of the FORMAT sub-field these options read, GT or DS; `None` means the
+    /// record has no such field, and its genotypes count as missing.
+    pub fn field_index(&self, format: &str) -> Option<usize> {
+        let field = if self.dosage { "DS" } else { "GT" };
+        format.trim().split(':').position(|f| f == field)
+    }
+
+    /// Effect-allele value of one sample column (e.g. `0|1:0.98`), after applying the model.
+    /// Returns `None` for missing or multi-allelic genotypes.
+    pub fn effect_dosage(&self, sample_field: &str, field_index: usize, effect_is_alt: bool) -> Option<f64> {
+        self.effect_count(sample_field, field_index, effect_is_alt).map(|count| self.model.apply(count))
+    }
+
+    /// Effect-allele count or dosage in [0, 2] of one sample column, before the model.
+    pub fn effect_count(&self, sample_field: &str, field_index: usize, effect_is_alt: bool) -> Option<f64> {
+        self.checked_effect_count(sample_field, field_index, effect_is_alt, &mut false)
+    }
+
+    /// [`ScoreOptions::effect_count`], setting `non_finite` when the DS is NaN or infinite.
+    /// Such a dosage is missing, or with [`NonFinite::Zero`] read as 0.
+    pub fn checked_effect_count(&self, sample_field: &str, field_index: usize, effect_is_alt: bool, non_finite: &mut bool) -> Option<f64> {
+        let value = sample_field.split(':').nth(field_index)?;
+        if self.dosage {
+            let mut alt_dosage: f64 = value.trim().parse().ok()?;
+            if !alt_dosage.is_finite() {
+                *non_finite = true;
+                match self.non_finite {
+                    NonFinite::Zero => alt_dosage = 0.0,
+                    NonFinite::Reject | NonFinite::Abort => return None,
+                }
+            }
+            Some(if effect_is_alt { alt_dosage } else { 2.0 - alt_dosage })
+        } else {
+            Some(parse_allele_count(value, effect_is_alt)? as f64)
+        }
+    }
+
+    /// Imputation quality of a record with INFO column `info`, clamped to [0, 1]; `None`
+    /// without `info_field`. Records without the field, such as genotyped sites, count fully.
+    pub fn info_weight(&self, info: &str) -> Option<f64> {
+        let key = self.info_field?.key();
+        let value = info.split(';').find_map(|entry| entry.strip_prefix(key)?.strip_prefix('='));
+        let quality = value.and_then(|value| value.parse::<f64>().ok()).filter(|q| q.is_finite());
+        Some(quality.map_or(1.0, |q| q.clamp(0.0, 1.0)))
+    }
+
+    /// Whether scoring needs the effect-allele frequency among the VCF's samples at each variant.
+    pub fn needs_cohort_frequency(&self) -> bool {
+        self.mean_impute || self.center == Some(Centering::Cohort)
+    }
+
+    /// What one called genotype with `count` effect alleles adds to the score: the weighted
+    /// model value plus, for scoring files with a `dominance_weight`, the dominance deviation.
+    pub fn contribution(&self, effect: &EffectWeight, count: f64) -> f64 {
+        effect.weight as f64 * self.model.apply(count) + effect.dominance as f64 * heterozygosity(count)
+    }
+
+    /// Mean of [`ScoreOptions::contribution`] over genotypes in Hardy-Weinberg equilibrium
+    /// with effect-allele frequency `p`.
+    pub fn expected_contribution(&self, effect: &EffectWeight, p: f64) -> f64 {
+        effect.weight as f64 * self.model.expected(p) + effect.dominance as f64 * 2.0 * p * (1.0 - p)
+    }
+
+    /// Amount subtracted from each called sample's contribution at a variant, given the
+    /// scoring-file entry and, for cohort centering, the effect-allele frequency observed
+    /// in the VCF (`None` when no sample was called).
+    pub fn centering_offset(&self, effect: &EffectWeight, cohort_frequency: Option<f64>) -> f64 {
+        self.centering_frequency(effect, cohort_frequency).map_or(0.0, |p| self.expected_contribution(effect, p))
+    }
+
+    /// Effect-allele frequency centering uses at a variant, if any.
+    fn centering_frequency(&self, effect: &EffectWeight, cohort_frequency: Option<f64>) -> Option<f64> {
+        match self.center {
+            None => None,
+            Some(Centering::ScoringFile) => effect.frequency.map(f64::from),
+            Some(Centering::Cohort) => cohort_frequency,
+        }
+    }
+
+    /// How much a sample's contribution at a variant changes per unit of `effect_weight`,
+    /// for a genotype with `count` effect alleles or a missing one (`None`). The score's
+    /// standard error scales each variant's weight standard error by this.
+    pub fn weight_sensitivity(&self, effect: &EffectWeight, count: Option<f64>, cohort_frequency: Option<f64>) -> f64 {
+        let value = match (count, cohort_frequency) {
+            (Some(count), _) => self.model.apply(count),
+            (None, Some(p)) if self.mean_impute => self.model.expected(p),
+            (None, _) => return 0.0,
+        };
+        value - self.centering_frequency(effect, cohort_frequency).map_or(0.0, |p| self.model.expected(p))
+    }
+
+    /// Reject option combinations the weights or VCF layout cannot support.
+    pub fn validate(&self, effect_weights: &EffectWeights, scoring_path: &str, file_type: &FileType) -> Result<()> {
+        // A dominance weight already models the heterozygote; recoding the count as well would count it twice
+        if self.model != Model::Additive && effect_weights.values().any(|w| w.dominance != 0.0) {
+            return Err(Error::invalid(scoring_path, "dominance weights are only supported with --model additive"));
+        }
+        match self.center {
+            Some(Centering::ScoringFile) => {
+                let missing = effect_weights.values().filter(|w| w.frequency.is_none()).count();
+                if missing > 0 {
+                    return Err(Error::invalid(
+                        scoring_path,
+                        format!(
+                            "--center scoring-file needs an allelefrequency_effect for every variant; {} of {} have none",
+                            missing,
+                            effect_weights.len()
+                        ),
+                    ));
+                }
+            }
+            Some(Centering::Cohort) if matches!(file_type, FileType::SingleSample | FileType::ConsumerGenotypes) => {
+                return Err(Error::invalid(
+                    "--center",
+                    "cohort frequencies need a multi-sample VCF; use --center scoring-file",
+                ));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// `line` without a UTF-8 byte order mark or a line ending, `\r\n` included: files saved by
+/// Windows editors have both.
+pub fn trim_line(line: &str) -> &str {
+    line.trim_start_matches('\u{feff}').trim_end_matches(['\r', '\n'])
+}
+
+/// Helper that counts how many effect alleles are present in `genotype`.
+/// If `effect_is_alt` = true, we count `'1'` as effect alleles.
+/// If `effect_is_alt` = false, we count `'0'` as effect alleles.
+/// Returns None if we see multi‐allelic (e.g. '2') or missing ('.').
+pub fn parse_allele_count(genotype: &str, effect_is_alt: bool) -> Option<u8> {
+    let mut count = 0u8;
+    for c in genotype.chars() {
+        match c {
+            '0' if !effect_is_alt => count += 1,
+            '1' if effect_is_alt => count += 1,
+            '.' | '2' | '3' => return None, // skip multi‐allelic or missing
+            '|' | '/' => {} // just a delimiter
+            _ => {}
+        }
+    }
+    Some(count)
+}
+
+/// Read the sample names from a VCF's `#CHROM` header line, made unique and non-empty by
+/// [`dedupe_sample_names`].
+pub fn read_sample_names(path: &str) -> Result<Vec<String>> {
+    read_sample_names_from(path, bgzf::open(path, &InputConfig::default())?)
+}
+
+/// [`read_sample_names`] of the VCF at `path`, read from `reader`.
+pub fn read_sample_names_from(path: &str, reader: impl BufRead) -> Result<Vec<String>> {
+    let mut names = read_header_sample_names(path, reader)?;
+    dedupe_sample_names(&mut names);
+    Ok(names)
+}
+
+/// The sample names [`read_sample_names`] changes, and to what.
+pub fn sample_name_changes(path: &str, reader: impl BufRead) -> Result<Vec<RenamedSample>> {
+    Ok(dedupe_sample_names(&mut read_header_sample_names(path, reader)?))
+}
+
+/// The sample names of a `#CHROM` line as they are, empty ones included.
+pub fn header_sample_names(line: &str) -> Vec<String> {
+    trim_line(line).trim_end().split('\t').skip(9).map(|name| name.trim().to_string()).collect()
+}
+
+/// A sample name changed by [`dedupe_sample_names`]. `column` is 0-based among the samples.
+#[derive(Clone, Debug)]
+pub struct RenamedSample {
+    pub column: usize,
+    pub from: String,
+    pub to: String,
+}
+
+/// Make sample names unique and non-empty, the same way every time: an empty name becomes
+/// `SAMPLE_<n>`, `n` its 1-based position among the samples, and each repeat of a name
+/// gets the first `_2`, `_3`, ... suffix no other sample has. The first sample with a
+/// name keeps it. Returns what was changed.
+pub fn dedupe_sample_names(names: &mut [String]) -> Vec<RenamedSample> {
+    let original: HashSet<String> = names.iter().filter(|name| !name.is_empty()).cloned().collect();
+    let mut used = HashSet::new();
+    let mut changes = Vec::new();
+    for (column, name) in names.iter_mut().enumerate() {
+        if !name.is_empty() && used.insert(name.clone()) {
+            continue;
+        }
+        let base = if name.is_empty() { format!("SAMPLE_{}", column + 1) } else { name.clone() };
+        let free = |candidate: &String| !used.contains(candidate) && !original.contains(candidate);
+        let renamed = if name.is_empty() && free(&base) {
+            base
+        } else {
+            (2..).map(|k| format!("{}_{}", base, k)).find(free).expect("some suffix is free")
+        };
+        used.insert(renamed.clone());
+        changes.push(RenamedSample { column, from: std::mem::replace(name, renamed.clone()), to: renamed });
+    }
+    changes
+}
+
+fn read_header_sample_names(path: &str, mut reader: impl BufRead) -> Result<Vec<String>> {
+    let mut line = String::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        line_number += 1;
+        if reader.read_line(&mut line).map_err(|e| Error::read(path, line_number, e))? == 0 {
+            return Err(Error::invalid(path, "VCF header (#CHROM line) not found"));
+        }
+        if trim_line(&line).starts_with("#CHROM") {
+            return Ok(header_sample_names(&line));
+        }
+    }
+}
+
+/// Whether the VCF's records have FORMAT/DS but no GT, as the dosage-only output of
+/// imputation servers does, judged by its first record. A first record with neither is
+/// an error: there is nothing to score.
+pub fn dosage_only(path: &str) -> Result<bool> {
+    dosage_only_from(path, bgzf::open(path, &InputConfig::default())?)
+}
+
+/// [`dosage_only`] for the VCF at `path`, read from `reader`.
+pub fn dosage_only_from(path: &str, reader: impl BufRead) -> Result<bool> {
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.map_err(|e| Error::read(path, line_number, e))?;
+        let line = trim_line(&line);
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let Some(format) = line.split('\t').nth(8) else {
+            // Left to the error policy when the record is scored
+            return Ok(false);
+        };
+        let fields: Vec<&str> = format.trim().split(':').collect();
+        return if fields.contains(&"GT") {
+            Ok(false)
+        } else if fields.contains(&"DS") {
+            Ok(true)
+        } else {
+            Err(Error::parse(path, line_number, format!("FORMAT '{}' has neither GT nor DS, so there are no genotypes to score", format), line))
+        };
+    }
+    Ok(false)
+}
+
+/// Aggregate outcome of a scoring run, shared by the single- and multi-sample paths.
+#[derive(Clone, Debug, Serialize)]
+pub struct ScoreSummary {
+    pub score: f64,
+    pub total_variants: u64,
+    pub matched_variants: u64,
+    /// Effect-allele count (or dosage) summed over every called genotype, before the model
+    pub dosage_sum: f64,
+    /// Imputation quality summed over every called genotype, with `--info-field`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective_variants: Option<f64>,
+    /// Share of the total |weight| at called genotypes, averaged over samples, with
+    /// `--rescale-by-coverage`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight_coverage: Option<f64>,
+    /// VCF records left out by `--include` or `--exclude`, with either
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filtered_records: Option<u64>,
+    pub vcf_chr_format: bool,
+    pub sites: SiteCounts,
+    /// Malformed VCF records skipped, by reason
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedRecords>,
+}
+
+/// Per-site overlap counters used for QC: how many VCF records hit a scoring
+/// position, and how many of those carried the effect allele as REF or ALT.
+#[derive(Clone, Copy, Default, Debug, Serialize)]
+pub struct SiteCounts {
+    pub position_hits: u64,
+    pub allele_hits: u64,
+}
+
+/// One scoring-file variant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EffectWeight {
+    pub effect_allele: String,
+    pub weight: f32,
+    /// Effect-allele frequency, from an `allelefrequency_effect` column if present
+    pub frequency: Option<f32>,
+    /// Weight of the heterozygote's deviation from the additive prediction, from a
+    /// `dominance_weight` column if present (0 otherwise)
+    pub dominance: f32,
+    /// GWAS p-value of the effect, from a `p_value` column if present; needed for --pt-thresholds
+    pub p_value: Option<f64>,
+    /// Standard error of `weight`, from an `effect_weight_se` column if present
+    pub standard_error: Option<f32>,
+    /// Weight of the genotype-by-exposure term, from an `interaction_weight` column if
+    /// present (0 otherwise); used with --environment
+    pub interaction: f32,
+    /// Weight of the effect allele on the maternal haplotype, from a `maternal_weight`
+    /// column if present (0 otherwise); used with --parent-of-origin
+    pub maternal: f32,
+    /// Weight of the effect allele on the paternal haplotype, from a `paternal_weight` column
+    pub paternal: f32,
+}
+
+/// Map from (chr, pos) -> effect weight, with "chr" stripped from chr.
+pub type EffectWeights = HashMap<(String, u64), EffectWeight>;
+
+/// Share of `total` (the scoring file's summed |effect_weight|) that `effect` holds; a
+/// sample's weight coverage sums this over its called variants. 0 when every weight is 0.
+pub fn weight_share(effect: &EffectWeight, total: f64) -> f64 {
+    if total > 0.0 {
+        effect.weight.abs() as f64 / total
+    } else {
+        0.0
+    }
+}
+
+/// Sum of |effect_weight| over every scoring variant.
+pub fn total_abs_weight(effect_weights: &EffectWeights) -> f64 {
+    ordered_sum(effect_weights.values().map(|w| w.weight.abs() as f64))
+}
+
+/// Sum of `values` taken in sorted order, so that a sum over a `HashMap` does not depend
+/// on hash order and reruns give the same digits.
+pub fn ordered_sum(values: impl Iterator<Item = f64>) -> f64 {
+    let mut values: Vec<f64> = values.collect();
+    values.sort_by(f64::total_cmp);
+    values.into_iter().sum()
+}
+
+/// Multiply every weight (and standard error) of each scoring variant by `[2p(1-p)]^alpha`,
+/// with `p` its `allelefrequency_effect`. `alpha = 0.5` turns per-allele effects into
+/// effects per standard deviation of the genotype, and `-0.5` turns them back.
+pub fn scale_by_frequency(effect_weights: &mut EffectWeights, alpha: f64, scoring_path: &str) -> Result<()> {
+    let missing = effect_weights.values().filter(|w| w.frequency.is_none()).count();
+    if missing > 0 {
+        return Err(Error::invalid(
+            scoring_path,
+            format!(
+                "--frequency-scaling needs an allelefrequency_effect for every variant; {} of {} have none",
+                missing,
+                effect_weights.len()
+            ),
+        ));
+    }
+    for ((chr, pos), w) in effect_weights.iter_mut() {
+        let p = w.frequency.map_or(0.0, f64::from);
+        let variance = 2.0 * p * (1.0 - p);
+        if variance == 0.0 && alpha < 0.0 {
+            return Err(Error::invalid(
+                scoring_path,
+                format!("variant {}:{} has effect-allele frequency {}, so a negative --frequency-scaling is undefined", chr, pos, p),
+            ));
+        }
+        let factor = variance.powf(alpha);
+        let scale = |value: f32| (value as f64 * factor) as f32;
+        w.weight = scale(w.weight);
+        w.dominance = scale(w.dominance);
+        w.interaction = scale(w.interaction);
+        w.maternal = scale(w.maternal);
+        w.paternal = scale(w.paternal);
+        w.standard_error = w.standard_error.map(scale);
+    }
+    Ok(())
+}
+
+/// The `quantile` (0 to 1) of |effect_weight| over the scoring variants, interpolated
+/// linearly between order statistics.
+pub fn abs_weight_quantile(effect_weights: &EffectWeights, quantile: f64) -> f64 {
+    let mut weights: Vec<f64> = effect_weights.values().map(|w| w.weight.abs() as f64).collect();
+    if weights.is_empty() {
+        return 0.0;
+    }
+    weights.sort_by(f64::total_cmp);
+    let h = (weights.len() - 1) as f64 * quantile;
+    let (lower, fraction) = (h.floor() as usize, h.fract());
+    let upper = (lower + 1).min(weights.len() - 1);
+    weights[lower] + fraction * (weights[upper] - weights[lower])
+}
+
+/// Clamp every effect weight to [-cap, cap], keeping its sign; returns how many were changed.
+pub fn winsorize_weights(effect_weights: &mut EffectWeights, cap: f64) -> usize {
+    let mut changed = 0;
+    for w in effect_weights.values_mut() {
+        if w.weight.abs() as f64 > cap {
+            w.weight = (cap as f32).copysign(w.weight);
+            changed += 1;
+        }
+    }
+    changed
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    Single,
+    Multi,
+    Auto,
 }
 
 pub enum FileType {
     SingleSample,
     MultiSample,
+    /// A 23andMe or AncestryDNA raw genotype export, scored as a single sample
+    ConsumerGenotypes,
 }
 
 impl FileType {
-    pub fn detect(path: &str) -> io::Result<Self> {
-        let file = File::open(path)?;
-        let mut reader: Box<dyn BufRead> = if path.ends_with(".gz") {
-            Box::new(BufReader::new(GzDecoder::new(file)))
-        } else {
-            Box::new(BufReader::new(file))
+    /// Resolve the file type from the requested mode, only inspecting the VCF header for
+    /// `Mode::Auto`. A file without a `##fileformat=VCF` first line is checked for a raw
+    /// genotype export whatever the mode.
+    pub fn from_mode(mode: Mode, path: &str, mut reader: impl BufRead) -> Result<Self> {
+        let forced = match mode {
+            Mode::Single => FileType::SingleSample,
+            Mode::Multi => FileType::MultiSample,
+            Mode::Auto => return Self::detect_from(path, reader),
         };
+        let mut first = String::new();
+        reader.read_line(&mut first).map_err(|e| Error::read(path, 1, e))?;
+        if !trim_line(&first).starts_with("##fileformat=VCF") && consumer::is_raw_genotypes_from(path, first.as_bytes().chain(reader))? {
+            return Ok(FileType::ConsumerGenotypes);
+        }
+        Ok(forced)
+    }
+
+    pub fn detect(path: &str) -> Result<Self> {
+        Self::detect_from(path, bgzf::open(path, &InputConfig::default())?)
+    }
 
+    /// [`FileType::detect`] for the file at `path`, read from `reader`.
+    pub fn detect_from(path: &str, mut reader: impl BufRead) -> Result<Self> {
         let mut buffer = String::new();
-        reader.read_line(&mut buffer)?;
+        let mut line_number = 1;
+        reader.read_line(&mut buffer).map_err(|e| Error::read(path, line_number, e))?;
 
-        if !buffer.starts_with("##fileformat=VCF") {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a VCF file"));
+        if !trim_line(&buffer).starts_with("##fileformat=VCF") {
+            if consumer::is_raw_genotypes_from(path, buffer.as_bytes().chain(reader))? {
+                return Ok(FileType::ConsumerGenotypes);
+            }
+            return Err(Error::parse(path, line_number, "Not a VCF file (missing ##fileformat)", &buffer));
         }
 
         buffer.clear();
-        while reader.read_line(&mut buffer)? > 0 {
-            if buffer.starts_with("#CHROM") {
-                let sample_count = buffer.split('\t').count() - 9;
+        loop {
+            line_number += 1;
+            if reader.read_line(&mut buffer).map_err(|e| Error::read(path, line_number, e))? == 0 {
+                break;
+            }
+            if trim_line(&buffer).starts_with("#CHROM") {
+                // Trailing whitespace/CRLF would otherwise be counted as an extra (empty) sample
+                let columns = buffer.trim_end().split('\t').count();
+                let sample_count = columns.checked_sub(9).filter(|&n| n > 0).ok_or_else(|| {
+                    Error::parse(path, line_number, "VCF header has no sample columns", &buffer)
+                })?;
                 return Ok(if sample_count > 1 { FileType::MultiSample } else { FileType::SingleSample });
             }
             buffer.clear();
         }
 
-        Err(io::Error::new(io::ErrorKind::InvalidData, "VCF header not found"))
+        Err(Error::invalid(path, "VCF header (#CHROM line) not found"))
     }
 }
 
 
+/// A position written as an integer or, as R and spreadsheets sometimes write them, in
+/// scientific notation (`1.5e+07`); `None` unless it is a whole number in range.
+pub fn parse_position(text: &str) -> Option<u64> {
+    text.parse::<u64>().ok().or_else(|| {
+        let value = text.parse::<f64>().ok()?;
+        (value.fract() == 0.0 && (0.0..=u64::MAX as f64).contains(&value)).then_some(value as u64)
+    })
+}
+
+/// The columns of a scoring file's chromosome and position, from its header `names`: a PGS
+/// Catalog harmonized file's `hm_chr` and `hm_pos`, lifted to the build its `#HmPOS_build=`
+/// names, or else `chr_name` and `chr_position`. `true` with the harmonized ones.
+pub fn position_columns<S: AsRef<str>>(names: &[S]) -> Option<(usize, usize, bool)> {
+    let column = |name: &str| names.iter().position(|h| h.as_ref() == name);
+    match (column("hm_chr"), column("hm_pos")) {
+        (Some(chr), Some(pos)) => Some((chr, pos, true)),
+        _ => Some((column("chr_name")?, column("chr_position")?, false)),
+    }
+}
+
+/// The text of the scoring file at `path`, plain, gzipped or zstd-compressed. A bucket
+/// object or URL is read as it downloads; a run that caches its inputs passes the cached
+/// copy's path instead.
+pub fn open_scoring_file(path: &str) -> Result<Box<dyn BufRead>> {
+    let config = InputConfig { stream: true, ..InputConfig::default() };
+    source::decompressed(stream::open(path, &config)?).with_path(path)
+}
+
+/// Indices of a scoring file's columns, found once from its header line.
+#[derive(Clone, Copy)]
+struct ScoringColumns {
+    chr: usize,
+    pos: usize,
+    /// Whether `chr` and `pos` are the harmonized `hm_chr` and `hm_pos`
+    harmonized: bool,
+    allele: usize,
+    weight: usize,
+    /// Optional; needed for --center scoring-file
+    frequency: Option<usize>,
+    /// Optional; adds a dominance deviation to the additive effect
+    dominance: Option<usize>,
+    /// Optional; needed for --pt-thresholds
+    p_value: Option<usize>,
+    /// Optional; gives every sample a score standard error
+    standard_error: Option<usize>,
+    /// Optional; needed for --environment
+    interaction: Option<usize>,
+    /// Optional; needed for --parent-of-origin
+    maternal: Option<usize>,
+    paternal: Option<usize>,
+}
+
+impl ScoringColumns {
+    /// Finds the columns in `headers`, failing if chr, position, effect_allele or effect_weight
+    /// is missing; a harmonized file's hm_chr and hm_pos are preferred, and make chr_name and
+    /// chr_position optional.
+    fn find(path: &str, headers: &[String]) -> Result<Self> {
+        let (chr, pos, harmonized) = position_columns(headers).ok_or_else(|| {
+            let missing = if headers.iter().any(|h| h == "chr_name") { "chr_position" } else { "chr_name" };
+            Error::invalid(path, format!("Missing '{}' column (or the harmonized 'hm_chr' and 'hm_pos')", missing))
+        })?;
+        let column = |name: &str| headers.iter().position(|h| h == name);
+        Ok(ScoringColumns {
+            chr,
+            pos,
+            harmonized,
+            allele: column("effect_allele").ok_or_else(|| Error::invalid(path, "Missing 'effect_allele' column"))?,
+            weight: column("effect_weight").ok_or_else(|| Error::invalid(path, "Missing 'effect_weight' column"))?,
+            frequency: column("allelefrequency_effect"),
+            dominance: column("dominance_weight"),
+            p_value: column("p_value"),
+            standard_error: column("effect_weight_se"),
+            interaction: column("interaction_weight"),
+            maternal: column("maternal_weight"),
+            paternal: column("paternal_weight"),
+        })
+    }
+}
+
+/// Read a scoring file. Weights may be in scientific notation; rows with a NaN or infinite
+/// weight are dropped, zeroed or refused as `non_finite` says. A row at the same position
+/// as an earlier one replaces it. A PGS Catalog harmonized file is read at its harmonized
+/// positions, and its rows without one are dropped. Dropped and replaced rows are kept in
+/// `rejections`, if given.
 pub fn load_scoring_file(
-    path: &str
-) -> io::Result<(HashMap<(String, u32), (String, f32)>, bool)> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let mut effect_weights: HashMap<(String, u32), (String, f32)> = HashMap::new();
-    let mut headers: Option<Vec<String>> = None;
+    path: &str,
+    non_finite: NonFinite,
+    rejections: Option<&Rejections>,
+) -> Result<(EffectWeights, bool)> {
+    let stage = Stage::load_scoring_file(path);
+    let reader = open_scoring_file(path)?;
+    let mut effect_weights: EffectWeights = HashMap::new();
+    // The header's column indices and its number of columns
+    let mut headers: Option<(ScoringColumns, usize)> = None;
     let mut scoring_chr_format = false;
+    // Rows with a NaN or infinite weight
+    let mut non_finite_rows = 0;
+    // Rows of a harmonized file the Catalog could not place on its build
+    let mut unharmonized_rows = 0;
+    // The `#key=value` lines before the header, such as `#HmPOS_build=GRCh38`
+    let mut metadata: HashMap<String, String> = HashMap::new();
+    // Line number and text of the row at each position, to report a replaced one
+    let mut rows: HashMap<(String, u64), (usize, String)> = HashMap::new();
 
     let mut count = 0;
-    for line in reader.lines() {
-        let line = line?;
-        if line.starts_with('#') {
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.map_err(|e| Error::read(path, line_number, e))?;
+        let line = trim_line(&line);
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some((key, value)) = comment.split_once('=') {
+                metadata.entry(key.trim().to_string()).or_insert_with(|| value.trim().to_string());
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
             continue;
         }
 
-        // First non‐comment line is assumed to be headers
+        // First non‐comment line is assumed to be headers. Fields are trimmed, and empty
+        // fields past the last column, from trailing tabs, are ignored
         if headers.is_none() {
-            headers = Some(line.split('\t').map(String::from).collect());
+            let mut names: Vec<String> = line.split('\t').map(|name| name.trim().to_string()).collect();
+            while names.last().is_some_and(String::is_empty) {
+                names.pop();
+            }
+            let columns = ScoringColumns::find(path, &names)?;
+            if columns.harmonized {
+                let build = metadata.get("HmPOS_build").map_or(String::new(), |build| format!(", on {}", build));
+                println!("Harmonized scoring file: reading the hm_chr and hm_pos positions{}", build);
+            }
+            headers = Some((columns, names.len()));
             continue;
         }
 
-        let headers = headers.as_ref().unwrap();
-        let parts: Vec<&str> = line.split('\t').collect();
-        if parts.len() != headers.len() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Mismatch between header and data columns"
+        let (columns, width) = headers.as_ref().unwrap();
+        let mut parts: Vec<&str> = line.split('\t').map(str::trim).collect();
+        while parts.len() > *width && parts.last() == Some(&"") {
+            parts.pop();
+        }
+        if parts.len() != *width {
+            return Err(Error::parse(
+                path,
+                line_number,
+                format!("Expected {} columns (from header), found {}", width, parts.len()),
+                line,
             ));
         }
 
-        // Find column indices for chr, position, effect_allele, effect_weight
-        let chr_index = headers.iter().position(|h| h == "chr_name").ok_or_else(|| {
-            io::Error::new(io::ErrorKind::InvalidData, "Missing 'chr_name' column")
-        })?;
-
-        let pos_index = headers.iter().position(|h| h == "chr_position").ok_or_else(|| {
-            io::Error::new(io::ErrorKind::InvalidData, "Missing 'chr_position' column")
-        })?;
-
-        let allele_index = headers.iter().position(|h| h == "effect_allele").ok_or_else(|| {
-            io::Error::new(io::ErrorKind::InvalidData, "Missing 'effect_allele' column")
+        // The Catalog leaves a variant it could not place on the harmonized build blank
+        if columns.harmonized && (parts[columns.chr].is_empty() || parts[columns.pos].is_empty()) {
+            unharmonized_rows += 1;
+            if let Some(rejections) = rejections {
+                rejections.push(Input::Scoring, line_number, Reason::Unharmonized, "no harmonized position (hm_chr, hm_pos)", line);
+            }
+            continue;
+        }
+        let chr = parts[columns.chr].to_string();
+        let pos = parse_position(parts[columns.pos]).ok_or_else(|| {
+            Error::parse(path, line_number, "Invalid numeric position", line)
         })?;
-
-        let weight_index = headers.iter().position(|h| h == "effect_weight").ok_or_else(|| {
-            io::Error::new(io::ErrorKind::InvalidData, "Missing 'effect_weight' column")
+        let allele = parts[columns.allele].to_string();  // e.g., "A", "T", etc.
+        let weight = parts[columns.weight].parse::<f32>().map_err(|_| {
+            Error::parse(path, line_number, "Invalid numeric weight", line)
         })?;
+        let frequency = match columns.frequency.map(|i| parts[i]) {
+            // PGS Catalog files leave the frequency blank when it is unknown
+            None | Some("") | Some("NA") => None,
+            Some(text) => Some(text.parse::<f32>().ok().filter(|f| (0.0..=1.0).contains(f)).ok_or_else(|| {
+                Error::parse(path, line_number, "Invalid allele frequency", line)
+            })?),
+        };
+        let dominance = match columns.dominance.map(|i| parts[i]) {
+            None | Some("") | Some("NA") => 0.0,
+            Some(text) => text.parse::<f32>().map_err(|_| {
+                Error::parse(path, line_number, "Invalid dominance weight", line)
+            })?,
+        };
+        let p_value = match columns.p_value.map(|i| parts[i]) {
+            None | Some("") | Some("NA") => None,
+            Some(text) => Some(text.parse::<f64>().ok().filter(|p| (0.0..=1.0).contains(p)).ok_or_else(|| {
+                Error::parse(path, line_number, "Invalid p-value", line)
+            })?),
+        };
+        let standard_error = match columns.standard_error.map(|i| parts[i]) {
+            None | Some("") | Some("NA") => None,
+            Some(text) => Some(text.parse::<f32>().ok().filter(|se| *se >= 0.0).ok_or_else(|| {
+                Error::parse(path, line_number, "Invalid weight standard error", line)
+            })?),
+        };
+        let interaction = match columns.interaction.map(|i| parts[i]) {
+            None | Some("") | Some("NA") => 0.0,
+            Some(text) => text.parse::<f32>().map_err(|_| {
+                Error::parse(path, line_number, "Invalid interaction weight", line)
+            })?,
+        };
+        let parental_weight = |index: Option<usize>, what: &str| match index.map(|i| parts[i]) {
+            None | Some("") | Some("NA") => Ok(0.0),
+            Some(text) => text.parse::<f32>().map_err(|_| {
+                Error::parse(path, line_number, format!("Invalid {} weight", what), line)
+            }),
+        };
+        let maternal = parental_weight(columns.maternal, "maternal")?;
+        let paternal = parental_weight(columns.paternal, "paternal")?;
 
-        let chr = parts[chr_index].to_string();
-        let pos = parts[pos_index].parse::<u32>().map_err(|_| {
-            io::Error::new(io::ErrorKind::InvalidData, "Invalid numeric position")
-        })?;
-        let allele = parts[allele_index].to_string();  // e.g., "A", "T", etc.
-        let weight = parts[weight_index].parse::<f32>().map_err(|_| {
-            io::Error::new(io::ErrorKind::InvalidData, "Invalid numeric weight")
-        })?;
+        let mut weights = [weight, dominance, interaction, maternal, paternal];
+        if weights.iter().any(|w| !w.is_finite()) {
+            non_finite_rows += 1;
+            match non_finite {
+                NonFinite::Reject => {
+                    if let Some(rejections) = rejections {
+                        rejections.push(Input::Scoring, line_number, Reason::NonFiniteWeight, "NaN or infinite weight", line);
+                    }
+                    continue;
+                }
+                NonFinite::Zero => weights.iter_mut().filter(|w| !w.is_finite()).for_each(|w| *w = 0.0),
+                NonFinite::Abort => return Err(Error::parse(path, line_number, "NaN or infinite weight", line)),
+            }
+        }
+        let [weight, dominance, interaction, maternal, paternal] = weights;
 
         // Check if our first line uses 'chr' prefix
         if count == 0 {
             scoring_chr_format = chr.starts_with("chr");
         }
 
-        // Normalize chromosome (remove leading "chr")
-        let normalized_chr = chr.trim_start_matches("chr").to_string();
+        // Normalize chromosome (remove leading "chr", map accessions)
+        let key = (aliases::canonical(&chr).to_string(), pos);
+        if let Some(rejections) = rejections {
+            if let Some((earlier_line, earlier)) = rows.insert(key.clone(), (line_number, line.to_string())) {
+                rejections.push(Input::Scoring, earlier_line, Reason::DuplicatePosition, format!("replaced by line {}", line_number), &earlier);
+            }
+        }
 
-        // Store (effect_allele, effect_weight)
-        effect_weights.insert((normalized_chr, pos), (allele.clone(), weight));
+        effect_weights.insert(key, EffectWeight {
+            effect_allele: allele.clone(),
+            weight,
+            frequency,
+            dominance,
+            p_value,
+            standard_error,
+            interaction,
+            maternal,
+            paternal,
+        });
         count += 1;
 
         if count <= 5 {
@@ -133,15 +1657,63 @@ pub fn load_scoring_file(
         }
     }
 
+    if non_finite_rows > 0 {
+        let action = if non_finite == NonFinite::Zero { "set to 0" } else { "dropped" };
+        println!("Scoring rows with a NaN or infinite weight: {} ({})", non_finite_rows, action);
+    }
+    if unharmonized_rows > 0 {
+        println!("Scoring rows without a harmonized position: {} (dropped)", unharmonized_rows);
+    }
     println!("Total scoring entries loaded: {}", effect_weights.len());
+    stage.record("variants", effect_weights.len() as u64);
     Ok((effect_weights, scoring_chr_format))
 }
 
 
-pub fn output_results(args: &Args, score: f64, total_variants: usize, matched_variants: usize, duration: Duration, scoring_variants: usize, vcf_chr_format: bool, scoring_chr_format: bool) -> io::Result<()> {
-    let output = format!(
-        "VCF_File\tScore_File\tPolygenic_Score\tCalculation_Time_Seconds\tTotal_Variants\tMatched_Variants\tScoring_Variants\tVCF_Chr_Format\tScoring_Chr_Format\n\
-         {}\t{}\t{}\t{:.6}\t{}\t{}\t{}\t{}\t{}\n",
+/// Default output path: the VCF path with its `.vcf`/`.bcf`/`.bed`/`.pgen`/`.bgen`/`.gen`/`.gz`/`.bgz`/`.zst` extensions
+/// replaced by `.score.tsv` (single-sample) or `.scores.csv` (multi-sample).
+pub fn default_output_path(vcf_path: &str, file_type: &FileType, format: OutputFormat) -> String {
+    let stem = vcf_stem(vcf_path);
+    match file_type {
+        FileType::SingleSample | FileType::ConsumerGenotypes => format!("{}.score.tsv", stem),
+        FileType::MultiSample => format!("{}.scores.{}", stem, format.extension()),
+    }
+}
+
+/// `vcf_path` without its `.vcf`, `.bcf`, `.bed`, `.pgen`, `.bgen`, `.gen`, `.gz`, `.bgz` and `.zst` extensions.
+pub fn vcf_stem(vcf_path: &str) -> &str {
+    let mut stem = vcf_path;
+    for ext in [".gz", ".bgz", ".zst", ".vcf", ".bcf", ".bed", ".pgen", ".bgen", ".gen"] {
+        stem = stem.strip_suffix(ext).unwrap_or(stem);
+    }
+    stem
+}
+
+/// Name used for a scoring file in output names and columns: its file name up to the first dot.
+pub fn scoring_id(path: &str) -> String {
+    let name = std::path::Path::new(path).file_name().map_or_else(|| path.into(), |n| n.to_string_lossy());
+    name.split('.').next().unwrap_or_default().to_string()
+}
+
+/// Split a `--scoring ID=PATH` argument of the scoring services.
+pub fn split_scoring_spec(spec: &str) -> Result<(&str, &str)> {
+    spec.split_once('=').ok_or_else(|| Error::invalid(spec, "expected --scoring ID=PATH"))
+}
+
+/// Create the parent directory of `path` if it does not exist yet.
+pub fn create_parent_dirs(path: &str) -> Result<()> {
+    match std::path::Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => std::fs::create_dir_all(parent).with_path(path),
+        _ => Ok(()),
+    }
+}
+
+/// Write the single-sample results table; `extra` holds any further (column, value) pairs.
+#[allow(clippy::too_many_arguments)]
+pub fn output_results(args: &Args, output_path: &str, score: f64, total_variants: u64, matched_variants: u64, duration: Duration, scoring_variants: usize, vcf_chr_format: bool, scoring_chr_format: bool, extra: &[(&str, String)]) -> Result<()> {
+    let mut header = "VCF_File\tScore_File\tPolygenic_Score\tCalculation_Time_Seconds\tTotal_Variants\tMatched_Variants\tScoring_Variants\tVCF_Chr_Format\tScoring_Chr_Format".to_string();
+    let mut row = format!(
+        "{}\t{}\t{:.6}\t{:.6}\t{}\t{}\t{}\t{}\t{}",
         args.vcf,
         args.scoring,
         score,
@@ -152,11 +1724,22 @@ pub fn output_results(args: &Args, score: f64, total_variants: usize, matched_va
         vcf_chr_format,
         scoring_chr_format
     );
+    for (column, value) in extra {
+        header.push('\t');
+        header.push_str(column);
+        row.push('\t');
+        row.push_str(value);
+    }
+    let output = format!("{}\n{}\n", header, row);
 
-    std::fs::write(&args.output, output)
+    let stage = Stage::write_output();
+    create_parent_dirs(output_path)?;
+    std::fs::write(output_path, output).with_path(output_path)?;
+    stage.record("samples", 1);
+    Ok(())
 }
 
-pub fn print_info(score: f64, total_variants: usize, matched_variants: usize, scoring_variants: usize, duration: Duration, vcf_chr_format: bool, scoring_chr_format: bool) {
+pub fn print_info(score: f64, total_variants: u64, matched_variants: u64, scoring_variants: usize, duration: Duration, vcf_chr_format: bool, scoring_chr_format: bool) {
     println!("\nDetailed Information:");
     println!("---------------------");
     println!("Total variants processed: {}", total_variants);
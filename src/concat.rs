@@ -0,0 +1,245 @@
+//! Scoring several VCFs as one (`--vcf chr1.vcf.gz,chr2.vcf.gz`, or `--vcf` given once for
+//! each), as biobanks release their genotypes one file per chromosome.
+//!
+//! The files are read one after another as a single VCF: the first file's header, with the
+//! `##contig` lines of the others added, then the records of each file in the order given,
+//! so each sample's score sums its partial scores over all of them. Every file must have the
+//! same `#CHROM` line, naming the same samples in the same order. A chromosome split across
+//! files is given in order, as the records of one VCF would be. A file with a tabix or CSI
+//! index is read only around the scoring variants, as a single VCF would be. The files are
+//! a [`VcfInput`](crate::input::VcfInput) of several paths.
+//!
+//! With `--vcf-jobs N`, the next `N - 1` files are opened and decompressed on threads of
+//! their own while one is scanned, each up to [`AHEAD`] chunks ahead of it.
+
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, BufRead, Read};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use crate::bgzf;
+use crate::common::trim_line;
+use crate::error::{Error, Result};
+use crate::input::InputConfig;
+use crate::resources;
+use crate::tabix;
+
+/// Chunks of [`resources::Tuning::read_buffer`] bytes a file is decompressed ahead of the
+/// scan with `--vcf-jobs`.
+pub const AHEAD: usize = 64;
+
+/// The header of the VCF at `path`, up to its `#CHROM` line, and that line.
+fn read_header(path: &str, config: &InputConfig) -> Result<(Vec<String>, String)> {
+    let mut reader = bgzf::open(path, config)?;
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(|e| Error::read(path, lines.len() + 1, e))? == 0 {
+            return Err(Error::invalid(path, "VCF header (#CHROM line) not found"));
+        }
+        let header = trim_line(&line).to_string();
+        if header.starts_with("#CHROM") {
+            return Ok((lines, header));
+        }
+        if !header.starts_with("##") {
+            return Err(Error::invalid(path, "VCF header (#CHROM line) not found"));
+        }
+        lines.push(header);
+    }
+}
+
+/// The VCFs at `files`, at least one, as one VCF. `positions`, when given, are those of the
+/// scoring variants, for the files with an index to be read only around them. Each file is
+/// opened as `config` says, and `config.jobs` of them at once.
+pub fn open(files: &[String], positions: Option<Vec<(String, u64)>>, config: &InputConfig) -> Result<Reader> {
+    let (mut header, columns) = read_header(&files[0], config)?;
+    let mut contigs: HashSet<String> = header.iter().filter(|line| line.starts_with("##contig=")).cloned().collect();
+    // Where the contigs of later files go: after the first file's, or else before #CHROM
+    let mut insert_at = header.iter().rposition(|line| line.starts_with("##contig=")).map_or(header.len(), |last| last + 1);
+    for file in &files[1..] {
+        let (lines, other) = read_header(file, config)?;
+        if other != columns {
+            let first: Vec<&str> = columns.split('\t').collect();
+            let mismatch = other.split('\t').zip(&first).enumerate().find(|(_, (a, b))| a != *b);
+            let message = match mismatch {
+                Some((column, (here, there))) => format!("its column {} is '{}', but '{}' in {}; every VCF must have the same samples, in the same order", column + 1, here, there, files[0]),
+                None => format!("it has {} columns, but {} has {}; every VCF must have the same samples, in the same order", other.split('\t').count(), files[0], first.len()),
+            };
+            return Err(Error::invalid(file, message));
+        }
+        for contig in lines.into_iter().filter(|line| line.starts_with("##contig=")) {
+            if contigs.insert(contig.clone()) {
+                header.insert(insert_at, contig);
+                insert_at += 1;
+            }
+        }
+    }
+    let mut text = String::new();
+    for line in header.iter().chain([&columns]) {
+        text.push_str(line);
+        text.push('\n');
+    }
+    Ok(Reader {
+        files: files.iter().cloned().collect(),
+        positions: positions.map(Arc::new),
+        config: config.clone(),
+        started: VecDeque::new(),
+        current: None,
+        pending: text.into_bytes(),
+        pos: 0,
+    })
+}
+
+/// The records of one file, with its header skipped: the file itself, or for `--vcf-jobs`
+/// the chunks a thread decompresses it into.
+enum Records {
+    Direct(Box<dyn BufRead + Send>),
+    Ahead(Receiver<io::Result<Vec<u8>>>),
+}
+
+/// Open the VCF at `path` and read past its header.
+fn records(path: &str, positions: Option<&[(String, u64)]>, config: &InputConfig) -> io::Result<Box<dyn BufRead + Send>> {
+    let indexed = positions.map(|positions| tabix::open(path, positions, config)).transpose().map_err(io::Error::other)?.flatten();
+    let mut reader = match indexed {
+        Some(indexed) => indexed.reader,
+        None => bgzf::open(path, config).map_err(io::Error::other)?,
+    };
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.starts_with("#CHROM") {
+            return Ok(reader);
+        }
+    }
+}
+
+/// The next chunk of `reader`, empty at its end.
+fn next_chunk(reader: &mut dyn BufRead) -> io::Result<Vec<u8>> {
+    let size = resources::current().read_buffer;
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Decompress the file at `path` into `chunks`, until it ends or the reader is dropped.
+fn pump(path: &str, positions: Option<&[(String, u64)]>, config: &InputConfig, chunks: &SyncSender<io::Result<Vec<u8>>>) {
+    let mut reader = match records(path, positions, config) {
+        Ok(reader) => reader,
+        Err(e) => {
+            let _ = chunks.send(Err(e));
+            return;
+        }
+    };
+    loop {
+        match next_chunk(reader.as_mut()) {
+            Ok(chunk) if chunk.is_empty() => return,
+            Ok(chunk) => {
+                // The reader was dropped
+                if chunks.send(Ok(chunk)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let _ = chunks.send(Err(e));
+                return;
+            }
+        }
+    }
+}
+
+/// Several VCFs read as one: the merged header, then the records of each file in turn.
+pub struct Reader {
+    /// Files not yet started
+    files: VecDeque<String>,
+    positions: Option<Arc<Vec<(String, u64)>>>,
+    config: InputConfig,
+    /// Files being decompressed ahead, in order
+    started: VecDeque<(String, Records)>,
+    /// The file being read
+    current: Option<(String, Records)>,
+    /// The header, or the last chunk of a file, and how much of it has been read
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl Reader {
+    /// Start the files to be read next, up to `--vcf-jobs` of them with the current one.
+    fn start(&mut self) -> io::Result<()> {
+        let jobs = self.config.jobs.max(1);
+        while self.started.len() + usize::from(self.current.is_some()) < jobs {
+            let Some(path) = self.files.pop_front() else {
+                break;
+            };
+            let records = match jobs {
+                1 => Records::Direct(records(&path, self.positions.as_deref().map(Vec::as_slice), &self.config).map_err(|e| in_file(&path, e))?),
+                _ => {
+                    let (tx, rx) = mpsc::sync_channel(AHEAD);
+                    let positions = self.positions.clone();
+                    let config = self.config.clone();
+                    let thread_path = path.clone();
+                    thread::spawn(move || pump(&thread_path, positions.as_deref().map(Vec::as_slice), &config, &tx));
+                    Records::Ahead(rx)
+                }
+            };
+            self.started.push_back((path, records));
+        }
+        Ok(())
+    }
+
+    /// Put the next chunk of the current file, or of the next one, in `pending`; `false`
+    /// after the last file.
+    fn advance(&mut self) -> io::Result<bool> {
+        loop {
+            if self.current.is_none() {
+                self.start()?;
+                self.current = self.started.pop_front();
+                // Keep the next files going while this one is read
+                self.start()?;
+            }
+            let Some((path, records)) = &mut self.current else {
+                return Ok(false);
+            };
+            let chunk = match records {
+                Records::Direct(reader) => Some(next_chunk(reader.as_mut())),
+                Records::Ahead(chunks) => chunks.recv().ok(),
+            };
+            match chunk.transpose().map_err(|e| in_file(path, e))? {
+                Some(chunk) if !chunk.is_empty() => {
+                    self.pending = chunk;
+                    self.pos = 0;
+                    return Ok(true);
+                }
+                _ => self.current = None,
+            }
+        }
+    }
+}
+
+/// `e`, naming the file it happened in.
+fn in_file(path: &str, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path, e))
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = buf.len().min(available.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for Reader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.pending.len() && !self.advance()? {
+            return Ok(&[]);
+        }
+        Ok(&self.pending[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.pending.len());
+    }
+}
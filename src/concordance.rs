@@ -0,0 +1,174 @@
+//! `speedscore concordance`: sample-by-sample agreement of SpeedScore's scores with another
+//! tool's, such as plink2's `--score`, to validate a switch between the two.
+//!
+//! Samples are matched by ID. A sample is discordant when its two scores differ by more
+//! than the tolerance, scaled up for scores larger than 1 so that sums over many variants
+//! are not held to more digits than either tool prints. plink2 reports the score averaged
+//! over the allele count by default; it is multiplied back to a sum to compare with
+//! SpeedScore's.
+
+use std::collections::HashMap;
+use std::fs;
+use serde::Serialize;
+use crate::common::{create_parent_dirs, ConcordanceArgs};
+use crate::error::{Error, IoContext, Result};
+use crate::evaluate::{parse_value, Table};
+use crate::qc::QcFailure;
+use crate::stats;
+use crate::upload::Uploads;
+
+/// Discordant samples printed; the JSON report lists them all.
+const MAX_PRINTED: usize = 10;
+
+/// How two score files compare.
+#[derive(Debug, Serialize)]
+pub struct Concordance {
+    pub scores: String,
+    pub score_column: String,
+    pub other: String,
+    /// The column of `other` compared, or how it was derived, e.g. `SCORE1_AVG * ALLELE_CT`
+    pub other_column: String,
+    /// Samples with a score in both files
+    pub samples: usize,
+    /// Samples with a score in only one of the files
+    pub only_in_scores: usize,
+    pub only_in_other: usize,
+    pub max_abs_difference: f64,
+    pub mean_abs_difference: f64,
+    /// Pearson correlation of the two scores; `None` when either is constant
+    pub correlation: Option<f64>,
+    pub tolerance: f64,
+    /// Samples whose scores differ by more than the tolerance, largest difference first
+    pub discordant: Vec<DiscordantSample>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiscordantSample {
+    pub sample: String,
+    pub score: f64,
+    pub other_score: f64,
+    /// `score - other_score`
+    pub difference: f64,
+}
+
+/// Compare the two files, print the report and optionally save it as JSON. With
+/// `--strict`, discordant samples fail the run with exit code 3.
+pub fn run(args: &ConcordanceArgs) -> Result<()> {
+    if args.tolerance.is_nan() || args.tolerance < 0.0 {
+        return Err(Error::invalid("--tolerance", format!("{} is not a non-negative number", args.tolerance)));
+    }
+    let concordance = compare(args)?;
+    print_report(&concordance);
+    if let Some(output) = &args.output {
+        let mut uploads = Uploads::new();
+        let path = uploads.stage(output)?;
+        create_parent_dirs(&path)?;
+        let json = serde_json::to_string_pretty(&concordance).expect("concordance serialization cannot fail");
+        fs::write(&path, json + "\n").with_path(&path)?;
+        println!("Report written to: {}", path);
+        uploads.finish()?;
+    }
+    if args.strict && !concordance.discordant.is_empty() {
+        return Err(Error::Qc(vec![QcFailure { reason: "discordant_samples", value: concordance.discordant.len() as f64, threshold: 0.0 }]));
+    }
+    Ok(())
+}
+
+pub fn compare(args: &ConcordanceArgs) -> Result<Concordance> {
+    let scores = Table::read(&args.scores)?;
+    let score_index = scores.column(&args.score_column)?;
+    let ours = by_sample(&scores, &args.scores, |row| parse_value(&row[score_index]))?;
+
+    let other = Table::read(&args.other)?;
+    let (other_column, other_scores) = match &args.other_column {
+        Some(name) => {
+            let index = other.column(name)?;
+            (name.clone(), by_sample(&other, &args.other, |row| parse_value(&row[index]))?)
+        }
+        None => match (other.column("SCORE1_SUM"), other.column("SCORE1_AVG"), other.column("ALLELE_CT")) {
+            (Ok(sum), ..) => ("SCORE1_SUM".to_string(), by_sample(&other, &args.other, |row| parse_value(&row[sum]))?),
+            (Err(_), Ok(average), Ok(count)) => {
+                let sum = |row: &[String]| Some(parse_value(&row[average])? * parse_value(&row[count])?);
+                ("SCORE1_AVG * ALLELE_CT".to_string(), by_sample(&other, &args.other, sum)?)
+            }
+            _ => {
+                return Err(Error::invalid(
+                    &args.other,
+                    "no SCORE1_SUM, or SCORE1_AVG and ALLELE_CT, column to compare with; name one with --other-column",
+                ))
+            }
+        },
+    };
+
+    let mut pairs: Vec<(&str, f64, f64)> = ours
+        .iter()
+        .filter_map(|(&sample, &score)| other_scores.get(sample).map(|&other_score| (sample, score, other_score)))
+        .collect();
+    pairs.sort_by(|a, b| a.0.cmp(b.0));
+    let samples = pairs.len();
+    if samples == 0 {
+        return Err(Error::invalid(&args.other, format!("no sample has a score in both {} and {}", args.scores, args.other)));
+    }
+
+    let differences: Vec<f64> = pairs.iter().map(|&(_, score, other_score)| (score - other_score).abs()).collect();
+    let mut discordant: Vec<DiscordantSample> = pairs
+        .iter()
+        .filter(|&&(_, score, other_score)| (score - other_score).abs() > args.tolerance * score.abs().max(other_score.abs()).max(1.0))
+        .map(|&(sample, score, other_score)| DiscordantSample { sample: sample.to_string(), score, other_score, difference: score - other_score })
+        .collect();
+    discordant.sort_by(|a, b| b.difference.abs().total_cmp(&a.difference.abs()).then_with(|| a.sample.cmp(&b.sample)));
+
+    let (x, y): (Vec<f64>, Vec<f64>) = pairs.iter().map(|&(_, score, other_score)| (score, other_score)).unzip();
+    Ok(Concordance {
+        scores: args.scores.clone(),
+        score_column: args.score_column.clone(),
+        other: args.other.clone(),
+        other_column,
+        samples,
+        only_in_scores: ours.len() - samples,
+        only_in_other: other_scores.len() - samples,
+        max_abs_difference: differences.iter().copied().fold(0.0, f64::max),
+        mean_abs_difference: differences.iter().sum::<f64>() / samples as f64,
+        correlation: stats::pearson(&x, &y),
+        tolerance: args.tolerance,
+        discordant,
+    })
+}
+
+/// The score `value` reads from each row of `table` with one, by sample ID.
+fn by_sample<'a>(table: &'a Table, path: &str, value: impl Fn(&[String]) -> Option<f64>) -> Result<HashMap<&'a str, f64>> {
+    let ids = table.id_column()?;
+    let mut scores = HashMap::new();
+    for row in &table.rows {
+        if let Some(score) = value(row) {
+            if scores.insert(row[ids].as_str(), score).is_some() {
+                return Err(Error::invalid(path, format!("duplicate sample '{}'", row[ids])));
+            }
+        }
+    }
+    Ok(scores)
+}
+
+fn print_report(c: &Concordance) {
+    println!("Compared {} of {} with {} of {}", c.score_column, c.scores, c.other_column, c.other);
+    println!("Samples: {}", c.samples);
+    if c.only_in_scores > 0 || c.only_in_other > 0 {
+        println!("Not compared: {} only in {}, {} only in {}", c.only_in_scores, c.scores, c.only_in_other, c.other);
+    }
+    println!("Max |difference|: {:.3e}", c.max_abs_difference);
+    println!("Mean |difference|: {:.3e}", c.mean_abs_difference);
+    match c.correlation {
+        Some(r) => println!("Correlation: {:.6}", r),
+        None => println!("Correlation: not estimable (a constant score)"),
+    }
+    println!("Discordant samples (|difference| > {} x max(1, |score|)): {}", c.tolerance, c.discordant.len());
+    if !c.discordant.is_empty() {
+        println!("Sample\tScore\tOther\tDifference");
+        for d in c.discordant.iter().take(MAX_PRINTED) {
+            println!("{}\t{:.6}\t{:.6}\t{:.3e}", d.sample, d.score, d.other_score, d.difference);
+        }
+        if c.discordant.len() > MAX_PRINTED {
+            println!("... and {} more", c.discordant.len() - MAX_PRINTED);
+        }
+    }
+}
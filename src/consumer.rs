@@ -0,0 +1,151 @@
+//! Scoring the raw genotype exports of direct-to-consumer tests, 23andMe and AncestryDNA.
+//!
+//! Both are tab-separated text, plain or gzipped, after `#` comment lines: one line per
+//! variant with its rsID, chromosome and position, then its genotype as allele letters,
+//! together (`AG`, 23andMe) or in two columns (`A` and `G`, AncestryDNA). There are no
+//! REF/ALT alleles to read a genotype against, so the scoring file's effect allele is
+//! counted among the letters. A heterozygous genotype carrying neither letter is an allele
+//! mismatch, as is an insertion or deletion (`D`, `I`) or a multi-base effect allele; a
+//! homozygous one without it is taken to carry no copies. Missing genotypes (`--`, `0`)
+//! are skipped, and a single letter on the sex chromosomes or mitochondria is haploid.
+
+use std::io::BufRead;
+use crate::aliases;
+use crate::bgzf;
+use crate::common::{total_abs_weight, trim_line, weight_share, EffectWeights, ScoreOptions, ScoreSummary, SiteCounts};
+use crate::error::{Error, ErrorPolicy, Result};
+use crate::input::{InputConfig, VcfInput};
+use crate::plink;
+use crate::rejected::{Input, Reason};
+use crate::spans::Stage;
+
+/// Whether the file at `path` is a raw genotype export: past its comments, it starts with
+/// AncestryDNA's column names or with a line of rsID, chromosome, position and genotype.
+pub fn is_raw_genotypes(path: &str) -> Result<bool> {
+    is_raw_genotypes_from(path, bgzf::open(path, &InputConfig::default())?)
+}
+
+/// [`is_raw_genotypes`] for the file at `path`, read from `reader`.
+pub fn is_raw_genotypes_from(path: &str, reader: impl BufRead) -> Result<bool> {
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::read(path, index + 1, e))?;
+        let line = trim_line(&line);
+        if line.starts_with("##fileformat=") {
+            return Ok(false);
+        }
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        return Ok(match fields.as_slice() {
+            [rsid, chromosome, position, ..] if rsid.eq_ignore_ascii_case("rsid") => chromosome.eq_ignore_ascii_case("chromosome") && position.eq_ignore_ascii_case("position"),
+            [_, _, position, genotype @ ..] => (1..=2).contains(&genotype.len()) && position.parse::<u64>().is_ok(),
+            _ => false,
+        });
+    }
+    Ok(false)
+}
+
+/// Score the raw genotype export `input`, as
+/// [`calculate_polygenic_score`](crate::single_sample::calculate_polygenic_score) scores a
+/// single-sample VCF.
+pub fn calculate_polygenic_score(input: &VcfInput, effect_weights: &EffectWeights, options: &ScoreOptions, policy: &ErrorPolicy) -> Result<ScoreSummary> {
+    let path = input.path();
+    let stage = Stage::scan_vcf(path);
+    let total_weight = total_abs_weight(effect_weights);
+    let mut summary = ScoreSummary {
+        score: 0.0,
+        total_variants: 0,
+        matched_variants: 0,
+        dosage_sum: 0.0,
+        effective_variants: None,
+        weight_coverage: options.rescale_by_coverage.then_some(0.0),
+        filtered_records: None,
+        vcf_chr_format: false,
+        sites: SiteCounts::default(),
+        skipped: Vec::new(),
+    };
+    let mut chr_format = None;
+    for (index, line) in input.open()?.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.map_err(|e| Error::read(path, line_number, e))?;
+        let line = trim_line(&line);
+        if line.starts_with('#') || line.trim().is_empty() || line.starts_with("rsid\t") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let (chr, position, letters) = match fields.as_slice() {
+            [_, chr, position, genotype] => (*chr, *position, genotype.to_string()),
+            [_, chr, position, first, second] => (*chr, *position, format!("{}{}", first, second)),
+            _ => {
+                policy.handle_record(Error::parse(path, line_number, "Expected rsID, chromosome, position and genotype columns", line), line)?;
+                continue;
+            }
+        };
+        let Ok(position) = position.parse::<u64>() else {
+            policy.handle_record(Error::parse(path, line_number, "Invalid numeric position", line), line)?;
+            continue;
+        };
+        chr_format.get_or_insert_with(|| chr.starts_with("chr"));
+        summary.total_variants += 1;
+
+        let Some(effect) = effect_weights.get(&(chromosome(chr).to_string(), position)) else {
+            continue;
+        };
+        summary.sites.position_hits += 1;
+        let Some(count) = effect_count(&letters, &effect.effect_allele) else {
+            if let Some(rejections) = policy.rejected() {
+                let detail = format!("effect allele {} not in genotype {}", effect.effect_allele, letters);
+                rejections.push(Input::Vcf, line_number, Reason::AlleleMismatch, detail, line);
+            }
+            continue;
+        };
+        summary.sites.allele_hits += 1;
+        let Some(count) = count else {
+            continue;
+        };
+        summary.score += options.contribution(effect, count) - options.centering_offset(effect, None);
+        summary.matched_variants += 1;
+        summary.dosage_sum += count;
+        if let Some(coverage) = summary.weight_coverage.as_mut() {
+            *coverage += weight_share(effect, total_weight);
+        }
+    }
+    stage.record("samples", 1);
+    stage.record("variants", summary.total_variants);
+    stage.record("matched", summary.sites.allele_hits);
+    summary.vcf_chr_format = chr_format.unwrap_or(false);
+    summary.skipped = policy.skipped();
+    summary.filtered_records = policy.filtered();
+    Ok(summary)
+}
+
+/// A raw chromosome as a scoring one: AncestryDNA numbers the sex chromosomes and
+/// mitochondria as PLINK does, and its 25 is the pseudoautosomal part of X.
+fn chromosome(chr: &str) -> &str {
+    match chr {
+        "25" | "XY" => "X",
+        chr => aliases::canonical(plink::chromosome(chr)),
+    }
+}
+
+/// Copies of `effect_allele` among the genotype's letters: `None` for an allele mismatch,
+/// and `Some(None)` for a missing genotype.
+fn effect_count(letters: &str, effect_allele: &str) -> Option<Option<f64>> {
+    if letters.is_empty() || letters.bytes().all(|b| b == b'-' || b == b'0') {
+        return Some(None);
+    }
+    let [effect] = effect_allele.as_bytes() else {
+        return None;
+    };
+    let letters = letters.as_bytes();
+    if !letters.iter().all(|b| b"ACGTacgt".contains(b)) || letters.len() > 2 {
+        return None;
+    }
+    let count = letters.iter().filter(|b| b.eq_ignore_ascii_case(effect)).count();
+    // Only a heterozygote shows both of a variant's alleles
+    if count == 0 && letters.len() == 2 && !letters[0].eq_ignore_ascii_case(&letters[1]) {
+        return None;
+    }
+    Some(Some(count as f64))
+}
@@ -0,0 +1,93 @@
+//! Cross-checking the VCF's `##contig` header lines against the scoring file's
+//! chromosomes, so that a naming mismatch (`MT` for `M`, `23` for `X`) shows up before the
+//! scan rather than as a 0% match rate after it. A `chr` prefix on either side is fine, as
+//! are accessions with an alias, as they are when matching variants.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::BufRead;
+use crate::aliases;
+use crate::common::{trim_line, EffectWeights};
+use crate::error::{Error, Result};
+
+/// Most names listed in a message.
+const MAX_LISTED: usize = 10;
+
+/// Names the same chromosome commonly goes by, without "chr".
+const ALIASES: &[&[&str]] = &[&["X", "23"], &["Y", "24"], &["XY", "25"], &["M", "MT", "26"]];
+
+/// How the scoring file's chromosomes compare with the VCF's `##contig` lines.
+#[derive(Clone, Debug, Default)]
+pub struct ContigCheck {
+    /// Contigs the VCF declares, as matched; empty when its header has none
+    pub contigs: Vec<String>,
+    /// Scoring chromosomes the VCF does not declare, with their number of scoring variants
+    pub missing: BTreeMap<String, usize>,
+    /// Missing scoring chromosomes the VCF declares under another name: (scoring, VCF)
+    pub renamed: Vec<(String, String)>,
+}
+
+impl ContigCheck {
+    /// Scoring chromosomes the VCF does declare.
+    pub fn covered(&self, effect_weights: &EffectWeights) -> usize {
+        let chromosomes: HashSet<&String> = effect_weights.keys().map(|(chr, _)| chr).collect();
+        chromosomes.len() - self.missing.len()
+    }
+}
+
+/// `names` comma-separated, cut short after [`MAX_LISTED`].
+pub fn list<'a>(names: impl ExactSizeIterator<Item = &'a String>) -> String {
+    let count = names.len();
+    let listed: Vec<&str> = names.take(MAX_LISTED).map(String::as_str).collect();
+    match count - listed.len() {
+        0 => listed.join(", "),
+        more => format!("{} and {} more", listed.join(", "), more),
+    }
+}
+
+/// The contig IDs of the `##contig` lines of the VCF at `path`, read from `reader`, as
+/// [`aliases::canonical`] names them, in header order.
+pub fn read_contigs(path: &str, mut reader: impl BufRead) -> Result<Vec<String>> {
+    let mut line = String::new();
+    let mut contigs = Vec::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        line_number += 1;
+        if reader.read_line(&mut line).map_err(|e| Error::read(path, line_number, e))? == 0 {
+            break;
+        }
+        let header = trim_line(&line);
+        if let Some(contig) = header.strip_prefix("##contig=<") {
+            let id = contig.trim_end_matches('>').split(',').find_map(|field| field.trim().strip_prefix("ID="));
+            if let Some(id) = id {
+                contigs.push(aliases::canonical(id).to_string());
+            }
+        } else if !header.starts_with("##") {
+            break;
+        }
+    }
+    Ok(contigs)
+}
+
+/// Compare the scoring chromosomes with the `##contig` lines of the VCF at `vcf_path`, read
+/// from `reader`.
+pub fn check(vcf_path: &str, reader: impl BufRead, effect_weights: &EffectWeights) -> Result<ContigCheck> {
+    let contigs = read_contigs(vcf_path, reader)?;
+    if contigs.is_empty() {
+        return Ok(ContigCheck::default());
+    }
+    let declared: HashSet<&str> = contigs.iter().map(String::as_str).collect();
+    let mut missing = BTreeMap::new();
+    for (chr, _) in effect_weights.keys().filter(|(chr, _)| !declared.contains(chr.as_str())) {
+        *missing.entry(chr.clone()).or_insert(0) += 1;
+    }
+    let renamed = missing
+        .keys()
+        .filter_map(|chr| {
+            let aliases = ALIASES.iter().find(|aliases| aliases.iter().any(|alias| alias.eq_ignore_ascii_case(chr)))?;
+            let alias = aliases.iter().find(|alias| declared.contains(**alias))?;
+            Some((chr.clone(), alias.to_string()))
+        })
+        .collect();
+    Ok(ContigCheck { contigs, missing, renamed })
+}
@@ -0,0 +1,101 @@
+//! Per-variant decomposition of the cohort's score variance.
+//!
+//! With `--variant-variance`, each matched scoring variant's contribution to the score
+//! variance is measured in the same pass as the scores. Two figures are reported. The
+//! expected variance is `w² * Var(g)` under Hardy-Weinberg equilibrium, which is
+//! `w² * 2p(1-p)` for the additive model, with `p` the effect-allele frequency among the
+//! scored samples' called genotypes. The empirical variance is that of the variant's
+//! actual per-sample contributions, including centering and imputation. Variants are
+//! ranked by empirical variance. Per-variant variances need not add up to the score
+//! variance: LD between variants adds covariance terms.
+
+use std::fmt::Write as _;
+use std::fs;
+use crate::common::{create_parent_dirs, Model};
+use crate::error::{IoContext, Result};
+
+/// Running sums for one matched scoring variant.
+#[derive(Clone, Debug)]
+pub struct VariantContribution {
+    pub chr: String,
+    pub pos: u64,
+    pub effect_allele: String,
+    pub weight: f32,
+    /// Scored samples
+    pub samples: usize,
+    /// Scored samples with a called genotype
+    pub called: usize,
+    /// Effect-allele count summed over the called genotypes
+    pub allele_sum: f64,
+    /// Per-sample contribution to the score, summed and squared-summed
+    pub sum: f64,
+    pub sum_squares: f64,
+}
+
+impl VariantContribution {
+    pub fn new(chr: &str, pos: u64, effect_allele: &str, weight: f32) -> Self {
+        VariantContribution {
+            chr: chr.to_string(),
+            pos,
+            effect_allele: effect_allele.to_string(),
+            weight,
+            samples: 0,
+            called: 0,
+            allele_sum: 0.0,
+            sum: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    /// Effect-allele frequency among the called genotypes.
+    pub fn frequency(&self) -> Option<f64> {
+        (self.called > 0).then(|| self.allele_sum / (2 * self.called) as f64)
+    }
+
+    /// `w² * Var(g)` under Hardy-Weinberg equilibrium, for the model-coded genotype `g`.
+    pub fn expected_variance(&self, model: Model) -> Option<f64> {
+        self.frequency().map(|p| {
+            let genotype_variance = match model {
+                Model::Additive => 2.0 * p * (1.0 - p),
+                // The coded genotype is 0 or 1, and 1 with probability `expected(p)`
+                _ => model.expected(p) * (1.0 - model.expected(p)),
+            };
+            (self.weight as f64).powi(2) * genotype_variance
+        })
+    }
+
+    /// Sample variance of the per-sample contributions.
+    pub fn empirical_variance(&self) -> Option<f64> {
+        let n = self.samples as f64;
+        (self.samples > 1).then(|| ((self.sum_squares - self.sum * self.sum / n) / (n - 1.0)).max(0.0))
+    }
+}
+
+/// Write the contributions to `path` as a tab-separated table, largest empirical variance
+/// first, with each variant's share of `score_variance`.
+pub fn write_report(path: &str, contributions: &mut [VariantContribution], model: Model, score_variance: f64) -> Result<()> {
+    contributions.sort_by(|a, b| b.empirical_variance().unwrap_or(0.0).total_cmp(&a.empirical_variance().unwrap_or(0.0)));
+    let na = |value: Option<f64>| value.map_or("NA".to_string(), |v| v.to_string());
+    let mut out = String::from("rank\tchr\tpos\teffect_allele\tweight\tfrequency\tcalled\texpected_variance\tempirical_variance\tvariance_share\n");
+    for (rank, contribution) in contributions.iter().enumerate() {
+        let empirical = contribution.empirical_variance();
+        let share = empirical.filter(|_| score_variance > 0.0).map(|v| v / score_variance);
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            rank + 1,
+            contribution.chr,
+            contribution.pos,
+            contribution.effect_allele,
+            contribution.weight,
+            na(contribution.frequency()),
+            contribution.called,
+            na(contribution.expected_variance(model)),
+            na(empirical),
+            na(share)
+        )
+        .expect("writing to a String cannot fail");
+    }
+    create_parent_dirs(path)?;
+    fs::write(path, out).with_path(path)
+}
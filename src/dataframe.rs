@@ -0,0 +1,67 @@
+//! Results as a [polars](https://docs.rs/polars) `DataFrame`, compiled with
+//! `--features polars`, for embedding SpeedScore in a Rust data-science workflow.
+
+use polars::prelude::{Column, DataFrame};
+use crate::scorer::{SampleScore, ScoreResults};
+
+/// Reads one optional score of a sample.
+type Value = fn(&SampleScore) -> Option<f64>;
+
+/// Optional per-sample scores, by column name. A column is included when any sample has
+/// a value, and is null for the samples without one.
+const OPTIONAL: [(&str, Value); 19] = [
+    ("effective_variants", |s| s.effective_variants),
+    ("weight_coverage", |s| s.weight_coverage),
+    ("rescaled_score", |s| s.rescaled_score),
+    ("score_se", |s| s.score_se),
+    ("adjusted_score", |s| s.adjusted_score),
+    ("calibrated_z", |s| s.calibrated_z),
+    ("null_percentile", |s| s.null_percentile),
+    ("null_z", |s| s.null_z),
+    ("interaction_score", |s| s.interaction_score),
+    ("gxe_score", |s| s.gxe_score),
+    ("maternal_score", |s| s.maternal_score),
+    ("paternal_score", |s| s.paternal_score),
+    ("midparent_score", |s| s.midparent_score),
+    ("offspring_deviation", |s| s.offspring_deviation),
+    ("sex_z", |s| s.sex_z),
+    ("reference_percentile", |s| s.reference_percentile),
+    ("reference_z", |s| s.reference_z),
+    ("t_score", |s| s.t_score),
+    ("scale_percentile", |s| s.scale_percentile),
+];
+
+impl ScoreResults {
+    /// One row per sample, in VCF header order: `sample` and `score`, then whichever
+    /// optional scores the run produced (`rescaled_score`, `score_se`, ..., and
+    /// `risk_category`), then the QC columns `total_variants`, `matched_variants` and
+    /// `dosage_sum`. Per-PC, per-threshold and per-set scores are left out; they are
+    /// unnamed here.
+    ///
+    /// ```no_run
+    /// # let scorer = speedscore::Scorer::from_scoring_file("PGS000001.txt")?;
+    /// let scores = scorer.run("cohort.vcf.gz")?.to_dataframe();
+    /// println!("{} samples, columns {:?}", scores.height(), scores.get_column_names());
+    /// # Ok::<(), speedscore::Error>(())
+    /// ```
+    pub fn to_dataframe(&self) -> DataFrame {
+        let samples = &self.samples;
+        let mut columns = vec![
+            Column::new("sample".into(), samples.iter().map(|s| s.name.as_str()).collect::<Vec<_>>()),
+            Column::new("score".into(), samples.iter().map(|s| s.score).collect::<Vec<_>>()),
+        ];
+        for (name, value) in OPTIONAL {
+            let values: Vec<Option<f64>> = samples.iter().map(value).collect();
+            if values.iter().any(Option::is_some) {
+                columns.push(Column::new(name.into(), values));
+            }
+        }
+        if samples.iter().any(|s| s.risk_category.is_some()) {
+            columns.push(Column::new("risk_category".into(), samples.iter().map(|s| s.risk_category.as_deref()).collect::<Vec<_>>()));
+        }
+        columns.push(Column::new("total_variants".into(), samples.iter().map(|s| s.total_variants).collect::<Vec<_>>()));
+        columns.push(Column::new("matched_variants".into(), samples.iter().map(|s| s.matched_variants).collect::<Vec<_>>()));
+        columns.push(Column::new("dosage_sum".into(), samples.iter().map(|s| s.dosage_sum).collect::<Vec<_>>()));
+        DataFrame::new(columns).expect("every column has one value per sample, under a name of its own")
+    }
+}
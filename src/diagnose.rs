@@ -0,0 +1,279 @@
+//! Explaining a poor match rate. When few scoring variants matched, the first records of
+//! the VCF are probed again to tell the usual causes apart: chromosomes named differently,
+//! positions on another genome build, effect alleles coded differently from REF and ALT,
+//! or a VCF that simply does not hold the scoring variants. Each comes with a suggestion
+//! of what to change.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::BufRead;
+use crate::aliases;
+use crate::common::{trim_line, EffectWeights};
+use crate::contigs;
+use crate::error::{Error, Result};
+use crate::genome::{self, GenomeBuild};
+use crate::input::VcfInput;
+use crate::liftover::complement;
+
+/// Records of the VCF probed.
+const PROBE_RECORDS: usize = 200_000;
+
+/// Fewer probed scoring positions than this are not evidence either way.
+const MIN_SITES: usize = 20;
+
+/// Of the probed positions, the fraction whose effect allele is REF or ALT below which the
+/// alleles are blamed.
+const MIN_CONCORDANCE: f64 = 0.9;
+
+/// How the effect alleles differ from REF and ALT where the positions match.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AlleleMismatch {
+    /// The complement of the effect allele is REF or ALT
+    Strand,
+    /// The effect allele is REF or ALT in another case
+    Case,
+    /// The effect allele is one of several comma-separated ALTs
+    Multiallelic,
+    /// Neither: the alleles are coded some other way, e.g. as 1 and 2
+    Coding,
+}
+
+/// How `effect` differs from a record's REF and ALT; `None` when it is one of them.
+pub fn classify(effect: &str, ref_allele: &str, alt_allele: &str) -> Option<AlleleMismatch> {
+    let is_ref_or_alt = |allele: &str| allele == ref_allele || allele == alt_allele;
+    if is_ref_or_alt(effect) {
+        return None;
+    }
+    Some(if effect.eq_ignore_ascii_case(ref_allele) || effect.eq_ignore_ascii_case(alt_allele) {
+        AlleleMismatch::Case
+    } else if is_ref_or_alt(&complement(effect)) {
+        AlleleMismatch::Strand
+    } else if alt_allele.split(',').any(|alt| alt == effect) {
+        AlleleMismatch::Multiallelic
+    } else {
+        AlleleMismatch::Coding
+    })
+}
+
+/// The likely cause of a poor match rate.
+#[derive(Clone, Debug)]
+pub enum Cause {
+    /// None of the VCF's chromosomes is a scoring chromosome by name
+    ChromosomeNames { vcf: Vec<String>, scoring: Vec<String> },
+    /// The scoring file and the VCF are known to be on different builds
+    Build { scoring: GenomeBuild, vcf: GenomeBuild },
+    /// The VCF has records all around the scoring variants but few at their positions, as
+    /// when the builds differ
+    ShiftedPositions { hits: usize, expected: usize },
+    /// Positions match but effect alleles are not REF or ALT; the probed mismatches of the
+    /// commonest kind and an example (effect allele, REF, ALT)
+    Alleles { kind: AlleleMismatch, count: usize, example: (String, String, String) },
+    /// The whole VCF holds only chromosomes carrying few of the scoring variants
+    OtherChromosomes { vcf: Vec<String>, variants: usize },
+    /// The VCF has fewer records than there are scoring variants where it has any, and few
+    /// at their positions, as an unimputed array has
+    Sparse { hits: usize, expected: usize },
+}
+
+/// What the first records of the VCF showed.
+#[derive(Default)]
+struct Probe {
+    /// Whether the whole VCF was read
+    complete: bool,
+    /// Records, lowest and highest position, by chromosome without "chr"
+    chromosomes: HashMap<String, (usize, u64, u64)>,
+    position_hits: usize,
+    allele_hits: usize,
+    mismatches: HashMap<AlleleMismatch, (usize, (String, String, String))>,
+}
+
+impl Probe {
+    /// Read up to [`PROBE_RECORDS`] records of the VCF `input`.
+    fn read(input: &VcfInput, effect_weights: &EffectWeights) -> Result<Self> {
+        let path = input.path();
+        let mut reader = input.open()?;
+        let mut probe = Probe::default();
+        let mut line = String::new();
+        let mut line_number = 0;
+        let mut records = 0;
+        loop {
+            line.clear();
+            line_number += 1;
+            if reader.read_line(&mut line).map_err(|e| Error::read(path, line_number, e))? == 0 {
+                probe.complete = true;
+                break;
+            }
+            let record = trim_line(&line);
+            if record.is_empty() || record.starts_with('#') {
+                continue;
+            }
+            if records == PROBE_RECORDS {
+                break;
+            }
+            records += 1;
+            let mut fields = record.split('\t').map(str::trim);
+            let (Some(chr), Some(Ok(pos)), Some(_), Some(ref_allele), Some(alt_allele)) =
+                (fields.next(), fields.next().map(str::parse::<u64>), fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let chr = aliases::canonical(chr);
+            let (count, first, last) = probe.chromosomes.entry(chr.to_string()).or_insert((0, pos, pos));
+            *count += 1;
+            *first = (*first).min(pos);
+            *last = (*last).max(pos);
+            if let Some(weight) = effect_weights.get(&(chr.to_string(), pos)) {
+                probe.position_hits += 1;
+                probe.compare(&weight.effect_allele, ref_allele, alt_allele);
+            }
+        }
+        Ok(probe)
+    }
+
+    fn compare(&mut self, effect: &str, ref_allele: &str, alt_allele: &str) {
+        let Some(kind) = classify(effect, ref_allele, alt_allele) else {
+            self.allele_hits += 1;
+            return;
+        };
+        let example = (effect.to_string(), ref_allele.to_string(), alt_allele.to_string());
+        self.mismatches.entry(kind).or_insert((0, example)).0 += 1;
+    }
+
+    /// Scoring variants between the lowest and highest probed position of their chromosome.
+    fn scoring_in_span(&self, effect_weights: &EffectWeights) -> usize {
+        effect_weights
+            .keys()
+            .filter(|(chr, pos)| self.chromosomes.get(chr).is_some_and(|&(_, first, last)| (first..=last).contains(pos)))
+            .count()
+    }
+
+    /// Probed records on the scoring chromosomes.
+    fn records_on(&self, scoring_chromosomes: &HashSet<&String>) -> usize {
+        self.chromosomes.iter().filter(|(chr, _)| scoring_chromosomes.contains(chr)).map(|(_, &(count, ..))| count).sum()
+    }
+}
+
+/// Whether `chr` (without "chr") is a human chromosome's usual name.
+fn is_usual_name(chr: &str) -> bool {
+    chr.parse::<u8>().is_ok_and(|number| (1..=22).contains(&number)) || matches!(chr, "X" | "Y" | "XY" | "M" | "MT")
+}
+
+/// Chromosome names, numbered ones first in numeric order.
+fn in_order(names: &HashSet<&String>) -> Vec<String> {
+    let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    names.sort_by_key(|name| (name.parse::<u32>().unwrap_or(u32::MAX), name.clone()));
+    names
+}
+
+/// Work out why few of the scoring variants matched the VCF `input`, which is read again
+/// from its start. `scoring_build` is the scoring file's build, if known. `None` when the
+/// probe shows nothing wrong.
+pub fn diagnose(input: &VcfInput, effect_weights: &EffectWeights, scoring_build: Option<GenomeBuild>) -> Result<Option<Cause>> {
+    let probe = Probe::read(input, effect_weights)?;
+    if probe.chromosomes.is_empty() {
+        return Ok(None);
+    }
+    let scoring_chromosomes: HashSet<&String> = effect_weights.keys().map(|(chr, _)| chr).collect();
+    let vcf_chromosomes: HashSet<&String> = probe.chromosomes.keys().collect();
+
+    if vcf_chromosomes.is_disjoint(&scoring_chromosomes) && vcf_chromosomes.iter().any(|chr| !is_usual_name(chr)) {
+        return Ok(Some(Cause::ChromosomeNames { vcf: in_order(&vcf_chromosomes), scoring: in_order(&scoring_chromosomes) }));
+    }
+    if probe.complete {
+        let variants = effect_weights.keys().filter(|(chr, _)| vcf_chromosomes.contains(chr)).count();
+        if variants * 2 < effect_weights.len() {
+            return Ok(Some(Cause::OtherChromosomes { vcf: in_order(&vcf_chromosomes), variants }));
+        }
+    }
+
+    if let (Some(scoring), Some(vcf)) = (scoring_build, genome::detect_vcf(input.path(), input.peek()?)?) {
+        if scoring != vcf.build {
+            return Ok(Some(Cause::Build { scoring, vcf: vcf.build }));
+        }
+    }
+
+    let mismatched = probe.position_hits - probe.allele_hits;
+    if probe.position_hits >= MIN_SITES && (probe.allele_hits as f64) < MIN_CONCORDANCE * probe.position_hits as f64 && mismatched > 0 {
+        let (kind, (count, example)) = probe.mismatches.into_iter().max_by_key(|(kind, (count, _))| (*count, *kind as u8)).expect("mismatches were counted");
+        return Ok(Some(Cause::Alleles { kind, count, example }));
+    }
+
+    let expected = probe.scoring_in_span(effect_weights);
+    if expected >= MIN_SITES && probe.records_on(&scoring_chromosomes) >= expected && probe.position_hits * 10 < expected {
+        return Ok(Some(Cause::ShiftedPositions { hits: probe.position_hits, expected }));
+    }
+    if expected >= MIN_SITES && probe.position_hits * 2 < expected {
+        return Ok(Some(Cause::Sparse { hits: probe.position_hits, expected }));
+    }
+    Ok(None)
+}
+
+impl Cause {
+    /// What to change.
+    pub fn suggestion(&self) -> String {
+        match self {
+            Cause::ChromosomeNames { .. } => {
+                "rename the VCF's chromosomes to match the scoring file's, e.g. with `bcftools annotate --rename-chrs`, or map them with --chr-aliases".to_string()
+            }
+            Cause::Build { scoring, vcf } => {
+                format!("lift the scoring file from {} to {} with --liftover and a chain file, or use the scoring file's {} version", scoring, vcf, vcf)
+            }
+            Cause::ShiftedPositions { .. } => "check that the two are on the same genome build; if not, lift the scoring file over with --liftover, or pass --genome-build so the mismatch is caught".to_string(),
+            Cause::Alleles { kind: AlleleMismatch::Strand, .. } => {
+                "the effect alleles look like they are on the opposite strand; flip them to the VCF's forward strand, as a harmonized (PGS Catalog `hm_`) scoring file would be".to_string()
+            }
+            Cause::Alleles { kind: AlleleMismatch::Case, .. } => "write the scoring file's effect alleles in upper case, as the VCF's are".to_string(),
+            Cause::Alleles { kind: AlleleMismatch::Multiallelic, .. } => "split the VCF's multiallelic records into biallelic ones with `bcftools norm -m-`".to_string(),
+            Cause::Alleles { kind: AlleleMismatch::Coding, .. } => {
+                "the effect_allele column does not hold bases as the VCF's REF and ALT do; recode it to A, C, G and T, or check that it is the right column".to_string()
+            }
+            Cause::OtherChromosomes { .. } => {
+                "check that this is the VCF meant for this scoring file; to score several per-chromosome VCFs at once, join them with `bcftools concat`".to_string()
+            }
+            Cause::Sparse { .. } => {
+                "check that this is the VCF meant for this scoring file; genotyping-array VCFs need imputing before they cover a genome-wide score".to_string()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cause::ChromosomeNames { vcf, scoring } => write!(
+                f,
+                "the VCF names its chromosomes {} but the scoring file {}",
+                contigs::list(vcf.iter()),
+                contigs::list(scoring.iter())
+            ),
+            Cause::Build { scoring, vcf } => write!(f, "the scoring file is on {} but the VCF is on {}", scoring, vcf),
+            Cause::ShiftedPositions { hits, expected } => write!(
+                f,
+                "the VCF has records all around the scoring variants, but only {} of the {} probed scoring positions are among them, as when positions are on another build",
+                hits, expected
+            ),
+            Cause::Alleles { kind, count, example: (effect, ref_allele, alt_allele) } => {
+                let how = match kind {
+                    AlleleMismatch::Strand => "the complement of REF or ALT",
+                    AlleleMismatch::Case => "REF or ALT in another case",
+                    AlleleMismatch::Multiallelic => "one of several ALTs",
+                    AlleleMismatch::Coding => "neither REF nor ALT",
+                };
+                write!(
+                    f,
+                    "the positions match but the effect alleles do not: {} probed effect alleles are {} (e.g. {} where REF is {} and ALT is {})",
+                    count, how, effect, ref_allele, alt_allele
+                )
+            }
+            Cause::OtherChromosomes { vcf, variants } => {
+                let noun = if vcf.len() == 1 { "chromosome" } else { "chromosomes" };
+                write!(f, "the VCF only holds {} {}, which carry {} of the scoring variants", noun, contigs::list(vcf.iter()), variants)
+            }
+            Cause::Sparse { hits, expected } => write!(
+                f,
+                "the VCF has fewer records than there are scoring variants where it has any, and only {} of the {} probed scoring positions",
+                hits, expected
+            ),
+        }
+    }
+}
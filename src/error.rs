@@ -0,0 +1,453 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::filter::Filter;
+use crate::order::{SortCheck, Unsorted};
+use crate::qc::QcFailure;
+use crate::rejected::{Input, Reason, Rejection, Rejections};
+#[cfg(feature = "strict-vcf")]
+use crate::strict::Validator;
+
+/// Longest excerpt of an offending line kept in an error message.
+const MAX_CONTENT_LEN: usize = 120;
+
+/// Crate-wide error type. File-related variants name the file involved, and parse
+/// errors also carry the 1-based line number and an excerpt of the bad line.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{path}, line {line}: {source}")]
+    Read {
+        path: String,
+        line: usize,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{path}, line {line}: {message}: {content:?}")]
+    Parse {
+        path: String,
+        line: usize,
+        message: String,
+        content: String,
+    },
+
+    #[error("{path}: {message}")]
+    Invalid { path: String, message: String },
+
+    #[error("failed to start thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+
+    #[error("{count} malformed records exceed --max-errors {max}; last one was: {last}")]
+    TooManyErrors {
+        count: usize,
+        max: usize,
+        last: Box<Error>,
+    },
+
+    #[error("run failed {} QC check(s): {}", .0.len(), .0.iter().map(|f| f.reason).collect::<Vec<_>>().join(", "))]
+    Qc(Vec<QcFailure>),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: &str, source: io::Error) -> Self {
+        Error::Io { path: path.to_string(), source }
+    }
+
+    pub fn read(path: &str, line: usize, source: io::Error) -> Self {
+        Error::Read { path: path.to_string(), line, source }
+    }
+
+    /// A record that could not be parsed. `content` is truncated so that a
+    /// 100k-sample VCF line doesn't flood the terminal.
+    pub fn parse(path: &str, line: usize, message: impl Into<String>, content: &str) -> Self {
+        Error::Parse {
+            path: path.to_string(),
+            line,
+            message: message.into(),
+            content: excerpt(content),
+        }
+    }
+
+    pub fn invalid(path: &str, message: impl Into<String>) -> Self {
+        Error::Invalid { path: path.to_string(), message: message.into() }
+    }
+
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            Error::Io { .. } | Error::Read { .. } => FailureKind::Input,
+            Error::Parse { .. } | Error::Invalid { .. } | Error::TooManyErrors { .. } => FailureKind::Validation,
+            Error::Qc(_) => FailureKind::Qc,
+            Error::ThreadPool(_) => FailureKind::Internal,
+        }
+    }
+
+    /// The file and line the error points at, if any.
+    pub fn location(&self) -> (Option<&str>, Option<usize>) {
+        match self {
+            Error::Io { path, .. } | Error::Invalid { path, .. } => (Some(path), None),
+            Error::Read { path, line, .. } | Error::Parse { path, line, .. } => (Some(path), Some(*line)),
+            Error::TooManyErrors { last, .. } => last.location(),
+            Error::ThreadPool(_) | Error::Qc(_) => (None, None),
+        }
+    }
+
+    /// This error as a [`ErrorReport`] for machine consumption.
+    pub fn report(&self) -> ErrorReport<'_> {
+        let (path, line) = self.location();
+        ErrorReport {
+            kind: self.kind(),
+            exit_code: self.kind().exit_code(),
+            message: self.to_string(),
+            path,
+            line,
+            qc_failures: match self {
+                Error::Qc(failures) => failures,
+                _ => &[],
+            },
+        }
+    }
+}
+
+/// Broad failure categories, each with its own exit code so that workflow managers
+/// can tell a failure worth retrying (`Input`) from one that will recur (`Validation`, `Qc`).
+/// Command-line usage errors exit with 2, from argument parsing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// A bug or resource failure inside SpeedScore, including panics
+    Internal,
+    /// The run finished but failed its `--strict` QC thresholds
+    Qc,
+    /// An input could not be opened, read or written
+    Input,
+    /// An input or option is malformed or inconsistent
+    Validation,
+}
+
+impl FailureKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::Internal => 1,
+            FailureKind::Qc => 3,
+            FailureKind::Input => 4,
+            FailureKind::Validation => 5,
+        }
+    }
+}
+
+/// The JSON object written to stderr with `--error-format json`.
+#[derive(Debug, Serialize)]
+pub struct ErrorReport<'a> {
+    pub kind: FailureKind,
+    pub exit_code: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub qc_failures: &'a [QcFailure],
+}
+
+/// How a failed run is reported on stderr.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// `Error: <message>`
+    #[default]
+    Text,
+    /// One JSON object, see `ErrorReport`
+    Json,
+}
+
+/// What to do when a malformed VCF record is encountered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Abort the run at the first malformed record
+    Fail,
+    /// Print each malformed record to stderr and continue
+    Warn,
+    /// Silently skip malformed records
+    Skip,
+}
+
+/// What to do with a NaN or infinite weight in the scoring file, or dosage in the VCF.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NonFinite {
+    /// Drop the scoring row, or count the dosage as missing
+    #[default]
+    Reject,
+    /// Use 0 for the weight, or read the dosage as DS=0
+    Zero,
+    /// Stop the run
+    Abort,
+}
+
+/// Malformed records seen for one reason, such as `Invalid numeric position`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SkippedRecords {
+    pub reason: String,
+    pub count: usize,
+    /// Line number and excerpt of the earliest such record
+    pub first_line: Option<usize>,
+    pub first_content: String,
+}
+
+/// What an [`ErrorPolicy`] has counted and kept so far, saved with a checkpoint so that a
+/// resumed run's tallies and `--max-errors` cap cover the whole VCF.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PolicyState {
+    pub count: usize,
+    pub non_finite: usize,
+    pub filtered: usize,
+    pub skipped: Vec<SkippedRecords>,
+    /// Every rejected record, with `--rejected`
+    pub rejected: Vec<Rejection>,
+}
+
+/// Applies an `OnError` mode plus an optional cap on how many malformed records are tolerated,
+/// and an `Unsorted` mode for out-of-order records. Malformed records are tallied by reason,
+/// and records with NaN or infinite dosages counted. Shared across rayon workers, hence the
+/// atomic counters and the lock.
+pub struct ErrorPolicy {
+    mode: OnError,
+    max_errors: Option<usize>,
+    unsorted: Unsorted,
+    count: AtomicUsize,
+    skipped: Mutex<BTreeMap<String, SkippedRecords>>,
+    non_finite: AtomicUsize,
+    /// Every rejected record, with `--rejected`
+    rejections: Option<Rejections>,
+    /// Records to score, with `--include` or `--exclude`
+    filter: Option<Filter>,
+    filtered: AtomicUsize,
+    /// Header to validate records against, with `--strict-vcf`
+    #[cfg(feature = "strict-vcf")]
+    strict: Option<Validator>,
+}
+
+impl ErrorPolicy {
+    pub fn new(mode: OnError, max_errors: Option<usize>) -> Self {
+        ErrorPolicy {
+            mode,
+            max_errors,
+            unsorted: Unsorted::Error,
+            count: AtomicUsize::new(0),
+            skipped: Mutex::new(BTreeMap::new()),
+            non_finite: AtomicUsize::new(0),
+            rejections: None,
+            filter: None,
+            filtered: AtomicUsize::new(0),
+            #[cfg(feature = "strict-vcf")]
+            strict: None,
+        }
+    }
+
+    /// Validate every record in full against `validator`'s header before it is scored.
+    #[cfg(feature = "strict-vcf")]
+    pub fn strict(mut self, validator: Validator) -> Self {
+        self.strict = Some(validator);
+        self
+    }
+
+    /// With [`ErrorPolicy::strict`], check the record at `line_number`, whose whole line is
+    /// `record`, against the VCF spec and handle it as malformed if it fails. `Ok(false)`
+    /// means the caller should skip it.
+    #[cfg(feature = "strict-vcf")]
+    pub fn conforms(&self, path: &str, line_number: usize, record: &str) -> Result<bool> {
+        match self.strict.as_ref().map(|validator| validator.check(record)) {
+            Some(Err(message)) => self.handle_record(Error::parse(path, line_number, message, record), record).map(|_| false),
+            _ => Ok(true),
+        }
+    }
+
+    /// Every record conforms in builds without `--features strict-vcf`.
+    #[cfg(not(feature = "strict-vcf"))]
+    pub fn conforms(&self, _path: &str, _line_number: usize, _record: &str) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Score only the records `filter` passes.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Whether to score the record at `line_number`, whose whole line is `record`: it
+    /// [`conforms`](ErrorPolicy::conforms) and passes the filter, if there is one. Records
+    /// the filter leaves out are counted, and kept if rejected records are.
+    pub fn keeps(&self, path: &str, line_number: usize, record: &str) -> Result<bool> {
+        if !self.conforms(path, line_number, record)? {
+            return Ok(false);
+        }
+        match &self.filter {
+            Some(filter) if !filter.passes(record) => {
+                self.filtered.fetch_add(1, Ordering::Relaxed);
+                if let Some(rejections) = &self.rejections {
+                    rejections.push(Input::Vcf, line_number, Reason::Filtered, filter.to_string(), record);
+                }
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// The `--include` or `--exclude` filter, if there is one.
+    pub fn record_filter(&self) -> Option<&Filter> {
+        self.filter.as_ref()
+    }
+
+    /// Number of records the filter left out so far; `None` without a filter.
+    pub fn filtered(&self) -> Option<u64> {
+        self.filter.as_ref().map(|_| self.filtered.load(Ordering::Relaxed) as u64)
+    }
+
+    /// Keep every rejected VCF record, in full, in `rejections` (default: only tallies are kept).
+    pub fn rejections(mut self, rejections: Rejections) -> Self {
+        self.rejections = Some(rejections);
+        self
+    }
+
+    /// The rejected records kept so far, if they are being kept.
+    pub fn rejected(&self) -> Option<&Rejections> {
+        self.rejections.as_ref()
+    }
+
+    /// What to do when the VCF is not sorted (default: abort).
+    pub fn unsorted(mut self, mode: Unsorted) -> Self {
+        self.unsorted = mode;
+        self
+    }
+
+    /// A fresh sortedness check for one pass over a VCF.
+    pub fn sort_check(&self) -> SortCheck {
+        SortCheck::new(self.unsorted)
+    }
+
+    /// [`ErrorPolicy::handle`] for a malformed record whose whole line is `record`, which
+    /// is kept if rejected records are.
+    pub fn handle_record(&self, err: Error, record: &str) -> Result<()> {
+        if let (Some(rejections), Error::Parse { line, message, .. }) = (&self.rejections, &err) {
+            rejections.push(Input::Vcf, *line, Reason::Malformed, message.as_str(), record);
+        }
+        self.handle(err)
+    }
+
+    /// Record a malformed record. `Ok(())` means the caller should skip it and carry on.
+    pub fn handle(&self, err: Error) -> Result<()> {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        self.tally(&err);
+        match self.mode {
+            OnError::Fail => return Err(err),
+            OnError::Warn => eprintln!("Warning: skipping {}", err),
+            OnError::Skip => {}
+        }
+        match self.max_errors {
+            Some(max) if count > max => Err(Error::TooManyErrors { count, max, last: Box::new(err) }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of malformed records seen so far.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Record a VCF record with a NaN or infinite dosage, described by `err`, whose whole
+    /// line is `record`. Under [`NonFinite::Abort`] that is the end of the run; otherwise the
+    /// dosage has already been read as `mode` says.
+    pub fn non_finite(&self, mode: NonFinite, err: Error, record: &str) -> Result<()> {
+        self.non_finite.fetch_add(1, Ordering::Relaxed);
+        let detail = match mode {
+            NonFinite::Abort => return Err(err),
+            NonFinite::Reject => "counted as missing",
+            NonFinite::Zero => "read as 0",
+        };
+        if let (Some(rejections), Some(line)) = (&self.rejections, err.location().1) {
+            rejections.push(Input::Vcf, line, Reason::NonFiniteDosage, detail, record);
+        }
+        Ok(())
+    }
+
+    /// Number of records with NaN or infinite dosages seen so far.
+    pub fn non_finite_count(&self) -> usize {
+        self.non_finite.load(Ordering::Relaxed)
+    }
+
+    /// Malformed records seen so far, by reason, most frequent first.
+    pub fn skipped(&self) -> Vec<SkippedRecords> {
+        let mut skipped: Vec<SkippedRecords> = self.skipped.lock().expect("skipped records lock poisoned").values().cloned().collect();
+        skipped.sort_by_key(|records| std::cmp::Reverse(records.count));
+        skipped
+    }
+
+    /// Everything counted and kept so far; see [`ErrorPolicy::restore`].
+    pub fn state(&self) -> PolicyState {
+        PolicyState {
+            count: self.count(),
+            non_finite: self.non_finite_count(),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            skipped: self.skipped.lock().expect("skipped records lock poisoned").values().cloned().collect(),
+            rejected: self.rejections.as_ref().map(Rejections::all).unwrap_or_default(),
+        }
+    }
+
+    /// Take up the counts and kept records of `state`, saved by a run that got further
+    /// through the same inputs, in place of those so far.
+    pub fn restore(&self, state: PolicyState) {
+        self.count.store(state.count, Ordering::Relaxed);
+        self.non_finite.store(state.non_finite, Ordering::Relaxed);
+        self.filtered.store(state.filtered, Ordering::Relaxed);
+        *self.skipped.lock().expect("skipped records lock poisoned") = state.skipped.into_iter().map(|records| (records.reason.clone(), records)).collect();
+        if let Some(rejections) = &self.rejections {
+            rejections.replace(state.rejected);
+        }
+    }
+
+    fn tally(&self, err: &Error) {
+        let (reason, line, content) = match err {
+            Error::Parse { message, line, content, .. } => (message.clone(), Some(*line), content.as_str()),
+            other => (other.to_string(), other.location().1, ""),
+        };
+        let mut skipped = self.skipped.lock().expect("skipped records lock poisoned");
+        let entry = skipped.entry(reason.clone()).or_insert_with(|| SkippedRecords { reason, count: 0, first_line: line, first_content: content.to_string() });
+        entry.count += 1;
+        // Workers report out of order; keep the earliest line
+        if line < entry.first_line {
+            entry.first_line = line;
+            entry.first_content = content.to_string();
+        }
+    }
+}
+
+/// Attach a file path to a bare `io::Result`.
+pub trait IoContext<T> {
+    fn with_path(self, path: &str) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn with_path(self, path: &str) -> Result<T> {
+        self.map_err(|e| Error::io(path, e))
+    }
+}
+
+fn excerpt(content: &str) -> String {
+    let content = content.trim_end_matches(['\n', '\r']);
+    match content.char_indices().nth(MAX_CONTENT_LEN) {
+        Some((idx, _)) => format!("{}...", &content[..idx]),
+        None => content.to_string(),
+    }
+}
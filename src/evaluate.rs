@@ -0,0 +1,483 @@
+//! `speedscore evaluate`: how well a computed score predicts a phenotype.
+//!
+//! The score is standardized across the evaluated samples, so effect sizes are per
+//! standard deviation. Quantitative traits are fitted by linear regression, binary
+//! traits by logistic regression, each on the covariates plus the score. R² is the
+//! increase over a covariates-only model on the observed scale; binary traits also get
+//! Nagelkerke's pseudo-R² and, given a prevalence, R² on the liability scale (Lee et al.
+//! 2012, which corrects for case-control ascertainment), the ROC AUC of the raw score, and
+//! odds ratios across score deciles adjusted for the same covariates.
+
+use std::collections::HashMap;
+use std::fs;
+use serde::Serialize;
+use crate::common::{create_parent_dirs, EvaluateArgs, TraitType};
+use crate::error::{Error, IoContext, Result};
+use crate::stats;
+use crate::upload::Uploads;
+
+/// Values read as missing, following plink (which also uses -9 for a missing phenotype).
+const MISSING: [&str; 6] = ["", "NA", "nan", "NaN", ".", "-9"];
+
+/// Association of one score with one phenotype.
+#[derive(Debug, Serialize)]
+pub struct Evaluation {
+    pub scores: String,
+    pub score_column: String,
+    pub phenotype: String,
+    pub phenotype_column: String,
+    pub covariates: Vec<String>,
+    /// `Quantitative` or `Binary`, never `Auto`
+    pub trait_type: TraitType,
+    /// Samples with a score, a phenotype and every covariate
+    pub samples: usize,
+    /// Phenotype-file rows left out for a missing score, phenotype or covariate
+    pub excluded: usize,
+    pub cases: Option<usize>,
+    pub controls: Option<usize>,
+    /// Effect per standard deviation of the score: a regression slope, or a log odds ratio
+    pub beta: f64,
+    pub standard_error: f64,
+    pub p_value: f64,
+    pub odds_ratio: Option<f64>,
+    /// Variance explained beyond the covariates, on the observed scale
+    pub r2: f64,
+    pub nagelkerke_r2: Option<f64>,
+    pub prevalence: Option<f64>,
+    pub liability_r2: Option<f64>,
+    /// Area under the ROC curve of the score alone, with a Hanley-McNeil 95% interval
+    pub auc: Option<f64>,
+    pub auc_ci: Option<[f64; 2]>,
+    /// Top score decile against the other nine
+    pub top_vs_rest: Option<OddsRatio>,
+    /// Top score decile against the bottom decile
+    pub top_vs_bottom: Option<OddsRatio>,
+    /// Each decile, lowest scores first, with its odds ratio against the bottom decile
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deciles: Vec<Decile>,
+}
+
+/// Covariate-adjusted odds ratio of a score group against a reference group.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct OddsRatio {
+    pub odds_ratio: f64,
+    pub ci_lower: f64,
+    pub ci_upper: f64,
+    pub p_value: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Decile {
+    /// 1 (lowest scores) to 10
+    pub decile: usize,
+    pub samples: usize,
+    pub cases: usize,
+    /// `None` for the bottom decile itself, or when the fit is not possible
+    pub vs_bottom: Option<OddsRatio>,
+}
+
+/// Evaluate `--scores` against the phenotype, print the report and optionally save it as JSON.
+pub fn run(args: &EvaluateArgs) -> Result<()> {
+    if let Some(k) = args.prevalence {
+        if !(k > 0.0 && k < 1.0) {
+            return Err(Error::invalid("--prevalence", format!("{} is not in (0, 1)", k)));
+        }
+    }
+    let evaluation = evaluate(args)?;
+    print_report(&evaluation);
+    if let Some(output) = &args.output {
+        let mut uploads = Uploads::new();
+        let path = uploads.stage(output)?;
+        create_parent_dirs(&path)?;
+        let json = serde_json::to_string_pretty(&evaluation).expect("evaluation serialization cannot fail");
+        fs::write(&path, json + "\n").with_path(&path)?;
+        println!("Report written to: {}", path);
+        uploads.finish()?;
+    }
+    Ok(())
+}
+
+pub fn evaluate(args: &EvaluateArgs) -> Result<Evaluation> {
+    let scores = Table::read(&args.scores)?;
+    let (score_ids, score_index) = (scores.id_column()?, scores.column(&args.score_column)?);
+    let mut score_by_id = HashMap::new();
+    for row in &scores.rows {
+        // Adjusted scores and z-scores can be blank
+        if let Some(score) = parse_value(&row[score_index]) {
+            if score_by_id.insert(row[score_ids].as_str(), score).is_some() {
+                return Err(Error::invalid(&args.scores, format!("duplicate sample '{}'", row[score_ids])));
+            }
+        }
+    }
+
+    let phenotypes = Table::read(&args.phenotype)?;
+    let phenotype_ids = phenotypes.id_column()?;
+    let phenotype_index = phenotypes.column(&args.phenotype_column)?;
+    let covariate_indices = args.covariates.iter().map(|name| phenotypes.column(name)).collect::<Result<Vec<_>>>()?;
+
+    let mut score = Vec::new();
+    let mut phenotype = Vec::new();
+    let mut covariates = Vec::new();
+    for row in &phenotypes.rows {
+        let values = (
+            score_by_id.get(row[phenotype_ids].as_str()),
+            parse_value(&row[phenotype_index]),
+            covariate_indices.iter().map(|&i| parse_value(&row[i])).collect::<Option<Vec<f64>>>(),
+        );
+        if let (Some(&s), Some(y), Some(c)) = values {
+            score.push(s);
+            phenotype.push(y);
+            covariates.push(c);
+        }
+    }
+    let samples = score.len();
+    if samples < covariate_indices.len() + 3 {
+        return Err(Error::invalid(
+            &args.phenotype,
+            format!("only {} samples have a score, a phenotype and all covariates", samples),
+        ));
+    }
+
+    let trait_type = resolve_trait(args.trait_type, &mut phenotype).map_err(|msg| Error::invalid(&args.phenotype, msg))?;
+
+    // Standardize, so the effect is per SD of the score
+    let mean = score.iter().sum::<f64>() / samples as f64;
+    let sd = (score.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (samples - 1) as f64).sqrt();
+    if sd == 0.0 {
+        return Err(Error::invalid(&args.scores, "the score is constant across the evaluated samples"));
+    }
+    let null_x: Vec<Vec<f64>> = covariates.iter().map(|c| std::iter::once(1.0).chain(c.iter().copied()).collect()).collect();
+    let full_x: Vec<Vec<f64>> = null_x.iter().zip(&score).map(|(row, s)| [&row[..], &[(s - mean) / sd]].concat()).collect();
+    let score_term = full_x[0].len() - 1;
+
+    let singular = || Error::invalid(&args.phenotype, "the covariates are collinear; cannot fit the regression");
+    let null_linear = stats::least_squares(&null_x, &phenotype).ok_or_else(singular)?;
+    let full_linear = stats::least_squares(&full_x, &phenotype).ok_or_else(singular)?;
+    let r2 = full_linear.r2 - null_linear.r2;
+
+    let mut evaluation = Evaluation {
+        scores: args.scores.clone(),
+        score_column: args.score_column.clone(),
+        phenotype: args.phenotype.clone(),
+        phenotype_column: args.phenotype_column.clone(),
+        covariates: args.covariates.clone(),
+        trait_type,
+        samples,
+        excluded: phenotypes.rows.len() - samples,
+        cases: None,
+        controls: None,
+        beta: full_linear.coefficients[score_term],
+        standard_error: full_linear.standard_errors[score_term],
+        p_value: 0.0,
+        odds_ratio: None,
+        r2,
+        nagelkerke_r2: None,
+        prevalence: None,
+        liability_r2: None,
+        auc: None,
+        auc_ci: None,
+        top_vs_rest: None,
+        top_vs_bottom: None,
+        deciles: Vec::new(),
+    };
+
+    match trait_type {
+        TraitType::Binary => {
+            let not_converged = || Error::invalid(&args.phenotype, "logistic regression did not converge (complete separation?)");
+            let null = stats::logistic(&null_x, &phenotype).ok_or_else(not_converged)?;
+            let full = stats::logistic(&full_x, &phenotype).ok_or_else(not_converged)?;
+            let cases = phenotype.iter().filter(|&&y| y == 1.0).count();
+            let case_fraction = cases as f64 / samples as f64;
+
+            evaluation.beta = full.coefficients[score_term];
+            evaluation.standard_error = full.standard_errors[score_term];
+            evaluation.p_value = stats::normal_two_sided_p(evaluation.beta / evaluation.standard_error);
+            evaluation.odds_ratio = Some(evaluation.beta.exp());
+            evaluation.cases = Some(cases);
+            evaluation.controls = Some(samples - cases);
+
+            // Nagelkerke: Cox-Snell R² against the intercept-only model, rescaled to reach 1
+            let intercept_only = cases as f64 * case_fraction.ln() + (samples - cases) as f64 * (1.0 - case_fraction).ln();
+            let nagelkerke = |log_likelihood: f64| {
+                let n = samples as f64;
+                (1.0 - (2.0 * (intercept_only - log_likelihood) / n).exp()) / (1.0 - (2.0 * intercept_only / n).exp())
+            };
+            evaluation.nagelkerke_r2 = Some(nagelkerke(full.log_likelihood) - nagelkerke(null.log_likelihood));
+
+            if let Some(k) = args.prevalence {
+                evaluation.prevalence = Some(k);
+                evaluation.liability_r2 = Some(liability_r2(r2, k, case_fraction));
+            }
+
+            let auc = auc(&score, &phenotype);
+            evaluation.auc = Some(auc);
+            evaluation.auc_ci = Some(auc_interval(auc, cases, samples - cases));
+
+            let deciles = deciles(&score);
+            let group = |decile: usize| deciles.iter().map(move |&d| d == decile);
+            evaluation.top_vs_rest = odds_ratio(&null_x, &phenotype, group(9).map(Some));
+            evaluation.top_vs_bottom = odds_ratio(&null_x, &phenotype, group(9).zip(group(0)).map(|(top, bottom)| (top || bottom).then_some(top)));
+            evaluation.deciles = (0..10)
+                .map(|decile| Decile {
+                    decile: decile + 1,
+                    samples: group(decile).filter(|&member| member).count(),
+                    cases: group(decile).zip(&phenotype).filter(|&(member, &y)| member && y == 1.0).count(),
+                    vs_bottom: match decile {
+                        0 => None,
+                        _ => odds_ratio(&null_x, &phenotype, group(decile).zip(group(0)).map(|(d, bottom)| (d || bottom).then_some(d))),
+                    },
+                })
+                .collect();
+        }
+        _ => {
+            let df = (samples - full_x[0].len()) as f64;
+            evaluation.p_value = stats::t_two_sided_p(evaluation.beta / evaluation.standard_error, df);
+        }
+    }
+    Ok(evaluation)
+}
+
+/// Settle `Auto` from the phenotype values, recoding plink's 1/2 case-control coding to 0/1.
+fn resolve_trait(requested: TraitType, phenotype: &mut [f64]) -> std::result::Result<TraitType, String> {
+    let all_in = |codes: [f64; 2]| phenotype.iter().all(|y| codes.contains(y));
+    let binary = if all_in([0.0, 1.0]) {
+        Some(0.0)
+    } else if all_in([1.0, 2.0]) {
+        Some(1.0)
+    } else {
+        None
+    };
+    match (requested, binary) {
+        (TraitType::Quantitative, _) | (TraitType::Auto, None) => Ok(TraitType::Quantitative),
+        (TraitType::Binary, None) => Err("a binary phenotype must be coded 0/1 or 1/2 (control/case)".to_string()),
+        (_, Some(control)) => {
+            phenotype.iter_mut().for_each(|y| *y -= control);
+            let cases = phenotype.iter().filter(|&&y| y == 1.0).count();
+            if cases == 0 || cases == phenotype.len() {
+                return Err("the binary phenotype needs both cases and controls".to_string());
+            }
+            Ok(TraitType::Binary)
+        }
+    }
+}
+
+/// ROC AUC of `score` for 0/1 `phenotype`: the Mann-Whitney probability that a random case
+/// outscores a random control, with ties counting half.
+fn auc(score: &[f64], phenotype: &[f64]) -> f64 {
+    let mut order: Vec<usize> = (0..score.len()).collect();
+    order.sort_by(|&a, &b| score[a].total_cmp(&score[b]));
+    // Sum of the cases' ranks, giving tied scores their average rank
+    let mut case_rank_sum = 0.0;
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end < order.len() && score[order[end]] == score[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.0;
+        case_rank_sum += order[start..end].iter().filter(|&&i| phenotype[i] == 1.0).count() as f64 * rank;
+        start = end;
+    }
+    let cases = phenotype.iter().filter(|&&y| y == 1.0).count() as f64;
+    let controls = phenotype.len() as f64 - cases;
+    (case_rank_sum - cases * (cases + 1.0) / 2.0) / (cases * controls)
+}
+
+/// 95% interval for an AUC from Hanley and McNeil's (1982) standard error.
+fn auc_interval(auc: f64, cases: usize, controls: usize) -> [f64; 2] {
+    let (n1, n0) = (cases as f64, controls as f64);
+    let q1 = auc / (2.0 - auc);
+    let q2 = 2.0 * auc * auc / (1.0 + auc);
+    let variance = (auc * (1.0 - auc) + (n1 - 1.0) * (q1 - auc * auc) + (n0 - 1.0) * (q2 - auc * auc)) / (n1 * n0);
+    let margin = 1.959964 * variance.sqrt();
+    [(auc - margin).max(0.0), (auc + margin).min(1.0)]
+}
+
+/// Decile (0 = lowest scores) of every sample, splitting the ranked scores into ten near-equal groups.
+fn deciles(score: &[f64]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..score.len()).collect();
+    order.sort_by(|&a, &b| score[a].total_cmp(&score[b]));
+    let mut decile = vec![0; score.len()];
+    for (rank, &i) in order.iter().enumerate() {
+        decile[i] = rank * 10 / score.len();
+    }
+    decile
+}
+
+/// Odds ratio of the samples marked `Some(true)` against those marked `Some(false)`, from a
+/// logistic regression on the covariates (`covariates` rows, intercept included) plus the
+/// group indicator. Samples marked `None` are left out. `None` if the fit fails, e.g. when
+/// a group has no cases.
+fn odds_ratio(covariates: &[Vec<f64>], phenotype: &[f64], groups: impl Iterator<Item = Option<bool>>) -> Option<OddsRatio> {
+    let (x, y): (Vec<Vec<f64>>, Vec<f64>) = covariates
+        .iter()
+        .zip(phenotype)
+        .zip(groups)
+        .filter_map(|((row, &y), group)| group.map(|member| ([&row[..], &[f64::from(u8::from(member))]].concat(), y)))
+        .unzip();
+    let fit = stats::logistic(&x, &y)?;
+    let term = fit.coefficients.len() - 1;
+    let (beta, se) = (fit.coefficients[term], fit.standard_errors[term]);
+    // A separated fit "converges" to a huge coefficient with a useless interval
+    if !(beta.is_finite() && se.is_finite()) || beta.abs() > 20.0 {
+        return None;
+    }
+    Some(OddsRatio {
+        odds_ratio: beta.exp(),
+        ci_lower: (beta - 1.959964 * se).exp(),
+        ci_upper: (beta + 1.959964 * se).exp(),
+        p_value: stats::normal_two_sided_p(beta / se),
+    })
+}
+
+/// Convert observed-scale R² of a 0/1 trait to the liability scale, given population
+/// prevalence `k` and the sample's case fraction `p` (Lee et al. 2012, equation 15).
+fn liability_r2(r2_observed: f64, k: f64, p: f64) -> f64 {
+    let threshold = stats::normal_quantile(1.0 - k);
+    let z = stats::normal_pdf(threshold);
+    let m = z / k;
+    let c = k * (1.0 - k) / (z * z) * k * (1.0 - k) / (p * (1.0 - p));
+    let theta = m * (p - k) / (1.0 - k) * (m * (p - k) / (1.0 - k) - threshold);
+    r2_observed * c / (1.0 + r2_observed * theta * c)
+}
+
+pub(crate) fn parse_value(text: &str) -> Option<f64> {
+    if MISSING.contains(&text) {
+        return None;
+    }
+    text.parse().ok().filter(|v: &f64| v.is_finite())
+}
+
+fn print_report(e: &Evaluation) {
+    let trait_name = match e.trait_type {
+        TraitType::Binary => "binary, logistic regression",
+        _ => "quantitative, linear regression",
+    };
+    println!("Evaluated {} against {} ({})", e.score_column, e.phenotype_column, trait_name);
+    match (e.cases, e.controls) {
+        (Some(cases), Some(controls)) => println!("Samples: {} ({} cases, {} controls)", e.samples, cases, controls),
+        _ => println!("Samples: {}", e.samples),
+    }
+    if e.excluded > 0 {
+        println!("Excluded: {} (missing score, phenotype or covariate)", e.excluded);
+    }
+    if !e.covariates.is_empty() {
+        println!("Covariates: {}", e.covariates.join(", "));
+    }
+    println!("Beta per SD: {:.6} (SE {:.6})", e.beta, e.standard_error);
+    if let Some(odds_ratio) = e.odds_ratio {
+        println!("Odds ratio per SD: {:.4}", odds_ratio);
+    }
+    println!("P-value: {:.3e}", e.p_value);
+    println!("R² (observed scale, beyond covariates): {:.6}", e.r2);
+    if let Some(r2) = e.nagelkerke_r2 {
+        println!("Nagelkerke R² (beyond covariates): {:.6}", r2);
+    }
+    if let (Some(k), Some(r2)) = (e.prevalence, e.liability_r2) {
+        println!("Liability-scale R² (prevalence {}): {:.6}", k, r2);
+    }
+    if let (Some(auc), Some([lower, upper])) = (e.auc, e.auc_ci) {
+        println!("AUC: {:.4} (95% CI {:.4}-{:.4})", auc, lower, upper);
+    }
+    let describe = |or: &Option<OddsRatio>| match or {
+        Some(or) => format!("{:.3} (95% CI {:.3}-{:.3}, p = {:.3e})", or.odds_ratio, or.ci_lower, or.ci_upper, or.p_value),
+        None => "not estimable".to_string(),
+    };
+    if e.trait_type == TraitType::Binary {
+        println!("Odds ratio, top decile vs rest: {}", describe(&e.top_vs_rest));
+        println!("Odds ratio, top vs bottom decile: {}", describe(&e.top_vs_bottom));
+    }
+    if !e.deciles.is_empty() {
+        println!("Decile\tSamples\tCases\tOR vs bottom decile");
+        for d in &e.deciles {
+            let or = if d.decile == 1 { "reference".to_string() } else { describe(&d.vs_bottom) };
+            println!("{}\t{}\t{}\t{}", d.decile, d.samples, d.cases, or);
+        }
+    }
+}
+
+/// A delimited text table: the first line is the header. Comma-separated if the header
+/// has a comma, with fields in double quotes as the score CSV writes them, otherwise split
+/// on tabs or runs of spaces.
+pub(crate) struct Table {
+    path: String,
+    headers: Vec<String>,
+    pub(crate) rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub(crate) fn read(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path).with_path(path)?;
+        let mut lines = text.lines().enumerate();
+        let (_, header) = lines.find(|(_, line)| !line.trim().is_empty()).ok_or_else(|| Error::invalid(path, "empty file"))?;
+        let comma = header.contains(',');
+        let split = |line: &str| -> Vec<String> {
+            if comma {
+                split_csv(line)
+            } else {
+                line.split_whitespace().map(String::from).collect()
+            }
+        };
+        let headers = split(header);
+        let mut rows = Vec::new();
+        while let Some((index, line)) = lines.next() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A quoted field can hold line breaks: read on until its closing quote
+            let mut record = line.to_string();
+            while comma && record.matches('"').count() % 2 == 1 {
+                let Some((_, next)) = lines.next() else { break };
+                record.push('\n');
+                record.push_str(next);
+            }
+            let row = split(&record);
+            if row.len() != headers.len() {
+                return Err(Error::parse(
+                    path,
+                    index + 1,
+                    format!("Expected {} columns (from header), found {}", headers.len(), row.len()),
+                    &record,
+                ));
+            }
+            rows.push(row);
+        }
+        Ok(Table { path: path.to_string(), headers, rows })
+    }
+
+    pub(crate) fn column(&self, name: &str) -> Result<usize> {
+        self.headers.iter().position(|h| h == name).ok_or_else(|| {
+            Error::invalid(&self.path, format!("no '{}' column (columns: {})", name, self.headers.join(", ")))
+        })
+    }
+
+    /// The sample ID column, by the names plink and SpeedScore write.
+    pub(crate) fn id_column(&self) -> Result<usize> {
+        ["IID", "#IID", "Sample_Name"]
+            .iter()
+            .find_map(|name| self.headers.iter().position(|h| h == name))
+            .ok_or_else(|| Error::invalid(&self.path, "no sample ID column (IID, #IID or Sample_Name)"))
+    }
+}
+
+/// The fields of one CSV record, trimmed, with the double quotes around a field removed
+/// and doubled double quotes inside one read as one.
+fn split_csv(record: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
@@ -0,0 +1,364 @@
+//! `speedscore export`: scores as a dataset for sgkit and Hail workflows, with what became
+//! of each scoring variant.
+//!
+//! The dataset follows sgkit's conventions: variables are named by their first dimension
+//! (`sample_id`, `variant_position`, ...), variants are placed by `variant_contig`, an index
+//! into `contig_id`, and `variant_allele` holds the VCF's REF and ALT. Besides the
+//! per-sample scores, every scoring variant has an inclusion mask, `variant_included`,
+//! and a harmonization flag, `variant_harmonization`, saying whether its effect allele was
+//! the VCF's ALT or REF or why it could not be scored.
+//!
+//! A Zarr store (format 2, uncompressed, with consolidated metadata) opens with
+//! `xarray.open_zarr` or `sgkit.load_dataset`. With `--features arrow`, `--format arrow`
+//! writes the same variables as two Arrow IPC files, `samples.arrow` and `variants.arrow`,
+//! for pyarrow, Spark or Hail's `hl.Table.from_pandas`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use crate::aliases;
+use crate::bgzf;
+use crate::common::{trim_line, EffectWeights, ExportArgs};
+use crate::diagnose::{self, AlleleMismatch};
+use crate::error::{Error, IoContext, Result};
+use crate::input::InputConfig;
+use crate::scorer::{SampleScore, Scorer};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Zarr,
+    /// `samples.arrow` and `variants.arrow`; requires `--features arrow`
+    Arrow,
+}
+
+/// What became of a scoring variant, by its code in `variant_harmonization`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Harmonization {
+    EffectIsAlt = 0,
+    EffectIsRef = 1,
+    /// No VCF record at the position
+    Absent = 2,
+    Strand = 3,
+    Case = 4,
+    Multiallelic = 5,
+    Coding = 6,
+}
+
+impl Harmonization {
+    const ALL: [Harmonization; 7] = [
+        Harmonization::EffectIsAlt,
+        Harmonization::EffectIsRef,
+        Harmonization::Absent,
+        Harmonization::Strand,
+        Harmonization::Case,
+        Harmonization::Multiallelic,
+        Harmonization::Coding,
+    ];
+
+    /// The flag's name, as in the CF `flag_meanings` attribute.
+    fn name(self) -> &'static str {
+        match self {
+            Harmonization::EffectIsAlt => "effect_is_alt",
+            Harmonization::EffectIsRef => "effect_is_ref",
+            Harmonization::Absent => "absent",
+            Harmonization::Strand => "strand_flip",
+            Harmonization::Case => "case_mismatch",
+            Harmonization::Multiallelic => "multiallelic",
+            Harmonization::Coding => "allele_mismatch",
+        }
+    }
+
+    fn included(self) -> bool {
+        matches!(self, Harmonization::EffectIsAlt | Harmonization::EffectIsRef)
+    }
+}
+
+/// One scoring variant and the VCF record it was matched against, if any.
+struct Variant {
+    contig: usize,
+    position: u64,
+    effect_allele: String,
+    weight: f32,
+    /// REF and ALT of the record at the position; empty when there is none
+    alleles: [String; 2],
+    harmonization: Harmonization,
+}
+
+pub fn run(args: &ExportArgs) -> Result<()> {
+    #[cfg(not(feature = "arrow"))]
+    if args.format == ExportFormat::Arrow {
+        return Err(Error::invalid("--format", "Arrow output is not available in this build; rebuild with --features arrow"));
+    }
+    let scorer = Scorer::from_scoring_file(&args.scoring)?.dosage(args.dosage).model(args.model);
+    let results = scorer.run(&args.vcf)?;
+    let (contigs, variants) = harmonize(&args.vcf, scorer.weights())?;
+    let included = variants.iter().filter(|v| v.harmonization.included()).count();
+    println!("{} of {} scoring variants included; {} samples scored", included, variants.len(), results.samples.len());
+
+    let attributes = json!({
+        "source": format!("SpeedScore {}", env!("CARGO_PKG_VERSION")),
+        "vcf": args.vcf,
+        "scoring": args.scoring,
+        "model": args.model,
+        "dosage": args.dosage,
+        "contigs": contigs,
+    });
+    fs::create_dir_all(&args.output).with_path(&args.output)?;
+    match args.format {
+        ExportFormat::Zarr => write_zarr(Path::new(&args.output), &results.samples, &contigs, &variants, attributes)?,
+        #[cfg(feature = "arrow")]
+        ExportFormat::Arrow => arrow::write(Path::new(&args.output), &results.samples, &contigs, &variants, attributes)?,
+        #[cfg(not(feature = "arrow"))]
+        ExportFormat::Arrow => unreachable!("checked above"),
+    }
+    println!("Dataset written to: {}", args.output);
+    Ok(())
+}
+
+/// Every scoring variant with what the VCF has at its position, ordered by contig
+/// (numbered ones first) and position, and the contigs.
+fn harmonize(vcf_path: &str, weights: &EffectWeights) -> Result<(Vec<String>, Vec<Variant>)> {
+    // Harmonization and REF/ALT of the best record seen for each scoring variant
+    let mut found: HashMap<&(String, u64), (Harmonization, [String; 2])> = HashMap::new();
+    let mut reader = bgzf::open(vcf_path, &InputConfig::default())?;
+    let mut line = String::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        line_number += 1;
+        if reader.read_line(&mut line).map_err(|e| Error::read(vcf_path, line_number, e))? == 0 {
+            break;
+        }
+        let record = trim_line(&line);
+        if record.is_empty() || record.starts_with('#') {
+            continue;
+        }
+        let mut fields = record.split('\t').map(str::trim);
+        // Malformed records are counted by the scoring pass; here they match nothing
+        let (Some(chr), Some(Ok(pos)), Some(_), Some(ref_allele), Some(alt_allele)) =
+            (fields.next(), fields.next().map(str::parse::<u64>), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let Some((key, weight)) = weights.get_key_value(&(aliases::canonical(chr).to_string(), pos)) else {
+            continue;
+        };
+        let harmonization = match diagnose::classify(&weight.effect_allele, ref_allele, alt_allele) {
+            None if weight.effect_allele == alt_allele => Harmonization::EffectIsAlt,
+            None => Harmonization::EffectIsRef,
+            Some(AlleleMismatch::Strand) => Harmonization::Strand,
+            Some(AlleleMismatch::Case) => Harmonization::Case,
+            Some(AlleleMismatch::Multiallelic) => Harmonization::Multiallelic,
+            Some(AlleleMismatch::Coding) => Harmonization::Coding,
+        };
+        // Of several records at a position, as in split multi-allelic sites, the one
+        // scored wins
+        let best = found.get(key).is_some_and(|(seen, _)| seen.included());
+        if !best {
+            found.insert(key, (harmonization, [ref_allele.to_string(), alt_allele.to_string()]));
+        }
+    }
+
+    let mut contigs: Vec<String> = weights.keys().map(|(chr, _)| chr.clone()).collect::<BTreeSet<_>>().into_iter().collect();
+    contigs.sort_by_key(|name| (name.parse::<u32>().unwrap_or(u32::MAX), name.clone()));
+    let index: BTreeMap<&str, usize> = contigs.iter().enumerate().map(|(i, chr)| (chr.as_str(), i)).collect();
+    let mut variants: Vec<Variant> = weights
+        .iter()
+        .map(|(key, weight)| {
+            let (harmonization, alleles) = found.remove(key).unwrap_or((Harmonization::Absent, Default::default()));
+            Variant {
+                contig: index[key.0.as_str()],
+                position: key.1,
+                effect_allele: weight.effect_allele.clone(),
+                weight: weight.weight,
+                alleles,
+                harmonization,
+            }
+        })
+        .collect();
+    variants.sort_by(|a, b| (a.contig, a.position, &a.effect_allele).cmp(&(b.contig, b.position, &b.effect_allele)));
+    Ok((contigs, variants))
+}
+
+fn write_zarr(dir: &Path, samples: &[SampleScore], contigs: &[String], variants: &[Variant], attributes: Value) -> Result<()> {
+    let mut store = Zarr::new(dir);
+    store.strings("sample_id", &["samples"], samples.iter().map(|s| s.name.as_str()), samples.len())?;
+    store.numbers("sample_score", &["samples"], "<f8", samples.iter().flat_map(|s| s.score.to_le_bytes()), samples.len())?;
+    store.numbers("sample_matched_variants", &["samples"], "<i8", samples.iter().flat_map(|s| (s.matched_variants as i64).to_le_bytes()), samples.len())?;
+    store.numbers("sample_total_variants", &["samples"], "<i8", samples.iter().flat_map(|s| (s.total_variants as i64).to_le_bytes()), samples.len())?;
+    store.numbers("sample_dosage_sum", &["samples"], "<f8", samples.iter().flat_map(|s| s.dosage_sum.to_le_bytes()), samples.len())?;
+
+    store.strings("contig_id", &["contigs"], contigs.iter().map(String::as_str), contigs.len())?;
+    store.numbers("variant_contig", &["variants"], "<i2", variants.iter().flat_map(|v| (v.contig as i16).to_le_bytes()), variants.len())?;
+    store.numbers("variant_position", &["variants"], "<i4", variants.iter().flat_map(|v| (v.position as i32).to_le_bytes()), variants.len())?;
+    store.strings("variant_allele", &["variants", "alleles"], variants.iter().flat_map(|v| v.alleles.iter().map(String::as_str)), variants.len())?;
+    store.strings("variant_effect_allele", &["variants"], variants.iter().map(|v| v.effect_allele.as_str()), variants.len())?;
+    store.numbers("variant_effect_weight", &["variants"], "<f4", variants.iter().flat_map(|v| v.weight.to_le_bytes()), variants.len())?;
+    store.numbers("variant_included", &["variants"], "|b1", variants.iter().map(|v| v.harmonization.included() as u8), variants.len())?;
+    store.numbers("variant_harmonization", &["variants"], "|i1", variants.iter().map(|v| v.harmonization as u8), variants.len())?;
+    // CF flag attributes, which xarray shows and cf_xarray decodes
+    let flags: Vec<String> = Harmonization::ALL.iter().map(|flag| flag.name().to_string()).collect();
+    store.attributes(
+        "variant_harmonization",
+        json!({ "flag_values": (0..flags.len()).collect::<Vec<_>>(), "flag_meanings": flags.join(" ") }),
+    );
+    store.finish(attributes)
+}
+
+/// A Zarr (format 2) group being written, one uncompressed chunk per array.
+struct Zarr<'a> {
+    dir: &'a Path,
+    /// Every metadata document, by key, for `.zmetadata`
+    metadata: Map<String, Value>,
+}
+
+impl<'a> Zarr<'a> {
+    fn new(dir: &'a Path) -> Self {
+        let mut metadata = Map::new();
+        metadata.insert(".zgroup".to_string(), json!({ "zarr_format": 2 }));
+        Zarr { dir, metadata }
+    }
+
+    /// An array of `length` rows of `dtype`, laid out along `dims`, from its raw bytes.
+    fn numbers(&mut self, name: &str, dims: &[&str], dtype: &str, bytes: impl Iterator<Item = u8>, length: usize) -> Result<()> {
+        self.array(name, dims, vec![length], dtype, bytes.collect())
+    }
+
+    /// An array of fixed-width Unicode strings (`<U`, as NumPy stores them) of `length`
+    /// rows, each of as many values as `dims` has beyond the first.
+    fn strings<'s>(&mut self, name: &str, dims: &[&str], values: impl Iterator<Item = &'s str> + Clone, length: usize) -> Result<()> {
+        let width = values.clone().map(|value| value.chars().count()).max().unwrap_or(0).max(1);
+        let mut bytes = Vec::new();
+        let mut count = 0;
+        for value in values {
+            let chars = value.chars().map(u32::from).chain(std::iter::repeat(0)).take(width);
+            bytes.extend(chars.flat_map(u32::to_le_bytes));
+            count += 1;
+        }
+        let mut shape = vec![length];
+        if dims.len() > 1 {
+            shape.push(count / length.max(1));
+        }
+        self.array(name, dims, shape, &format!("<U{}", width), bytes)
+    }
+
+    fn array(&mut self, name: &str, dims: &[&str], shape: Vec<usize>, dtype: &str, bytes: Vec<u8>) -> Result<()> {
+        let dir = self.dir.join(name);
+        fs::create_dir_all(&dir).with_path(&dir.to_string_lossy())?;
+        // Zarr needs chunks of at least one row, even for an empty array
+        let chunks: Vec<usize> = shape.iter().map(|&n| n.max(1)).collect();
+        if shape.iter().all(|&n| n > 0) {
+            let chunk = dir.join(vec!["0"; shape.len()].join("."));
+            fs::write(&chunk, bytes).with_path(&chunk.to_string_lossy())?;
+        }
+        let array = json!({
+            "zarr_format": 2,
+            "shape": shape,
+            "chunks": chunks,
+            "dtype": dtype,
+            "compressor": null,
+            "fill_value": null,
+            "filters": null,
+            "order": "C",
+        });
+        self.metadata.insert(format!("{}/.zarray", name), array);
+        // xarray's convention for naming an array's dimensions
+        self.metadata.insert(format!("{}/.zattrs", name), json!({ "_ARRAY_DIMENSIONS": dims }));
+        Ok(())
+    }
+
+    /// Add `attributes` to those of array `name`.
+    fn attributes(&mut self, name: &str, attributes: Value) {
+        if let (Some(Value::Object(existing)), Value::Object(added)) = (self.metadata.get_mut(&format!("{}/.zattrs", name)), attributes) {
+            existing.extend(added);
+        }
+    }
+
+    /// Write the group's `attributes` and every metadata document, alone and consolidated.
+    fn finish(mut self, attributes: Value) -> Result<()> {
+        self.metadata.insert(".zattrs".to_string(), attributes);
+        for (key, document) in &self.metadata {
+            let path = self.dir.join(key);
+            fs::write(&path, serde_json::to_string_pretty(document).expect("metadata serialization cannot fail")).with_path(&path.to_string_lossy())?;
+        }
+        let consolidated = json!({ "zarr_consolidated_format": 1, "metadata": self.metadata });
+        let path = self.dir.join(".zmetadata");
+        fs::write(&path, serde_json::to_string_pretty(&consolidated).expect("metadata serialization cannot fail")).with_path(&path.to_string_lossy())
+    }
+}
+
+#[cfg(feature = "arrow")]
+mod arrow {
+    //! The dataset as Arrow IPC files. Arrow has no dimensions, so the variables of each
+    //! dimension make one table; `variant_contig` is the contig's name rather than an index.
+
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::path::Path;
+    use std::sync::Arc;
+    use arrow_array::builder::{FixedSizeListBuilder, StringBuilder};
+    use arrow_array::{ArrayRef, BooleanArray, Float32Array, Float64Array, Int32Array, Int64Array, RecordBatch, StringArray};
+    use arrow_ipc::writer::FileWriter;
+    use arrow_schema::{ArrowError, Schema};
+    use serde_json::Value;
+    use super::{Harmonization, Variant};
+    use crate::error::{Error, IoContext, Result};
+    use crate::scorer::SampleScore;
+
+    pub fn write(dir: &Path, samples: &[SampleScore], contigs: &[String], variants: &[Variant], attributes: Value) -> Result<()> {
+        let sample_columns: Vec<(&str, ArrayRef)> = vec![
+            ("sample_id", Arc::new(StringArray::from_iter_values(samples.iter().map(|s| s.name.as_str())))),
+            ("sample_score", Arc::new(Float64Array::from_iter_values(samples.iter().map(|s| s.score)))),
+            ("sample_matched_variants", Arc::new(Int64Array::from_iter_values(samples.iter().map(|s| s.matched_variants as i64)))),
+            ("sample_total_variants", Arc::new(Int64Array::from_iter_values(samples.iter().map(|s| s.total_variants as i64)))),
+            ("sample_dosage_sum", Arc::new(Float64Array::from_iter_values(samples.iter().map(|s| s.dosage_sum)))),
+        ];
+        let mut alleles = FixedSizeListBuilder::new(StringBuilder::new(), 2);
+        for variant in variants {
+            for allele in &variant.alleles {
+                match allele.as_str() {
+                    "" => alleles.values().append_null(),
+                    allele => alleles.values().append_value(allele),
+                }
+            }
+            alleles.append(variant.harmonization != Harmonization::Absent);
+        }
+        let variant_columns: Vec<(&str, ArrayRef)> = vec![
+            ("variant_contig", Arc::new(StringArray::from_iter_values(variants.iter().map(|v| contigs[v.contig].as_str())))),
+            ("variant_position", Arc::new(Int32Array::from_iter_values(variants.iter().map(|v| v.position as i32)))),
+            ("variant_allele", Arc::new(alleles.finish())),
+            ("variant_effect_allele", Arc::new(StringArray::from_iter_values(variants.iter().map(|v| v.effect_allele.as_str())))),
+            ("variant_effect_weight", Arc::new(Float32Array::from_iter_values(variants.iter().map(|v| v.weight)))),
+            ("variant_included", Arc::new(variants.iter().map(|v| Some(v.harmonization.included())).collect::<BooleanArray>())),
+            ("variant_harmonization", Arc::new(StringArray::from_iter_values(variants.iter().map(|v| v.harmonization.name())))),
+        ];
+        // The dataset's attributes go with each table's schema, as strings
+        let metadata: HashMap<String, String> = match attributes {
+            Value::Object(attributes) => attributes
+                .into_iter()
+                .map(|(key, value)| match value {
+                    Value::String(text) => (key, text),
+                    value => (key, value.to_string()),
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+        table(&dir.join("samples.arrow"), sample_columns, &metadata)?;
+        table(&dir.join("variants.arrow"), variant_columns, &metadata)
+    }
+
+    fn table(path: &Path, columns: Vec<(&str, ArrayRef)>, metadata: &HashMap<String, String>) -> Result<()> {
+        let name = path.to_string_lossy();
+        let arrow_error = |e: ArrowError| Error::io(&name, std::io::Error::other(e));
+        let batch = RecordBatch::try_from_iter(columns).map_err(arrow_error)?;
+        let schema = Schema::new_with_metadata(batch.schema().fields().clone(), metadata.clone());
+        let batch = batch.with_schema(Arc::new(schema)).map_err(arrow_error)?;
+        let file = File::create(path).with_path(&name)?;
+        let mut writer = FileWriter::try_new(file, &batch.schema()).map_err(arrow_error)?;
+        writer.write(&batch).map_err(arrow_error)?;
+        writer.finish().map_err(arrow_error)
+    }
+}
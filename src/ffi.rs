@@ -0,0 +1,278 @@
+//! Stable C ABI, compiled with `--features ffi`. The matching declarations are in
+//! `include/speedscore.h`. Handles are opaque; every `*_new`/`speedscore_score_file*`
+//! result must be released with the corresponding `*_free`.
+//!
+//! Functions that can fail return NULL or a negative value and leave a message in
+//! `speedscore_last_error`, which is per-thread. A panic is caught at the boundary and
+//! reported the same way, as unwinding into C would abort the host process.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use crate::common::Model;
+use crate::error::{Error, Result};
+use crate::scorer::{ScoreResults, Scorer};
+
+/// Opaque scorer handle. The `Option` lets by-value builder methods be applied in place.
+pub struct SpeedscoreScorer(Option<Scorer>);
+
+/// Opaque results handle. Names are kept as C strings so pointers handed out stay valid.
+pub struct SpeedscoreResults {
+    results: ScoreResults,
+    names: Vec<CString>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// `body`'s result, or `failed` with the panic's message in `speedscore_last_error` if it panics.
+fn guard<T>(failed: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".to_string());
+        set_last_error(format!("internal error: {}", message));
+        failed
+    })
+}
+
+/// Borrow a C string argument, recording an error for NULL or non-UTF-8 input.
+unsafe fn str_arg<'a>(arg: *const c_char, name: &str) -> Result<&'a str> {
+    if arg.is_null() {
+        return Err(Error::invalid(name, "argument is NULL"));
+    }
+    CStr::from_ptr(arg).to_str().map_err(|_| Error::invalid(name, "argument is not valid UTF-8"))
+}
+
+/// Apply a by-value builder method to the scorer behind `scorer`.
+unsafe fn update(scorer: *mut SpeedscoreScorer, op: impl FnOnce(Scorer) -> Scorer) -> c_int {
+    match scorer.as_mut().and_then(|s| s.0.take().map(|inner| (s, inner))) {
+        Some((handle, inner)) => {
+            handle.0 = Some(op(inner));
+            0
+        }
+        None => {
+            set_last_error("scorer is NULL".to_string());
+            -1
+        }
+    }
+}
+
+/// Message describing the last failure on this thread, or NULL. Valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn speedscore_last_error() -> *const c_char {
+    guard(ptr::null(), || LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr())))
+}
+
+/// Load a scoring file. Returns NULL on failure.
+///
+/// # Safety
+/// `scoring_path` must be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_scorer_new(scoring_path: *const c_char) -> *mut SpeedscoreScorer {
+    guard(ptr::null_mut(), || {
+        match str_arg(scoring_path, "scoring_path").and_then(Scorer::from_scoring_file) {
+            Ok(scorer) => Box::into_raw(Box::new(SpeedscoreScorer(Some(scorer)))),
+            Err(e) => {
+                set_last_error(e.to_string());
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Score from FORMAT/DS (non-zero) or GT hard calls (zero). Returns 0, or -1 on failure.
+///
+/// # Safety
+/// `scorer` must be NULL or a handle from `speedscore_scorer_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_scorer_set_dosage(scorer: *mut SpeedscoreScorer, dosage: c_int) -> c_int {
+    guard(-1, || update(scorer, |s| s.dosage(dosage != 0)))
+}
+
+/// 0 = additive, 1 = dominant, 2 = recessive. Returns 0, or -1 on failure.
+///
+/// # Safety
+/// As for `speedscore_scorer_set_dosage`.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_scorer_set_model(scorer: *mut SpeedscoreScorer, model: c_int) -> c_int {
+    guard(-1, || {
+        let model = match model {
+            0 => Model::Additive,
+            1 => Model::Dominant,
+            2 => Model::Recessive,
+            other => {
+                set_last_error(format!("unknown model {}", other));
+                return -1;
+            }
+        };
+        update(scorer, |s| s.model(model))
+    })
+}
+
+/// Number of scoring threads; 0 keeps the current setting (the global pool by default). Returns 0, or -1 on failure.
+///
+/// # Safety
+/// As for `speedscore_scorer_set_dosage`.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_scorer_set_threads(scorer: *mut SpeedscoreScorer, threads: usize) -> c_int {
+    guard(-1, || update(scorer, |s| if threads == 0 { s } else { s.threads(threads) }))
+}
+
+/// # Safety
+/// `scorer` must be NULL or a handle from `speedscore_scorer_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_scorer_free(scorer: *mut SpeedscoreScorer) {
+    guard((), || {
+        if !scorer.is_null() {
+            drop(Box::from_raw(scorer));
+        }
+    })
+}
+
+/// Score every sample of the VCF at `vcf_path`. Returns NULL on failure.
+///
+/// # Safety
+/// `scorer` must be NULL or a live scorer handle; `vcf_path` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_score_file(
+    scorer: *const SpeedscoreScorer,
+    vcf_path: *const c_char,
+) -> *mut SpeedscoreResults {
+    guard(ptr::null_mut(), || {
+        let outcome = match scorer.as_ref().and_then(|s| s.0.as_ref()) {
+            Some(scorer) => str_arg(vcf_path, "vcf_path").and_then(|path| scorer.run(path)),
+            None => Err(Error::invalid("scorer", "argument is NULL")),
+        };
+        results_handle(outcome)
+    })
+}
+
+/// Score every sample of the `count` VCFs at `vcf_paths`, read one after another as a
+/// single VCF. Returns NULL on failure.
+///
+/// # Safety
+/// `scorer` must be NULL or a live scorer handle; `vcf_paths` NULL or an array of `count`
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_score_files(
+    scorer: *const SpeedscoreScorer,
+    vcf_paths: *const *const c_char,
+    count: usize,
+) -> *mut SpeedscoreResults {
+    guard(ptr::null_mut(), || {
+        let outcome = match (scorer.as_ref().and_then(|s| s.0.as_ref()), vcf_paths.is_null()) {
+            (Some(scorer), false) => std::slice::from_raw_parts(vcf_paths, count)
+                .iter()
+                .map(|&path| str_arg(path, "vcf_paths").map(str::to_string))
+                .collect::<Result<Vec<_>>>()
+                .and_then(|paths| scorer.run_files(&paths)),
+            (None, _) => Err(Error::invalid("scorer", "argument is NULL")),
+            (_, true) => Err(Error::invalid("vcf_paths", "argument is NULL")),
+        };
+        results_handle(outcome)
+    })
+}
+
+/// Hand scoring results to C, or record the error and return NULL.
+fn results_handle(outcome: Result<ScoreResults>) -> *mut SpeedscoreResults {
+    match outcome {
+        Ok(results) => {
+            let names = results
+                .samples
+                .iter()
+                .map(|s| CString::new(s.name.replace('\0', " ")).unwrap_or_default())
+                .collect();
+            Box::into_raw(Box::new(SpeedscoreResults { results, names }))
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Number of samples in `results` (0 for NULL).
+///
+/// # Safety
+/// `results` must be NULL or a live handle from `speedscore_score_file`.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_results_len(results: *const SpeedscoreResults) -> usize {
+    guard(0, || results.as_ref().map_or(0, |r| r.results.samples.len()))
+}
+
+/// Name of sample `index`, or NULL when out of range. Owned by `results`.
+///
+/// # Safety
+/// As for `speedscore_results_len`.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_results_sample_name(results: *const SpeedscoreResults, index: usize) -> *const c_char {
+    guard(ptr::null(), || results.as_ref().and_then(|r| r.names.get(index)).map_or(ptr::null(), |name| name.as_ptr()))
+}
+
+/// Score of sample `index`, or NaN when out of range.
+///
+/// # Safety
+/// As for `speedscore_results_len`.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_results_score(results: *const SpeedscoreResults, index: usize) -> f64 {
+    guard(f64::NAN, || results.as_ref().and_then(|r| r.results.samples.get(index)).map_or(f64::NAN, |s| s.score))
+}
+
+/// Variants seen for sample `index`, or 0 when out of range.
+///
+/// # Safety
+/// As for `speedscore_results_len`.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_results_total_variants(results: *const SpeedscoreResults, index: usize) -> u64 {
+    guard(0, || results.as_ref().and_then(|r| r.results.samples.get(index)).map_or(0, |s| s.total_variants))
+}
+
+/// Variants that contributed to the score of sample `index`, or 0 when out of range.
+///
+/// # Safety
+/// As for `speedscore_results_len`.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_results_matched_variants(results: *const SpeedscoreResults, index: usize) -> u64 {
+    guard(0, || results.as_ref().and_then(|r| r.results.samples.get(index)).map_or(0, |s| s.matched_variants))
+}
+
+/// # Safety
+/// `results` must be NULL or a handle from `speedscore_score_file` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn speedscore_results_free(results: *mut SpeedscoreResults) {
+    guard((), || {
+        if !results.is_null() {
+            drop(Box::from_raw(results));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_is_reported_as_failure() {
+        assert_eq!(guard(-1, || -> c_int { panic!("scorer state lost") }), -1);
+        let message = unsafe { CStr::from_ptr(speedscore_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "internal error: scorer state lost");
+    }
+
+    #[test]
+    fn null_handles_fail_without_panicking() {
+        let path = CString::new("missing.vcf").unwrap();
+        assert!(unsafe { speedscore_score_file(ptr::null(), path.as_ptr()) }.is_null());
+        assert_eq!(unsafe { speedscore_scorer_set_dosage(ptr::null_mut(), 1) }, -1);
+        assert!(unsafe { speedscore_results_score(ptr::null(), 0) }.is_nan());
+    }
+}
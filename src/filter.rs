@@ -0,0 +1,406 @@
+//! bcftools-style filter expressions, `--include` (`-i`) and `--exclude` (`-e`), applied to
+//! each VCF record before it is scored, so that filtering does not need a bcftools pass.
+//!
+//! An expression compares the record's fixed columns and INFO fields with constants, such
+//! as `INFO/R2>0.8 && FILTER="PASS"`. Comparisons combine with `&&` and `||` (or `&` and
+//! `|`, which mean the same for whole records) and group with parentheses. As in bcftools,
+//! a comparison with a missing value is false, and one with a list of values (`ALT`, or
+//! an INFO field such as `AF=0.1,0.3`) is true when any of them passes.
+
+use std::fmt;
+use regex::Regex;
+use crate::aliases;
+use crate::error::{Error, Result};
+
+/// A parsed `--include` or `--exclude` expression.
+#[derive(Debug)]
+pub struct Filter {
+    expression: String,
+    /// Whether records matching the expression are the ones left out
+    exclude: bool,
+    root: Expr,
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare { field: Field, op: Op, value: Constant },
+    /// An INFO tag is present, e.g. a flag such as `DB`
+    Has(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Chrom,
+    Pos,
+    Id,
+    Ref,
+    Alt,
+    Qual,
+    Filter,
+    Info(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `~`: matches a regular expression, or for FILTER, includes the filters
+    Match,
+    NoMatch,
+}
+
+#[derive(Debug)]
+struct Constant {
+    text: String,
+    /// The value of an unquoted number
+    number: Option<f64>,
+    /// For `~` and `!~`, except on FILTER
+    regex: Option<Regex>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Number(String),
+    Text(String),
+    Op(Op),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl Filter {
+    /// Parse `expression`, given to `--exclude` if `exclude` and to `--include` otherwise.
+    pub fn parse(expression: &str, exclude: bool) -> Result<Self> {
+        let flag = if exclude { "--exclude" } else { "--include" };
+        let invalid = |message: String| Error::invalid(flag, format!("{} in {:?}", message, expression));
+        let tokens = tokenize(expression).map_err(invalid)?;
+        let mut parser = Parser { tokens: &tokens, next: 0 };
+        let root = parser.or().map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected {}", token)));
+        }
+        Ok(Filter { expression: expression.to_string(), exclude, root })
+    }
+
+    /// Whether the record `record`, a whole VCF line, is scored.
+    pub fn passes(&self, record: &str) -> bool {
+        let mut columns = [""; 8];
+        for (column, value) in columns.iter_mut().zip(record.splitn(9, '\t')) {
+            *column = value.trim();
+        }
+        self.root.eval(&columns) != self.exclude
+    }
+}
+
+impl fmt::Display for Filter {
+    /// The filter as given on the command line, e.g. `-i 'INFO/R2>0.8'`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} '{}'", if self.exclude { "-e" } else { "-i" }, self.expression)
+    }
+}
+
+impl Expr {
+    fn eval(&self, columns: &[&str; 8]) -> bool {
+        match self {
+            Expr::And(left, right) => left.eval(columns) && right.eval(columns),
+            Expr::Or(left, right) => left.eval(columns) || right.eval(columns),
+            Expr::Has(tag) => info_entry(columns[7], tag).is_some(),
+            Expr::Compare { field: Field::Filter, op, value } => compare_filters(columns[6], *op, &value.text),
+            Expr::Compare { field, op, value } => {
+                let values: Vec<&str> = match field {
+                    Field::Chrom => vec![columns[0]],
+                    Field::Pos => vec![columns[1]],
+                    Field::Id => columns[2].split(';').collect(),
+                    Field::Ref => vec![columns[3]],
+                    Field::Alt => columns[4].split(',').collect(),
+                    Field::Qual => vec![columns[5]],
+                    Field::Info(tag) => info_entry(columns[7], tag).flatten().map_or_else(Vec::new, |v| v.split(',').collect()),
+                    Field::Filter => unreachable!("FILTER is compared as a set"),
+                };
+                let mut present = values.into_iter().filter(|v| !v.is_empty() && *v != ".").peekable();
+                match op {
+                    // Missing values fail the comparison, including the negated ones
+                    Op::Ne | Op::NoMatch if present.peek().is_some() => {
+                        let positive = if *op == Op::Ne { Op::Eq } else { Op::Match };
+                        !present.any(|v| compare(field, v, positive, value))
+                    }
+                    _ => present.any(|v| compare(field, v, *op, value)),
+                }
+            }
+        }
+    }
+}
+
+/// The value of INFO field `tag` in `info`: `None` when absent, `Some(None)` for a flag.
+fn info_entry<'a>(info: &'a str, tag: &str) -> Option<Option<&'a str>> {
+    info.split(';').find_map(|entry| match entry.split_once('=') {
+        Some((key, value)) if key == tag => Some(Some(value)),
+        None if entry == tag => Some(None),
+        _ => None,
+    })
+}
+
+/// One value of `field` against `constant`, for a non-negated `op`.
+fn compare(field: &Field, value: &str, op: Op, constant: &Constant) -> bool {
+    if let Some(regex) = &constant.regex {
+        return regex.is_match(value);
+    }
+    if let (Some(number), Ok(value)) = (constant.number, value.parse::<f64>()) {
+        return match op {
+            Op::Eq => value == number,
+            Op::Lt => value < number,
+            Op::Le => value <= number,
+            Op::Gt => value > number,
+            Op::Ge => value >= number,
+            Op::Ne | Op::Match | Op::NoMatch => unreachable!("negated and regex comparisons are handled by the caller"),
+        };
+    }
+    match (op, field) {
+        (Op::Eq, Field::Chrom) => aliases::canonical(value) == aliases::canonical(&constant.text),
+        (Op::Eq, _) => value == constant.text,
+        // A number compared with text, such as QUAL<30 against a non-numeric QUAL
+        _ => false,
+    }
+}
+
+/// The record's FILTER column against `constant` (`PASS`, `.`, or names joined by `;`):
+/// `=` is the same set of filters in any order, `~` includes them all.
+fn compare_filters(column: &str, op: Op, constant: &str) -> bool {
+    let names = |text: &str| -> Vec<String> {
+        let mut names: Vec<String> = text.split(';').map(str::trim).filter(|n| !n.is_empty() && *n != ".").map(String::from).collect();
+        names.sort();
+        names.dedup();
+        names
+    };
+    let (record, wanted) = (names(column), names(constant));
+    match op {
+        Op::Eq => record == wanted,
+        Op::Ne => record != wanted,
+        Op::Match => wanted.iter().all(|name| record.contains(name)),
+        Op::NoMatch => !wanted.iter().all(|name| record.contains(name)),
+        Op::Lt | Op::Le | Op::Gt | Op::Ge => unreachable!("FILTER is only compared with =, !=, ~ and !~"),
+    }
+}
+
+fn tokenize(expression: &str) -> std::result::Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, length) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => (Token::And, 2),
+            ('&', _) => (Token::And, 1),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('|', _) => (Token::Or, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('=', _) => (Token::Op(Op::Eq), 1),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('!', Some('~')) => (Token::Op(Op::NoMatch), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('~', _) => (Token::Op(Op::Match), 1),
+            ('"' | '\'', _) => {
+                let end = chars[i + 1..].iter().position(|&q| q == c).ok_or("unterminated string")?;
+                (Token::Text(chars[i + 1..i + 1 + end].iter().collect()), end + 2)
+            }
+            (c, _) if c.is_ascii_digit() || c == '.' || ((c == '-' || c == '+') && next.is_some_and(|n| n.is_ascii_digit() || n == '.')) => {
+                let mut end = i + 1;
+                while end < chars.len()
+                    && (chars[end].is_ascii_alphanumeric() || chars[end] == '.' || (matches!(chars[end], '+' | '-') && matches!(chars[end - 1], 'e' | 'E')))
+                {
+                    end += 1;
+                }
+                (Token::Number(chars[i..end].iter().collect()), end - i)
+            }
+            (c, _) if c.is_ascii_alphabetic() || c == '_' => {
+                let end = chars[i..].iter().position(|&n| !(n.is_ascii_alphanumeric() || matches!(n, '_' | '/' | '.'))).map_or(chars.len(), |n| i + n);
+                (Token::Name(chars[i..end].iter().collect()), end - i)
+            }
+            (c, _) => return Err(format!("unexpected character {:?}", c)),
+        };
+        tokens.push(token);
+        i += length;
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    next: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> std::result::Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> std::result::Result<Expr, String> {
+        let mut expr = self.term()?;
+        while self.peek() == Some(&Token::And) {
+            self.next += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    /// A parenthesized expression, a comparison, or an INFO tag on its own.
+    fn term(&mut self) -> std::result::Result<Expr, String> {
+        let left = match self.take() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                return match self.take() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("missing ')'".to_string()),
+                };
+            }
+            Some(token @ (Token::Name(_) | Token::Number(_) | Token::Text(_))) => token,
+            Some(token) => return Err(format!("unexpected {}", token)),
+            None => return Err("unexpected end of expression".to_string()),
+        };
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => {
+                return match left {
+                    Token::Name(name) => match field(&name)? {
+                        Field::Info(tag) => Ok(Expr::Has(tag)),
+                        _ => Err(format!("{} needs a comparison, such as {}=\"...\"", name, name)),
+                    },
+                    token => Err(format!("{} needs a comparison with a field", token)),
+                };
+            }
+        };
+        self.next += 1;
+        let right = self.take().ok_or("unexpected end of expression")?;
+        // A constant may come first, as in 0.8<INFO/R2
+        let (name, constant, op) = match (left, right) {
+            (Token::Name(name), constant @ (Token::Number(_) | Token::Text(_))) => (name, constant, op),
+            (constant @ (Token::Number(_) | Token::Text(_)), Token::Name(name)) => (name, constant, flip(op)),
+            (Token::Name(_), Token::Name(name)) => {
+                return Err(format!("compare fields with constants; quote text, as in FILTER=\"PASS\", not {}", name))
+            }
+            (_, token) => return Err(format!("unexpected {}", token)),
+        };
+        let field = field(&name)?;
+        let value = constant_for(&field, op, constant)?;
+        Ok(Expr::Compare { field, op, value })
+    }
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Match => "~",
+            Op::NoMatch => "!~",
+        }
+    }
+}
+
+/// `op` with its operands swapped.
+fn flip(op: Op) -> Op {
+    match op {
+        Op::Lt => Op::Gt,
+        Op::Le => Op::Ge,
+        Op::Gt => Op::Lt,
+        Op::Ge => Op::Le,
+        op => op,
+    }
+}
+
+fn field(name: &str) -> std::result::Result<Field, String> {
+    if let Some(tag) = name.strip_prefix("INFO/") {
+        return Ok(Field::Info(tag.to_string()));
+    }
+    if name.starts_with("FMT/") || name.starts_with("FORMAT/") {
+        return Err(format!("{}: per-sample FORMAT fields are not supported; filters apply to whole records", name));
+    }
+    Ok(match name.to_ascii_uppercase().as_str() {
+        "CHROM" => Field::Chrom,
+        "POS" => Field::Pos,
+        "ID" => Field::Id,
+        "REF" => Field::Ref,
+        "ALT" => Field::Alt,
+        "QUAL" => Field::Qual,
+        "FILTER" => Field::Filter,
+        // bcftools reads any other name as an INFO tag
+        _ => Field::Info(name.to_string()),
+    })
+}
+
+fn constant_for(field: &Field, op: Op, token: Token) -> std::result::Result<Constant, String> {
+    let (text, number) = match token {
+        Token::Number(text) => {
+            let number = text.parse::<f64>().map_err(|_| format!("invalid number {:?}", text))?;
+            (text, Some(number))
+        }
+        Token::Text(text) => (text, None),
+        token => return Err(format!("unexpected {}", token)),
+    };
+    if matches!(op, Op::Lt | Op::Le | Op::Gt | Op::Ge) {
+        if *field == Field::Filter {
+            return Err("FILTER can only be compared with =, !=, ~ and !~".to_string());
+        }
+        if number.is_none() {
+            return Err(format!("text such as {:?} can only be compared with =, !=, ~ and !~", text));
+        }
+    }
+    let regex = match (op, field) {
+        (Op::Match | Op::NoMatch, Field::Filter) => None,
+        (Op::Match | Op::NoMatch, _) => Some(Regex::new(&text).map_err(|e| format!("invalid regular expression {:?}: {}", text, e.to_string().lines().last().unwrap_or_default().trim_start_matches("error: ")))?),
+        _ => None,
+    };
+    Ok(Constant { text, number, regex })
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Op(op) => write!(f, "'{}'", op.symbol()),
+            Token::And => f.write_str("'&&'"),
+            Token::Or => f.write_str("'||'"),
+            Token::Open => f.write_str("'('"),
+            Token::Close => f.write_str("')'"),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Arrow Flight on the `speedscore grpc` service, compiled with `--features flight`:
+//! score tables are streamed to analytics clients (pyarrow, R's arrow, Spark) as Arrow
+//! record batches, without intermediate files.
+//!
+//! A flight is one VCF scored against a registered scoring file, named by a JSON command
+//! with the fields of `ScoreVcfRequest`, e.g. `{"scoring_id": "height", "vcf_path":
+//! "/data/cohort.vcf.gz", "dosage": true, "model": "dominant"}`. The command is both the
+//! flight's descriptor and its ticket: `GetFlightInfo` and `GetSchema` describe the table
+//! without scoring anything, and `DoGet` scores the VCF, sending each block of samples as
+//! a record batch as soon as it is scored.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_ipc::writer::{self, DictionaryTracker, EncodedData, IpcDataGenerator, IpcWriteOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use crate::common::{read_sample_names, EffectWeights, Model};
+use crate::grpc::{send, status};
+use crate::resources;
+use crate::scorer::{SampleScore, Scorer};
+
+pub mod proto {
+    tonic::include_proto!("arrow.flight.protocol");
+}
+
+use proto::flight_descriptor::DescriptorType;
+use proto::flight_service_server::{FlightService, FlightServiceServer};
+
+/// Samples per record batch at most, whatever the block size, to stay well under the
+/// message size limits of gRPC clients.
+const MAX_BATCH_ROWS: usize = 65_536;
+
+type DataStream = ReceiverStream<std::result::Result<proto::FlightData, Status>>;
+
+/// What to score: the JSON of a descriptor's `cmd` or of a ticket.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Command {
+    scoring_id: String,
+    vcf_path: String,
+    #[serde(default)]
+    dosage: bool,
+    /// `additive` (the default), `dominant` or `recessive`
+    #[serde(default)]
+    model: Option<String>,
+    /// Samples scored per pass over the VCF; 0 sizes blocks to the server's memory limit
+    #[serde(default)]
+    block_size: usize,
+}
+
+pub struct Service {
+    scoring: BTreeMap<String, Arc<EffectWeights>>,
+}
+
+/// The Flight service for the scoring files in `scoring`, by ID.
+pub fn server(scoring: BTreeMap<String, Arc<EffectWeights>>) -> FlightServiceServer<Service> {
+    FlightServiceServer::new(Service { scoring })
+}
+
+impl Service {
+    /// The command in `json`, with a scorer for it.
+    fn parse(&self, json: &[u8]) -> std::result::Result<(Command, Scorer), Status> {
+        let command: Command =
+            serde_json::from_slice(json).map_err(|e| Status::invalid_argument(format!("invalid command: {}", e)))?;
+        let weights = self
+            .scoring
+            .get(&command.scoring_id)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("unknown scoring file {:?}", command.scoring_id)))?;
+        let model = match &command.model {
+            Some(model) => Model::from_str(model, true).map_err(|e| Status::invalid_argument(format!("model: {}", e)))?,
+            None => Model::Additive,
+        };
+        let scorer = Scorer::from_shared(weights).dosage(command.dosage).model(model);
+        Ok((command, scorer))
+    }
+
+    fn descriptor_command<'a>(&self, descriptor: &'a proto::FlightDescriptor) -> std::result::Result<&'a [u8], Status> {
+        match descriptor.r#type() {
+            DescriptorType::Cmd => Ok(&descriptor.cmd),
+            _ => Err(Status::invalid_argument("flights are named by a JSON command (a CMD descriptor), not a path")),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for Service {
+    type DoGetStream = DataStream;
+
+    async fn get_flight_info(
+        &self,
+        request: Request<proto::FlightDescriptor>,
+    ) -> std::result::Result<Response<proto::FlightInfo>, Status> {
+        let descriptor = request.into_inner();
+        let cmd = self.descriptor_command(&descriptor)?.to_vec();
+        let (command, _) = self.parse(&cmd)?;
+        let samples = tokio::task::spawn_blocking(move || read_sample_names(&command.vcf_path))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(status)?;
+        let endpoint = proto::FlightEndpoint { ticket: Some(proto::Ticket { ticket: cmd }), location: Vec::new(), app_metadata: Vec::new() };
+        Ok(Response::new(proto::FlightInfo {
+            schema: encapsulated_schema(),
+            flight_descriptor: Some(descriptor),
+            endpoint: vec![endpoint],
+            total_records: samples.len() as i64,
+            total_bytes: -1,
+            ordered: true,
+            app_metadata: Vec::new(),
+        }))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<proto::FlightDescriptor>,
+    ) -> std::result::Result<Response<proto::SchemaResult>, Status> {
+        let descriptor = request.into_inner();
+        self.parse(self.descriptor_command(&descriptor)?)?;
+        Ok(Response::new(proto::SchemaResult { schema: encapsulated_schema() }))
+    }
+
+    async fn do_get(&self, request: Request<proto::Ticket>) -> std::result::Result<Response<DataStream>, Status> {
+        let (command, scorer) = self.parse(&request.into_inner().ticket)?;
+        let block_size = match command.block_size {
+            0 => resources::current().sample_block,
+            n => n,
+        };
+        let batch_rows = block_size.min(MAX_BATCH_ROWS);
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            let stream = match scorer.stream(&command.vcf_path, block_size) {
+                Ok(stream) => stream,
+                Err(e) => return send(&tx, Err(status(e))),
+            };
+            let mut encoder = Encoder::new();
+            send(&tx, Ok(encoder.schema()));
+            let mut rows = Vec::with_capacity(batch_rows);
+            for result in stream {
+                match result {
+                    Ok(sample) => rows.push(sample),
+                    Err(e) => return send(&tx, Err(status(e))),
+                }
+                if rows.len() == batch_rows {
+                    send(&tx, encoder.batch(&rows));
+                    rows.clear();
+                }
+            }
+            if !rows.is_empty() {
+                send(&tx, encoder.batch(&rows));
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// The columns of a score table; counts are signed, as Spark has no unsigned integers.
+fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("sample_name", DataType::Utf8, false),
+        Field::new("polygenic_score", DataType::Float64, false),
+        Field::new("total_variants", DataType::Int64, false),
+        Field::new("matched_variants", DataType::Int64, false),
+    ]))
+}
+
+/// The schema as `FlightInfo` and `SchemaResult` carry it: an encapsulated IPC message.
+fn encapsulated_schema() -> Vec<u8> {
+    let options = IpcWriteOptions::default();
+    let encoded = IpcDataGenerator::default().schema_to_bytes_with_dictionary_tracker(&schema(), &mut DictionaryTracker::new(false), &options);
+    let mut bytes = Vec::new();
+    writer::write_message(&mut bytes, encoded, &options).expect("a schema message is always aligned");
+    bytes
+}
+
+/// Encodes one `DoGet` stream: the schema, then record batches.
+struct Encoder {
+    generator: IpcDataGenerator,
+    dictionaries: DictionaryTracker,
+    options: IpcWriteOptions,
+    schema: SchemaRef,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Encoder {
+            generator: IpcDataGenerator::default(),
+            dictionaries: DictionaryTracker::new(false),
+            options: IpcWriteOptions::default(),
+            schema: schema(),
+        }
+    }
+
+    fn schema(&mut self) -> proto::FlightData {
+        flight_data(self.generator.schema_to_bytes_with_dictionary_tracker(&self.schema, &mut self.dictionaries, &self.options))
+    }
+
+    fn batch(&mut self, samples: &[SampleScore]) -> std::result::Result<proto::FlightData, Status> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(samples.iter().map(|s| s.name.as_str()))),
+            Arc::new(Float64Array::from_iter_values(samples.iter().map(|s| s.score))),
+            Arc::new(Int64Array::from_iter_values(samples.iter().map(|s| s.total_variants as i64))),
+            Arc::new(Int64Array::from_iter_values(samples.iter().map(|s| s.matched_variants as i64))),
+        ];
+        let batch = RecordBatch::try_new(Arc::clone(&self.schema), columns).map_err(|e| Status::internal(e.to_string()))?;
+        // The columns have no dictionaries to send ahead of the batch
+        let (_, encoded) = self
+            .generator
+            .encoded_batch(&batch, &mut self.dictionaries, &self.options)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(flight_data(encoded))
+    }
+}
+
+fn flight_data(encoded: EncodedData) -> proto::FlightData {
+    proto::FlightData {
+        flight_descriptor: None,
+        data_header: encoded.ipc_message,
+        app_metadata: Vec::new(),
+        data_body: encoded.arrow_data,
+    }
+}
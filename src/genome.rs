@@ -0,0 +1,181 @@
+//! Reference genome builds, and guarding against scoring a VCF of one build with weights
+//! positioned on another, which otherwise only shows as a near-zero match rate.
+//!
+//! A scoring file's build is taken from its `#HmPOS_build=` (in a harmonized file) or
+//! `#genome_build=` header line (as PGS Catalog files have), else from `--genome-build`, else inferred from its
+//! positions. A VCF's build is taken from the lengths of its `##contig` lines, else from
+//! its `##reference` line, else inferred from the positions of its first records.
+//! Positions can only rule a build out: one beyond the end of a chromosome in one build
+//! but not the other points to the other.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use clap::ValueEnum;
+use crate::aliases;
+use crate::common::{open_scoring_file, trim_line};
+use crate::error::{Error, Result};
+
+/// Records of the VCF whose positions are probed when its header names no build.
+const PROBE_RECORDS: usize = 10_000;
+
+/// Reference genome build of the scoring file's positions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GenomeBuild {
+    /// GRCh37 / hg19
+    Grch37,
+    /// GRCh38 / hg38
+    Grch38,
+}
+
+impl fmt::Display for GenomeBuild {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GenomeBuild::Grch37 => "GRCh37",
+            GenomeBuild::Grch38 => "GRCh38",
+        })
+    }
+}
+
+/// What to do when the scoring file and the VCF are on different builds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BuildMismatch {
+    /// Refuse to score
+    Error,
+    /// Print a warning and score anyway
+    Warn,
+}
+
+/// Chromosome lengths in GRCh37 and GRCh38, by chromosome without "chr".
+const LENGTHS: &[(&str, u64, u64)] = &[
+    ("1", 249_250_621, 248_956_422),
+    ("2", 243_199_373, 242_193_529),
+    ("3", 198_022_430, 198_295_559),
+    ("4", 191_154_276, 190_214_555),
+    ("5", 180_915_260, 181_538_259),
+    ("6", 171_115_067, 170_805_979),
+    ("7", 159_138_663, 159_345_973),
+    ("8", 146_364_022, 145_138_636),
+    ("9", 141_213_431, 138_394_717),
+    ("10", 135_534_747, 133_797_422),
+    ("11", 135_006_516, 135_086_622),
+    ("12", 133_851_895, 133_275_309),
+    ("13", 115_169_878, 114_364_328),
+    ("14", 107_349_540, 107_043_718),
+    ("15", 102_531_392, 101_991_189),
+    ("16", 90_354_753, 90_338_345),
+    ("17", 81_195_210, 83_257_441),
+    ("18", 78_077_248, 80_373_285),
+    ("19", 59_128_983, 58_617_616),
+    ("20", 63_025_520, 64_444_167),
+    ("21", 48_129_895, 46_709_983),
+    ("22", 51_304_566, 50_818_468),
+    ("X", 155_270_560, 156_040_895),
+    ("Y", 59_373_566, 57_227_415),
+];
+
+/// A build and how it was determined, e.g. `contig lengths`.
+#[derive(Clone, Debug)]
+pub struct Detected {
+    pub build: GenomeBuild,
+    pub evidence: &'static str,
+}
+
+/// The build a name such as `GRCh37`, `hg19`, `b37` or `hs37d5` stands for, if any.
+pub fn parse_name(name: &str) -> Option<GenomeBuild> {
+    let name = name.to_ascii_lowercase();
+    if ["grch37", "hg19", "b37", "hs37d5", "human_g1k_v37"].iter().any(|known| name.contains(known)) || name == "37" {
+        Some(GenomeBuild::Grch37)
+    } else if ["grch38", "hg38", "b38", "hs38"].iter().any(|known| name.contains(known)) || name == "38" {
+        Some(GenomeBuild::Grch38)
+    } else {
+        None
+    }
+}
+
+/// The build `#HmPOS_build=` or else `#genome_build=` declares in the scoring file's header.
+pub fn declared_build(scoring_path: &str) -> Result<Option<GenomeBuild>> {
+    let reader = open_scoring_file(scoring_path)?;
+    let mut declared = None;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::read(scoring_path, index + 1, e))?;
+        let Some(comment) = trim_line(&line).strip_prefix('#') else {
+            break;
+        };
+        match comment.split_once('=') {
+            // Harmonized files record the build their positions were lifted to, which is
+            // the one they are read on, after the build of the original positions
+            Some(("HmPOS_build", value)) if parse_name(value.trim()).is_some() => return Ok(parse_name(value.trim())),
+            Some(("genome_build", value)) if declared.is_none() => declared = parse_name(value.trim()),
+            _ => {}
+        }
+    }
+    Ok(declared)
+}
+
+/// The build positions `(chr, pos)` are consistent with, if only one.
+pub fn infer_from_positions<'a>(positions: impl IntoIterator<Item = (&'a str, u64)>) -> Option<GenomeBuild> {
+    let lengths: HashMap<&str, (u64, u64)> = LENGTHS.iter().map(|&(chr, grch37, grch38)| (chr, (grch37, grch38))).collect();
+    let (mut fits_grch37, mut fits_grch38) = (true, true);
+    for (chr, pos) in positions {
+        if let Some(&(grch37, grch38)) = lengths.get(chr.trim_start_matches("chr")) {
+            fits_grch37 &= pos <= grch37;
+            fits_grch38 &= pos <= grch38;
+        }
+    }
+    match (fits_grch37, fits_grch38) {
+        (true, false) => Some(GenomeBuild::Grch37),
+        (false, true) => Some(GenomeBuild::Grch38),
+        _ => None,
+    }
+}
+
+/// The build of the VCF at `path`, read from `reader`, from its header or its first records.
+pub fn detect_vcf(path: &str, mut reader: impl BufRead) -> Result<Option<Detected>> {
+    let mut line = String::new();
+    let mut line_number = 0;
+    // Votes of the recognized ##contig lengths
+    let (mut grch37, mut grch38) = (0, 0);
+    let mut reference = None;
+    // Last position probed on each chromosome
+    let mut positions: HashMap<String, u64> = HashMap::new();
+    let mut probed = 0;
+    loop {
+        line.clear();
+        line_number += 1;
+        if reader.read_line(&mut line).map_err(|e| Error::read(path, line_number, e))? == 0 {
+            break;
+        }
+        if let Some(contig) = line.strip_prefix("##contig=<") {
+            let field = |name: &str| contig.trim_end().trim_end_matches('>').split(',').find_map(|f| f.strip_prefix(name)).map(str::to_string);
+            if let (Some(id), Some(length)) = (field("ID="), field("length=").and_then(|l| l.parse::<u64>().ok())) {
+                if let Some(&(_, len37, len38)) = LENGTHS.iter().find(|(chr, ..)| *chr == aliases::canonical(&id)) {
+                    grch37 += usize::from(length == len37);
+                    grch38 += usize::from(length == len38);
+                }
+            }
+        } else if let Some(value) = line.strip_prefix("##reference=").or_else(|| line.strip_prefix("##assembly=")) {
+            reference = reference.or(parse_name(value.trim_end()));
+        } else if !line.starts_with('#') {
+            if grch37 != grch38 || reference.is_some() || probed == PROBE_RECORDS {
+                break;
+            }
+            probed += 1;
+            let mut fields = line.split('\t');
+            if let (Some(chr), Some(Ok(pos))) = (fields.next(), fields.next().map(str::parse::<u64>)) {
+                let last = positions.entry(chr.to_string()).or_default();
+                *last = (*last).max(pos);
+            }
+        }
+    }
+    Ok(if grch37 > grch38 {
+        Some(Detected { build: GenomeBuild::Grch37, evidence: "contig lengths" })
+    } else if grch38 > grch37 {
+        Some(Detected { build: GenomeBuild::Grch38, evidence: "contig lengths" })
+    } else if let Some(build) = reference {
+        Some(Detected { build, evidence: "##reference line" })
+    } else {
+        infer_from_positions(positions.iter().map(|(chr, pos)| (chr.as_str(), *pos)))
+            .map(|build| Detected { build, evidence: "positions beyond the other build's chromosome ends" })
+    })
+}
@@ -0,0 +1,218 @@
+//! `speedscore grpc`: the gRPC service defined in `proto/speedscore.proto`, compiled
+//! with `--features grpc`. Streamed genotypes are scored through the same
+//! `GenotypeSource` core as VCFs; the generated client is exported as `proto` for Rust callers.
+//! With `--features flight`, the same server also speaks Arrow Flight; see [`crate::flight`].
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+use crate::aliases;
+use crate::common::{load_scoring_file, split_scoring_spec, EffectWeights, GrpcArgs, Model};
+use crate::error::{Error, NonFinite, Result};
+use crate::resources;
+use crate::scorer::{SampleScore, Scorer};
+use crate::source::{GenotypeSource, Variant};
+
+pub mod proto {
+    tonic::include_proto!("speedscore.v1");
+}
+
+use proto::genotype_message::Kind;
+use proto::scoring_server::{Scoring, ScoringServer};
+
+type ScoreStream = ReceiverStream<std::result::Result<proto::SampleScore, Status>>;
+
+/// Start the service and block serving requests.
+pub fn run(args: &GrpcArgs) -> Result<()> {
+    let mut scoring = BTreeMap::new();
+    for spec in &args.scoring {
+        let (id, path) = split_scoring_spec(spec)?;
+        let (weights, _) = load_scoring_file(path, NonFinite::default(), None)?;
+        println!("Registered scoring file {} ({} variants) as {}", path, weights.len(), id);
+        scoring.insert(id.to_string(), Arc::new(weights));
+    }
+    let addr = args.bind.parse().map_err(|_| Error::invalid(&args.bind, "not a socket address"))?;
+
+    let runtime = Runtime::new().map_err(|e| Error::io("tokio runtime", e))?;
+    println!("Listening on {}", args.bind);
+    runtime.block_on(async {
+        let router = Server::builder().add_service(ScoringServer::new(Service { scoring: scoring.clone() }));
+        #[cfg(feature = "flight")]
+        let router = router.add_service(crate::flight::server(scoring));
+        router
+            .serve(addr)
+            .await
+            .map_err(|e| Error::invalid(&args.bind, e.to_string()))
+    })
+}
+
+struct Service {
+    scoring: BTreeMap<String, Arc<EffectWeights>>,
+}
+
+impl Service {
+    fn weights(&self, id: &str) -> std::result::Result<Arc<EffectWeights>, Status> {
+        self.scoring
+            .get(id)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("unknown scoring file {:?}", id)))
+    }
+}
+
+#[tonic::async_trait]
+impl Scoring for Service {
+    type ScoreGenotypesStream = ScoreStream;
+    type ScoreVcfStream = ScoreStream;
+
+    async fn score_genotypes(
+        &self,
+        request: Request<Streaming<proto::GenotypeMessage>>,
+    ) -> std::result::Result<Response<ScoreStream>, Status> {
+        let mut inbound = request.into_inner();
+        let start = match inbound.message().await?.and_then(|m| m.kind) {
+            Some(Kind::Start(start)) => start,
+            _ => return Err(Status::invalid_argument("the first message must be `start`")),
+        };
+        let scorer = Scorer::from_shared(self.weights(&start.scoring_id)?).model(model(start.model));
+
+        let (tx, rx) = mpsc::channel(64);
+        let handle = Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let mut source = StreamSource {
+                sample_names: start.sample_names,
+                inbound,
+                handle,
+                pending: Vec::new().into_iter(),
+                current: None,
+            };
+            match scorer.run_source(&mut source) {
+                Ok(results) => results.samples.iter().for_each(|s| send(&tx, Ok(sample(s)))),
+                Err(e) => send(&tx, Err(status(e))),
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn score_vcf(
+        &self,
+        request: Request<proto::ScoreVcfRequest>,
+    ) -> std::result::Result<Response<ScoreStream>, Status> {
+        let request = request.into_inner();
+        let scorer = Scorer::from_shared(self.weights(&request.scoring_id)?)
+            .dosage(request.dosage)
+            .model(model(request.model));
+        let block_size = match request.block_size {
+            0 => resources::current().sample_block,
+            n => n as usize,
+        };
+
+        let (tx, rx) = mpsc::channel(64);
+        tokio::task::spawn_blocking(move || match scorer.stream(&request.vcf_path, block_size) {
+            Ok(stream) => {
+                for result in stream {
+                    send(&tx, result.map(|s| sample(&s)).map_err(status));
+                }
+            }
+            Err(e) => send(&tx, Err(status(e))),
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Genotypes arriving on a client stream. Runs on a blocking thread and waits
+/// for each batch through the runtime handle.
+struct StreamSource {
+    sample_names: Vec<String>,
+    inbound: Streaming<proto::GenotypeMessage>,
+    handle: Handle,
+    pending: std::vec::IntoIter<proto::Variant>,
+    current: Option<proto::Variant>,
+}
+
+impl GenotypeSource for StreamSource {
+    fn sample_names(&self) -> &[String] {
+        &self.sample_names
+    }
+
+    fn next_variant(&mut self) -> Result<Option<Variant>> {
+        loop {
+            if let Some(record) = self.pending.next() {
+                let variant = Variant {
+                    chr: aliases::canonical(&record.chr).to_string(),
+                    pos: record.pos,
+                    ref_allele: record.ref_allele.clone(),
+                    alt_allele: record.alt_allele.clone(),
+                    chr_prefixed: record.chr.starts_with("chr"),
+                };
+                self.current = Some(record);
+                return Ok(Some(variant));
+            }
+            let message = self
+                .handle
+                .block_on(self.inbound.message())
+                .map_err(|s| Error::invalid("genotype stream", s.message()))?;
+            match message.and_then(|m| m.kind) {
+                Some(Kind::Variants(batch)) => self.pending = batch.variants.into_iter(),
+                Some(Kind::Start(_)) => return Err(Error::invalid("genotype stream", "`start` sent twice")),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn alt_dosages(&mut self, out: &mut [Option<f64>]) -> Result<()> {
+        let Some(record) = &self.current else {
+            return Ok(());
+        };
+        if record.alt_dosages.len() != out.len() {
+            return Err(Error::invalid(
+                "genotype stream",
+                format!(
+                    "variant {}:{} has {} dosages for {} samples",
+                    record.chr,
+                    record.pos,
+                    record.alt_dosages.len(),
+                    out.len()
+                ),
+            ));
+        }
+        for (slot, dosage) in out.iter_mut().zip(&record.alt_dosages) {
+            *slot = if dosage.is_nan() { None } else { Some(*dosage) };
+        }
+        Ok(())
+    }
+}
+
+fn model(model: i32) -> Model {
+    match proto::Model::try_from(model) {
+        Ok(proto::Model::Dominant) => Model::Dominant,
+        Ok(proto::Model::Recessive) => Model::Recessive,
+        _ => Model::Additive,
+    }
+}
+
+fn sample(sample: &SampleScore) -> proto::SampleScore {
+    proto::SampleScore {
+        name: sample.name.clone(),
+        score: sample.score,
+        total_variants: sample.total_variants,
+        matched_variants: sample.matched_variants,
+    }
+}
+
+pub(crate) fn status(e: Error) -> Status {
+    match &e {
+        Error::Io { source, .. } if source.kind() == io::ErrorKind::NotFound => Status::not_found(e.to_string()),
+        Error::ThreadPool(_) => Status::internal(e.to_string()),
+        _ => Status::invalid_argument(e.to_string()),
+    }
+}
+
+/// Forward one item to the client; a closed channel just means the client went away.
+pub(crate) fn send<T>(tx: &mpsc::Sender<std::result::Result<T, Status>>, item: std::result::Result<T, Status>) {
+    let _ = tx.blocking_send(item);
+}
@@ -0,0 +1,47 @@
+//! Gene-environment interaction terms.
+//!
+//! Scoring files may carry an `interaction_weight` column, as published GxE-augmented
+//! risk models do. Given each sample's exposure `E` (`--environment`), every sample also
+//! gets the interaction score `E * sum(interaction_weight * g)` over its scoring variants,
+//! and the GxE score: its polygenic score plus the interaction score. The genotype term
+//! `g` is the model-coded effect-allele count, centered and mean-imputed like the score.
+
+use std::collections::HashMap;
+use crate::common::EffectWeights;
+use crate::error::{Error, Result};
+use crate::evaluate::Table;
+
+/// Each sample's exposure, by sample ID.
+pub type Environments = HashMap<String, f64>;
+
+/// Read sample exposures from column `column` of a sample table with an IID, #IID or
+/// Sample_Name column. Blank and `NA` values leave a sample's exposure unknown.
+pub fn load_environments(path: &str, column: &str) -> Result<Environments> {
+    let table = Table::read(path)?;
+    let (ids, value_index) = (table.id_column()?, table.column(column)?);
+    let mut environments = HashMap::new();
+    for (index, row) in table.rows.iter().enumerate() {
+        let value = &row[value_index];
+        if value.is_empty() || value.eq_ignore_ascii_case("NA") || value == "." {
+            continue;
+        }
+        let value = value.parse::<f64>().ok().filter(|v| v.is_finite()).ok_or_else(|| {
+            Error::parse(path, index + 2, format!("Invalid exposure '{}'", value), &row.join("\t"))
+        })?;
+        if environments.insert(row[ids].clone(), value).is_some() {
+            return Err(Error::invalid(path, format!("duplicate sample '{}'", row[ids])));
+        }
+    }
+    if environments.is_empty() {
+        return Err(Error::invalid(path, format!("no sample has a value in column '{}'", column)));
+    }
+    Ok(environments)
+}
+
+/// Number of scoring variants with a nonzero interaction weight; an error when there are none.
+pub fn check_weights(effect_weights: &EffectWeights, scoring_path: &str) -> Result<usize> {
+    match effect_weights.values().filter(|w| w.interaction != 0.0).count() {
+        0 => Err(Error::invalid(scoring_path, "--environment needs an interaction_weight column with nonzero weights")),
+        count => Ok(count),
+    }
+}
@@ -0,0 +1,70 @@
+//! Reading VCFs and BCFs through htslib (`--htslib`), for sites that standardize on it.
+//!
+//! htslib opens plain, gzipped and bgzipped VCF and BCF alike, and inflates BGZF on
+//! `io_threads` threads of its own. It parses each record, which is then handed to the
+//! scan as VCF text, so everything after reading works as it does without htslib. A
+//! record htslib cannot parse stops the run with its error: htslib does not say where the
+//! next record starts, so `--on-error` cannot skip it.
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::{ptr, slice};
+use rust_htslib::bcf::{self, header::HeaderView, Read as _};
+use rust_htslib::htslib;
+use crate::error::{Error, IoContext, Result};
+use crate::resources;
+
+/// A VCF or BCF as VCF text: the header, then one line per record.
+struct VcfText {
+    reader: bcf::Reader,
+    record: bcf::Record,
+    /// The text of the header or the last record read, and how much of it has been read
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+/// Open the VCF or BCF at `path` with htslib, sized by [`resources::current`].
+pub fn open(path: &str) -> Result<Box<dyn BufRead + Send>> {
+    let tuning = resources::current();
+    let mut reader = bcf::Reader::from_path(path).map_err(io::Error::other).with_path(path)?;
+    if reader.header().inner.is_null() {
+        return Err(Error::invalid(path, "htslib could not read the VCF header"));
+    }
+    if tuning.io_threads > 0 {
+        reader.set_threads(tuning.io_threads).map_err(io::Error::other).with_path(path)?;
+    }
+    let header = header_text(reader.header()).ok_or_else(|| Error::invalid(path, "htslib could not format the VCF header"))?;
+    let record = reader.empty_record();
+    Ok(Box::new(BufReader::with_capacity(tuning.read_buffer, VcfText { reader, record, pending: header, pos: 0 })))
+}
+
+/// The header as VCF text, as htslib writes it.
+fn header_text(header: &HeaderView) -> Option<Vec<u8>> {
+    let mut text = htslib::kstring_t { l: 0, m: 0, s: ptr::null_mut() };
+    // SAFETY: the header stays valid while the reader it belongs to is borrowed, and
+    // htslib allocates `text.s`, which is copied out and then freed
+    unsafe {
+        let formatted = htslib::bcf_hdr_format(header.inner, 0, &mut text) == 0;
+        let bytes = (!text.s.is_null()).then(|| slice::from_raw_parts(text.s as *const u8, text.l).to_vec());
+        htslib::free(text.s.cast());
+        bytes.filter(|_| formatted)
+    }
+}
+
+impl Read for VcfText {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.pending.len() {
+            match self.reader.read(&mut self.record) {
+                None => return Ok(0),
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+                Some(Ok(())) => {
+                    self.pending = self.record.to_vcf_string().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?.into_bytes();
+                    self.pos = 0;
+                }
+            }
+        }
+        let read = buf.len().min(self.pending.len() - self.pos);
+        buf[..read].copy_from_slice(&self.pending[self.pos..self.pos + read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
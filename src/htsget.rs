@@ -0,0 +1,340 @@
+//! Scoring the variants an htsget server holds (`--htsget`), as GA4GH data repositories
+//! serve them: an endpoint such as `https://htsget.example.org/variants/NA12878`.
+//!
+//! htsget answers a request for a region of a dataset with a ticket: a list of URLs whose
+//! data, one after another, make up a bgzipped VCF of the records there, the header and
+//! then the BGZF blocks the records lie in. The scan asks for the stretches of each
+//! chromosome holding scoring variants, splitting one where they are more than [`GAP`]
+//! apart, and reads them in header order. A block also holds records just outside its
+//! region, which are dropped, so no record is read twice. The checks before the scan ask
+//! for the whole dataset, once, and read only its header and first blocks; the scan takes
+//! the header from them.
+//!
+//! Chromosomes are asked for by their names in the dataset's `##contig` lines, which may
+//! differ from the scoring file's (`chr1` for `1`); without contigs, by the scoring file's
+//! names. A token in `$SPEEDSCORE_HTSGET_TOKEN` is sent with each request for a ticket as
+//! its bearer token. Requires a build with `--features remote`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Read};
+use crate::aliases;
+use crate::bgzf;
+use crate::common::trim_line;
+use crate::error::{Error, Result};
+use crate::input::InputConfig;
+#[cfg(feature = "remote")]
+use crate::error::IoContext;
+
+/// Scoring variants on a chromosome closer than this, in bases, share a region.
+pub const GAP: u64 = 1_000_000;
+
+/// Whether `path` is the endpoint given with `--htsget`.
+pub fn is_endpoint(path: &str, config: &InputConfig) -> bool {
+    config.htsget.as_deref() == Some(path)
+}
+
+/// A stretch of a chromosome as htsget names it: 0-based, from `start` up to `end`.
+struct Region {
+    name: String,
+    start: u64,
+    end: u64,
+}
+
+impl Region {
+    fn holds(&self, chr: &str, pos: u64) -> bool {
+        chr == self.name && pos > self.start && pos <= self.end
+    }
+}
+
+/// The regions holding `positions`, in the order of `contigs`, the dataset's chromosomes.
+/// Chromosomes it does not declare are left out, unless it declares none.
+fn regions<'a>(positions: impl IntoIterator<Item = &'a (String, u64)>, contigs: &[String]) -> Vec<Region> {
+    let mut wanted: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+    for (chr, pos) in positions {
+        wanted.entry(chr.as_str()).or_default().push(*pos);
+    }
+    let declared: HashMap<&str, (usize, &str)> = contigs.iter().enumerate().map(|(order, id)| (aliases::canonical(id), (order, id.as_str()))).collect();
+    let mut chromosomes: Vec<(usize, &str, Vec<u64>)> = wanted
+        .into_iter()
+        .filter_map(|(chr, positions)| match declared.get(chr) {
+            Some(&(order, name)) => Some((order, name, positions)),
+            None => contigs.is_empty().then_some((0, chr, positions)),
+        })
+        .collect();
+    chromosomes.sort_by_key(|&(order, ..)| order);
+
+    let mut regions = Vec::new();
+    for (_, name, mut positions) in chromosomes {
+        positions.sort_unstable();
+        let mut region: Option<Region> = None;
+        for pos in positions {
+            if let Some(region) = region.as_mut().filter(|region| pos <= region.end + GAP) {
+                region.end = pos;
+                continue;
+            }
+            regions.extend(region.replace(Region { name: name.to_string(), start: pos.saturating_sub(1), end: pos }));
+        }
+        regions.extend(region);
+    }
+    regions
+}
+
+/// The raw data of the whole dataset at `endpoint`, for [`crate::stream`].
+pub fn stream(endpoint: &str) -> Result<Box<dyn Read + Send>> {
+    fetch(endpoint, None)
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch(endpoint: &str, _region: Option<&Region>) -> Result<Box<dyn Read + Send>> {
+    Err(Error::invalid(endpoint, "htsget is not available in this build; rebuild with --features remote"))
+}
+
+#[cfg(feature = "remote")]
+fn fetch(endpoint: &str, region: Option<&Region>) -> Result<Box<dyn Read + Send>> {
+    client::ticket(endpoint, region).with_path(endpoint)
+}
+
+/// The dataset at the htsget `endpoint` as VCF text: its header, read from `whole`, the
+/// start of the whole dataset, then its records in the regions around `positions`.
+pub fn open<'a>(endpoint: &str, mut whole: impl BufRead, positions: impl IntoIterator<Item = &'a (String, u64)>) -> Result<Box<dyn BufRead + Send>> {
+    let mut header = String::new();
+    let mut contigs = Vec::new();
+    let mut line_number = 0;
+    loop {
+        let start = header.len();
+        line_number += 1;
+        if whole.read_line(&mut header).map_err(|e| Error::read(endpoint, line_number, e))? == 0 {
+            return Err(Error::invalid(endpoint, "VCF header (#CHROM line) not found"));
+        }
+        let line = trim_line(&header[start..]);
+        if let Some(contig) = line.strip_prefix("##contig=<") {
+            if let Some(id) = contig.trim_end_matches('>').split(',').find_map(|field| field.trim().strip_prefix("ID=")) {
+                contigs.push(id.to_string());
+            }
+        } else if line.starts_with("#CHROM") {
+            break;
+        } else if !line.starts_with("##") {
+            return Err(Error::invalid(endpoint, "VCF header (#CHROM line) not found"));
+        }
+    }
+    let regions = regions(positions, &contigs);
+    Ok(Box::new(Reader { endpoint: endpoint.to_string(), regions: regions.into_iter(), current: None, line: String::new(), pending: header.into_bytes(), pos: 0 }))
+}
+
+/// The records of a dataset's regions, one region after another, after its header.
+struct Reader {
+    endpoint: String,
+    regions: std::vec::IntoIter<Region>,
+    /// The region being read, and the VCF text of its ticket
+    current: Option<(Region, Box<dyn BufRead + Send>)>,
+    line: String,
+    /// The header or the last record, and how much of it has been read
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl Reader {
+    /// Put the next record in `pending`; `false` after the last region.
+    fn next_record(&mut self) -> io::Result<bool> {
+        loop {
+            let Some((region, text)) = &mut self.current else {
+                let Some(region) = self.regions.next() else {
+                    return Ok(false);
+                };
+                let data = fetch(&self.endpoint, Some(&region)).map_err(io::Error::other)?;
+                // A region is bgzipped VCF, whatever the endpoint's name
+                let text = bgzf::open_stream(&self.endpoint, data, &InputConfig::default()).map_err(io::Error::other)?;
+                self.current = Some((region, text));
+                continue;
+            };
+            self.line.clear();
+            if text.read_line(&mut self.line)? == 0 {
+                self.current = None;
+                continue;
+            }
+            let mut fields = self.line.splitn(3, '\t');
+            let (Some(chr), Some(pos)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            if chr.starts_with('#') || !pos.parse().is_ok_and(|pos| region.holds(chr, pos)) {
+                continue;
+            }
+            self.pending.clear();
+            self.pending.extend_from_slice(self.line.as_bytes());
+            self.pos = 0;
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = buf.len().min(available.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for Reader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.pending.len() && !self.next_record()? {
+            return Ok(&[]);
+        }
+        Ok(&self.pending[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.pending.len());
+    }
+}
+
+#[cfg(feature = "remote")]
+mod client {
+    use std::collections::HashMap;
+    use std::io::{self, Read};
+    use std::time::Duration;
+    use serde::Deserialize;
+    use super::Region;
+
+    #[derive(Deserialize)]
+    struct Answer {
+        htsget: Ticket,
+    }
+
+    /// A ticket, or the error the server answered with instead.
+    #[derive(Deserialize)]
+    struct Ticket {
+        #[serde(default)]
+        urls: Vec<Url>,
+        error: Option<String>,
+        message: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct Url {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        class: Option<String>,
+    }
+
+    fn agent() -> ureq::Agent {
+        ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(30)).timeout_read(Duration::from_secs(120)).build()
+    }
+
+    /// The data of the ticket for `region` of the dataset at `endpoint`, or for all of it.
+    /// A region's header is left out, as the whole dataset's was read already.
+    pub(super) fn ticket(endpoint: &str, region: Option<&Region>) -> io::Result<Box<dyn Read + Send>> {
+        let agent = agent();
+        let mut request = agent.get(endpoint).query("format", "VCF");
+        if let Some(region) = region {
+            request = request.query("referenceName", &region.name).query("start", &region.start.to_string()).query("end", &region.end.to_string());
+        }
+        if let Some(token) = std::env::var("SPEEDSCORE_HTSGET_TOKEN").ok().filter(|token| !token.is_empty()) {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        let answer: Answer = match request.call() {
+            Ok(response) => serde_json::from_reader(response.into_reader()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("not an htsget ticket: {}", e)))?,
+            Err(ureq::Error::Status(code, response)) => {
+                let status = format!("{} {}", code, response.status_text());
+                // htsget explains its errors in the same JSON as its tickets
+                let message = match serde_json::from_reader::<_, Answer>(response.into_reader()) {
+                    Ok(Answer { htsget: Ticket { error: Some(error), message, .. } }) => format!("{}: {}", error, message.unwrap_or_default()),
+                    _ => status,
+                };
+                return Err(io::Error::other(format!("the htsget server answered {}", message)));
+            }
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        let urls = answer.htsget.urls.into_iter().filter(|url| region.is_none() || url.class.as_deref() != Some("header")).collect::<Vec<_>>();
+        Ok(Box::new(Blocks { agent, urls: urls.into_iter(), current: None }))
+    }
+
+    /// The data of a ticket's URLs, one after another.
+    struct Blocks {
+        agent: ureq::Agent,
+        urls: std::vec::IntoIter<Url>,
+        current: Option<Box<dyn Read + Send>>,
+    }
+
+    impl Read for Blocks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                if let Some(current) = &mut self.current {
+                    let read = current.read(buf)?;
+                    if read > 0 || buf.is_empty() {
+                        return Ok(read);
+                    }
+                    self.current = None;
+                }
+                let Some(url) = self.urls.next() else {
+                    return Ok(0);
+                };
+                self.current = Some(match url.url.strip_prefix("data:") {
+                    Some(data) => Box::new(io::Cursor::new(decode_data(data)?)),
+                    None => {
+                        let mut request = self.agent.get(&url.url);
+                        for (name, value) in &url.headers {
+                            request = request.set(name, value);
+                        }
+                        match request.call() {
+                            Ok(response) => response.into_reader(),
+                            Err(ureq::Error::Status(code, response)) => {
+                                return Err(io::Error::other(format!("{} answered {} {}", url.url, code, response.status_text())));
+                            }
+                            Err(e) => return Err(io::Error::other(e)),
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    /// The bytes of a `data:` URL, after its scheme: base64 or percent-encoded.
+    fn decode_data(data: &str) -> io::Result<Vec<u8>> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "the htsget ticket has a malformed data: URL");
+        let (media_type, data) = data.split_once(',').ok_or_else(invalid)?;
+        if media_type.ends_with(";base64") {
+            return decode_base64(data).ok_or_else(invalid);
+        }
+        let mut bytes = Vec::with_capacity(data.len());
+        let mut rest = data.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            match (byte, tail.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())) {
+                (b'%', Some(decoded)) => {
+                    bytes.push(decoded);
+                    rest = &tail[2..];
+                }
+                _ => {
+                    bytes.push(byte);
+                    rest = tail;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn decode_base64(text: &str) -> Option<Vec<u8>> {
+        let value = |byte: u8| match byte {
+            b'A'..=b'Z' => Some(byte - b'A'),
+            b'a'..=b'z' => Some(byte - b'a' + 26),
+            b'0'..=b'9' => Some(byte - b'0' + 52),
+            b'+' | b'-' => Some(62),
+            b'/' | b'_' => Some(63),
+            _ => None,
+        };
+        let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+        let (mut bits, mut count) = (0u32, 0);
+        for byte in text.bytes().filter(|byte| !byte.is_ascii_whitespace() && *byte != b'=') {
+            bits = bits << 6 | value(byte)? as u32;
+            count += 6;
+            if count >= 8 {
+                count -= 8;
+                bytes.push((bits >> count) as u8);
+            }
+        }
+        Some(bytes)
+    }
+}
@@ -0,0 +1,202 @@
+//! The VCF a run scores, opened for the checks before scoring and for the scan, and the
+//! options that change how it is opened.
+//!
+//! A run looks at the start of its VCF several times before scoring it: for its file
+//! type, build, contigs, sample names and whether it has GT calls. A file is opened again
+//! for each look, and for the scan. Standard input can be read only once, and a streamed
+//! bucket object, URL or htsget dataset is better downloaded once, so [`VcfInput::new`]
+//! reads their header, up to and including the first record, and keeps it:
+//! [`VcfInput::peek`] reads that again, and [`VcfInput::open`] hands the scan the kept
+//! header followed by the rest of the same reader. Several VCFs with the same samples are
+//! one input, read one after another; see [`crate::concat`].
+
+use std::io::{self, BufRead, Read};
+use std::sync::Mutex;
+use crate::bgzf;
+use crate::common::Args;
+use crate::concat;
+use crate::error::{Error, IoContext, Result};
+use crate::stdin;
+use crate::stream;
+
+/// How inputs are opened: what [`crate::bgzf::open`] and the readers it hands files to
+/// need besides a path.
+#[derive(Clone, Debug, Default)]
+pub struct InputConfig {
+    /// Stream `http://` and `https://` URLs instead of downloading them first (`--stream`)
+    pub stream: bool,
+    /// The htsget endpoint read as the VCF (`--htsget`); see [`crate::htsget`]
+    pub htsget: Option<String>,
+    /// Scan VCFs in full, indexed or not (`--no-index`)
+    pub no_index: bool,
+    /// Read VCFs and BCFs through htslib (`--htslib`); see [`crate::hts`]. Ignored in
+    /// builds without the `htslib` feature
+    pub htslib: bool,
+    /// The `.sample` file naming the samples of a BGEN or `.gen` (`--sample`)
+    pub sample_file: Option<String>,
+    /// The chromosome of every variant of a `.gen` (`--gen-chr`)
+    pub gen_chromosome: Option<String>,
+    /// Of several VCFs read as one, how many to open and decompress at once (`--vcf-jobs`);
+    /// see [`crate::concat`]. 0 is taken as 1.
+    pub jobs: usize,
+}
+
+impl InputConfig {
+    /// The input options of a run.
+    pub fn from_args(args: &Args) -> Self {
+        InputConfig {
+            stream: args.stream,
+            htsget: args.htsget.clone(),
+            no_index: args.no_index,
+            htslib: args.htslib,
+            sample_file: args.sample.clone(),
+            gen_chromosome: args.gen_chr.clone(),
+            jobs: args.vcf_jobs,
+        }
+    }
+}
+
+/// The VCF at a path, or standard input or a stream read once, or several VCFs read as one.
+pub struct VcfInput {
+    /// The path, or the paths of several VCFs joined by commas, to name the input in messages
+    path: String,
+    paths: Vec<String>,
+    config: InputConfig,
+    piped: Option<Piped>,
+}
+
+/// An input that can be read only once: its header and first record, and the reader
+/// after them until the scan takes it.
+struct Piped {
+    start: Vec<u8>,
+    rest: Mutex<Option<Box<dyn BufRead + Send>>>,
+}
+
+impl VcfInput {
+    /// The VCF at `path`, opened as `config` says. Standard input (`-`) and streamed
+    /// inputs are opened, and their header read, here.
+    pub fn new(path: &str, config: &InputConfig) -> Result<Self> {
+        let piped = match stdin::is_stdin(path) || stream::is_streamed(path, config) {
+            true => Some(Piped::read(path, bgzf::open(path, config)?)?),
+            false => None,
+        };
+        Ok(VcfInput { path: path.to_string(), paths: vec![path.to_string()], config: config.clone(), piped })
+    }
+
+    /// The VCFs at `paths` as one, or the one VCF if there is only one.
+    pub fn files(paths: Vec<String>, config: &InputConfig) -> Result<Self> {
+        match paths.as_slice() {
+            [] => Err(Error::invalid("--vcf", "no VCF to score")),
+            [path] => Self::new(path, config),
+            _ => Ok(VcfInput { path: paths.join(","), paths, config: config.clone(), piped: None }),
+        }
+    }
+
+    /// The input as messages name it. Of several VCFs this is no file's path; see [`Self::file`].
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The path of the one file, stream or endpoint read, to open or look up an index by;
+    /// `None` for several VCFs read as one.
+    pub fn file(&self) -> Option<&str> {
+        match self.paths.as_slice() {
+            [path] => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The VCFs read as this one input, in order.
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn config(&self) -> &InputConfig {
+        &self.config
+    }
+
+    /// Whether the input is read as it arrives, and so can be scanned only once.
+    pub fn is_piped(&self) -> bool {
+        self.piped.is_some()
+    }
+
+    /// The VCF from its start, for a look before scoring. Of a piped input, only the
+    /// header and first record can be read.
+    pub fn peek(&self) -> Result<Box<dyn BufRead + Send + '_>> {
+        match &self.piped {
+            Some(piped) => Ok(Box::new(&piped.start[..])),
+            None => self.reopen(),
+        }
+    }
+
+    /// The whole VCF, for the scan. A piped input can be scanned once.
+    pub fn open(&self) -> Result<Box<dyn BufRead + Send>> {
+        let Some(piped) = &self.piped else {
+            return self.reopen();
+        };
+        let rest = piped.rest.lock().expect("input lock poisoned").take();
+        let rest = rest.ok_or_else(|| Error::invalid(&self.path, "the input is read as it arrives, and was scanned already"))?;
+        Ok(Box::new(io::Cursor::new(piped.start.clone()).chain(rest)))
+    }
+
+    /// The file or files, opened again from the start.
+    fn reopen(&self) -> Result<Box<dyn BufRead + Send>> {
+        match self.paths.as_slice() {
+            [path] => bgzf::open(path, &self.config),
+            paths => Ok(Box::new(concat::open(paths, None, &self.config)?)),
+        }
+    }
+}
+
+impl Piped {
+    /// Read the lines of `reader` up to and including the first that is neither a `#`
+    /// header line nor empty.
+    fn read(path: &str, mut reader: Box<dyn BufRead + Send>) -> Result<Self> {
+        let mut start = Vec::new();
+        loop {
+            let line_start = start.len();
+            if reader.read_until(b'\n', &mut start).with_path(path)? == 0 {
+                break;
+            }
+            let line = &start[line_start..];
+            if !line.starts_with(b"#") && !line.trim_ascii().is_empty() {
+                break;
+            }
+        }
+        Ok(Piped { start, rest: Mutex::new(Some(reader)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VCF: &str = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\n1\t100\t.\tA\tG\t.\t.\t.\tGT\t0/1\n1\t200\t.\tC\tT\t.\t.\t.\tGT\t1/1\n";
+
+    fn piped() -> VcfInput {
+        let piped = Piped::read(stdin::PATH, Box::new(VCF.as_bytes())).unwrap();
+        VcfInput { path: stdin::PATH.to_string(), paths: vec![stdin::PATH.to_string()], config: InputConfig::default(), piped: Some(piped) }
+    }
+
+    fn read(mut reader: impl BufRead) -> String {
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn peek_reads_the_header_and_first_record() {
+        let input = piped();
+        let first_record = VCF.find("1\t200").unwrap();
+        assert_eq!(read(input.peek().unwrap()), &VCF[..first_record]);
+        assert_eq!(read(input.peek().unwrap()), &VCF[..first_record]);
+    }
+
+    #[test]
+    fn open_reads_everything_once() {
+        let input = piped();
+        read(input.peek().unwrap());
+        assert_eq!(read(input.open().unwrap()), VCF);
+        assert!(input.open().is_err());
+    }
+}
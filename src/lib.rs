@@ -0,0 +1,99 @@
+//! Fast polygenic score calculation from VCF files.
+//!
+//! The command-line tool is a thin wrapper around this library; embedders will
+//! usually only need [`Scorer`].
+
+pub mod aliases;
+pub mod ancestry;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod batch;
+pub mod bcf;
+pub mod bgen;
+pub mod bgzf;
+pub mod catalog;
+pub mod checkpoint;
+pub mod checksum;
+pub mod clump;
+pub mod common;
+pub mod concat;
+pub mod concordance;
+pub mod consumer;
+pub mod contigs;
+pub mod contribution;
+#[cfg(feature = "polars")]
+pub mod dataframe;
+pub mod diagnose;
+pub mod error;
+pub mod evaluate;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filter;
+#[cfg(feature = "flight")]
+pub mod flight;
+pub mod genome;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod gxe;
+#[cfg(feature = "htslib")]
+pub mod hts;
+pub mod htsget;
+pub mod input;
+pub mod liftover;
+pub mod lims;
+pub mod metrics;
+pub mod multi_sample;
+pub mod order;
+pub mod origin;
+pub mod oxford;
+pub mod panel;
+pub mod partition;
+pub mod permutation;
+pub mod pgen;
+pub mod plan;
+pub mod plink;
+pub mod progress;
+pub mod provenance;
+#[cfg(feature = "python")]
+mod python;
+pub mod qc;
+#[cfg(feature = "queue")]
+pub mod queue;
+pub mod reference;
+pub mod regions;
+pub mod remote;
+pub mod rejected;
+pub mod resources;
+mod rng;
+pub mod scale;
+pub mod schema;
+pub mod scorer;
+#[cfg(feature = "server")]
+pub mod serve;
+pub mod sex;
+pub mod simulate;
+pub mod single_sample;
+pub mod sink;
+pub mod source;
+pub mod spans;
+mod stats;
+pub mod stdin;
+pub mod stream;
+#[cfg(feature = "strict-vcf")]
+pub mod strict;
+pub mod tabix;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod thresholding;
+pub mod trio;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod upload;
+pub mod watch;
+
+pub use common::{load_scoring_file, Centering, EffectWeight, EffectWeights, InfoField, Model};
+pub use error::{Error, Result};
+pub use scorer::{SampleScore, SampleScoreStream, ScoreResults, Scorer};
+pub use sink::{OutputFormat, ScoreSink};
+pub use source::{GenotypeSource, Variant, VcfSource};
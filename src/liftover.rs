@@ -0,0 +1,334 @@
+//! Lifting scoring-file positions to another genome build with a UCSC chain file.
+//!
+//! A chain file (such as UCSC's `hg19ToHg38.over.chain.gz`) aligns blocks of the source
+//! build to the target build. Each scoring variant is moved to where the block containing
+//! it maps. Variants in no block are unmapped, and those in blocks of several chains are
+//! multi-mapped; both are dropped, as are variants that land on the same target position
+//! as another. Where a block maps to the reverse strand, the effect allele is
+//! complemented; multi-base alleles there are dropped, as their VCF representation
+//! would change.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::BufRead;
+use crate::common::{create_parent_dirs, EffectWeights};
+use crate::error::{Error, IoContext, Result};
+use crate::source;
+
+/// A variant's (chr without "chr", pos).
+type Key = (String, u64);
+
+/// An ungapped block of a chain: source `[start, end)`, 0-based, and where it starts in
+/// the target.
+#[derive(Clone, Debug)]
+struct Block {
+    start: u64,
+    end: u64,
+    /// Index into [`Chains::targets`]
+    target: usize,
+    target_start: u64,
+}
+
+/// A target chromosome and strand.
+#[derive(Clone, Debug)]
+struct Target {
+    chr: String,
+    size: u64,
+    reverse: bool,
+}
+
+/// The blocks of a chain file, by source chromosome without "chr".
+pub struct Chains {
+    /// Blocks sorted by start, each with the largest end of it and the blocks before it
+    blocks: HashMap<String, Vec<(Block, u64)>>,
+    targets: Vec<Target>,
+}
+
+/// Why a scoring variant was not lifted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Unlifted {
+    Unmapped,
+    MultiMapped,
+    /// Another variant lifts to the same position
+    Collision,
+    /// A multi-base allele on a block mapping to the reverse strand
+    ReverseStrandIndel,
+}
+
+impl Unlifted {
+    fn name(self) -> &'static str {
+        match self {
+            Unlifted::Unmapped => "unmapped",
+            Unlifted::MultiMapped => "multi-mapped",
+            Unlifted::Collision => "collision",
+            Unlifted::ReverseStrandIndel => "reverse-strand-indel",
+        }
+    }
+}
+
+/// What lifting did to the scoring file.
+#[derive(Clone, Debug, Default)]
+pub struct LiftSummary {
+    pub lifted: usize,
+    /// Lifted to the reverse strand, with the effect allele complemented
+    pub reversed: usize,
+    /// Dropped variants: (chr, pos, effect allele, reason)
+    pub dropped: Vec<(String, u64, String, Unlifted)>,
+}
+
+impl LiftSummary {
+    pub fn count(&self, reason: Unlifted) -> usize {
+        self.dropped.iter().filter(|(.., r)| *r == reason).count()
+    }
+}
+
+impl Chains {
+    /// Read a chain file, plain or gzipped.
+    pub fn load(path: &str) -> Result<Self> {
+        let reader = source::decompressed(File::open(path).with_path(path)?).with_path(path)?;
+        let mut blocks: HashMap<String, Vec<(Block, u64)>> = HashMap::new();
+        let mut targets = Vec::new();
+        // Source chromosome and the next block's source and target offsets, within a chain
+        let mut current: Option<(String, u64, u64)> = None;
+        for (index, line) in reader.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.map_err(|e| Error::read(path, line_number, e))?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let number = |text: &str| text.parse::<u64>().map_err(|_| Error::parse(path, line_number, "Invalid number", &line));
+            if fields[0] == "chain" {
+                if fields.len() < 12 {
+                    return Err(Error::parse(path, line_number, "Expected 12 fields in a chain header", &line));
+                }
+                if fields[4] != "+" {
+                    return Err(Error::parse(path, line_number, "Source strand must be '+'", &line));
+                }
+                targets.push(Target { chr: fields[7].trim_start_matches("chr").to_string(), size: number(fields[8])?, reverse: fields[9] == "-" });
+                current = Some((fields[2].trim_start_matches("chr").to_string(), number(fields[5])?, number(fields[10])?));
+                continue;
+            }
+            let Some((chr, source, target)) = current.as_mut() else {
+                return Err(Error::parse(path, line_number, "Alignment data before any chain header", &line));
+            };
+            let size = number(fields[0])?;
+            let block = Block { start: *source, end: *source + size, target: targets.len() - 1, target_start: *target };
+            blocks.entry(chr.clone()).or_default().push((block, 0));
+            match fields[..] {
+                [_, gap_source, gap_target] => {
+                    *source += size + number(gap_source)?;
+                    *target += size + number(gap_target)?;
+                }
+                // The last block of a chain
+                [_] => current = None,
+                _ => return Err(Error::parse(path, line_number, "Expected 1 or 3 fields in an alignment line", &line)),
+            }
+        }
+        for chromosome in blocks.values_mut() {
+            chromosome.sort_by_key(|(block, _)| block.start);
+            let mut max_end = 0;
+            for (block, running) in chromosome.iter_mut() {
+                max_end = max_end.max(block.end);
+                *running = max_end;
+            }
+        }
+        if blocks.is_empty() {
+            return Err(Error::invalid(path, "no chains"));
+        }
+        Ok(Chains { blocks, targets })
+    }
+
+    /// Where 1-based `pos` on `chr` lands: (chr, pos, reverse strand), or why it does not.
+    fn map(&self, chr: &str, pos: u64) -> std::result::Result<(String, u64, bool), Unlifted> {
+        let Some(blocks) = self.blocks.get(chr) else {
+            return Err(Unlifted::Unmapped);
+        };
+        let Some(offset) = pos.checked_sub(1) else {
+            return Err(Unlifted::Unmapped);
+        };
+        let mut hits = blocks[..blocks.partition_point(|(block, _)| block.start <= offset)]
+            .iter()
+            .rev()
+            .take_while(|(_, max_end)| *max_end > offset)
+            .filter(|(block, _)| block.end > offset);
+        let (block, _) = hits.next().ok_or(Unlifted::Unmapped)?;
+        if hits.next().is_some() {
+            return Err(Unlifted::MultiMapped);
+        }
+        let target = &self.targets[block.target];
+        let mapped = block.target_start + (offset - block.start);
+        let mapped = if target.reverse { target.size - 1 - mapped } else { mapped };
+        Ok((target.chr.clone(), mapped + 1, target.reverse))
+    }
+
+    /// Move every scoring variant to its lifted position, dropping the ones that cannot be.
+    pub fn lift(&self, effect_weights: &mut EffectWeights) -> LiftSummary {
+        let mut summary = LiftSummary::default();
+        // Scoring variants (key, reverse strand) by the position they lift to
+        let mut lifted: HashMap<Key, Vec<(Key, bool)>> = HashMap::new();
+        for key in effect_weights.keys() {
+            let allele = &effect_weights[key].effect_allele;
+            match self.map(&key.0, key.1) {
+                Ok((_, _, true)) if allele.len() > 1 => summary.dropped.push((key.0.clone(), key.1, allele.clone(), Unlifted::ReverseStrandIndel)),
+                Ok((chr, pos, reverse)) => lifted.entry((chr, pos)).or_default().push((key.clone(), reverse)),
+                Err(reason) => summary.dropped.push((key.0.clone(), key.1, allele.clone(), reason)),
+            }
+        }
+        let mut moved = EffectWeights::with_capacity(effect_weights.len());
+        for (target, sources) in lifted {
+            if sources.len() > 1 {
+                for (key, _) in sources {
+                    summary.dropped.push((key.0.clone(), key.1, effect_weights[&key].effect_allele.clone(), Unlifted::Collision));
+                }
+                continue;
+            }
+            let (key, reverse) = &sources[0];
+            let mut weight = effect_weights[key].clone();
+            if *reverse {
+                weight.effect_allele = complement(&weight.effect_allele);
+                summary.reversed += 1;
+            }
+            moved.insert(target, weight);
+        }
+        summary.lifted = moved.len();
+        summary.dropped.sort();
+        *effect_weights = moved;
+        summary
+    }
+}
+
+/// Write the variants that were not lifted to `path`: chr, pos, effect allele and reason.
+pub fn write_unlifted(summary: &LiftSummary, path: &str) -> Result<()> {
+    let mut out = String::from("chr_name\tchr_position\teffect_allele\treason\n");
+    for (chr, pos, allele, reason) in &summary.dropped {
+        writeln!(out, "{}\t{}\t{}\t{}", chr, pos, allele, reason.name()).expect("writing to a String cannot fail");
+    }
+    create_parent_dirs(path)?;
+    fs::write(path, out).with_path(path)
+}
+
+/// Complement of a single-base allele; bases other than A, C, G and T are kept.
+pub(crate) fn complement(allele: &str) -> String {
+    allele
+        .chars()
+        .map(|base| match base {
+            'A' => 'T',
+            'T' => 'A',
+            'C' => 'G',
+            'G' => 'C',
+            'a' => 't',
+            't' => 'a',
+            'c' => 'g',
+            'g' => 'c',
+            other => other,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::EffectWeight;
+
+    /// Two chains from chr1: a forward one with a gap, over source `[100, 200)` and
+    /// `[250, 400)`, and a reverse one over `[350, 450)`, landing on the forward chain's target.
+    const CHAIN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/liftover/tiny.chain");
+
+    fn weight(effect_allele: &str) -> EffectWeight {
+        EffectWeight {
+            effect_allele: effect_allele.to_string(),
+            weight: 1.0,
+            frequency: None,
+            dominance: 0.0,
+            p_value: None,
+            standard_error: None,
+            interaction: 0.0,
+            maternal: 0.0,
+            paternal: 0.0,
+        }
+    }
+
+    #[test]
+    fn positions_map_through_forward_and_reverse_blocks() {
+        let chains = Chains::load(CHAIN).unwrap();
+        // The first and last base of the first block, and the second block after the gap
+        assert_eq!(chains.map("1", 101), Ok(("1".to_string(), 201, false)));
+        assert_eq!(chains.map("1", 200), Ok(("1".to_string(), 300, false)));
+        assert_eq!(chains.map("1", 251), Ok(("1".to_string(), 311, false)));
+        // On the reverse strand, counted back from the end of the 1200-base target
+        assert_eq!(chains.map("1", 401), Ok(("1".to_string(), 1200 - 850, true)));
+        assert_eq!(chains.map("1", 450), Ok(("1".to_string(), 1200 - 899, true)));
+        assert_eq!(chains.map("1", 220), Err(Unlifted::Unmapped));
+        assert_eq!(chains.map("1", 0), Err(Unlifted::Unmapped));
+        assert_eq!(chains.map("2", 101), Err(Unlifted::Unmapped));
+        assert_eq!(chains.map("1", 360), Err(Unlifted::MultiMapped));
+        assert_eq!(chains.map("1", 400), Err(Unlifted::MultiMapped));
+    }
+
+    #[test]
+    fn lift_moves_complements_and_drops() {
+        let chains = Chains::load(CHAIN).unwrap();
+        let mut effect_weights: EffectWeights = [
+            (("1", 101), "A"),
+            (("1", 200), "C"),
+            (("1", 220), "G"),
+            // Both land on 1:330
+            (("1", 270), "T"),
+            (("1", 421), "G"),
+            (("1", 360), "A"),
+            (("1", 401), "A"),
+            (("1", 450), "c"),
+            (("1", 430), "AT"),
+            (("2", 50), "A"),
+        ]
+        .into_iter()
+        .map(|((chr, pos), allele)| ((chr.to_string(), pos), weight(allele)))
+        .collect();
+        let summary = chains.lift(&mut effect_weights);
+
+        let mut lifted: Vec<(&str, u64, &str)> = effect_weights.iter().map(|((chr, pos), weight)| (chr.as_str(), *pos, weight.effect_allele.as_str())).collect();
+        lifted.sort();
+        assert_eq!(lifted, [("1", 201, "A"), ("1", 300, "C"), ("1", 301, "g"), ("1", 350, "T")]);
+        assert_eq!((summary.lifted, summary.reversed), (4, 2));
+        assert_eq!((summary.count(Unlifted::Unmapped), summary.count(Unlifted::Collision)), (2, 2));
+        assert_eq!((summary.count(Unlifted::MultiMapped), summary.count(Unlifted::ReverseStrandIndel)), (1, 1));
+
+        let path = std::env::temp_dir().join(format!("speedscore-unlifted-{}.tsv", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_unlifted(&summary, path).unwrap();
+        let written = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            written,
+            "chr_name\tchr_position\teffect_allele\treason\n\
+             1\t220\tG\tunmapped\n\
+             1\t270\tT\tcollision\n\
+             1\t360\tA\tmulti-mapped\n\
+             1\t421\tG\tcollision\n\
+             1\t430\tAT\treverse-strand-indel\n\
+             2\t50\tA\tunmapped\n"
+        );
+    }
+
+    #[test]
+    fn malformed_chain_files_are_rejected() {
+        let path = std::env::temp_dir().join(format!("speedscore-chain-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let cases = [
+            ("chain 1 chr1 1000 - 0 10 chr1 1000 + 0 10 1\n10\n", "Source strand must be '+'"),
+            ("10\n", "Alignment data before any chain header"),
+            ("chain 1 chr1 1000 + 0 10 chr1 1000 + 0\n", "Expected 12 fields in a chain header"),
+            ("chain 1 chr1 1000 + 0 10 chr1 1000 + 0 10 1\n5 1\n", "Expected 1 or 3 fields in an alignment line"),
+            ("chain 1 chr1 1000 + 0 10 chr1 1000 + 0 10 1\nten\n", "Invalid number"),
+            ("# no chains\n", "no chains"),
+        ];
+        for (text, message) in cases {
+            fs::write(path, text).unwrap();
+            let error = Chains::load(path).err().expect(message).to_string();
+            assert!(error.contains(message), "{}", error);
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+//! Results laid out for import into a LIMS or a REDCap instrument (`--lims-mapping`).
+//!
+//! A JSON mapping file names the table's columns, in order. Each is filled from a field of
+//! the results (the score, its percentile, the variant counts, the sample ID, ...) or holds
+//! a fixed value, such as a REDCap event name or an instrument's `_complete` status. The
+//! sample ID can be rewritten on the way: a regular expression can pull the study's record
+//! ID out of the VCF's sample name, and a prefix, a suffix and a case can be applied.
+//!
+//! ```json
+//! {
+//!   "id": { "pattern": "^S(\\d+)_", "replace": "$1" },
+//!   "columns": [
+//!     { "name": "record_id", "value": "sample_id" },
+//!     { "name": "redcap_event_name", "fixed": "baseline_arm_1" },
+//!     { "name": "prs_score", "value": "score", "decimals": 4 },
+//!     { "name": "prs_complete", "fixed": "2" }
+//!   ]
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use regex::Regex;
+use serde::Deserialize;
+use crate::common::{create_parent_dirs, ScoreSummary};
+use crate::error::{Error, IoContext, Result};
+use crate::scorer::SampleScore;
+use crate::sink::{RunInfo, ScoreSink};
+
+/// Decimals written for fractional fields without a `decimals` of their own, as in the
+/// CSV results.
+const DEFAULT_DECIMALS: usize = 6;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MappingFile {
+    /// A single character; `,` by default
+    #[serde(default)]
+    delimiter: Option<String>,
+    /// Written for fields a sample has no value for
+    #[serde(default)]
+    missing: String,
+    #[serde(default)]
+    id: IdTransform,
+    columns: Vec<ColumnSpec>,
+}
+
+/// How a VCF sample name becomes the `sample_id` written.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct IdTransform {
+    /// Samples whose name does not match are left out
+    pattern: Option<String>,
+    /// What the match is replaced with, with `$1` or `$name` for capture groups; without
+    /// it the whole match is the ID
+    replace: Option<String>,
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    suffix: String,
+    case: Option<Case>,
+}
+
+#[derive(Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+enum Case {
+    Upper,
+    Lower,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColumnSpec {
+    name: String,
+    value: Option<Field>,
+    fixed: Option<String>,
+    /// Decimals of a fractional field
+    decimals: Option<usize>,
+}
+
+/// The fields of the results a column can be filled from.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Field {
+    /// The sample name after the ID transform
+    SampleId,
+    /// The sample name as in the VCF
+    SampleName,
+    Vcf,
+    Scoring,
+    /// UTC date of the run, as YYYY-MM-DD
+    RunDate,
+    Score,
+    TotalVariants,
+    MatchedVariants,
+    ScoringVariants,
+    /// Matched variants as a percentage of the scoring variants
+    MatchRate,
+    DosageSum,
+    ScoreSe,
+    WeightCoverage,
+    RescaledScore,
+    AdjustedScore,
+    CalibratedZ,
+    SexZ,
+    ReferencePercentile,
+    ReferenceZ,
+    TScore,
+    Percentile,
+    RiskCategory,
+}
+
+enum Column {
+    Field { field: Field, decimals: usize },
+    Fixed(String),
+}
+
+/// A checked `--lims-mapping` file.
+pub struct Mapping {
+    delimiter: char,
+    missing: String,
+    pattern: Option<Regex>,
+    replace: Option<String>,
+    prefix: String,
+    suffix: String,
+    case: Option<Case>,
+    names: Vec<String>,
+    columns: Vec<Column>,
+}
+
+impl Mapping {
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path).with_path(path)?;
+        let file: MappingFile = serde_json::from_str(&text).map_err(|e| Error::invalid(path, format!("not a LIMS mapping: {}", e)))?;
+        let delimiter = match file.delimiter.as_deref() {
+            None => ',',
+            Some(text) => {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c != '"' && c != '\n' => c,
+                    _ => return Err(Error::invalid(path, format!("the delimiter must be a single character other than a quote or a line break, not {:?}", text))),
+                }
+            }
+        };
+        if file.columns.is_empty() {
+            return Err(Error::invalid(path, "the mapping has no columns"));
+        }
+        let mut seen = HashSet::new();
+        let mut names = Vec::new();
+        let mut columns = Vec::new();
+        for spec in file.columns {
+            if spec.name.is_empty() || !seen.insert(spec.name.clone()) {
+                return Err(Error::invalid(path, format!("column names must be non-empty and unique, not {:?}", spec.name)));
+            }
+            let column = match (spec.value, spec.fixed) {
+                (Some(field), None) => Column::Field { field, decimals: spec.decimals.unwrap_or(DEFAULT_DECIMALS) },
+                (None, Some(value)) => Column::Fixed(value),
+                _ => return Err(Error::invalid(path, format!("column {:?} needs exactly one of \"value\" and \"fixed\"", spec.name))),
+            };
+            names.push(spec.name);
+            columns.push(column);
+        }
+        let pattern = match &file.id.pattern {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| Error::invalid(path, format!("invalid ID pattern {:?}: {}", pattern, e)))?),
+            None if file.id.replace.is_some() => return Err(Error::invalid(path, "the ID's \"replace\" needs a \"pattern\"")),
+            None => None,
+        };
+        Ok(Mapping {
+            delimiter,
+            missing: file.missing,
+            pattern,
+            replace: file.id.replace,
+            prefix: file.id.prefix,
+            suffix: file.id.suffix,
+            case: file.id.case,
+            names,
+            columns,
+        })
+    }
+
+    /// The ID written for sample `name`; `None` if it does not match the ID pattern.
+    pub fn sample_id(&self, name: &str) -> Option<String> {
+        let id = match &self.pattern {
+            Some(pattern) => {
+                let captures = pattern.captures(name)?;
+                match &self.replace {
+                    Some(replace) => {
+                        let mut id = String::new();
+                        captures.expand(replace, &mut id);
+                        id
+                    }
+                    None => captures[0].to_string(),
+                }
+            }
+            None => name.to_string(),
+        };
+        let id = format!("{}{}{}", self.prefix, id, self.suffix);
+        Some(match self.case {
+            Some(Case::Upper) => id.to_uppercase(),
+            Some(Case::Lower) => id.to_lowercase(),
+            None => id,
+        })
+    }
+
+    /// `field` as a cell: quoted if it holds the delimiter, a quote or a line break.
+    fn cell(&self, field: &str) -> String {
+        match field.contains([self.delimiter, '"', '\n', '\r']) {
+            true => format!("\"{}\"", field.replace('"', "\"\"")),
+            false => field.to_string(),
+        }
+    }
+}
+
+/// Writes each sample as one row of a [`Mapping`]'s table.
+pub struct LimsSink {
+    path: String,
+    out: BufWriter<File>,
+    mapping: Mapping,
+    vcf_path: String,
+    scoring_path: String,
+    scoring_variants: usize,
+    run_date: String,
+    /// Sample each ID written so far came from
+    ids: HashMap<String, String>,
+    /// Samples left out for not matching the ID pattern
+    unmatched: Vec<String>,
+}
+
+impl LimsSink {
+    pub fn create(path: &str, mapping: Mapping, scoring_path: &str, scoring_variants: usize) -> Result<Self> {
+        create_parent_dirs(path)?;
+        let file = File::create(path).with_path(path)?;
+        Ok(LimsSink {
+            path: path.to_string(),
+            out: BufWriter::new(file),
+            mapping,
+            vcf_path: String::new(),
+            scoring_path: scoring_path.to_string(),
+            scoring_variants,
+            run_date: utc_date(SystemTime::now()),
+            ids: HashMap::new(),
+            unmatched: Vec::new(),
+        })
+    }
+
+    fn value(&self, field: Field, decimals: usize, id: &str, sample: &SampleScore) -> String {
+        let number = |value: Option<f64>| value.map(|v| format!("{:.*}", decimals, v));
+        let value = match field {
+            Field::SampleId => Some(id.to_string()),
+            Field::SampleName => Some(sample.name.clone()),
+            Field::Vcf => Some(self.vcf_path.clone()),
+            Field::Scoring => Some(self.scoring_path.clone()),
+            Field::RunDate => Some(self.run_date.clone()),
+            Field::Score => number(Some(sample.score)),
+            Field::TotalVariants => Some(sample.total_variants.to_string()),
+            Field::MatchedVariants => Some(sample.matched_variants.to_string()),
+            Field::ScoringVariants => Some(self.scoring_variants.to_string()),
+            Field::MatchRate => number((self.scoring_variants > 0).then(|| 100.0 * sample.matched_variants as f64 / self.scoring_variants as f64)),
+            Field::DosageSum => number(Some(sample.dosage_sum)),
+            Field::ScoreSe => number(sample.score_se),
+            Field::WeightCoverage => number(sample.weight_coverage),
+            Field::RescaledScore => number(sample.rescaled_score),
+            Field::AdjustedScore => number(sample.adjusted_score),
+            Field::CalibratedZ => number(sample.calibrated_z),
+            Field::SexZ => number(sample.sex_z),
+            Field::ReferencePercentile => number(sample.reference_percentile),
+            Field::ReferenceZ => number(sample.reference_z),
+            Field::TScore => number(sample.t_score),
+            Field::Percentile => number(sample.scale_percentile),
+            Field::RiskCategory => sample.risk_category.clone(),
+        };
+        value.unwrap_or_else(|| self.mapping.missing.clone())
+    }
+}
+
+impl ScoreSink for LimsSink {
+    fn begin(&mut self, run: &RunInfo) -> Result<()> {
+        self.vcf_path = run.vcf_path.to_string();
+        let header: Vec<String> = self.mapping.names.iter().map(|name| self.mapping.cell(name)).collect();
+        writeln!(self.out, "{}", header.join(&self.mapping.delimiter.to_string())).with_path(&self.path)
+    }
+
+    fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+        let Some(id) = self.mapping.sample_id(&sample.name) else {
+            self.unmatched.push(sample.name.clone());
+            return Ok(());
+        };
+        // Rows sharing an ID would overwrite each other on import
+        if let Some(earlier) = self.ids.insert(id.clone(), sample.name.clone()) {
+            return Err(Error::invalid(&self.path, format!("samples {} and {} both map to the ID {:?}", earlier, sample.name, id)));
+        }
+        let row: Vec<String> = self
+            .mapping
+            .columns
+            .iter()
+            .map(|column| match column {
+                Column::Field { field, decimals } => self.value(*field, *decimals, &id, sample),
+                Column::Fixed(value) => value.clone(),
+            })
+            .map(|value| self.mapping.cell(&value))
+            .collect();
+        writeln!(self.out, "{}", row.join(&self.mapping.delimiter.to_string())).with_path(&self.path)
+    }
+
+    fn finish(&mut self, _summary: &ScoreSummary) -> Result<()> {
+        if !self.unmatched.is_empty() {
+            eprintln!(
+                "Warning: {} samples left out of {} for not matching the ID pattern, e.g. {}",
+                self.unmatched.len(),
+                self.path,
+                self.unmatched[0]
+            );
+        }
+        self.out.flush().with_path(&self.path)
+    }
+}
+
+/// The UTC calendar date of `time`, as YYYY-MM-DD.
+fn utc_date(time: SystemTime) -> String {
+    let days = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86_400) as i64;
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's days_from_civil, inverted)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -1,55 +1,1093 @@
-use std::time::Instant;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::time::{Duration, Instant};
 use clap::Parser;
-mod common;
-mod single_sample;
-mod multi_sample;
-use common::{Args, FileType, load_scoring_file, output_results, print_info};
+use speedscore::aliases;
+use speedscore::ancestry::{load_calibration, load_loadings};
+use speedscore::catalog::ScoreMetadata;
+use speedscore::checkpoint::CheckpointConfig;
+use speedscore::clump::{self, ClumpOptions};
+use speedscore::checksum::{Digests, InputChecksums};
+use speedscore::contigs;
+use speedscore::diagnose;
+use speedscore::common::{Args, Cli, Command, EffectWeights, EvaluateArgs, FileType, read_sample_names_from, GrpcArgs, QueueArgs, ServeArgs, ScoreOptions, TraitType, abs_weight_quantile, default_output_path, dosage_only_from, load_scoring_file, sample_name_changes, output_results, print_info, scale_by_frequency, winsorize_weights};
+use speedscore::error::{self, Error, ErrorFormat, ErrorPolicy, FailureKind, NonFinite};
+use speedscore::filter::Filter;
+use speedscore::genome::{self, BuildMismatch, Detected, GenomeBuild};
+use speedscore::input::{InputConfig, VcfInput};
+use speedscore::liftover::{self, Chains, Unlifted};
+use speedscore::lims::{LimsSink, Mapping};
+use speedscore::multi_sample::{Analyses, Samples};
+use speedscore::panel::Panel;
+use speedscore::partition::Partitions;
+use speedscore::permutation::Permutations;
+use speedscore::progress::Progress;
+use speedscore::provenance::Provenance;
+use speedscore::qc::{self, QcThresholds};
+use speedscore::reference::Reference;
+use speedscore::regions::Regions;
+use speedscore::remote::{self, Cache};
+use speedscore::rejected::Rejections;
+use speedscore::resources::{self, Limits, Tuning};
+use speedscore::scale::{ReportScale, ScaleSource};
+use speedscore::sex;
+use speedscore::scorer::{SampleScore, ScoreResults};
+use speedscore::sink::{self, csv_field, OutputFormat, RunInfo, Tee, Untimed};
+use speedscore::spans::Stage;
+use speedscore::upload::{Provider, Uploads};
+use speedscore::{batch, concordance, consumer, evaluate, gxe, multi_sample, origin, simulate, single_sample, stdin, stream, thresholding, trio, watch};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+fn main() {
+    let cli = Cli::parse();
+    // A panic has already been printed by the default hook; it only needs classifying
+    let result = std::panic::catch_unwind(|| dispatch(&cli));
+    let error = match result {
+        Ok(Ok(())) => return,
+        Ok(Err(e)) => e,
+        Err(_) => {
+            report_panic(cli.error_format);
+            std::process::exit(FailureKind::Internal.exit_code());
+        }
+    };
+    match cli.error_format {
+        ErrorFormat::Json => eprintln!("{}", serde_json::to_string(&error.report()).expect("error report serializes")),
+        ErrorFormat::Text => match &error {
+            Error::Qc(failures) => failures.iter().for_each(|failure| eprintln!("{}", failure)),
+            _ => eprintln!("Error: {}", error),
+        },
+    }
+    std::process::exit(error.kind().exit_code());
+}
+
+fn dispatch(cli: &Cli) -> error::Result<()> {
+    if cli.otlp_endpoint.is_some() && !cfg!(feature = "otel") {
+        return Err(Error::invalid("--otlp-endpoint", "OpenTelemetry export is not available in this build; rebuild with --features otel"));
+    }
+    // Flushes the remaining spans when dropped, after the command
+    #[cfg(feature = "otel")]
+    let _exporter = cli.otlp_endpoint.as_deref().map(speedscore::telemetry::export).transpose()?;
+    if let Some(version) = cli.print_schema {
+        print!("{}", speedscore::schema::document("--print-schema", version)?);
+        return Ok(());
+    }
+    match (&cli.command, &cli.score) {
+        (Some(Command::Serve(serve_args)), _) => serve(serve_args),
+        (Some(Command::Grpc(grpc_args)), _) => grpc(grpc_args),
+        (Some(Command::Watch(watch_args)), _) => watch::run(watch_args),
+        (Some(Command::Queue(queue_args)), _) => queue(queue_args),
+        (Some(Command::Plan(plan_args)), _) => speedscore::plan::run(plan_args),
+        (Some(Command::Simulate(simulate_args)), _) => simulate::run(simulate_args),
+        (Some(Command::Evaluate(evaluate_args)), _) => evaluate::run(evaluate_args),
+        (Some(Command::Concordance(concordance_args)), _) => concordance::run(concordance_args),
+        (Some(Command::BuildReference(reference_args)), _) => speedscore::reference::run(reference_args),
+        (Some(Command::Export(export_args)), _) => speedscore::export::run(export_args),
+        (None, Some(args)) => run(args),
+        (None, None) => unreachable!("clap requires --vcf and --scoring when no subcommand is given"),
+    }
+}
+
+fn report_panic(format: ErrorFormat) {
+    if format == ErrorFormat::Json {
+        let report = serde_json::json!({
+            "kind": FailureKind::Internal,
+            "exit_code": FailureKind::Internal.exit_code(),
+            "message": "internal error (panic); please report this as a bug",
+        });
+        eprintln!("{}", report);
+    }
+}
+
+#[cfg(feature = "server")]
+fn serve(args: &ServeArgs) -> error::Result<()> {
+    speedscore::serve::run(args)
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_args: &ServeArgs) -> error::Result<()> {
+    Err(error::Error::invalid("serve", "the REST server is not available in this build; rebuild with --features server"))
+}
+
+#[cfg(feature = "grpc")]
+fn grpc(args: &GrpcArgs) -> error::Result<()> {
+    speedscore::grpc::run(args)
+}
+
+#[cfg(not(feature = "grpc"))]
+fn grpc(_args: &GrpcArgs) -> error::Result<()> {
+    Err(error::Error::invalid("grpc", "the gRPC service is not available in this build; rebuild with --features grpc"))
+}
+
+#[cfg(feature = "queue")]
+fn queue(args: &QueueArgs) -> error::Result<()> {
+    speedscore::queue::run(args)
+}
+
+#[cfg(not(feature = "queue"))]
+fn queue(_args: &QueueArgs) -> error::Result<()> {
+    Err(error::Error::invalid("queue", "queue intake is not available in this build; rebuild with --features queue"))
+}
+
+/// Run `score` with the `--tui` dashboard drawing `progress` on a separate thread.
+#[cfg(feature = "tui")]
+fn with_dashboard<T>(
+    progress: &Progress,
+    policy: &ErrorPolicy,
+    weights: &speedscore::common::EffectWeights,
+    vcf_path: &str,
+    score: impl FnOnce() -> error::Result<T>,
+) -> error::Result<T> {
+    std::thread::scope(|scope| {
+        let dashboard = scope.spawn(|| speedscore::tui::run(progress, policy, weights, vcf_path));
+        let result = score();
+        progress.finish();
+        let shown = dashboard.join().expect("dashboard thread panicked");
+        let value = result?;
+        shown?;
+        Ok(value)
+    })
+}
+
+/// Size buffers and thread pools to the memory and CPU limits in effect.
+fn configure_resources(args: &Args, scoring_variants: usize) -> error::Result<()> {
+    if args.threads == Some(0) {
+        return Err(Error::invalid("--threads", "must be at least 1"));
+    }
+    let limits = Limits::detect(args.memory_limit, args.threads, args.io_threads);
+    let tuning = Tuning::plan(&limits, scoring_variants)?;
+    if limits.constrained() {
+        let memory = match limits.memory {
+            Some((bytes, source)) => format!("{} memory ({})", resources::format_size(bytes), source),
+            None => "no memory limit".to_string(),
+        };
+        println!("Resource limits: {}, {} CPUs ({}); using {}", memory, limits.cpus.0, limits.cpus.1, tuning);
+    }
+    rayon::ThreadPoolBuilder::new().num_threads(tuning.scoring_threads).build_global()?;
+    resources::configure(tuning);
+    Ok(())
+}
+
+/// The reporting scales requested on the command line, with `--scale-from auto` resolved.
+fn report_scale(args: &Args, has_reference: bool, file_type: &FileType) -> error::Result<ReportScale> {
+    let source = match args.scale_from {
+        ScaleSource::Auto if has_reference => ScaleSource::Reference,
+        ScaleSource::Auto if args.calibration.is_some() => ScaleSource::Calibration,
+        ScaleSource::Auto => ScaleSource::Cohort,
+        source => source,
+    };
+    let missing = match source {
+        ScaleSource::Reference if !has_reference => Some("scaling against the reference needs --reference"),
+        ScaleSource::Calibration if args.calibration.is_none() => Some("scaling against the calibration needs --calibration"),
+        _ if matches!(file_type, FileType::SingleSample | FileType::ConsumerGenotypes) && source != ScaleSource::Reference => {
+            Some("a single-sample VCF can only be scaled against a --reference distribution")
+        }
+        _ => None,
+    };
+    if let Some(message) = missing {
+        return Err(Error::invalid("--scale-from", message));
+    }
+    ReportScale::new(source, args.t_scores, args.percentiles, &args.risk_cutoffs, &args.risk_labels)
+}
+
+/// The scoring file's genome build: `--genome-build`, else the one its header declares,
+/// else the one its positions point to.
+fn scoring_build(args: &Args, effect_weights: &EffectWeights) -> error::Result<Option<Detected>> {
+    if let Some(build) = args.genome_build {
+        return Ok(Some(Detected { build, evidence: "--genome-build" }));
+    }
+    if let Some(build) = genome::declared_build(&args.scoring)? {
+        return Ok(Some(Detected { build, evidence: "declared in its header" }));
+    }
+    let positions = effect_weights.keys().map(|(chr, pos)| (chr.as_str(), *pos));
+    Ok(genome::infer_from_positions(positions).map(|build| Detected { build, evidence: "positions beyond the other build's chromosome ends" }))
+}
+
+/// Refuse, or warn about, a VCF on another build than the scoring file.
+fn check_build(args: &Args, input: &VcfInput, scoring: Option<&Detected>) -> error::Result<()> {
+    let Some(scoring) = scoring else {
+        return Ok(());
+    };
+    let Some(vcf) = genome::detect_vcf(&args.vcf, input.peek()?)? else {
+        println!("Genome build: scoring file {} ({}); VCF unknown", scoring.build, scoring.evidence);
+        return Ok(());
+    };
+    if scoring.build == vcf.build {
+        println!("Genome build: {} (scoring file: {}; VCF: {})", scoring.build, scoring.evidence, vcf.evidence);
+        return Ok(());
+    }
+    let message = format!(
+        "the scoring file is on {} ({}) but the VCF is on {} ({}); almost no variants would match. Lift one over to the other's build",
+        scoring.build, scoring.evidence, vcf.build, vcf.evidence
+    );
+    match args.build_mismatch {
+        BuildMismatch::Error => Err(Error::invalid(&args.vcf, format!("{}, or pass --build-mismatch warn to score anyway", message))),
+        BuildMismatch::Warn => {
+            eprintln!("WARNING: {}", message);
+            Ok(())
+        }
+    }
+}
+
+/// Look up the score in the PGS Catalog, unless `--pgs-id` already has, and print what it
+/// says, noting when the scoring file does not have the published number of variants.
+#[cfg(feature = "pgs-catalog")]
+fn pgs_catalog(scoring_path: &str, scoring_variants: usize, looked_up: Option<ScoreMetadata>) -> error::Result<ScoreMetadata> {
+    let metadata = match looked_up {
+        Some(metadata) => metadata,
+        None => speedscore::catalog::lookup(scoring_path)?,
+    };
+    println!("{}", metadata);
+    if let Some(published) = metadata.variants.filter(|&published| published != scoring_variants as u64) {
+        println!("Note: {} has {} variants in the PGS Catalog, but {} were read from {}", metadata.pgs_id, published, scoring_variants, scoring_path);
+    }
+    Ok(metadata)
+}
+
+/// With --pgs-id, `args` with the scoring files of those scores, harmonized to
+/// --genome-build or else to the VCF's build, and read from the URLs the Catalog gives as
+/// `resolve` reads them; and what the Catalog says of the first score.
+#[cfg(feature = "pgs-catalog")]
+fn catalog_scoring_files(
+    args: &Args,
+    input: &VcfInput,
+    resolve: impl Fn(&str, Option<&str>) -> error::Result<String>,
+) -> error::Result<Option<(Args, ScoreMetadata)>> {
+    if args.pgs_ids.is_empty() {
+        return Ok(None);
+    }
+    let build = match args.genome_build {
+        Some(build) => build,
+        None => genome::detect_vcf(&args.vcf, input.peek()?)?.map(|vcf| vcf.build).ok_or_else(|| {
+            Error::invalid("--pgs-id", "the VCF's genome build cannot be told from its header or positions; give --genome-build to choose the harmonized scoring file")
+        })?,
+    };
+    let mut first = None;
+    let mut scorings = Vec::new();
+    for id in &args.pgs_ids {
+        let (metadata, url) = speedscore::catalog::scoring_file(id, build)?;
+        println!("Scoring file of {} harmonized to {}: {}", id, build, url);
+        let expected = if first.is_none() { args.expect_scoring_checksum.as_deref() } else { None };
+        scorings.push(resolve(&url, expected)?);
+        first.get_or_insert(metadata);
+    }
+    let args = Args { scoring: scorings[0].clone(), scorings, pgs_catalog: true, ..args.clone() };
+    Ok(first.map(|metadata| (args, metadata)))
+}
+
+/// Hash the inputs with --checksums, and check them against the expected checksums given.
+fn input_checksums(args: &Args) -> error::Result<Option<InputChecksums>> {
+    if !args.checksums && args.expect_vcf_checksum.is_none() && args.expect_scoring_checksum.is_none() {
+        return Ok(None);
+    }
+    let checksums = InputChecksums { vcf: Digests::of(&args.vcf)?, scoring: Digests::of(&args.scoring)? };
+    let inputs = [
+        (&args.vcf, &checksums.vcf, &args.expect_vcf_checksum),
+        (&args.scoring, &checksums.scoring, &args.expect_scoring_checksum),
+    ];
+    for (path, digests, expected) in inputs {
+        println!("{}: SHA-256 {}, MD5 {}", path, digests.sha256, digests.md5);
+        if let Some(expected) = expected {
+            digests.verify(path, expected)?;
+            println!("{}: checksum matches", path);
+        }
+    }
+    Ok(Some(checksums))
+}
+
+/// Whether to read FORMAT/DS without --dosage, because the VCF has no GT.
+fn falls_back_to_dosage(input: &VcfInput) -> error::Result<bool> {
+    let dosage_only = dosage_only_from(input.path(), input.peek()?)?;
+    if dosage_only {
+        println!("The VCF's records have no FORMAT/GT; scoring FORMAT/DS dosages instead");
+    }
+    Ok(dosage_only)
+}
+
+/// Flag scoring chromosomes missing from the VCF's `##contig` lines, and refuse to scan a
+/// VCF that declares none of them.
+fn check_contigs(args: &Args, input: &VcfInput, effect_weights: &EffectWeights) -> error::Result<()> {
+    let check = contigs::check(&args.vcf, input.peek()?, effect_weights)?;
+    if check.contigs.is_empty() {
+        return Ok(());
+    }
+    for (chr, alias) in &check.renamed {
+        eprintln!("WARNING: scoring chromosome {} is named {} in the VCF; its variants will not match", chr, alias);
+    }
+    if check.covered(effect_weights) == 0 {
+        return Err(Error::invalid(
+            &args.vcf,
+            format!(
+                "none of the scoring file's chromosomes ({}) is among the VCF's ##contig lines ({}); check how the two name chromosomes, or map the VCF's names with --chr-aliases",
+                contigs::list(check.missing.keys()),
+                contigs::list(check.contigs.iter())
+            ),
+        ));
+    }
+    if !check.missing.is_empty() {
+        println!(
+            "{} scoring variants are on chromosomes the VCF's header does not declare: {}",
+            check.missing.values().sum::<usize>(),
+            contigs::list(check.missing.keys())
+        );
+    }
+    Ok(())
+}
+
+/// Name the likely cause of a match rate under --min-match-rate, and what to change.
+fn explain_match_rate(input: &VcfInput, effect_weights: &EffectWeights, scoring_build: Option<GenomeBuild>, rate: f64) -> error::Result<()> {
+    eprintln!("WARNING: only {:.2}% of the scoring variants matched the VCF", 100.0 * rate);
+    // Standard input or a stream has been read, and cannot be looked through again
+    if input.is_piped() {
+        return Ok(());
+    }
+    if let Some(cause) = diagnose::diagnose(input, effect_weights, scoring_build)? {
+        eprintln!("  Likely cause: {}", cause);
+        eprintln!("  Suggestion: {}", cause.suggestion());
+    }
+    Ok(())
+}
+
+/// List the sample names that were empty or repeated and so renamed, and count those the
+/// CSV has to quote.
+fn report_sample_names(input: &VcfInput, output_format: OutputFormat) -> error::Result<()> {
+    let changes = sample_name_changes(input.path(), input.peek()?)?;
+    if !changes.is_empty() {
+        println!("Renamed {} empty or duplicate sample names:", changes.len());
+        for change in changes.iter().take(10) {
+            println!("  column {}: '{}' -> '{}'", change.column + 1, change.from, change.to);
+        }
+        if changes.len() > 10 {
+            println!("  ... and {} more", changes.len() - 10);
+        }
+    }
+    if output_format == OutputFormat::Csv {
+        let names = read_sample_names_from(input.path(), input.peek()?)?;
+        let quoted = names.iter().filter(|name| csv_field(name) != name.as_str()).count();
+        if quoted > 0 {
+            println!("Note: {} sample names contain ',' or '\"' and are quoted in the CSV", quoted);
+        }
+    }
+    Ok(())
+}
+
+/// Pick the `--sample-fraction` subset of the VCF's samples.
+fn sample_subset(input: &VcfInput, fraction: f64, seed: u64) -> error::Result<Vec<usize>> {
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err(Error::invalid("--sample-fraction", format!("{} is not in (0, 1]", fraction)));
+    }
+    let count = read_sample_names_from(input.path(), input.peek()?)?.len();
+    let subset = multi_sample::random_subset(count, fraction, seed);
+    println!("Scoring a random {} of {} samples (seed {})", subset.len(), count, seed);
+    Ok(subset)
+}
+
+/// The `--sample-range` block of the VCF's samples, which must hold at least the first one.
+fn sample_block(input: &VcfInput, range: &Range<usize>) -> error::Result<Samples<'static>> {
+    let count = read_sample_names_from(input.path(), input.peek()?)?.len();
+    if range.start >= count {
+        return Err(Error::invalid("--sample-range", format!("the VCF has {} samples, so none is at position {}", count, range.start + 1)));
+    }
+    println!("Scoring samples {} to {} of {}", range.start + 1, range.end.min(count), count);
+    Ok(Samples::Block { start: range.start, end: range.end })
+}
+
+/// Score every VCF `--vcf-dir` names against the scoring file, loaded once, into one
+/// table; see `speedscore::batch`.
+fn run_batch(args: &Args, pattern: &str) -> error::Result<()> {
+    batch::check(args)?;
+    let Some(output) = &args.output else {
+        return Err(Error::invalid("--vcf-dir", "the VCFs have no one path to name the results after; give --output"));
+    };
+    let vcfs = batch::expand(pattern)?;
+    println!("Scoring {} VCFs from {}", vcfs.len(), pattern);
+    let mut uploads = Uploads::new();
+    let output = uploads.stage(output)?;
+    let cache = Cache::new(args.cache_dir.as_deref());
+    let scoring = match stream::is_streamed(&args.scorings[0], &InputConfig::from_args(args)) {
+        true => args.scorings[0].clone(),
+        false => cache.resolve(&args.scorings[0], None)?,
+    };
+    if let Some(path) = &args.chr_aliases {
+        aliases::configure(aliases::load(&cache.resolve(path, None)?)?);
+    }
+    let (effect_weights, _) = load_scoring_file(&scoring, args.non_finite, None)?;
+    configure_resources(args, effect_weights.len())?;
+    batch::run(&vcfs, effect_weights, args, &output)?;
+    uploads.finish()
+}
+
+fn run(args: &Args) -> error::Result<()> {
+    if args.tui && !cfg!(feature = "tui") {
+        return Err(error::Error::invalid("--tui", "the dashboard is not available in this build; rebuild with --features tui"));
+    }
+    if args.strict_vcf && !cfg!(feature = "strict-vcf") {
+        return Err(error::Error::invalid("--strict-vcf", "spec validation is not available in this build; rebuild with --features strict-vcf"));
+    }
+    if args.htslib && !cfg!(feature = "htslib") {
+        return Err(error::Error::invalid("--htslib", "htslib is not available in this build; rebuild with --features htslib"));
+    }
+    if args.pgs_catalog && !cfg!(feature = "pgs-catalog") {
+        return Err(error::Error::invalid("--pgs-catalog", "PGS Catalog lookups are not available in this build; rebuild with --features pgs-catalog"));
+    }
+    if !args.pgs_ids.is_empty() && !cfg!(feature = "pgs-catalog") {
+        return Err(error::Error::invalid("--pgs-id", "PGS Catalog lookups are not available in this build; rebuild with --features pgs-catalog,remote"));
+    }
+    speedscore::schema::check(args.schema_version)?;
+    if let Some(pattern) = &args.vcf_dir {
+        return run_batch(args, pattern);
+    }
+    // A bucket is read and written with its provider's tool, which must be there before the run starts
+    let outputs = [&args.output, &args.lims_output, &args.provenance, &args.rejected, &args.unlifted, &args.variant_variance];
+    for path in args.vcfs.iter().chain(&args.scorings).chain(outputs.into_iter().flatten()) {
+        if let Some(provider) = Provider::of(path) {
+            provider.check_tool(path)?;
+        }
+    }
+    // Outputs bound for a bucket are written locally, and uploaded once all are written
+    let mut uploads = Uploads::new();
+    let staged = Args {
+        // A PLINK fileset is read through its .bed or .pgen; see `speedscore::plink` and `speedscore::pgen`
+        vcf: match (&args.bfile, &args.pfile, &args.htsget) {
+            (Some(prefix), _, _) => format!("{}.bed", prefix),
+            (_, Some(prefix), _) => format!("{}.pgen", prefix),
+            // An htsget dataset is read by its endpoint; see `speedscore::htsget`
+            (_, _, Some(endpoint)) => endpoint.clone(),
+            // Of several VCFs, the first; they are read as one through `vcfs`
+            _ => args.vcfs.first().cloned().unwrap_or_default(),
+        },
+        // Set from the Catalog's scoring files below with --pgs-id
+        scoring: args.scorings.first().cloned().unwrap_or_default(),
+        output: uploads.stage_option(&args.output)?,
+        lims_output: uploads.stage_option(&args.lims_output)?,
+        provenance: uploads.stage_option(&args.provenance)?,
+        rejected: uploads.stage_option(&args.rejected)?,
+        unlifted: uploads.stage_option(&args.unlifted)?,
+        variant_variance: uploads.stage_option(&args.variant_variance)?,
+        ..args.clone()
+    };
+    let args = &staged;
+    // Only the VCF and scoring file are streamed; a URL of another input is downloaded
+    let optional_inputs = [
+        ("--reference", &args.reference),
+        ("--pca-loadings", &args.pca_loadings),
+        ("--calibration", &args.calibration),
+        ("--liftover", &args.liftover),
+        ("--chr-aliases", &args.chr_aliases),
+    ];
+    for (flag, path) in optional_inputs {
+        if path.as_deref().is_some_and(|path| Provider::of(path).is_some()) {
+            return Err(Error::invalid(flag, "only --vcf and --scoring can be read from a bucket; copy this file locally or give an http(s):// URL"));
+        }
+    }
+    if args.vcfs.len() > 1 && args.vcfs.iter().any(|path| stdin::is_stdin(path)) {
+        return Err(Error::invalid("--vcf", "standard input cannot be one of several VCFs"));
+    }
+    // Inputs given as URLs are read from the download cache, unless streamed
+    let config = InputConfig::from_args(args);
+    let cache = Cache::new(args.cache_dir.as_deref());
+    let resolve = |path: &str, expected: Option<&str>| match stream::is_streamed(path, &config) {
+        true => Ok(path.to_string()),
+        false => cache.resolve(path, expected),
+    };
+    // Several VCFs are read as one; see `speedscore::concat`
+    let vcfs = match args.vcfs.len() {
+        0 | 1 => Vec::new(),
+        _ => args.vcfs.iter().map(|path| resolve(path, None)).collect::<error::Result<Vec<_>>>()?,
+    };
+    let fetched = Args {
+        vcf: match vcfs.first() {
+            None => resolve(&args.vcf, args.expect_vcf_checksum.as_deref())?,
+            Some(first) => first.clone(),
+        },
+        vcfs,
+        scoring: resolve(&args.scoring, args.expect_scoring_checksum.as_deref())?,
+        scorings: args.scorings.iter().enumerate().map(|(index, path)| match index {
+            0 => resolve(path, args.expect_scoring_checksum.as_deref()),
+            _ => resolve(path, None),
+        }).collect::<error::Result<_>>()?,
+        reference: cache.resolve_option(&args.reference)?,
+        pca_loadings: cache.resolve_option(&args.pca_loadings)?,
+        calibration: cache.resolve_option(&args.calibration)?,
+        liftover: cache.resolve_option(&args.liftover)?,
+        chr_aliases: cache.resolve_option(&args.chr_aliases)?,
+        ..args.clone()
+    };
+    // The default output of a downloaded or streamed VCF goes in the working directory
+    let vcf_name = match remote::is_url(&args.vcf) || stream::is_streamed(&args.vcf, &config) {
+        true => remote::file_name(&args.vcf).to_string(),
+        false => args.vcf.clone(),
+    };
+    let args = &fetched;
+    if stdin::is_stdin(&args.vcf) {
+        if args.output.is_none() {
+            return Err(Error::invalid("--vcf", "a VCF read from standard input has no path to name the results after; give --output"));
+        }
+        if args.checksums || args.expect_vcf_checksum.is_some() {
+            let flag = if args.checksums { "--checksums" } else { "--expect-vcf-checksum" };
+            return Err(Error::invalid(flag, "standard input is read once, as it is scored, so it cannot be checksummed first"));
+        }
+    }
+    if args.vcfs.len() > 1 {
+        if args.output.is_none() {
+            return Err(Error::invalid("--vcf", "several VCFs have no one path to name the results after; give --output"));
+        }
+        if args.checksums || args.expect_vcf_checksum.is_some() {
+            let flag = if args.checksums { "--checksums" } else { "--expect-vcf-checksum" };
+            return Err(Error::invalid(flag, "checksums are of a single VCF, not of several read as one"));
+        }
+    }
+    // Standard input or a stream is opened here, once; the checks before scoring read its header
+    let input = match args.vcfs.len() > 1 {
+        true => VcfInput::files(args.vcfs.clone(), &config)?,
+        false => VcfInput::new(&args.vcf, &config)?,
+    };
+    #[cfg(feature = "pgs-catalog")]
+    let from_catalog = catalog_scoring_files(args, &input, resolve)?;
+    #[cfg(not(feature = "pgs-catalog"))]
+    let from_catalog: Option<(Args, ScoreMetadata)> = None;
+    let args = from_catalog.as_ref().map_or(args, |(args, _)| args);
+    let streamed = [(&args.vcf, "--expect-vcf-checksum", &args.expect_vcf_checksum), (&args.scoring, "--expect-scoring-checksum", &args.expect_scoring_checksum)];
+    for (path, expect_flag, expected) in streamed {
+        if stream::is_streamed(path, &config) && (args.checksums || expected.is_some()) {
+            let flag = if args.checksums { "--checksums" } else { expect_flag };
+            return Err(Error::invalid(flag, format!("{} is read as it downloads, so it cannot be checksummed first", path)));
+        }
+    }
+    let _stage = Stage::score(&args.vcf);
     let start = Instant::now();
-    let (effect_weights, scoring_chr_format) = load_scoring_file(&args.scoring)?;
-    
-    let file_type = FileType::detect(&args.vcf)?;
-    
-    let (score, total_variants, matched_variants, vcf_chr_format) = match file_type {
+    if let Some(path) = &args.chr_aliases {
+        aliases::configure(aliases::load(path)?);
+    }
+    let checksums = input_checksums(args)?;
+    let rejections = args.rejected.is_some().then(Rejections::new);
+    let filter = match (&args.include, &args.exclude) {
+        (Some(expression), _) => Some(Filter::parse(expression, false)?),
+        (None, Some(expression)) => Some(Filter::parse(expression, true)?),
+        (None, None) => None,
+    };
+    let filter_label = filter.as_ref().map(Filter::to_string);
+    let mut lims = args.lims_mapping.as_deref().map(Mapping::load).transpose()?;
+    if args.scorings.len() > 1 {
+        let refused = [
+            ("--liftover", args.liftover.is_some()),
+            ("--frequency-scaling", args.frequency_scaling.is_some()),
+            ("--max-abs-weight", args.max_abs_weight.is_some()),
+            ("--winsorize-quantile", args.winsorize_quantile.is_some()),
+            ("--exclude-regions", !args.exclude_regions.is_empty()),
+            ("--region-cap", args.region_cap.is_some()),
+            ("--clump-reference", args.clump_reference.is_some()),
+            ("--center", args.center.is_some()),
+            ("--plink-compat", args.plink_compat),
+        ];
+        if let Some((flag, _)) = refused.iter().find(|(_, given)| *given) {
+            return Err(Error::invalid(flag, "would only change the first scoring file's score; give a single --scoring with it"));
+        }
+    }
+    let (mut effect_weights, scoring_chr_format) = load_scoring_file(&args.scoring, args.non_finite, rejections.as_ref())?;
+    configure_resources(args, effect_weights.len())?;
+    #[cfg(feature = "pgs-catalog")]
+    let pgs_catalog = args.pgs_catalog.then(|| pgs_catalog(&args.scoring, effect_weights.len(), from_catalog.as_ref().map(|(_, metadata)| metadata.clone()))).transpose()?;
+    #[cfg(not(feature = "pgs-catalog"))]
+    let pgs_catalog: Option<ScoreMetadata> = None;
+    let scoring_build = match &args.liftover {
+        Some(path) => {
+            let summary = Chains::load(path)?.lift(&mut effect_weights);
+            println!(
+                "Lifted {} scoring variants with {} ({} to the reverse strand); dropped {} unmapped, {} multi-mapped, {} colliding and {} reverse-strand indels",
+                summary.lifted,
+                path,
+                summary.reversed,
+                summary.count(Unlifted::Unmapped),
+                summary.count(Unlifted::MultiMapped),
+                summary.count(Unlifted::Collision),
+                summary.count(Unlifted::ReverseStrandIndel)
+            );
+            if let Some(unlifted) = &args.unlifted {
+                liftover::write_unlifted(&summary, unlifted)?;
+                println!("Variants not lifted written to: {}", unlifted);
+            }
+            if effect_weights.is_empty() {
+                return Err(Error::invalid(path, "no scoring variant could be lifted"));
+            }
+            // The positions are now on the chain's target build, which should be the VCF's
+            genome::detect_vcf(&args.vcf, input.peek()?)?.map(|vcf| Detected { build: vcf.build, evidence: "lifted to the VCF's build" })
+        }
+        None => {
+            let scoring_build = scoring_build(args, &effect_weights)?;
+            check_build(args, &input, scoring_build.as_ref())?;
+            scoring_build
+        }
+    };
+    if let Some(alpha) = args.frequency_scaling {
+        scale_by_frequency(&mut effect_weights, alpha, &args.scoring)?;
+        println!("Scaled the weights by [2p(1-p)]^{}", alpha);
+    }
+    let weight_cap = match (args.max_abs_weight, args.winsorize_quantile) {
+        (Some(cap), _) if !(cap > 0.0 && cap.is_finite()) => {
+            return Err(Error::invalid("--max-abs-weight", "must be a positive number"));
+        }
+        (Some(cap), _) => Some(cap),
+        (None, Some(quantile)) if !(0.0..=1.0).contains(&quantile) => {
+            return Err(Error::invalid("--winsorize-quantile", "must be between 0 and 1"));
+        }
+        (None, Some(quantile)) => Some(abs_weight_quantile(&effect_weights, quantile)),
+        (None, None) => None,
+    };
+    if let Some(cap) = weight_cap {
+        let capped = winsorize_weights(&mut effect_weights, cap);
+        println!("Capped {} of {} effect weights at |weight| = {}", capped, effect_weights.len(), cap);
+    }
+    if args.region_cap.is_some_and(|fraction| !(0.0..1.0).contains(&fraction)) {
+        return Err(Error::invalid("--region-cap", "must be a share in [0, 1)"));
+    }
+    if !args.exclude_regions.is_empty() {
+        let regions = Regions::resolve(&args.exclude_regions, scoring_build.as_ref().map(|d| d.build), "--exclude-regions")?;
+        let excluded = regions.exclude(&mut effect_weights);
+        if effect_weights.is_empty() {
+            return Err(Error::invalid(&args.scoring, "every scoring variant lies in the --exclude-regions"));
+        }
+        println!("Excluded {} scoring variants in {}; {} left", excluded, args.exclude_regions.join(","), effect_weights.len());
+    }
+    if let Some(reference) = &args.clump_reference {
+        let options = ClumpOptions { window_kb: args.clump_kb, r2: args.clump_r2, p: args.clump_p };
+        let summary = clump::clump(&mut effect_weights, reference, &options, &args.scoring)?;
+        println!(
+            "Clumped against {}: {} of {} variants kept ({} clumped, {} not in the reference, {} above --clump-p)",
+            reference, summary.kept, summary.input, summary.clumped, summary.not_in_reference, summary.above_p
+        );
+        if let Some(path) = &args.clumped_scoring {
+            clump::write_clumped(&args.scoring, &effect_weights, path)?;
+            println!("Clumped scoring file written to: {}", path);
+        }
+    }
+    if let Some(fraction) = args.region_cap {
+        let regions = Regions::resolve(&args.cap_regions, scoring_build.as_ref().map(|d| d.build), "--cap-regions")?;
+        let capped = regions.cap(&mut effect_weights, fraction);
+        if capped.factor < 1.0 {
+            println!(
+                "Capped {} scoring variants in {}: they held {:.2}% of the total absolute weight, now {:.2}% (weights scaled by {:.4})",
+                capped.variants, args.cap_regions.join(","), 100.0 * capped.share, 100.0 * fraction, capped.factor
+            );
+        } else {
+            println!(
+                "{} scoring variants in {} hold {:.2}% of the total absolute weight, within the cap",
+                capped.variants, args.cap_regions.join(","), 100.0 * capped.share
+            );
+        }
+    }
+    if !args.chromosomes.is_empty() {
+        // Last, so that a job of a plan scores its chromosomes with the weights the whole run would use
+        let keep: HashSet<&str> = args.chromosomes.iter().map(|chr| aliases::canonical(chr.trim())).collect();
+        effect_weights.retain(|(chr, _), _| keep.contains(chr.as_str()));
+        if effect_weights.is_empty() {
+            return Err(Error::invalid(&args.scoring, format!("no scoring variant lies on chromosome {}", args.chromosomes.join(","))));
+        }
+        println!("Scoring the {} scoring variants on chromosome {}", effect_weights.len(), args.chromosomes.join(","));
+    }
+    // The other scoring files are scored in the same pass, each into a column of its own
+    let panel = match args.scorings.len() {
+        0 | 1 => None,
+        _ => {
+            let mut panel = Panel::load(&args.scoring, &args.scorings[1..], args.non_finite)?;
+            if !args.chromosomes.is_empty() {
+                let keep: HashSet<&str> = args.chromosomes.iter().map(|chr| aliases::canonical(chr.trim())).collect();
+                panel.retain(|chr| keep.contains(chr));
+            }
+            println!("Scoring {} scoring files in one pass: {}", panel.ids.len(), panel.ids.join(", "));
+            Some(panel)
+        }
+    };
+    check_contigs(args, &input, &effect_weights)?;
+
+    let mut policy = ErrorPolicy::new(args.on_error, args.max_errors).unsorted(args.unsorted);
+    if let Some(rejections) = rejections {
+        policy = policy.rejections(rejections);
+    }
+    if let Some(filter) = filter {
+        policy = policy.filter(filter);
+    }
+    #[cfg(feature = "strict-vcf")]
+    if args.strict_vcf {
+        policy = policy.strict(speedscore::strict::Validator::load(&args.vcf, input.peek()?)?);
+    }
+    let options = ScoreOptions {
+        dosage: args.dosage || falls_back_to_dosage(&input)?,
+        model: args.model,
+        center: args.center,
+        mean_impute: args.plink_compat,
+        info_field: args.info_field,
+        rescale_by_coverage: args.rescale_by_coverage,
+        non_finite: args.non_finite,
+    };
+    let mut loadings = args.pca_loadings.as_deref().map(load_loadings).transpose()?;
+    if let (Some(loadings), Some(path)) = (loadings.as_mut(), &args.calibration) {
+        loadings.calibration = Some(load_calibration(path, &loadings.names)?);
+    }
+    let pt_thresholds = match args.pt_thresholds.is_empty() {
+        true => Vec::new(),
+        false => thresholding::validate(&args.pt_thresholds, &effect_weights, &args.scoring)?,
+    };
+    let permutations = args.permutations.map(|count| Permutations::new(&effect_weights, count, args.seed)).transpose()?;
+    let partitions = match (&args.set_column, &args.set_bed) {
+        (Some(column), _) => Some(Partitions::from_column(&args.scoring, column, &effect_weights)?),
+        (None, Some(bed)) => Some(Partitions::from_bed(bed, &effect_weights)?),
+        (None, None) => None,
+    };
+    if let Some(partitions) = &partitions {
+        if partitions.names.is_empty() {
+            return Err(Error::invalid(args.set_bed.as_deref().unwrap_or(&args.scoring), "no set contains any scoring variant"));
+        }
+        println!(
+            "Partitioned into {} sets; {} of {} scoring variants are in at least one",
+            partitions.names.len(),
+            partitions.assigned(),
+            effect_weights.len()
+        );
+    }
+    let reference = match &args.reference {
+        Some(path) => {
+            let reference = Reference::load(path)?;
+            Some(reference.select(args.reference_population.as_deref(), effect_weights.len(), &options, path)?.clone())
+        }
+        None => None,
+    };
+    let sexes = args.sex.as_deref().map(|path| sex::load_sexes(path, &args.sex_column)).transpose()?;
+    let environments = match (&args.environment, &args.environment_column) {
+        (Some(path), Some(column)) => {
+            let interacting = gxe::check_weights(&effect_weights, &args.scoring)?;
+            let environments = gxe::load_environments(path, column)?;
+            println!("{} scoring variants have interaction weights; {} samples have an exposure", interacting, environments.len());
+            Some(environments)
+        }
+        _ => None,
+    };
+    if args.parent_of_origin.is_some() {
+        if options.dosage {
+            return Err(Error::invalid("--parent-of-origin", "parent-of-origin scores need phased GT calls, not dosages"));
+        }
+        let weighted = origin::check_weights(&effect_weights, &args.scoring)?;
+        println!("{} scoring variants have parent-of-origin weights", weighted);
+    }
+    let pedigree = match &args.pedigree {
+        Some(path) => {
+            let pedigree = trio::load_pedigree(path)?;
+            let trios = trio::complete_trios(&pedigree, &read_sample_names_from(&args.vcf, input.peek()?)?);
+            if trios == 0 {
+                return Err(Error::invalid(path, "no trio has all three members in the VCF"));
+            }
+            println!("{} of the pedigree's {} trios are complete in the VCF", trios, pedigree.len());
+            Some(pedigree)
+        }
+        None => None,
+    };
+    // plink2 writes the same .sscore table whether the VCF holds one sample or many
+    let (file_type, output_format) = if args.plink_compat {
+        (FileType::MultiSample, OutputFormat::Sscore)
+    } else if loadings.is_some() || !pt_thresholds.is_empty() || permutations.is_some() || partitions.is_some() || sexes.is_some() || environments.is_some() || args.parent_of_origin.is_some() || pedigree.is_some() || args.variant_variance.is_some() || panel.is_some() {
+        (FileType::MultiSample, args.output_format)
+    } else {
+        (FileType::from_mode(args.mode, &args.vcf, input.peek()?)?, args.output_format)
+    };
+    if loadings.is_some() && !matches!(output_format, OutputFormat::Csv | OutputFormat::Json) {
+        return Err(Error::invalid("--pca-loadings", "PC coordinates can only be written with --output-format csv or json"));
+    }
+    if !pt_thresholds.is_empty() && !matches!(output_format, OutputFormat::Csv | OutputFormat::Json) {
+        return Err(Error::invalid("--pt-thresholds", "thresholded scores can only be written with --output-format csv or json"));
+    }
+    if permutations.is_some() && !matches!(output_format, OutputFormat::Csv | OutputFormat::Json) {
+        return Err(Error::invalid("--permutations", "null percentiles can only be written with --output-format csv or json"));
+    }
+    if panel.is_some() && !matches!(output_format, OutputFormat::Csv | OutputFormat::Json) {
+        return Err(Error::invalid("--scoring", "the scores of several scoring files can only be written with --output-format csv or json"));
+    }
+    if partitions.is_some() && !matches!(output_format, OutputFormat::Csv | OutputFormat::Json) {
+        let flag = if args.set_column.is_some() { "--set-column" } else { "--set-bed" };
+        return Err(Error::invalid(flag, "set scores can only be written with --output-format csv or json"));
+    }
+    let csv_or_json = matches!(output_format, OutputFormat::Csv | OutputFormat::Json);
+    if reference.is_some() && matches!(file_type, FileType::MultiSample) && !csv_or_json {
+        return Err(Error::invalid("--reference", "reference percentiles can only be written with --output-format csv or json"));
+    }
+    let scale = match args.t_scores || args.percentiles || !args.risk_cutoffs.is_empty() {
+        true => Some(report_scale(args, reference.is_some(), &file_type)?),
+        false => None,
+    };
+    if scale.is_some() && matches!(file_type, FileType::MultiSample) && !csv_or_json {
+        let flag = if args.t_scores { "--t-scores" } else if args.percentiles { "--percentiles" } else { "--risk-cutoffs" };
+        return Err(Error::invalid(flag, "reporting scales can only be written with --output-format csv or json"));
+    }
+    if args.info_field.is_some() && matches!(file_type, FileType::MultiSample) && !csv_or_json {
+        return Err(Error::invalid("--info-field", "effective variant counts can only be written with --output-format csv or json"));
+    }
+    if args.rescale_by_coverage && matches!(file_type, FileType::MultiSample) && !csv_or_json {
+        return Err(Error::invalid("--rescale-by-coverage", "rescaled scores can only be written with --output-format csv or json"));
+    }
+    if environments.is_some() && !csv_or_json {
+        return Err(Error::invalid("--environment", "interaction scores can only be written with --output-format csv or json"));
+    }
+    if args.parent_of_origin.is_some() && !csv_or_json {
+        return Err(Error::invalid("--parent-of-origin", "parent-of-origin scores can only be written with --output-format csv or json"));
+    }
+    if pedigree.is_some() && !csv_or_json {
+        return Err(Error::invalid("--pedigree", "mid-parent scores can only be written with --output-format csv or json"));
+    }
+    if sexes.is_some() && !matches!(output_format, OutputFormat::Csv | OutputFormat::Json) {
+        return Err(Error::invalid("--sex", "sex-stratified z-scores can only be written with --output-format csv or json"));
+    }
+    // The comparison reads the thresholded scores back from the results table
+    if args.phenotype.is_some() && output_format != OutputFormat::Csv {
+        return Err(Error::invalid("--phenotype", "choosing a P+T threshold needs --output-format csv"));
+    }
+    if matches!(file_type, FileType::ConsumerGenotypes) {
+        if args.info_field.is_some() {
+            return Err(Error::invalid("--info-field", "a raw genotype export has no INFO fields to read"));
+        }
+        if filter_label.is_some() {
+            let flag = if args.include.is_some() { "--include" } else { "--exclude" };
+            return Err(Error::invalid(flag, "a raw genotype export has no VCF fields to filter on"));
+        }
+    }
+    options.validate(&effect_weights, &args.scoring, &file_type)?;
+    let output_path = args.output.clone().unwrap_or_else(|| default_output_path(&vcf_name, &file_type, output_format));
+
+    let summary = match file_type {
         FileType::SingleSample => {
-            single_sample::calculate_polygenic_score(&args.vcf, &effect_weights)?
+            if args.tui {
+                println!("Note: --tui only applies to multi-sample VCFs; showing normal output.");
+            }
+            if args.sample_fraction.is_some() {
+                println!("Note: --sample-fraction only applies to multi-sample VCFs; scoring the single sample.");
+            }
+            if args.sample_range.is_some() {
+                println!("Note: --sample-range only applies to multi-sample VCFs; scoring the single sample.");
+            }
+            single_sample::calculate_polygenic_score(&input, &effect_weights, &options, &policy)?
+        },
+        FileType::ConsumerGenotypes => {
+            println!("Scoring {} as a raw genotype export", args.vcf);
+            consumer::calculate_polygenic_score(&input, &effect_weights, &options, &policy)?
         },
         FileType::MultiSample => {
-            let output_path = if args.output.is_empty() {
-                format!("{}.csv", args.vcf)
-            } else {
-                args.output.clone()
+            report_sample_names(&input, output_format)?;
+            let checkpoint = args.checkpoint.as_ref().map(|path| CheckpointConfig {
+                path: path.clone(),
+                interval: args.checkpoint_interval,
+            });
+            let subset = match args.sample_fraction {
+                Some(fraction) => Some(sample_subset(&input, fraction, args.seed)?),
+                None => None,
+            };
+            let samples = match &args.sample_range {
+                Some(range) => sample_block(&input, range)?,
+                None => subset.as_deref().map_or(Samples::All, Samples::Subset),
             };
-            multi_sample::calculate_polygenic_score_multi(
-                &args.vcf,
+            let mut sink = sink::open_sink(output_format, &output_path)?;
+            if let (Some(mapping), Some(path)) = (lims.take(), &args.lims_output) {
+                sink = Box::new(Tee(sink, Box::new(LimsSink::create(path, mapping, &args.scoring, effect_weights.len())?)));
+            }
+            if args.reproducible {
+                sink = Box::new(Untimed(sink));
+            }
+            let progress = Progress::default();
+            let mut score = || multi_sample::calculate_polygenic_score_multi(
+                &input,
                 &effect_weights,
-                &output_path,
-                args.info
-            )?
+                &options,
+                samples,
+                Analyses {
+                    loadings: loadings.as_ref(),
+                    thresholds: &pt_thresholds,
+                    permutations: permutations.as_ref(),
+                    partitions: partitions.as_ref(),
+                    sexes: sexes.as_ref(),
+                    environments: environments.as_ref(),
+                    origin: args.parent_of_origin,
+                    pedigree: pedigree.as_ref(),
+                    reference: reference.as_ref(),
+                    scale: scale.as_ref(),
+                    variant_variance: args.variant_variance.as_deref(),
+                    pgs_catalog: pgs_catalog.as_ref(),
+                    panel: panel.as_ref(),
+                },
+                sink.as_mut(),
+                args.info,
+                checkpoint.as_ref(),
+                &policy,
+                args.tui.then_some(&progress),
+            );
+            #[cfg(feature = "tui")]
+            let summary = if args.tui {
+                with_dashboard(&progress, &policy, &effect_weights, &args.vcf, score)?
+            } else {
+                score()?
+            };
+            #[cfg(not(feature = "tui"))]
+            let summary = score()?;
+            println!("Results written to: {}", output_path);
+            if let Some(path) = &args.lims_output {
+                println!("LIMS table written to: {}", path);
+            }
+            summary
         },
     };
 
     let duration = start.elapsed();
+    let score = summary.score;
+    let total_variants = summary.total_variants;
+    let matched_variants = summary.matched_variants;
+    let vcf_chr_format = summary.vcf_chr_format;
 
     match file_type {
-        FileType::SingleSample => {
-            output_results(&args, score, total_variants, matched_variants, duration, effect_weights.len(), vcf_chr_format, scoring_chr_format)?;
+        FileType::SingleSample | FileType::ConsumerGenotypes => {
+            let mut extra = Vec::new();
+            // The sample as the --lims-mapping table sees it; a raw genotype export names none
+            let name = match lims {
+                Some(_) if matches!(file_type, FileType::SingleSample) => read_sample_names_from(&args.vcf, input.peek()?)?.into_iter().next().unwrap_or_default(),
+                _ => String::new(),
+            };
+            let mut sample = SampleScore::single(name, &summary);
+            if let Some(effective) = summary.effective_variants {
+                extra.push(("Effective_Variants", format!("{:.4}", effective)));
+            }
+            if let Some(coverage) = summary.weight_coverage {
+                let rescaled = (coverage > 0.0).then(|| score / coverage);
+                extra.push(("Weight_Coverage", format!("{:.6}", coverage)));
+                extra.push(("Rescaled_Score", rescaled.map_or_else(String::new, |r| format!("{:.6}", r))));
+            }
+            if let Some(reference) = &reference {
+                let (percentile, z) = (reference.percentile(score), reference.z_score(score));
+                extra.push(("Reference_Percentile", format!("{:.4}", percentile)));
+                extra.push(("Reference_Z", z.map_or_else(String::new, |z| format!("{:.6}", z))));
+                (sample.reference_percentile, sample.reference_z) = (Some(percentile), z);
+                if let Some(scale) = &scale {
+                    // A single sample can only be scaled against the reference
+                    let values = scale.values(z, Some(percentile));
+                    (sample.t_score, sample.scale_percentile, sample.risk_category) = (values.t_score, values.percentile, values.risk_category.clone());
+                    if scale.t_scores {
+                        extra.push(("T_Score", values.t_score.map_or_else(String::new, |t| format!("{:.4}", t))));
+                    }
+                    if scale.percentiles {
+                        extra.push(("Percentile", format!("{:.4}", percentile)));
+                    }
+                    if let Some(category) = values.risk_category {
+                        extra.push(("Risk_Category", category));
+                    }
+                }
+            }
+            if let Some(metadata) = &pgs_catalog {
+                extra.extend(metadata.columns());
+            }
+            let written_duration = if args.reproducible { Duration::ZERO } else { duration };
+            output_results(args, &output_path, score, total_variants, matched_variants, written_duration, effect_weights.len(), vcf_chr_format, scoring_chr_format, &extra)?;
+            if let (Some(mapping), Some(path)) = (lims, &args.lims_output) {
+                let run = RunInfo {
+                    vcf_path: input.path(),
+                    duration: written_duration,
+                    pcs: &[],
+                    calibrated: false,
+                    thresholds: &[],
+                    standard_errors: false,
+                    effective_variants: sample.effective_variants.is_some(),
+                    rescaled: sample.weight_coverage.is_some(),
+                    permutations: false,
+                    sets: &[],
+                    sex_strata: None,
+                    interactions: false,
+                    parent_of_origin: false,
+                    trios: None,
+                    reference: args.reference_population.as_deref(),
+                    scale: scale.as_ref(),
+                    pgs_catalog: pgs_catalog.as_ref(),
+                    scores: &[],
+                };
+                let results = ScoreResults { samples: vec![sample], summary: summary.clone() };
+                sink::emit(&results, &run, &mut LimsSink::create(path, mapping, &args.scoring, effect_weights.len())?)?;
+                println!("LIMS table written to: {}", path);
+            }
             println!("Polygenic Score: {}", score);
+            for (column, value) in extra.iter().filter(|(column, _)| !matches!(*column, "Effective_Variants" | "Reference_Z") && !column.starts_with("PGS_")) {
+                println!("{}: {}", column.replace('_', " "), value);
+            }
         },
         FileType::MultiSample => {
             println!("Average Polygenic Score: {}", score);
+            if let Some(coverage) = summary.weight_coverage {
+                println!("Mean weight coverage: {:.2}%", 100.0 * coverage);
+            }
         },
     }
 
     println!("Calculation time: {:?}", duration);
     println!("Total variants processed: {}", total_variants);
     println!("Matched variants: {}", matched_variants);
+    if let Some(effective) = summary.effective_variants {
+        println!("Effective variants (info-weighted): {:.2}", effective);
+    }
+    if policy.count() > 0 {
+        println!("Malformed records skipped: {}", policy.count());
+        for skipped in policy.skipped() {
+            match skipped.first_line {
+                Some(line) => println!("  {}: {} (first at line {}: {:?})", skipped.reason, skipped.count, line, skipped.first_content),
+                None => println!("  {}: {}", skipped.reason, skipped.count),
+            }
+        }
+    }
+    if let (Some(filtered), Some(filter)) = (policy.filtered(), &filter_label) {
+        println!("Records left out by {}: {}", filter, filtered);
+    }
+    if policy.non_finite_count() > 0 {
+        let action = if args.non_finite == NonFinite::Zero { "read as 0" } else { "counted as missing" };
+        println!("Records with NaN or infinite dosages: {} ({})", policy.non_finite_count(), action);
+    }
+    if let (Some(path), Some(rejections)) = (&args.rejected, policy.rejected()) {
+        rejections.write(path)?;
+        println!("Rejected records ({}) written to: {}", rejections.len(), path);
+    }
+    let rate = qc::match_rate(&summary.sites, effect_weights.len());
+    if rate < args.min_match_rate {
+        explain_match_rate(&input, &effect_weights, scoring_build.map(|d| d.build), rate)?;
+    }
+
+    if let Some(path) = &args.provenance {
+        let mut provenance = Provenance::new(input.path(), &args.scoring, effect_weights.len(), options, &summary, duration);
+        if let Some(checksums) = checksums {
+            provenance = provenance.with_checksums(checksums);
+        }
+        if let Some(metadata) = pgs_catalog {
+            provenance = provenance.with_pgs_catalog(metadata);
+        }
+        provenance.write(path)?;
+        println!("Provenance written to: {}", path);
+    }
 
     if args.info {
         print_info(score, total_variants, matched_variants, effect_weights.len(), duration, vcf_chr_format, scoring_chr_format);
     }
 
+    if let (Some(phenotype), Some(phenotype_column)) = (&args.phenotype, &args.phenotype_column) {
+        let template = EvaluateArgs {
+            scores: output_path.clone(),
+            score_column: String::new(),
+            phenotype: phenotype.clone(),
+            phenotype_column: phenotype_column.clone(),
+            covariates: args.covariates.clone(),
+            trait_type: TraitType::Auto,
+            prevalence: None,
+            output: None,
+        };
+        thresholding::select_best(&pt_thresholds, &effect_weights, &template)?;
+    }
+    uploads.finish()?;
+
+    if args.strict {
+        let thresholds = QcThresholds {
+            min_match_rate: args.min_match_rate,
+            min_allele_concordance: args.min_allele_concordance,
+        };
+        let failures = qc::check(&summary.sites, effect_weights.len(), &thresholds);
+        if !failures.is_empty() {
+            return Err(Error::Qc(failures));
+        }
+    }
+
     Ok(())
 }
@@ -0,0 +1,147 @@
+//! Prometheus metrics for the long-running modes, for operations teams to monitor them:
+//! `serve` exposes them at `GET /metrics`, and `watch` at `--metrics-bind`.
+//!
+//! A job is one VCF scored against one scoring file. Counters cover finished jobs; the
+//! queue depth is the jobs waiting or running: score requests in progress for `serve`, and
+//! VCFs in the intake directory not yet scored for `watch`.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+#[cfg(feature = "server")]
+use std::sync::Arc;
+use std::sync::Mutex;
+#[cfg(feature = "server")]
+use std::thread;
+use std::time::Duration;
+#[cfg(feature = "server")]
+use tiny_http::{Header, Method, Response, Server};
+#[cfg(feature = "server")]
+use crate::error::{Error, Result};
+use crate::scorer::ScoreResults;
+
+/// The content type of [`Metrics::render`].
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Upper bounds of the job duration histogram's buckets, in seconds.
+const DURATION_BUCKETS: [f64; 12] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0];
+
+/// Counters and histograms of the jobs a process has run. Shared between threads.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    /// VCF records read by successful jobs
+    lines: AtomicU64,
+    /// VCF records matching a scoring variant's position and alleles
+    matched: AtomicU64,
+    samples: AtomicU64,
+    queue_depth: AtomicUsize,
+    durations: Mutex<Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations at or below each of [`DURATION_BUCKETS`], not cumulative
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// A job counted in the queue depth until dropped.
+pub struct InFlight<'a>(&'a Metrics);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn job_succeeded(&self, duration: Duration, results: &ScoreResults) {
+        self.succeeded.fetch_add(1, Ordering::Relaxed);
+        // Every sample sees every record, so the summary's totals are per sample
+        let lines = results.samples.iter().map(|sample| sample.total_variants).max().unwrap_or(0);
+        self.lines.fetch_add(lines, Ordering::Relaxed);
+        self.matched.fetch_add(results.summary.sites.allele_hits, Ordering::Relaxed);
+        self.samples.fetch_add(results.samples.len() as u64, Ordering::Relaxed);
+        self.observe(duration);
+    }
+
+    pub fn job_failed(&self, duration: Duration) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        self.observe(duration);
+    }
+
+    /// Count a job in the queue depth for as long as the returned guard lives.
+    pub fn in_flight(&self) -> InFlight<'_> {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+        InFlight(self)
+    }
+
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut histogram = self.durations.lock().unwrap();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = writeln!(out, "# HELP speedscore_{} {}\n# TYPE speedscore_{} {}", name, help, name, kind);
+        };
+        header(&mut out, "jobs_total", "counter", "Scoring jobs finished, by outcome.");
+        let _ = writeln!(out, "speedscore_jobs_total{{outcome=\"success\"}} {}", load(&self.succeeded));
+        let _ = writeln!(out, "speedscore_jobs_total{{outcome=\"failure\"}} {}", load(&self.failed));
+        header(&mut out, "lines_processed_total", "counter", "VCF records read by successful jobs.");
+        let _ = writeln!(out, "speedscore_lines_processed_total {}", load(&self.lines));
+        header(&mut out, "variants_matched_total", "counter", "VCF records matching a scoring variant, in successful jobs.");
+        let _ = writeln!(out, "speedscore_variants_matched_total {}", load(&self.matched));
+        header(&mut out, "samples_scored_total", "counter", "Samples scored by successful jobs.");
+        let _ = writeln!(out, "speedscore_samples_scored_total {}", load(&self.samples));
+        header(&mut out, "queue_depth", "gauge", "Jobs waiting or running.");
+        let _ = writeln!(out, "speedscore_queue_depth {}", self.queue_depth.load(Ordering::Relaxed));
+
+        let histogram = self.durations.lock().unwrap();
+        header(&mut out, "job_duration_seconds", "histogram", "Time taken by finished jobs.");
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "speedscore_job_duration_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative);
+        }
+        let _ = writeln!(out, "speedscore_job_duration_seconds_bucket{{le=\"+Inf\"}} {}", histogram.count);
+        let _ = writeln!(out, "speedscore_job_duration_seconds_sum {}", histogram.sum);
+        let _ = writeln!(out, "speedscore_job_duration_seconds_count {}", histogram.count);
+        out
+    }
+}
+
+/// Serve `metrics` at `http://{bind}/metrics` from a thread of its own.
+#[cfg(feature = "server")]
+pub fn serve(bind: &str, metrics: Arc<Metrics>) -> Result<()> {
+    let server = Server::http(bind).map_err(|e| Error::invalid(bind, e.to_string()))?;
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match (request.method(), request.url()) {
+                (Method::Get, "/metrics") => {
+                    let content_type = Header::from_bytes(&b"Content-Type"[..], CONTENT_TYPE.as_bytes()).unwrap();
+                    Response::from_string(metrics.render()).with_header(content_type)
+                }
+                _ => Response::from_string("not found\n").with_status_code(404),
+            };
+            if let Err(e) = request.respond(response) {
+                eprintln!("Warning: failed to send response: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
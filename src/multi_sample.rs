@@ -1,135 +1,595 @@
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
+use crate::common::{dedupe_sample_names, header_sample_names, total_abs_weight, trim_line, weight_share, EffectWeight, EffectWeights, ScoreOptions, ScoreSummary, SiteCounts};
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
 use std::time::Instant;
-use std::path::Path;
-use flate2::read::MultiGzDecoder;
 use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use crate::aliases;
+use crate::ancestry::{Loading, Loadings};
+use crate::catalog::ScoreMetadata;
+use crate::contribution::{self, VariantContribution};
+use crate::gxe::Environments;
+use crate::origin::HaplotypeOrder;
+use crate::panel::Panel;
+use crate::partition::Partitions;
+use crate::permutation::Permutations;
+use crate::reference::Distribution;
+use crate::scale::{self, ReportScale};
+use crate::sex::{self, Sexes};
+use crate::checkpoint::{self, Checkpoint, CheckpointConfig, Fingerprint};
+use crate::concat;
+use crate::error::{Error, ErrorPolicy, Result};
+use crate::htsget;
+use crate::input::VcfInput;
+use crate::pgen;
+use crate::progress::Progress;
+use crate::resources;
+use crate::rejected::{allele_mismatch, Input, Reason};
+use crate::rng::Rng;
+use crate::scorer::ScoreResults;
+use crate::source::{self, GenotypeSource};
+use crate::sink::{self, RunInfo, ScoreSink};
+use crate::spans::Stage;
+use crate::tabix;
+use crate::thresholding;
+use crate::trio::{self, Pedigree};
 
-#[derive(Debug)]
-pub enum VcfError {
-    Io(io::Error),
-    Utf8Error(std::string::FromUtf8Error),
+/// Running totals for one sample.
+#[derive(Clone, Default, Debug)]
+pub struct SampleData {
+    pub score: f64,
+    pub matched_variants: u64,
+    pub total_variants: u64,
+    /// Effect-allele count (or dosage) summed over called genotypes, before the model
+    pub dosage_sum: f64,
+    /// Imputation quality summed over called genotypes; `None` without `--info-field`
+    pub effective_variants: Option<f64>,
+    /// Share of the total |weight| at called genotypes; `None` without `--rescale-by-coverage`
+    pub weight_coverage: Option<f64>,
+    /// Variance of the score from the weights' standard errors; `None` without them
+    pub variance: Option<f64>,
+    /// Coordinates on the `--pca-loadings` PCs; empty without loadings
+    pub pcs: Vec<f64>,
+    /// Score over the variants passing each `--pt-thresholds` threshold; empty without thresholds
+    pub threshold_scores: Vec<f64>,
+    /// Each `--permutations` null score minus `score`; empty without permutations
+    pub null_deviations: Vec<f64>,
+    /// Partial score over each `--set-column`/`--set-bed` set; empty without sets
+    pub set_scores: Vec<f64>,
+    /// Score over each `--scoring` file after the first; empty with one
+    pub panel_scores: Vec<f64>,
+    /// Matched variants of each `--scoring` file after the first; empty with one
+    pub panel_matched: Vec<u64>,
+    /// `interaction_weight` times the genotype term, summed; `None` without `--environment`
+    pub interaction_sum: Option<f64>,
+    /// Maternal and paternal scores; `None` without `--parent-of-origin`
+    pub maternal_score: Option<f64>,
+    pub paternal_score: Option<f64>,
 }
 
-impl std::fmt::Display for VcfError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            VcfError::Io(err) => write!(f, "I/O error: {}", err),
-            VcfError::Utf8Error(err) => write!(f, "UTF-8 error: {}", err),
+/// Per-sample results of a multi-sample run, in VCF header order.
+pub struct MultiSampleScores {
+    pub sample_names: Vec<String>,
+    pub sample_data: Vec<SampleData>,
+    pub summary: ScoreSummary,
+    /// Per-variant variance sums, in VCF order; empty without `--variant-variance`
+    pub contributions: Vec<VariantContribution>,
+}
+
+/// Everything a record is scored with besides the record itself and the accumulators.
+#[derive(Clone, Copy)]
+struct LineContext<'a> {
+    input: &'a VcfInput,
+    vcf_path: &'a str,
+    effect_weights: &'a EffectWeights,
+    options: &'a ScoreOptions,
+    policy: &'a ErrorPolicy,
+    samples: Samples<'a>,
+    analyses: Analyses<'a>,
+    /// The scoring file's summed |effect_weight|, for weight coverage
+    total_weight: f64,
+}
+
+/// Optional per-sample analyses computed in the same pass as the scores.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Analyses<'a> {
+    /// Reference PCs to project samples onto, adjusting scores for them (`--pca-loadings`)
+    pub loadings: Option<&'a Loadings>,
+    /// Ascending p-value thresholds to score separately (`--pt-thresholds`); see [`crate::thresholding`]
+    pub thresholds: &'a [f64],
+    /// Weight shuffles to score each sample's empirical null under (`--permutations`)
+    pub permutations: Option<&'a Permutations>,
+    /// Variant sets to score separately (`--set-column`, `--set-bed`); see [`crate::partition`]
+    pub partitions: Option<&'a Partitions>,
+    /// Sample sexes to normalize scores within (`--sex`); see [`crate::sex`]
+    pub sexes: Option<&'a Sexes>,
+    /// Sample exposures for gene-environment terms (`--environment`); see [`crate::gxe`]
+    pub environments: Option<&'a Environments>,
+    /// Haplotype order of phased calls, for parent-of-origin scores (`--parent-of-origin`); see [`crate::origin`]
+    pub origin: Option<HaplotypeOrder>,
+    /// Pedigree for mid-parent scores and the pTDT (`--pedigree`); see [`crate::trio`]
+    pub pedigree: Option<&'a Pedigree>,
+    /// Reference distribution to place scores in (`--reference`); see [`crate::reference`]
+    pub reference: Option<&'a Distribution>,
+    /// Reporting scales to add (`--t-scores`, `--percentiles`, `--risk-cutoffs`); see [`crate::scale`]
+    pub scale: Option<&'a ReportScale>,
+    /// Where to write each variant's contribution to the score variance (`--variant-variance`); see [`crate::contribution`]
+    pub variant_variance: Option<&'a str>,
+    /// The PGS Catalog's description of the score, recorded with the results (`--pgs-catalog`); see [`crate::catalog`]
+    pub pgs_catalog: Option<&'a ScoreMetadata>,
+    /// The scoring files scored along with the first (several `--scoring` files); see [`crate::panel`]
+    pub panel: Option<&'a Panel>,
+}
+
+/// Which of a VCF's samples to score.
+#[derive(Clone, Copy, Debug)]
+pub enum Samples<'a> {
+    All,
+    /// Header positions `start..end`, clamped to the sample count
+    Block { start: usize, end: usize },
+    /// Header positions, e.g. from [`random_subset`]; scored in header order whatever
+    /// order they are given in
+    Subset(&'a [usize]),
+}
+
+impl Samples<'_> {
+    /// Header positions (0-based, among the samples) to score in a VCF with `count` samples,
+    /// ascending, so that output rows always follow the header.
+    fn columns(&self, count: usize) -> Vec<usize> {
+        match *self {
+            Samples::All => (0..count).collect(),
+            Samples::Block { start, end } => (start.min(count)..end.min(count)).collect(),
+            Samples::Subset(indices) => {
+                let mut columns: Vec<usize> = indices.iter().copied().filter(|&i| i < count).collect();
+                columns.sort_unstable();
+                columns.dedup();
+                columns
+            }
         }
     }
 }
 
-impl std::error::Error for VcfError {}
+/// A reproducible random `fraction` of `count` sample positions, in header order and
+/// never empty. The same `seed` always picks the same samples.
+pub fn random_subset(count: usize, fraction: f64, seed: u64) -> Vec<usize> {
+    let wanted = match count {
+        0 => 0,
+        _ => ((count as f64 * fraction).round() as usize).clamp(1, count),
+    };
+    let mut rng = Rng(seed);
+    let mut chosen = Vec::with_capacity(wanted);
+    // Selection sampling (Knuth's Algorithm S): one pass, output already sorted
+    for index in 0..count {
+        let remaining = count - index;
+        if (rng.next_f64() * remaining as f64) < (wanted - chosen.len()) as f64 {
+            chosen.push(index);
+        }
+    }
+    chosen
+}
 
-impl From<io::Error> for VcfError {
-    fn from(error: io::Error) -> Self {
-        VcfError::Io(error)
+/// Parse a `--sample-range` such as `1-50000` (1-based, inclusive) into header positions.
+pub fn parse_sample_range(text: &str) -> std::result::Result<Range<usize>, String> {
+    let invalid = || format!("invalid sample range {:?}; expected FIRST-LAST, e.g. 1-50000", text);
+    let (first, last) = text.split_once('-').ok_or_else(invalid)?;
+    let first: usize = first.trim().parse().map_err(|_| invalid())?;
+    let last: usize = last.trim().parse().map_err(|_| invalid())?;
+    if first == 0 || last < first {
+        return Err(format!("invalid sample range {:?}; positions start at 1 and LAST must not be below FIRST", text));
     }
+    Ok(first - 1..last)
 }
 
-impl From<std::string::FromUtf8Error> for VcfError {
-    fn from(error: std::string::FromUtf8Error) -> Self {
-        VcfError::Utf8Error(error)
+/// Score the selected samples in a multi-sample VCF, run the requested `analyses` in the
+/// same pass, and emit the per-sample results to `sink`. With `progress`, live state is
+/// published there instead of drawing a spinner.
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_polygenic_score_multi(
+    input: &VcfInput,
+    effect_weights: &EffectWeights,
+    options: &ScoreOptions,
+    samples: Samples,
+    analyses: Analyses,
+    sink: &mut dyn ScoreSink,
+    debug: bool,
+    checkpoint: Option<&CheckpointConfig>,
+    policy: &ErrorPolicy,
+    progress: Option<&Progress>,
+) -> Result<ScoreSummary> {
+    let start_time = Instant::now();
+
+    let total_weight = total_abs_weight(effect_weights);
+    let vcf_path = input.path();
+    let ctx = LineContext { input, vcf_path, effect_weights, options, policy, samples, analyses, total_weight };
+    let Analyses { loadings, thresholds, permutations, partitions, sexes, environments, origin, pedigree, reference, scale, variant_variance, pgs_catalog, panel } = analyses;
+    let mut scores = score_samples(&ctx, debug, checkpoint, progress)?;
+
+    let duration = start_time.elapsed();
+
+    // Exposures are looked up by name, which the results no longer pair with the sums
+    let interactions: Option<Vec<Option<f64>>> = environments.map(|environments| {
+        let sums = scores.sample_data.iter().map(|data| data.interaction_sum.unwrap_or(0.0));
+        scores.sample_names.iter().zip(sums).map(|(name, sum)| environments.get(name).map(|e| e * sum)).collect()
+    });
+    let mut contributions = std::mem::take(&mut scores.contributions);
+    let mut results = ScoreResults::from(scores);
+    if let Some(interactions) = &interactions {
+        for (sample, &interaction) in results.samples.iter_mut().zip(interactions) {
+            sample.interaction_score = interaction;
+            sample.gxe_score = interaction.map(|interaction| sample.score + interaction);
+        }
+        if progress.is_none() {
+            let missing = interactions.iter().filter(|i| i.is_none()).count();
+            if missing > 0 {
+                println!("Note: {} samples have no --environment value; their interaction and GxE scores are left blank.", missing);
+            }
+        }
     }
+    if let Some(calibration) = loadings.and_then(|l| l.calibration.as_ref()) {
+        for sample in &mut results.samples {
+            sample.calibrated_z = Some(calibration.z_score(sample.score, &sample.pcs));
+        }
+    }
+    if loadings.is_some() && progress.is_none() && results.samples.iter().any(|s| s.adjusted_score.is_none()) {
+        println!("Note: too few samples to regress scores on the PCs; adjusted scores are left blank.");
+    }
+    if let Some(reference) = reference {
+        for sample in &mut results.samples {
+            sample.reference_percentile = Some(reference.percentile(sample.score));
+            sample.reference_z = reference.z_score(sample.score);
+        }
+    }
+    if let Some(scale) = scale {
+        scale::apply(&mut results.samples, scale);
+    }
+    let sex_strata = sexes.map(|sexes| sex::normalize(&mut results.samples, sexes));
+    if let Some(strata) = &sex_strata {
+        if progress.is_none() {
+            for stratum in strata {
+                println!("{:?} samples: {}, score mean {:.6}, SD {:.6}", stratum.sex, stratum.samples, stratum.mean, stratum.sd);
+            }
+            if results.samples.iter().any(|s| s.sex_z.is_none()) {
+                println!("Note: samples of unknown sex, or of a sex with too few samples, get no sex-stratified z-score.");
+            }
+        }
+    }
+    let trios = pedigree.map(|pedigree| trio::analyze(&mut results.samples, pedigree));
+    if let Some(trios) = &trios {
+        if progress.is_none() {
+            match (trios.mean_deviation, trios.t, trios.p_value) {
+                (Some(mean), Some(t), Some(p)) => {
+                    println!("pTDT over {} trios: mean offspring deviation {:.4} mid-parent SDs, t = {:.4}, p = {:.4e}", trios.trios, mean, t, p);
+                }
+                _ => println!("Note: {} complete trios scored; the pTDT needs at least two, with varying mid-parent scores.", trios.trios),
+            }
+        }
+    }
+    let pcs = loadings.map_or(&[][..], |l| &l.names);
+    let calibrated = loadings.is_some_and(|l| l.calibration.is_some());
+    let standard_errors = has_standard_errors(effect_weights);
+    let run = RunInfo {
+        vcf_path,
+        duration,
+        pcs,
+        calibrated,
+        thresholds,
+        standard_errors,
+        effective_variants: options.info_field.is_some(),
+        rescaled: options.rescale_by_coverage,
+        permutations: permutations.is_some(),
+        sets: partitions.map_or(&[][..], |p| &p.names),
+        sex_strata: sex_strata.as_deref(),
+        interactions: environments.is_some(),
+        parent_of_origin: origin.is_some(),
+        trios: trios.as_ref(),
+        reference: reference.map(|r| r.population.as_str()),
+        scale,
+        pgs_catalog,
+        scores: panel.map_or(&[][..], |p| &p.ids),
+    };
+    sink::emit(&results, &run, sink)?;
+    if let Some(path) = variant_variance {
+        let n = results.samples.len() as f64;
+        let mean = results.samples.iter().map(|s| s.score).sum::<f64>() / n;
+        let score_variance = results.samples.iter().map(|s| (s.score - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        contribution::write_report(path, &mut contributions, options.model, score_variance)?;
+        if progress.is_none() {
+            let explained: f64 = contributions.iter().filter_map(VariantContribution::empirical_variance).sum();
+            println!(
+                "Per-variant variances written to: {} ({} variants; they sum to {:.6}, the score variance is {:.6})",
+                path,
+                contributions.len(),
+                explained,
+                score_variance
+            );
+        }
+    }
+
+    // Results are safely on disk; a stale checkpoint would only cause confusion on the next run
+    if let Some(checkpoint) = checkpoint {
+        checkpoint::remove(&checkpoint.path)?;
+    }
+
+    if progress.is_none() {
+        println!("Processing time: {:?}", duration);
+    }
+
+    Ok(results.summary)
 }
 
-#[derive(Clone, Default)]
-struct SampleData {
-    score: f64,
-    matched_variants: usize,
-    total_variants: usize,
+/// Score every sample in a multi-sample VCF, returning the per-sample totals without writing anything.
+pub fn score_multi(
+    input: &VcfInput,
+    effect_weights: &EffectWeights,
+    options: &ScoreOptions,
+    debug: bool,
+    checkpoint: Option<&CheckpointConfig>,
+    policy: &ErrorPolicy,
+    progress: Option<&Progress>,
+) -> Result<MultiSampleScores> {
+    let total_weight = total_abs_weight(effect_weights);
+    let ctx = LineContext { input, vcf_path: input.path(), effect_weights, options, policy, samples: Samples::All, analyses: Analyses::default(), total_weight };
+    score_samples(&ctx, debug, checkpoint, progress)
 }
 
-fn open_vcf_reader(path: &str) -> Result<BufReader<MultiGzDecoder<File>>, VcfError> {
-    let file = File::open(path).map_err(VcfError::Io)?;
-    let decoder = MultiGzDecoder::new(file);
-    Ok(BufReader::with_capacity(1024 * 1024, decoder)) // 1MB buffer
+/// Score only the samples at header positions `samples` (clamped to the sample count).
+/// Lets callers bound memory and get finished scores early by making one pass per block.
+pub fn score_sample_block(
+    input: &VcfInput,
+    effect_weights: &EffectWeights,
+    options: &ScoreOptions,
+    policy: &ErrorPolicy,
+    samples: Range<usize>,
+) -> Result<MultiSampleScores> {
+    let samples = Samples::Block { start: samples.start, end: samples.end };
+    let total_weight = total_abs_weight(effect_weights);
+    let ctx = LineContext { input, vcf_path: input.path(), effect_weights, options, policy, samples, analyses: Analyses::default(), total_weight };
+    score_samples(&ctx, false, None, None)
 }
 
-pub fn calculate_polygenic_score_multi(
-    vcf_path: &str,
-    effect_weights: &HashMap<(String, u32), (String, f32)>,
-    output_path: &str,
-    debug: bool
-) -> Result<(f64, usize, usize, bool), VcfError> {
-    let start_time = Instant::now();
+fn score_samples(
+    ctx: &LineContext,
+    debug: bool,
+    checkpoint: Option<&CheckpointConfig>,
+    progress: Option<&Progress>,
+) -> Result<MultiSampleScores> {
+    let LineContext { vcf_path, effect_weights, .. } = *ctx;
+    // A dashboard owns the terminal; printing underneath it would garble the display
+    let verbose = progress.is_none();
 
-    println!("Opening file: {}", vcf_path);
-    println!("Effect weights loaded: {} variants", effect_weights.len());
+    if verbose {
+        println!("Opening file: {}", vcf_path);
+        println!("Effect weights loaded: {} variants", effect_weights.len());
+    }
+    if ctx.input.file().is_some_and(pgen::is_pgen) && genotypes_only(ctx, checkpoint, progress) {
+        return score_fileset(ctx, verbose);
+    }
+    let stage = Stage::scan_vcf(vcf_path);
 
-    let mut reader = open_vcf_reader(vcf_path)?;
+    // An indexed VCF or htsget dataset is only read around the scoring variants, those of
+    // the other scoring files and ancestry loadings
+    let loading_positions = ctx.analyses.loadings.map(|l| l.variants.keys());
+    let panel_positions = ctx.analyses.panel.map(Panel::positions);
+    let positions = effect_weights.keys().chain(panel_positions.into_iter().flatten()).chain(loading_positions.into_iter().flatten());
+    let config = ctx.input.config();
+    let mut reader = match ctx.input.file() {
+        Some(file) => match tabix::open(file, positions.clone(), config)? {
+            Some(indexed) => {
+                if verbose {
+                    println!(
+                        "Reading through index {}: {} of the {} VCF",
+                        indexed.index,
+                        resources::format_size(indexed.span),
+                        resources::format_size(indexed.file_size)
+                    );
+                }
+                indexed.reader
+            }
+            None if htsget::is_endpoint(file, config) => htsget::open(file, ctx.input.peek()?, positions)?,
+            None => ctx.input.open()?,
+        },
+        None => Box::new(concat::open(ctx.input.paths(), Some(positions.cloned().collect()), config)?),
+    };
     let mut header_line = String::new();
     let sample_names: Vec<String>;
+    let columns: Vec<usize>;
+    let header_samples: usize;
 
     // Find the header
+    let mut header_lines = 0;
     loop {
-        reader.read_line(&mut header_line)?;
-        if header_line.starts_with("#CHROM") {
-            sample_names = header_line.split_whitespace().skip(9).map(String::from).collect();
+        header_lines += 1;
+        if reader.read_line(&mut header_line).map_err(|e| Error::read(vcf_path, header_lines, e))? == 0 {
+            return Err(Error::invalid(vcf_path, "VCF header (#CHROM line) not found"));
+        }
+        if trim_line(&header_line).starts_with("#CHROM") {
+            let mut all_samples = header_sample_names(&header_line);
+            dedupe_sample_names(&mut all_samples);
+            header_samples = all_samples.len();
+            columns = ctx.samples.columns(header_samples);
+            sample_names = columns.iter().map(|&i| all_samples[i].clone()).collect();
             break;
         }
         header_line.clear();
     }
+    // A variance needs two scores to be estimated from
+    if ctx.analyses.variant_variance.is_some() && sample_names.len() < 2 {
+        return Err(Error::invalid("--variant-variance", format!("the score variance needs at least two samples, and {} scores {}", vcf_path, sample_names.len())));
+    }
 
-    println!("VCF data start found.");
-    println!("Sample count: {}", sample_names.len());
-    println!("Processing variants...");
-
-    let pb = ProgressBar::new_spinner();
+    let pb = if verbose {
+        println!("VCF data start found.");
+        println!("Sample count: {}", sample_names.len());
+        println!("Processing variants...");
+        ProgressBar::new_spinner()
+    } else {
+        ProgressBar::hidden()
+    };
     pb.set_style(ProgressStyle::default_spinner()
         .template("{spinner:.green} [{elapsed_precise}] {msg}")
         .unwrap());
     pb.set_message("Processing...");
 
-    let mut buffer = Vec::new();
-    let mut sample_data: Vec<SampleData> = vec![SampleData::default(); sample_names.len()];
+    let pc_count = ctx.analyses.loadings.map_or(0, |l| l.names.len());
+    let sample_data_init = SampleData {
+        variance: has_standard_errors(effect_weights).then_some(0.0),
+        effective_variants: ctx.options.info_field.is_some().then_some(0.0),
+        weight_coverage: ctx.options.rescale_by_coverage.then_some(0.0),
+        pcs: vec![0.0; pc_count],
+        threshold_scores: vec![0.0; ctx.analyses.thresholds.len()],
+        null_deviations: vec![0.0; ctx.analyses.permutations.map_or(0, |p| p.count)],
+        set_scores: vec![0.0; ctx.analyses.partitions.map_or(0, |p| p.names.len())],
+        panel_scores: vec![0.0; ctx.analyses.panel.map_or(0, Panel::others)],
+        panel_matched: vec![0; ctx.analyses.panel.map_or(0, Panel::others)],
+        interaction_sum: ctx.analyses.environments.is_some().then_some(0.0),
+        maternal_score: ctx.analyses.origin.is_some().then_some(0.0),
+        paternal_score: ctx.analyses.origin.is_some().then_some(0.0),
+        ..Default::default()
+    };
+    let mut sample_data: Vec<SampleData> = vec![sample_data_init.clone(); sample_names.len()];
     let mut lines_processed = 0;
     let mut last_chr = String::new();
     let mut last_pos = 0;
     let mut vcf_chr_format = false;
+    let mut sites = SiteCounts::default();
+    let mut contributions = Vec::new();
+    let mut sort_check = ctx.policy.sort_check();
+    // Chromosome last reported to `progress`, and the latest position seen
+    let mut last_progress_chr = String::new();
+    let mut last_progress_pos = 0;
 
+    // Pick up where a previous (killed) run left off
+    let fingerprint = checkpoint.map(|_| Fingerprint::of(effect_weights, &ctx.analyses, ctx.options, ctx.policy));
+    if let (Some(CheckpointConfig { path, .. }), Some(fingerprint)) = (checkpoint, &fingerprint) {
+        if let Some(saved) = Checkpoint::load(path)? {
+            saved.validate(path, ctx.input.paths(), effect_weights.len(), fingerprint, &sample_names, &sample_data_init)?;
+            if verbose {
+                println!("Resuming from checkpoint {} at line {}", path, saved.lines_processed);
+            }
+            for (data, (_, saved_data)) in sample_data.iter_mut().zip(saved.samples) {
+                *data = saved_data;
+            }
+            vcf_chr_format = saved.vcf_chr_format;
+            sites = saved.sites;
+            ctx.policy.restore(saved.policy);
+            sort_check.resume(saved.sort);
+            let mut buffer = Vec::new();
+            while lines_processed < saved.lines_processed {
+                buffer.clear();
+                let line_number = header_lines + lines_processed + 1;
+                if reader.read_until(b'\n', &mut buffer).map_err(|e| Error::read(vcf_path, line_number, e))? == 0 {
+                    return Err(Error::invalid(vcf_path, "VCF is shorter than the checkpointed position"));
+                }
+                lines_processed += 1;
+            }
+        }
+    }
+
+    let tuning = resources::current();
+    let mut batch = LineBatch::default();
     loop {
-        buffer.clear();
-        let num_lines = reader.read_until(b'\n', &mut buffer)?;
-        if num_lines == 0 {
+        // Batches end where checkpoints are due, so they fall on the same lines as ever
+        let mut max_lines = tuning.line_batch;
+        if let Some(CheckpointConfig { interval, .. }) = checkpoint.filter(|c| c.interval > 0) {
+            max_lines = max_lines.min(interval - lines_processed % interval);
+        }
+        let first_line = header_lines + lines_processed + 1;
+        batch.fill(&mut reader, max_lines, tuning.record_batch, vcf_path, first_line)?;
+        if batch.is_empty() {
             break;
         }
-    
-        lines_processed += 1;
-    
-        if !buffer.starts_with(&[b'#']) {
-            let result = process_chunk(&buffer, effect_weights, &mut sample_data, debug);
-            if let Some((chr, pos, chr_format)) = result {
-                if debug && (chr != last_chr || pos > last_pos + 20_000_000) {
-                    pb.suspend(|| {
-                        println!(
-                            "\rProcessed up to Chr {}, Pos {:.2}M",
-                            chr,
-                            pos as f64 / 1_000_000.0
-                        );
-                        io::stdout().flush().unwrap();
-                    });
-                    last_chr = chr;
-                    last_pos = pos;
+        let lines_before = lines_processed;
+
+        // Splitting records into fields is the bulk of the per-line work, so it is shared out
+        let splits = batch.split();
+
+        // The error policy, order check and site counts see the records in file order
+        let mut records = Vec::new();
+        for (offset, split) in splits.into_iter().enumerate() {
+            let line_number = first_line + offset;
+            lines_processed += 1;
+            let (line, parts) = match split {
+                Split::Skip => continue,
+                Split::NotUtf8(line) => {
+                    let lossy = String::from_utf8_lossy(line);
+                    ctx.policy.handle_record(Error::parse(vcf_path, line_number, "Invalid UTF-8", &lossy), &lossy)?;
+                    continue;
                 }
-                if lines_processed == 1 {
-                    vcf_chr_format = chr_format;
+                Split::Fields(line, parts) => (line, parts),
+            };
+            let Some(record) = prepare(ctx, line_number, line, parts, header_samples, &mut sites)? else {
+                continue;
+            };
+            let chr = record.fields[0].trim();
+            let pos = record.pos;
+            sort_check.check(vcf_path, line_number, chr, pos, line)?;
+            if let Some(progress) = progress {
+                if lines_processed % 1_000 == 0 || chr != last_progress_chr {
+                    progress.record(lines_processed, chr, pos, sites);
+                    last_progress_chr = chr.to_string();
+                }
+                last_progress_pos = pos;
+            }
+            if verbose && debug && (chr != last_chr || pos > last_pos + 20_000_000) {
+                pb.suspend(|| {
+                    println!(
+                        "\rProcessed up to Chr {}, Pos {:.2}M",
+                        chr,
+                        pos as f64 / 1_000_000.0
+                    );
+                    io::stdout().flush().unwrap();
+                });
+                last_chr = chr.to_string();
+                last_pos = pos;
+            }
+            if lines_processed == 1 {
+                vcf_chr_format = chr.starts_with("chr");
+            }
+            records.push(record);
+        }
+
+        // Centering and mean imputation need each record's effect-allele frequency first
+        records.par_iter_mut().for_each(|record| {
+            if let Some(scored) = &mut record.scored {
+                scored.center(&record.fields, ctx.options);
+            }
+        });
+
+        // Each block of samples takes the batch's records in file order, so every sample's
+        // sums are added up in the same order as when scoring one line at a time
+        let partials: Vec<Vec<Partial>> = sample_data
+            .par_chunks_mut(SAMPLE_BLOCK)
+            .zip(columns.par_chunks(SAMPLE_BLOCK))
+            .map(|(block, block_columns)| score_block(&records, ctx, block_columns, block))
+            .collect();
+        for (index, record) in records.into_iter().enumerate() {
+            let Some(scored) = record.scored else {
+                continue;
+            };
+            let mut non_finite = false;
+            let mut contribution = scored.contribution;
+            for partial in partials.iter().map(|block| &block[index]) {
+                non_finite |= partial.non_finite;
+                if let Some(contribution) = &mut contribution {
+                    partial.add_to(contribution);
                 }
             }
+            contributions.extend(contribution);
+            if non_finite {
+                ctx.policy.non_finite(ctx.options.non_finite, Error::parse(vcf_path, record.line_number, "NaN or infinite dosage", record.line), record.line)?;
+            }
         }
-    
-        if lines_processed % 100_000 == 0 {
+
+        if lines_processed / 100_000 > lines_before / 100_000 {
             let lines_in_k = lines_processed / 1000;
             let variants = sample_data
                 .iter()
                 .map(|sd| sd.total_variants)
-                .sum::<usize>();
+                .sum::<u64>();
             let matched = sample_data
                 .iter()
                 .map(|sd| sd.matched_variants)
-                .sum::<usize>();
+                .sum::<u64>();
             pb.set_message(format!(
                 "{}K lines, {}K variants, {}K matched",
                 lines_in_k,
@@ -137,164 +597,541 @@ pub fn calculate_polygenic_score_multi(
                 matched / 1000
             ));
         }
+
+        if let (Some(CheckpointConfig { path, interval }), Some(fingerprint)) = (checkpoint, &fingerprint) {
+            if *interval > 0 && lines_processed % interval == 0 {
+                let checkpoint = Checkpoint {
+                    vcf_paths: ctx.input.paths().to_vec(),
+                    scoring_variants: effect_weights.len(),
+                    fingerprint: fingerprint.clone(),
+                    lines_processed,
+                    vcf_chr_format,
+                    sites,
+                    policy: ctx.policy.state(),
+                    sort: sort_check.position(),
+                    samples: sample_names.iter().cloned().zip(sample_data.iter().cloned()).collect(),
+                };
+                checkpoint.save(path)?;
+            }
+        }
     }
     pb.finish_with_message("Processing complete");
+    if let Some(progress) = progress {
+        progress.record(lines_processed, &last_progress_chr, last_progress_pos, sites);
+    }
 
-    let duration = start_time.elapsed();
-
-    write_csv_output(output_path, vcf_path, &sample_names, &sample_data, duration)?;
+    if let Some(panel) = ctx.analyses.panel {
+        for (index, id) in panel.ids[1..].iter().enumerate() {
+            if sample_data.iter().all(|sd| sd.panel_matched[index] == 0) {
+                eprintln!("Warning: scoring file {} matched no variants in the VCF; its score is 0 for every sample", id);
+            }
+        }
+    }
 
     let avg_score = sample_data.iter().map(|sd| sd.score).sum::<f64>() / sample_data.len() as f64;
     let total_variants = sample_data.iter().map(|sd| sd.total_variants).sum();
     let matched_variants = sample_data.iter().map(|sd| sd.matched_variants).sum();
+    let dosage_sum = sample_data.iter().map(|sd| sd.dosage_sum).sum();
+    let effective_variants = sample_data.iter().map(|sd| sd.effective_variants).sum();
+    let weight_coverage = mean_coverage(&sample_data);
 
-    println!("\nFinished processing.");
-    println!("Total lines processed: {:.3}K", lines_processed as f64 / 1000.0);
-    println!("Results written to: {}", output_path);
-    println!("Processing time: {:?}", duration);
+    if verbose {
+        println!("\nFinished processing.");
+        println!("Total lines processed: {:.3}K", lines_processed as f64 / 1000.0);
+    }
+    stage.record("samples", sample_names.len() as u64);
+    stage.record("variants", lines_processed as u64);
+    stage.record("matched", sites.allele_hits);
 
-    Ok((avg_score, total_variants, matched_variants, vcf_chr_format))
+    Ok(MultiSampleScores {
+        sample_names,
+        sample_data,
+        summary: ScoreSummary {
+            score: avg_score,
+            total_variants,
+            matched_variants,
+            dosage_sum,
+            effective_variants,
+            weight_coverage,
+            filtered_records: ctx.policy.filtered(),
+            vcf_chr_format,
+            sites,
+            skipped: ctx.policy.skipped(),
+        },
+        contributions,
+    })
 }
 
-/// Processes one chunk of lines (already read from the file).
-/// For each line, parse CHR, POS, REF, ALT, then genotypes for each sample.
-/// We skip multi‐allelic sites or missing genotypes. 
-/// Returns `(last_chr, last_pos, vcf_uses_chr_prefix)`.
-fn process_chunk(
-    chunk: &[u8],
-    effect_weights: &HashMap<(String, u32), (String, f32)>,
-    sample_data: &mut [SampleData],
-    _debug: bool
-) -> Option<(String, u32, bool)> {
-    let mut last_chr = String::new();
-    let mut last_pos = 0;
-    let mut vcf_chr_format = false;
+/// Whether a run needs nothing of each record but its genotypes: no per-variant analysis,
+/// record filter, rejected-record log, INFO field, sample selection, checkpoint or dashboard.
+fn genotypes_only(ctx: &LineContext, checkpoint: Option<&CheckpointConfig>, progress: Option<&Progress>) -> bool {
+    let analyses = &ctx.analyses;
+    analyses.loadings.is_none()
+        && analyses.thresholds.is_empty()
+        && analyses.permutations.is_none()
+        && analyses.partitions.is_none()
+        && analyses.environments.is_none()
+        && analyses.origin.is_none()
+        && analyses.variant_variance.is_none()
+        && analyses.panel.is_none()
+        && matches!(ctx.samples, Samples::All)
+        && ctx.options.info_field.is_none()
+        && ctx.policy.record_filter().is_none()
+        && ctx.policy.rejected().is_none()
+        && checkpoint.is_none()
+        && progress.is_none()
+}
 
-    // Split chunk by newlines
-    for line in chunk.split(|&b| b == b'\n') {
-        if line.is_empty() || line.starts_with(&[b'#']) {
-            continue;
+/// Score a PLINK 2 fileset from its decoded genotypes and dosages, without reading it as
+/// VCF text; see [`pgen::Source`].
+fn score_fileset(ctx: &LineContext, verbose: bool) -> Result<MultiSampleScores> {
+    let mut source = pgen::Source::open(ctx.vcf_path, ctx.options.dosage, ctx.policy)?;
+    if verbose {
+        println!("Sample count: {}", source.sample_names().len());
+        println!("Processing variants...");
+    }
+    let mut scores = source::score_source(&mut source, ctx.effect_weights, ctx.options)?;
+    scores.summary.skipped = ctx.policy.skipped();
+    if verbose {
+        println!("\nFinished processing.");
+    }
+    Ok(scores)
+}
+
+/// Samples scored together by one task. Fixed, so that the per-variant sums of
+/// `--variant-variance`, which are added up block by block, do not depend on the number
+/// of threads.
+const SAMPLE_BLOCK: usize = 64;
+
+/// Lines read from the VCF to be scored together, one after another in `data`.
+#[derive(Default)]
+struct LineBatch {
+    data: Vec<u8>,
+    /// Where each line ends in `data`
+    ends: Vec<usize>,
+    /// Where each field of each line starts, kept from batch to batch to reuse the buffers
+    fields: Vec<Vec<usize>>,
+}
+
+impl LineBatch {
+    /// Replace the batch with the next lines of `reader`: up to `max_lines` of them, and no
+    /// more once `max_bytes` are held. `first_line` is the first one's 1-based line number.
+    fn fill(&mut self, reader: &mut dyn BufRead, max_lines: usize, max_bytes: usize, vcf_path: &str, first_line: usize) -> Result<()> {
+        self.data.clear();
+        self.ends.clear();
+        while self.ends.len() < max_lines && self.data.len() < max_bytes {
+            let line_number = first_line + self.ends.len();
+            if reader.read_until(b'\n', &mut self.data).map_err(|e| Error::read(vcf_path, line_number, e))? == 0 {
+                break;
+            }
+            self.ends.push(self.data.len());
         }
+        Ok(())
+    }
 
-        // Convert line to string
-        let line_str = match std::str::from_utf8(line) {
-            Ok(s) => s,
-            Err(_) => continue, // skip invalid UTF-8
-        };
+    fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
 
-        let parts: Vec<&str> = line_str.split('\t').collect();
-        if parts.len() < 10 {
-            continue; // skip malformed line
+    /// Split every line into its fields, in parallel.
+    fn split(&mut self) -> Vec<Split<'_>> {
+        if self.fields.len() < self.ends.len() {
+            self.fields.resize_with(self.ends.len(), Vec::new);
         }
+        let LineBatch { data, ends, fields } = self;
+        let (data, ends) = (&*data, &*ends);
+        fields[..ends.len()]
+            .par_iter_mut()
+            .enumerate()
+            .map(|(index, starts)| {
+                let start = index.checked_sub(1).map_or(0, |previous| ends[previous]);
+                let line = &data[start..ends[index]];
+                split(line.strip_suffix(b"\n").unwrap_or(line), starts)
+            })
+            .collect()
+    }
+}
+
+/// A line of a [`LineBatch`].
+enum Split<'a> {
+    /// A header line or a blank one
+    Skip,
+    NotUtf8(&'a [u8]),
+    /// The record as [`trim_line`] leaves it, and its fields
+    Fields(&'a str, Fields<'a>),
+}
+
+fn split<'a>(line: &'a [u8], starts: &'a mut Vec<usize>) -> Split<'a> {
+    if line.is_empty() || line.starts_with(b"#") {
+        return Split::Skip;
+    }
+    let Ok(line) = std::str::from_utf8(line) else {
+        return Split::NotUtf8(line);
+    };
+    let line = trim_line(line);
+    // Tabs after the last column, as spreadsheets leave them, are not genotype columns
+    let fields = line.trim_end_matches('\t');
+    starts.clear();
+    starts.push(0);
+    starts.extend(fields.match_indices('\t').map(|(tab, _)| tab + 1));
+    Split::Fields(line, Fields { line: fields, starts })
+}
 
-        let chr_raw = parts[0];
-        let pos_raw = parts[1];
-        let ref_allele = parts[3];
-        let alt_allele = parts[4];
+/// A record's tab-separated fields, kept as where each starts so that no strings are
+/// allocated per field.
+struct Fields<'a> {
+    line: &'a str,
+    starts: &'a [usize],
+}
+
+impl<'a> Fields<'a> {
+    fn len(&self) -> usize {
+        self.starts.len()
+    }
 
-        // The 8th column is `FORMAT`; sample genotypes start at index 9
-        let genotype_fields = &parts[9..];
+    fn get(&self, index: usize) -> Option<&'a str> {
+        let start = *self.starts.get(index)?;
+        let end = self.starts.get(index + 1).map_or(self.line.len(), |next| next - 1);
+        Some(&self.line[start..end])
+    }
+
+    /// The sample columns, after `FORMAT`.
+    fn genotypes(&self) -> impl Iterator<Item = &'a str> + '_ {
+        (9..self.len()).filter_map(|index| self.get(index))
+    }
+}
+
+impl std::ops::Index<usize> for Fields<'_> {
+    type Output = str;
+
+    fn index(&self, index: usize) -> &str {
+        self.get(index).expect("field index out of range")
+    }
+}
 
-        let pos = match pos_raw.parse::<u32>() {
-            Ok(p) => p,
-            Err(_) => continue,
+/// A well-formed record, with what its samples are scored from.
+struct Record<'a> {
+    line_number: usize,
+    line: &'a str,
+    fields: Fields<'a>,
+    pos: u64,
+    /// The ancestry loading at the record's position, if any
+    loading: Option<&'a Loading>,
+    /// The weights of the scoring files after the first at the record's position
+    panel: &'a [(usize, EffectWeight)],
+    /// The scoring variant whose effect allele is the record's REF or ALT; without one,
+    /// the record only counts towards each sample's total variants
+    scored: Option<Scored<'a>>,
+}
+
+/// Everything about a matched scoring variant that is the same for every sample.
+struct Scored<'a> {
+    effect: &'a EffectWeight,
+    effect_is_alt: bool,
+    field_index: Option<usize>,
+    cohort_frequency: Option<f64>,
+    offset: f64,
+    /// What a missing genotype contributes under mean imputation
+    imputed: f64,
+    info_weight: Option<f64>,
+    share: f64,
+    null_weights: Option<&'a [f32]>,
+    sets: &'a [usize],
+    /// First of the thresholded scores this variant counts towards
+    first_threshold: usize,
+    origin: Option<HaplotypeOrder>,
+    contribution: Option<VariantContribution>,
+}
+
+impl Scored<'_> {
+    /// Work out the centering offset and imputed contribution, which depend on the
+    /// effect-allele frequency among the record's genotypes.
+    fn center(&mut self, fields: &Fields, options: &ScoreOptions) {
+        self.cohort_frequency = match self.field_index {
+            Some(index) if options.needs_cohort_frequency() => cohort_frequency(fields.genotypes(), options, index, self.effect_is_alt),
+            _ => None,
+        };
+        self.offset = options.centering_offset(self.effect, self.cohort_frequency);
+        self.imputed = match self.cohort_frequency {
+            Some(p) if options.mean_impute => options.expected_contribution(self.effect, p) - self.offset,
+            _ => 0.0,
         };
+    }
+}
+
+/// One record's per-variant sums over one block of samples.
+#[derive(Clone, Copy, Default)]
+struct Partial {
+    samples: usize,
+    called: usize,
+    allele_sum: f64,
+    sum: f64,
+    sum_squares: f64,
+    /// Whether a sample had a NaN or infinite dosage
+    non_finite: bool,
+}
+
+impl Partial {
+    fn add_to(&self, contribution: &mut VariantContribution) {
+        contribution.samples += self.samples;
+        contribution.called += self.called;
+        contribution.allele_sum += self.allele_sum;
+        contribution.sum += self.sum;
+        contribution.sum_squares += self.sum_squares;
+    }
+}
+
+/// Check one record (split into `fields`) and look it up in the scoring file, passing
+/// malformed records to the error policy, which decides whether to abort. A record with
+/// another number of genotype columns than the `header_samples` is malformed, as its
+/// genotypes cannot be told apart. `None` for records that are skipped.
+fn prepare<'a>(
+    ctx: &LineContext<'a>,
+    line_number: usize,
+    line: &'a str,
+    fields: Fields<'a>,
+    header_samples: usize,
+    sites: &mut SiteCounts,
+) -> Result<Option<Record<'a>>> {
+    let LineContext { vcf_path, effect_weights, options, policy, .. } = *ctx;
+    if !policy.keeps(vcf_path, line_number, line)? {
+        return Ok(None);
+    }
+    if fields.len() < 10 {
+        policy.handle_record(Error::parse(vcf_path, line_number, "Too few columns (no genotypes)", line), line)?;
+        return Ok(None);
+    }
+    if fields.len() - 9 != header_samples {
+        policy.handle_record(Error::parse(vcf_path, line_number, "Genotype columns do not match the header's samples", line), line)?;
+        return Ok(None);
+    }
 
-        last_chr = chr_raw.to_string();
-        last_pos = pos;
-        vcf_chr_format = chr_raw.starts_with("chr");
+    // Hand-edited files can have stray spaces around the fields
+    let chr_raw = fields[0].trim();
+    let ref_allele = fields[3].trim();
+    let alt_allele = fields[4].trim();
+    let Ok(pos) = fields[1].trim().parse::<u64>() else {
+        policy.handle_record(Error::parse(vcf_path, line_number, "Invalid numeric position", line), line)?;
+        return Ok(None);
+    };
 
-        // Normalize chromosome to match how we stored it in effect_weights
-        let normalized_chr = chr_raw.trim_start_matches("chr").to_string();
+    // Normalize chromosome to match how we stored it in effect_weights
+    let key = (aliases::canonical(chr_raw).to_string(), pos);
+    // Ancestry loadings are matched independently of the scoring file
+    let loading = ctx.analyses.loadings.and_then(|l| l.variants.get(&key));
+    let panel = ctx.analyses.panel.map_or(&[][..], |p| p.weights(&key));
+    let scored = effect_weights.get(&key).and_then(|effect| {
+        sites.position_hits += 1;
+        // Check if effect allele is REF or ALT; otherwise the record only counts towards the totals
+        let effect_is_ref = effect.effect_allele == ref_allele;
+        let effect_is_alt = effect.effect_allele == alt_allele;
+        if !effect_is_ref && !effect_is_alt {
+            if let Some(rejections) = policy.rejected() {
+                rejections.push(Input::Vcf, line_number, Reason::AlleleMismatch, allele_mismatch(&effect.effect_allele, ref_allele, alt_allele), line);
+            }
+            return None;
+        }
+        sites.allele_hits += 1;
+
+        let thresholds = ctx.analyses.thresholds;
+        Some(Scored {
+            effect,
+            effect_is_alt,
+            field_index: options.field_index(&fields[8]),
+            // Set by `Scored::center`
+            cohort_frequency: None,
+            offset: 0.0,
+            imputed: 0.0,
+            info_weight: options.info_weight(&fields[7]),
+            share: weight_share(effect, ctx.total_weight),
+            null_weights: ctx.analyses.permutations.and_then(|p| p.weights(&key)),
+            sets: ctx.analyses.partitions.map_or(&[][..], |p| p.sets(&key)),
+            first_threshold: effect.p_value.map_or(thresholds.len(), |p| thresholding::first_included(thresholds, p)),
+            origin: ctx.analyses.origin.filter(|_| effect.maternal != 0.0 || effect.paternal != 0.0),
+            contribution: ctx.analyses.variant_variance.map(|_| VariantContribution::new(&key.0, pos, &effect.effect_allele, effect.weight)),
+        })
+    });
+    Ok(Some(Record { line_number, line, fields, pos, loading, panel, scored }))
+}
 
-        // If not found in effect_weights, skip
-        let (effect_allele, weight) = match effect_weights.get(&(normalized_chr.clone(), pos)) {
-            Some(x) => x,
-            None => {
-                // Still count total_variants for each sample?
+/// Add `records`, in order, to the samples in `sample_data`, at header positions `columns`.
+/// Missing genotypes are skipped unless they are mean-imputed. Returns each record's
+/// per-variant sums over these samples.
+fn score_block(records: &[Record], ctx: &LineContext, columns: &[usize], sample_data: &mut [SampleData]) -> Vec<Partial> {
+    let options = ctx.options;
+    records
+        .iter()
+        .map(|record| {
+            let mut partial = Partial::default();
+            if let Some(loading) = record.loading {
+                project_line(loading, &record.fields, options, columns, sample_data);
+            }
+            if !record.panel.is_empty() {
+                score_panel(record.panel, &record.fields, options, columns, sample_data);
+            }
+            let Some(scored) = &record.scored else {
                 for sample in sample_data.iter_mut() {
                     sample.total_variants += 1;
                 }
-                continue;
-            }
-        };
-
-        // Check if effect allele is REF or ALT. Otherwise skip
-        let effect_is_ref = effect_allele == ref_allele;
-        let effect_is_alt = effect_allele == alt_allele;
-        if !effect_is_ref && !effect_is_alt {
-            // Increase total_variants but not matched
-            for sample in sample_data.iter_mut() {
+                return partial;
+            };
+            let effect = scored.effect;
+            for (sample, &column) in sample_data.iter_mut().zip(columns) {
+                // The 8th column is `FORMAT`; sample genotypes start at index 9
+                let Some(genotype_field) = record.fields.get(9 + column) else {
+                    continue;
+                };
                 sample.total_variants += 1;
+                let allele_count = scored
+                    .field_index
+                    .and_then(|index| options.checked_effect_count(genotype_field, index, scored.effect_is_alt, &mut partial.non_finite));
+                let added = match allele_count {
+                    Some(allele_count) => {
+                        sample.matched_variants += 1;
+                        sample.dosage_sum += allele_count;
+                        if let (Some(effective), Some(weight)) = (&mut sample.effective_variants, scored.info_weight) {
+                            *effective += weight;
+                        }
+                        if let Some(coverage) = &mut sample.weight_coverage {
+                            *coverage += scored.share;
+                        }
+                        options.contribution(effect, allele_count) - scored.offset
+                    }
+                    None => scored.imputed,
+                };
+                sample.score += added;
+                partial.samples += 1;
+                partial.sum += added;
+                partial.sum_squares += added * added;
+                if let Some(count) = allele_count {
+                    partial.called += 1;
+                    partial.allele_sum += count;
+                }
+                for score in &mut sample.threshold_scores[scored.first_threshold..] {
+                    *score += added;
+                }
+                for &set in scored.sets {
+                    sample.set_scores[set] += added;
+                }
+                if let (Some(variance), Some(se)) = (&mut sample.variance, effect.standard_error) {
+                    *variance += (options.weight_sensitivity(effect, allele_count, scored.cohort_frequency) * se as f64).powi(2);
+                }
+                if let Some(sum) = sample.interaction_sum.as_mut().filter(|_| effect.interaction != 0.0) {
+                    *sum += effect.interaction as f64 * options.weight_sensitivity(effect, allele_count, scored.cohort_frequency);
+                }
+                if let Some((maternal, paternal)) = scored.origin.and_then(|order| order.effect_alleles(genotype_field, scored.effect_is_alt)) {
+                    if let (Some(maternal_score), Some(paternal_score)) = (&mut sample.maternal_score, &mut sample.paternal_score) {
+                        *maternal_score += effect.maternal as f64 * maternal;
+                        *paternal_score += effect.paternal as f64 * paternal;
+                    }
+                }
+                if let Some(null_weights) = scored.null_weights {
+                    let sensitivity = options.weight_sensitivity(effect, allele_count, scored.cohort_frequency);
+                    for (deviation, &weight) in sample.null_deviations.iter_mut().zip(null_weights) {
+                        *deviation += (weight - effect.weight) as f64 * sensitivity;
+                    }
+                }
             }
-            continue;
+            partial
+        })
+        .collect()
+}
+
+/// Add one VCF record to the PC coordinates of the samples at `columns`, if the
+/// loading's effect allele is its REF or ALT.
+fn project_line(loading: &Loading, fields: &Fields, options: &ScoreOptions, columns: &[usize], sample_data: &mut [SampleData]) {
+    let effect_is_alt = loading.effect_allele == fields[4];
+    if !effect_is_alt && loading.effect_allele != fields[3] {
+        return;
+    }
+    let Some(field_index) = options.field_index(&fields[8]) else {
+        return;
+    };
+    for (sample, &column) in sample_data.iter_mut().zip(columns) {
+        let count = fields.get(9 + column).and_then(|field| options.effect_count(field, field_index, effect_is_alt));
+        if let Some(count) = count {
+            loading.project(count, &mut sample.pcs);
         }
+    }
+}
 
-        // At this point, we have a matched variant that matters for scoring
-        // Increase total_variants for each sample
-        // And only increment matched_variants if genotype is valid
-        for (sample, genotype_field) in sample_data.iter_mut().zip(genotype_fields) {
-            sample.total_variants += 1;
-            let gt = genotype_field.split(':').next().unwrap_or(".");
-            if let Some(allele_count) = parse_allele_count(gt, effect_is_alt) {
-                sample.matched_variants += 1;
-                sample.score += (*weight as f64) * (allele_count as f64);
+/// Add one VCF record to the scores of the samples at `columns` over the scoring files after
+/// the first with a weight at its position whose effect allele is its REF or ALT.
+fn score_panel(weights: &[(usize, EffectWeight)], fields: &Fields, options: &ScoreOptions, columns: &[usize], sample_data: &mut [SampleData]) {
+    let Some(field_index) = options.field_index(&fields[8]) else {
+        return;
+    };
+    for (index, effect) in weights {
+        let effect_is_alt = effect.effect_allele == fields[4].trim();
+        if !effect_is_alt && effect.effect_allele != fields[3].trim() {
+            continue;
+        }
+        for (sample, &column) in sample_data.iter_mut().zip(columns) {
+            let count = fields.get(9 + column).and_then(|field| options.effect_count(field, field_index, effect_is_alt));
+            if let Some(count) = count {
+                sample.panel_scores[*index] += options.contribution(effect, count);
+                sample.panel_matched[*index] += 1;
             }
         }
     }
+}
 
-    Some((last_chr, last_pos, vcf_chr_format))
+/// Mean weight coverage of the samples, if tracked.
+pub(crate) fn mean_coverage(sample_data: &[SampleData]) -> Option<f64> {
+    let total: Option<f64> = sample_data.iter().map(|sd| sd.weight_coverage).sum();
+    total.map(|total| total / sample_data.len().max(1) as f64)
 }
 
-/// Identical to the single-sample helper (move to common later):
-/// If `effect_is_alt`, we count '1' as effect alleles; otherwise we count '0'.
-fn parse_allele_count(gt: &str, effect_is_alt: bool) -> Option<u8> {
-    let mut count = 0u8;
-    for c in gt.chars() {
-        match c {
-            '0' if !effect_is_alt => count += 1,
-            '1' if effect_is_alt => count += 1,
-            '.' | '2' | '3' => return None, // skip multi-allelic or missing
-            '|' | '/' => {}
-            _ => {}
-        }
+/// Whether any scoring variant has a weight standard error, so samples get a score SE.
+pub fn has_standard_errors(effect_weights: &EffectWeights) -> bool {
+    effect_weights.values().any(|w| w.standard_error.is_some())
+}
+
+/// Effect-allele frequency among the called genotypes of every sample in the VCF,
+/// not just the ones being scored, so that blocks and subsets center alike.
+fn cohort_frequency<'a>(genotype_fields: impl Iterator<Item = &'a str>, options: &ScoreOptions, field_index: usize, effect_is_alt: bool) -> Option<f64> {
+    let (sum, called) = genotype_fields
+        .filter_map(|field| options.effect_count(field, field_index, effect_is_alt))
+        .fold((0.0, 0usize), |(sum, called), count| (sum + count, called + 1));
+    (called > 0).then(|| sum / (2 * called) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::EffectWeight;
+    use crate::error::OnError;
+    use crate::input::InputConfig;
+    use crate::scorer::SampleScore;
+
+    #[test]
+    fn variant_variance_needs_two_samples() {
+        let dir = std::env::temp_dir();
+        let vcf = dir.join(format!("speedscore-one-sample-{}.vcf", std::process::id()));
+        let report = dir.join(format!("speedscore-one-sample-{}.tsv", std::process::id()));
+        std::fs::write(&vcf, "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\n1\t100\t.\tA\tG\t.\t.\t.\tGT\t0/1\n").unwrap();
+        let vcf = vcf.to_str().unwrap();
+        let effect = EffectWeight {
+            effect_allele: "G".to_string(),
+            weight: 1.0,
+            frequency: None,
+            dominance: 0.0,
+            p_value: None,
+            standard_error: None,
+            interaction: 0.0,
+            maternal: 0.0,
+            paternal: 0.0,
+        };
+        let effect_weights: EffectWeights = [(("1".to_string(), 100), effect)].into();
+        let analyses = Analyses { variant_variance: report.to_str(), ..Default::default() };
+        let mut sink: Vec<SampleScore> = Vec::new();
+        let input = VcfInput::new(vcf, &InputConfig::default()).unwrap();
+        let policy = ErrorPolicy::new(OnError::Fail, None);
+        let outcome = calculate_polygenic_score_multi(&input, &effect_weights, &ScoreOptions::default(), Samples::All, analyses, &mut sink, false, None, &policy, None);
+        std::fs::remove_file(vcf).unwrap();
+
+        assert!(outcome.unwrap_err().to_string().contains("needs at least two samples"));
+        assert!(sink.is_empty());
+        assert!(!report.exists());
     }
-    Some(count)
-}
-
-fn write_csv_output(
-    output_path: &str,
-    vcf_path: &str,
-    sample_names: &[String],
-    sample_data: &[SampleData],
-    duration: std::time::Duration
-) -> Result<(), VcfError> {
-    let path = Path::new(output_path);
-    let prefix = path.parent().unwrap_or_else(|| Path::new(""));
-    std::fs::create_dir_all(prefix).map_err(VcfError::Io)?;
-
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_path)
-        .map_err(VcfError::Io)?;
-
-    writeln!(file, "VCF_File,Sample_Name,Polygenic_Score,Calculation_Time_Seconds,Total_Variants,Matched_Variants")
-        .map_err(VcfError::Io)?;
-
-    for (name, data) in sample_names.iter().zip(sample_data.iter()) {
-        writeln!(
-            file,
-            "{},{},{:.6},{:.6},{},{}",
-            vcf_path,
-            name,
-            data.score,
-            duration.as_secs_f64(),
-            data.total_variants,
-            data.matched_variants
-        ).map_err(VcfError::Io)?;
-    }
-
-    Ok(())
 }
@@ -0,0 +1,87 @@
+//! Checking that VCF records are sorted, as `bcftools sort` leaves them: each chromosome's
+//! records together, and by position within it. The order of the chromosomes themselves
+//! is not checked, since `1, 2, ..., 10` and `1, 10, 2, ...` are both common.
+
+use std::collections::HashSet;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, Result};
+
+/// What to do when the VCF is not sorted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Unsorted {
+    /// Abort at the first out-of-order record
+    Error,
+    /// Print a warning for the first out-of-order record and carry on
+    Warn,
+}
+
+/// Tracks the records of one pass over a VCF.
+pub struct SortCheck {
+    mode: Unsorted,
+    chr: String,
+    pos: u64,
+    /// Chromosomes whose records have ended
+    finished: HashSet<String>,
+    warned: bool,
+}
+
+/// How far a [`SortCheck`] got, saved with a checkpoint so a resumed run checks on from there.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SortPosition {
+    pub chr: String,
+    pub pos: u64,
+    /// Chromosomes whose records have ended, sorted
+    pub finished: Vec<String>,
+    pub warned: bool,
+}
+
+impl SortCheck {
+    pub fn new(mode: Unsorted) -> Self {
+        SortCheck { mode, chr: String::new(), pos: 0, finished: HashSet::new(), warned: false }
+    }
+
+    pub fn position(&self) -> SortPosition {
+        let mut finished: Vec<String> = self.finished.iter().cloned().collect();
+        finished.sort();
+        SortPosition { chr: self.chr.clone(), pos: self.pos, finished, warned: self.warned }
+    }
+
+    /// Carry on from `position`, as if the records before it had been checked.
+    pub fn resume(&mut self, position: SortPosition) {
+        self.chr = position.chr;
+        self.pos = position.pos;
+        self.finished = position.finished.into_iter().collect();
+        self.warned = position.warned;
+    }
+
+    /// Check the record at `line_number`, with CHROM `chr` and POS `pos`, against the ones
+    /// before it. `line` is only used in the error message.
+    pub fn check(&mut self, path: &str, line_number: usize, chr: &str, pos: u64, line: &str) -> Result<()> {
+        if self.warned {
+            return Ok(());
+        }
+        let message = if chr == self.chr {
+            if pos >= self.pos {
+                self.pos = pos;
+                return Ok(());
+            }
+            format!("VCF is not sorted: position {} on chromosome {} follows position {}", pos, chr, self.pos)
+        } else if self.finished.contains(chr) {
+            format!("VCF is not sorted: chromosome {} appears again after {}", chr, self.chr)
+        } else {
+            self.finished.insert(std::mem::replace(&mut self.chr, chr.to_string()));
+            self.pos = pos;
+            return Ok(());
+        };
+        let err = Error::parse(path, line_number, format!("{} (sort it with `bcftools sort`)", message), line);
+        match self.mode {
+            Unsorted::Error => Err(err),
+            Unsorted::Warn => {
+                eprintln!("WARNING: {}", err);
+                self.warned = true;
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Parent-of-origin scoring for imprinted regions.
+//!
+//! Scoring files may carry `maternal_weight` and `paternal_weight` columns, the effect of
+//! the effect allele when inherited from the mother or the father. With phased GT calls
+//! and the order in which they list the parental haplotypes (`--parent-of-origin`),
+//! every sample gets a maternal score, the maternal weights summed over the effect alleles
+//! on its maternal haplotype, and likewise a paternal score. Unphased, haploid and missing
+//! calls add nothing to either. The scores are not centered or imputed.
+
+use clap::ValueEnum;
+use crate::common::EffectWeights;
+use crate::error::{Error, Result};
+
+/// Which parent's haplotype a phased GT call lists first, e.g. `0|1`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum HaplotypeOrder {
+    /// Maternal|paternal
+    MaternalFirst,
+    /// Paternal|maternal
+    PaternalFirst,
+}
+
+impl HaplotypeOrder {
+    /// Whether the maternal and the paternal haplotype of `sample_field` (GT first, e.g.
+    /// `0|1:...`) carry the effect allele, as 0 or 1. `None` for calls that are not phased
+    /// and diploid, or that are missing or multi-allelic.
+    pub fn effect_alleles(self, sample_field: &str, effect_is_alt: bool) -> Option<(f64, f64)> {
+        let gt = sample_field.split(':').next()?;
+        let (first, second) = gt.split_once('|')?;
+        let effect = |allele: &str| match allele {
+            "0" => Some(if effect_is_alt { 0.0 } else { 1.0 }),
+            "1" => Some(if effect_is_alt { 1.0 } else { 0.0 }),
+            _ => None,
+        };
+        let (first, second) = (effect(first)?, effect(second)?);
+        Some(match self {
+            HaplotypeOrder::MaternalFirst => (first, second),
+            HaplotypeOrder::PaternalFirst => (second, first),
+        })
+    }
+}
+
+/// Number of scoring variants with a nonzero maternal or paternal weight; an error when
+/// there are none.
+pub fn check_weights(effect_weights: &EffectWeights, scoring_path: &str) -> Result<usize> {
+    match effect_weights.values().filter(|w| w.maternal != 0.0 || w.paternal != 0.0).count() {
+        0 => Err(Error::invalid(
+            scoring_path,
+            "--parent-of-origin needs maternal_weight and paternal_weight columns with nonzero weights",
+        )),
+        count => Ok(count),
+    }
+}
@@ -0,0 +1,156 @@
+//! Reading Oxford `.gen` files, as SNPTEST and IMPUTE2 write imputed genotypes.
+//!
+//! [`crate::bgzf::open`] hands the text of a `.gen`, plain or compressed, here, and it is
+//! read as the VCF of its dosages, as [`crate::bgen`] reads a BGEN: each sample's three
+//! genotype probabilities, of carrying 0, 1 and 2 copies of the second allele, become the
+//! expected count of that allele, a `DS` field rounded to 4 decimals, with the first allele
+//! as REF. A sample whose probabilities are all 0 is missing.
+//!
+//! Each line holds a variant's ID, rsID, position and two alleles, then the probabilities,
+//! and may start with its chromosome as well. Without one, the first column is taken as the
+//! chromosome, as PLINK takes it, unless `--gen-chr` names the chromosome of every variant.
+//! Samples are named by the `.sample` file given with `--sample`, or else by `PREFIX.sample`
+//! next to `PREFIX.gen`.
+
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use crate::bgen;
+use crate::error::{Error, Result};
+use crate::input::InputConfig;
+
+/// Whether the file at `path` is a `.gen`, by its name.
+pub fn is_gen(path: &str) -> bool {
+    let path = path.strip_suffix(".gz").or_else(|| path.strip_suffix(".bgz")).or_else(|| path.strip_suffix(".zst")).unwrap_or(path);
+    path.ends_with(".gen")
+}
+
+/// A `.gen` file as VCF text: the header, then one line per variant.
+pub struct Reader {
+    lines: Box<dyn BufRead + Send>,
+    samples: usize,
+    /// The chromosome of every variant, given with `--gen-chr`
+    chromosome: Option<String>,
+    /// The line being read, and its number
+    line: String,
+    number: usize,
+    /// The text of the header or the last variant, and how much of it has been read
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+/// Read the `.gen` at `path`, whose text `lines` holds, naming its samples.
+pub fn open(path: &str, lines: Box<dyn BufRead + Send>, config: &InputConfig) -> Result<Reader> {
+    let stem = path.strip_suffix(".gz").or_else(|| path.strip_suffix(".bgz")).or_else(|| path.strip_suffix(".zst")).unwrap_or(path);
+    let default = format!("{}.sample", stem.strip_suffix(".gen").unwrap_or(stem));
+    let samples = match config.sample_file.as_deref() {
+        Some(sample_file) => bgen::read_sample_file(sample_file)?,
+        None if Path::new(&default).exists() => bgen::read_sample_file(&default)?,
+        None => return Err(Error::invalid(path, format!("a .gen names no samples, and there is no {}; give its .sample file with --sample", default))),
+    };
+
+    let mut text = format!("##fileformat=VCFv4.2\n##source=SpeedScore, from the .gen file {}\n", path);
+    text.push_str("##FORMAT=<ID=DS,Number=A,Type=Float,Description=\"Expected count of the second allele\">\n");
+    text.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT");
+    for sample in &samples {
+        text.push('\t');
+        text.push_str(sample);
+    }
+    text.push('\n');
+    Ok(Reader { lines, samples: samples.len(), chromosome: config.gen_chromosome.clone(), line: String::new(), number: 0, pending: text.into_bytes(), pos: 0 })
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Reader {
+    /// Write the next variant to `pending`; `false` after the last one.
+    fn next_variant(&mut self) -> io::Result<bool> {
+        loop {
+            self.line.clear();
+            if self.lines.read_line(&mut self.line)? == 0 {
+                return Ok(false);
+            }
+            self.number += 1;
+            if !self.line.trim().is_empty() {
+                break;
+            }
+        }
+        let number = self.number;
+        self.write_variant().map_err(|e| invalid(format!("variant {}: {}", number, e)))?;
+        Ok(true)
+    }
+
+    fn write_variant(&mut self) -> io::Result<()> {
+        let fields: Vec<&str> = self.line.split_ascii_whitespace().collect();
+        let leading = match fields.len().checked_sub(3 * self.samples) {
+            Some(leading @ (5 | 6)) => leading,
+            _ => {
+                let message = format!("it has {} columns, not 5 or 6 and then 3 for each of the {} samples", fields.len(), self.samples);
+                return Err(invalid(message));
+            }
+        };
+        // The variant ID, unless the first column is taken as the chromosome
+        let (chr, snp_id) = match (leading, &self.chromosome) {
+            (6, _) => (fields[0], fields[1]),
+            (_, Some(chr)) => (chr.as_str(), fields[0]),
+            _ => (fields[0], "."),
+        };
+        let [rsid, position, first, second] = [0, 1, 2, 3].map(|at| fields[leading - 4 + at]);
+        if chr == "---" || chr == "." {
+            return Err(invalid(format!("its chromosome is '{}'; give the file's chromosome with --gen-chr", chr)));
+        }
+        if position.parse::<u64>().is_err() {
+            return Err(invalid(format!("its position '{}' is not a number", position)));
+        }
+
+        self.pending.clear();
+        self.pos = 0;
+        let id = [rsid, snp_id].into_iter().find(|id| *id != "." && *id != "---").unwrap_or(".");
+        for field in [bgen::chromosome(chr), position, id, first, second, ".", ".", ".", "DS"] {
+            self.pending.extend_from_slice(field.as_bytes());
+            self.pending.push(b'\t');
+        }
+        for sample in fields[leading..].chunks_exact(3) {
+            let mut probabilities = [0.0f64; 3];
+            for (probability, field) in probabilities.iter_mut().zip(sample) {
+                *probability = field.parse().ok().filter(|p: &f64| p.is_finite() && *p >= 0.0).ok_or_else(|| invalid(format!("'{}' is not a probability", field)))?;
+            }
+            let [_, het, hom_alt] = probabilities;
+            let total: f64 = probabilities.iter().sum();
+            if total == 0.0 {
+                self.pending.extend_from_slice(b".\t");
+            } else {
+                // Scaled to add up to 1, as imputed probabilities may not quite. A tie such
+                // as 0.073 / 0.8 rounds up, though its binary value falls just short
+                let scaled = (het + 2.0 * hom_alt) / total * 10000.0;
+                bgen::write_scaled_dosage(&mut self.pending, (scaled + 1e-6).round() as u64);
+            }
+        }
+        *self.pending.last_mut().expect("a FORMAT column") = b'\n';
+        Ok(())
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = buf.len().min(available.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for Reader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.pending.len() && !self.next_variant()? {
+            return Ok(&[]);
+        }
+        Ok(&self.pending[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.pending.len());
+    }
+}
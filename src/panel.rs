@@ -0,0 +1,80 @@
+//! Several scoring files scored in one pass over the VCF (`--scoring a.txt,b.txt`, or
+//! `--scoring` given once for each), for when reading the VCF is what a score costs.
+//!
+//! The first scoring file is scored as it would be alone, and every analysis is of its
+//! score. The others are looked up in one index of all their positions, matched to each
+//! record on their own as ancestry loadings are, and only summed: every sample gets a
+//! `Score_<id>` and a `Matched_<id>` column for each scoring file, the first one's included,
+//! where `<id>` is the file name up to its first dot. A file that matches no variant at all
+//! is warned about. An indexed VCF is read around the positions of all of them.
+
+use std::collections::HashMap;
+use crate::common::{load_scoring_file, scoring_id, EffectWeight};
+use crate::error::{Error, NonFinite, Result};
+
+/// The scoring files after the first, indexed by position.
+#[derive(Clone, Debug)]
+pub struct Panel {
+    /// IDs of every scoring file, the first one's included, in the order given
+    pub ids: Vec<String>,
+    /// The weights at each position (chr without "chr", pos), with the index of their file
+    /// among those after the first
+    weights: HashMap<(String, u64), Vec<(usize, EffectWeight)>>,
+}
+
+impl Panel {
+    /// Load the scoring files at `paths`, which are scored along with the one at `first`.
+    pub fn load(first: &str, paths: &[String], non_finite: NonFinite) -> Result<Self> {
+        let mut ids: Vec<String> = Vec::with_capacity(paths.len() + 1);
+        for path in std::iter::once(first).chain(paths.iter().map(String::as_str)) {
+            let id = scoring_id(path);
+            // IDs become CSV column names
+            if id.is_empty() || id.contains([',', '"', '\n', '\r']) {
+                return Err(Error::invalid(path, format!("'{}' cannot name a score column; rename the file", id)));
+            }
+            if ids.contains(&id) {
+                return Err(Error::invalid(path, format!("another scoring file is also named {}; scores are told apart by file name, up to the first dot", id)));
+            }
+            ids.push(id);
+        }
+        let mut weights: HashMap<(String, u64), Vec<(usize, EffectWeight)>> = HashMap::new();
+        for (index, path) in paths.iter().enumerate() {
+            let (loaded, _) = load_scoring_file(path, non_finite, None)?;
+            for (key, weight) in loaded {
+                weights.entry(key).or_default().push((index, weight));
+            }
+        }
+        Ok(Panel { ids, weights })
+    }
+
+    /// Number of scoring files after the first.
+    pub fn others(&self) -> usize {
+        self.ids.len() - 1
+    }
+
+    /// The weights of the files after the first at `key`, with the index of their file.
+    pub fn weights(&self, key: &(String, u64)) -> &[(usize, EffectWeight)] {
+        self.weights.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every position a file after the first has a weight at.
+    pub fn positions(&self) -> impl Iterator<Item = &(String, u64)> + Clone {
+        self.weights.keys()
+    }
+
+    /// Keep only the weights on the chromosomes `keep` accepts.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.weights.retain(|(chr, _), _| keep(chr));
+    }
+}
+
+/// Results column of the score over the scoring file `id`, e.g. `Score_PGS000001`.
+pub fn column_name(id: &str) -> String {
+    format!("Score_{}", id)
+}
+
+/// Results column of the number of variants matched from the scoring file `id`, e.g.
+/// `Matched_PGS000001`.
+pub fn matched_column_name(id: &str) -> String {
+    format!("Matched_{}", id)
+}
@@ -0,0 +1,163 @@
+//! Partitioned scores: per-set partial scores for gene- or pathway-level analyses.
+//!
+//! Scoring variants are assigned to named sets, either by a scoring-file column listing
+//! each variant's sets (`--set-column`) or by the named intervals of a BED file
+//! (`--set-bed`), and every sample gets the partial score over each set in the same pass
+//! as its total score. A variant may belong to several sets or to none, so the partial
+//! scores need not add up to the total.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use crate::common::{open_scoring_file, parse_position, position_columns, trim_line, EffectWeights};
+use crate::error::{Error, IoContext, Result};
+
+/// The sets each scoring variant belongs to.
+#[derive(Clone, Debug)]
+pub struct Partitions {
+    /// Set names, in order of first appearance; sets without scoring variants are dropped
+    pub names: Vec<String>,
+    /// Indices into `names` of each variant's sets, by (chr without "chr", pos)
+    sets: HashMap<(String, u64), Vec<usize>>,
+}
+
+impl Partitions {
+    /// Sets from the scoring file's `column`, whose values list a variant's sets separated
+    /// by `,` or `;`. Blank and `NA` values put a variant in no set.
+    pub fn from_column(scoring_path: &str, column: &str, effect_weights: &EffectWeights) -> Result<Self> {
+        let mut text = String::new();
+        open_scoring_file(scoring_path)?.read_to_string(&mut text).with_path(scoring_path)?;
+        let mut builder = Builder::default();
+        // Positions of the chromosome, position and set columns, once the header is seen
+        let mut columns: Option<(usize, usize, usize)> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line = trim_line(line);
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = line.split('\t').map(str::trim).collect();
+            let Some((chr, pos, sets)) = columns else {
+                let sets = parts.iter().position(|h| *h == column)
+                    .ok_or_else(|| Error::invalid(scoring_path, format!("no '{}' column for --set-column", column)))?;
+                let (chr, pos, _) = position_columns(&parts).unwrap_or((0, 0, false));
+                columns = Some((chr, pos, sets));
+                continue;
+            };
+            // Rows were validated when the scoring file was loaded
+            let key = (parts[chr].trim_start_matches("chr").to_string(), parse_position(parts[pos]).unwrap_or(0));
+            if !effect_weights.contains_key(&key) {
+                continue;
+            }
+            for name in parts[sets].split([',', ';']).map(str::trim).filter(|name| !name.is_empty() && *name != "NA") {
+                let set = builder.set(name).map_err(|msg| Error::parse(scoring_path, index + 1, msg, line))?;
+                builder.add(key.clone(), set);
+            }
+        }
+        Ok(builder.finish())
+    }
+
+    /// Sets from the named intervals of the BED file at `path`: chromosome, 0-based start,
+    /// end and set name. A set may span several intervals.
+    pub fn from_bed(path: &str, effect_weights: &EffectWeights) -> Result<Self> {
+        let text = fs::read_to_string(path).with_path(path)?;
+        // Per chromosome, the scoring variant positions in order
+        let mut chromosomes: HashMap<&str, Vec<u64>> = HashMap::new();
+        for (chr, pos) in effect_weights.keys() {
+            chromosomes.entry(chr).or_default().push(*pos);
+        }
+        chromosomes.values_mut().for_each(|positions| positions.sort_unstable());
+
+        let mut builder = Builder::default();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            if line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let parts: Vec<&str> = line.split('\t').collect();
+            if parts.len() < 4 {
+                return Err(Error::parse(path, line_number, "Expected at least 4 columns (chrom, start, end, name)", line));
+            }
+            let start = parts[1].parse::<u64>().map_err(|_| Error::parse(path, line_number, "Invalid numeric start", line))?;
+            let end = parts[2].parse::<u64>().map_err(|_| Error::parse(path, line_number, "Invalid numeric end", line))?;
+            let set = builder.set(parts[3].trim()).map_err(|msg| Error::parse(path, line_number, msg, line))?;
+            let chr = parts[0].trim_start_matches("chr");
+            let Some(positions) = chromosomes.get(chr) else {
+                continue;
+            };
+            // BED intervals are 0-based and half-open; VCF positions are 1-based
+            let first = positions.partition_point(|&pos| pos <= start);
+            for &pos in positions[first..].iter().take_while(|&&pos| pos <= end) {
+                builder.add((chr.to_string(), pos), set);
+            }
+        }
+        Ok(builder.finish())
+    }
+
+    /// Indices into [`Partitions::names`] of the sets the variant at `key` belongs to.
+    pub fn sets(&self, key: &(String, u64)) -> &[usize] {
+        self.sets.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Number of scoring variants in at least one set.
+    pub fn assigned(&self) -> usize {
+        self.sets.len()
+    }
+}
+
+/// Results column of the partial score over the set `name`, e.g. `Set_BRCA1`.
+pub fn column_name(name: &str) -> String {
+    format!("Set_{}", name)
+}
+
+#[derive(Default)]
+struct Builder {
+    names: Vec<String>,
+    indices: HashMap<String, usize>,
+    sets: HashMap<(String, u64), Vec<usize>>,
+}
+
+impl Builder {
+    /// Index of the set `name`, adding it if it is new.
+    fn set(&mut self, name: &str) -> std::result::Result<usize, String> {
+        if let Some(&index) = self.indices.get(name) {
+            return Ok(index);
+        }
+        // Names become CSV column names
+        if name.is_empty() || name.contains([',', '"', '\n', '\r']) {
+            return Err(format!("Invalid set name '{}'", name));
+        }
+        self.names.push(name.to_string());
+        self.indices.insert(name.to_string(), self.names.len() - 1);
+        Ok(self.names.len() - 1)
+    }
+
+    fn add(&mut self, key: (String, u64), set: usize) {
+        let sets = self.sets.entry(key).or_default();
+        if !sets.contains(&set) {
+            sets.push(set);
+        }
+    }
+
+    /// The partitions, without the sets no scoring variant belongs to.
+    fn finish(self) -> Partitions {
+        let mut used = vec![false; self.names.len()];
+        for &set in self.sets.values().flatten() {
+            used[set] = true;
+        }
+        // Old index to new, for the sets that are kept
+        let mut renumbered = vec![0; self.names.len()];
+        let mut names = Vec::new();
+        for (index, name) in self.names.into_iter().enumerate() {
+            if used[index] {
+                renumbered[index] = names.len();
+                names.push(name);
+            }
+        }
+        let sets = self
+            .sets
+            .into_iter()
+            .map(|(key, sets)| (key, sets.into_iter().map(|set| renumbered[set]).collect()))
+            .collect();
+        Partitions { names, sets }
+    }
+}
@@ -0,0 +1,68 @@
+//! Empirical null distributions by weight permutation.
+//!
+//! With `--permutations N`, the scoring file's effect weights are shuffled across its
+//! variants N times, and every sample is scored under each shuffle in the same pass as
+//! its real score. Alleles, frequencies and dominance weights stay with their variant.
+//! A sample's percentile against its own N null scores says how unusual its score is
+//! given its genotypes, rather than against the cohort; its null z-score is the distance
+//! from the null mean in null standard deviations.
+//!
+//! Memory grows as N times the number of scoring variants (4 bytes each).
+
+use std::collections::HashMap;
+use crate::common::EffectWeights;
+use crate::error::{Error, Result};
+use crate::rng::Rng;
+
+/// Permuted weights of every scoring variant.
+#[derive(Clone, Debug)]
+pub struct Permutations {
+    pub count: usize,
+    /// The weight each permutation gives a variant, by (chr without "chr", pos)
+    weights: HashMap<(String, u64), Vec<f32>>,
+}
+
+impl Permutations {
+    /// Draw `count` shuffles of the weights in `effect_weights`. The same seed and scoring
+    /// file always give the same shuffles.
+    pub fn new(effect_weights: &EffectWeights, count: usize, seed: u64) -> Result<Self> {
+        if count < 2 {
+            return Err(Error::invalid("--permutations", "at least 2 permutations are needed for a null distribution"));
+        }
+        // Sorted, so the shuffles do not depend on hash order
+        let mut keys: Vec<&(String, u64)> = effect_weights.keys().collect();
+        keys.sort_unstable();
+        let mut weights: HashMap<(String, u64), Vec<f32>> =
+            keys.iter().map(|&key| (key.clone(), Vec::with_capacity(count))).collect();
+
+        let mut rng = Rng(seed);
+        let mut shuffled: Vec<f32> = keys.iter().map(|key| effect_weights[*key].weight).collect();
+        for _ in 0..count {
+            // Fisher-Yates
+            for i in (1..shuffled.len()).rev() {
+                shuffled.swap(i, rng.below(i as u64 + 1) as usize);
+            }
+            for (key, &weight) in keys.iter().zip(&shuffled) {
+                weights.get_mut(*key).expect("keys come from the map").push(weight);
+            }
+        }
+        Ok(Permutations { count, weights })
+    }
+
+    /// The permuted weights of the variant at `key`, one per permutation.
+    pub fn weights(&self, key: &(String, u64)) -> Option<&[f32]> {
+        self.weights.get(key).map(Vec::as_slice)
+    }
+}
+
+/// Percentile (0-100, ties counting half) and z-score of a sample's score against its
+/// null scores, given as `deviations` of each null score from the real one. The z-score
+/// is `None` when the null scores are all equal.
+pub fn summarize(deviations: &[f64]) -> (f64, Option<f64>) {
+    let n = deviations.len() as f64;
+    let below = deviations.iter().filter(|&&d| d < 0.0).count() as f64;
+    let ties = deviations.iter().filter(|&&d| d == 0.0).count() as f64;
+    let mean = deviations.iter().sum::<f64>() / n;
+    let sd = (deviations.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    (100.0 * (below + 0.5 * ties) / n, (sd > 0.0).then(|| -mean / sd))
+}
@@ -0,0 +1,732 @@
+//! Reading PLINK 2 filesets (`.pgen`, `.pvar` and `.psam`).
+//!
+//! Samples come from the `.psam` (by IID), variants from the `.pvar`, and each variant's
+//! genotypes from its `.pgen` record: a hardcall for every sample and, if the record has
+//! them, dosages for some or all samples. A [`Source`] scores these decoded genotypes
+//! directly, as [`crate::multi_sample`] does when a run needs nothing else of the records.
+//! Otherwise [`crate::bgzf::open`] hands the `.pgen` here, and the fileset is read as the
+//! VCF it stands for, as [`crate::plink`] reads PLINK 1 filesets: each variant has `GT`
+//! calls and a `DS` field, which `--dosage` scores. Either way, a sample without a dosage
+//! of its own is scored by its hardcall.
+//!
+//! Records are decoded in full as PLINK 2 writes them, compressed against the common
+//! genotype or the previous variant's or not. Phase is not read, so calls are unphased.
+//! Variants whose extra ALT alleles are called (multiallelic hardcalls) are not supported:
+//! split them with `plink2 --make-pgen multiallelics=-`. Neither is a `.pgen` whose index
+//! is in a separate `.pgi` file.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use crate::aliases;
+use crate::bgzf;
+use crate::error::{Error, ErrorPolicy, IoContext, Result};
+use crate::input::InputConfig;
+use crate::order::SortCheck;
+use crate::plink;
+use crate::resources;
+use crate::source::{GenotypeSource, Variant};
+
+/// Storage modes: a PLINK 1 `.bed`, fixed-width hardcalls, fixed-width hardcalls and
+/// dosages, and variable-width records (the default).
+const MODE_BED: u8 = 0x01;
+const MODE_FIXED: u8 = 0x02;
+const MODE_FIXED_DOSAGE: u8 = 0x03;
+const MODE_VARIABLE: u8 = 0x10;
+
+/// Variants per block of the variable-width index.
+const BLOCK: u64 = 1 << 16;
+
+/// Record type bits: which of the tracks a record has.
+const MULTIALLELIC: u8 = 0x08;
+const PHASE: u8 = 0x10;
+const DOSAGE: u8 = 0x60;
+const DOSAGE_LIST: u8 = 0x20;
+const DOSAGE_DENSE: u8 = 0x40;
+
+/// A dosage of 2 ALT alleles, and a missing one.
+const DOSAGE_SCALE: f64 = 16384.0;
+const DOSAGE_MISSING: u16 = 65535;
+
+/// `GT` of each genotype code: homozygous REF, heterozygous, homozygous ALT, missing.
+const CALLS: [&[u8; 3]; 4] = [b"0/0", b"0/1", b"1/1", b"./."];
+
+/// How the `.pgen`'s records are stored.
+enum Layout {
+    /// Every record has this type and size
+    Fixed { vrtype: u8, size: usize },
+    /// Each block of [`BLOCK`] variants has the types and sizes of its records in the
+    /// index, which `index` reads one block at a time
+    Variable { index: BufReader<File>, wide_types: bool, length_bytes: usize, allele_count_bytes: usize, nonref_flags: bool },
+}
+
+/// A PLINK 2 fileset, decoded one variant at a time.
+struct Fileset {
+    path: String,
+    pvar: Pvar,
+    records: BufReader<File>,
+    layout: Layout,
+    /// Whether records are `.bed` rows, coded as PLINK 1 codes them
+    bed: bool,
+    variants: u64,
+    variant: u64,
+    samples: usize,
+    /// Types and sizes of the current block's records, and which comes next
+    block: Vec<(u8, usize)>,
+    in_block: usize,
+    record: Vec<u8>,
+    /// One genotype code per sample, of the current variant and of the last one not
+    /// stored against the one before it
+    genotypes: Vec<u8>,
+    ld_base: Vec<u8>,
+    dosages: Vec<u16>,
+}
+
+/// A PLINK 2 fileset as VCF text: the header, then one line per variant.
+pub struct Reader {
+    fileset: Fileset,
+    /// The text of the header or the last variant, and how much of it has been read
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+/// A PLINK 2 fileset as a [`GenotypeSource`], reporting each sample's decoded dosage, or
+/// with `dosage` off its hardcall. Malformed `.pvar` lines and out-of-order variants are
+/// handled as `policy` says.
+pub struct Source<'a> {
+    fileset: Fileset,
+    sample_names: Vec<String>,
+    dosage: bool,
+    policy: &'a ErrorPolicy,
+    sort_check: SortCheck,
+}
+
+/// Open the fileset whose `.pgen` is at `path`, with its `.pvar` and `.psam` next to it.
+pub fn open(path: &str) -> Result<Reader> {
+    let (fileset, samples) = Fileset::open(path)?;
+    let prefix = path.strip_suffix(".pgen").unwrap_or(path);
+    let mut text = format!("##fileformat=VCFv4.2\n##source=SpeedScore, from the PLINK 2 fileset {}\n", prefix);
+    text.push_str("##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n");
+    text.push_str("##FORMAT=<ID=DS,Number=A,Type=Float,Description=\"ALT dosage\">\n");
+    text.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT");
+    for sample in &samples {
+        text.push('\t');
+        text.push_str(sample);
+    }
+    text.push('\n');
+    Ok(Reader { fileset, pending: text.into_bytes(), pos: 0 })
+}
+
+/// Whether `path` is a local `.pgen`: it has the PLINK magic and a PLINK 2 storage mode,
+/// or is named `.pgen`.
+pub fn is_pgen(path: &str) -> bool {
+    let mut header = Vec::with_capacity(3);
+    File::open(path).and_then(|file| file.take(3).read_to_end(&mut header)).is_ok() && is_pgen_header(&header, path)
+}
+
+/// Whether a file starting with `header` is a `.pgen`; PLINK 1 and 2 share their magic, so
+/// a `.pgen` is told apart by its storage mode, or its name for one stored as `.bed` rows.
+pub(crate) fn is_pgen_header(header: &[u8], path: &str) -> bool {
+    header.starts_with(&plink::BED_MAGIC[..2]) && (header.get(2).is_some_and(|&mode| mode > plink::BED_MAGIC[2]) || path.ends_with(".pgen"))
+}
+
+impl Fileset {
+    /// Open the `.pgen` at `path` and its `.pvar`, returning the `.psam`'s samples too.
+    fn open(path: &str) -> Result<(Fileset, Vec<String>)> {
+        let prefix = path
+            .strip_suffix(".pgen")
+            .ok_or_else(|| Error::invalid(path, "a PLINK 2 .pgen must be named PREFIX.pgen, next to PREFIX.pvar and PREFIX.psam"))?;
+        let (pvar_path, psam_path) = (format!("{}.pvar", prefix), format!("{}.psam", prefix));
+        if !std::path::Path::new(&pvar_path).exists() && std::path::Path::new(&format!("{}.zst", pvar_path)).exists() {
+            return Err(Error::invalid(&pvar_path, "the .pvar is zstd-compressed; decompress it with `zstd -d` first"));
+        }
+        let samples = read_psam(&psam_path)?;
+
+        let mut file = File::open(path).with_path(path)?;
+        let mut header = Vec::with_capacity(12);
+        file.by_ref().take(12).read_to_end(&mut header).with_path(path)?;
+        let header_len = header.len();
+        if header_len < 3 || header[..2] != plink::BED_MAGIC[..2] {
+            return Err(Error::invalid(path, "not a PLINK 2 .pgen"));
+        }
+        let mode = header[2];
+        let row = samples.len().div_ceil(4);
+        let counts = || -> Result<(u64, usize)> {
+            if header_len < 11 {
+                return Err(Error::invalid(path, "not a PLINK 2 .pgen: its header ends early"));
+            }
+            let variants = u32::from_le_bytes(header[3..7].try_into().expect("four bytes")) as u64;
+            let sample_count = u32::from_le_bytes(header[7..11].try_into().expect("four bytes")) as usize;
+            if sample_count != samples.len() {
+                return Err(Error::invalid(path, format!("the .pgen has {} samples, and {} has {}", sample_count, psam_path, samples.len())));
+            }
+            Ok((variants, sample_count))
+        };
+        let (variants, layout, first_record) = match mode {
+            MODE_BED => {
+                let size = file.metadata().with_path(path)?.len();
+                let variants = size.saturating_sub(3) / row.max(1) as u64;
+                (variants, Layout::Fixed { vrtype: 0, size: row }, 3)
+            }
+            MODE_FIXED => (counts()?.0, Layout::Fixed { vrtype: 0, size: row }, 11),
+            MODE_FIXED_DOSAGE => (counts()?.0, Layout::Fixed { vrtype: DOSAGE_DENSE, size: row + 2 * samples.len() }, 11),
+            MODE_VARIABLE => {
+                let (variants, _) = counts()?;
+                if header_len < 12 {
+                    return Err(Error::invalid(path, "not a PLINK 2 .pgen: its header ends early"));
+                }
+                let control = header[11];
+                if control & 0x0f >= 8 {
+                    return Err(Error::invalid(path, format!("unsupported .pgen record index layout {}", control & 0x0f)));
+                }
+                let allele_count_bytes = match (control >> 4) & 3 {
+                    3 => return Err(Error::invalid(path, "unsupported .pgen allele count width")),
+                    width => width as usize,
+                };
+                // The block offsets come first; the first block's records start at the first
+                let mut offset = [0u8; 8];
+                file.read_exact(&mut offset).with_path(path)?;
+                let first_record = u64::from_le_bytes(offset);
+                let blocks = variants.div_ceil(BLOCK);
+                let mut index = BufReader::new(File::open(path).with_path(path)?);
+                index.seek(SeekFrom::Start(12 + 8 * blocks)).with_path(path)?;
+                let layout = Layout::Variable {
+                    index,
+                    wide_types: control & 4 != 0,
+                    length_bytes: (control & 3) as usize + 1,
+                    allele_count_bytes,
+                    nonref_flags: control >> 6 == 3,
+                };
+                (variants, layout, first_record)
+            }
+            0x11 => return Err(Error::invalid(path, "a .pgen with its index in a separate .pgi is not supported; rewrite it with plink2 --make-pgen")),
+            mode => return Err(Error::invalid(path, format!("unsupported .pgen storage mode {:#04x}", mode))),
+        };
+        file.seek(SeekFrom::Start(first_record)).with_path(path)?;
+
+        let pvar = Pvar::open(&pvar_path)?;
+        let fileset = Fileset {
+            path: path.to_string(),
+            pvar,
+            records: BufReader::with_capacity(resources::current().read_buffer, file),
+            layout,
+            bed: mode == MODE_BED,
+            variants,
+            variant: 0,
+            samples: samples.len(),
+            block: Vec::new(),
+            in_block: 0,
+            record: Vec::new(),
+            genotypes: vec![0; samples.len()],
+            ld_base: vec![0; samples.len()],
+            dosages: vec![DOSAGE_MISSING; samples.len()],
+        };
+        Ok((fileset, samples))
+    }
+}
+
+/// The IIDs of the `.psam` at `path`, in order. Without a header line, it is read as a
+/// `.fam`, with the IID second.
+fn read_psam(path: &str) -> Result<Vec<String>> {
+    let reader = BufReader::new(File::open(path).with_path(path)?);
+    let mut samples = Vec::new();
+    let mut iid_column = 1;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::read(path, index + 1, e))?;
+        if line.starts_with("##") || line.trim().is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('#') {
+            iid_column = header
+                .split_whitespace()
+                .position(|column| column == "IID")
+                .ok_or_else(|| Error::parse(path, index + 1, "Expected an IID column", &line))?;
+            continue;
+        }
+        let iid = line.split_whitespace().nth(iid_column).ok_or_else(|| Error::parse(path, index + 1, "Expected an IID", &line))?;
+        samples.push(iid.to_string());
+    }
+    Ok(samples)
+}
+
+/// A `.pvar`, read a line at a time alongside the `.pgen`.
+struct Pvar {
+    path: String,
+    reader: Box<dyn BufRead + Send>,
+    /// The current variant's line, or the one read after the header, and its number
+    line: String,
+    line_number: usize,
+    /// Whether `line` was read after the header and not used yet
+    held: bool,
+    /// Where CHROM, POS, ID, REF and ALT are among its columns
+    columns: [usize; 5],
+}
+
+impl Pvar {
+    /// Open the `.pvar` at `path`, plain or gzipped, and find its columns from its header
+    /// line. Without one, it is read as a `.bim`.
+    fn open(path: &str) -> Result<Pvar> {
+        let mut reader = bgzf::open(path, &InputConfig::default())?;
+        let mut line = String::new();
+        let mut line_number = 0;
+        loop {
+            line.clear();
+            line_number += 1;
+            if reader.read_line(&mut line).map_err(|e| Error::read(path, line_number, e))? == 0 || !line.starts_with("##") {
+                break;
+            }
+        }
+        let mut columns = [0, 3, 1, 5, 4];
+        if let Some(header) = line.strip_prefix('#') {
+            let names: Vec<&str> = header.split_whitespace().collect();
+            for (column, name) in columns.iter_mut().zip(["CHROM", "POS", "ID", "REF", "ALT"]) {
+                *column = names.iter().position(|&n| n == name).ok_or_else(|| Error::parse(path, line_number, format!("Expected a {} column", name), &line))?;
+            }
+            line.clear();
+        }
+        Ok(Pvar { path: path.to_string(), reader, line, line_number, held: true, columns })
+    }
+
+    /// Read the next variant's line; `false` at the end.
+    fn advance(&mut self) -> io::Result<bool> {
+        if !std::mem::take(&mut self.held) {
+            self.line.clear();
+        }
+        while self.line.trim().is_empty() {
+            self.line.clear();
+            self.line_number += 1;
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The CHROM, POS, ID, REF and ALT of the current line.
+    fn fields(&self) -> io::Result<[&str; 5]> {
+        let fields: Vec<&str> = self.line.split_whitespace().collect();
+        let field = |column: usize| fields.get(column).copied();
+        match self.columns.map(field) {
+            [Some(chr), Some(pos), Some(id), Some(ref_allele), Some(alt)] => Ok([chr, pos, id, ref_allele, alt]),
+            _ => Err(invalid(format!("{}, line {}: too few columns for CHROM, POS, ID, REF and ALT", self.path, self.line_number))),
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Fileset {
+    /// Decode the next variant into `genotypes` and `dosages`, with its `.pvar` line in
+    /// `pvar`; `false` after the last one.
+    fn next_variant(&mut self) -> io::Result<bool> {
+        if !self.pvar.advance()? {
+            if self.variant != self.variants {
+                return Err(invalid(format!("{} has {} variants, and the .pgen has {}", self.pvar.path, self.variant, self.variants)));
+            }
+            return Ok(false);
+        }
+        if self.variant == self.variants {
+            return Err(invalid(format!("{} has more variants than the {} of the .pgen", self.pvar.path, self.variants)));
+        }
+        let vrtype = self.read_record()?;
+        self.decode(vrtype).map_err(|e| invalid(format!("variant {}: {}", self.variant + 1, e)))?;
+        self.variant += 1;
+        Ok(true)
+    }
+
+    /// The ALT dosage of `sample` in the current variant: its own dosage, if it has one and
+    /// `dosage` is on, or else its hardcall; `None` if both are missing.
+    fn alt_dosage(&self, sample: usize, dosage: bool) -> Option<f64> {
+        match (self.dosages[sample], self.genotypes[sample]) {
+            (value, _) if dosage && value != DOSAGE_MISSING => Some(value as f64 / DOSAGE_SCALE),
+            (_, 3) => None,
+            (_, genotype) => Some(genotype as f64),
+        }
+    }
+
+    /// Read the next record into `record`, returning its type.
+    fn read_record(&mut self) -> io::Result<u8> {
+        let (vrtype, size) = match &mut self.layout {
+            Layout::Fixed { vrtype, size } => (*vrtype, *size),
+            Layout::Variable { index, wide_types, length_bytes, allele_count_bytes, nonref_flags } => {
+                if self.in_block == self.block.len() {
+                    let count = (self.variants - self.variant).min(BLOCK) as usize;
+                    let mut types = vec![0u8; if *wide_types { count } else { count.div_ceil(2) }];
+                    let mut lengths = vec![0u8; count * *length_bytes];
+                    index.read_exact(&mut types)?;
+                    index.read_exact(&mut lengths)?;
+                    // Allele counts and provisional-REF flags, which do not change the calls
+                    let skip = count * *allele_count_bytes + if *nonref_flags { count.div_ceil(8) } else { 0 };
+                    index.seek_relative(skip as i64)?;
+                    self.block = (0..count)
+                        .map(|i| {
+                            let vrtype = if *wide_types { types[i] } else { (types[i / 2] >> (4 * (i % 2))) & 0x0f };
+                            let length = lengths[i * *length_bytes..(i + 1) * *length_bytes].iter().rev().fold(0, |size, &b| size << 8 | b as usize);
+                            (vrtype, length)
+                        })
+                        .collect();
+                    self.in_block = 0;
+                }
+                self.in_block += 1;
+                self.block[self.in_block - 1]
+            }
+        };
+        self.record.resize(size, 0);
+        self.records.read_exact(&mut self.record).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid(format!("the .pgen ends in variant {}", self.variant + 1)),
+            _ => e,
+        })?;
+        Ok(vrtype)
+    }
+
+    /// Decode `record`, of type `vrtype`, into `genotypes` and `dosages`.
+    fn decode(&mut self, vrtype: u8) -> std::result::Result<(), String> {
+        let samples = self.samples;
+        let mut record = Record { bytes: &self.record, at: 0, samples };
+        if self.bed {
+            // Homozygous A1 (ALT), missing, heterozygous, homozygous A2 (REF)
+            let bytes = record.take(samples.div_ceil(4))?;
+            for (sample, genotype) in self.genotypes.iter_mut().enumerate() {
+                *genotype = [2, 3, 1, 0][((bytes[sample / 4] >> (2 * (sample % 4))) & 3) as usize];
+            }
+            return Ok(());
+        }
+        match vrtype & 7 {
+            0 => {
+                let bytes = record.take(samples.div_ceil(4))?;
+                for (sample, genotype) in self.genotypes.iter_mut().enumerate() {
+                    *genotype = (bytes[sample / 4] >> (2 * (sample % 4))) & 3;
+                }
+            }
+            1 => {
+                // Two common genotypes, told apart by one bit each, then a list of the rest
+                let pair = record.take(1)?[0];
+                let (low, high) = (pair >> 2, (pair >> 2) + (pair & 3));
+                let bits = record.take(samples.div_ceil(8))?;
+                for (sample, genotype) in self.genotypes.iter_mut().enumerate() {
+                    *genotype = if bits[sample / 8] >> (sample % 8) & 1 == 1 { high } else { low };
+                }
+                record.difflist(true, |sample, genotype| self.genotypes[sample] = genotype)?;
+            }
+            kind @ (2 | 3) => {
+                // The differences from the last variant not stored this way, with REF and
+                // ALT swapped for 3
+                self.genotypes.copy_from_slice(&self.ld_base);
+                record.difflist(true, |sample, genotype| self.genotypes[sample] = genotype)?;
+                if kind == 3 {
+                    for genotype in &mut self.genotypes {
+                        *genotype = [2, 1, 0, 3][*genotype as usize];
+                    }
+                }
+            }
+            kind => {
+                // The differences from a genotype most samples have
+                self.genotypes.fill(kind & 3);
+                record.difflist(true, |sample, genotype| self.genotypes[sample] = genotype)?;
+            }
+        }
+        if vrtype & 6 != 2 {
+            self.ld_base.copy_from_slice(&self.genotypes);
+        }
+        if vrtype & MULTIALLELIC != 0 {
+            return Err("multiallelic hardcalls are not supported; split the variants with plink2 --make-pgen multiallelics=-".to_string());
+        }
+        if vrtype & PHASE != 0 {
+            // Which heterozygous calls are phased, and how; calls are read unphased
+            let hets = self.genotypes.iter().filter(|&&g| g == 1).count();
+            let first = record.bytes.get(record.at).copied().ok_or("the record ends early")?;
+            let mut bits = 1 + hets;
+            if first & 1 == 1 {
+                let present = record.bits(1, hets)?;
+                bits += present;
+            }
+            record.take(bits.div_ceil(8))?;
+        }
+
+        self.dosages.fill(DOSAGE_MISSING);
+        match vrtype & DOSAGE {
+            0 => {}
+            DOSAGE_LIST => {
+                let mut listed = Vec::new();
+                record.difflist(false, |sample, _| listed.push(sample))?;
+                for sample in listed {
+                    self.dosages[sample] = record.u16()?;
+                }
+            }
+            DOSAGE_DENSE => {
+                for sample in 0..samples {
+                    self.dosages[sample] = record.u16()?;
+                }
+            }
+            _ => {
+                let bits = record.take(samples.div_ceil(8))?;
+                for sample in 0..samples {
+                    if bits[sample / 8] >> (sample % 8) & 1 == 1 {
+                        self.dosages[sample] = record.u16()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Reader {
+    /// Write the next variant to `pending`; `false` after the last one.
+    fn next_variant(&mut self) -> io::Result<bool> {
+        let Reader { fileset, pending, pos } = self;
+        if !fileset.next_variant()? {
+            return Ok(false);
+        }
+        let [chr, position, id, ref_allele, alt] = fileset.pvar.fields()?;
+        pending.clear();
+        *pos = 0;
+        for field in [plink::chromosome(chr), position, id, ref_allele, alt, ".", ".", ".", "GT:DS"] {
+            pending.extend_from_slice(field.as_bytes());
+            pending.push(b'\t');
+        }
+        let mut floats = ryu::Buffer::new();
+        for (&genotype, &dosage) in fileset.genotypes.iter().zip(&fileset.dosages) {
+            pending.extend_from_slice(CALLS[genotype as usize]);
+            pending.push(b':');
+            match (dosage, genotype) {
+                (DOSAGE_MISSING, 3) => pending.push(b'.'),
+                (DOSAGE_MISSING, genotype) => pending.push(b'0' + genotype),
+                (dosage, _) => pending.extend_from_slice(floats.format(dosage as f64 / DOSAGE_SCALE).as_bytes()),
+            }
+            pending.push(b'\t');
+        }
+        *pending.last_mut().expect("a FORMAT column") = b'\n';
+        Ok(true)
+    }
+}
+
+impl<'a> Source<'a> {
+    /// Open the fileset whose `.pgen` is at `path`. With `dosage`, samples are scored by
+    /// their dosages where the `.pgen` has them.
+    pub fn open(path: &str, dosage: bool, policy: &'a ErrorPolicy) -> Result<Self> {
+        let (fileset, sample_names) = Fileset::open(path)?;
+        Ok(Source { fileset, sample_names, dosage, policy, sort_check: policy.sort_check() })
+    }
+}
+
+impl GenotypeSource for Source<'_> {
+    fn sample_names(&self) -> &[String] {
+        &self.sample_names
+    }
+
+    fn next_variant(&mut self) -> Result<Option<Variant>> {
+        let Source { fileset, policy, sort_check, .. } = self;
+        loop {
+            if !fileset.next_variant().map_err(|e| Error::read(&fileset.path, fileset.variant as usize + 1, e))? {
+                return Ok(None);
+            }
+            let pvar = &fileset.pvar;
+            let [chr, pos, _, ref_allele, alt_allele] = pvar.fields().map_err(|e| Error::read(&pvar.path, pvar.line_number, e))?;
+            let chr = plink::chromosome(chr);
+            let Ok(pos) = pos.parse::<u64>() else {
+                policy.handle_record(Error::parse(&pvar.path, pvar.line_number, "Invalid numeric position", &pvar.line), &pvar.line)?;
+                continue;
+            };
+            sort_check.check(&pvar.path, pvar.line_number, chr, pos, &pvar.line)?;
+            return Ok(Some(Variant {
+                chr: aliases::canonical(chr).to_string(),
+                pos,
+                ref_allele: ref_allele.to_string(),
+                alt_allele: alt_allele.to_string(),
+                chr_prefixed: chr.starts_with("chr"),
+            }));
+        }
+    }
+
+    fn alt_dosages(&mut self, out: &mut [Option<f64>]) -> Result<()> {
+        for (sample, slot) in out.iter_mut().enumerate() {
+            *slot = self.fileset.alt_dosage(sample, self.dosage);
+        }
+        Ok(())
+    }
+}
+
+/// The fields of one record, read in order.
+struct Record<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    samples: usize,
+}
+
+impl<'a> Record<'a> {
+    fn take(&mut self, length: usize) -> std::result::Result<&'a [u8], String> {
+        let bytes = self.bytes.get(self.at..self.at + length).ok_or("the record ends early")?;
+        self.at += length;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().expect("two bytes")))
+    }
+
+    /// A variable-length integer: seven bits to a byte, low bits first.
+    fn varint(&mut self) -> std::result::Result<usize, String> {
+        let mut value = 0usize;
+        for shift in (0..35).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("a malformed variable-length integer".to_string())
+    }
+
+    /// How many of `count` bits, starting `skip` bits into the next byte, are set; does
+    /// not advance.
+    fn bits(&self, skip: usize, count: usize) -> std::result::Result<usize, String> {
+        let bytes = self.bytes.get(self.at..self.at + (skip + count).div_ceil(8)).ok_or("the record ends early")?;
+        Ok((skip..skip + count).filter(|&bit| bytes[bit / 8] >> (bit % 8) & 1 == 1).count())
+    }
+
+    /// A list of samples, each with a genotype code if `genotypes`, passed to `each` in
+    /// order. Samples come in groups of 64: the first of each group is stored whole, and
+    /// the others as the difference from the one before.
+    fn difflist(&mut self, genotypes: bool, mut each: impl FnMut(usize, u8)) -> std::result::Result<(), String> {
+        let length = self.varint()?;
+        if length == 0 {
+            return Ok(());
+        }
+        let groups = length.div_ceil(64);
+        let id_bytes = (usize::BITS - self.samples.leading_zeros()).div_ceil(8).max(1) as usize;
+        let firsts = self.take(groups * id_bytes)?;
+        // The byte lengths of each group but the last, to skip groups by
+        self.take(groups - 1)?;
+        let codes = if genotypes { self.take(length.div_ceil(4))? } else { &[] };
+        let mut sample = 0;
+        for entry in 0..length {
+            if entry % 64 == 0 {
+                let group = entry / 64;
+                sample = firsts[group * id_bytes..(group + 1) * id_bytes].iter().rev().fold(0, |id, &b| id << 8 | b as usize);
+            } else {
+                sample += self.varint()?;
+            }
+            if sample >= self.samples {
+                return Err(format!("sample {} is past the last", sample + 1));
+            }
+            let code = if genotypes { (codes[entry / 4] >> (2 * (entry % 4))) & 3 } else { 0 };
+            each(sample, code);
+        }
+        Ok(())
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = buf.len().min(available.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for Reader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.pending.len() && !self.next_variant()? {
+            return Ok(&[]);
+        }
+        Ok(&self.pending[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.pending.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! The fixtures in `tests/data/pgen` hold one fileset twice: `variable.pgen` in
+    //! variable-width records of every genotype compression, phase and dosage track, and
+    //! `fixed.pgen` as fixed-width hardcalls and dosages. The expected scores were worked
+    //! out by hand from the genotypes and dosages the fixtures encode.
+
+    use crate::common::{load_scoring_file, EffectWeights, ScoreOptions};
+    use crate::error::{NonFinite, OnError};
+    use crate::source::{score_source, VcfSource};
+    use super::*;
+
+    /// Scores and matched variants of S1 to S9 from their hardcalls, and from their dosages.
+    const HARDCALLS: [(f64, u64); 9] = [(2.125, 7), (3.625, 7), (4.375, 7), (3.375, 7), (1.0, 7), (-1.875, 5), (0.625, 7), (2.75, 7), (0.875, 8)];
+    const DOSAGES: [(f64, u64); 9] = [(2.625, 7), (1.125, 7), (5.5, 8), (3.875, 7), (1.0, 7), (-0.875, 6), (0.625, 7), (2.5, 7), (0.8125, 8)];
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/data/pgen/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn weights() -> EffectWeights {
+        load_scoring_file(&fixture("weights.txt"), NonFinite::Reject, None).unwrap().0
+    }
+
+    fn scores(source: &mut dyn GenotypeSource) -> Vec<(f64, u64)> {
+        let scores = score_source(source, &weights(), &ScoreOptions::default()).unwrap();
+        assert_eq!(scores.sample_names, ["S1", "S2", "S3", "S4", "S5", "S6", "S7", "S8", "S9"]);
+        scores.sample_data.iter().map(|data| (data.score, data.matched_variants)).collect()
+    }
+
+    fn decoded(pgen: &str, dosage: bool) -> Vec<(f64, u64)> {
+        let policy = ErrorPolicy::new(OnError::Fail, None);
+        scores(&mut Source::open(&fixture(pgen), dosage, &policy).unwrap())
+    }
+
+    fn as_text(pgen: &str, dosage: bool) -> Vec<(f64, u64)> {
+        let path = fixture(pgen);
+        scores(&mut VcfSource::from_reader(&path, Box::new(open(&path).unwrap()), dosage).unwrap())
+    }
+
+    #[test]
+    fn hardcalls_score_as_known() {
+        assert_eq!(decoded("variable.pgen", false), HARDCALLS);
+        assert_eq!(decoded("fixed.pgen", false), HARDCALLS);
+    }
+
+    #[test]
+    fn dosages_score_as_known() {
+        assert_eq!(decoded("variable.pgen", true), DOSAGES);
+        assert_eq!(decoded("fixed.pgen", true), DOSAGES);
+    }
+
+    #[test]
+    fn vcf_text_scores_as_decoded() {
+        for pgen in ["variable.pgen", "fixed.pgen"] {
+            assert_eq!(as_text(pgen, false), HARDCALLS);
+            assert_eq!(as_text(pgen, true), DOSAGES);
+        }
+    }
+
+    #[test]
+    fn vcf_text_has_calls_and_dosages() {
+        let mut text = String::new();
+        open(&fixture("variable.pgen")).unwrap().read_to_string(&mut text).unwrap();
+        let records: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+        // A dosage list with an explicitly missing dosage, then a missing call with a dosage
+        assert_eq!(records[1], "1\t200\trs200_1\tC\tT\t.\t.\t.\tGT:DS\t0/0:0.5\t0/0:0\t0/1:1.5\t0/0:0\t1/1:2\t0/0:0\t0/0:0\t0/0:0\t0/1:1.25");
+        assert_eq!(records[7], "2\t300\trs300_2\tT\tA\t.\t.\t.\tGT:DS\t./.:.\t./.:.\t./.:1.0\t0/1:1.5\t./.:.\t./.:.\t./.:.\t./.:.\t0/0:0");
+    }
+
+    #[test]
+    fn fileset_of_another_size_is_refused() {
+        let dir = std::env::temp_dir().join(format!("speedscore-pgen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("short").to_string_lossy().into_owned();
+        std::fs::copy(fixture("fixed.pgen"), format!("{}.pgen", prefix)).unwrap();
+        std::fs::copy(fixture("fixed.psam"), format!("{}.psam", prefix)).unwrap();
+        let pvar = std::fs::read_to_string(fixture("fixed.pvar")).unwrap();
+        std::fs::write(format!("{}.pvar", prefix), pvar.lines().take(5).collect::<Vec<_>>().join("\n")).unwrap();
+
+        let policy = ErrorPolicy::new(OnError::Fail, None);
+        let mut source = Source::open(&format!("{}.pgen", prefix), false, &policy).unwrap();
+        let Err(error) = score_source(&mut source, &weights(), &ScoreOptions::default()) else {
+            panic!("a .pvar short of the .pgen's variants was read");
+        };
+        assert!(error.to_string().contains("has 4 variants, and the .pgen has 9"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! `speedscore plan`: splitting one run into jobs for a batch scheduler (SLURM job arrays,
+//! AWS Batch array jobs, DNAnexus subjobs).
+//!
+//! A shard is a block of samples (`--sample-range`) and, with `--by-chromosome`, one of the
+//! scoring file's chromosomes (`--chromosomes`). The manifest lists each job's shard, the
+//! memory and CPUs to request for it, where it writes its results and the command that
+//! runs it. Sample blocks are merged by concatenating their rows; chromosome shards by
+//! summing each sample's scores and variant counts, as the score is a sum over variants.
+
+use std::collections::BTreeMap;
+use std::fs;
+use clap::ValueEnum;
+use serde::Serialize;
+use crate::bgzf;
+use crate::common::{create_parent_dirs, read_sample_names, vcf_stem, EffectWeights, Model, PlanArgs};
+use crate::contigs;
+use crate::error::{Error, IoContext, Result};
+use crate::input::InputConfig;
+use crate::resources;
+use crate::scorer::Scorer;
+use crate::tabix;
+use crate::upload::Uploads;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Share of the estimated memory added on top when requesting it.
+const HEADROOM: f64 = 0.25;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    Json,
+    /// One line per job, with the command in the last column
+    Tsv,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    vcf: String,
+    vcf_bytes: u64,
+    /// The VCF's tabix (`.tbi`) or CSI (`.csi`) index, if it has one
+    index: Option<String>,
+    samples: usize,
+    scoring: String,
+    scoring_variants: usize,
+    /// How to combine the jobs' results: `concatenate` the rows of sample blocks, or `sum`
+    /// each sample's scores over chromosome shards (and concatenate the sample blocks)
+    merge: &'static str,
+    jobs: Vec<Job>,
+}
+
+#[derive(Serialize)]
+struct Job {
+    /// 1-based, like SLURM array task IDs
+    job: usize,
+    /// 1-based header positions, inclusive
+    first_sample: usize,
+    last_sample: usize,
+    /// The job's chromosome; absent unless planned `--by-chromosome`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chromosome: Option<String>,
+    variants: usize,
+    memory_mib: u64,
+    cpus: usize,
+    output: String,
+    command: Vec<String>,
+}
+
+pub fn run(args: &PlanArgs) -> Result<()> {
+    if args.samples_per_job == Some(0) {
+        return Err(Error::invalid("--samples-per-job", "must be at least 1"));
+    }
+    if args.cpus_per_job == 0 {
+        return Err(Error::invalid("--cpus-per-job", "must be at least 1"));
+    }
+    let vcf_bytes = fs::metadata(&args.vcf).with_path(&args.vcf)?.len();
+    let samples = read_sample_names(&args.vcf)?.len();
+    if samples == 0 {
+        return Err(Error::invalid(&args.vcf, "the VCF has no samples to score"));
+    }
+    let index = tabix::find(&args.vcf);
+    let scorer = Scorer::from_scoring_file(&args.scoring)?;
+    let weights = scorer.weights();
+
+    let block_size = match args.samples_per_job {
+        // A single-sample VCF is scored whole
+        Some(size) if samples > 1 => size,
+        _ => samples,
+    };
+    let blocks: Vec<_> = (0..samples).step_by(block_size).map(|start| start..(start + block_size).min(samples)).collect();
+    let shards = match args.by_chromosome {
+        true => chromosomes(args, weights, index.as_deref())?.into_iter().map(|(chr, n)| (Some(chr), n)).collect(),
+        false => vec![(None, weights.len())],
+    };
+
+    let stem = vcf_stem(args.vcf.rsplit('/').next().unwrap_or(&args.vcf));
+    let extension = if samples > 1 { "scores.csv" } else { "score.tsv" };
+    let mut jobs = Vec::new();
+    for block in &blocks {
+        for (chromosome, variants) in &shards {
+            let job = jobs.len() + 1;
+            let output = format!("{}/{}.job{}.{}", args.output_dir.trim_end_matches('/'), stem, job, extension);
+            let mut command = vec!["SpeedScore", "--vcf", &args.vcf, "--scoring", &args.scoring].into_iter().map(String::from).collect::<Vec<_>>();
+            if blocks.len() > 1 {
+                command.extend(["--sample-range".to_string(), format!("{}-{}", block.start + 1, block.end)]);
+            }
+            if let Some(chr) = chromosome {
+                command.extend(["--chromosomes".to_string(), chr.clone()]);
+            }
+            if args.dosage {
+                command.push("--dosage".to_string());
+            }
+            if args.model != Model::Additive {
+                command.extend(["--model".to_string(), args.model.to_possible_value().expect("no model is skipped").get_name().to_string()]);
+            }
+            command.extend(["--output".to_string(), output.clone()]);
+            let memory = resources::estimate(*variants, block.len()) as f64 * (1.0 + HEADROOM);
+            jobs.push(Job {
+                job,
+                first_sample: block.start + 1,
+                last_sample: block.end,
+                chromosome: chromosome.clone(),
+                variants: *variants,
+                memory_mib: (memory / MIB as f64).ceil() as u64,
+                cpus: args.cpus_per_job,
+                output,
+                command,
+            });
+        }
+    }
+
+    let manifest = Manifest {
+        vcf: args.vcf.clone(),
+        vcf_bytes,
+        index,
+        samples,
+        scoring: args.scoring.clone(),
+        scoring_variants: weights.len(),
+        merge: if args.by_chromosome { "sum" } else { "concatenate" },
+        jobs,
+    };
+    let text = match args.manifest_format {
+        ManifestFormat::Json => serde_json::to_string_pretty(&manifest).expect("manifest serialization cannot fail") + "\n",
+        ManifestFormat::Tsv => tsv(&manifest),
+    };
+    let summary = format!(
+        "{} jobs over {} samples and {} scoring variants: {} sample block(s) x {} scoring shard(s), merged by {}",
+        manifest.jobs.len(),
+        samples,
+        shards.iter().map(|(_, variants)| variants).sum::<usize>(),
+        blocks.len(),
+        shards.len(),
+        manifest.merge
+    );
+    let mut uploads = Uploads::new();
+    let path = uploads.stage(&args.output)?;
+    create_parent_dirs(&path)?;
+    fs::write(&path, text).with_path(&path)?;
+    println!("Planned {}", summary);
+    println!("Manifest written to: {}", path);
+    uploads.finish()?;
+    Ok(())
+}
+
+/// The scoring file's chromosomes with their variant counts, leaving out the ones the VCF
+/// has no records on (per its index) or does not declare (per its `##contig` lines). They
+/// come in the VCF's order when it names its chromosomes, and sorted by name otherwise.
+fn chromosomes(args: &PlanArgs, weights: &EffectWeights, index: Option<&str>) -> Result<Vec<(String, usize)>> {
+    let mut counts = BTreeMap::new();
+    for (chr, _) in weights.keys() {
+        *counts.entry(chr.clone()).or_insert(0) += 1;
+    }
+    let (source, present) = match index.map(tabix::Index::read).transpose()?.and_then(|index| index.names) {
+        Some(names) => ("index", names),
+        None => ("##contig lines", contigs::read_contigs(&args.vcf, bgzf::open(&args.vcf, &InputConfig::default())?)?),
+    };
+    if present.is_empty() {
+        return Ok(counts.into_iter().collect());
+    }
+    let kept: Vec<(String, usize)> = present.iter().filter_map(|chr| counts.remove_entry(chr)).collect();
+    if !counts.is_empty() {
+        let names: Vec<String> = counts.keys().cloned().collect();
+        eprintln!(
+            "Warning: no jobs for the {} scoring variants on chromosomes missing from the VCF's {}: {}",
+            counts.values().sum::<usize>(),
+            source,
+            contigs::list(names.iter())
+        );
+    }
+    if kept.is_empty() {
+        return Err(Error::invalid(&args.vcf, format!("none of the scoring file's chromosomes is in the VCF's {}", source)));
+    }
+    Ok(kept)
+}
+
+/// The manifest as a table with a header line, one job per line.
+fn tsv(manifest: &Manifest) -> String {
+    let mut text = String::from("job\tfirst_sample\tlast_sample\tchromosome\tvariants\tmemory_mib\tcpus\toutput\tcommand\n");
+    for job in &manifest.jobs {
+        let command: Vec<String> = job.command.iter().map(|arg| shell_quote(arg)).collect();
+        text += &format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            job.job,
+            job.first_sample,
+            job.last_sample,
+            job.chromosome.as_deref().unwrap_or("."),
+            job.variants,
+            job.memory_mib,
+            job.cpus,
+            job.output,
+            command.join(" ")
+        );
+    }
+    text
+}
+
+/// `arg` as one word for a POSIX shell.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    match !arg.is_empty() && arg.chars().all(plain) {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', r"'\''")),
+    }
+}
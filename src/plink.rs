@@ -0,0 +1,193 @@
+//! Reading PLINK 1 binary filesets (`.bed`, `.bim` and `.fam`).
+//!
+//! [`crate::bgzf::open`] hands a file starting with the `.bed` magic here, and the fileset
+//! is read as the VCF it stands for: samples come from the `.fam` (by IID), variants from
+//! the `.bim`, and each variant's genotypes from its row of the `.bed`, as `GT` calls. So
+//! everything after reading works as it does for a VCF.
+//!
+//! Each variant's A2 allele (the `.bim`'s last column, usually the major allele) is REF
+//! and A1 is ALT, as `plink --recode vcf` writes them. PLINK's numeric codes for the sex
+//! chromosomes and mitochondria, 23, 24 and 26, are written as X, Y and MT; 25, the
+//! pseudo-autosomal region PLINK calls XY, stays XY.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use crate::error::{Error, IoContext, Result};
+use crate::resources;
+
+/// The first three bytes of a `.bed`: two magic bytes, then 1 for variant-major order.
+pub const BED_MAGIC: [u8; 3] = [0x6c, 0x1b, 0x01];
+
+/// `GT` of each two-bit `.bed` code: homozygous A1, missing, heterozygous, homozygous A2.
+const CALLS: [&[u8; 3]; 4] = [b"1/1", b"./.", b"0/1", b"0/0"];
+
+/// A PLINK fileset as VCF text: the header, then one line per variant.
+pub struct Reader {
+    bim_path: String,
+    bim: BufReader<File>,
+    bim_line: String,
+    bim_line_number: usize,
+    bed: BufReader<File>,
+    samples: usize,
+    /// The `.bed` row of the variant being read: four samples to a byte
+    row: Vec<u8>,
+    /// The text of the header or the last variant, and how much of it has been read
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+/// Open the fileset whose `.bed` is at `path`, with its `.bim` and `.fam` next to it.
+pub fn open(path: &str) -> Result<Reader> {
+    let prefix = path
+        .strip_suffix(".bed")
+        .ok_or_else(|| Error::invalid(path, "a PLINK .bed must be named PREFIX.bed, next to PREFIX.bim and PREFIX.fam"))?;
+    let (bim_path, fam_path) = (format!("{}.bim", prefix), format!("{}.fam", prefix));
+    let samples = read_fam(&fam_path)?;
+
+    let mut bed = BufReader::with_capacity(resources::current().read_buffer, File::open(path).with_path(path)?);
+    let mut magic = [0u8; 3];
+    bed.read_exact(&mut magic).map_err(|_| Error::invalid(path, "not a PLINK .bed: it is shorter than its magic"))?;
+    if magic[..2] != BED_MAGIC[..2] {
+        return Err(Error::invalid(path, "not a PLINK .bed"));
+    }
+    if magic[2] != BED_MAGIC[2] {
+        return Err(Error::invalid(path, "sample-major .bed files are not supported; rewrite it with plink --make-bed"));
+    }
+    // Every variant has a row of the same size, so the three files must agree
+    let variants = count_lines(&bim_path)?;
+    let row_size = samples.len().div_ceil(4);
+    let expected = 3 + variants * row_size as u64;
+    let size = bed.get_ref().metadata().with_path(path)?.len();
+    if size != expected {
+        return Err(Error::invalid(
+            path,
+            format!("the .bed has {} bytes, where {} variants in {} and {} samples in {} need {}", size, variants, bim_path, samples.len(), fam_path, expected),
+        ));
+    }
+
+    let mut header = format!("##fileformat=VCFv4.2\n##source=SpeedScore, from the PLINK fileset {}\n", prefix);
+    header.push_str("##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n");
+    header.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT");
+    for sample in &samples {
+        header.push('\t');
+        header.push_str(sample);
+    }
+    header.push('\n');
+    let bim = BufReader::with_capacity(resources::current().read_buffer, File::open(&bim_path).with_path(&bim_path)?);
+    Ok(Reader {
+        bim_path,
+        bim,
+        bim_line: String::new(),
+        bim_line_number: 0,
+        bed,
+        samples: samples.len(),
+        row: vec![0; row_size],
+        pending: header.into_bytes(),
+        pos: 0,
+    })
+}
+
+/// The IIDs of the `.fam` at `path`, in order.
+fn read_fam(path: &str) -> Result<Vec<String>> {
+    let reader = BufReader::new(File::open(path).with_path(path)?);
+    let mut samples = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| Error::read(path, index + 1, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let iid = line.split_whitespace().nth(1).ok_or_else(|| Error::parse(path, index + 1, "Expected a family ID and an individual ID", &line))?;
+        samples.push(iid.to_string());
+    }
+    Ok(samples)
+}
+
+/// Lines of the file at `path` that are not blank.
+fn count_lines(path: &str) -> Result<u64> {
+    let reader = BufReader::new(File::open(path).with_path(path)?);
+    let mut count = 0;
+    for (index, line) in reader.split(b'\n').enumerate() {
+        let line = line.map_err(|e| Error::read(path, index + 1, e))?;
+        count += u64::from(!line.trim_ascii().is_empty());
+    }
+    Ok(count)
+}
+
+impl Reader {
+    /// Write the next variant to `pending`; `false` after the last one.
+    fn next_variant(&mut self) -> io::Result<bool> {
+        loop {
+            self.bim_line.clear();
+            self.bim_line_number += 1;
+            if self.bim.read_line(&mut self.bim_line)? == 0 {
+                return Ok(false);
+            }
+            if !self.bim_line.trim().is_empty() {
+                break;
+            }
+        }
+        let fields: Vec<&str> = self.bim_line.split_whitespace().collect();
+        let [chr, id, _, pos, a1, a2, ..] = fields[..] else {
+            let message = format!("{}, line {}: expected 6 columns (chromosome, ID, cM, position, A1, A2)", self.bim_path, self.bim_line_number);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        };
+        self.bed.read_exact(&mut self.row)?;
+
+        self.pending.clear();
+        self.pos = 0;
+        for field in [chromosome(chr), pos, id, allele(a2), allele(a1), ".", ".", ".", "GT"] {
+            self.pending.extend_from_slice(field.as_bytes());
+            self.pending.push(b'\t');
+        }
+        for sample in 0..self.samples {
+            let code = (self.row[sample / 4] >> (2 * (sample % 4))) & 0b11;
+            self.pending.extend_from_slice(CALLS[code as usize]);
+            self.pending.push(b'\t');
+        }
+        *self.pending.last_mut().expect("a GT column") = b'\n';
+        Ok(true)
+    }
+}
+
+/// A PLINK chromosome code as a VCF chromosome: 23, 24 and 26 are X, Y and MT.
+pub(crate) fn chromosome(code: &str) -> &str {
+    match code {
+        "23" => "X",
+        "24" => "Y",
+        "26" => "MT",
+        code => code,
+    }
+}
+
+/// A `.bim` allele as a VCF one: PLINK writes a missing allele, as in a monomorphic
+/// variant, as 0.
+fn allele(allele: &str) -> &str {
+    if allele == "0" {
+        "."
+    } else {
+        allele
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = buf.len().min(available.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for Reader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.pending.len() && !self.next_variant()? {
+            return Ok(&[]);
+        }
+        Ok(&self.pending[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.pending.len());
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Mutex;
+use crate::aliases;
+use crate::common::SiteCounts;
+
+/// Live state of a multi-sample run, written by the scoring loop and read by a
+/// dashboard on another thread.
+#[derive(Default)]
+pub struct Progress {
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProgressState {
+    pub lines: usize,
+    /// Current chromosome, with "chr" stripped
+    pub chr: String,
+    pub pos: u64,
+    /// Chromosomes the run has moved past, in file order
+    pub finished_chromosomes: Vec<String>,
+    pub sites: SiteCounts,
+    pub finished: bool,
+}
+
+impl Progress {
+    pub fn record(&self, lines: usize, chr: &str, pos: u64, sites: SiteCounts) {
+        let chr = aliases::canonical(chr);
+        let mut state = self.state.lock().unwrap();
+        if state.chr != chr {
+            let previous = std::mem::replace(&mut state.chr, chr.to_string());
+            if !previous.is_empty() {
+                state.finished_chromosomes.push(previous);
+            }
+        }
+        state.lines = lines;
+        state.pos = pos;
+        state.sites = sites;
+    }
+
+    /// Mark the run as over, successfully or not.
+    pub fn finish(&self) {
+        self.state.lock().unwrap().finished = true;
+    }
+
+    pub fn snapshot(&self) -> ProgressState {
+        self.state.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use crate::catalog::ScoreMetadata;
+use crate::checksum::{Digests, InputChecksums};
+use crate::common::{create_parent_dirs, ScoreOptions, ScoreSummary};
+use crate::error::{IoContext, Result};
+use crate::qc;
+use crate::schema;
+
+/// Everything needed to trace a score back to its inputs and settings, written as
+/// JSON next to the results.
+#[derive(Serialize)]
+pub struct Provenance {
+    /// See [`crate::schema`]
+    pub schema_version: u32,
+    pub tool: &'static str,
+    pub version: &'static str,
+    /// Seconds since the Unix epoch when the record was created
+    pub created: u64,
+    pub duration_seconds: f64,
+    pub vcf: FileRecord,
+    pub scoring: FileRecord,
+    pub scoring_variants: usize,
+    /// With `--pgs-catalog`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pgs_catalog: Option<ScoreMetadata>,
+    pub options: ScoreOptions,
+    pub match_rate: f64,
+    pub summary: ScoreSummary,
+}
+
+/// An input file as it was when scored.
+#[derive(Serialize)]
+pub struct FileRecord {
+    pub path: String,
+    pub size_bytes: Option<u64>,
+    /// Modification time, seconds since the Unix epoch
+    pub modified: Option<u64>,
+    /// With `--checksums`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<Digests>,
+}
+
+impl FileRecord {
+    /// Describe `path`; size and mtime are left empty if it can't be stat'ed.
+    pub fn describe(path: &str) -> Self {
+        let metadata = fs::metadata(path).ok();
+        FileRecord {
+            path: path.to_string(),
+            size_bytes: metadata.as_ref().map(|m| m.len()),
+            modified: metadata.and_then(|m| m.modified().ok()).map(unix_seconds),
+            checksums: None,
+        }
+    }
+}
+
+impl Provenance {
+    pub fn new(
+        vcf_path: &str,
+        scoring_path: &str,
+        scoring_variants: usize,
+        options: ScoreOptions,
+        summary: &ScoreSummary,
+        duration: Duration,
+    ) -> Self {
+        Provenance {
+            schema_version: schema::SCHEMA_VERSION,
+            tool: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            created: unix_seconds(SystemTime::now()),
+            duration_seconds: duration.as_secs_f64(),
+            vcf: FileRecord::describe(vcf_path),
+            scoring: FileRecord::describe(scoring_path),
+            scoring_variants,
+            pgs_catalog: None,
+            options,
+            match_rate: qc::match_rate(&summary.sites, scoring_variants),
+            summary: summary.clone(),
+        }
+    }
+
+    /// Record the inputs' checksums.
+    pub fn with_checksums(mut self, checksums: InputChecksums) -> Self {
+        self.vcf.checksums = Some(checksums.vcf);
+        self.scoring.checksums = Some(checksums.scoring);
+        self
+    }
+
+    /// Record what the PGS Catalog says of the score.
+    pub fn with_pgs_catalog(mut self, metadata: ScoreMetadata) -> Self {
+        self.pgs_catalog = Some(metadata);
+        self
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        create_parent_dirs(path)?;
+        let json = serde_json::to_string_pretty(self).expect("provenance serialization cannot fail");
+        fs::write(path, json + "\n").with_path(path)
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,85 @@
+//! Python bindings, compiled with `--features python` and packaged by maturin (see
+//! `pyproject.toml`). Results are returned as dicts of equal-length columns, which
+//! `pandas.DataFrame(...)` and `numpy.asarray(...)` accept directly.
+
+use clap::ValueEnum;
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use crate::common::{load_scoring_file, Model};
+use crate::error::{Error, NonFinite};
+use crate::scorer::Scorer;
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> PyErr {
+        match e {
+            Error::Io { .. } | Error::Read { .. } => PyIOError::new_err(e.to_string()),
+            Error::Parse { .. } | Error::Invalid { .. } => PyValueError::new_err(e.to_string()),
+            _ => PyRuntimeError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// Load a scoring file as columns `chr`, `pos`, `effect_allele`, `effect_weight`,
+/// sorted by chromosome and position.
+#[pyfunction]
+fn load_scoring<'py>(py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyDict>> {
+    let (weights, _) = py.detach(|| load_scoring_file(path, NonFinite::default(), None))?;
+    let mut rows: Vec<_> = weights.into_iter().collect();
+    rows.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let columns = PyDict::new(py);
+    columns.set_item("chr", rows.iter().map(|((chr, _), _)| chr.as_str()).collect::<Vec<_>>())?;
+    columns.set_item("pos", rows.iter().map(|((_, pos), _)| *pos).collect::<Vec<_>>())?;
+    columns.set_item("effect_allele", rows.iter().map(|(_, w)| w.effect_allele.as_str()).collect::<Vec<_>>())?;
+    columns.set_item("effect_weight", rows.iter().map(|(_, w)| w.weight).collect::<Vec<_>>())?;
+    Ok(columns)
+}
+
+/// A VCF's path, or a list of paths of VCFs read as one.
+#[derive(FromPyObject)]
+enum Vcfs {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Score every sample of `vcf` against `scoring`, returning columns `sample`, `score`,
+/// `total_variants` and `matched_variants`. `vcf` may be a list of VCFs with the same
+/// samples, such as one per chromosome, scored as one. The GIL is released while scoring.
+#[pyfunction]
+#[pyo3(signature = (vcf, scoring, dosage = false, model = "additive", threads = None))]
+fn score<'py>(
+    py: Python<'py>,
+    vcf: Vcfs,
+    scoring: &str,
+    dosage: bool,
+    model: &str,
+    threads: Option<usize>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let model = Model::from_str(model, true).map_err(PyValueError::new_err)?;
+    let results = py.detach(|| {
+        let mut scorer = Scorer::from_scoring_file(scoring)?.dosage(dosage).model(model);
+        if let Some(threads) = threads {
+            scorer = scorer.threads(threads);
+        }
+        match vcf {
+            Vcfs::One(path) => scorer.run(&path),
+            Vcfs::Many(paths) => scorer.run_files(&paths),
+        }
+    })?;
+
+    let samples = &results.samples;
+    let columns = PyDict::new(py);
+    columns.set_item("sample", samples.iter().map(|s| s.name.as_str()).collect::<Vec<_>>())?;
+    columns.set_item("score", samples.iter().map(|s| s.score).collect::<Vec<_>>())?;
+    columns.set_item("total_variants", samples.iter().map(|s| s.total_variants).collect::<Vec<_>>())?;
+    columns.set_item("matched_variants", samples.iter().map(|s| s.matched_variants).collect::<Vec<_>>())?;
+    Ok(columns)
+}
+
+#[pymodule]
+fn speedscore(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(load_scoring, m)?)?;
+    m.add_function(wrap_pyfunction!(score, m)?)?;
+    Ok(())
+}
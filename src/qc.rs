@@ -0,0 +1,66 @@
+use std::fmt;
+use serde::Serialize;
+use crate::common::SiteCounts;
+
+/// Thresholds enforced by `--strict`. Rates are fractions in [0, 1].
+pub struct QcThresholds {
+    pub min_match_rate: f64,
+    pub min_allele_concordance: f64,
+}
+
+/// A failed QC check, carrying enough detail to be parsed by a pipeline.
+#[derive(Debug, Serialize)]
+pub struct QcFailure {
+    pub reason: &'static str,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for QcFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "QC_FAILURE\treason={}\tvalue={:.6}\tthreshold={:.6}",
+            self.reason, self.value, self.threshold
+        )
+    }
+}
+
+impl std::error::Error for QcFailure {}
+
+/// Fraction of scoring-file variants found in the VCF with a concordant allele.
+pub fn match_rate(sites: &SiteCounts, scoring_variants: usize) -> f64 {
+    if scoring_variants == 0 {
+        return 0.0;
+    }
+    sites.allele_hits as f64 / scoring_variants as f64
+}
+
+/// Of the scoring positions present in the VCF, the fraction whose effect allele is REF or ALT.
+pub fn allele_concordance(sites: &SiteCounts) -> f64 {
+    if sites.position_hits == 0 {
+        return 0.0;
+    }
+    sites.allele_hits as f64 / sites.position_hits as f64
+}
+
+/// Check the run against the thresholds, returning every failed check.
+pub fn check(sites: &SiteCounts, scoring_variants: usize, thresholds: &QcThresholds) -> Vec<QcFailure> {
+    let mut failures = Vec::new();
+
+    let rate = match_rate(sites, scoring_variants);
+    if rate < thresholds.min_match_rate {
+        failures.push(QcFailure { reason: "low_match_rate", value: rate, threshold: thresholds.min_match_rate });
+    }
+
+    let concordance = allele_concordance(sites);
+    if concordance < thresholds.min_allele_concordance {
+        failures.push(QcFailure {
+            reason: "low_allele_concordance",
+            value: concordance,
+            threshold: thresholds.min_allele_concordance,
+        });
+    }
+
+    failures
+}
@@ -0,0 +1,192 @@
+//! `speedscore queue`: scoring jobs taken from a Redis list, compiled with
+//! `--features queue`, for platforms that hand work out through a message queue.
+//!
+//! A job is a JSON object pushed (`RPUSH`) onto the jobs list, naming a VCF and the IDs
+//! of scoring files registered with `--scoring ID=PATH`:
+//! `{"id": "job-42", "vcf": "/data/cohort.vcf.gz", "scoring": ["PGS000001"]}`, with
+//! optional `dosage` and `model`. `--workers` jobs run at once, each worker on a
+//! connection of its own. While a job runs it sits on `<jobs>:processing`, where `BLMOVE`
+//! put it atomically, so the jobs of a worker killed mid-run can be pushed back rather
+//! than lost. Every job ends with an event on the events list, whether it succeeded or not.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
+use clap::ValueEnum;
+use redis::{Client, Connection, RedisError};
+use serde::{Deserialize, Serialize};
+use crate::common::{split_scoring_spec, vcf_stem, Model, QueueArgs, ScoreOptions};
+use crate::error::{Error, IoContext, Result};
+use crate::qc;
+use crate::scorer::Scorer;
+use crate::watch::{Configured, Outputs};
+
+/// Seconds a worker waits for a job before checking whether it should stop.
+const POLL_SECONDS: u64 = 1;
+
+#[derive(Deserialize)]
+struct Job {
+    /// Any JSON value; echoed in the job's event
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    vcf: String,
+    scoring: Vec<String>,
+    #[serde(default)]
+    dosage: bool,
+    /// `additive` (the default), `dominant` or `recessive`
+    #[serde(default)]
+    model: Option<String>,
+}
+
+/// What became of a job, pushed onto the events list.
+#[derive(Serialize)]
+struct Event {
+    /// The job's `id`; null for a job that is not valid JSON
+    id: Option<serde_json::Value>,
+    vcf: Option<String>,
+    /// `succeeded` or `failed`
+    status: &'static str,
+    /// One per scoring file, in the job's order; only for succeeded jobs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    results: Vec<Outcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    duration_seconds: f64,
+}
+
+#[derive(Serialize)]
+struct Outcome {
+    scoring_id: String,
+    scores: String,
+    provenance: String,
+    samples: usize,
+    match_rate: f64,
+}
+
+/// Take jobs until killed, or until the connection to Redis fails.
+pub fn run(args: &QueueArgs) -> Result<()> {
+    let mut scoring = BTreeMap::new();
+    for spec in &args.scoring {
+        let (id, path) = split_scoring_spec(spec)?;
+        let scorer = Scorer::from_scoring_file(path)?;
+        println!("Registered scoring file {} ({} variants) as {}", path, scorer.weights().len(), id);
+        scoring.insert(id.to_string(), Configured { id: id.to_string(), path: path.to_string(), scorer });
+    }
+    fs::create_dir_all(&args.output_dir).with_path(&args.output_dir)?;
+
+    let client = Client::open(args.redis.as_str()).map_err(|e| Error::invalid("--redis", e.to_string()))?;
+    // Connect up front, so that a wrong address fails at startup rather than in the workers
+    client.get_connection().map_err(redis_error)?;
+    println!("Taking jobs from {} with {} worker(s)", args.jobs, args.workers.max(1));
+
+    let stop = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..args.workers.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let result = work(&client, &scoring, args, &stop);
+                    // The other workers finish their jobs and stop too
+                    stop.store(true, Ordering::Relaxed);
+                    result
+                })
+            })
+            .collect();
+        workers.into_iter().try_for_each(|worker| worker.join().expect("queue worker panicked"))
+    })
+}
+
+fn work(client: &Client, scoring: &BTreeMap<String, Configured>, args: &QueueArgs, stop: &AtomicBool) -> Result<()> {
+    let mut connection = client.get_connection().map_err(redis_error)?;
+    let processing = format!("{}:processing", args.jobs);
+    while !stop.load(Ordering::Relaxed) {
+        let job: Option<String> = redis::cmd("BLMOVE")
+            .arg(&args.jobs)
+            .arg(&processing)
+            .arg("LEFT")
+            .arg("RIGHT")
+            .arg(POLL_SECONDS)
+            .query(&mut connection)
+            .map_err(redis_error)?;
+        let Some(job) = job else {
+            continue;
+        };
+        let event = serde_json::to_string(&run_job(&job, scoring, args)).expect("event serialization cannot fail");
+        finish(&mut connection, args, &processing, &job, &event).map_err(redis_error)?;
+    }
+    Ok(())
+}
+
+/// Push a job's event and take it off the processing list, as one transaction.
+fn finish(connection: &mut Connection, args: &QueueArgs, processing: &str, job: &str, event: &str) -> redis::RedisResult<()> {
+    redis::pipe().atomic().rpush(&args.events, event).lrem(processing, 1, job).query(connection)
+}
+
+/// Run one job; a job that fails is reported in its event rather than stopping the worker.
+fn run_job(raw: &str, scoring: &BTreeMap<String, Configured>, args: &QueueArgs) -> Event {
+    let start = Instant::now();
+    let job: Job = match serde_json::from_str(raw) {
+        Ok(job) => job,
+        Err(e) => {
+            eprintln!("Error: invalid job {}: {}", raw, e);
+            let error = format!("invalid job: {}", e);
+            return Event { id: None, vcf: None, status: "failed", results: Vec::new(), error: Some(error), duration_seconds: 0.0 };
+        }
+    };
+    let label = match &job.id {
+        Some(serde_json::Value::String(id)) => id.clone(),
+        Some(id) => id.to_string(),
+        None => job.vcf.clone(),
+    };
+    println!("Job {}: scoring {}", label, job.vcf);
+    let (status, results, error) = match score(&job, scoring, args) {
+        Ok(results) => ("succeeded", results, None),
+        Err(e) => {
+            eprintln!("Error: job {}: {}", label, e);
+            ("failed", Vec::new(), Some(e.to_string()))
+        }
+    };
+    Event { id: job.id, vcf: Some(job.vcf), status, results, error, duration_seconds: start.elapsed().as_secs_f64() }
+}
+
+fn score(job: &Job, scoring: &BTreeMap<String, Configured>, args: &QueueArgs) -> Result<Vec<Outcome>> {
+    let model = match &job.model {
+        Some(model) => Model::from_str(model, true).map_err(|e| Error::invalid("model", e))?,
+        None => Model::Additive,
+    };
+    let options = ScoreOptions { dosage: job.dosage, model, ..Default::default() };
+    if job.scoring.is_empty() {
+        return Err(Error::invalid("scoring", "the job names no scoring file"));
+    }
+    // Check every ID before scoring anything
+    let configured = job
+        .scoring
+        .iter()
+        .map(|id| scoring.get(id).ok_or_else(|| Error::invalid(id, "not a registered scoring file")))
+        .collect::<Result<Vec<_>>>()?;
+
+    let file_name = Path::new(&job.vcf).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let outputs = Outputs { dir: Path::new(&args.output_dir), stem: vcf_stem(&file_name), format: args.output_format };
+    let mut outcomes = Vec::new();
+    for configured in configured {
+        let start = Instant::now();
+        let results = configured.scorer.clone().dosage(options.dosage).model(options.model).run(&job.vcf)?;
+        let duration = start.elapsed();
+        let (scores, provenance) = outputs.write(&job.vcf, configured, options, &results, duration, None)?;
+        outcomes.push(Outcome {
+            scoring_id: configured.id.clone(),
+            scores,
+            provenance,
+            samples: results.samples.len(),
+            match_rate: qc::match_rate(&results.summary.sites, configured.scorer.weights().len()),
+        });
+    }
+    Ok(outcomes)
+}
+
+fn redis_error(e: RedisError) -> Error {
+    Error::io("Redis", io::Error::other(e))
+}
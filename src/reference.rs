@@ -0,0 +1,255 @@
+//! Reference score distributions, for reporting a score as a percentile of a reference cohort.
+//!
+//! `speedscore build-reference` scores a reference cohort such as 1000 Genomes and writes
+//! the distribution of its scores, overall and per population: the sample count, mean,
+//! SD and the 0th to 100th percentiles. A scoring run given the file with `--reference`
+//! reports every sample's percentile within it, interpolated between those percentiles,
+//! and its z-score against the reference mean and SD.
+//!
+//! The file is tab-separated. `#key=value` comment lines record how the reference was
+//! scored, followed by a header row and one row per distribution; the overall one is
+//! named `ALL`.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use clap::ValueEnum;
+use crate::common::{create_parent_dirs, BuildReferenceArgs, Centering, Model, ScoreOptions};
+use crate::error::{Error, IoContext, Result};
+use crate::evaluate::Table;
+use crate::scorer::Scorer;
+use crate::upload::Uploads;
+
+const MAGIC: &str = "#SpeedScore reference distribution v1";
+/// Name of the distribution over the whole reference cohort.
+pub const ALL: &str = "ALL";
+/// Percentiles 0 to 100.
+const QUANTILES: usize = 101;
+
+/// Score distribution of one reference population.
+#[derive(Clone, Debug)]
+pub struct Distribution {
+    pub population: String,
+    pub samples: usize,
+    pub mean: f64,
+    /// Sample standard deviation
+    pub sd: f64,
+    /// The 0th to 100th percentiles, interpolated linearly between order statistics
+    pub quantiles: Vec<f64>,
+}
+
+impl Distribution {
+    fn from_scores(population: &str, mut scores: Vec<f64>) -> Self {
+        scores.sort_by(f64::total_cmp);
+        let n = scores.len() as f64;
+        let mean = scores.iter().sum::<f64>() / n;
+        let sd = (scores.iter().map(|score| (score - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+        let quantiles = (0..QUANTILES)
+            .map(|k| {
+                let h = (n - 1.0) * k as f64 / (QUANTILES - 1) as f64;
+                let (lower, fraction) = (h.floor() as usize, h.fract());
+                let upper = (lower + 1).min(scores.len() - 1);
+                scores[lower] + fraction * (scores[upper] - scores[lower])
+            })
+            .collect();
+        Distribution { population: population.to_string(), samples: scores.len(), mean, sd, quantiles }
+    }
+
+    /// Percentile (0-100) of `score` in this distribution, interpolated between the stored
+    /// percentiles. A score equal to several of them gets the middle one.
+    pub fn percentile(&self, score: f64) -> f64 {
+        let q = &self.quantiles;
+        let (below, not_above) = (q.partition_point(|&v| v < score), q.partition_point(|&v| v <= score));
+        let percentile = if below < not_above {
+            (below + not_above - 1) as f64 / 2.0
+        } else if below == 0 {
+            0.0
+        } else if below == q.len() {
+            (q.len() - 1) as f64
+        } else {
+            (below - 1) as f64 + (score - q[below - 1]) / (q[below] - q[below - 1])
+        };
+        percentile * 100.0 / (QUANTILES - 1) as f64
+    }
+
+    /// `score` as a z-score against this distribution; `None` when it has no spread.
+    pub fn z_score(&self, score: f64) -> Option<f64> {
+        (self.sd > 0.0).then(|| (score - self.mean) / self.sd)
+    }
+}
+
+/// A distribution file, as written by [`run`].
+#[derive(Clone, Debug)]
+pub struct Reference {
+    pub scoring_variants: usize,
+    pub model: Model,
+    pub center: Option<Centering>,
+    pub distributions: Vec<Distribution>,
+}
+
+impl Reference {
+    /// Read the distribution file at `path`.
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path).with_path(path)?;
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(MAGIC) {
+            return Err(Error::invalid(path, "not a SpeedScore reference distribution file"));
+        }
+        let mut metadata = HashMap::new();
+        let mut distributions = Vec::new();
+        let mut header_seen = false;
+        for (index, line) in lines {
+            if let Some(entry) = line.strip_prefix('#') {
+                if let Some((key, value)) = entry.split_once('=') {
+                    metadata.insert(key, value);
+                }
+                continue;
+            }
+            let parts: Vec<&str> = line.split('\t').collect();
+            if parts.len() != 4 + QUANTILES {
+                return Err(Error::parse(path, index + 1, format!("Expected {} columns", 4 + QUANTILES), line));
+            }
+            if !header_seen {
+                header_seen = true;
+                continue;
+            }
+            let number = |text: &str| text.parse::<f64>().map_err(|_| Error::parse(path, index + 1, "Invalid number", line));
+            distributions.push(Distribution {
+                population: parts[0].to_string(),
+                samples: parts[1].parse().map_err(|_| Error::parse(path, index + 1, "Invalid sample count", line))?,
+                mean: number(parts[2])?,
+                sd: number(parts[3])?,
+                quantiles: parts[4..].iter().map(|text| number(text)).collect::<Result<_>>()?,
+            });
+        }
+
+        let field = |key: &str| metadata.get(key).copied().ok_or_else(|| Error::invalid(path, format!("no '{}' line", key)));
+        let scoring_variants = field("scoring_variants")?
+            .parse()
+            .map_err(|_| Error::invalid(path, "invalid scoring_variants"))?;
+        let model = Model::from_str(field("model")?, false).map_err(|_| Error::invalid(path, "invalid model"))?;
+        let center = match field("center")? {
+            "none" => None,
+            value => Some(Centering::from_str(value, false).map_err(|_| Error::invalid(path, "invalid center"))?),
+        };
+        Ok(Reference { scoring_variants, model, center, distributions })
+    }
+
+    /// The distribution of `population` (the whole cohort for `None`), after checking that
+    /// the reference was scored like this run.
+    pub fn select(&self, population: Option<&str>, scoring_variants: usize, options: &ScoreOptions, path: &str) -> Result<&Distribution> {
+        if self.scoring_variants != scoring_variants {
+            return Err(Error::invalid(path, format!(
+                "built from a scoring file with {} variants, this one has {}",
+                self.scoring_variants, scoring_variants
+            )));
+        }
+        if self.model != options.model || self.center != options.center {
+            return Err(Error::invalid(path, format!(
+                "built with --model {} and --center {}; this run must use the same",
+                value_name(&self.model),
+                self.center.as_ref().map_or("none".to_string(), value_name)
+            )));
+        }
+        let population = population.unwrap_or(ALL);
+        self.distributions.iter().find(|d| d.population == population).ok_or_else(|| {
+            let names: Vec<&str> = self.distributions.iter().map(|d| d.population.as_str()).collect();
+            Error::invalid(path, format!("no population '{}' (populations: {})", population, names.join(", ")))
+        })
+    }
+}
+
+/// `speedscore build-reference`: score the reference cohort and write its distributions.
+pub fn run(args: &BuildReferenceArgs) -> Result<()> {
+    let mut scorer = Scorer::from_scoring_file(&args.scoring)?.dosage(args.dosage).model(args.model);
+    if let Some(center) = args.center {
+        scorer = scorer.center(center);
+    }
+    let results = scorer.run(&args.vcf)?;
+    if results.samples.len() < 2 {
+        return Err(Error::invalid(&args.vcf, "a reference cohort needs at least two samples"));
+    }
+
+    let populations = match &args.populations {
+        Some(path) => load_populations(path, &args.population_column)?,
+        None => HashMap::new(),
+    };
+    let mut grouped: Vec<(&str, Vec<f64>)> = vec![(ALL, Vec::new())];
+    for sample in &results.samples {
+        grouped[0].1.push(sample.score);
+        if let Some(population) = populations.get(&sample.name) {
+            match grouped.iter_mut().find(|(name, _)| name == population) {
+                Some((_, scores)) => scores.push(sample.score),
+                None => grouped.push((population, vec![sample.score])),
+            }
+        }
+    }
+    if args.populations.is_some() && grouped.len() == 1 {
+        return Err(Error::invalid(args.populations.as_deref().unwrap_or_default(), "none of the VCF's samples have a population in it"));
+    }
+
+    let mut out = format!(
+        "{}\n#scoring={}\n#scoring_variants={}\n#vcf={}\n#model={}\n#center={}\n#dosage={}\npopulation\tsamples\tmean\tsd",
+        MAGIC,
+        args.scoring,
+        scorer.weights().len(),
+        args.vcf,
+        value_name(&args.model),
+        args.center.as_ref().map_or("none".to_string(), value_name),
+        args.dosage
+    );
+    for k in 0..QUANTILES {
+        write!(out, "\tP{}", k).expect("writing to a String cannot fail");
+    }
+    out.push('\n');
+    for (population, scores) in grouped {
+        if scores.len() < 2 {
+            println!("Note: population {} has fewer than two samples; left out", population);
+            continue;
+        }
+        let distribution = Distribution::from_scores(population, scores);
+        println!(
+            "{}: {} samples, mean {:.6}, SD {:.6}, median {:.6}",
+            population, distribution.samples, distribution.mean, distribution.sd, distribution.quantiles[QUANTILES / 2]
+        );
+        // `{}` on f64 prints the shortest representation that round-trips exactly
+        write!(out, "{}\t{}\t{}\t{}", population, distribution.samples, distribution.mean, distribution.sd)
+            .expect("writing to a String cannot fail");
+        for quantile in &distribution.quantiles {
+            write!(out, "\t{}", quantile).expect("writing to a String cannot fail");
+        }
+        out.push('\n');
+    }
+    let mut uploads = Uploads::new();
+    let path = uploads.stage(&args.output)?;
+    create_parent_dirs(&path)?;
+    fs::write(&path, out).with_path(&path)?;
+    println!("Reference distribution written to: {}", path);
+    uploads.finish()?;
+    Ok(())
+}
+
+/// Population of each sample, by sample ID.
+fn load_populations(path: &str, column: &str) -> Result<HashMap<String, String>> {
+    let table = Table::read(path)?;
+    let (ids, population_index) = (table.id_column()?, table.column(column)?);
+    let mut populations = HashMap::new();
+    for row in &table.rows {
+        let population = &row[population_index];
+        if population.is_empty() || population == "NA" {
+            continue;
+        }
+        if population == ALL {
+            return Err(Error::invalid(path, format!("'{}' is reserved for the whole cohort", ALL)));
+        }
+        if populations.insert(row[ids].clone(), population.clone()).is_some() {
+            return Err(Error::invalid(path, format!("duplicate sample '{}'", row[ids])));
+        }
+    }
+    Ok(populations)
+}
+
+/// How `value` is written on the command line, e.g. `scoring-file`.
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value.to_possible_value().map_or_else(String::new, |v| v.get_name().to_string())
+}
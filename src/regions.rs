@@ -0,0 +1,157 @@
+//! Excluding, or capping the weight of, genomic regions such as the MHC.
+//!
+//! Regions of extended LD can dominate a score: the MHC in autoimmune traits is the usual
+//! example. `--exclude-regions` drops the scoring variants in the given regions, and
+//! `--cap-regions` scales their weights down, when needed, so that together they hold at
+//! most `--region-cap` of the scoring file's summed |effect_weight|. Regions are built-in
+//! presets for a `--genome-build`, or BED files.
+
+use std::collections::HashMap;
+use std::fs;
+use crate::common::{ordered_sum, total_abs_weight, EffectWeights};
+use crate::error::{Error, IoContext, Result};
+use crate::genome::GenomeBuild;
+
+/// The extended MHC on chromosome 6, as defined by the GRC: (chr, first, last), 1-based.
+const MHC_GRCH37: &[(&str, u64, u64)] = &[("6", 28_477_797, 33_448_354)];
+const MHC_GRCH38: &[(&str, u64, u64)] = &[("6", 28_510_120, 33_480_577)];
+
+/// The long-range LD regions of Price et al. (2008) in the GRCh37 coordinates distributed
+/// for GWAS quality control (Anderson et al. 2010); the chromosome 6 region covers the MHC.
+const LONG_RANGE_LD_GRCH37: &[(&str, u64, u64)] = &[
+    ("1", 48_287_981, 52_287_979),
+    ("2", 86_088_342, 101_041_482),
+    ("2", 134_666_268, 138_166_268),
+    ("2", 183_174_494, 190_174_494),
+    ("3", 47_524_996, 50_024_996),
+    ("3", 83_417_310, 86_917_310),
+    ("3", 89_917_310, 97_417_310),
+    ("5", 44_464_243, 50_464_243),
+    ("5", 98_917_310, 100_517_310),
+    ("5", 129_636_407, 132_636_407),
+    ("5", 135_536_407, 138_536_407),
+    ("6", 25_392_021, 33_392_022),
+    ("6", 57_012_966, 64_012_966),
+    ("6", 140_012_966, 142_312_966),
+    ("7", 55_193_151, 66_193_151),
+    ("8", 8_105_067, 12_105_067),
+    ("8", 43_405_067, 50_405_067),
+    ("8", 112_105_067, 115_205_067),
+    ("10", 37_374_060, 43_374_060),
+    ("11", 46_353_922, 57_353_922),
+    ("11", 87_853_922, 90_853_922),
+    ("12", 33_108_733, 41_713_733),
+    ("12", 111_037_280, 113_537_280),
+    ("20", 32_536_339, 35_066_586),
+];
+
+/// A set of genomic intervals.
+#[derive(Clone, Debug, Default)]
+pub struct Regions {
+    /// (first, last) 1-based positions, by chromosome without "chr"
+    intervals: HashMap<String, Vec<(u64, u64)>>,
+}
+
+/// What capping did to the scoring file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CapSummary {
+    /// Scoring variants in the capped regions
+    pub variants: usize,
+    /// Share of the summed |effect_weight| the regions held before capping
+    pub share: f64,
+    /// Factor their weights were multiplied by; 1 when they were already under the cap
+    pub factor: f64,
+}
+
+impl Regions {
+    /// The union of `specs`, each a preset (`mhc`, `long-range-ld`) or the path of a BED
+    /// file (chrom, 0-based start, end). Presets need `build`; `flag` is for error messages.
+    pub fn resolve(specs: &[String], build: Option<GenomeBuild>, flag: &str) -> Result<Self> {
+        let mut regions = Regions::default();
+        for spec in specs {
+            let preset = match spec.as_str() {
+                "mhc" | "long-range-ld" => {
+                    let build = build.ok_or_else(|| Error::invalid(flag, format!("the '{}' preset needs the scoring file's build; pass --genome-build", spec)))?;
+                    match (spec.as_str(), build) {
+                        ("mhc", GenomeBuild::Grch37) => MHC_GRCH37,
+                        ("mhc", GenomeBuild::Grch38) => MHC_GRCH38,
+                        (_, GenomeBuild::Grch37) => LONG_RANGE_LD_GRCH37,
+                        (_, GenomeBuild::Grch38) => {
+                            return Err(Error::invalid(flag, "there is no built-in GRCh38 long-range LD list; give the regions as a BED file"));
+                        }
+                    }
+                }
+                path => {
+                    regions.read_bed(path)?;
+                    continue;
+                }
+            };
+            for &(chr, first, last) in preset {
+                regions.add(chr, first, last);
+            }
+        }
+        Ok(regions)
+    }
+
+    fn read_bed(&mut self, path: &str) -> Result<()> {
+        let text = fs::read_to_string(path).with_path(path)?;
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            if line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let parts: Vec<&str> = line.split('\t').collect();
+            if parts.len() < 3 {
+                return Err(Error::parse(path, line_number, "Expected at least 3 columns (chrom, start, end)", line));
+            }
+            let start = parts[1].parse::<u64>().map_err(|_| Error::parse(path, line_number, "Invalid numeric start", line))?;
+            let end = parts[2].parse::<u64>().map_err(|_| Error::parse(path, line_number, "Invalid numeric end", line))?;
+            // BED intervals are 0-based and half-open; VCF positions are 1-based
+            if end > start {
+                self.add(parts[0].trim_start_matches("chr"), start + 1, end);
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, chr: &str, first: u64, last: u64) {
+        self.intervals.entry(chr.to_string()).or_default().push((first, last));
+    }
+
+    /// Whether the variant at `key` (chr without "chr", pos) lies in any of the regions.
+    pub fn contains(&self, key: &(String, u64)) -> bool {
+        let (chr, pos) = key;
+        self.intervals.get(chr).is_some_and(|intervals| intervals.iter().any(|&(first, last)| (first..=last).contains(pos)))
+    }
+
+    /// Drop the scoring variants in the regions, returning how many there were.
+    pub fn exclude(&self, effect_weights: &mut EffectWeights) -> usize {
+        let before = effect_weights.len();
+        effect_weights.retain(|key, _| !self.contains(key));
+        before - effect_weights.len()
+    }
+
+    /// Scale every weight of the scoring variants in the regions by one factor, so that
+    /// they hold at most `fraction` of the summed |effect_weight|, if they hold more.
+    pub fn cap(&self, effect_weights: &mut EffectWeights, fraction: f64) -> CapSummary {
+        let total = total_abs_weight(effect_weights);
+        let variants = effect_weights.keys().filter(|key| self.contains(key)).count();
+        let inside = ordered_sum(effect_weights.iter().filter(|(key, _)| self.contains(key)).map(|(_, w)| w.weight.abs() as f64));
+        let share = if total > 0.0 { inside / total } else { 0.0 };
+        if share <= fraction {
+            return CapSummary { variants, share, factor: 1.0 };
+        }
+        // Solve capped / (capped + outside) = fraction for the regions' capped sum
+        let factor = fraction * (total - inside) / (1.0 - fraction) / inside;
+        for (_, w) in effect_weights.iter_mut().filter(|(key, _)| self.contains(key)) {
+            let scale = |value: f32| (value as f64 * factor) as f32;
+            w.weight = scale(w.weight);
+            w.dominance = scale(w.dominance);
+            w.interaction = scale(w.interaction);
+            w.maternal = scale(w.maternal);
+            w.paternal = scale(w.paternal);
+            w.standard_error = w.standard_error.map(scale);
+        }
+        CapSummary { variants, share, factor }
+    }
+}
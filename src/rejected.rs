@@ -0,0 +1,139 @@
+//! The rejected-records sidecar (`--rejected`): every VCF record and scoring row a run left
+//! out of the score, with a reason code, so exactly what was excluded can be audited.
+//!
+//! The file is tab-separated with a header: `input` (`vcf` or `scoring`), `line` (1-based,
+//! counting header lines), `reason` (one of the codes of [`Reason`]), `detail` and `record`,
+//! the line as it was read. `record` comes last so that a VCF line's own tabs do not shift
+//! the other columns.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::common::create_parent_dirs;
+use crate::error::{IoContext, Result};
+
+/// Which input a rejected line came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Input {
+    Scoring,
+    Vcf,
+}
+
+impl Input {
+    pub fn name(self) -> &'static str {
+        match self {
+            Input::Scoring => "scoring",
+            Input::Vcf => "vcf",
+        }
+    }
+}
+
+/// Why a line was left out. Serialized as its [`code`](Reason::code).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    /// A VCF record that could not be parsed (`--on-error skip` or `warn`)
+    Malformed,
+    /// A VCF record with a NaN or infinite dosage, which was read as `--non-finite` says
+    NonFiniteDosage,
+    /// A VCF record at a scoring position whose REF and ALT are both not the effect allele
+    AlleleMismatch,
+    /// A scoring row with a NaN or infinite weight, dropped under `--non-finite reject`
+    NonFiniteWeight,
+    /// A scoring row replaced by a later row at the same position
+    DuplicatePosition,
+    /// A row of a PGS Catalog harmonized scoring file without a harmonized position
+    Unharmonized,
+    /// A VCF record left out by `--include` or `--exclude`
+    Filtered,
+}
+
+impl Reason {
+    /// The code written to the `reason` column.
+    pub fn code(self) -> &'static str {
+        match self {
+            Reason::Malformed => "malformed",
+            Reason::NonFiniteDosage => "non-finite-dosage",
+            Reason::AlleleMismatch => "allele-mismatch",
+            Reason::NonFiniteWeight => "non-finite-weight",
+            Reason::DuplicatePosition => "duplicate-position",
+            Reason::Unharmonized => "unharmonized",
+            Reason::Filtered => "filtered",
+        }
+    }
+}
+
+/// The `detail` of a [`Reason::AlleleMismatch`].
+pub fn allele_mismatch(effect_allele: &str, ref_allele: &str, alt_allele: &str) -> String {
+    format!("effect allele {} is neither REF {} nor ALT {}", effect_allele, ref_allele, alt_allele)
+}
+
+/// One rejected line.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rejection {
+    pub input: Input,
+    pub line: usize,
+    pub reason: Reason,
+    pub detail: String,
+    pub record: String,
+}
+
+/// Rejected lines collected over a run. Workers may add to it concurrently.
+#[derive(Debug, Default)]
+pub struct Rejections {
+    rejected: Mutex<Vec<Rejection>>,
+}
+
+impl Rejections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, input: Input, line: usize, reason: Reason, detail: impl Into<String>, record: &str) {
+        let rejection = Rejection { input, line, reason, detail: detail.into(), record: record.to_string() };
+        self.rejected.lock().expect("rejections lock poisoned").push(rejection);
+    }
+
+    /// The lines rejected so far, in the order they were reported.
+    pub fn all(&self) -> Vec<Rejection> {
+        self.rejected.lock().expect("rejections lock poisoned").clone()
+    }
+
+    /// Replace the lines rejected so far with `rejected`, e.g. those of a checkpoint.
+    pub fn replace(&self, rejected: Vec<Rejection>) {
+        *self.rejected.lock().expect("rejections lock poisoned") = rejected;
+    }
+
+    /// Number of lines rejected so far.
+    pub fn len(&self) -> usize {
+        self.rejected.lock().expect("rejections lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the sidecar to `path`: scoring rows first, then VCF records, each in line order
+    /// whatever order workers reported them in.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut rejected = self.rejected.lock().expect("rejections lock poisoned").clone();
+        rejected.sort_by_key(|rejection| (rejection.input, rejection.line));
+        let mut out = String::from("input\tline\treason\tdetail\trecord\n");
+        for rejection in &rejected {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                rejection.input.name(),
+                rejection.line,
+                rejection.reason.code(),
+                rejection.detail.replace(['\t', '\n'], " "),
+                rejection.record.trim_end_matches(['\r', '\n']),
+            )
+            .expect("writing to a String cannot fail");
+        }
+        create_parent_dirs(path)?;
+        fs::write(path, out).with_path(path)
+    }
+}
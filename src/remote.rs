@@ -0,0 +1,397 @@
+//! Inputs named by an `http://` or `https://` URL: the VCF, the scoring file and the
+//! reference files a run reads are downloaded into a local cache and read from there.
+//!
+//! The cache is content-addressed: a file lives at `sha256/<digest>/<name>`, so the same
+//! file reached by different URLs is stored once, and runs that pin a SHA-256 can use a
+//! cached copy without asking the server. `urls/` records what each URL last gave, with
+//! the server's `ETag` and `Last-Modified`, so a later run only downloads it again if it
+//! changed. Downloads go to `partial/` first and pick up where they stopped, in the same
+//! run after a dropped connection or in the next one, as long as the server supports
+//! range requests and the file is unchanged. Runs sharing the cache take turns at a URL.
+//!
+//! A download is checked before it enters the cache, against the checksum the run
+//! expects (`--expect-vcf-checksum`, or a `#sha256=` or `#md5=` fragment on the URL) or
+//! else the one the server publishes next to the file (`<url>.sha256` or `<url>.md5`).
+//!
+//! With `--stream`, the VCF and scoring file are read as they download instead, without
+//! the cache; see [`crate::stream`].
+
+use std::path::PathBuf;
+use crate::error::{Error, Result};
+
+/// Whether `path` names a file to download.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// The file name a URL ends in, without its query and fragment.
+pub fn file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("download")
+}
+
+/// The download cache of a run.
+pub struct Cache {
+    #[cfg_attr(not(feature = "remote"), allow(dead_code))]
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// The cache at `dir`, or else at `$SPEEDSCORE_CACHE_DIR`, `$XDG_CACHE_HOME/speedscore`
+    /// or `~/.cache/speedscore`.
+    pub fn new(dir: Option<&str>) -> Self {
+        let env = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+        let dir = dir
+            .map(PathBuf::from)
+            .or_else(|| env("SPEEDSCORE_CACHE_DIR"))
+            .or_else(|| env("XDG_CACHE_HOME").map(|dir| dir.join("speedscore")))
+            .or_else(|| env("HOME").map(|dir| dir.join(".cache").join("speedscore")))
+            .unwrap_or_else(|| std::env::temp_dir().join("speedscore-cache"));
+        Cache { dir }
+    }
+
+    /// `path` itself, or for a URL the path of a checked local copy. `expected` is the
+    /// SHA-256 or MD5 the file must have.
+    pub fn resolve(&self, path: &str, expected: Option<&str>) -> Result<String> {
+        match is_url(path) {
+            true => self.fetch(path, expected),
+            false => Ok(path.to_string()),
+        }
+    }
+
+    /// [`Cache::resolve`] for an optional input without an expected checksum.
+    pub fn resolve_option(&self, path: &Option<String>) -> Result<Option<String>> {
+        path.as_deref().map(|path| self.resolve(path, None)).transpose()
+    }
+
+    #[cfg(not(feature = "remote"))]
+    fn fetch(&self, url: &str, _expected: Option<&str>) -> Result<String> {
+        Err(Error::invalid(url, "URL inputs are not available in this build; rebuild with --features remote"))
+    }
+}
+
+/// The file at `url`, read as it downloads; see [`crate::stream`].
+#[cfg(not(feature = "remote"))]
+pub fn stream(url: &str) -> Result<Box<dyn std::io::Read + Send>> {
+    Err(Error::invalid(url, "URL inputs are not available in this build; rebuild with --features remote"))
+}
+
+#[cfg(feature = "remote")]
+pub use download::stream;
+
+#[cfg(feature = "remote")]
+mod download {
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Read};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use serde::{Deserialize, Serialize};
+    use sha2::{Digest, Sha256};
+    use super::{file_name, Cache, Error, Result};
+    use crate::checksum::{self, Digests};
+    use crate::error::IoContext;
+    use crate::resources::format_size;
+
+    /// Tries at a download, each resuming where the last stopped.
+    const ATTEMPTS: u64 = 5;
+
+    /// What a URL gave when it was cached.
+    #[derive(Serialize, Deserialize)]
+    struct Entry {
+        url: String,
+        sha256: String,
+        md5: String,
+        #[serde(flatten)]
+        validators: Validators,
+    }
+
+    /// What tells a server's file apart from an earlier version of it.
+    #[derive(Serialize, Deserialize, Default, PartialEq, Eq)]
+    struct Validators {
+        etag: Option<String>,
+        last_modified: Option<String>,
+        size: Option<u64>,
+    }
+
+    impl Validators {
+        fn of(response: &ureq::Response) -> Self {
+            Validators {
+                etag: response.header("ETag").map(str::to_string),
+                last_modified: response.header("Last-Modified").map(str::to_string),
+                size: response.header("Content-Length").and_then(|length| length.parse().ok()),
+            }
+        }
+
+        /// Whether these are the same file's, by the strongest validator both have.
+        fn same(&self, other: &Validators) -> bool {
+            match (&self.etag, &other.etag, &self.last_modified, &other.last_modified) {
+                (Some(a), Some(b), _, _) => a == b,
+                (_, _, Some(a), Some(b)) => a == b && self.size == other.size,
+                _ => self.size.is_some() && self.size == other.size,
+            }
+        }
+
+        /// The `If-Range` value a resumed download is conditional on.
+        fn if_range(&self) -> Option<&str> {
+            self.etag.as_deref().filter(|etag| !etag.starts_with("W/")).or(self.last_modified.as_deref())
+        }
+    }
+
+    impl Cache {
+        pub(super) fn fetch(&self, url: &str, expected: Option<&str>) -> Result<String> {
+            // The fragment never reaches the server
+            let (url, pin) = match url.split_once('#') {
+                Some((base, fragment)) => (base, fragment.split_once('=').filter(|(kind, _)| matches!(*kind, "sha256" | "md5")).map(|(_, digest)| digest)),
+                None => (url, None),
+            };
+            let expected = expected.or(pin).map(|digest| digest.trim().to_ascii_lowercase());
+            if let Some(path) = expected.as_deref().and_then(|digest| self.stored(digest, url)) {
+                println!("{}: using the cached copy with SHA-256 {}", url, expected.as_deref().unwrap_or_default());
+                return Ok(path);
+            }
+
+            let key = checksum::hex(&Sha256::digest(url.as_bytes()));
+            let partial_dir = self.dir.join("partial");
+            fs::create_dir_all(&partial_dir).with_path(&partial_dir.to_string_lossy())?;
+            // Another run fetching the same URL finishes first; its copy is then used
+            let lock_path = partial_dir.join(format!("{}.lock", key));
+            let lock = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path).with_path(&lock_path.to_string_lossy())?;
+            lock.lock().with_path(&lock_path.to_string_lossy())?;
+
+            let agent = agent();
+            let entry_path = self.dir.join("urls").join(format!("{}.json", key));
+            if let Some(entry) = read_entry::<Entry>(&entry_path) {
+                let cached = Digests { sha256: entry.sha256.clone(), md5: entry.md5.clone() };
+                // A copy with another checksum than expected is downloaded again
+                let wanted = expected.as_deref().is_none_or(|digest| cached.verify(url, digest).is_ok());
+                if let Some(path) = self.stored(&entry.sha256, url).filter(|_| wanted) {
+                    match agent.head(url).call() {
+                        Ok(response) if entry.validators.same(&Validators::of(&response)) => {
+                            println!("{}: unchanged since it was cached", url);
+                            return Ok(path);
+                        }
+                        Ok(_) => println!("{}: changed since it was cached; downloading it again", url),
+                        Err(e) => {
+                            eprintln!("Warning: could not check {} for changes ({}); using the cached copy", url, e);
+                            return Ok(path);
+                        }
+                    }
+                }
+            }
+
+            let part = partial_dir.join(&key);
+            let part_name = part.to_string_lossy().into_owned();
+            let state = partial_dir.join(format!("{}.json", key));
+            let validators = download(&agent, url, &part, &state)?;
+            let digests = Digests::of(&part_name)?;
+            match expected.or_else(|| published_checksum(&agent, url)).map(|digest| digests.verify(url, &digest)) {
+                Some(Err(e)) => {
+                    // The next run starts over rather than resuming a bad file
+                    let _ = fs::remove_file(&part);
+                    let _ = fs::remove_file(&state);
+                    return Err(e);
+                }
+                Some(Ok(())) => println!("{}: checksum matches", url),
+                None => println!("Note: {} publishes no checksum; cached with SHA-256 {}", url, digests.sha256),
+            }
+
+            let blob_dir = self.dir.join("sha256").join(&digests.sha256);
+            fs::create_dir_all(&blob_dir).with_path(&blob_dir.to_string_lossy())?;
+            let blob = blob_dir.join(file_name(url));
+            fs::rename(&part, &blob).with_path(&blob.to_string_lossy())?;
+            let entry = Entry { url: url.to_string(), sha256: digests.sha256, md5: digests.md5, validators };
+            write_atomically(&entry_path, &serde_json::to_string_pretty(&entry).expect("cache entry serialization cannot fail"))?;
+            let _ = fs::remove_file(&state);
+            Ok(blob.to_string_lossy().into_owned())
+        }
+
+        /// The cached file with SHA-256 `digest`, preferring one named as `url` names it.
+        fn stored(&self, digest: &str, url: &str) -> Option<String> {
+            if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let dir = self.dir.join("sha256").join(digest);
+            let named = dir.join(file_name(url));
+            let path = match named.is_file() {
+                true => named,
+                false => fs::read_dir(&dir).ok()?.filter_map(|entry| entry.ok()).map(|entry| entry.path()).find(|path| path.is_file())?,
+            };
+            Some(path.to_string_lossy().into_owned())
+        }
+    }
+
+    fn agent() -> ureq::Agent {
+        ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(30)).timeout_read(Duration::from_secs(120)).build()
+    }
+
+    /// The file at `url`, read as it downloads; see [`crate::stream`].
+    pub fn stream(url: &str) -> Result<Box<dyn Read + Send>> {
+        // The fragment never reaches the server
+        let url = url.split('#').next().unwrap_or(url);
+        let mut stream = Stream { agent: agent(), url: url.to_string(), body: None, offset: 0, validators: Validators::default() };
+        stream.connect().with_path(url)?;
+        Ok(Box::new(stream))
+    }
+
+    /// A URL being read, which picks up where a dropped connection stopped.
+    struct Stream {
+        agent: ureq::Agent,
+        url: String,
+        body: Option<Box<dyn Read + Send + Sync>>,
+        /// Bytes read so far
+        offset: u64,
+        /// Those of the version being read
+        validators: Validators,
+    }
+
+    impl Stream {
+        /// Request the file from `offset` on: all of it at first, the rest after a dropped
+        /// connection, as long as it is the same version.
+        fn connect(&mut self) -> io::Result<()> {
+            let mut request = self.agent.get(&self.url).set("Accept-Encoding", "identity");
+            if self.offset > 0 {
+                let validator = self.validators.if_range().ok_or_else(|| io::Error::other("the server gives no ETag or Last-Modified to resume the file by"))?;
+                request = request.set("Range", &format!("bytes={}-", self.offset)).set("If-Range", validator);
+            }
+            let response = match request.call() {
+                Ok(response) => response,
+                Err(ureq::Error::Status(code, response)) => return Err(io::Error::other(format!("the server answered {} {}", code, response.status_text()))),
+                Err(e) => return Err(io::Error::other(e)),
+            };
+            if self.offset == 0 {
+                self.validators = Validators::of(&response);
+            } else if response.status() != 206 || content_range_start(&response) != Some(self.offset) {
+                return Err(io::Error::other(format!("the server would not resume the file at byte {}; it may have changed", self.offset)));
+            }
+            self.body = Some(response.into_reader());
+            Ok(())
+        }
+    }
+
+    impl Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut attempt = 1;
+            loop {
+                let error = match self.body.as_mut().map(|body| body.read(buf)) {
+                    Some(Ok(0)) if !buf.is_empty() && self.validators.size.is_some_and(|size| self.offset < size) => {
+                        let size = self.validators.size.unwrap_or_default();
+                        io::Error::new(io::ErrorKind::UnexpectedEof, format!("the connection closed after {} of {} bytes", self.offset, size))
+                    }
+                    Some(Ok(read)) => {
+                        self.offset += read as u64;
+                        return Ok(read);
+                    }
+                    Some(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Some(Err(e)) => e,
+                    None => match self.connect() {
+                        Ok(()) => continue,
+                        Err(e) => e,
+                    },
+                };
+                if attempt == ATTEMPTS {
+                    return Err(io::Error::new(error.kind(), format!("reading {} failed after {} tries: {}", self.url, ATTEMPTS, error)));
+                }
+                eprintln!("Warning: reading {} stopped at byte {} ({}); try {} of {}", self.url, self.offset, error, attempt, ATTEMPTS);
+                self.body = None;
+                std::thread::sleep(Duration::from_secs(attempt * attempt));
+                attempt += 1;
+            }
+        }
+    }
+
+    /// Download `url` to `part`, resuming what an earlier try left there. `state` keeps
+    /// the validators of the version being downloaded, for resuming in a later run.
+    fn download(agent: &ureq::Agent, url: &str, part: &Path, state: &Path) -> Result<Validators> {
+        let name = part.to_string_lossy().into_owned();
+        let mut saved: Validators = read_entry(state).unwrap_or_default();
+        let mut error = None;
+        for attempt in 1..=ATTEMPTS {
+            if attempt > 1 {
+                std::thread::sleep(Duration::from_secs(attempt * attempt));
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(part).with_path(&name)?;
+            let have = file.metadata().with_path(&name)?.len();
+            let mut request = agent.get(url).set("Accept-Encoding", "identity");
+            // Only resume a version that can be told apart from a newer one
+            let resume = saved.if_range().filter(|_| have > 0).map(str::to_string);
+            if let Some(validator) = &resume {
+                request = request.set("Range", &format!("bytes={}-", have)).set("If-Range", validator);
+            }
+            let response = match request.call() {
+                Ok(response) => response,
+                // The partial file is not a prefix of the current version
+                Err(ureq::Error::Status(416, _)) => {
+                    file.set_len(0).with_path(&name)?;
+                    continue;
+                }
+                Err(ureq::Error::Status(code, response)) => {
+                    return Err(Error::invalid(url, format!("the server answered {} {}", code, response.status_text())));
+                }
+                Err(e) => {
+                    eprintln!("Warning: downloading {} failed ({}); try {} of {}", url, e, attempt, ATTEMPTS);
+                    error = Some(e);
+                    continue;
+                }
+            };
+            let resumed = response.status() == 206 && content_range_start(&response) == Some(have);
+            let total = match resumed {
+                true => {
+                    println!("Resuming {} at {}", url, format_size(have));
+                    response.header("Content-Range").and_then(|range| range.rsplit('/').next()).and_then(|total| total.parse().ok())
+                }
+                false => {
+                    file.set_len(0).with_path(&name)?;
+                    saved = Validators::of(&response);
+                    write_atomically(state, &serde_json::to_string(&saved).expect("validators serialization cannot fail"))?;
+                    println!("Downloading {}{}", url, saved.size.map_or_else(String::new, |size| format!(" ({})", format_size(size))));
+                    saved.size
+                }
+            };
+            if let Err(e) = io::copy(&mut response.into_reader(), &mut file) {
+                eprintln!("Warning: downloading {} failed ({}); try {} of {}", url, e, attempt, ATTEMPTS);
+                continue;
+            }
+            let got = file.metadata().with_path(&name)?.len();
+            match total {
+                Some(total) if got != total => {
+                    eprintln!("Warning: {} ended after {} of {} bytes; try {} of {}", url, got, total, attempt, ATTEMPTS);
+                }
+                _ => return Ok(saved),
+            }
+        }
+        let reason = error.map_or_else(|| "the download kept stopping short".to_string(), |e| e.to_string());
+        Err(io::Error::other(format!("{} failed after {} tries: {}", url, ATTEMPTS, reason))).with_path(&name)
+    }
+
+    /// Where the body of a `206 Partial Content` answer starts.
+    fn content_range_start(response: &ureq::Response) -> Option<u64> {
+        let range = response.header("Content-Range")?.strip_prefix("bytes ")?;
+        range.split('-').next()?.trim().parse().ok()
+    }
+
+    /// The checksum published at `<url>.sha256` or `<url>.md5`, as written by `sha256sum`
+    /// or `md5sum` or alone.
+    fn published_checksum(agent: &ureq::Agent, url: &str) -> Option<String> {
+        ["sha256", "md5"].iter().find_map(|kind| {
+            let text = agent.get(&format!("{}.{}", url, kind)).call().ok()?.into_string().ok()?;
+            let digest = text.split_whitespace().next()?.to_ascii_lowercase();
+            let length = if *kind == "sha256" { 64 } else { 32 };
+            (digest.len() == length && digest.bytes().all(|b| b.is_ascii_hexdigit())).then_some(digest)
+        })
+    }
+
+    fn read_entry<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Write `text` to `path` through a temporary file, so readers never see half of it.
+    fn write_atomically(path: &Path, text: &str) -> Result<()> {
+        let name = path.to_string_lossy();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_path(&name)?;
+        }
+        let temporary = PathBuf::from(format!("{}.{}.tmp", name, std::process::id()));
+        fs::write(&temporary, text).with_path(&name)?;
+        fs::rename(&temporary, path).with_path(&name)
+    }
+}
@@ -0,0 +1,232 @@
+//! Memory and CPU budgeting for shared nodes. Limits come from `--memory-limit`,
+//! `--threads` and `--io-threads`, falling back to what the scheduler imposes (cgroup memory limits,
+//! `SLURM_MEM_PER_NODE`/`SLURM_MEM_PER_CPU`, `SLURM_CPUS_PER_TASK`), and are turned
+//! into a [`Tuning`]: read-buffer size, line and record batches, streaming sample
+//! blocks, BGZF read-ahead and the split between I/O and scoring threads.
+//!
+//! The budget is an estimate, not an allocator cap: it accounts for the loaded
+//! scoring file and the buffers sized here, which dominate a run's footprint.
+
+use std::fmt;
+use std::fs;
+use std::sync::OnceLock;
+use crate::error::{Error, Result};
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+const GIB: u64 = 1024 * MIB;
+
+/// Binary, allocator slack, thread stacks and other fixed costs
+const BASE_OVERHEAD: u64 = 32 * MIB;
+/// Rough heap cost of one loaded scoring variant (map entry plus its two strings)
+const WEIGHT_COST: u64 = 160;
+/// Rough cost of one buffered single-sample VCF line
+const LINE_COST: u64 = 256;
+/// Rough cost of a buffered multi-sample record per byte of it, with where its fields start
+const RECORD_COST: u64 = 3;
+/// Rough cost of one finished sample held by a streaming block
+const SAMPLE_COST: u64 = 128;
+/// A BGZF block in flight: up to 64 KiB compressed plus 64 KiB inflated
+const BLOCK_COST: u64 = 128 * KIB;
+
+/// Where a limit came from, for the startup summary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitSource {
+    Flag,
+    Cgroup,
+    Slurm,
+    System,
+}
+
+impl fmt::Display for LimitSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LimitSource::Flag => "flag",
+            LimitSource::Cgroup => "cgroup",
+            LimitSource::Slurm => "SLURM",
+            LimitSource::System => "system",
+        })
+    }
+}
+
+/// The limits a run has to fit in.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Bytes, if anything caps memory
+    pub memory: Option<(u64, LimitSource)>,
+    pub cpus: (usize, LimitSource),
+    /// Threads reserved for reading and decompressing the VCF
+    pub io_threads: usize,
+}
+
+impl Limits {
+    /// Resolve limits, preferring explicit flags over scheduler settings.
+    pub fn detect(memory_limit: Option<u64>, threads: Option<usize>, io_threads: Option<usize>) -> Self {
+        let memory = memory_limit
+            .map(|bytes| (bytes, LimitSource::Flag))
+            .or_else(|| cgroup_memory().map(|bytes| (bytes, LimitSource::Cgroup)))
+            .or_else(|| slurm_memory().map(|bytes| (bytes, LimitSource::Slurm)));
+        let cpus = match (threads, env_number("SLURM_CPUS_PER_TASK")) {
+            (Some(threads), _) => (threads, LimitSource::Flag),
+            (None, Some(cpus)) if cpus > 0 => (cpus as usize, LimitSource::Slurm),
+            // Already honours cgroup CPU quotas and affinity masks
+            _ => (std::thread::available_parallelism().map_or(1, |n| n.get()), LimitSource::System),
+        };
+        Limits { memory, cpus, io_threads: io_threads.unwrap_or(1) }
+    }
+
+    /// Whether anything beyond the defaults applies, i.e. worth reporting.
+    pub fn constrained(&self) -> bool {
+        self.memory.is_some() || self.cpus.1 != LimitSource::System || self.io_threads != 1
+    }
+}
+
+/// Buffer and batch sizes derived from [`Limits`].
+#[derive(Clone, Copy, Debug)]
+pub struct Tuning {
+    /// Capacity of the buffered reader over each VCF
+    pub read_buffer: usize,
+    /// VCF lines held in memory and scored in parallel at once
+    pub line_batch: usize,
+    /// Bytes of multi-sample records held in memory and scored in parallel at once
+    pub record_batch: usize,
+    /// Samples per pass when streaming results block by block and no size was asked for
+    pub sample_block: usize,
+    /// BGZF blocks decompressed ahead of the scorer
+    pub readahead_blocks: usize,
+    pub io_threads: usize,
+    /// Size of the rayon pool left for scoring
+    pub scoring_threads: usize,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning {
+            read_buffer: MIB as usize,
+            line_batch: 1_000_000,
+            record_batch: 16 * MIB as usize,
+            sample_block: usize::MAX,
+            readahead_blocks: 64,
+            io_threads: 1,
+            scoring_threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+impl Tuning {
+    /// Size everything to fit `limits` once `scoring_variants` weights are loaded.
+    pub fn plan(limits: &Limits, scoring_variants: usize) -> Result<Self> {
+        let io_threads = limits.io_threads;
+        let scoring_threads = limits.cpus.0.saturating_sub(io_threads).max(1);
+        let Some((memory, _)) = limits.memory else {
+            return Ok(Tuning { io_threads, scoring_threads, ..Tuning::default() });
+        };
+
+        let fixed = BASE_OVERHEAD + scoring_variants as u64 * WEIGHT_COST;
+        let available = memory.saturating_sub(fixed);
+        if available < 16 * MIB {
+            return Err(Error::invalid(
+                "--memory-limit",
+                format!(
+                    "{} leaves no room to work: the {} loaded scoring variants alone need about {}",
+                    format_size(memory),
+                    scoring_variants,
+                    format_size(fixed + 16 * MIB)
+                ),
+            ));
+        }
+
+        Ok(Tuning {
+            read_buffer: (available / 64).clamp(64 * KIB, MIB) as usize,
+            line_batch: (available / 2 / LINE_COST).clamp(10_000, 4_000_000) as usize,
+            record_batch: (available / 4 / RECORD_COST).clamp(MIB, 64 * MIB) as usize,
+            sample_block: (available / 8 / SAMPLE_COST).max(1_000) as usize,
+            readahead_blocks: (available / 8 / BLOCK_COST).clamp(4, 256) as usize,
+            io_threads,
+            scoring_threads,
+        })
+    }
+}
+
+/// Rough peak memory, in bytes, of a multi-sample run scoring `samples` samples against
+/// `scoring_variants` loaded weights with the default buffers.
+pub fn estimate(scoring_variants: usize, samples: usize) -> u64 {
+    let tuning = Tuning::default();
+    BASE_OVERHEAD
+        + scoring_variants as u64 * WEIGHT_COST
+        + samples as u64 * SAMPLE_COST
+        + tuning.read_buffer as u64
+        + tuning.record_batch as u64 * RECORD_COST
+        + tuning.readahead_blocks as u64 * BLOCK_COST
+}
+
+impl fmt::Display for Tuning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} I/O and {} scoring threads, {} read buffer, {}-line batches, {} record batches, {} blocks of read-ahead",
+            self.io_threads,
+            self.scoring_threads,
+            format_size(self.read_buffer as u64),
+            self.line_batch,
+            format_size(self.record_batch as u64),
+            self.readahead_blocks
+        )
+    }
+}
+
+static TUNING: OnceLock<Tuning> = OnceLock::new();
+
+/// Fix the tuning for the rest of the process. Has no effect once [`current`] has run.
+pub fn configure(tuning: Tuning) {
+    let _ = TUNING.set(tuning);
+}
+
+/// The configured tuning, or one planned from the scheduler's limits alone.
+pub fn current() -> &'static Tuning {
+    TUNING.get_or_init(|| Tuning::plan(&Limits::detect(None, None, None), 0).unwrap_or_default())
+}
+
+/// Parse a byte count such as `512M`, `16G` or `1.5GiB` (binary units; a bare number is bytes).
+pub fn parse_size(text: &str) -> std::result::Result<u64, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size {:?}", text))?;
+    let scale = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => KIB,
+        "M" => MIB,
+        "G" => GIB,
+        "T" => 1024 * GIB,
+        _ => return Err(format!("unknown size unit in {:?}; use K, M, G or T", text)),
+    };
+    Ok((number * scale as f64) as u64)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= GIB => format!("{:.1} GiB", b as f64 / GIB as f64),
+        b if b >= MIB => format!("{:.0} MiB", b as f64 / MIB as f64),
+        b => format!("{:.0} KiB", b as f64 / KIB as f64),
+    }
+}
+
+fn cgroup_memory() -> Option<u64> {
+    // cgroup v2 reports "max" when unlimited; v1 reports a huge sentinel instead
+    ["/sys/fs/cgroup/memory.max", "/sys/fs/cgroup/memory/memory.limit_in_bytes"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|text| text.trim().parse::<u64>().ok())
+        .find(|&bytes| bytes < 1 << 60)
+}
+
+fn slurm_memory() -> Option<u64> {
+    let per_node = env_number("SLURM_MEM_PER_NODE");
+    let per_cpu = env_number("SLURM_MEM_PER_CPU").map(|mb| mb * env_number("SLURM_CPUS_PER_TASK").unwrap_or(1));
+    per_node.or(per_cpu).filter(|&mb| mb > 0).map(|mb| mb * MIB)
+}
+
+fn env_number(name: &str) -> Option<u64> {
+    std::env::var(name).ok()?.trim().parse().ok()
+}
@@ -0,0 +1,126 @@
+//! Clinical reporting scales: T-scores, percentiles and risk categories.
+//!
+//! Scales are computed from a standardized score, taken from one of three sources:
+//! the `--reference` distribution (its z-score and empirical percentile), the
+//! `--calibration` z-score (percentile from the normal CDF), or the scored cohort itself
+//! (z-score against the cohort mean and SD, percentile by rank). A T-score is `50 + 10z`.
+//! A risk category is the band between the `--risk-cutoffs` percentiles a sample falls
+//! in; a percentile equal to a cutoff falls in the band above it.
+
+use clap::ValueEnum;
+use crate::error::{Error, Result};
+use crate::scorer::SampleScore;
+use crate::stats;
+
+/// Where the standardized score behind the reporting scales comes from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ScaleSource {
+    /// `reference` with --reference, else `calibration` with --calibration, else `cohort`
+    Auto,
+    /// The --reference distribution
+    Reference,
+    /// The ancestry-calibrated z-score (--calibration)
+    Calibration,
+    /// The scored samples themselves (multi-sample VCFs only)
+    Cohort,
+}
+
+/// Which reporting scales to add to the results.
+#[derive(Clone, Debug)]
+pub struct ReportScale {
+    /// Never [`ScaleSource::Auto`]
+    pub source: ScaleSource,
+    pub t_scores: bool,
+    pub percentiles: bool,
+    /// Ascending percentile cutoffs between risk categories; empty for none
+    pub cutoffs: Vec<f64>,
+    /// One label per category, lowest first
+    pub labels: Vec<String>,
+}
+
+impl ReportScale {
+    /// Check the cutoffs and labels. Without labels, categories are named by their
+    /// percentile range, e.g. `<20`, `20-80` and `>=80`.
+    pub fn new(source: ScaleSource, t_scores: bool, percentiles: bool, cutoffs: &[f64], labels: &[String]) -> Result<Self> {
+        if let Some(bad) = cutoffs.iter().find(|&&c| !(c > 0.0 && c < 100.0)) {
+            return Err(Error::invalid("--risk-cutoffs", format!("{} is not a percentile in (0, 100)", bad)));
+        }
+        if cutoffs.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(Error::invalid("--risk-cutoffs", "cutoffs must be strictly ascending"));
+        }
+        let labels = match labels {
+            [] if cutoffs.is_empty() => Vec::new(),
+            [] => default_labels(cutoffs),
+            _ if labels.len() != cutoffs.len() + 1 => {
+                return Err(Error::invalid(
+                    "--risk-labels",
+                    format!("{} cutoffs make {} categories, but {} labels were given", cutoffs.len(), cutoffs.len() + 1, labels.len()),
+                ));
+            }
+            _ => {
+                // Labels are written into CSV cells
+                if let Some(bad) = labels.iter().find(|label| label.is_empty() || label.contains(['"', '\n'])) {
+                    return Err(Error::invalid("--risk-labels", format!("invalid label '{}'", bad)));
+                }
+                labels.to_vec()
+            }
+        };
+        Ok(ReportScale { source, t_scores, percentiles, cutoffs: cutoffs.to_vec(), labels })
+    }
+
+    /// The risk category of a sample at `percentile`.
+    pub fn category(&self, percentile: f64) -> &str {
+        &self.labels[self.cutoffs.partition_point(|&cutoff| cutoff <= percentile)]
+    }
+
+    /// The requested scale values of a sample with standardized score `z` at `percentile`.
+    pub fn values(&self, z: Option<f64>, percentile: Option<f64>) -> ScaleValues {
+        ScaleValues {
+            t_score: z.filter(|_| self.t_scores).map(|z| 50.0 + 10.0 * z),
+            percentile: percentile.filter(|_| self.percentiles),
+            risk_category: percentile.filter(|_| !self.cutoffs.is_empty()).map(|p| self.category(p).to_string()),
+        }
+    }
+}
+
+/// One sample's reporting-scale values; `None` where not requested or not computable.
+#[derive(Clone, Debug, Default)]
+pub struct ScaleValues {
+    pub t_score: Option<f64>,
+    pub percentile: Option<f64>,
+    pub risk_category: Option<String>,
+}
+
+/// Fill in the samples' T-scores, percentiles and risk categories. Run after any
+/// reference percentiles and calibrated z-scores are set.
+pub fn apply(samples: &mut [SampleScore], scale: &ReportScale) {
+    let mut sorted: Vec<f64> = samples.iter().map(|sample| sample.score).collect();
+    sorted.sort_by(f64::total_cmp);
+    let n = sorted.len() as f64;
+    let mean = sorted.iter().sum::<f64>() / n;
+    let sd = (sorted.iter().map(|score| (score - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    for sample in samples.iter_mut() {
+        let (z, percentile) = match scale.source {
+            ScaleSource::Reference => (sample.reference_z, sample.reference_percentile),
+            ScaleSource::Calibration => (sample.calibrated_z, sample.calibrated_z.map(|z| 100.0 * stats::normal_cdf(z))),
+            ScaleSource::Cohort | ScaleSource::Auto => {
+                let below = sorted.partition_point(|&score| score < sample.score);
+                let not_above = sorted.partition_point(|&score| score <= sample.score);
+                let z = (sd > 0.0).then(|| (sample.score - mean) / sd);
+                (z, Some(100.0 * (below + not_above) as f64 / 2.0 / n))
+            }
+        };
+        let values = scale.values(z, percentile);
+        sample.t_score = values.t_score;
+        sample.scale_percentile = values.percentile;
+        sample.risk_category = values.risk_category;
+    }
+}
+
+/// Percentile ranges as category names.
+fn default_labels(cutoffs: &[f64]) -> Vec<String> {
+    let mut labels = vec![format!("<{}", cutoffs[0])];
+    labels.extend(cutoffs.windows(2).map(|pair| format!("{}-{}", pair[0], pair[1])));
+    labels.push(format!(">={}", cutoffs[cutoffs.len() - 1]));
+    labels
+}
@@ -0,0 +1,32 @@
+//! Versions of the machine-readable output: the `--output-format json` results and the
+//! `--provenance` file.
+//!
+//! Both carry a top-level `schema_version`. Within a version, fields are only added, so a
+//! workflow validating against one keeps working; removing, renaming or retyping a field
+//! makes a new version. `--print-schema` prints a version's JSON Schema, and
+//! `--schema-version` makes a run fail up front when this build can't write the version a
+//! pipeline was written against.
+
+use crate::error::{Error, Result};
+
+/// The version this build writes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schemas of the versions this build can write, oldest first.
+const SCHEMAS: [(u32, &str); 1] = [(1, include_str!("../schema/v1.json"))];
+
+/// The JSON Schema of `version`, which `flag` asked for.
+pub fn document(flag: &str, version: u32) -> Result<&'static str> {
+    SCHEMAS.iter().find(|&&(v, _)| v == version).map(|&(_, schema)| schema).ok_or_else(|| {
+        let supported: Vec<String> = SCHEMAS.iter().map(|(v, _)| v.to_string()).collect();
+        Error::invalid(
+            flag,
+            format!("SpeedScore {} writes schema version {}, not {}", env!("CARGO_PKG_VERSION"), supported.join(", "), version),
+        )
+    })
+}
+
+/// Fail unless this build writes output of schema `version`.
+pub fn check(version: u32) -> Result<()> {
+    document("--schema-version", version).map(|_| ())
+}
@@ -0,0 +1,471 @@
+use crate::common::{dosage_only_from, load_scoring_file, read_sample_names_from, Centering, EffectWeights, FileType, InfoField, Model, ScoreOptions, ScoreSummary};
+use std::io::BufRead;
+use std::ops::Range;
+use std::sync::Arc;
+use serde::Serialize;
+use crate::bgzf;
+use crate::error::{ErrorPolicy, NonFinite, OnError, Result};
+use crate::input::{InputConfig, VcfInput};
+use crate::multi_sample::MultiSampleScores;
+use crate::order::Unsorted;
+use crate::source::{self, GenotypeSource, VcfSource};
+use crate::spans::Stage;
+use crate::{ancestry, consumer, multi_sample, permutation, single_sample};
+
+/// Final score for one sample.
+#[derive(Clone, Debug, Serialize)]
+pub struct SampleScore {
+    pub name: String,
+    pub score: f64,
+    pub total_variants: u64,
+    pub matched_variants: u64,
+    /// Effect-allele count (or dosage) summed over called genotypes, before the model
+    pub dosage_sum: f64,
+    /// Imputation quality summed over the variants with a called genotype, with `--info-field`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective_variants: Option<f64>,
+    /// Share of the scoring file's total |weight| at the sample's called genotypes, with
+    /// `--rescale-by-coverage`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight_coverage: Option<f64>,
+    /// Score divided by `weight_coverage`; `None` when no weight was covered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rescaled_score: Option<f64>,
+    /// Standard error of the score from the weights' standard errors, assuming they are
+    /// independent; only when the scoring file has an `effect_weight_se` column
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score_se: Option<f64>,
+    /// Coordinates on the reference ancestry PCs, when projected
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pcs: Vec<f64>,
+    /// Residual of the score after regressing it on the PCs across the cohort
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adjusted_score: Option<f64>,
+    /// Score as a z-score against samples of similar ancestry, with `--calibration`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calibrated_z: Option<f64>,
+    /// Score at each `--pt-thresholds` p-value threshold, in ascending threshold order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub threshold_scores: Vec<f64>,
+    /// Percentile (0-100) of the score among the sample's `--permutations` null scores
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub null_percentile: Option<f64>,
+    /// Distance of the score from the null mean, in null standard deviations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub null_z: Option<f64>,
+    /// Partial score over each `--set-column`/`--set-bed` set, in the run's set order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub set_scores: Vec<f64>,
+    /// Score over each of several `--scoring` files, the first one's included, in the order given
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scores: Vec<f64>,
+    /// Matched variants of each of several `--scoring` files, in the order of `scores`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub matched: Vec<u64>,
+    /// Exposure times the interaction-weighted genotype sum, with `--environment`; `None`
+    /// for samples without an exposure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interaction_score: Option<f64>,
+    /// Score plus the interaction score
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gxe_score: Option<f64>,
+    /// Maternal weights summed over the effect alleles on the maternal haplotype, with
+    /// `--parent-of-origin`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maternal_score: Option<f64>,
+    /// Paternal weights summed over the effect alleles on the paternal haplotype
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paternal_score: Option<f64>,
+    /// Mean of the scores of the sample's father and mother, with `--pedigree`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub midparent_score: Option<f64>,
+    /// Score minus the mid-parent score
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offspring_deviation: Option<f64>,
+    /// Score as a z-score against the scored samples of the same sex, with `--sex`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sex_z: Option<f64>,
+    /// Percentile (0-100) of the score in the `--reference` distribution
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference_percentile: Option<f64>,
+    /// Score as a z-score against the `--reference` distribution
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference_z: Option<f64>,
+    /// `50 + 10z` of the standardized score, with `--t-scores`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub t_score: Option<f64>,
+    /// Percentile (0-100) of the standardized score, with `--percentiles`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale_percentile: Option<f64>,
+    /// Risk category from `--risk-cutoffs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk_category: Option<String>,
+}
+
+/// Per-sample scores (in VCF header order) plus the run-level summary.
+impl SampleScore {
+    /// The one sample of a single-sample VCF, as `summary` scored it.
+    pub fn single(name: String, summary: &ScoreSummary) -> Self {
+        SampleScore {
+            name,
+            score: summary.score,
+            total_variants: summary.total_variants,
+            matched_variants: summary.matched_variants,
+            dosage_sum: summary.dosage_sum,
+            effective_variants: summary.effective_variants,
+            weight_coverage: summary.weight_coverage,
+            rescaled_score: rescaled_score(summary.score, summary.weight_coverage),
+            score_se: None,
+            pcs: Vec::new(),
+            adjusted_score: None,
+            calibrated_z: None,
+            threshold_scores: Vec::new(),
+            null_percentile: None,
+            null_z: None,
+            set_scores: Vec::new(),
+            scores: Vec::new(),
+            matched: Vec::new(),
+            interaction_score: None,
+            gxe_score: None,
+            maternal_score: None,
+            paternal_score: None,
+            midparent_score: None,
+            offspring_deviation: None,
+            sex_z: None,
+            reference_percentile: None,
+            reference_z: None,
+            t_score: None,
+            scale_percentile: None,
+            risk_category: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScoreResults {
+    pub samples: Vec<SampleScore>,
+    pub summary: ScoreSummary,
+}
+
+/// Fluent entry point for library users:
+///
+/// ```no_run
+/// use speedscore::{Model, Scorer};
+///
+/// let results = Scorer::from_scoring_file("PGS000001.txt")?
+///     .dosage(true)
+///     .model(Model::Additive)
+///     .threads(16)
+///     .run("cohort.vcf.gz")?;
+/// for sample in &results.samples {
+///     println!("{}\t{}", sample.name, sample.score);
+/// }
+/// # Ok::<(), speedscore::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct Scorer {
+    weights: Arc<EffectWeights>,
+    options: ScoreOptions,
+    threads: Option<usize>,
+    on_error: OnError,
+    max_errors: Option<usize>,
+    unsorted: Unsorted,
+    input: InputConfig,
+}
+
+impl Scorer {
+    pub fn new(weights: EffectWeights) -> Self {
+        Self::from_shared(Arc::new(weights))
+    }
+
+    /// Share already-loaded weights between scorers, e.g. with different models.
+    pub fn from_shared(weights: Arc<EffectWeights>) -> Self {
+        Scorer {
+            weights,
+            options: ScoreOptions::default(),
+            threads: None,
+            on_error: OnError::Skip,
+            max_errors: None,
+            unsorted: Unsorted::Error,
+            input: InputConfig::default(),
+        }
+    }
+
+    /// Load weights from a PGS Catalog-style scoring file.
+    pub fn from_scoring_file(path: &str) -> Result<Self> {
+        let (weights, _) = load_scoring_file(path, NonFinite::default(), None)?;
+        Ok(Self::new(weights))
+    }
+
+    /// Score from FORMAT/DS dosages instead of GT hard calls.
+    pub fn dosage(mut self, dosage: bool) -> Self {
+        self.options.dosage = dosage;
+        self
+    }
+
+    pub fn model(mut self, model: Model) -> Self {
+        self.options.model = model;
+        self
+    }
+
+    /// Mean-center scores using effect-allele frequencies from `centering`; see `--center`.
+    pub fn center(mut self, centering: Centering) -> Self {
+        self.options.center = Some(centering);
+        self
+    }
+
+    /// Score missing genotypes at their expected value given the cohort's effect-allele
+    /// frequency instead of skipping them, as plink2 --score does. Needs a multi-sample input.
+    pub fn mean_impute(mut self, mean_impute: bool) -> Self {
+        self.options.mean_impute = mean_impute;
+        self
+    }
+
+    /// Also sum each sample's imputation quality from this INFO field over its called
+    /// scoring variants; see `--info-field`. VCF input only.
+    pub fn info_field(mut self, field: InfoField) -> Self {
+        self.options.info_field = Some(field);
+        self
+    }
+
+    /// Also report every score divided by the share of the total |weight| at the sample's
+    /// called genotypes; see `--rescale-by-coverage`.
+    pub fn rescale_by_coverage(mut self, rescale: bool) -> Self {
+        self.options.rescale_by_coverage = rescale;
+        self
+    }
+
+    /// How NaN or infinite FORMAT/DS dosages are read; see `--non-finite`. Weights are
+    /// checked when the scoring file is loaded, where such rows are dropped.
+    pub fn non_finite(mut self, mode: NonFinite) -> Self {
+        self.options.non_finite = mode;
+        self
+    }
+
+    /// Size of the rayon pool used for scoring. Defaults to the global pool.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// How malformed VCF records are handled; see `--on-error` and `--max-errors`.
+    pub fn on_error(mut self, mode: OnError, max_errors: Option<usize>) -> Self {
+        self.on_error = mode;
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Whether an unsorted VCF is an error or only a warning; see `--unsorted`.
+    pub fn unsorted(mut self, mode: Unsorted) -> Self {
+        self.unsorted = mode;
+        self
+    }
+
+    /// How VCFs are opened: streamed, from an htsget server, without their index, or with
+    /// a `.sample` file; see `--stream`, `--htsget`, `--no-index` and `--sample`.
+    pub fn input_config(mut self, config: InputConfig) -> Self {
+        self.input = config;
+        self
+    }
+
+    pub fn weights(&self) -> &EffectWeights {
+        &self.weights
+    }
+
+    /// Score every sample in the VCF at `input`.
+    pub fn run(&self, input: &str) -> Result<ScoreResults> {
+        self.run_files(&[input.to_string()])
+    }
+
+    /// Score every sample in the VCFs at `inputs`, read one after another as a single
+    /// VCF; see [`crate::concat`]. Each must have the same samples in the same order.
+    pub fn run_files(&self, inputs: &[String]) -> Result<ScoreResults> {
+        let input = VcfInput::files(inputs.to_vec(), &self.input)?;
+        let _stage = Stage::score(input.path());
+        self.in_pool(|| self.run_in_pool(&input))
+    }
+
+    /// Score the VCF at `input` `block_size` samples at a time, yielding each sample's
+    /// final score as soon as its block is done. Every block is a separate pass over
+    /// the file, so larger blocks trade memory and latency for fewer passes.
+    ///
+    /// ```no_run
+    /// # let scorer = speedscore::Scorer::from_scoring_file("PGS000001.txt")?;
+    /// for sample in scorer.stream("cohort.vcf.gz", 1000)? {
+    ///     let sample = sample?;
+    ///     println!("{}\t{}", sample.name, sample.score);
+    /// }
+    /// # Ok::<(), speedscore::Error>(())
+    /// ```
+    pub fn stream(&self, input: &str, block_size: usize) -> Result<SampleScoreStream<'_>> {
+        self.options.validate(&self.weights, "scoring weights", &FileType::MultiSample)?;
+        Ok(SampleScoreStream {
+            scorer: self,
+            input: input.to_string(),
+            block_size: block_size.max(1),
+            next_sample: 0,
+            sample_count: read_sample_names_from(input, bgzf::open(input, &self.input)?)?.len(),
+            pending: Vec::new().into_iter(),
+            failed: false,
+        })
+    }
+
+    /// Score a custom genotype backend. Dosage and error-handling settings are the
+    /// source's own concern; only the weights, model and centering are applied here.
+    pub fn run_source<S: GenotypeSource + ?Sized>(&self, source: &mut S) -> Result<ScoreResults> {
+        self.options.validate(&self.weights, "scoring weights", &FileType::MultiSample)?;
+        let scores = source::score_source(source, &self.weights, &self.options)?;
+        Ok(ScoreResults::from(scores))
+    }
+
+    /// A `VcfSource` over `reader` honouring this scorer's dosage and error settings.
+    pub fn vcf_source(&self, name: &str, reader: Box<dyn BufRead>) -> Result<VcfSource> {
+        let policy = self.policy();
+        Ok(VcfSource::from_reader(name, reader, self.options.dosage)?.non_finite(self.options.non_finite).with_policy(policy))
+    }
+
+    fn policy(&self) -> ErrorPolicy {
+        ErrorPolicy::new(self.on_error, self.max_errors).unsorted(self.unsorted)
+    }
+
+    fn in_pool<T: Send>(&self, op: impl FnOnce() -> Result<T> + Send) -> Result<T> {
+        match self.threads {
+            Some(threads) => rayon::ThreadPoolBuilder::new().num_threads(threads).build()?.install(op),
+            None => op(),
+        }
+    }
+
+    fn score_block(&self, input: &str, samples: Range<usize>) -> Result<ScoreResults> {
+        self.in_pool(|| {
+            let policy = self.policy();
+            let scores = multi_sample::score_sample_block(&VcfInput::new(input, &self.input)?, &self.weights, &self.options, &policy, samples)?;
+            Ok(ScoreResults::from(scores))
+        })
+    }
+
+    fn run_in_pool(&self, input: &VcfInput) -> Result<ScoreResults> {
+        let policy = self.policy();
+        let path = input.path();
+        // Dosage-only VCFs have no GT to read
+        let mut options = self.options;
+        options.dosage |= dosage_only_from(path, input.peek()?)?;
+        let file_type = FileType::detect_from(path, input.peek()?)?;
+        options.validate(&self.weights, "scoring weights", &file_type)?;
+        match file_type {
+            FileType::SingleSample => {
+                let summary = single_sample::calculate_polygenic_score(input, &self.weights, &options, &policy)?;
+                let name = read_sample_names_from(path, input.peek()?)?.into_iter().next().unwrap_or_default();
+                Ok(ScoreResults {
+                    samples: vec![SampleScore::single(name, &summary)],
+                    summary,
+                })
+            }
+            FileType::MultiSample => {
+                let scores = multi_sample::score_multi(input, &self.weights, &options, false, None, &policy, None)?;
+                Ok(ScoreResults::from(scores))
+            }
+            FileType::ConsumerGenotypes => {
+                let summary = consumer::calculate_polygenic_score(input, &self.weights, &options, &policy)?;
+                Ok(ScoreResults {
+                    samples: vec![SampleScore::single(String::new(), &summary)],
+                    summary,
+                })
+            }
+        }
+    }
+}
+
+/// Iterator returned by [`Scorer::stream`]. Stops after the first error.
+pub struct SampleScoreStream<'a> {
+    scorer: &'a Scorer,
+    input: String,
+    block_size: usize,
+    next_sample: usize,
+    sample_count: usize,
+    pending: std::vec::IntoIter<SampleScore>,
+    failed: bool,
+}
+
+impl Iterator for SampleScoreStream<'_> {
+    type Item = Result<SampleScore>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(sample) = self.pending.next() {
+                return Some(Ok(sample));
+            }
+            if self.failed || self.next_sample >= self.sample_count {
+                return None;
+            }
+            let block = self.next_sample..(self.next_sample + self.block_size).min(self.sample_count);
+            self.next_sample = block.end;
+            match self.scorer.score_block(&self.input, block) {
+                Ok(results) => self.pending = results.samples.into_iter(),
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl From<MultiSampleScores> for ScoreResults {
+    fn from(scores: MultiSampleScores) -> Self {
+        let adjusted = ancestry::adjusted_scores(&scores.sample_data);
+        let samples = scores
+            .sample_names
+            .into_iter()
+            .zip(scores.sample_data)
+            .enumerate()
+            .map(|(index, (name, data))| {
+                let null = (!data.null_deviations.is_empty()).then(|| permutation::summarize(&data.null_deviations));
+                SampleScore {
+                    name,
+                    score: data.score,
+                    total_variants: data.total_variants,
+                    matched_variants: data.matched_variants,
+                    dosage_sum: data.dosage_sum,
+                    effective_variants: data.effective_variants,
+                    weight_coverage: data.weight_coverage,
+                    rescaled_score: rescaled_score(data.score, data.weight_coverage),
+                    score_se: data.variance.map(f64::sqrt),
+                    pcs: data.pcs,
+                    adjusted_score: adjusted.as_ref().map(|adjusted| adjusted[index]),
+                    calibrated_z: None,
+                    threshold_scores: data.threshold_scores,
+                    null_percentile: null.map(|(percentile, _)| percentile),
+                    null_z: null.and_then(|(_, z)| z),
+                    set_scores: data.set_scores,
+                    // The first scoring file's score is the score itself
+                    scores: match data.panel_scores.is_empty() {
+                        true => Vec::new(),
+                        false => std::iter::once(data.score).chain(data.panel_scores).collect(),
+                    },
+                    matched: match data.panel_matched.is_empty() {
+                        true => Vec::new(),
+                        false => std::iter::once(data.matched_variants).chain(data.panel_matched).collect(),
+                    },
+                    interaction_score: None,
+                    gxe_score: None,
+                    maternal_score: data.maternal_score,
+                    paternal_score: data.paternal_score,
+                    midparent_score: None,
+                    offspring_deviation: None,
+                    sex_z: None,
+                    reference_percentile: None,
+                    reference_z: None,
+                    t_score: None,
+                    scale_percentile: None,
+                    risk_category: None,
+                }
+            })
+            .collect();
+        ScoreResults { samples, summary: scores.summary }
+    }
+}
+
+/// `score` scaled up to what it would be with every weight covered.
+fn rescaled_score(score: f64, coverage: Option<f64>) -> Option<f64> {
+    coverage.filter(|&coverage| coverage > 0.0).map(|coverage| score / coverage)
+}
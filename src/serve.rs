@@ -0,0 +1,210 @@
+//! `speedscore serve`: a small JSON-over-HTTP scoring service, compiled with
+//! `--features server`. Scoring files are loaded once and kept in memory, so each
+//! request only pays for reading the VCF.
+//!
+//! Routes:
+//! - `GET /health`
+//! - `GET /metrics`: Prometheus metrics; see [`crate::metrics`]
+//! - `GET /scoring`: registered scoring files
+//! - `POST /scoring` with `{"id": ..., "path": ...}`: load and register a scoring file
+//! - `POST /scoring/{id}/score`: score a VCF, given either as `{"vcf": "/server/path"}`
+//!   (`Content-Type: application/json`) or as the raw (optionally gzipped) request body.
+//!   `?dosage=true` and `?model=dominant|recessive` select the scoring options.
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::common::{load_scoring_file, split_scoring_spec, EffectWeights, Model, ServeArgs};
+use crate::error::{Error, IoContext, NonFinite, Result};
+use crate::metrics::{self, Metrics};
+use crate::scorer::{ScoreResults, Scorer};
+use crate::source;
+
+/// Registered scoring files, by ID.
+#[derive(Default)]
+struct Registry {
+    scoring: RwLock<BTreeMap<String, Registered>>,
+}
+
+#[derive(Clone)]
+struct Registered {
+    path: String,
+    weights: Arc<EffectWeights>,
+}
+
+#[derive(Serialize)]
+struct ScoringInfo<'a> {
+    id: &'a str,
+    path: &'a str,
+    variants: usize,
+}
+
+#[derive(Deserialize)]
+struct RegisterRequest {
+    id: String,
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct ScoreRequest {
+    vcf: String,
+}
+
+/// An HTTP status plus a message, rendered as `{"error": ...}`.
+struct Failure(u16, String);
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        Failure(422, e.to_string())
+    }
+}
+
+type Reply = std::result::Result<(u16, String), Failure>;
+
+impl Registry {
+    fn register(&self, id: &str, path: &str) -> Result<usize> {
+        let (weights, _) = load_scoring_file(path, NonFinite::default(), None)?;
+        let variants = weights.len();
+        let entry = Registered { path: path.to_string(), weights: Arc::new(weights) };
+        self.scoring.write().unwrap().insert(id.to_string(), entry);
+        Ok(variants)
+    }
+
+    fn get(&self, id: &str) -> Option<Registered> {
+        self.scoring.read().unwrap().get(id).cloned()
+    }
+}
+
+/// Start the server and block serving requests.
+pub fn run(args: &ServeArgs) -> Result<()> {
+    let registry = Arc::new(Registry::default());
+    let metrics = Arc::new(Metrics::default());
+    for spec in &args.scoring {
+        let (id, path) = split_scoring_spec(spec)?;
+        let variants = registry.register(id, path)?;
+        println!("Registered scoring file {} ({} variants) as {}", path, variants, id);
+    }
+
+    let server = Arc::new(Server::http(&args.bind).map_err(|e| Error::invalid(&args.bind, e.to_string()))?);
+    println!("Listening on http://{}", args.bind);
+
+    let workers: Vec<_> = (0..args.workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let registry = Arc::clone(&registry);
+            let metrics = Arc::clone(&metrics);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&registry, &metrics, request);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+fn handle(registry: &Registry, metrics: &Metrics, mut request: Request) {
+    let prometheus = *request.method() == Method::Get && request.url() == "/metrics";
+    let (status, body) = match route(registry, metrics, &mut request) {
+        Ok(reply) => reply,
+        Err(Failure(status, message)) => (status, json(&BTreeMap::from([("error", message)]))),
+    };
+    let content_type = match prometheus {
+        true => metrics::CONTENT_TYPE,
+        false => "application/json",
+    };
+    let content_type = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap();
+    let response = Response::from_string(body).with_status_code(status).with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("Warning: failed to send response: {}", e);
+    }
+}
+
+fn route(registry: &Registry, metrics: &Metrics, request: &mut Request) -> Reply {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["health"]) => Ok((200, json(&BTreeMap::from([("status", "ok")])))),
+        (Method::Get, ["metrics"]) => Ok((200, metrics.render())),
+        (Method::Get, ["scoring"]) => {
+            let scoring = registry.scoring.read().unwrap();
+            let list: Vec<_> = scoring
+                .iter()
+                .map(|(id, entry)| ScoringInfo { id, path: &entry.path, variants: entry.weights.len() })
+                .collect();
+            Ok((200, json(&list)))
+        }
+        (Method::Post, ["scoring"]) => {
+            let body: RegisterRequest = parse_body(request)?;
+            let variants = registry.register(&body.id, &body.path)?;
+            Ok((201, json(&ScoringInfo { id: &body.id, path: &body.path, variants })))
+        }
+        (Method::Post, ["scoring", id, "score"]) => {
+            let entry = registry.get(id).ok_or_else(|| Failure(404, format!("unknown scoring file {:?}", id)))?;
+            let scorer = scorer_for(entry.weights, query)?;
+            let _in_flight = metrics.in_flight();
+            let start = Instant::now();
+            let results = score(&scorer, request);
+            match &results {
+                Ok(results) => metrics.job_succeeded(start.elapsed(), results),
+                Err(_) => metrics.job_failed(start.elapsed()),
+            }
+            Ok((200, json(&results?)))
+        }
+        (_, ["health"] | ["metrics"] | ["scoring"] | ["scoring", _, "score"]) => Err(Failure(405, "method not allowed".to_string())),
+        _ => Err(Failure(404, format!("no route for {}", path))),
+    }
+}
+
+/// Build a scorer with the options in the query string.
+fn scorer_for(weights: Arc<EffectWeights>, query: &str) -> std::result::Result<Scorer, Failure> {
+    let mut scorer = Scorer::from_shared(weights);
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        scorer = match key {
+            "dosage" => scorer.dosage(matches!(value, "true" | "1" | "")),
+            "model" => scorer.model(Model::from_str(value, true).map_err(|e| Failure(400, format!("model: {}", e)))?),
+            _ => return Err(Failure(400, format!("unknown query parameter {:?}", key))),
+        };
+    }
+    Ok(scorer)
+}
+
+/// Score the VCF named in a JSON body, or uploaded as the body itself.
+fn score(scorer: &Scorer, request: &mut Request) -> std::result::Result<ScoreResults, Failure> {
+    if is_json(request) {
+        let body: ScoreRequest = parse_body(request)?;
+        return Ok(scorer.run(&body.vcf)?);
+    }
+    // tiny_http only lends out the body reader, so an upload is buffered before scoring
+    let mut upload = Vec::new();
+    request.as_reader().read_to_end(&mut upload).with_path("upload")?;
+    let reader = source::decompressed(Cursor::new(upload)).with_path("upload")?;
+    let mut vcf = scorer.vcf_source("upload", reader)?;
+    Ok(scorer.run_source(&mut vcf)?)
+}
+
+fn is_json(request: &Request) -> bool {
+    request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"))
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(request: &mut Request) -> std::result::Result<T, Failure> {
+    serde_json::from_reader(request.as_reader()).map_err(|e| Failure(400, format!("invalid request body: {}", e)))
+}
+
+fn json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).expect("response serialization cannot fail")
+}
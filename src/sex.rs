@@ -0,0 +1,78 @@
+//! Sex-stratified normalization.
+//!
+//! Given each sample's sex (`--sex`), every sample's score is also reported as a z-score
+//! against the mean and standard deviation of the scored samples of the same sex, as
+//! traits with sex-specific distributions such as lipids and anthropometrics need. The
+//! per-sex means and SDs are reported alongside.
+
+use std::collections::HashMap;
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::evaluate::Table;
+use crate::scorer::SampleScore;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sex {
+    Male,
+    Female,
+}
+
+/// Each sample's sex, by sample ID.
+pub type Sexes = HashMap<String, Sex>;
+
+/// Score distribution of the scored samples of one sex.
+#[derive(Clone, Debug, Serialize)]
+pub struct SexStratum {
+    pub sex: Sex,
+    pub samples: usize,
+    pub mean: f64,
+    /// Sample standard deviation
+    pub sd: f64,
+}
+
+/// Read sample sexes from column `column` of a sample table with an IID, #IID or
+/// Sample_Name column, such as a plink2 .psam. Sex is coded `1`/`M`/`male` or
+/// `2`/`F`/`female`; `0` and the usual missing values leave a sample's sex unknown.
+pub fn load_sexes(path: &str, column: &str) -> Result<Sexes> {
+    let table = Table::read(path)?;
+    let (ids, sex_index) = (table.id_column()?, table.column(column)?);
+    let mut sexes = HashMap::new();
+    for (index, row) in table.rows.iter().enumerate() {
+        let sex = match row[sex_index].to_ascii_lowercase().as_str() {
+            "1" | "m" | "male" => Sex::Male,
+            "2" | "f" | "female" => Sex::Female,
+            "0" | "" | "na" | "nan" | "." | "-9" => continue,
+            _ => {
+                let message = format!("Unrecognized sex '{}'", row[sex_index]);
+                return Err(Error::parse(path, index + 2, message, &row.join("\t")));
+            }
+        };
+        if sexes.insert(row[ids].clone(), sex).is_some() {
+            return Err(Error::invalid(path, format!("duplicate sample '{}'", row[ids])));
+        }
+    }
+    Ok(sexes)
+}
+
+/// Set each sample's `sex_z` from the scores of the samples of its sex, and return the
+/// strata. Samples of unknown sex, or of a sex with fewer than two samples or no score
+/// variation, are left without one.
+pub fn normalize(samples: &mut [SampleScore], sexes: &Sexes) -> Vec<SexStratum> {
+    let mut strata = Vec::new();
+    for sex in [Sex::Male, Sex::Female] {
+        let scores: Vec<f64> = samples.iter().filter(|s| sexes.get(&s.name) == Some(&sex)).map(|s| s.score).collect();
+        let n = scores.len() as f64;
+        if scores.len() < 2 {
+            continue;
+        }
+        let mean = scores.iter().sum::<f64>() / n;
+        let sd = (scores.iter().map(|score| (score - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+        strata.push(SexStratum { sex, samples: scores.len(), mean, sd });
+    }
+    for sample in samples.iter_mut() {
+        let stratum = sexes.get(&sample.name).and_then(|sex| strata.iter().find(|stratum| stratum.sex == *sex));
+        sample.sex_z = stratum.filter(|stratum| stratum.sd > 0.0).map(|stratum| (sample.score - stratum.mean) / stratum.sd);
+    }
+    strata
+}
@@ -0,0 +1,151 @@
+//! `speedscore simulate`: synthetic VCFs with a matching scoring file and the
+//! scores SpeedScore should produce for them, for benchmarking and for validating
+//! pipelines built around it.
+//!
+//! Uses its own small PRNG so that a given `--seed` produces the same files
+//! regardless of dependency versions.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::common::{create_parent_dirs, SimulateArgs};
+use crate::error::{Error, IoContext, Result};
+use crate::rng::Rng;
+
+const BASES: [&str; 4] = ["A", "C", "G", "T"];
+
+/// Expected totals for one sample under one genotype field.
+#[derive(Clone, Copy, Default)]
+struct Expected {
+    score: f64,
+    matched: usize,
+}
+
+/// Write `PREFIX.vcf.gz`, `PREFIX.scoring.txt` and `PREFIX.expected.tsv`.
+pub fn run(args: &SimulateArgs) -> Result<()> {
+    for (name, value) in [("--scored-fraction", args.scored_fraction), ("--missing-rate", args.missing_rate)] {
+        if !(0.0..=1.0).contains(&value) {
+            return Err(Error::invalid(name, format!("{} is not between 0 and 1", value)));
+        }
+    }
+    if args.samples == 0 || args.chromosomes == 0 {
+        return Err(Error::invalid("simulate", "--samples and --chromosomes must be at least 1"));
+    }
+
+    let vcf_path = format!("{}.vcf.gz", args.out);
+    let scoring_path = format!("{}.scoring.txt", args.out);
+    let expected_path = format!("{}.expected.tsv", args.out);
+    create_parent_dirs(&vcf_path)?;
+
+    let mut rng = Rng(args.seed);
+    let mut vcf = GzEncoder::new(BufWriter::new(File::create(&vcf_path).with_path(&vcf_path)?), Compression::fast());
+    let mut scoring = BufWriter::new(File::create(&scoring_path).with_path(&scoring_path)?);
+    let sample_names: Vec<String> = (1..=args.samples).map(|i| format!("SAMPLE_{}", i)).collect();
+
+    let chr_prefix = if args.chr_prefix { "chr" } else { "" };
+    let format = if args.dosage { "GT:DS" } else { "GT" };
+    write_vcf_header(&mut vcf, args, chr_prefix, &sample_names).with_path(&vcf_path)?;
+    writeln!(scoring, "#pgs_id=SIMULATED\n#seed={}", args.seed).with_path(&scoring_path)?;
+    writeln!(scoring, "chr_name\tchr_position\teffect_allele\tother_allele\teffect_weight\tallelefrequency_effect")
+        .with_path(&scoring_path)?;
+
+    let mut expected_gt = vec![Expected::default(); args.samples];
+    let mut expected_ds = vec![Expected::default(); args.samples];
+    let mut scored_variants = 0;
+    let mut line = String::new();
+
+    for chr in 1..=args.chromosomes {
+        let count = args.variants / args.chromosomes + usize::from(chr <= args.variants % args.chromosomes);
+        let mut pos = 10_000;
+        for _ in 0..count {
+            pos += 1 + rng.below(2_000);
+            let ref_index = rng.below(4) as usize;
+            let alt_index = (ref_index + 1 + rng.below(3) as usize) % 4;
+            let (ref_allele, alt_allele) = (BASES[ref_index], BASES[alt_index]);
+            let alt_freq = 0.05 + 0.45 * rng.next_f64();
+
+            // Scoring entry, with the weight read back exactly as the scorer will parse it
+            let weight = if rng.next_f64() < args.scored_fraction {
+                let effect_is_alt = rng.below(2) == 0;
+                let (effect, other) = if effect_is_alt { (alt_allele, ref_allele) } else { (ref_allele, alt_allele) };
+                let weight_text = format!("{:.4}", rng.next_f64() - 0.5);
+                let effect_freq = if effect_is_alt { alt_freq } else { 1.0 - alt_freq };
+                writeln!(scoring, "{}{}\t{}\t{}\t{}\t{}\t{:.4}", chr_prefix, chr, pos, effect, other, weight_text, effect_freq)
+                    .with_path(&scoring_path)?;
+                scored_variants += 1;
+                Some((weight_text.parse::<f32>().unwrap_or_default() as f64, effect_is_alt))
+            } else {
+                None
+            };
+
+            line.clear();
+            line.push_str(&format!("{}{}\t{}\t.\t{}\t{}\t.\tPASS\t.\t{}", chr_prefix, chr, pos, ref_allele, alt_allele, format));
+            for sample in 0..args.samples {
+                if rng.next_f64() < args.missing_rate {
+                    line.push_str(if args.dosage { "\t./.:." } else { "\t./." });
+                    continue;
+                }
+                let a = u8::from(rng.next_f64() < alt_freq);
+                let b = u8::from(rng.next_f64() < alt_freq);
+                let alt_count = (a + b) as f64;
+                line.push_str(&format!("\t{}|{}", a, b));
+
+                let mut alt_dosage = alt_count;
+                if args.dosage {
+                    // Imputation-style uncertainty around the hard call
+                    let ds_text = format!("{:.3}", (alt_count + 0.4 * (rng.next_f64() - 0.5)).clamp(0.0, 2.0));
+                    alt_dosage = ds_text.parse().unwrap_or(alt_count);
+                    line.push(':');
+                    line.push_str(&ds_text);
+                }
+
+                if let Some((weight, effect_is_alt)) = weight {
+                    let effect = |alt: f64| if effect_is_alt { alt } else { 2.0 - alt };
+                    expected_gt[sample].score += weight * effect(alt_count);
+                    expected_gt[sample].matched += 1;
+                    expected_ds[sample].score += weight * effect(alt_dosage);
+                    expected_ds[sample].matched += 1;
+                }
+            }
+            writeln!(vcf, "{}", line).with_path(&vcf_path)?;
+        }
+    }
+
+    vcf.finish().and_then(|mut w| w.flush()).with_path(&vcf_path)?;
+    scoring.flush().with_path(&scoring_path)?;
+    write_expected(&expected_path, &sample_names, &expected_gt, args.dosage.then_some(&expected_ds[..]))?;
+
+    println!("Wrote {} ({} samples, {} variants)", vcf_path, args.samples, args.variants);
+    println!("Wrote {} ({} variants)", scoring_path, scored_variants);
+    println!("Wrote {}", expected_path);
+    Ok(())
+}
+
+fn write_vcf_header(out: &mut impl Write, args: &SimulateArgs, chr_prefix: &str, sample_names: &[String]) -> std::io::Result<()> {
+    writeln!(out, "##fileformat=VCFv4.2")?;
+    writeln!(out, "##source=SpeedScore simulate (seed {})", args.seed)?;
+    for chr in 1..=args.chromosomes {
+        writeln!(out, "##contig=<ID={}{}>", chr_prefix, chr)?;
+    }
+    writeln!(out, "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">")?;
+    if args.dosage {
+        writeln!(out, "##FORMAT=<ID=DS,Number=1,Type=Float,Description=\"Alternate allele dosage\">")?;
+    }
+    writeln!(out, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}", sample_names.join("\t"))
+}
+
+/// Expected additive-model scores: from GT, and from DS when dosages were written.
+fn write_expected(path: &str, sample_names: &[String], gt: &[Expected], ds: Option<&[Expected]>) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path).with_path(path)?);
+    let ds_columns = if ds.is_some() { "\tExpected_Score_DS\tExpected_Matched_Variants_DS" } else { "" };
+    writeln!(out, "Sample_Name\tExpected_Score\tExpected_Matched_Variants{}", ds_columns).with_path(path)?;
+    for (i, name) in sample_names.iter().enumerate() {
+        write!(out, "{}\t{:.6}\t{}", name, gt[i].score, gt[i].matched).with_path(path)?;
+        if let Some(ds) = ds {
+            write!(out, "\t{:.6}\t{}", ds[i].score, ds[i].matched).with_path(path)?;
+        }
+        writeln!(out).with_path(path)?;
+    }
+    out.flush().with_path(path)
+}
@@ -1,116 +1,341 @@
-use flate2::read::MultiGzDecoder;
 use rayon::prelude::*;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use crate::common::{header_sample_names, total_abs_weight, trim_line, weight_share, EffectWeights, ScoreOptions, ScoreSummary, SiteCounts};
+use std::io::BufRead;
+use crate::aliases;
+use crate::concat;
+use crate::error::{Error, ErrorPolicy, Result};
+use crate::htsget;
+use crate::input::VcfInput;
+use crate::rejected::{allele_mismatch, Input, Reason, Rejections};
+use crate::resources;
+use crate::spans::Stage;
+use crate::tabix;
 
 /// Single sample polygenic score calculation.
 ///
-/// `effect_weights` is a map from (chr, pos) -> (effect_allele, effect_weight).
+/// `effect_weights` is a map from (chr, pos) -> (effect_allele, effect_weight). The VCF is
+/// streamed [`Tuning::line_batch`](resources::Tuning::line_batch) lines at a time, each batch
+/// scored in parallel and added to the running tally, so memory does not grow with the file.
 pub fn calculate_polygenic_score(
-    path: &str,
-    effect_weights: &HashMap<(String, u32), (String, f32)>,
-) -> io::Result<(f64, usize, usize, bool)> {
-    let file = File::open(path)?;
-    let reader = BufReader::with_capacity(1024 * 1024, MultiGzDecoder::new(file)); // 1MB buffer
-
-    // Read entire file lines
-    let lines: Vec<String> = reader.lines().collect::<io::Result<_>>()?;
-
-    // Detect whether the VCF uses "chr" prefix by scanning first non‐header line
-    let vcf_chr_format = lines.iter()
-        .find(|line| !line.starts_with('#'))
-        .map(|line| line.starts_with("chr"))
-        .unwrap_or(false);
-
-    // We will parallelize over lines, collecting (score, total, matched)
-    let (score_sum, total_variants, matched_variants) = lines
-        .par_iter()
-        .filter(|line| !line.starts_with('#'))
-        .map(|line| process_single_sample_line(line, effect_weights))
-        .reduce(
-            || (0.0, 0, 0),
-            |acc, val| (acc.0 + val.0, acc.1 + val.1, acc.2 + val.2),
-        );
-
-    Ok((score_sum, total_variants, matched_variants, vcf_chr_format))
+    input: &VcfInput,
+    effect_weights: &EffectWeights,
+    options: &ScoreOptions,
+    policy: &ErrorPolicy,
+) -> Result<ScoreSummary> {
+    let path = input.path();
+    let stage = Stage::scan_vcf(path);
+    let config = input.config();
+    let mut reader = match input.file() {
+        Some(file) => match tabix::open(file, effect_weights.keys(), config)? {
+            Some(indexed) => indexed.reader,
+            None if htsget::is_endpoint(file, config) => htsget::open(file, input.peek()?, effect_weights.keys())?,
+            None => input.open()?,
+        },
+        None => Box::new(concat::open(input.paths(), Some(effect_weights.keys().cloned().collect()), config)?),
+    };
+    let line_batch = resources::current().line_batch;
+    let total_weight = total_abs_weight(effect_weights);
+
+    let mut vcf_chr_format = None;
+    // Genotype columns in the header, which every record must have
+    let mut header_samples = 1;
+    let mut tally = LineTally::default();
+    let mut sort_check = policy.sort_check();
+    let mut first_line = 1;
+    loop {
+        // Hold a bounded batch of lines at a time, scoring each batch in parallel
+        let batch = read_batch(&mut reader, line_batch, path, first_line)?;
+        if batch.is_empty() {
+            break;
+        }
+
+        if let Some(header) = batch.iter().filter_map(Line::text).find(|line| line.starts_with("#CHROM")) {
+            header_samples = header_sample_names(header).len();
+        }
+
+        // Detect whether the VCF uses "chr" prefix by scanning first non‐header line
+        if vcf_chr_format.is_none() {
+            vcf_chr_format = batch.iter()
+                .filter_map(Line::text)
+                .find(|line| !line.starts_with('#'))
+                .map(|line| line.starts_with("chr"));
+        }
+
+        // Order has to be checked line by line; malformed positions are left to the policy below
+        let records = batch.iter().enumerate().filter_map(|(index, line)| Some((index, line.text()?)));
+        for (index, line) in records.filter(|(_, line)| !line.starts_with('#')) {
+            let mut fields = line.split('\t').map(str::trim);
+            if let (Some(chr), Some(Ok(pos))) = (fields.next(), fields.next().map(str::parse::<u64>)) {
+                sort_check.check(path, first_line + index, chr, pos, line)?;
+            }
+        }
+
+        // We will parallelize over lines, collecting per-line tallies. They are added up in
+        // line order, so the floating-point sums do not depend on how the work was split.
+        let line_tallies = batch
+            .par_iter()
+            .enumerate()
+            .map(|(index, line)| match line {
+                Line::Text(line) if line.starts_with('#') => Ok(LineTally::default()),
+                Line::Text(line) => {
+                    if !policy.keeps(path, first_line + index, line)? {
+                        return Ok(LineTally::default());
+                    }
+                    match process_single_sample_line(line, first_line + index, header_samples, effect_weights, options, total_weight, policy.rejected()) {
+                        Ok(tally) if tally.non_finite > 0 => policy
+                            .non_finite(options.non_finite, Error::parse(path, first_line + index, "NaN or infinite dosage", line), line)
+                            .map(|_| tally),
+                        Ok(tally) => Ok(tally),
+                        Err(message) => policy
+                            .handle_record(Error::parse(path, first_line + index, message, line), line)
+                            .map(|_| LineTally::default()),
+                    }
+                }
+                Line::NotUtf8(lossy) => policy
+                    .handle_record(Error::parse(path, first_line + index, "Invalid UTF-8", lossy), lossy)
+                    .map(|_| LineTally::default()),
+            })
+            .collect::<Result<Vec<LineTally>>>()?;
+        tally = line_tallies.into_iter().fold(tally, LineTally::add);
+        first_line += batch.len();
+    }
+    stage.record("samples", 1);
+    stage.record("variants", tally.total);
+    stage.record("matched", tally.sites.allele_hits);
+
+    Ok(ScoreSummary {
+        score: tally.score,
+        total_variants: tally.total,
+        matched_variants: tally.matched,
+        dosage_sum: tally.dosage_sum,
+        effective_variants: options.info_field.is_some().then_some(tally.effective),
+        weight_coverage: options.rescale_by_coverage.then_some(tally.coverage),
+        filtered_records: policy.filtered(),
+        vcf_chr_format: vcf_chr_format.unwrap_or(false),
+        sites: tally.sites,
+        skipped: policy.skipped(),
+    })
+}
+
+/// A line of the VCF as read, without its line ending.
+enum Line {
+    Text(String),
+    /// A record that is not UTF-8, as lossy text, for the error policy to handle
+    NotUtf8(String),
+}
+
+impl Line {
+    fn text(&self) -> Option<&str> {
+        match self {
+            Line::Text(line) => Some(line),
+            Line::NotUtf8(_) => None,
+        }
+    }
+}
+
+/// Read up to `count` lines, the first of them line `first_line`. A header line that is not
+/// UTF-8, such as a `##` line in Latin-1, is read lossily, as only the `#CHROM` line matters.
+fn read_batch(reader: &mut impl BufRead, count: usize, path: &str, first_line: usize) -> Result<Vec<Line>> {
+    let mut batch = Vec::with_capacity(count);
+    let mut buffer = Vec::new();
+    while batch.len() < count {
+        if reader.read_until(b'\n', &mut buffer).map_err(|e| Error::read(path, first_line + batch.len(), e))? == 0 {
+            break;
+        }
+        batch.push(match String::from_utf8(std::mem::take(&mut buffer)) {
+            Ok(line) => Line::Text(trimmed(line)),
+            Err(e) if e.as_bytes().starts_with(b"#") => Line::Text(trim_line(&String::from_utf8_lossy(e.as_bytes())).to_string()),
+            Err(e) => Line::NotUtf8(trim_line(&String::from_utf8_lossy(e.as_bytes())).to_string()),
+        });
+    }
+    Ok(batch)
+}
+
+/// `line` as [`trim_line`] leaves it, reallocated only when there was something to trim.
+fn trimmed(line: String) -> String {
+    let trimmed = trim_line(&line);
+    if trimmed.len() == line.len() {
+        line
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct LineTally {
+    score: f64,
+    total: u64,
+    matched: u64,
+    dosage_sum: f64,
+    /// Imputation quality summed over the called genotypes, with `--info-field`
+    effective: f64,
+    /// Share of the total |weight| at the called genotypes
+    coverage: f64,
+    sites: SiteCounts,
+    /// Records with a NaN or infinite dosage
+    non_finite: usize,
+}
+
+impl LineTally {
+    fn add(self, other: Self) -> Self {
+        LineTally {
+            score: self.score + other.score,
+            total: self.total + other.total,
+            matched: self.matched + other.matched,
+            dosage_sum: self.dosage_sum + other.dosage_sum,
+            effective: self.effective + other.effective,
+            coverage: self.coverage + other.coverage,
+            sites: SiteCounts {
+                position_hits: self.sites.position_hits + other.sites.position_hits,
+                allele_hits: self.sites.allele_hits + other.sites.allele_hits,
+            },
+            non_finite: self.non_finite + other.non_finite,
+        }
+    }
+
+    fn counted(position_hit: bool, allele_hit: bool) -> Self {
+        LineTally {
+            total: 1,
+            sites: SiteCounts {
+                position_hits: position_hit as u64,
+                allele_hits: allele_hit as u64,
+            },
+            ..Default::default()
+        }
+    }
 }
 
 /// Process a single VCF line for the single‐sample case:
 ///  - Parse CHR, POS, REF, ALT, sample genotype
 ///  - If (CHR, POS) in effect_weights, check effect allele vs. REF/ALT
-///  - Parse genotype to count effect alleles
-/// Returns `(score, total_variants, matched_variants)`.
+///  - Parse genotype (or dosage) to count effect alleles
+///
+/// Returns the line's contribution to the score and variant counts, or a
+/// description of why the line is malformed. `total_weight` is the scoring file's
+/// summed |effect_weight|. An allele mismatch is kept in `rejections`, if given, under
+/// `line_number`. Only the first sample is scored, but the line must have a genotype
+/// column for each of the `header_samples`.
 fn process_single_sample_line(
     line: &str,
-    effect_weights: &HashMap<(String, u32), (String, f32)>,
-) -> (f64, usize, usize) {
-    let parts: Vec<&str> = line.split('\t').collect();
+    line_number: usize,
+    header_samples: usize,
+    effect_weights: &EffectWeights,
+    options: &ScoreOptions,
+    total_weight: f64,
+    rejections: Option<&Rejections>,
+) -> std::result::Result<LineTally, &'static str> {
+    // Tabs after the last column, as spreadsheets leave them, are not genotype columns
+    let parts: Vec<&str> = line.trim_end_matches('\t').split('\t').collect();
     if parts.len() < 10 {
-        return (0.0, 0, 0); // Malformed line or no genotype
+        return Err("Too few columns (no genotypes)");
+    }
+    if parts.len() - 9 != header_samples {
+        return Err("Genotype columns do not match the header's samples");
     }
 
-    let chr_raw = parts[0];
-    let pos_raw = parts[1];
-    let ref_allele = parts[3];
-    let alt_allele = parts[4];
+    // Hand-edited files can have stray spaces around the fields
+    let chr_raw = parts[0].trim();
+    let pos_raw = parts[1].trim();
+    let ref_allele = parts[3].trim();
+    let alt_allele = parts[4].trim();
     let gt_field = parts[9]; // The sample genotype field (e.g., "0/1", "1/1", "0|1:...")
 
-    // Convert pos to u32
-    let pos = match pos_raw.parse::<u32>() {
+    // Convert pos to u64
+    let pos = match pos_raw.parse::<u64>() {
         Ok(p) => p,
-        Err(_) => return (0.0, 0, 0),
+        Err(_) => return Err("Invalid numeric position"),
     };
 
-    // Normalize chromosome (remove "chr" if present)
-    let normalized_chr = chr_raw.trim_start_matches("chr").to_string();
+    // Normalize chromosome (remove "chr" if present, map accessions)
+    let normalized_chr = aliases::canonical(chr_raw).to_string();
 
     // If not in effect_weights, skip
-    let (effect_allele, weight) = match effect_weights.get(&(normalized_chr.clone(), pos)) {
+    let effect = match effect_weights.get(&(normalized_chr.clone(), pos)) {
         Some(x) => x,
-        None => return (0.0, 1, 0), // total=1, matched=0
+        None => return Ok(LineTally::counted(false, false)), // total=1, matched=0
     };
 
     // Decide if effect_allele is the REF or the ALT. If neither, skip
-    let effect_is_ref = effect_allele == ref_allele;
-    let effect_is_alt = effect_allele == alt_allele;
+    let effect_is_ref = effect.effect_allele == ref_allele;
+    let effect_is_alt = effect.effect_allele == alt_allele;
     if !effect_is_ref && !effect_is_alt {
         // The scoring file says effect_allele is something else (e.g. "T") 
         // but the VCF has REF="A", ALT="G". No match => skip
-        return (0.0, 1, 0);
+        if let Some(rejections) = rejections {
+            rejections.push(Input::Vcf, line_number, Reason::AlleleMismatch, allele_mismatch(&effect.effect_allele, ref_allele, alt_allele), line);
+        }
+        return Ok(LineTally::counted(true, false));
     }
 
-    // Extract just the genotype itself (e.g. "0/1") from "0/1:..."
-    let genotype = gt_field.split(':').next().unwrap_or(".");
+    // Extract the genotype (e.g. "0/1" from "0/1:...") or the DS value and count effect alleles
+    let mut non_finite = false;
+    let allele_count = options
+        .field_index(parts[8])
+        .and_then(|index| options.checked_effect_count(gt_field, index, effect_is_alt, &mut non_finite));
 
-    // Count how many effect alleles
-    match parse_allele_count(genotype, effect_is_alt) {
+    Ok(match allele_count {
         Some(allele_count) => {
-            let line_score = *weight as f64 * allele_count as f64;
-            (line_score, 1, 1)
+            LineTally {
+                // A lone sample has no cohort to estimate frequencies from, so only
+                // scoring-file centering applies here
+                score: options.contribution(effect, allele_count) - options.centering_offset(effect, None),
+                matched: 1,
+                dosage_sum: allele_count,
+                effective: options.info_weight(parts[7]).unwrap_or(0.0),
+                coverage: weight_share(effect, total_weight),
+                non_finite: non_finite as usize,
+                ..LineTally::counted(true, true)
+            }
         }
         None => {
             // Missing or invalid genotype => skip
-            (0.0, 1, 0)
+            LineTally { non_finite: non_finite as usize, ..LineTally::counted(true, true) }
         }
-    }
+    })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::EffectWeight;
+    use crate::error::OnError;
+    use crate::input::InputConfig;
 
-/// Helper that counts how many effect alleles are present in `genotype`.
-/// If `effect_is_alt` = true, we count `'1'` as effect alleles.
-/// If `effect_is_alt` = false, we count `'0'` as effect alleles.
-/// Returns None if we see multi‐allelic (e.g. '2') or missing ('.').
-fn parse_allele_count(genotype: &str, effect_is_alt: bool) -> Option<u8> {
-    let mut count = 0u8;
-    for c in genotype.chars() {
-        match c {
-            '0' if !effect_is_alt => count += 1,
-            '1' if effect_is_alt => count += 1,
-            '.' | '2' | '3' => return None, // skip multi‐allelic or missing
-            '|' | '/' => {} // just a delimiter
-            _ => {}
+    fn weight(effect_allele: &str, weight: f32) -> EffectWeight {
+        EffectWeight {
+            effect_allele: effect_allele.to_string(),
+            weight,
+            frequency: None,
+            dominance: 0.0,
+            p_value: None,
+            standard_error: None,
+            interaction: 0.0,
+            maternal: 0.0,
+            paternal: 0.0,
         }
     }
-    Some(count)
+
+    #[test]
+    fn skips_a_record_that_is_not_utf8() {
+        let path = std::env::temp_dir().join(format!("speedscore-latin1-{}.vcf", std::process::id()));
+        let mut vcf = b"##fileformat=VCFv4.2\n##comment=caf\xe9\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\n".to_vec();
+        vcf.extend_from_slice(b"1\t100\trs1\tA\tG\t.\t.\t.\tGT\t0/1\n");
+        vcf.extend_from_slice(b"1\t150\tcaf\xe9\tA\tG\t.\t.\t.\tGT\t1/1\n");
+        vcf.extend_from_slice(b"1\t200\trs2\tC\tT\t.\t.\t.\tGT\t1/1\n");
+        std::fs::write(&path, vcf).unwrap();
+        let path = path.to_str().unwrap();
+        let effect_weights: EffectWeights = [(("1".to_string(), 100), weight("G", 1.0)), (("1".to_string(), 200), weight("T", 0.5))].into();
+        let input = VcfInput::new(path, &InputConfig::default()).unwrap();
+
+        let failed = calculate_polygenic_score(&input, &effect_weights, &ScoreOptions::default(), &ErrorPolicy::new(OnError::Fail, None));
+        let policy = ErrorPolicy::new(OnError::Skip, None);
+        let summary = calculate_polygenic_score(&input, &effect_weights, &ScoreOptions::default(), &policy).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert!(failed.unwrap_err().to_string().contains("line 5: Invalid UTF-8"));
+        assert_eq!(summary.score, 2.0);
+        assert_eq!((summary.total_variants, summary.matched_variants), (2, 2));
+        assert_eq!(summary.skipped[0].reason, "Invalid UTF-8");
+        assert_eq!(summary.skipped[0].first_line, Some(5));
+    }
 }
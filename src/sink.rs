@@ -0,0 +1,715 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+use clap::ValueEnum;
+use crate::catalog::ScoreMetadata;
+use crate::common::{create_parent_dirs, ScoreSummary};
+use crate::error::{Error, IoContext, Result};
+use crate::scale::ReportScale;
+use crate::schema;
+use crate::scorer::{SampleScore, ScoreResults};
+use crate::sex::SexStratum;
+use crate::spans::Stage;
+use crate::trio::TrioSummary;
+use crate::{panel, partition, thresholding};
+
+/// Run-level context handed to a sink before any sample is written.
+pub struct RunInfo<'a> {
+    pub vcf_path: &'a str,
+    pub duration: Duration,
+    /// Names of the ancestry PCs samples were projected onto; empty without `--pca-loadings`
+    pub pcs: &'a [String],
+    /// Whether samples carry calibrated z-scores (`--calibration`)
+    pub calibrated: bool,
+    /// `--pt-thresholds` p-value thresholds, ascending; empty without them
+    pub thresholds: &'a [f64],
+    /// Whether samples carry score standard errors (scoring files with `effect_weight_se`)
+    pub standard_errors: bool,
+    /// Whether samples carry an info-weighted effective variant count (`--info-field`)
+    pub effective_variants: bool,
+    /// Whether samples carry a weight coverage and rescaled score (`--rescale-by-coverage`)
+    pub rescaled: bool,
+    /// Whether samples carry a permutation null percentile and z-score (`--permutations`)
+    pub permutations: bool,
+    /// Names of the `--set-column`/`--set-bed` sets samples have partial scores for; empty without them
+    pub sets: &'a [String],
+    /// Per-sex score distributions the samples' `sex_z` is relative to; `None` without `--sex`
+    pub sex_strata: Option<&'a [SexStratum]>,
+    /// Whether rows have interaction and GxE score columns (`--environment`), which are
+    /// blank for samples without an exposure
+    pub interactions: bool,
+    /// Whether samples carry maternal and paternal scores (`--parent-of-origin`)
+    pub parent_of_origin: bool,
+    /// pTDT over the trios; `None` without `--pedigree`. Rows have mid-parent and offspring
+    /// deviation columns, blank for samples without both parents scored
+    pub trios: Option<&'a TrioSummary>,
+    /// Population of the `--reference` distribution samples are placed in; `None` without one
+    pub reference: Option<&'a str>,
+    /// Reporting scales samples carry; `None` without any
+    pub scale: Option<&'a ReportScale>,
+    /// The PGS Catalog's description of the score; `None` without `--pgs-catalog`
+    pub pgs_catalog: Option<&'a ScoreMetadata>,
+    /// IDs of the several `--scoring` files samples have a score over each of; empty with one
+    pub scores: &'a [String],
+}
+
+/// Destination for per-sample results. Implement this to stream scores into
+/// your own storage instead of a file.
+pub trait ScoreSink {
+    fn begin(&mut self, _run: &RunInfo) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_sample(&mut self, sample: &SampleScore) -> Result<()>;
+
+    /// Called once after the last sample. Buffered sinks flush here.
+    fn finish(&mut self, _summary: &ScoreSummary) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Collecting into a `Vec` is the simplest in-memory sink.
+impl ScoreSink for Vec<SampleScore> {
+    fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+        self.push(sample.clone());
+        Ok(())
+    }
+}
+
+/// Wraps a sink to write every run's calculation time as 0 (`--reproducible`), the one
+/// value that differs between runs on the same inputs.
+pub struct Untimed(pub Box<dyn ScoreSink>);
+
+impl ScoreSink for Untimed {
+    fn begin(&mut self, run: &RunInfo) -> Result<()> {
+        self.0.begin(&RunInfo { duration: Duration::ZERO, ..*run })
+    }
+
+    fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+        self.0.write_sample(sample)
+    }
+
+    fn finish(&mut self, summary: &ScoreSummary) -> Result<()> {
+        self.0.finish(summary)
+    }
+}
+
+/// Sends every sample to two sinks, e.g. the results file and a `--lims-mapping` table.
+pub struct Tee(pub Box<dyn ScoreSink>, pub Box<dyn ScoreSink>);
+
+impl ScoreSink for Tee {
+    fn begin(&mut self, run: &RunInfo) -> Result<()> {
+        self.0.begin(run)?;
+        self.1.begin(run)
+    }
+
+    fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+        self.0.write_sample(sample)?;
+        self.1.write_sample(sample)
+    }
+
+    fn finish(&mut self, summary: &ScoreSummary) -> Result<()> {
+        self.0.finish(summary)?;
+        self.1.finish(summary)
+    }
+}
+
+/// Send every sample of `results` through `sink`.
+pub fn emit(results: &ScoreResults, run: &RunInfo, sink: &mut dyn ScoreSink) -> Result<()> {
+    let stage = Stage::write_output();
+    sink.begin(run)?;
+    for sample in &results.samples {
+        sink.write_sample(sample)?;
+    }
+    sink.finish(&results.summary)?;
+    stage.record("samples", results.samples.len() as u64);
+    Ok(())
+}
+
+/// File formats available for per-sample output.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    /// Requires building with `--features parquet`
+    Parquet,
+    /// Requires building with `--features sqlite`
+    Sqlite,
+    /// plink2 `--score` output, for comparing runs sample by sample
+    Sscore,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Sqlite => "sqlite",
+            OutputFormat::Sscore => "sscore",
+        }
+    }
+}
+
+/// Open a file sink of the given format at `path`, creating parent directories.
+pub fn open_sink(format: OutputFormat, path: &str) -> Result<Box<dyn ScoreSink>> {
+    create_parent_dirs(path)?;
+    match format {
+        OutputFormat::Csv => Ok(Box::new(CsvSink::create(path)?)),
+        OutputFormat::Json => Ok(Box::new(JsonSink::create(path)?)),
+        OutputFormat::Sscore => Ok(Box::new(SscoreSink::create(path)?)),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => Ok(Box::new(parquet_sink::ParquetSink::create(path)?)),
+        #[cfg(feature = "sqlite")]
+        OutputFormat::Sqlite => Ok(Box::new(sqlite_sink::SqliteSink::create(path)?)),
+        #[allow(unreachable_patterns)]
+        other => Err(Error::invalid(
+            path,
+            format!("{} output is not available in this build; rebuild with --features {}", other.extension(), other.extension()),
+        )),
+    }
+}
+
+/// `field` as a CSV field (RFC 4180): in double quotes, with its own double quotes doubled,
+/// if it holds a comma, a double quote or a line break, and as it is otherwise.
+pub fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// The classic comma-separated per-sample table.
+pub struct CsvSink {
+    path: String,
+    out: BufWriter<File>,
+    vcf_path: String,
+    seconds: f64,
+    /// Whether rows have a Sex_Z column, which is blank for some samples
+    sex_z: bool,
+    /// Whether rows have Interaction_Score and GxE_Score columns, blank for some samples
+    interactions: bool,
+    /// Whether rows have Midparent_Score and Offspring_Deviation columns, blank for some samples
+    trios: bool,
+    /// Reporting scales rows have columns for
+    scale: Option<ReportScale>,
+    /// Sample names written so far
+    names: HashSet<String>,
+}
+
+impl CsvSink {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_path(path)?;
+        Ok(CsvSink { path: path.to_string(), out: BufWriter::new(file), vcf_path: String::new(), seconds: 0.0, sex_z: false, interactions: false, trios: false, scale: None, names: HashSet::new() })
+    }
+
+    /// `name`, or if an earlier row has it, `name` with the first `_2`, `_3`, ... suffix no
+    /// earlier row has, so that no two rows share a name. Names from a VCF are unique
+    /// already; other sources may repeat them.
+    fn unique_name(&mut self, name: &str) -> String {
+        let unique = if self.names.contains(name) {
+            (2..).map(|k| format!("{}_{}", name, k)).find(|candidate| !self.names.contains(candidate)).expect("some suffix is free")
+        } else {
+            name.to_string()
+        };
+        self.names.insert(unique.clone());
+        unique
+    }
+}
+
+impl ScoreSink for CsvSink {
+    fn begin(&mut self, run: &RunInfo) -> Result<()> {
+        self.vcf_path = run.vcf_path.to_string();
+        self.seconds = run.duration.as_secs_f64();
+        write!(self.out, "VCF_File,Sample_Name,Polygenic_Score,Calculation_Time_Seconds,Total_Variants,Matched_Variants")
+            .with_path(&self.path)?;
+        if run.effective_variants {
+            write!(self.out, ",Effective_Variants").with_path(&self.path)?;
+        }
+        if run.rescaled {
+            write!(self.out, ",Weight_Coverage,Rescaled_Score").with_path(&self.path)?;
+        }
+        if run.standard_errors {
+            write!(self.out, ",Score_SE").with_path(&self.path)?;
+        }
+        if !run.pcs.is_empty() {
+            for pc in run.pcs {
+                write!(self.out, ",{}", csv_field(pc)).with_path(&self.path)?;
+            }
+            write!(self.out, ",Adjusted_Polygenic_Score").with_path(&self.path)?;
+        }
+        if run.calibrated {
+            write!(self.out, ",Calibrated_Z").with_path(&self.path)?;
+        }
+        for &threshold in run.thresholds {
+            write!(self.out, ",{}", thresholding::column_name(threshold)).with_path(&self.path)?;
+        }
+        if run.permutations {
+            write!(self.out, ",Null_Percentile,Null_Z").with_path(&self.path)?;
+        }
+        for name in run.sets {
+            write!(self.out, ",{}", csv_field(&partition::column_name(name))).with_path(&self.path)?;
+        }
+        for id in run.scores {
+            write!(self.out, ",{}", csv_field(&panel::column_name(id))).with_path(&self.path)?;
+        }
+        for id in run.scores {
+            write!(self.out, ",{}", csv_field(&panel::matched_column_name(id))).with_path(&self.path)?;
+        }
+        self.interactions = run.interactions;
+        if self.interactions {
+            write!(self.out, ",Interaction_Score,GxE_Score").with_path(&self.path)?;
+        }
+        if run.parent_of_origin {
+            write!(self.out, ",Maternal_Score,Paternal_Score").with_path(&self.path)?;
+        }
+        self.trios = run.trios.is_some();
+        if self.trios {
+            write!(self.out, ",Midparent_Score,Offspring_Deviation").with_path(&self.path)?;
+        }
+        self.sex_z = run.sex_strata.is_some();
+        if self.sex_z {
+            write!(self.out, ",Sex_Z").with_path(&self.path)?;
+        }
+        if run.reference.is_some() {
+            write!(self.out, ",Reference_Percentile,Reference_Z").with_path(&self.path)?;
+        }
+        self.scale = run.scale.cloned();
+        if let Some(scale) = &self.scale {
+            for (requested, column) in [(scale.t_scores, "T_Score"), (scale.percentiles, "Percentile"), (!scale.cutoffs.is_empty(), "Risk_Category")] {
+                if requested {
+                    write!(self.out, ",{}", column).with_path(&self.path)?;
+                }
+            }
+        }
+        writeln!(self.out).with_path(&self.path)
+    }
+
+    fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+        let name = self.unique_name(&sample.name);
+        write!(
+            self.out,
+            "{},{},{:.6},{:.6},{},{}",
+            csv_field(&self.vcf_path),
+            csv_field(&name),
+            sample.score,
+            self.seconds,
+            sample.total_variants,
+            sample.matched_variants
+        )
+        .with_path(&self.path)?;
+        if let Some(effective) = sample.effective_variants {
+            write!(self.out, ",{:.4}", effective).with_path(&self.path)?;
+        }
+        if let Some(coverage) = sample.weight_coverage {
+            // The rescaled score is left blank when no weight was covered
+            match sample.rescaled_score {
+                Some(rescaled) => write!(self.out, ",{:.6},{:.6}", coverage, rescaled),
+                None => write!(self.out, ",{:.6},", coverage),
+            }
+            .with_path(&self.path)?;
+        }
+        if let Some(se) = sample.score_se {
+            write!(self.out, ",{:.6}", se).with_path(&self.path)?;
+        }
+        if !sample.pcs.is_empty() {
+            for pc in &sample.pcs {
+                write!(self.out, ",{:.6}", pc).with_path(&self.path)?;
+            }
+            // Left blank when the cohort is too small to regress on the PCs
+            match sample.adjusted_score {
+                Some(adjusted) => write!(self.out, ",{:.6}", adjusted),
+                None => write!(self.out, ","),
+            }
+            .with_path(&self.path)?;
+        }
+        if let Some(z) = sample.calibrated_z {
+            write!(self.out, ",{:.6}", z).with_path(&self.path)?;
+        }
+        for score in &sample.threshold_scores {
+            write!(self.out, ",{:.6}", score).with_path(&self.path)?;
+        }
+        if let Some(percentile) = sample.null_percentile {
+            // The z-score is left blank when every null score is the same
+            match sample.null_z {
+                Some(z) => write!(self.out, ",{:.4},{:.6}", percentile, z),
+                None => write!(self.out, ",{:.4},", percentile),
+            }
+            .with_path(&self.path)?;
+        }
+        for score in &sample.set_scores {
+            write!(self.out, ",{:.6}", score).with_path(&self.path)?;
+        }
+        for score in &sample.scores {
+            write!(self.out, ",{:.6}", score).with_path(&self.path)?;
+        }
+        for matched in &sample.matched {
+            write!(self.out, ",{}", matched).with_path(&self.path)?;
+        }
+        // Left blank for samples without an exposure
+        if self.interactions {
+            match (sample.interaction_score, sample.gxe_score) {
+                (Some(interaction), Some(gxe)) => write!(self.out, ",{:.6},{:.6}", interaction, gxe),
+                _ => write!(self.out, ",,"),
+            }
+            .with_path(&self.path)?;
+        }
+        if let (Some(maternal), Some(paternal)) = (sample.maternal_score, sample.paternal_score) {
+            write!(self.out, ",{:.6},{:.6}", maternal, paternal).with_path(&self.path)?;
+        }
+        // Left blank for samples without both parents scored
+        if self.trios {
+            match (sample.midparent_score, sample.offspring_deviation) {
+                (Some(midparent), Some(deviation)) => write!(self.out, ",{:.6},{:.6}", midparent, deviation),
+                _ => write!(self.out, ",,"),
+            }
+            .with_path(&self.path)?;
+        }
+        // Left blank for samples of unknown sex
+        if self.sex_z {
+            match sample.sex_z {
+                Some(z) => write!(self.out, ",{:.6}", z),
+                None => write!(self.out, ","),
+            }
+            .with_path(&self.path)?;
+        }
+        if let Some(percentile) = sample.reference_percentile {
+            // The z-score is left blank when the reference scores have no spread
+            match sample.reference_z {
+                Some(z) => write!(self.out, ",{:.4},{:.6}", percentile, z),
+                None => write!(self.out, ",{:.4},", percentile),
+            }
+            .with_path(&self.path)?;
+        }
+        // Scale values are left blank when their standardized score is unavailable
+        if let Some(scale) = &self.scale {
+            if scale.t_scores {
+                write!(self.out, ",{}", sample.t_score.map_or_else(String::new, |t| format!("{:.4}", t))).with_path(&self.path)?;
+            }
+            if scale.percentiles {
+                write!(self.out, ",{}", sample.scale_percentile.map_or_else(String::new, |p| format!("{:.4}", p))).with_path(&self.path)?;
+            }
+            if !scale.cutoffs.is_empty() {
+                write!(self.out, ",{}", csv_field(sample.risk_category.as_deref().unwrap_or_default())).with_path(&self.path)?;
+            }
+        }
+        writeln!(self.out).with_path(&self.path)
+    }
+
+    fn finish(&mut self, _summary: &ScoreSummary) -> Result<()> {
+        self.out.flush().with_path(&self.path)
+    }
+}
+
+/// A single JSON document: the schema version and run metadata, a `samples` array and the
+/// summary. See [`crate::schema`].
+/// Samples are streamed as they arrive rather than buffered.
+pub struct JsonSink {
+    path: String,
+    out: BufWriter<File>,
+    first: bool,
+}
+
+impl JsonSink {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_path(path)?;
+        Ok(JsonSink { path: path.to_string(), out: BufWriter::new(file), first: true })
+    }
+}
+
+impl ScoreSink for JsonSink {
+    fn begin(&mut self, run: &RunInfo) -> Result<()> {
+        write!(
+            self.out,
+            "{{\"schema_version\":{},\"vcf_file\":{},\"calculation_time_seconds\":{:.6},",
+            schema::SCHEMA_VERSION,
+            serde_json::to_string(run.vcf_path).expect("string serialization cannot fail"),
+            run.duration.as_secs_f64()
+        )
+        .with_path(&self.path)?;
+        if let Some(metadata) = run.pgs_catalog {
+            let metadata = serde_json::to_string(metadata).expect("metadata serialization cannot fail");
+            write!(self.out, "\"pgs_catalog\":{},", metadata).with_path(&self.path)?;
+        }
+        if !run.thresholds.is_empty() {
+            let thresholds = serde_json::to_string(run.thresholds).expect("number serialization cannot fail");
+            write!(self.out, "\"pt_thresholds\":{},", thresholds).with_path(&self.path)?;
+        }
+        if !run.sets.is_empty() {
+            let sets = serde_json::to_string(run.sets).expect("string serialization cannot fail");
+            write!(self.out, "\"sets\":{},", sets).with_path(&self.path)?;
+        }
+        if !run.scores.is_empty() {
+            let scores = serde_json::to_string(run.scores).expect("string serialization cannot fail");
+            write!(self.out, "\"scores\":{},", scores).with_path(&self.path)?;
+        }
+        if let Some(population) = run.reference {
+            let population = serde_json::to_string(population).expect("string serialization cannot fail");
+            write!(self.out, "\"reference_population\":{},", population).with_path(&self.path)?;
+        }
+        if let Some(strata) = run.sex_strata {
+            let strata = serde_json::to_string(strata).expect("stratum serialization cannot fail");
+            write!(self.out, "\"sex_strata\":{},", strata).with_path(&self.path)?;
+        }
+        if let Some(trios) = run.trios {
+            let trios = serde_json::to_string(trios).expect("summary serialization cannot fail");
+            write!(self.out, "\"ptdt\":{},", trios).with_path(&self.path)?;
+        }
+        write!(self.out, "\"samples\":[").with_path(&self.path)
+    }
+
+    fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+        if !self.first {
+            write!(self.out, ",").with_path(&self.path)?;
+        }
+        self.first = false;
+        serde_json::to_writer(&mut self.out, sample).map_err(|e| Error::invalid(&self.path, e.to_string()))
+    }
+
+    fn finish(&mut self, summary: &ScoreSummary) -> Result<()> {
+        write!(self.out, "],\"summary\":").with_path(&self.path)?;
+        serde_json::to_writer(&mut self.out, summary).map_err(|e| Error::invalid(&self.path, e.to_string()))?;
+        writeln!(self.out, "}}").with_path(&self.path)?;
+        self.out.flush().with_path(&self.path)
+    }
+}
+
+/// The tab-separated table plink2 `--score` writes, with its default columns: the
+/// number of non-missing alleles, the named (effect) allele dosage sum, and the score
+/// averaged over the allele count. Numbers are printed like plink2's, to 6 significant digits.
+pub struct SscoreSink {
+    path: String,
+    out: BufWriter<File>,
+}
+
+impl SscoreSink {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_path(path)?;
+        Ok(SscoreSink { path: path.to_string(), out: BufWriter::new(file) })
+    }
+}
+
+impl ScoreSink for SscoreSink {
+    fn begin(&mut self, _run: &RunInfo) -> Result<()> {
+        writeln!(self.out, "#IID\tALLELE_CT\tNAMED_ALLELE_DOSAGE_SUM\tSCORE1_AVG").with_path(&self.path)
+    }
+
+    fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+        let allele_count = 2 * sample.matched_variants;
+        writeln!(
+            self.out,
+            "{}\t{}\t{}\t{}",
+            sample.name,
+            allele_count,
+            format_g(sample.dosage_sum),
+            format_g(sample.score / allele_count as f64)
+        )
+        .with_path(&self.path)
+    }
+
+    fn finish(&mut self, _summary: &ScoreSummary) -> Result<()> {
+        self.out.flush().with_path(&self.path)
+    }
+}
+
+/// `value` as C's `%g` prints it: 6 significant digits, trailing zeros dropped, and
+/// scientific notation for exponents below -4 or above 5.
+fn format_g(value: f64) -> String {
+    if !value.is_finite() {
+        return value.to_string().to_lowercase();
+    }
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    // Round to 6 significant digits first: that decides the exponent (9.999995 -> 1e+01)
+    let scientific = format!("{:.5e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').expect("{:e} always has an exponent");
+    let exponent: i32 = exponent.parse().expect("{:e} exponent is an integer");
+    if (-4..6).contains(&exponent) {
+        let fixed = format!("{:.*}", (5 - exponent) as usize, value);
+        trim_fraction(&fixed).to_string()
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_fraction(mantissa), sign, exponent.abs())
+    }
+}
+
+fn trim_fraction(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_sink {
+    use std::fs::File;
+    use std::sync::Arc;
+    use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use super::{RunInfo, ScoreSink};
+    use crate::common::ScoreSummary;
+    use crate::error::{Error, IoContext, Result};
+    use crate::scorer::SampleScore;
+
+    const SCHEMA: &str = "
+        message scores {
+            REQUIRED BYTE_ARRAY vcf_file (UTF8);
+            REQUIRED BYTE_ARRAY sample_name (UTF8);
+            REQUIRED DOUBLE polygenic_score;
+            REQUIRED DOUBLE calculation_time_seconds;
+            REQUIRED INT64 total_variants;
+            REQUIRED INT64 matched_variants;
+        }";
+
+    /// Parquet is columnar, so rows are buffered and written as one row group in `finish`.
+    pub struct ParquetSink {
+        path: String,
+        file: Option<File>,
+        vcf_path: String,
+        seconds: f64,
+        samples: Vec<SampleScore>,
+    }
+
+    impl ParquetSink {
+        pub fn create(path: &str) -> Result<Self> {
+            let file = File::create(path).with_path(path)?;
+            Ok(ParquetSink { path: path.to_string(), file: Some(file), vcf_path: String::new(), seconds: 0.0, samples: Vec::new() })
+        }
+
+        fn write_all(&mut self, file: File) -> parquet::errors::Result<()> {
+            let schema = Arc::new(parse_message_type(SCHEMA)?);
+            let mut writer = SerializedFileWriter::new(file, schema, Arc::new(WriterProperties::builder().build()))?;
+            let mut row_group = writer.next_row_group()?;
+            let n = self.samples.len();
+
+            let mut column = 0;
+            while let Some(mut col) = row_group.next_column()? {
+                match column {
+                    0 => {
+                        let values = vec![ByteArray::from(self.vcf_path.as_str()); n];
+                        col.typed::<ByteArrayType>().write_batch(&values, None, None)?;
+                    }
+                    1 => {
+                        let values: Vec<ByteArray> = self.samples.iter().map(|s| ByteArray::from(s.name.as_str())).collect();
+                        col.typed::<ByteArrayType>().write_batch(&values, None, None)?;
+                    }
+                    2 => {
+                        let values: Vec<f64> = self.samples.iter().map(|s| s.score).collect();
+                        col.typed::<DoubleType>().write_batch(&values, None, None)?;
+                    }
+                    3 => {
+                        col.typed::<DoubleType>().write_batch(&vec![self.seconds; n], None, None)?;
+                    }
+                    4 => {
+                        let values: Vec<i64> = self.samples.iter().map(|s| s.total_variants as i64).collect();
+                        col.typed::<Int64Type>().write_batch(&values, None, None)?;
+                    }
+                    _ => {
+                        let values: Vec<i64> = self.samples.iter().map(|s| s.matched_variants as i64).collect();
+                        col.typed::<Int64Type>().write_batch(&values, None, None)?;
+                    }
+                }
+                col.close()?;
+                column += 1;
+            }
+            row_group.close()?;
+            writer.close()?;
+            Ok(())
+        }
+    }
+
+    impl ScoreSink for ParquetSink {
+        fn begin(&mut self, run: &RunInfo) -> Result<()> {
+            self.vcf_path = run.vcf_path.to_string();
+            self.seconds = run.duration.as_secs_f64();
+            Ok(())
+        }
+
+        fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+            self.samples.push(sample.clone());
+            Ok(())
+        }
+
+        fn finish(&mut self, _summary: &ScoreSummary) -> Result<()> {
+            let file = self.file.take().ok_or_else(|| Error::invalid(&self.path, "sink already finished"))?;
+            self.write_all(file).map_err(|e| Error::invalid(&self.path, e.to_string()))
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite_sink {
+    use rusqlite::{params, Connection};
+    use super::{RunInfo, ScoreSink};
+    use crate::common::ScoreSummary;
+    use crate::error::{Error, Result};
+    use crate::scorer::SampleScore;
+
+    /// Writes rows into a `scores` table, all within one transaction.
+    pub struct SqliteSink {
+        path: String,
+        conn: Connection,
+        vcf_path: String,
+        seconds: f64,
+    }
+
+    impl SqliteSink {
+        pub fn create(path: &str) -> Result<Self> {
+            let conn = Connection::open(path).map_err(|e| Error::invalid(path, e.to_string()))?;
+            Ok(SqliteSink { path: path.to_string(), conn, vcf_path: String::new(), seconds: 0.0 })
+        }
+
+        fn err(&self, e: rusqlite::Error) -> Error {
+            Error::invalid(&self.path, e.to_string())
+        }
+    }
+
+    impl ScoreSink for SqliteSink {
+        fn begin(&mut self, run: &RunInfo) -> Result<()> {
+            self.vcf_path = run.vcf_path.to_string();
+            self.seconds = run.duration.as_secs_f64();
+            self.conn
+                .execute_batch(
+                    "CREATE TABLE IF NOT EXISTS scores (
+                        vcf_file TEXT NOT NULL,
+                        sample_name TEXT NOT NULL,
+                        polygenic_score REAL NOT NULL,
+                        calculation_time_seconds REAL NOT NULL,
+                        total_variants INTEGER NOT NULL,
+                        matched_variants INTEGER NOT NULL
+                    );
+                    BEGIN;",
+                )
+                .map_err(|e| self.err(e))
+        }
+
+        fn write_sample(&mut self, sample: &SampleScore) -> Result<()> {
+            self.conn
+                .execute(
+                    "INSERT INTO scores VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        self.vcf_path,
+                        sample.name,
+                        sample.score,
+                        self.seconds,
+                        sample.total_variants as i64,
+                        sample.matched_variants as i64
+                    ],
+                )
+                .map(|_| ())
+                .map_err(|e| self.err(e))
+        }
+
+        fn finish(&mut self, _summary: &ScoreSummary) -> Result<()> {
+            self.conn.execute_batch("COMMIT;").map_err(|e| self.err(e))
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::io::{self, BufRead, BufReader, Read};
+use crate::aliases;
+use crate::common::{dedupe_sample_names, header_sample_names, total_abs_weight, trim_line, weight_share, EffectWeights, ScoreOptions, ScoreSummary, SiteCounts};
+use crate::error::{Error, ErrorPolicy, NonFinite, OnError, Result};
+use crate::multi_sample::{has_standard_errors, mean_coverage, MultiSampleScores, SampleData};
+use crate::order::{SortCheck, Unsorted};
+use crate::bcf;
+use crate::bgzf::{self, Gunzip};
+use crate::input::{InputConfig, VcfInput};
+use crate::resources;
+use crate::spans::Stage;
+
+/// Position and alleles of one biallelic variant, with "chr" already stripped from `chr`.
+#[derive(Clone, Debug, Default)]
+pub struct Variant {
+    pub chr: String,
+    pub pos: u64,
+    pub ref_allele: String,
+    pub alt_allele: String,
+    /// Whether the input spelled this chromosome with a "chr" prefix
+    pub chr_prefixed: bool,
+}
+
+/// A stream of variants with per-sample genotypes. Implement this to score a
+/// genotype backend that SpeedScore doesn't read natively.
+///
+/// The scoring core calls `next_variant` for every site and `alt_dosages` only
+/// for sites present in the scoring file, so implementations can defer
+/// decoding genotypes until they are asked for.
+pub trait GenotypeSource {
+    /// Sample names, in the order dosages are reported.
+    fn sample_names(&self) -> &[String];
+
+    /// Advance to the next variant, or `Ok(None)` at the end of the input.
+    fn next_variant(&mut self) -> Result<Option<Variant>>;
+
+    /// ALT-allele dosage in [0, 2] of the current variant for every sample
+    /// (`None` for missing). `out` has one slot per sample.
+    fn alt_dosages(&mut self, out: &mut [Option<f64>]) -> Result<()>;
+}
+
+/// Score every sample of `source` against `effect_weights`. Of `options`, only the
+/// model, centering and mean imputation apply; reading dosages is the source's concern.
+pub fn score_source<S: GenotypeSource + ?Sized>(
+    source: &mut S,
+    effect_weights: &EffectWeights,
+    options: &ScoreOptions,
+) -> Result<MultiSampleScores> {
+    let stage = Stage::scan_source();
+    let sample_names = source.sample_names().to_vec();
+    let sample_data_init = SampleData {
+        variance: has_standard_errors(effect_weights).then_some(0.0),
+        weight_coverage: options.rescale_by_coverage.then_some(0.0),
+        ..Default::default()
+    };
+    let total_weight = total_abs_weight(effect_weights);
+    let mut sample_data = vec![sample_data_init; sample_names.len()];
+    let mut dosages = vec![None; sample_names.len()];
+    let mut sites = SiteCounts::default();
+    let mut variants_seen = 0;
+    let mut vcf_chr_format = None;
+
+    while let Some(variant) = source.next_variant()? {
+        variants_seen += 1;
+        vcf_chr_format.get_or_insert(variant.chr_prefixed);
+
+        let Some(effect) = effect_weights.get(&(variant.chr, variant.pos)) else {
+            continue;
+        };
+        sites.position_hits += 1;
+
+        let effect_is_alt = effect.effect_allele == variant.alt_allele;
+        if !effect_is_alt && effect.effect_allele != variant.ref_allele {
+            continue;
+        }
+        sites.allele_hits += 1;
+
+        source.alt_dosages(&mut dosages)?;
+        let effect_count = |alt_dosage: f64| if effect_is_alt { alt_dosage } else { 2.0 - alt_dosage };
+        let cohort_frequency = if options.needs_cohort_frequency() {
+            let called: Vec<f64> = dosages.iter().flatten().map(|&d| effect_count(d)).collect();
+            (!called.is_empty()).then(|| called.iter().sum::<f64>() / (2 * called.len()) as f64)
+        } else {
+            None
+        };
+        let offset = options.centering_offset(effect, cohort_frequency);
+        let imputed = match cohort_frequency {
+            Some(p) if options.mean_impute => options.expected_contribution(effect, p) - offset,
+            _ => 0.0,
+        };
+        for (sample, dosage) in sample_data.iter_mut().zip(&dosages) {
+            let count = dosage.map(effect_count);
+            match count {
+                Some(count) => {
+                    sample.matched_variants += 1;
+                    sample.dosage_sum += count;
+                    sample.score += options.contribution(effect, count) - offset;
+                    if let Some(coverage) = &mut sample.weight_coverage {
+                        *coverage += weight_share(effect, total_weight);
+                    }
+                }
+                None => sample.score += imputed,
+            }
+            if let (Some(variance), Some(se)) = (&mut sample.variance, effect.standard_error) {
+                *variance += (options.weight_sensitivity(effect, count, cohort_frequency) * se as f64).powi(2);
+            }
+        }
+    }
+
+    for sample in &mut sample_data {
+        sample.total_variants = variants_seen;
+    }
+    stage.record("samples", sample_names.len() as u64);
+    stage.record("variants", variants_seen);
+    stage.record("matched", sites.allele_hits);
+
+    let avg_score = sample_data.iter().map(|sd| sd.score).sum::<f64>() / sample_data.len().max(1) as f64;
+    let summary = ScoreSummary {
+        score: avg_score,
+        total_variants: variants_seen * sample_data.len() as u64,
+        matched_variants: sample_data.iter().map(|sd| sd.matched_variants).sum(),
+        dosage_sum: sample_data.iter().map(|sd| sd.dosage_sum).sum(),
+        // Sources report no imputation quality
+        effective_variants: None,
+        weight_coverage: mean_coverage(&sample_data),
+        // Sources are not filtered
+        filtered_records: None,
+        vcf_chr_format: vcf_chr_format.unwrap_or(false),
+        sites,
+        // Sources deal with their own malformed records; see `VcfSource::policy`
+        skipped: Vec::new(),
+    };
+
+    Ok(MultiSampleScores { sample_names, sample_data, summary, contributions: Vec::new() })
+}
+
+/// Wrap a VCF byte stream, transparently decompressing it if it starts with the gzip
+/// magic bytes (plain gzip and BGZF alike) or a zstd frame, and decoding it if it is a BCF. For inputs
+/// that aren't files: pipes, uploads, sockets.
+pub fn decompressed(reader: impl Read + 'static) -> io::Result<Box<dyn BufRead>> {
+    let capacity = resources::current().read_buffer;
+    let mut reader = BufReader::with_capacity(capacity, reader);
+    let header = reader.fill_buf()?;
+    let mut reader: Box<dyn BufRead> = if header.starts_with(&bgzf::GZIP_MAGIC) {
+        Box::new(BufReader::with_capacity(capacity, Gunzip::new(reader)))
+    } else if bgzf::is_zstd(header) {
+        Box::new(bgzf::unzstd(reader)?)
+    } else {
+        Box::new(reader)
+    };
+    if bcf::is_bcf(&mut reader)? {
+        return Ok(Box::new(bcf::Reader::new(reader)?));
+    }
+    Ok(reader)
+}
+
+/// `GenotypeSource` over a (optionally gzipped/bgzipped) VCF file.
+pub struct VcfSource {
+    path: String,
+    reader: Box<dyn BufRead>,
+    sample_names: Vec<String>,
+    /// Always additive: the model is applied by the scoring core, not the source
+    options: ScoreOptions,
+    policy: ErrorPolicy,
+    sort_check: SortCheck,
+    line: String,
+    line_number: usize,
+}
+
+impl VcfSource {
+    /// Open `path` and read its header. With `dosage`, genotypes come from FORMAT/DS instead of GT.
+    pub fn open(path: &str, dosage: bool) -> Result<Self> {
+        Self::from_reader(path, bgzf::open(path, &InputConfig::default())?, dosage)
+    }
+
+    /// Open the VCFs at `paths` as one, read one after another; see [`crate::concat`].
+    pub fn open_files(paths: &[String], dosage: bool) -> Result<Self> {
+        let input = VcfInput::files(paths.to_vec(), &InputConfig::default())?;
+        Self::from_reader(input.path(), input.open()?, dosage)
+    }
+
+    /// Read an already-decompressed VCF stream. `name` is only used in error messages.
+    pub fn from_reader(name: &str, reader: Box<dyn BufRead>, dosage: bool) -> Result<Self> {
+        let mut source = VcfSource {
+            path: name.to_string(),
+            reader,
+            sample_names: Vec::new(),
+            options: ScoreOptions { dosage, ..Default::default() },
+            policy: ErrorPolicy::new(OnError::Skip, None),
+            sort_check: SortCheck::new(Unsorted::Error),
+            line: String::new(),
+            line_number: 0,
+        };
+
+        loop {
+            if !source.read_line()? {
+                return Err(Error::invalid(name, "VCF header (#CHROM line) not found"));
+            }
+            if trim_line(&source.line).starts_with("#CHROM") {
+                source.sample_names = header_sample_names(&source.line);
+                dedupe_sample_names(&mut source.sample_names);
+                return Ok(source);
+            }
+        }
+    }
+
+    /// How malformed and out-of-order records are handled (default: skip malformed records
+    /// silently, abort at out-of-order ones).
+    pub fn with_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.sort_check = policy.sort_check();
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> &ErrorPolicy {
+        &self.policy
+    }
+
+    /// How NaN or infinite dosages are read (default: as missing).
+    pub fn non_finite(mut self, mode: NonFinite) -> Self {
+        self.options.non_finite = mode;
+        self
+    }
+
+    fn read_line(&mut self) -> Result<bool> {
+        self.line.clear();
+        self.line_number += 1;
+        let bytes = self
+            .reader
+            .read_line(&mut self.line)
+            .map_err(|e| Error::read(&self.path, self.line_number, e))?;
+        Ok(bytes > 0)
+    }
+
+    fn parse_variant(&self) -> std::result::Result<Variant, &'static str> {
+        let mut parts = self.line.trim_end().split('\t').map(str::trim);
+        let (Some(chr), Some(pos), Some(_id), Some(ref_allele), Some(alt_allele)) =
+            (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("Too few columns (no genotypes)");
+        };
+        if parts.nth(4).is_none() {
+            return Err("Too few columns (no genotypes)");
+        }
+        if parts.count() + 1 != self.sample_names.len() {
+            return Err("Genotype columns do not match the header's samples");
+        }
+        let pos = pos.parse::<u64>().map_err(|_| "Invalid numeric position")?;
+        Ok(Variant {
+            chr: aliases::canonical(chr).to_string(),
+            pos,
+            ref_allele: ref_allele.to_string(),
+            alt_allele: alt_allele.to_string(),
+            chr_prefixed: chr.starts_with("chr"),
+        })
+    }
+}
+
+impl GenotypeSource for VcfSource {
+    fn sample_names(&self) -> &[String] {
+        &self.sample_names
+    }
+
+    fn next_variant(&mut self) -> Result<Option<Variant>> {
+        loop {
+            if !self.read_line()? {
+                return Ok(None);
+            }
+            if self.line.starts_with('#') || self.line.trim_end().is_empty() {
+                continue;
+            }
+            if !self.policy.keeps(&self.path, self.line_number, self.line.trim_end())? {
+                continue;
+            }
+            match self.parse_variant() {
+                Ok(variant) => {
+                    let chr = self.line.split('\t').next().unwrap_or_default().trim();
+                    self.sort_check.check(&self.path, self.line_number, chr, variant.pos, &self.line)?;
+                    return Ok(Some(variant));
+                }
+                Err(message) => {
+                    self.policy.handle_record(Error::parse(&self.path, self.line_number, message, &self.line), &self.line)?;
+                }
+            }
+        }
+    }
+
+    fn alt_dosages(&mut self, out: &mut [Option<f64>]) -> Result<()> {
+        let mut parts = self.line.trim_end().split('\t');
+        let field_index = self.options.field_index(parts.nth(8).unwrap_or(""));
+
+        out.iter_mut().for_each(|slot| *slot = None);
+        let mut non_finite = false;
+        if let Some(field_index) = field_index {
+            for (slot, sample_field) in out.iter_mut().zip(parts) {
+                // Always additive here, so the count is the dosage
+                *slot = self.options.checked_effect_count(sample_field, field_index, true, &mut non_finite);
+            }
+        }
+        if non_finite {
+            self.policy.non_finite(self.options.non_finite, Error::parse(&self.path, self.line_number, "NaN or infinite dosage", &self.line), &self.line)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+//! Tracing spans for the stages of a run, compiled with `--features tracing`:
+//! `load_scoring_file`, `scan_vcf` (or `scan_source` for a custom genotype backend) and
+//! `write_output`, inside a `score` span for each scoring run.
+//!
+//! They are [`tracing`](https://docs.rs/tracing) spans, so an application embedding
+//! SpeedScore sees them in whatever subscriber it installs, such as `tracing-opentelemetry`
+//! to send them on to its OpenTelemetry collector. The counts a stage produces (variants,
+//! samples, matches) are recorded on its span before it closes. Without the feature, the
+//! spans compile to nothing.
+
+/// A stage of a run, traced from its creation until it is dropped.
+pub struct Stage {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+/// A [`Stage`] entering an info-level span `$name` with the fields given, and further
+/// fields to be recorded with [`Stage::record`].
+macro_rules! stage {
+    ($name:literal, $($field:ident = $value:expr),* ; $($later:ident),*) => {{
+        #[cfg(feature = "tracing")]
+        let stage = Stage { span: tracing::info_span!($name, $($field = $value,)* $($later = tracing::field::Empty),*).entered() };
+        #[cfg(not(feature = "tracing"))]
+        let stage = {
+            $(let _ = $value;)*
+            Stage {}
+        };
+        stage
+    }};
+}
+
+impl Stage {
+    /// One scoring run, of the VCF at `vcf`.
+    pub fn score(vcf: &str) -> Self {
+        stage!("score", vcf = vcf;)
+    }
+
+    /// Reading a scoring file; records `variants`, the scoring variants loaded.
+    pub fn load_scoring_file(path: &str) -> Self {
+        stage!("load_scoring_file", path = path; variants)
+    }
+
+    /// One pass over a VCF; records `samples`, `variants` (the records read) and `matched`
+    /// (the records matching a scoring variant).
+    pub fn scan_vcf(path: &str) -> Self {
+        stage!("scan_vcf", path = path; samples, variants, matched)
+    }
+
+    /// Reading a custom genotype backend; records the same as [`Stage::scan_vcf`].
+    pub fn scan_source() -> Self {
+        stage!("scan_source", ; samples, variants, matched)
+    }
+
+    /// Writing the results; records `samples`.
+    pub fn write_output() -> Self {
+        stage!("write_output", ; samples)
+    }
+
+    /// Record `value` as the span's `field`, one of those its constructor lists.
+    pub fn record(&self, field: &'static str, value: u64) {
+        #[cfg(feature = "tracing")]
+        self.span.record(field, value);
+        #[cfg(not(feature = "tracing"))]
+        let _ = (field, value);
+    }
+}
@@ -0,0 +1,289 @@
+//! The small amount of regression and distribution code that ancestry adjustment and
+//! `speedscore evaluate` need, kept in-tree rather than pulling in a linear-algebra stack.
+//! Design matrices are row-major, one `Vec` per observation, and include any intercept.
+
+/// Ordinary least-squares fit.
+pub(crate) struct LinearFit {
+    pub coefficients: Vec<f64>,
+    pub standard_errors: Vec<f64>,
+    /// Fraction of the variance of `y` around its mean that the fit explains
+    pub r2: f64,
+}
+
+/// Logistic regression fit by iteratively reweighted least squares.
+pub(crate) struct LogisticFit {
+    pub coefficients: Vec<f64>,
+    pub standard_errors: Vec<f64>,
+    pub log_likelihood: f64,
+}
+
+/// Regress `y` on the columns of `x`. `None` if `x` is rank-deficient or has no more
+/// rows than columns.
+pub(crate) fn least_squares(x: &[Vec<f64>], y: &[f64]) -> Option<LinearFit> {
+    let p = x.first()?.len();
+    if x.len() <= p {
+        return None;
+    }
+    let weights = vec![1.0; y.len()];
+    let (xtx, xty) = normal_equations(x, y, &weights);
+    let coefficients = solve(xtx.clone(), xty)?;
+    let rss: f64 = x.iter().zip(y).map(|(row, &y)| (y - dot(row, &coefficients)).powi(2)).sum();
+    let mean = y.iter().sum::<f64>() / y.len() as f64;
+    let tss: f64 = y.iter().map(|y| (y - mean).powi(2)).sum();
+    let sigma2 = rss / (x.len() - p) as f64;
+    let standard_errors = inverse_diagonal(&xtx)?.iter().map(|d| (d * sigma2).sqrt()).collect();
+    Some(LinearFit { coefficients, standard_errors, r2: if tss > 0.0 { 1.0 - rss / tss } else { 0.0 } })
+}
+
+/// Logistic regression of 0/1 outcomes `y` on `x`. `None` if the fit does not converge,
+/// e.g. under complete separation, or `x` is rank-deficient.
+pub(crate) fn logistic(x: &[Vec<f64>], y: &[f64]) -> Option<LogisticFit> {
+    let p = x.first()?.len();
+    let mut beta = vec![0.0; p];
+    let mut log_likelihood = f64::NEG_INFINITY;
+    for _ in 0..100 {
+        let mu: Vec<f64> = x.iter().map(|row| sigmoid(dot(row, &beta))).collect();
+        let weights: Vec<f64> = mu.iter().map(|m| (m * (1.0 - m)).max(1e-12)).collect();
+        // Working response of the IRLS step: eta + (y - mu) / w
+        let working: Vec<f64> = x
+            .iter()
+            .zip(y)
+            .zip(&mu)
+            .zip(&weights)
+            .map(|(((row, y), m), w)| dot(row, &beta) + (y - m) / w)
+            .collect();
+        let (xtwx, xtwz) = normal_equations(x, &working, &weights);
+        beta = solve(xtwx, xtwz)?;
+
+        let updated = log_likelihood_at(x, y, &beta);
+        let converged = (updated - log_likelihood).abs() < 1e-10 * (1.0 + updated.abs());
+        log_likelihood = updated;
+        if converged {
+            let weights: Vec<f64> = x.iter().map(|row| sigmoid(dot(row, &beta))).map(|m| m * (1.0 - m)).collect();
+            let (information, _) = normal_equations(x, y, &weights);
+            let standard_errors = inverse_diagonal(&information)?.iter().map(|d| d.sqrt()).collect();
+            return Some(LogisticFit { coefficients: beta, standard_errors, log_likelihood });
+        }
+    }
+    None
+}
+
+fn log_likelihood_at(x: &[Vec<f64>], y: &[f64], beta: &[f64]) -> f64 {
+    x.iter()
+        .zip(y)
+        .map(|(row, &y)| {
+            let eta = dot(row, beta);
+            // log(1 + e^eta) without overflow
+            let log1p_exp = if eta > 0.0 { eta + (-eta).exp().ln_1p() } else { eta.exp().ln_1p() };
+            y * eta - log1p_exp
+        })
+        .sum()
+}
+
+fn sigmoid(eta: f64) -> f64 {
+    1.0 / (1.0 + (-eta).exp())
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// `X'WX` and `X'Wy` for diagonal weights `w`.
+fn normal_equations(x: &[Vec<f64>], y: &[f64], w: &[f64]) -> (Vec<Vec<f64>>, Vec<f64>) {
+    let p = x.first().map_or(0, Vec::len);
+    let mut xtx = vec![vec![0.0; p]; p];
+    let mut xty = vec![0.0; p];
+    for ((row, &y), &w) in x.iter().zip(y).zip(w) {
+        for (i, &xi) in row.iter().enumerate() {
+            xty[i] += w * xi * y;
+            for (cell, &xj) in xtx[i].iter_mut().zip(row) {
+                *cell += w * xi * xj;
+            }
+        }
+    }
+    (xtx, xty)
+}
+
+/// Diagonal of `a`'s inverse, one column solve at a time.
+fn inverse_diagonal(a: &[Vec<f64>]) -> Option<Vec<f64>> {
+    (0..a.len())
+        .map(|i| {
+            let unit = (0..a.len()).map(|j| if i == j { 1.0 } else { 0.0 }).collect();
+            solve(a.to_vec(), unit).map(|column| column[i])
+        })
+        .collect()
+}
+
+/// Solve `a x = b` by Gaussian elimination with partial pivoting; `None` if `a` is singular.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    let scale = a.iter().flatten().fold(0.0f64, |max, v| max.max(v.abs()));
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() <= scale * 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col].clone();
+        for row in col + 1..n {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, pivot) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let rest: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - rest) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Pearson correlation of `x` and `y`. `None` with fewer than three pairs or when either
+/// is constant.
+pub(crate) fn pearson(x: &[f64], y: &[f64]) -> Option<f64> {
+    let n = x.len() as f64;
+    if x.len() < 3 {
+        return None;
+    }
+    let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y) {
+        sxx += (a - mx) * (a - mx);
+        syy += (b - my) * (b - my);
+        sxy += (a - mx) * (b - my);
+    }
+    (sxx > 0.0 && syy > 0.0).then(|| sxy / (sxx * syy).sqrt())
+}
+
+/// Standard normal density.
+pub(crate) fn normal_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+/// Two-sided p-value of a standard normal statistic.
+pub(crate) fn normal_two_sided_p(z: f64) -> f64 {
+    erfc(z.abs() / std::f64::consts::SQRT_2)
+}
+
+/// Standard normal CDF.
+pub(crate) fn normal_cdf(z: f64) -> f64 {
+    0.5 * erfc(-z / std::f64::consts::SQRT_2)
+}
+
+/// Complementary error function (Numerical Recipes' Chebyshev fit, relative error below 1.2e-7).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let value = t * poly.exp();
+    if x >= 0.0 {
+        value
+    } else {
+        2.0 - value
+    }
+}
+
+/// Inverse of the standard normal CDF (Acklam's rational approximation, relative error
+/// below 1.2e-9), for `p` in (0, 1).
+pub(crate) fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e1, 2.209460984245205e2, -2.759285104469687e2, 1.38357751867269e2, -3.066479806614716e1, 2.506628277459239];
+    const B: [f64; 5] = [-5.447609879822406e1, 1.615858368580409e2, -1.556989798598866e2, 6.680131188771972e1, -1.328068155288572e1];
+    const C: [f64; 6] = [-7.784894002430293e-3, -3.223964580411365e-1, -2.400758277161838, -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < 0.02425 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.02425 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Two-sided p-value of a Student t statistic with `df` degrees of freedom.
+pub(crate) fn t_two_sided_p(t: f64, df: f64) -> f64 {
+    incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// Regularized incomplete beta function I_x(a, b), by continued fraction.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // The continued fraction converges quickly only below the mean; use the symmetry otherwise
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction for the incomplete beta function (modified Lentz's method).
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        for numerator in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + numerator * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + numerator / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    h
+}
+
+/// Natural log of the gamma function (Lanczos approximation), for positive `x`.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |sum, (i, c)| sum + c / (x + 1.0 + i as f64));
+    (x + 0.5) * tmp.ln() - tmp + (2.5066282746310005 * series / x).ln()
+}
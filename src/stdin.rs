@@ -0,0 +1,13 @@
+//! Reading the VCF from standard input (`--vcf -`), as at the end of a `bcftools view` pipe.
+//!
+//! Standard input can be read only once, so a run opens it once, as a
+//! [`VcfInput`](crate::input::VcfInput): the checks before scoring look at its header, and
+//! the scan reads on from the same reader.
+
+/// The `--vcf` that stands for standard input.
+pub const PATH: &str = "-";
+
+/// Whether `path` stands for standard input.
+pub fn is_stdin(path: &str) -> bool {
+    path == PATH
+}
@@ -0,0 +1,89 @@
+//! Reading inputs straight from where they are stored, as they download, rather than from
+//! a local copy: `s3://bucket/key` and `gs://bucket/key` always, and `http://` or
+//! `https://` URLs with `--stream`, which are otherwise downloaded into the cache of
+//! [`crate::remote`] first.
+//!
+//! A bucket object is read through the provider's own command-line tool, `aws s3 cp` for
+//! S3 and `gcloud storage cat` for Google Cloud Storage, writing it to a pipe (a run checks
+//! first that the tool is installed; see [`Provider::check_tool`]); a URL is
+//! read with an HTTP request that resumes with a range request at the byte a dropped
+//! connection stopped at. Nothing is written to disk. A streamed VCF is opened once, as a
+//! [`VcfInput`](crate::input::VcfInput): the checks before scoring read its header, and
+//! the scan reads on from the same download. An htsget dataset is streamed as well; see
+//! [`crate::htsget`].
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Stdio};
+use crate::error::{IoContext, Result};
+use crate::htsget;
+use crate::input::InputConfig;
+use crate::remote;
+use crate::upload::Provider;
+
+/// Whether `path` is read as it downloads: a bucket object, the htsget endpoint, or with
+/// `--stream` a URL.
+pub fn is_streamed(path: &str, config: &InputConfig) -> bool {
+    Provider::of(path).is_some() || htsget::is_endpoint(path, config) || (config.stream && remote::is_url(path))
+}
+
+/// The file at `path`, or the stream of the object or URL it names if [`is_streamed`].
+pub fn open(path: &str, config: &InputConfig) -> Result<Box<dyn Read + Send>> {
+    if !is_streamed(path, config) {
+        return Ok(Box::new(File::open(path).with_path(path)?));
+    }
+    match Provider::of(path) {
+        Some(provider) => Ok(Box::new(Download::start(provider, path)?)),
+        None if htsget::is_endpoint(path, config) => htsget::stream(path),
+        None => remote::stream(path),
+    }
+}
+
+/// Why the provider's tool `program` could not be started.
+pub(crate) fn tool_error(program: &str, e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(e.kind(), format!("reading from a bucket needs `{}` on the PATH", program)),
+        _ => e,
+    }
+}
+
+/// A bucket object being written to the standard output of the provider's tool.
+struct Download {
+    child: Child,
+    stdout: ChildStdout,
+    program: &'static str,
+}
+
+impl Download {
+    fn start(provider: Provider, url: &str) -> Result<Self> {
+        let (program, mut command) = provider.read_command(url);
+        let spawned = command.stdin(Stdio::null()).stdout(Stdio::piped()).spawn();
+        let mut child = spawned.map_err(|e| tool_error(program, e)).with_path(url)?;
+        let stdout = child.stdout.take().expect("the tool's output is piped");
+        Ok(Download { child, stdout, program })
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        // The object ends where the tool does, which has to have succeeded
+        if read == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("`{}` failed ({})", self.program, status)));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Download {
+    /// A reader dropped before the end, having read what it needed, stops the download.
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,101 @@
+//! Spec-level validation of VCF records with noodles-vcf (`--strict-vcf`).
+//!
+//! The scan's own parser reads only the columns a score needs, and tolerates much that the
+//! VCF spec does not: a bad QUAL, an INFO value of the wrong type, an undeclared FORMAT
+//! key. In strict mode the header is parsed by noodles, and every record is parsed in full
+//! against it before it is scored. Records that fail are malformed records, handled as
+//! `--on-error` says. Expect the scan to take several times as long.
+
+use std::io::{self, BufRead};
+use noodles_vcf::variant::record::samples::Sample as _;
+use noodles_vcf::variant::record::{AlternateBases as _, Filters as _, Info as _, ReferenceBases as _, Samples as _};
+use noodles_vcf::variant::record_buf::info::field::Value as InfoValue;
+use noodles_vcf::variant::record_buf::samples::sample::Value as SampleValue;
+use noodles_vcf::{variant, Header, Record};
+use crate::error::{Error, Result};
+
+/// The parsed header of one VCF, to check its records against.
+pub struct Validator {
+    header: Header,
+}
+
+impl Validator {
+    /// Read and parse the header of the VCF at `path` from `reader`.
+    pub fn load(path: &str, mut reader: impl BufRead) -> Result<Self> {
+        let mut text = String::new();
+        let mut line = String::new();
+        let mut line_number = 0;
+        loop {
+            line.clear();
+            line_number += 1;
+            if reader.read_line(&mut line).map_err(|e| Error::read(path, line_number, e))? == 0 || !line.starts_with('#') {
+                break;
+            }
+            let header_line = line.trim_end_matches(['\r', '\n']).trim_start_matches('\u{feff}');
+            text.push_str(header_line);
+            text.push('\n');
+            if header_line.starts_with("#CHROM") {
+                break;
+            }
+        }
+        let header = text.parse().map_err(|e| Error::invalid(path, format!("header is not valid VCF: {}", e)))?;
+        Ok(Validator { header })
+    }
+
+    /// Parse `record`, one data line, in full against the header; `Err` says which field is
+    /// not valid VCF and why. INFO, FORMAT and FILTER keys must be declared in the header,
+    /// and there must be a genotype column per header sample.
+    pub fn check(&self, record: &str) -> std::result::Result<(), String> {
+        let record = Record::try_from(record.as_bytes()).map_err(|e| format!("Not valid VCF: {}", e))?;
+        let undeclared = |field: &str, key: &str| format!("Not valid VCF: {} key '{}' is not declared in the header", field, key);
+        let header = &self.header;
+
+        record.variant_start().transpose().map_err(invalid("POS"))?;
+        for base in variant::Record::reference_bases(&record).iter() {
+            base.map_err(invalid("REF"))?;
+        }
+        for allele in variant::Record::alternate_bases(&record).iter() {
+            allele.map_err(invalid("ALT"))?;
+        }
+        record.quality_score().transpose().map_err(invalid("QUAL"))?;
+        for filter in variant::Record::filters(&record).iter(header) {
+            let filter = filter.map_err(invalid("FILTER"))?;
+            if filter != "PASS" && !header.filters().contains_key(filter) {
+                return Err(undeclared("FILTER", filter));
+            }
+        }
+        for field in variant::Record::info(&record).iter(header) {
+            let (key, value) = field.map_err(invalid("INFO"))?;
+            if !header.infos().contains_key(key) {
+                return Err(undeclared("INFO", key));
+            }
+            if let Some(value) = value {
+                let _: InfoValue = value.try_into().map_err(invalid(&format!("INFO/{}", key)))?;
+            }
+        }
+        let samples = variant::Record::samples(&record).map_err(invalid("FORMAT"))?;
+        for key in samples.column_names(header) {
+            let key = key.map_err(invalid("FORMAT"))?;
+            if !header.formats().contains_key(key) {
+                return Err(undeclared("FORMAT", key));
+            }
+        }
+        if samples.len() != header.sample_names().len() {
+            return Err(format!("Not valid VCF: {} genotype columns for {} header samples", samples.len(), header.sample_names().len()));
+        }
+        for sample in samples.iter() {
+            for field in sample.iter(header) {
+                let (key, value) = field.map_err(invalid("FORMAT"))?;
+                if let Some(value) = value {
+                    let _: SampleValue = value.try_into().map_err(invalid(&format!("FORMAT/{}", key)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Turns noodles' error for `field` into a message naming it.
+fn invalid(field: &str) -> impl Fn(io::Error) -> String + '_ {
+    move |e| format!("Not valid VCF: {}: {}", field, e)
+}
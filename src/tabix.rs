@@ -0,0 +1,513 @@
+//! Reading only the parts of a bgzipped VCF that can hold scoring variants, through its
+//! tabix (`.tbi`) or CSI (`.csi`) index.
+//!
+//! Both kinds of index sort the records into bins of positions, and list for each bin the
+//! chunks of the file, as BGZF virtual offsets, that its records lie in. The chunks of the
+//! bins around each scoring variant are merged and read in file order after the header,
+//! and handed to the scan as if they were the whole file. Records near a scoring variant
+//! come along with it, as the index cannot single out records.
+//!
+//! The whole file is scanned instead when there is no index, when the index is older than
+//! the VCF, or when the chunks span most of the file anyway: a full scan inflates blocks
+//! in parallel, while chunks are read one block at a time.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use flate2::read::MultiGzDecoder;
+use crate::aliases;
+use crate::bgzf;
+use crate::error::{Error, IoContext, Result};
+use crate::input::InputConfig;
+
+/// Share of the VCF's compressed bytes above which the chunks are not worth seeking to.
+const MAX_SPAN: f64 = 0.5;
+
+/// A tabix or CSI index.
+pub struct Index {
+    /// Sequence names as [`aliases::canonical`] gives them, in index order; `None` for a
+    /// CSI index without them (one not made for a VCF)
+    pub names: Option<Vec<String>>,
+    min_shift: u32,
+    depth: u32,
+    references: Vec<Reference>,
+}
+
+/// The index of one sequence.
+#[derive(Default)]
+struct Reference {
+    /// Each bin's chunks, as `[start, end)` virtual offsets
+    bins: HashMap<u32, Vec<(u64, u64)>>,
+    /// Virtual offset of the first record in each window of `1 << min_shift` positions
+    /// (.tbi only)
+    linear: Vec<u64>,
+    /// Virtual offset of the first record in each bin (.csi only)
+    first_offsets: HashMap<u32, u64>,
+}
+
+/// Little-endian fields read one after another from an inflated index.
+struct Fields<'a> {
+    path: &'a str,
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Fields<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8]> {
+        let field = self.bytes.get(self.at..self.at + length).ok_or_else(|| Error::invalid(self.path, "not a tabix or CSI index: it ends early"))?;
+        self.at += length;
+        Ok(field)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("four bytes")))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().expect("eight bytes")))
+    }
+
+    /// A count or length, which the formats store as a signed 32-bit integer.
+    fn count(&mut self) -> Result<usize> {
+        let value = i32::from_le_bytes(self.take(4)?.try_into().expect("four bytes"));
+        usize::try_from(value).map_err(|_| Error::invalid(self.path, format!("not a tabix or CSI index: negative count {}", value)))
+    }
+
+    /// The six fields of the tabix header, then the sequence names.
+    fn names(&mut self) -> Result<Vec<String>> {
+        self.take(24)?;
+        let length = self.count()?;
+        Ok(self
+            .take(length)?
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| aliases::canonical(&String::from_utf8_lossy(name)).to_string())
+            .collect())
+    }
+}
+
+impl Index {
+    pub fn read(path: &str) -> Result<Self> {
+        let mut bytes = Vec::new();
+        MultiGzDecoder::new(File::open(path).with_path(path)?).read_to_end(&mut bytes).with_path(path)?;
+        let mut fields = Fields { path, bytes: &bytes, at: 4 };
+        let csi = match bytes.get(..4) {
+            Some(b"TBI\x01") => false,
+            Some(b"CSI\x01") => true,
+            _ => return Err(Error::invalid(path, "not a tabix or CSI index")),
+        };
+        let (min_shift, depth, names) = if csi {
+            let min_shift = fields.u32()?;
+            let depth = fields.u32()?;
+            let aux_length = fields.count()?;
+            let aux_end = fields.at + aux_length;
+            // The auxiliary data is the tabix header when the index was made for a VCF
+            let names = (aux_length >= 28).then(|| fields.names()).transpose()?;
+            fields.at = aux_end;
+            (min_shift, depth, names)
+        } else {
+            (14, 5, None)
+        };
+        if min_shift > 32 || depth > 10 {
+            return Err(Error::invalid(path, format!("not a tabix or CSI index: unsupported bin layout (min_shift {}, depth {})", min_shift, depth)));
+        }
+        let reference_count = fields.count()?;
+        let names = match names {
+            Some(names) => Some(names),
+            None if !csi => Some(fields.names()?),
+            None => None,
+        };
+        // Holds metadata, not chunks
+        let pseudo_bin = bin_offset(depth + 1) + 1;
+
+        let mut references = Vec::with_capacity(reference_count);
+        for _ in 0..reference_count {
+            let mut reference = Reference::default();
+            for _ in 0..fields.count()? {
+                let bin = fields.u32()?;
+                if csi {
+                    let first_offset = fields.u64()?;
+                    reference.first_offsets.insert(bin, first_offset);
+                }
+                let chunk_count = fields.count()?;
+                let mut chunks = Vec::with_capacity(chunk_count);
+                for _ in 0..chunk_count {
+                    chunks.push((fields.u64()?, fields.u64()?));
+                }
+                if bin != pseudo_bin {
+                    reference.bins.insert(bin, chunks);
+                }
+            }
+            if !csi {
+                let windows = fields.count()?;
+                reference.linear = (0..windows).map(|_| fields.u64()).collect::<Result<_>>()?;
+            }
+            references.push(reference);
+        }
+        Ok(Index { names, min_shift, depth, references })
+    }
+
+    /// Add the `[start, end)` virtual-offset ranges holding the records of sequence
+    /// `reference` at the 1-based `positions` to `chunks`, unmerged.
+    fn chunks(&self, reference: usize, positions: &BTreeSet<u64>, chunks: &mut Vec<(u64, u64)>) {
+        let Some(reference) = self.references.get(reference) else {
+            return;
+        };
+        let windows: BTreeSet<u64> = positions.iter().map(|&pos| pos.saturating_sub(1) >> self.min_shift).collect();
+        for window in windows {
+            let bins = (0..=self.depth).map(|level| bin_offset(level) + (window >> (3 * (self.depth - level))) as u32);
+            // Chunks ending before the window's first record cannot hold its records
+            let first = match reference.linear.is_empty() {
+                false => reference.linear.get(window as usize).or(reference.linear.last()).copied().unwrap_or(0),
+                true => bins.clone().rev().find_map(|bin| reference.first_offsets.get(&bin).copied()).unwrap_or(0),
+            };
+            for bin in bins {
+                let bin_chunks = reference.bins.get(&bin).map_or(&[][..], Vec::as_slice);
+                chunks.extend(bin_chunks.iter().filter(|&&(_, end)| end > first));
+            }
+        }
+    }
+}
+
+/// Number of the first bin at `level` (0 is the root), in the binning scheme both formats share.
+fn bin_offset(level: u32) -> u32 {
+    ((1u64 << (3 * level)) - 1) as u32 / 7
+}
+
+/// The index next to `path`, if it has one.
+pub fn find(path: &str) -> Option<String> {
+    ["tbi", "csi"].iter().map(|ext| format!("{}.{}", path, ext)).find(|index| fs::metadata(index).is_ok())
+}
+
+/// A VCF read through its index.
+pub struct Indexed {
+    /// The header, then the records of the chunks around the scoring variants
+    pub reader: Box<dyn BufRead + Send>,
+    pub index: String,
+    /// Compressed bytes the chunks span, and the whole file's size
+    pub span: u64,
+    pub file_size: u64,
+}
+
+/// Open the bgzipped VCF at `path` to read only the records around `positions`, through
+/// its index. `None` when the whole file is to be scanned instead, as with `--no-index`; see
+/// the module docs.
+pub fn open<'a>(path: &str, positions: impl IntoIterator<Item = &'a (String, u64)>, config: &InputConfig) -> Result<Option<Indexed>> {
+    if config.no_index || config.htslib {
+        return Ok(None);
+    }
+    let Some(index_path) = find(path) else {
+        return Ok(None);
+    };
+    let modified = |file: &str| fs::metadata(file).and_then(|m| m.modified()).ok();
+    if let (Some(vcf_time), Some(index_time)) = (modified(path), modified(&index_path)) {
+        if index_time < vcf_time {
+            eprintln!("Warning: {} is older than {}; scanning the whole VCF instead of using it.", index_path, path);
+            return Ok(None);
+        }
+    }
+    let index = Index::read(&index_path)?;
+    let Some(names) = &index.names else {
+        return Ok(None);
+    };
+
+    let mut wanted: HashMap<&str, BTreeSet<u64>> = HashMap::new();
+    for (chr, pos) in positions {
+        wanted.entry(chr.as_str()).or_default().insert(*pos);
+    }
+    let mut chunks = Vec::new();
+    for (reference, name) in names.iter().enumerate() {
+        if let Some(positions) = wanted.get(name.as_str()) {
+            index.chunks(reference, positions, &mut chunks);
+        }
+    }
+    let ranges = merge(chunks);
+
+    let file_size = fs::metadata(path).with_path(path)?.len();
+    // Each range also reads the block it ends in, which is at most 64 KiB compressed and
+    // ends before the next range or the file does
+    let next_starts = ranges.iter().skip(1).map(|&(start, _)| start >> 16).chain([file_size]);
+    let span: u64 = ranges
+        .iter()
+        .zip(next_starts)
+        .map(|(&(start, end), next)| (end >> 16) - (start >> 16) + (1 << 16).min(next.saturating_sub(end >> 16)))
+        .sum::<u64>()
+        .min(file_size);
+    if span as f64 > MAX_SPAN * file_size as f64 {
+        return Ok(None);
+    }
+
+    let mut file = File::open(path).with_path(path)?;
+    let header = read_header(path, &mut file)?;
+    let limit = header.len();
+    let reader = Chunks { file, ranges: ranges.into_iter(), end: 0, next: None, loaded: None, data: header, pos: 0, limit };
+    Ok(Some(Indexed { reader: Box::new(reader), index: index_path, span, file_size }))
+}
+
+/// Sort chunks into file order and merge the ones that overlap or touch.
+fn merge(mut chunks: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    chunks.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(chunks.len());
+    for (start, end) in chunks {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// The header lines, up to and including `#CHROM`, from the start of the file.
+fn read_header(path: &str, file: &mut File) -> Result<Vec<u8>> {
+    let mut header = Vec::new();
+    let mut offset = 0;
+    // Where the line being looked at starts
+    let mut line_start = 0;
+    loop {
+        let Some(block) = bgzf::next_block(file, offset).with_path(path)? else {
+            return Err(Error::invalid(path, "VCF header (#CHROM line) not found"));
+        };
+        header.extend(bgzf::inflate(&block, offset).with_path(path)?);
+        offset += block.len() as u64;
+        while let Some(length) = header[line_start..].iter().position(|&b| b == b'\n') {
+            let line_end = line_start + length + 1;
+            if header[line_start..].starts_with(b"#CHROM") {
+                header.truncate(line_end);
+                return Ok(header);
+            }
+            if !header[line_start..].starts_with(b"#") {
+                return Err(Error::invalid(path, "VCF header (#CHROM line) not found"));
+            }
+            line_start = line_end;
+        }
+    }
+}
+
+/// Reads the given virtual-offset ranges of a BGZF file, one after another.
+struct Chunks {
+    file: File,
+    ranges: std::vec::IntoIter<(u64, u64)>,
+    /// End of the range being read
+    end: u64,
+    /// Where in the range to go on from: a block's file offset, and where in it to start
+    next: Option<(u64, usize)>,
+    /// File offset and compressed size of the block in `data`
+    loaded: Option<(u64, u64)>,
+    data: Vec<u8>,
+    pos: usize,
+    limit: usize,
+}
+
+impl Chunks {
+    fn load(&mut self, offset: u64) -> io::Result<()> {
+        if self.loaded.is_some_and(|(loaded, _)| loaded == offset) {
+            return Ok(());
+        }
+        self.file.seek(SeekFrom::Start(offset))?;
+        let block = bgzf::next_block(&mut self.file, offset)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("the index points past the end of the file, to byte {}", offset)))?;
+        self.data = bgzf::inflate(&block, offset)?;
+        self.loaded = Some((offset, block.len() as u64));
+        Ok(())
+    }
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Chunks {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos >= self.limit {
+            let (offset, start) = match self.next.take() {
+                Some(next) => next,
+                None => match self.ranges.next() {
+                    Some((start, end)) => {
+                        self.end = end;
+                        (start >> 16, (start & 0xffff) as usize)
+                    }
+                    None => return Ok(&[]),
+                },
+            };
+            let (end_offset, end_within) = (self.end >> 16, (self.end & 0xffff) as usize);
+            if offset > end_offset || (offset == end_offset && start >= end_within) {
+                continue;
+            }
+            self.load(offset)?;
+            let (_, size) = self.loaded.expect("a block was just loaded");
+            self.pos = start.min(self.data.len());
+            if offset == end_offset {
+                self.limit = end_within.min(self.data.len());
+            } else {
+                self.limit = self.data.len();
+                self.next = Some((offset + size, 0));
+            }
+        }
+        Ok(&self.data[self.pos..self.limit])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+    use super::*;
+    use crate::scorer::{ScoreResults, Scorer};
+
+    /// The same VCF twice, bgzipped in 1000-byte blocks, as `tbi.vcf.gz` with a tabix index
+    /// and as `csi.vcf.gz` with a CSI index of `min_shift` 10; and scoring weights for it,
+    /// on chromosome 2 at the end of the file, as a file this small is otherwise scanned whole.
+    const DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tabix");
+
+    /// A copy of the fixture `kind` (`tbi` or `csi`) and its index in a directory of its own,
+    /// the index made a minute after the VCF, or before it if `stale`.
+    fn fixture(kind: &str, stale: bool) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("speedscore-tabix-{}-{}-{}", kind, stale, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let vcf = dir.join(format!("{}.vcf.gz", kind));
+        let index = dir.join(format!("{}.vcf.gz.{}", kind, kind));
+        fs::copy(format!("{}/{}.vcf.gz", DATA, kind), &vcf).unwrap();
+        fs::copy(format!("{}/{}.vcf.gz.{}", DATA, kind, kind), &index).unwrap();
+        let made = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let indexed = if stale { made - Duration::from_secs(60) } else { made + Duration::from_secs(60) };
+        File::options().write(true).open(&vcf).unwrap().set_modified(made).unwrap();
+        File::options().write(true).open(&index).unwrap().set_modified(indexed).unwrap();
+        vcf
+    }
+
+    fn weights() -> String {
+        format!("{}/weights.txt", DATA)
+    }
+
+    fn positions() -> Vec<(String, u64)> {
+        let weights = fs::read_to_string(weights()).unwrap();
+        weights.lines().skip(1).map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            (fields[0].to_string(), fields[1].parse().unwrap())
+        }).collect()
+    }
+
+    fn inflated(path: &str) -> String {
+        let mut text = String::new();
+        MultiGzDecoder::new(File::open(path).unwrap()).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn indexed_reads_hold_the_records_at_the_positions_and_skip_others() {
+        for kind in ["tbi", "csi"] {
+            let vcf = fixture(kind, false);
+            let path = vcf.to_str().unwrap();
+            let positions = positions();
+            let Indexed { mut reader, index, span, file_size } = open(path, &positions, &InputConfig::default()).unwrap().expect("the index is used");
+            assert!(index.ends_with(kind));
+            assert!(span < file_size / 2, "{} of {}", span, file_size);
+            let mut text = String::new();
+            reader.read_to_string(&mut text).unwrap();
+            let whole = inflated(path);
+            fs::remove_dir_all(vcf.parent().unwrap()).unwrap();
+
+            let columns = whole.find("#CHROM").unwrap();
+            let header_length = columns + whole[columns..].find('\n').unwrap() + 1;
+            assert_eq!(text[..header_length], whole[..header_length]);
+            // Whole records, in file order, however the chunks cut through blocks
+            let all: Vec<&str> = whole[header_length..].lines().collect();
+            let read: Vec<&str> = text[header_length..].lines().collect();
+            let at: Vec<usize> = read.iter().map(|record| all.iter().position(|line| line == record).expect("a whole record")).collect();
+            assert!(at.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(read.len() < all.len() / 2, "{} of {} records read", read.len(), all.len());
+            for (chr, pos) in &positions {
+                let in_vcf = |records: &[&str]| records.iter().any(|record| record.starts_with(&format!("{}\t{}\t", chr, pos)));
+                assert_eq!(in_vcf(&read), in_vcf(&all), "{}:{}", chr, pos);
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_and_full_scan_scores_are_the_same() {
+        let scores = |results: &ScoreResults| results.samples.iter().map(|sample| (sample.name.clone(), sample.score)).collect::<Vec<_>>();
+        for kind in ["tbi", "csi"] {
+            let vcf = fixture(kind, false);
+            let path = vcf.to_str().unwrap();
+            let scorer = Scorer::from_scoring_file(&weights()).unwrap();
+            let indexed = scorer.run(path).unwrap();
+            let scanned = scorer.input_config(InputConfig { no_index: true, ..InputConfig::default() }).run(path).unwrap();
+            fs::remove_dir_all(vcf.parent().unwrap()).unwrap();
+            assert_eq!(scores(&indexed), scores(&scanned));
+            assert!(scores(&indexed).iter().any(|(_, score)| *score != 0.0));
+            assert_eq!(indexed.summary.matched_variants, scanned.summary.matched_variants);
+            assert!(indexed.summary.total_variants < scanned.summary.total_variants);
+        }
+    }
+
+    #[test]
+    fn an_index_older_than_the_vcf_is_not_used() {
+        let vcf = fixture("tbi", true);
+        let indexed = open(vcf.to_str().unwrap(), &positions(), &InputConfig::default()).unwrap();
+        fs::remove_dir_all(vcf.parent().unwrap()).unwrap();
+        assert!(indexed.is_none());
+    }
+
+    #[test]
+    fn csi_bin_layout_is_read_from_the_index() {
+        let tbi = Index::read(&format!("{}/tbi.vcf.gz.tbi", DATA)).unwrap();
+        let csi = Index::read(&format!("{}/csi.vcf.gz.csi", DATA)).unwrap();
+        assert_eq!((tbi.min_shift, tbi.depth), (14, 5));
+        assert_eq!((csi.min_shift, csi.depth), (10, 7));
+        for index in [&tbi, &csi] {
+            assert_eq!(index.names.as_deref(), Some(&["1".to_string(), "2".to_string()][..]));
+            assert_eq!(index.references.len(), 2);
+        }
+        assert!(tbi.references.iter().all(|reference| !reference.linear.is_empty() && reference.first_offsets.is_empty()));
+        assert!(csi.references.iter().all(|reference| reference.linear.is_empty() && !reference.first_offsets.is_empty()));
+    }
+
+    #[test]
+    fn chunks_are_those_of_the_windows_bins_after_the_linear_offset() {
+        // Window 1 of a tabix index lies in bins 0, 1, 9, 73, 585 and 4682
+        let reference = Reference {
+            bins: [(0, vec![(10, 120), (50, 400)]), (4681, vec![(0, 100)]), (4682, vec![(200, 300)]), (585, vec![(500, 600)])].into(),
+            linear: vec![0, 150],
+            first_offsets: HashMap::new(),
+        };
+        let index = Index { names: None, min_shift: 14, depth: 5, references: vec![reference] };
+        let mut chunks = Vec::new();
+        index.chunks(0, &[16385, 20000].into(), &mut chunks);
+        chunks.sort_unstable();
+        assert_eq!(chunks, [(50, 400), (200, 300), (500, 600)]);
+        // A sequence the index has no entry for has no chunks
+        index.chunks(1, &[1].into(), &mut chunks);
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn merge_sorts_and_joins_chunks_that_overlap_or_touch() {
+        assert_eq!(merge(vec![(30, 40), (0, 10), (60, 70), (10, 20), (35, 50)]), [(0, 20), (30, 50), (60, 70)]);
+    }
+
+    #[test]
+    fn chunks_read_from_one_virtual_offset_to_another() {
+        let path = format!("{}/tbi.vcf.gz", DATA);
+        let whole = inflated(&path);
+        let mut file = File::open(&path).unwrap();
+        let first_block = bgzf::next_block(&mut file, 0).unwrap().unwrap().len() as u64;
+        let second_block = bgzf::next_block(&mut file, first_block).unwrap().unwrap().len() as u64;
+        let third = first_block + second_block;
+        // Within a block, across the end of one into the next, and across a whole block
+        let ranges = vec![(2, 7), (990, first_block << 16 | 15), (first_block << 16 | 500, third << 16 | 40)];
+        let mut chunks = Chunks { file, ranges: ranges.into_iter(), end: 0, next: None, loaded: None, data: Vec::new(), pos: 0, limit: 0 };
+        let mut text = String::new();
+        chunks.read_to_string(&mut text).unwrap();
+        assert_eq!(text, [&whole[2..7], &whole[990..1015], &whole[1500..2040]].concat());
+    }
+}
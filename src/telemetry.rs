@@ -0,0 +1,49 @@
+//! Exporting the [`crate::spans`] of a command-line run to an OpenTelemetry collector over
+//! OTLP/HTTP (`--otlp-endpoint`), compiled with `--features otel`.
+//!
+//! Spans are batched and sent from a thread of the exporter's own; the rest are flushed
+//! when the [`Exporter`] is dropped, at the end of the run. The exporter also honours the
+//! standard `OTEL_EXPORTER_OTLP_HEADERS`, `_TIMEOUT` and `_COMPRESSION` variables.
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+use crate::error::{Error, Result};
+
+/// Sends spans to the collector while it lives.
+pub struct Exporter {
+    provider: SdkTracerProvider,
+}
+
+/// Start sending spans to the collector at `endpoint`, such as `http://localhost:4318`.
+/// Traces go to its `/v1/traces`, unless `endpoint` already names that path.
+pub fn export(endpoint: &str) -> Result<Exporter> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let url = match endpoint.ends_with("/v1/traces") {
+        true => endpoint.to_string(),
+        false => format!("{}/v1/traces", endpoint),
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&url)
+        .build()
+        .map_err(|e| Error::invalid("--otlp-endpoint", e.to_string()))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(env!("CARGO_PKG_NAME")).build())
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber).map_err(|e| Error::invalid("--otlp-endpoint", e.to_string()))?;
+    Ok(Exporter { provider })
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Warning: failed to send traces: {}", e);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Multi-threshold P+T (pruning and thresholding) scoring.
+//!
+//! With `--pt-thresholds`, every sample gets one extra score per p-value threshold, summed
+//! over the scoring variants whose `p_value` is at or below it. All thresholds are scored in
+//! the same pass over the VCF as the full score; pruning is left to the scoring file.
+//!
+//! Given a phenotype, each thresholded score is evaluated as `speedscore evaluate` would and
+//! the best threshold reported: the one explaining the most variance beyond the covariates,
+//! by Nagelkerke's R² for binary traits as PRSice does, and observed-scale R² otherwise.
+
+use crate::common::{EffectWeights, EvaluateArgs, TraitType};
+use crate::error::{Error, Result};
+use crate::evaluate::{self, Evaluation};
+
+/// Check `thresholds` against the scoring file and return them in ascending order, without duplicates.
+pub fn validate(thresholds: &[f64], effect_weights: &EffectWeights, scoring_path: &str) -> Result<Vec<f64>> {
+    if let Some(bad) = thresholds.iter().find(|&&t| !(t > 0.0 && t <= 1.0)) {
+        return Err(Error::invalid("--pt-thresholds", format!("{} is not in (0, 1]", bad)));
+    }
+    let missing = effect_weights.values().filter(|w| w.p_value.is_none()).count();
+    if missing > 0 {
+        return Err(Error::invalid(
+            scoring_path,
+            format!("--pt-thresholds needs a p_value for every variant; {} of {} have none", missing, effect_weights.len()),
+        ));
+    }
+    let mut sorted = thresholds.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted.dedup();
+    Ok(sorted)
+}
+
+/// Index of the first of the ascending `thresholds` that a variant with p-value `p` is
+/// included at; it is included at every threshold from there on.
+pub fn first_included(thresholds: &[f64], p: f64) -> usize {
+    thresholds.partition_point(|&t| t < p)
+}
+
+/// Results column of the score at `threshold`, e.g. `Score_P0.05` or `Score_P5e-8`.
+pub fn column_name(threshold: f64) -> String {
+    format!("Score_P{}", label(threshold))
+}
+
+/// `threshold` as written in reports: scientific notation below 0.001.
+fn label(threshold: f64) -> String {
+    if threshold < 1e-3 {
+        format!("{:e}", threshold)
+    } else {
+        threshold.to_string()
+    }
+}
+
+/// Evaluate the score at each of `thresholds` in the per-sample CSV `template.scores`
+/// against `template`'s phenotype, print the comparison and return the best threshold
+/// with its evaluation. Thresholds no scoring variant passes are left out.
+pub fn select_best(thresholds: &[f64], effect_weights: &EffectWeights, template: &EvaluateArgs) -> Result<(f64, Evaluation)> {
+    let mut evaluated = Vec::new();
+    println!("\nP+T threshold comparison against {}:", template.phenotype_column);
+    for &threshold in thresholds {
+        let variants = effect_weights.values().filter(|w| w.p_value.is_some_and(|p| p <= threshold)).count();
+        if variants == 0 {
+            println!("P <= {}: no scoring variants; skipped", label(threshold));
+            continue;
+        }
+        let args = EvaluateArgs { score_column: column_name(threshold), ..template.clone() };
+        let evaluation = evaluate::evaluate(&args)?;
+        if evaluated.is_empty() {
+            let r2 = if evaluation.trait_type == TraitType::Binary { "Nagelkerke_R2" } else { "R2" };
+            println!("Threshold\tVariants\tBeta_per_SD\t{}\tP_value", r2);
+        }
+        println!(
+            "{}\t{}\t{:.6}\t{:.6}\t{:.3e}",
+            label(threshold),
+            variants,
+            evaluation.beta,
+            explained(&evaluation),
+            evaluation.p_value
+        );
+        evaluated.push((threshold, evaluation));
+    }
+
+    let best = evaluated
+        .into_iter()
+        .max_by(|a, b| explained(&a.1).total_cmp(&explained(&b.1)))
+        .ok_or_else(|| Error::invalid("--pt-thresholds", "no threshold includes any scoring variant"))?;
+    println!(
+        "Best threshold: P <= {} ({}, R² {:.6}, p = {:.3e})",
+        label(best.0),
+        column_name(best.0),
+        explained(&best.1),
+        best.1.p_value
+    );
+    Ok(best)
+}
+
+/// The variance explained that thresholds are ranked by.
+fn explained(evaluation: &Evaluation) -> f64 {
+    evaluation.nagelkerke_r2.unwrap_or(evaluation.r2)
+}
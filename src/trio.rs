@@ -0,0 +1,126 @@
+//! Trio analysis: mid-parent expected scores and the polygenic transmission
+//! disequilibrium test (pTDT).
+//!
+//! Given a pedigree (`--pedigree`), every sample whose father and mother were scored too
+//! gets its mid-parent score, the mean of its parents' scores and so its expected score,
+//! and its offspring deviation, its own score minus the mid-parent score. Following
+//! Weiner et al. (2017), the pTDT divides each deviation by the standard deviation of the
+//! mid-parent scores and tests whether their mean differs from zero with a one-sample
+//! t-test: offspring affected by a trait should inherit more than their expected share
+//! of its risk alleles.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use serde::Serialize;
+use crate::error::{Error, IoContext, Result};
+use crate::evaluate::Table;
+use crate::scorer::SampleScore;
+use crate::stats;
+
+/// Each child's (father, mother), by sample ID.
+pub type Pedigree = HashMap<String, (String, String)>;
+
+/// The pTDT over the trios with all three members scored.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TrioSummary {
+    pub trios: usize,
+    /// Mean offspring deviation, in mid-parent standard deviations; `None` with fewer than
+    /// two trios or no mid-parent spread
+    pub mean_deviation: Option<f64>,
+    pub t: Option<f64>,
+    /// Two-sided p-value of `t`, with one degree of freedom less than there are trios
+    pub p_value: Option<f64>,
+}
+
+/// Read a pedigree: a plink .fam file (FID, IID, PAT, MAT, ...; no header), or a sample
+/// table with an IID, #IID or Sample_Name column and PAT and MAT columns, such as a
+/// plink2 .psam. `0` or `NA` for a parent means unknown; only samples with both parents
+/// known are kept.
+pub fn load_pedigree(path: &str) -> Result<Pedigree> {
+    let text = fs::read_to_string(path).with_path(path)?;
+    let first = text.lines().find(|line| !line.trim().is_empty()).ok_or_else(|| Error::invalid(path, "empty file"))?;
+    let has_header = first.starts_with('#') || first.split_whitespace().any(|field| field == "IID" || field == "Sample_Name");
+    let rows: Vec<(usize, [String; 3])> = if has_header {
+        let table = Table::read(path)?;
+        let (ids, pat, mat) = (table.id_column()?, table.column("PAT")?, table.column("MAT")?);
+        table.rows.iter().enumerate().map(|(index, row)| (index + 2, [row[ids].clone(), row[pat].clone(), row[mat].clone()])).collect()
+    } else {
+        let mut rows = Vec::new();
+        for (index, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                return Err(Error::parse(path, index + 1, "Expected at least 4 columns (FID, IID, PAT, MAT)", line));
+            }
+            rows.push((index + 1, [fields[1].to_string(), fields[2].to_string(), fields[3].to_string()]));
+        }
+        rows
+    };
+
+    let mut pedigree = HashMap::new();
+    for (line_number, [child, father, mother]) in rows {
+        let unknown = |parent: &str| matches!(parent, "0" | "NA" | "." | "");
+        if unknown(&father) || unknown(&mother) {
+            continue;
+        }
+        if father == child || mother == child || father == mother {
+            let message = format!("Sample '{}' cannot have father '{}' and mother '{}'", child, father, mother);
+            return Err(Error::parse(path, line_number, message, &[child.as_str(), father.as_str(), mother.as_str()].join("\t")));
+        }
+        if pedigree.insert(child.clone(), (father, mother)).is_some() {
+            return Err(Error::invalid(path, format!("duplicate sample '{}'", child)));
+        }
+    }
+    if pedigree.is_empty() {
+        return Err(Error::invalid(path, "no sample has both parents listed"));
+    }
+    Ok(pedigree)
+}
+
+/// Number of the pedigree's trios with all three members among `sample_names`.
+pub fn complete_trios(pedigree: &Pedigree, sample_names: &[String]) -> usize {
+    let names: HashSet<&String> = sample_names.iter().collect();
+    let present = |name: &String| names.contains(name);
+    pedigree.iter().filter(|(child, (father, mother))| present(child) && present(father) && present(mother)).count()
+}
+
+/// Set each child's mid-parent score and offspring deviation, and run the pTDT.
+pub fn analyze(samples: &mut [SampleScore], pedigree: &Pedigree) -> TrioSummary {
+    let scores: HashMap<String, f64> = samples.iter().map(|sample| (sample.name.clone(), sample.score)).collect();
+    let mut midparents = Vec::new();
+    let mut deviations = Vec::new();
+    for sample in samples.iter_mut() {
+        let Some((father, mother)) = pedigree.get(&sample.name) else {
+            continue;
+        };
+        if let (Some(father), Some(mother)) = (scores.get(father), scores.get(mother)) {
+            let midparent = (father + mother) / 2.0;
+            sample.midparent_score = Some(midparent);
+            sample.offspring_deviation = Some(sample.score - midparent);
+            midparents.push(midparent);
+            deviations.push(sample.score - midparent);
+        }
+    }
+
+    let n = deviations.len() as f64;
+    let mut summary = TrioSummary { trios: deviations.len(), ..Default::default() };
+    let midparent_sd = sample_sd(&midparents);
+    if deviations.len() < 2 || midparent_sd <= 0.0 {
+        return summary;
+    }
+    let standardized: Vec<f64> = deviations.iter().map(|deviation| deviation / midparent_sd).collect();
+    let mean = standardized.iter().sum::<f64>() / n;
+    summary.mean_deviation = Some(mean);
+    let sd = sample_sd(&standardized);
+    if sd > 0.0 {
+        let t = mean / (sd / n.sqrt());
+        summary.t = Some(t);
+        summary.p_value = Some(stats::t_two_sided_p(t, n - 1.0));
+    }
+    summary
+}
+
+fn sample_sd(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    (values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+}
@@ -0,0 +1,192 @@
+//! `--tui`: a full-screen dashboard for long multi-sample runs, compiled with
+//! `--features tui`. It polls the shared [`Progress`] from its own thread while the
+//! scoring loop runs, so the scoring code never waits on the terminal.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, LineGauge, Paragraph};
+use ratatui::Frame;
+use thousands::Separable;
+use crate::common::EffectWeights;
+use crate::error::{Error, ErrorPolicy, Result};
+use crate::progress::{Progress, ProgressState};
+
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Exit code when the user quits the dashboard mid-run, as for Ctrl-C in a shell.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Draw the dashboard until `progress` is finished. Pressing `q` or Ctrl-C restores
+/// the terminal and exits the process.
+pub fn run(progress: &Progress, policy: &ErrorPolicy, weights: &EffectWeights, vcf_path: &str) -> Result<()> {
+    let dashboard = Dashboard::new(weights, vcf_path);
+    let mut terminal = ratatui::init();
+    let mut rate = Rate::default();
+
+    let result = loop {
+        let state = progress.snapshot();
+        rate.update(state.lines);
+        if let Err(e) = terminal.draw(|frame| dashboard.draw(frame, &state, policy.count(), rate.per_second)) {
+            break Err(Error::io("terminal", e));
+        }
+        if state.finished {
+            break Ok(());
+        }
+        match quit_requested() {
+            Ok(false) => {}
+            Ok(true) => {
+                ratatui::restore();
+                eprintln!("Interrupted.");
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+            Err(e) => break Err(Error::io("terminal", e)),
+        }
+    };
+    ratatui::restore();
+    result
+}
+
+/// Wait up to one refresh interval for a quit key.
+fn quit_requested() -> std::io::Result<bool> {
+    if !event::poll(REFRESH)? {
+        return Ok(false);
+    }
+    Ok(match event::read()? {
+        Event::Key(key) if key.kind == KeyEventKind::Press => {
+            key.code == KeyCode::Char('q')
+                || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+        }
+        _ => false,
+    })
+}
+
+/// Lines per second, measured over roughly the last second rather than the whole run.
+#[derive(Default)]
+struct Rate {
+    mark: Option<(Instant, usize)>,
+    per_second: f64,
+}
+
+impl Rate {
+    fn update(&mut self, lines: usize) {
+        let now = Instant::now();
+        match self.mark {
+            Some((at, seen)) => {
+                let elapsed = now.duration_since(at);
+                if elapsed >= Duration::from_secs(1) {
+                    self.per_second = lines.saturating_sub(seen) as f64 / elapsed.as_secs_f64();
+                    self.mark = Some((now, lines));
+                }
+            }
+            None => self.mark = Some((now, lines)),
+        }
+    }
+}
+
+struct Dashboard<'a> {
+    vcf_path: &'a str,
+    started: Instant,
+    scoring_variants: usize,
+    /// Sorted scoring positions per chromosome, in karyotype order
+    chromosomes: Vec<(String, Vec<u64>)>,
+}
+
+impl<'a> Dashboard<'a> {
+    fn new(weights: &EffectWeights, vcf_path: &'a str) -> Self {
+        let mut by_chr: BTreeMap<(u8, u32, String), Vec<u64>> = BTreeMap::new();
+        for (chr, pos) in weights.keys() {
+            by_chr.entry(karyotype_key(chr)).or_default().push(*pos);
+        }
+        let chromosomes = by_chr
+            .into_iter()
+            .map(|((_, _, chr), mut positions)| {
+                positions.sort_unstable();
+                (chr, positions)
+            })
+            .collect();
+        Dashboard { vcf_path, started: Instant::now(), scoring_variants: weights.len(), chromosomes }
+    }
+
+    fn draw(&self, frame: &mut Frame, state: &ProgressState, malformed: usize, rate: f64) {
+        let [stats_area, chr_area, help_area] =
+            Layout::vertical([Constraint::Length(9), Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+
+        let match_rate = ratio(state.sites.allele_hits, self.scoring_variants as u64);
+        let concordance = ratio(state.sites.allele_hits, state.sites.position_hits);
+        let position = if state.chr.is_empty() {
+            "-".to_string()
+        } else {
+            format!("chr{}:{}", state.chr, state.pos.separate_with_commas())
+        };
+        let stats = vec![
+            stat("Elapsed", format!("{:.0?}", self.started.elapsed())),
+            stat("Lines processed", state.lines.separate_with_commas()),
+            stat("Throughput", format!("{} lines/s", (rate.round() as u64).separate_with_commas())),
+            stat("Position", position),
+            stat("Match rate", format!("{:.1}% of {} scoring variants", match_rate * 100.0, self.scoring_variants.separate_with_commas())),
+            stat("Allele concordance", format!("{:.1}%", concordance * 100.0)),
+            stat("Malformed records", malformed.separate_with_commas()).style(if malformed > 0 {
+                Style::new().yellow()
+            } else {
+                Style::new()
+            }),
+        ];
+        let title = format!(" SpeedScore: {} ", self.vcf_path);
+        frame.render_widget(Paragraph::new(stats).block(Block::bordered().title(title)), stats_area);
+
+        let block = Block::bordered().title(" Scoring variants passed, by chromosome ");
+        let inner = block.inner(chr_area);
+        frame.render_widget(block, chr_area);
+        let rows = Layout::vertical(vec![Constraint::Length(1); self.chromosomes.len()]).split(inner);
+        for ((chr, positions), row) in self.chromosomes.iter().zip(rows.iter()) {
+            let done = chromosome_ratio(state, chr, positions);
+            let color = if done >= 1.0 { Color::Green } else { Color::Cyan };
+            let gauge = LineGauge::default()
+                .label(format!("{:>5}", format!("chr{}", chr)))
+                .ratio(done)
+                .filled_style(Style::new().fg(color));
+            frame.render_widget(gauge, *row);
+        }
+
+        frame.render_widget(Line::from("q: quit").dim(), help_area);
+    }
+}
+
+fn stat(name: &str, value: String) -> Line<'static> {
+    Line::from(vec![format!(" {:<20}", name).bold(), value.into()])
+}
+
+/// Share of a chromosome's scoring variants the run has passed.
+fn chromosome_ratio(state: &ProgressState, chr: &str, positions: &[u64]) -> f64 {
+    if state.finished || state.finished_chromosomes.iter().any(|c| c == chr) {
+        1.0
+    } else if state.chr == chr {
+        ratio(positions.partition_point(|&p| p <= state.pos) as u64, positions.len() as u64)
+    } else {
+        0.0
+    }
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// Sort key putting autosomes in numeric order, then X, Y, MT, then anything else.
+fn karyotype_key(chr: &str) -> (u8, u32, String) {
+    let rank = match chr {
+        _ if chr.parse::<u32>().is_ok() => 0,
+        "X" => 1,
+        "Y" => 2,
+        "M" | "MT" => 3,
+        _ => 4,
+    };
+    (rank, chr.parse().unwrap_or(0), chr.to_string())
+}
@@ -0,0 +1,150 @@
+//! Writing output files to cloud storage: an output path of `s3://bucket/key` or
+//! `gs://bucket/key`.
+//!
+//! Such a file is written to a local staging directory like any other output, then copied
+//! to the bucket once the run has produced everything, with the provider's own
+//! command-line tool: `aws s3 cp` for S3 and `gcloud storage cp` for Google Cloud
+//! Storage. Credentials, profiles and regions are whatever that tool is set up with. A
+//! failed run uploads nothing, and a failed upload leaves the staged files in place.
+//! [`crate::stream`] reads inputs from a bucket with the same tools.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::error::{Error, IoContext, Result};
+
+/// A cloud storage provider an output path can name.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Provider {
+    S3,
+    Gcs,
+}
+
+impl Provider {
+    /// The provider `path` is a URL of, if any.
+    pub fn of(path: &str) -> Option<Self> {
+        if path.starts_with("s3://") {
+            Some(Provider::S3)
+        } else if path.starts_with("gs://") {
+            Some(Provider::Gcs)
+        } else {
+            None
+        }
+    }
+
+    /// The provider's command-line tool, which reads and writes its objects.
+    pub fn tool(self) -> &'static str {
+        match self {
+            Provider::S3 => "aws",
+            Provider::Gcs => "gcloud",
+        }
+    }
+
+    /// Make sure [`Provider::tool`] is on the `PATH`, so that a run reading or writing
+    /// `url` stops before it starts rather than when it first reaches the bucket.
+    pub fn check_tool(self, url: &str) -> Result<()> {
+        let tool = self.tool();
+        let found = env::var_os("PATH").is_some_and(|dirs| {
+            env::split_paths(&dirs).any(|dir| dir.join(tool).is_file() || dir.join(format!("{}{}", tool, env::consts::EXE_SUFFIX)).is_file())
+        });
+        let scheme = match self {
+            Provider::S3 => "s3://",
+            Provider::Gcs => "gs://",
+        };
+        match found {
+            true => Ok(()),
+            false => Err(Error::invalid(url, format!("`{}` is not on the PATH; {} URLs are read and written with it, so install it or use a local file", tool, scheme))),
+        }
+    }
+
+    /// The command that copies a local file to the provider, before its two arguments.
+    fn copy_command(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Provider::S3 => ("aws", &["s3", "cp", "--only-show-errors"]),
+            Provider::Gcs => ("gcloud", &["storage", "cp"]),
+        }
+    }
+
+    /// The command that writes the object at `url` to its standard output, and its program.
+    pub(crate) fn read_command(self, url: &str) -> (&'static str, Command) {
+        let arguments: &[&str] = match self {
+            Provider::S3 => &["s3", "cp", "--only-show-errors", url, "-"],
+            Provider::Gcs => &["storage", "cat", url],
+        };
+        let mut command = Command::new(self.tool());
+        command.args(arguments);
+        (self.tool(), command)
+    }
+}
+
+/// Output files bound for cloud storage, staged locally until [`Uploads::finish`].
+#[derive(Debug, Default)]
+pub struct Uploads {
+    /// (local staging file, destination URL), in the order staged
+    staged: Vec<(String, String)>,
+    dir: Option<PathBuf>,
+}
+
+impl Uploads {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where to write the output `path`: `path` itself, or for a bucket URL a staging file
+    /// with the same file name, to be uploaded by [`Uploads::finish`].
+    pub fn stage(&mut self, path: &str) -> Result<String> {
+        if Provider::of(path).is_none() {
+            return Ok(path.to_string());
+        }
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = std::env::temp_dir().join(format!("speedscore-upload-{}", std::process::id()));
+                fs::create_dir_all(&dir).with_path(&dir.to_string_lossy())?;
+                self.dir.insert(dir).clone()
+            }
+        };
+        let name = path.rsplit('/').find(|part| !part.is_empty()).unwrap_or("output");
+        // Numbered, as two destinations can share a file name
+        let local = dir.join(format!("{}-{}", self.staged.len() + 1, name)).to_string_lossy().into_owned();
+        self.staged.push((local.clone(), path.to_string()));
+        Ok(local)
+    }
+
+    /// `path` as staged by [`Uploads::stage`], for the options holding output paths.
+    pub fn stage_option(&mut self, path: &Option<String>) -> Result<Option<String>> {
+        path.as_deref().map(|path| self.stage(path)).transpose()
+    }
+
+    /// Upload every staged file that was written, in the order staged, then remove the
+    /// staging directory. Stops at the first failure, keeping the staged files.
+    pub fn finish(self) -> Result<()> {
+        for (local, remote) in &self.staged {
+            if !Path::new(local).exists() {
+                continue;
+            }
+            upload(local, remote)?;
+            println!("Uploaded {}", remote);
+        }
+        if let Some(dir) = &self.dir {
+            fs::remove_dir_all(dir).with_path(&dir.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy the local file `local` to the bucket URL `remote`.
+fn upload(local: &str, remote: &str) -> Result<()> {
+    let provider = Provider::of(remote).expect("only bucket URLs are staged");
+    let (program, arguments) = provider.copy_command();
+    let status = Command::new(program).args(arguments).arg(local).arg(remote).status().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(e.kind(), format!("uploading needs `{}` on the PATH; the file is kept at {}", program, local)),
+        _ => e,
+    });
+    match status.with_path(remote)? {
+        status if status.success() => Ok(()),
+        status => Err(io::Error::other(format!("`{} {}` failed ({}); the file is kept at {}", program, arguments[..2].join(" "), status, local))).with_path(remote),
+    }
+}
@@ -0,0 +1,257 @@
+//! `speedscore watch`: a drop-folder daemon. Each VCF that lands in the intake
+//! directory is scored against every configured scoring file; results and a
+//! provenance record are written to the output directory and the VCF is moved
+//! out of the intake directory. With several scoring files, the pairwise correlations
+//! of their scores across the VCF's samples are written too, as a quick check for
+//! redundant or mislabeled scoring files.
+//!
+//! The directory is polled rather than watched with inotify, which works on the
+//! network shares labs typically use as drop folders. A file is only picked up
+//! once its size and mtime are unchanged between two scans, so copies in
+//! progress are left alone.
+//!
+//! With `--metrics-bind`, Prometheus metrics of the jobs run and the VCFs waiting are
+//! served over HTTP; see [`crate::metrics`].
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use crate::checksum::{Digests, InputChecksums};
+use crate::common::{scoring_id, vcf_stem, ScoreOptions, WatchArgs};
+use crate::error::{Error, IoContext, Result};
+use crate::metrics::Metrics;
+use crate::provenance::Provenance;
+use crate::scorer::{ScoreResults, Scorer};
+use crate::sink::{self, OutputFormat, RunInfo};
+use crate::stats;
+
+/// Scoring files whose scores correlate at least this strongly (in absolute value) are
+/// reported as possibly the same score.
+const REDUNDANT_CORRELATION: f64 = 0.98;
+
+/// A scoring file loaded once at startup.
+pub(crate) struct Configured {
+    pub id: String,
+    pub path: String,
+    pub scorer: Scorer,
+}
+
+/// Run until killed, or after one scan with `--once`.
+pub fn run(args: &WatchArgs) -> Result<()> {
+    if args.metrics_bind.is_some() && !cfg!(feature = "server") {
+        return Err(Error::invalid("--metrics-bind", "the metrics endpoint is not available in this build; rebuild with --features server"));
+    }
+    let options = ScoreOptions { dosage: args.dosage, model: args.model, ..Default::default() };
+    let mut scoring = Vec::new();
+    for path in &args.scoring {
+        let scorer = Scorer::from_scoring_file(path)?.dosage(options.dosage).model(options.model);
+        scoring.push(Configured { id: scoring_id(path), path: path.clone(), scorer });
+    }
+
+    let intake = Path::new(&args.intake);
+    let processed_dir = args.processed_dir.as_ref().map_or_else(|| intake.join("processed"), PathBuf::from);
+    let failed_dir = args.failed_dir.as_ref().map_or_else(|| intake.join("failed"), PathBuf::from);
+    for dir in [&processed_dir, &failed_dir, Path::new(&args.output_dir)] {
+        fs::create_dir_all(dir).with_path(&dir.to_string_lossy())?;
+    }
+
+    let metrics = Arc::new(Metrics::default());
+    #[cfg(feature = "server")]
+    if let Some(bind) = &args.metrics_bind {
+        crate::metrics::serve(bind, Arc::clone(&metrics))?;
+        println!("Serving metrics on http://{}/metrics", bind);
+    }
+
+    println!("Watching {} with {} scoring file(s)", args.intake, scoring.len());
+    let mut pending: HashMap<PathBuf, (u64, SystemTime)> = HashMap::new();
+    loop {
+        let mut still_pending = HashMap::new();
+        let found = scan(intake)?;
+        let mut waiting = found.len();
+        metrics.set_queue_depth(waiting);
+        for (path, stamp) in found {
+            if args.once || pending.get(&path) == Some(&stamp) {
+                process(&path, &scoring, options, args, &metrics, &processed_dir, &failed_dir)?;
+                waiting -= 1;
+                metrics.set_queue_depth(waiting);
+            } else {
+                still_pending.insert(path, stamp);
+            }
+        }
+        pending = still_pending;
+
+        if args.once {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_secs(args.poll_interval));
+    }
+}
+
+/// VCFs and BCFs directly inside `dir`, with their size and mtime. Hidden files are skipped.
+fn scan(dir: &Path) -> Result<Vec<(PathBuf, (u64, SystemTime))>> {
+    let dir_name = dir.to_string_lossy();
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).with_path(&dir_name)? {
+        let entry = entry.with_path(&dir_name)?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_vcf = [".vcf", ".vcf.gz", ".vcf.bgz", ".bcf"].iter().any(|ext| name.ends_with(ext));
+        if !is_vcf || name.starts_with('.') {
+            continue;
+        }
+        let metadata = entry.metadata().with_path(&name)?;
+        if metadata.is_file() {
+            let modified = metadata.modified().with_path(&name)?;
+            found.push((entry.path(), (metadata.len(), modified)));
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Score one VCF against every scoring file, then move it (and any index) out of the intake.
+/// A VCF that fails to score is moved to `failed_dir` next to an `.error` file instead of
+/// stopping the daemon; only errors writing results or moving files are returned.
+fn process(
+    vcf: &Path,
+    scoring: &[Configured],
+    options: ScoreOptions,
+    args: &WatchArgs,
+    metrics: &Metrics,
+    processed_dir: &Path,
+    failed_dir: &Path,
+) -> Result<()> {
+    let vcf_path = vcf.to_string_lossy().into_owned();
+    let file_name = vcf.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let stem = vcf_stem(&file_name);
+    println!("Scoring {}", vcf_path);
+
+    let vcf_checksums = args.checksums.then(|| Digests::of(&vcf_path)).transpose()?;
+    // Each scoring file's scores, in VCF sample order
+    let mut scores = Vec::new();
+    for configured in scoring {
+        let start = Instant::now();
+        let results = match configured.scorer.run(&vcf_path) {
+            Ok(results) => {
+                metrics.job_succeeded(start.elapsed(), &results);
+                results
+            }
+            Err(e) => {
+                metrics.job_failed(start.elapsed());
+                eprintln!("Error: {}: {}", configured.id, e);
+                let error_path = failed_dir.join(format!("{}.error", file_name));
+                fs::write(&error_path, format!("{}: {}\n", configured.id, e)).with_path(&error_path.to_string_lossy())?;
+                return move_with_index(vcf, failed_dir);
+            }
+        };
+        let duration = start.elapsed();
+        let checksums = match &vcf_checksums {
+            Some(vcf) => Some(InputChecksums { vcf: vcf.clone(), scoring: Digests::of(&configured.path)? }),
+            None => None,
+        };
+        let outputs = Outputs { dir: Path::new(&args.output_dir), stem, format: args.output_format };
+        let (output, _) = outputs.write(&vcf_path, configured, options, &results, duration, checksums)?;
+        println!("Results written to: {}", output);
+        scores.push(results.samples.iter().map(|sample| sample.score).collect::<Vec<f64>>());
+    }
+
+    if scores.len() > 1 && scores[0].len() >= 3 {
+        let ids: Vec<&str> = scoring.iter().map(|configured| configured.id.as_str()).collect();
+        let path = Path::new(&args.output_dir).join(format!("{}.correlations.tsv", stem));
+        write_correlations(&ids, &scores, &path.to_string_lossy())?;
+    }
+    move_with_index(vcf, processed_dir)
+}
+
+/// Where the results of one VCF go: `DIR/<stem>.<scoring>.scores.<ext>`, with a
+/// `DIR/<stem>.<scoring>.provenance.json` record next to each.
+pub(crate) struct Outputs<'a> {
+    pub dir: &'a Path,
+    pub stem: &'a str,
+    pub format: OutputFormat,
+}
+
+impl Outputs<'_> {
+    /// Write `results` of `vcf_path` against `configured`, and their provenance record.
+    /// Returns the paths of both.
+    pub fn write(
+        &self,
+        vcf_path: &str,
+        configured: &Configured,
+        options: ScoreOptions,
+        results: &ScoreResults,
+        duration: Duration,
+        checksums: Option<InputChecksums>,
+    ) -> Result<(String, String)> {
+        let output = self.dir.join(format!("{}.{}.scores.{}", self.stem, configured.id, self.format.extension()));
+        let output = output.to_string_lossy().into_owned();
+        let mut sink = sink::open_sink(self.format, &output)?;
+        let run = RunInfo {
+            vcf_path,
+            duration,
+            pcs: &[],
+            calibrated: false,
+            thresholds: &[],
+            standard_errors: results.samples.iter().any(|s| s.score_se.is_some()),
+            effective_variants: results.samples.iter().any(|s| s.effective_variants.is_some()),
+            rescaled: results.samples.iter().any(|s| s.weight_coverage.is_some()),
+            permutations: false,
+            sets: &[],
+            sex_strata: None,
+            interactions: false,
+            parent_of_origin: false,
+            trios: None,
+            reference: None,
+            scale: None,
+            pgs_catalog: None,
+            scores: &[],
+        };
+        sink::emit(results, &run, sink.as_mut())?;
+
+        let provenance = self.dir.join(format!("{}.{}.provenance.json", self.stem, configured.id));
+        let provenance = provenance.to_string_lossy().into_owned();
+        let record = Provenance::new(vcf_path, &configured.path, configured.scorer.weights().len(), options, &results.summary, duration);
+        let record = match checksums {
+            Some(checksums) => record.with_checksums(checksums),
+            None => record,
+        };
+        record.write(&provenance)?;
+        Ok((output, provenance))
+    }
+}
+
+/// Write the matrix of Pearson correlations between the scoring files' `scores` across
+/// samples to `path`, and note pairs that look like the same score.
+fn write_correlations(ids: &[&str], scores: &[Vec<f64>], path: &str) -> Result<()> {
+    let mut table = format!("score\t{}\n", ids.join("\t"));
+    for (i, row) in scores.iter().enumerate() {
+        table.push_str(ids[i]);
+        for (j, column) in scores.iter().enumerate() {
+            let r = stats::pearson(row, column);
+            table.push_str(&r.map_or("\tNA".to_string(), |r| format!("\t{:.6}", r)));
+            if let Some(r) = r.filter(|r| j > i && r.abs() >= REDUNDANT_CORRELATION) {
+                let flipped = if r < 0.0 { " with flipped effect alleles" } else { "" };
+                println!("Note: {} and {} correlate at r = {:.4}; they may be the same score{}", ids[i], ids[j], r, flipped);
+            }
+        }
+        table.push('\n');
+    }
+    fs::write(path, table).with_path(path)?;
+    println!("Score correlations written to: {}", path);
+    Ok(())
+}
+
+/// Move `vcf` into `dir`, along with a `.tbi` or `.csi` index if there is one.
+fn move_with_index(vcf: &Path, dir: &Path) -> Result<()> {
+    let vcf_name = vcf.as_os_str().to_string_lossy().into_owned();
+    for path in [vcf_name.clone(), format!("{}.tbi", vcf_name), format!("{}.csi", vcf_name)] {
+        let path = Path::new(&path);
+        if path == vcf || path.exists() {
+            let target = dir.join(path.file_name().unwrap_or_default());
+            fs::rename(path, &target).with_path(&path.to_string_lossy())?;
+        }
+    }
+    Ok(())
+}